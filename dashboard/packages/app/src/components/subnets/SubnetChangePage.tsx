@@ -125,6 +125,9 @@ export const SubnetChangePage = ({ network }: { network: string }) => {
   const params = useRouteRefParams(subnetChangePreviewRouteRef);
   const change = fetchChangePreview(params.subnet);
   console.log(change);
+  // A coefficient cannot exceed a third of the nodes of the subnet.
+  const numNodes = Object.values(change?.feature_diff?.node_provider ?? {}).reduce((sum, [_, after]) => sum + after, 0);
+  const maxScore = Math.ceil((numNodes || 1) / 3);
 
   return (
     <Page themeId="other">
@@ -144,16 +147,15 @@ export const SubnetChangePage = ({ network }: { network: string }) => {
           <Grid item xs={12} container direction='column'>
 
             {Object.entries(change?.score_after?.coefficients ?? {}).map(([key, _]) => {
-              let max_score = Math.ceil((change?.score_after?.value_counts.node_provider.map(([_, v]) => v).reduce((sum, c) => sum + c) ?? 1) / 3);
               let before = (change?.score_before?.coefficients[key as keyof Coefficients] ?? 0);
               let after = (change?.score_after?.coefficients[key as keyof Coefficients] ?? 0);
-              return <ImprovementBar label={key} before={before} after={after} max_score={max_score} />
+              return <ImprovementBar label={key} before={before} after={after} max_score={maxScore} />
             })}
             <ImprovementBar
               label="Overall"
-              before={change?.score_before?.avg_linear ?? 0}
-              after={change?.score_after?.avg_linear ?? 0}
-              max_score={Math.ceil((change?.score_after?.value_counts.node_provider.map(([_, v]) => v).reduce((sum, c) => sum + c) ?? 1) / 3)}
+              before={change?.score_before?.average_linear ?? 0}
+              after={change?.score_after?.average_linear ?? 0}
+              max_score={maxScore}
             />
           </Grid>
           {change?.comment && <Grid item>
@@ -66,6 +66,10 @@ export function fetchNodesHealths(): { [principal: string]: NodeHealth } {
     fetch(
       `${config.getString('backend.baseUrl')}/api/proxy/registry/${network}/nodes/healths`
     ).then((res) => res.json())
+      // The backend names the statuses in lowercase, e.g. "healthy".
+      .then((healths: { [principal: string]: string }) => Object.fromEntries(
+        Object.entries(healths).map(([principal, health]) => [principal, health.charAt(0).toUpperCase() + health.slice(1) as NodeHealth])
+      ))
   );
   return data ?? {};
 }
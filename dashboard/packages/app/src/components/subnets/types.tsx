@@ -33,6 +33,7 @@ export interface Node {
   subnet_id?: string;
   dfinity_owned: boolean;
   proposal?: TopologyProposal;
+  annotations?: Record<string, string>;
 }
 
 export type NodeHealth = "Healthy" | "Degraded" | "Dead" | "Unknown";
@@ -128,25 +129,31 @@ export interface ReplicaRelease {
   previous_patch_release?: ReplicaRelease;
 }
 
+export interface NodeSummary {
+  principal: string
+  features: { [f: string]: string }
+}
+
 export interface ChangePreview {
-  added: string[]
-  removed: string[]
-  subnet_id: string
-  score_before: NakamotoScore
-  score_after: NakamotoScore
+  added: NodeSummary[]
+  removed: NodeSummary[]
+  subnet_id?: string
+  score_before: ScoreSummary
+  score_after: ScoreSummary
   feature_diff: { [f: string]: { [n: string]: [number, number] } }
   proposal_id?: number
   comment?: string
-  run_log?: string[]
+  run_log: string[]
 }
 
-export interface NakamotoScore {
+export interface ScoreSummary {
   coefficients: Coefficients
-  value_counts: ValueCounts
-  controlled_nodes: ControlledNodes
-  avg_linear: number
-  avg_log2: number
   min: number
+  average_linear: number
+  average_log2: number | null
+  critical_features: (keyof Coefficients)[]
+  controlled_nodes: ControlledNodes
+  headroom: ControlledNodes
 }
 
 export interface Coefficients {
@@ -158,15 +165,6 @@ export interface Coefficients {
   continent: number
 }
 
-export interface ValueCounts {
-  node_provider: [string, number][]
-  data_center: [string, number][]
-  data_center_owner: [string, number][]
-  city: [string, number][]
-  country: [string, number][]
-  continent: [string, number][]
-}
-
 export interface ControlledNodes {
   node_provider: number
   data_center: number
@@ -1,10 +1,14 @@
 use clap::{Parser, Subcommand};
 use clap_num::maybe_hex;
 use ic_base_types::PrincipalId;
-use ic_management_types::{Artifact, Network};
+use ic_management_types::versions::ReplicaVersion;
+use ic_management_types::{Artifact, Network, NodeFeature, SubnetKind};
 use log::error;
+use std::path::PathBuf;
 
 use crate::detect_neuron::{detect_hsm_auth, detect_neuron, Auth, Neuron};
+use crate::features::OutputFormat;
+use crate::report::ReportFormat;
 
 // For more info about the version setup, look at https://docs.rs/clap/latest/clap/struct.Command.html#method.version
 #[derive(Parser, Clone)]
@@ -20,6 +24,11 @@ pub struct Opts {
     pub(crate) private_key_pem: Option<String>,
     #[clap(long, env = "NEURON_ID", global = true)]
     pub(crate) neuron_id: Option<u64>,
+    /// Command printing the credential named by its argument ("neuron-id",
+    /// "private-key-pem" or "hsm-pin") on stdout. Asked for credentials that
+    /// are neither given as flags nor found in the OS keyring.
+    #[clap(long, env = "CREDENTIAL_COMMAND", global = true)]
+    pub(crate) credential_command: Option<String>,
     #[clap(long, env = "IC_ADMIN", global = true)]
     pub(crate) ic_admin: Option<String>,
     #[clap(long, env = "DEV", global = true)]
@@ -36,7 +45,24 @@ pub struct Opts {
     #[clap(long, env = "VERBOSE", global = true)]
     pub(crate) verbose: bool,
 
-    // Specify the target network: "mainnet" (default), "staging", or NNS URL
+    /// Only allow the commands reading the network, e.g. `get` or `report`.
+    /// Commands that would submit proposals or votes fail, even with `--yes`
+    #[clap(long, env = "READ_ONLY", global = true)]
+    pub(crate) read_only: bool,
+
+    /// Seed for breaking ties between equally good nodes, so that the same
+    /// inputs always result in the same proposal
+    #[clap(long, env = "SEED", global = true, default_value_t = 0)]
+    pub(crate) seed: u64,
+
+    /// How often to retry the requests computing subnet changes if the
+    /// backend cannot be reached or is unavailable. Retried requests carry an
+    /// idempotency key, so the backend computes the change only once
+    #[clap(long, env = "BACKEND_RETRIES", global = true, default_value_t = 0)]
+    pub(crate) backend_retries: usize,
+
+    // Specify the target network: "mainnet" (default), "staging", or the comma
+    // separated NNS URLs of another network
     #[clap(long, env = "NETWORK", default_value = "mainnet")]
     pub(crate) network: Network,
 
@@ -44,6 +70,18 @@ pub struct Opts {
     pub(crate) subcommand: Commands,
 }
 
+impl Opts {
+    /// Fails if the command would change the network in read-only mode.
+    pub(crate) fn check_read_only(&self) -> anyhow::Result<()> {
+        if self.read_only && self.subcommand.submits_proposals() {
+            return Err(anyhow::anyhow!(
+                "The command submits proposals or votes, which is not allowed with --read-only"
+            ));
+        }
+        Ok(())
+    }
+}
+
 #[derive(Subcommand, Clone)]
 pub(crate) enum Commands {
     // Convert a DER file to a Principal
@@ -107,6 +145,80 @@ pub(crate) enum Commands {
         accepted_topics: Vec<i32>,
     },
 
+    /// List the distinct values of a node feature across all nodes in the
+    /// network, with the number of nodes having each value
+    Features {
+        /// The feature to list the values of, e.g. "country" or "node_provider"
+        #[clap(value_parser = NodeFeature::parse_with_suggestion)]
+        feature: NodeFeature,
+
+        /// Output format
+        #[clap(long, value_enum, default_value_t)]
+        output: OutputFormat,
+    },
+
+    /// List the node providers with the number of nodes they run
+    Providers {
+        /// Output format
+        #[clap(long, value_enum, default_value_t)]
+        output: OutputFormat,
+    },
+
+    /// List the node operators with their data center, node allowance and
+    /// rewardable nodes
+    Operators {
+        /// Output format
+        #[clap(long, value_enum, default_value_t)]
+        output: OutputFormat,
+    },
+
+    /// List the subnets with their kind and number of nodes
+    Subnets {
+        /// Output format
+        #[clap(long, value_enum, default_value_t)]
+        output: OutputFormat,
+    },
+
+    /// Show which subnets run which replica version, with since when
+    RolloutStatus {
+        /// Output format
+        #[clap(long, value_enum, default_value_t)]
+        output: OutputFormat,
+    },
+
+    /// Score the decentralization of every subnet in the network
+    Report {
+        /// Output format. "jsonl" writes a line per subnet as soon as it is
+        /// scored, which scales to any number of subnets
+        #[clap(long, value_enum, default_value_t)]
+        format: ReportFormat,
+
+        /// CSV of `node_principal,feature,value` rows overriding the feature
+        /// values of the nodes before they are scored, to model what-if
+        /// changes of the network
+        #[clap(long)]
+        feature_overrides: Option<String>,
+
+        /// Score only this subnet, fetching only its nodes rather than the
+        /// whole network
+        #[clap(long)]
+        subnet: Option<PrincipalId>,
+
+        /// Rank the node providers by their nodes across all subnets instead
+        /// of scoring each subnet, to find the systemically important ones
+        #[clap(long)]
+        providers: bool,
+    },
+
+    /// Score every subnet of a registry snapshot and print the violated
+    /// invariants of the scores, without access to the network
+    Selfcheck {
+        /// JSON file of a subnet, a list of subnets or a list of nodes, as
+        /// returned by the backend
+        #[clap(long)]
+        snapshot: PathBuf,
+    },
+
     /// Trustworthy Metrics
     TrustworthyMetrics {
         /// Wallet that should be used to query node metrics history
@@ -122,9 +234,35 @@ pub(crate) enum Commands {
     },
 }
 
+impl Commands {
+    /// Whether the command submits proposals or votes, also if only
+    /// simulating it.
+    pub(crate) fn submits_proposals(&self) -> bool {
+        match self {
+            Commands::Subnet(cmd) => !matches!(cmd.subcommand, subnet::Commands::Verify { .. }),
+            Commands::Propose { .. }
+            | Commands::Version(_)
+            | Commands::Hostos(_)
+            | Commands::Nodes(_)
+            | Commands::Vote { .. } => true,
+            Commands::DerToPrincipal { .. }
+            | Commands::Get { .. }
+            | Commands::Features { .. }
+            | Commands::Providers { .. }
+            | Commands::Operators { .. }
+            | Commands::Subnets { .. }
+            | Commands::RolloutStatus { .. }
+            | Commands::Report { .. }
+            | Commands::Selfcheck { .. }
+            | Commands::TrustworthyMetrics { .. } => false,
+        }
+    }
+}
+
 pub(crate) mod subnet {
     use super::*;
     use ic_base_types::PrincipalId;
+    use std::path::PathBuf;
 
     #[derive(Parser, Clone)]
     pub struct Cmd {
@@ -137,7 +275,7 @@ pub(crate) mod subnet {
     #[derive(Subcommand, Clone)]
     pub enum Commands {
         /// Create a new proposal to rollout a new version to the subnet
-        Deploy { version: String },
+        Deploy { version: ReplicaVersion },
 
         /// Replace the nodes in a subnet
         Replace {
@@ -153,7 +291,8 @@ pub(crate) mod subnet {
             #[clap(short, long)]
             optimize: Option<usize>,
 
-            /// Motivation for replacing custom nodes
+            /// Motivation for replacing custom nodes, or `auto` for a
+            /// structured one showing the decentralization impact
             #[clap(short, long, aliases = ["summary"])]
             motivation: Option<String>,
 
@@ -161,10 +300,17 @@ pub(crate) mod subnet {
             #[clap(long, num_args(1..))]
             min_nakamoto_coefficients: Vec<String>,
 
-            /// Features or Node IDs to exclude from the available nodes pool
+            /// Node IDs or feature values to exclude from the available nodes
+            /// pool, as `<feature>=<value>` or just the value to exclude it
+            /// for any feature
             #[clap(long, num_args(1..))]
             exclude: Vec<String>,
 
+            /// Annotation keys, e.g. `rma`, of the nodes to exclude from the
+            /// available nodes pool
+            #[clap(long, num_args(1..))]
+            exclude_annotated: Vec<String>,
+
             /// Features or Node IDs to only choose from
             #[clap(long, num_args(1..))]
             only: Vec<String>,
@@ -173,6 +319,16 @@ pub(crate) mod subnet {
             /// regardless of the decentralization score
             #[clap(long, num_args(1..))]
             include: Vec<PrincipalId>,
+
+            /// Fail instead of replacing more nodes if the replacement keeps
+            /// fewer of the current nodes of the subnet
+            #[clap(long)]
+            min_retained: Option<usize>,
+
+            /// Save the score the subnet is expected to have after the
+            /// replacement to this file, to check it with `subnet verify`
+            #[clap(long)]
+            save_expected_score: Option<PathBuf>,
         },
 
         /// Resize the subnet
@@ -201,6 +357,11 @@ pub(crate) mod subnet {
             /// Motivation for resing the subnet
             #[clap(short, long, aliases = ["summary"])]
             motivation: Option<String>,
+
+            /// Save the score the subnet is expected to have after the
+            /// resize to this file, to check it with `subnet verify`
+            #[clap(long)]
+            save_expected_score: Option<PathBuf>,
         },
 
         /// Create a new subnet
@@ -231,7 +392,25 @@ pub(crate) mod subnet {
             motivation: Option<String>,
 
             #[clap(long)]
-            replica_version: Option<String>,
+            replica_version: Option<ReplicaVersion>,
+
+            /// Kind of the subnet: system, application, verified_application
+            /// or european. An application subnet if not provided
+            #[clap(long)]
+            subnet_kind: Option<SubnetKind>,
+        },
+
+        /// Check that the current score of the subnet is the one expected,
+        /// e.g. after a proposal changing its nodes is executed. Fails if any
+        /// of the coefficients or averages differs by more than `epsilon`
+        Verify {
+            /// File with the expected score, as saved by the
+            /// `--save-expected-score` option of `replace` and `resize`
+            #[clap(long)]
+            expected: PathBuf,
+
+            #[clap(long, default_value_t = 0.01)]
+            epsilon: f64,
         },
     }
 }
@@ -256,7 +435,7 @@ pub(crate) mod version {
         /// by adding a new version and potentially removing obsolete versions
         Replica {
             /// Specify the commit hash of the version that is being elected.
-            version: String,
+            version: ReplicaVersion,
 
             /// Git tag for the release.
             release_tag: String,
@@ -327,6 +506,7 @@ pub(crate) mod hostos {
 
 pub(crate) mod nodes {
     use super::*;
+    use ic_management_types::requests::DecommissionReason;
 
     #[derive(Parser, Clone)]
     pub struct Cmd {
@@ -357,6 +537,26 @@ pub(crate) mod nodes {
             #[clap(long, aliases = ["summary"])]
             motivation: Option<String>,
         },
+
+        /// Decommission the given nodes, e.g. of an offboarding provider
+        Decommission {
+            /// The nodes to decommission
+            #[clap(required = true)]
+            nodes: Vec<PrincipalId>,
+
+            /// Why the nodes are decommissioned: offboarding, broken, duplicate or any other text
+            #[clap(long)]
+            reason: DecommissionReason,
+
+            /// Motivation for decommissioning the nodes, or `auto` for a
+            /// structured one stating the reason
+            #[clap(long, aliases = ["summary"])]
+            motivation: String,
+
+            /// Show how removing each node affects the decentralization of its subnet
+            #[clap(long)]
+            check_subnet_impact: bool,
+        },
     }
 }
 
@@ -365,6 +565,7 @@ pub struct Cli {
     pub ic_admin: Option<String>,
     pub nns_url: url::Url,
     pub yes: bool,
+    pub read_only: bool,
     pub neuron: Option<Neuron>,
 }
 
@@ -414,7 +615,7 @@ impl Cli {
     }
 
     pub async fn from_opts(opts: &Opts, require_authentication: bool) -> anyhow::Result<Self> {
-        let nns_url = opts.network.get_url();
+        let nns_url = opts.network.get_url()?;
         let neuron = if let Some(id) = opts.neuron_id {
             Some(Neuron {
                 id,
@@ -444,9 +645,69 @@ impl Cli {
         };
         Ok(Cli {
             yes: opts.yes,
+            read_only: opts.read_only,
             neuron,
             ic_admin: opts.ic_admin.clone(),
             nns_url,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn opts(args: &[&str]) -> Opts {
+        Opts::try_parse_from([&["dre"], args].concat()).unwrap()
+    }
+
+    #[test]
+    fn proposals_are_refused_in_read_only_mode() {
+        let replace = [
+            "subnet",
+            "--id",
+            "yndj2-3ybaa-aaaaa-aaaap-yai",
+            "replace",
+            "--optimize",
+            "1",
+        ];
+        for args in [
+            [&["--read-only"], replace.as_slice()].concat(),
+            [&["--read-only", "--yes"], replace.as_slice()].concat(),
+            [&["--read-only", "--simulate"], replace.as_slice()].concat(),
+            [replace.as_slice(), &["--read-only"]].concat(),
+        ] {
+            let err = opts(&args).check_read_only().unwrap_err();
+            assert!(err.to_string().contains("--read-only"), "{}", err);
+        }
+        assert!(opts(&replace).check_read_only().is_ok());
+
+        for args in [vec!["propose", "update-subnet"], vec!["nodes", "remove"], vec!["vote"]] {
+            assert!(opts(&[&["--read-only"], args.as_slice()].concat())
+                .check_read_only()
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn reports_are_allowed_in_read_only_mode() {
+        for args in [
+            vec!["get", "subnet", "0"],
+            vec!["report"],
+            vec!["subnets"],
+            vec!["features", "country"],
+            vec![
+                "subnet",
+                "--id",
+                "yndj2-3ybaa-aaaaa-aaaap-yai",
+                "verify",
+                "--expected",
+                "score.json",
+            ],
+        ] {
+            assert!(opts(&[&["--read-only"], args.as_slice()].concat())
+                .check_read_only()
+                .is_ok());
+        }
+    }
+}
@@ -1,20 +1,35 @@
 use async_trait::async_trait;
-use decentralization::SubnetChangeResponse;
 use ic_base_types::PrincipalId;
+use ic_management_types::api_version::{ApiVersion, API_VERSION_HEADER};
+use ic_management_types::idempotency::IdempotencyKey;
+use ic_management_types::pagination::{Page, PageRequest};
 use ic_management_types::requests::HostosRolloutResponse;
+use ic_management_types::unknown_fields;
+use ic_management_types::validation::{describe_issues, Validate};
+use ic_management_types::versions::{NetworkRolloutStatus, ReplicaVersion};
 use ic_management_types::{
     requests::{
-        HostosRolloutRequest, MembershipReplaceRequest, NodesRemoveRequest, NodesRemoveResponse, SubnetCreateRequest,
-        SubnetResizeRequest,
+        HostosRolloutRequest, MembershipChangeResponse, MembershipReplaceRequest, NodeRemovalRequest,
+        NodeRemovalResponse, NodesRemoveRequest, NodesRemoveResponse, SubnetCreateRequest, SubnetResizeRequest,
     },
-    Artifact, Network, NetworkError, Release, TopologyProposal,
+    Artifact, ManagementError, ManagementErrorBody, Network, Node, OperatorDetails, ProviderDetails, Release, Subnet,
+    TopologyProposal,
 };
-use log::error;
+use log::{error, warn};
 use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// The delay before the first retry of a request, growing linearly with
+/// each further one.
+const RETRY_DELAY: Duration = Duration::from_millis(500);
 
 #[derive(Clone)]
 pub struct DashboardBackendClient {
     pub(crate) url: reqwest::Url,
+    /// How often to retry the requests computing changes.
+    pub(crate) retries: usize,
 }
 
 impl DashboardBackendClient {
@@ -33,15 +48,56 @@ impl DashboardBackendClient {
             .join(match network {
                 Network::Mainnet => "mainnet/",
                 Network::Staging => "staging/",
-                Network::Url(_) => "/",
+                Network::Custom { .. } => "/",
             })
             .expect("failed to join url"),
+            retries: 0,
         }
     }
 
     pub fn new_with_network_url(url: String) -> Self {
         Self {
             url: reqwest::Url::parse(&url).unwrap(),
+            retries: 0,
+        }
+    }
+
+    /// Retry the requests computing changes up to `retries` times if the
+    /// backend cannot be reached or is unavailable. The requests then carry
+    /// an idempotency key, the same for all attempts, so that the backend
+    /// computes the change only once.
+    pub fn with_retries(self, retries: usize) -> Self {
+        Self { retries, ..self }
+    }
+
+    /// A key for the request if it is to be retried and has none yet.
+    fn idempotency_key(&self, key: Option<IdempotencyKey>) -> Option<IdempotencyKey> {
+        match key {
+            None if self.retries > 0 => Some(IdempotencyKey::new_v4()),
+            key => key,
+        }
+    }
+
+    /// Post the request to `path`, retrying as configured.
+    async fn post_change<R: Serialize + Sync, T: DeserializeOwned>(
+        &self,
+        path: &str,
+        request: &R,
+    ) -> anyhow::Result<T> {
+        let url = self.url.join(path).map_err(|e| anyhow::anyhow!(e))?;
+        let mut attempt = 0;
+        loop {
+            match reqwest::Client::new().post(url.clone()).json(request).rest_send().await {
+                Err(e) if attempt < self.retries && is_transient(&e) => {
+                    attempt += 1;
+                    warn!(
+                        "Request to {} failed, retrying ({}/{}): {}",
+                        path, attempt, self.retries, e
+                    );
+                    tokio::time::sleep(RETRY_DELAY * attempt as u32).await;
+                }
+                result => return result,
+            }
         }
     }
 
@@ -56,56 +112,65 @@ impl DashboardBackendClient {
             .await
     }
 
-    pub async fn membership_replace(&self, request: MembershipReplaceRequest) -> anyhow::Result<SubnetChangeResponse> {
-        reqwest::Client::new()
-            .post(
-                self.url
-                    .join("subnet/membership/replace")
-                    .map_err(|e| anyhow::anyhow!(e))?,
-            )
-            .json(&request)
-            .rest_send()
-            .await
+    pub async fn membership_replace(
+        &self,
+        request: MembershipReplaceRequest,
+    ) -> anyhow::Result<MembershipChangeResponse> {
+        validate_request(&request)?;
+        let request = MembershipReplaceRequest {
+            idempotency_key: self.idempotency_key(request.idempotency_key),
+            ..request
+        };
+        self.post_change("subnet/membership/replace", &request).await
+    }
+
+    pub async fn subnet_resize(&self, request: SubnetResizeRequest) -> anyhow::Result<MembershipChangeResponse> {
+        validate_request(&request)?;
+        let request = SubnetResizeRequest {
+            idempotency_key: self.idempotency_key(request.idempotency_key),
+            ..request
+        };
+        self.post_change("subnet/membership/resize", &request).await
+    }
+
+    pub async fn subnet_create(&self, request: SubnetCreateRequest) -> anyhow::Result<MembershipChangeResponse> {
+        validate_request(&request)?;
+        let request = SubnetCreateRequest {
+            idempotency_key: self.idempotency_key(request.idempotency_key),
+            ..request
+        };
+        self.post_change("subnet/create", &request).await
     }
 
-    pub async fn subnet_resize(&self, request: SubnetResizeRequest) -> anyhow::Result<SubnetChangeResponse> {
+    pub async fn get_retireable_versions(&self, release_artifact: &Artifact) -> anyhow::Result<Vec<Release>> {
         reqwest::Client::new()
-            .post(
+            .get(
                 self.url
-                    .join("subnet/membership/resize")
+                    .join(&format!("release/retireable/{}", release_artifact))
                     .map_err(|e| anyhow::anyhow!(e))?,
             )
-            .json(&request)
             .rest_send()
             .await
     }
 
-    pub async fn subnet_create(&self, request: SubnetCreateRequest) -> anyhow::Result<SubnetChangeResponse> {
+    pub async fn get_nns_replica_version(&self) -> anyhow::Result<ReplicaVersion> {
         reqwest::Client::new()
-            .post(self.url.join("subnet/create").map_err(|e| anyhow::anyhow!(e))?)
-            .json(&request)
+            .get(self.url.join("release/versions/nns").map_err(|e| anyhow::anyhow!(e))?)
             .rest_send()
             .await
     }
 
-    pub async fn get_retireable_versions(&self, release_artifact: &Artifact) -> anyhow::Result<Vec<Release>> {
+    pub async fn rollout_status(&self) -> anyhow::Result<NetworkRolloutStatus> {
         reqwest::Client::new()
             .get(
                 self.url
-                    .join(&format!("release/retireable/{}", release_artifact))
+                    .join("release/rollout/status")
                     .map_err(|e| anyhow::anyhow!(e))?,
             )
             .rest_send()
             .await
     }
 
-    pub async fn get_nns_replica_version(&self) -> anyhow::Result<String> {
-        reqwest::Client::new()
-            .get(self.url.join("release/versions/nns").map_err(|e| anyhow::anyhow!(e))?)
-            .rest_send()
-            .await
-    }
-
     pub async fn get_blessed_versions(&self, release_artifact: &Artifact) -> anyhow::Result<Option<Vec<String>>> {
         reqwest::Client::new()
             .get(
@@ -125,6 +190,64 @@ impl DashboardBackendClient {
             .await
     }
 
+    /// All nodes of the network, fetched one page at a time.
+    pub async fn nodes(&self) -> anyhow::Result<BTreeMap<PrincipalId, Node>> {
+        let url = self.url.join("nodes/page").map_err(|e| anyhow::anyhow!(e))?;
+        let client = reqwest::Client::new();
+        let mut nodes = BTreeMap::new();
+        let mut request = PageRequest::default();
+        loop {
+            let page: Page<Node> = client.get(url.clone()).query(&request).rest_send().await?;
+            nodes.extend(page.items.into_iter().map(|n| (n.principal, n)));
+            match page.next_cursor {
+                Some(cursor) => request = PageRequest::after(Some(cursor)),
+                None => return Ok(nodes),
+            }
+        }
+    }
+
+    /// The subnet with its nodes, without fetching the rest of the network.
+    pub async fn subnet(&self, subnet: PrincipalId) -> anyhow::Result<Subnet> {
+        let mut url = self.url.join("subnet").map_err(|e| anyhow::anyhow!(e))?;
+        url.query_pairs_mut().append_pair("id", &subnet.to_string());
+        reqwest::Client::new().get(url).rest_send().await
+    }
+
+    /// The nodes of `subnet` if given, otherwise all nodes of the network.
+    pub async fn nodes_of(&self, subnet: Option<PrincipalId>) -> anyhow::Result<BTreeMap<PrincipalId, Node>> {
+        match subnet {
+            Some(subnet) => Ok(self
+                .subnet(subnet)
+                .await?
+                .nodes
+                .into_iter()
+                .map(|n| (n.principal, n))
+                .collect()),
+            None => self.nodes().await,
+        }
+    }
+
+    pub async fn providers(&self) -> anyhow::Result<BTreeMap<PrincipalId, ProviderDetails>> {
+        reqwest::Client::new()
+            .get(self.url.join("providers").map_err(|e| anyhow::anyhow!(e))?)
+            .rest_send()
+            .await
+    }
+
+    pub async fn subnets(&self) -> anyhow::Result<BTreeMap<PrincipalId, Subnet>> {
+        reqwest::Client::new()
+            .get(self.url.join("subnets").map_err(|e| anyhow::anyhow!(e))?)
+            .rest_send()
+            .await
+    }
+
+    pub async fn operators(&self) -> anyhow::Result<BTreeMap<PrincipalId, OperatorDetails>> {
+        reqwest::Client::new()
+            .get(self.url.join("operators/details").map_err(|e| anyhow::anyhow!(e))?)
+            .rest_send()
+            .await
+    }
+
     pub async fn remove_nodes(&self, request: NodesRemoveRequest) -> anyhow::Result<NodesRemoveResponse> {
         reqwest::Client::new()
             .post(self.url.join("nodes/remove").map_err(|e| anyhow::anyhow!(e))?)
@@ -132,6 +255,37 @@ impl DashboardBackendClient {
             .rest_send()
             .await
     }
+
+    pub async fn decommission_nodes(&self, request: NodeRemovalRequest) -> anyhow::Result<NodeRemovalResponse> {
+        validate_request(&request)?;
+        reqwest::Client::new()
+            .post(self.url.join("nodes/decommission").map_err(|e| anyhow::anyhow!(e))?)
+            .json(&request)
+            .rest_send()
+            .await
+    }
+}
+
+/// Whether a failed request may succeed when retried: the backend could not
+/// be reached or could not reach the registry.
+fn is_transient(error: &anyhow::Error) -> bool {
+    if let Some(error) = error.downcast_ref::<reqwest::Error>() {
+        return error.is_connect() || error.is_timeout() || error.is_request();
+    }
+    matches!(
+        error.downcast_ref::<ManagementError>(),
+        Some(ManagementError::RegistryUnavailable { .. })
+    )
+}
+
+/// Fail with the issues of `request` rather than sending it.
+fn validate_request(request: &impl Validate) -> anyhow::Result<()> {
+    let issues = request.validate();
+    if issues.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!("Invalid request: {}", describe_issues(&issues)))
+    }
 }
 
 #[async_trait]
@@ -142,15 +296,20 @@ trait RESTRequestBuilder {
 #[async_trait]
 impl RESTRequestBuilder for reqwest::RequestBuilder {
     async fn rest_send<T: DeserializeOwned>(self) -> anyhow::Result<T> {
-        let response_result = self.send().await?;
+        let response_result = self
+            .header(API_VERSION_HEADER, ApiVersion::CURRENT.to_string())
+            .send()
+            .await?;
         if let Err(e) = response_result.error_for_status_ref() {
             let response = response_result.text().await?;
-            match serde_json::from_str(&response) {
-                Ok(NetworkError::ResizeFailed(s)) => {
-                    error!("{}", s);
-                    Err(anyhow::anyhow!("failed request (error: {})", e))
+            match serde_json::from_str::<ManagementErrorBody>(&response) {
+                Ok(ManagementErrorBody { error }) => {
+                    if let ManagementError::ConstraintUnsatisfiable { details } = &error {
+                        error!("{}", details);
+                    }
+                    Err(anyhow::Error::new(error))
                 }
-                _ => Err(anyhow::anyhow!("failed request (error: {}, response: {})", e, response)),
+                Err(_) => Err(anyhow::anyhow!("failed request (error: {}, response: {})", e, response)),
             }
         } else {
             response_result
@@ -158,14 +317,19 @@ impl RESTRequestBuilder for reqwest::RequestBuilder {
                 .await
                 .map_err(|e| anyhow::anyhow!(e))
                 .and_then(|body| {
-                    serde_json::from_str::<T>(&body).map_err(|e| {
+                    let decoding_error = |e: serde_json::Error| {
                         anyhow::anyhow!(
                             "Error decoding {} from backend output: {}\n{}",
                             std::any::type_name::<T>(),
                             body,
                             e
                         )
-                    })
+                    };
+                    let value = serde_json::from_str::<serde_json::Value>(&body).map_err(decoding_error)?;
+                    for warning in unknown_fields::warnings(&value) {
+                        warn!("Backend: {}", warning);
+                    }
+                    serde_json::from_value::<T>(value).map_err(decoding_error)
                 })
         }
     }
@@ -173,6 +337,11 @@ impl RESTRequestBuilder for reqwest::RequestBuilder {
 
 #[cfg(test)]
 mod tests {
+    use std::net::Ipv6Addr;
+
+    use wiremock::matchers::{header, method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
     use super::*;
 
     #[test]
@@ -194,4 +363,172 @@ mod tests {
             "http://localhost:17000/api/proxy/registry/staging/"
         );
     }
+
+    #[tokio::test]
+    async fn nodes_of_a_subnet_fetch_only_the_subnet() {
+        let server = MockServer::start().await;
+        let subnet = Subnet {
+            principal: PrincipalId::new_subnet_test_id(1),
+            ..Default::default()
+        };
+        Mock::given(method("GET"))
+            .and(path("/subnet"))
+            .and(query_param("id", subnet.principal.to_string()))
+            .respond_with(ResponseTemplate::new(200).set_body_json(&subnet))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(path("/nodes/page"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(Page::<Node> {
+                items: vec![],
+                next_cursor: None,
+                total: Some(0),
+            }))
+            .expect(0)
+            .mount(&server)
+            .await;
+
+        let client = DashboardBackendClient::new_with_network_url(format!("{}/", server.uri()));
+        assert!(client.nodes_of(Some(subnet.principal)).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn nodes_are_fetched_page_by_page() {
+        let server = MockServer::start().await;
+        let node = |id| Node {
+            principal: PrincipalId::new_node_test_id(id),
+            ip_addr: Ipv6Addr::LOCALHOST,
+            operator: Default::default(),
+            hostname: None,
+            subnet_id: None,
+            hostos_release: None,
+            hostos_version: String::new(),
+            dfinity_owned: None,
+            proposal: None,
+            label: None,
+            decentralized: true,
+            duplicates: None,
+            annotations: Default::default(),
+        };
+        Mock::given(method("GET"))
+            .and(path("/nodes/page"))
+            .and(query_param("cursor", "next"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(Page {
+                items: vec![node(2)],
+                next_cursor: None,
+                total: Some(2),
+            }))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/nodes/page"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(Page {
+                items: vec![node(1)],
+                next_cursor: Some("next".to_string()),
+                total: Some(2),
+            }))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = DashboardBackendClient::new_with_network_url(format!("{}/", server.uri()));
+        assert_eq!(
+            client.nodes().await.unwrap().into_keys().collect::<Vec<_>>(),
+            vec![PrincipalId::new_node_test_id(1), PrincipalId::new_node_test_id(2)]
+        );
+    }
+
+    #[tokio::test]
+    async fn requests_declare_the_api_version() {
+        let server = MockServer::start().await;
+        let error = ManagementError::IncompatibleApiVersion {
+            requested: ApiVersion::CURRENT.to_string(),
+            supported: ">=2.0, <3.0".to_string(),
+        };
+        Mock::given(path("/subnet"))
+            .and(header(API_VERSION_HEADER, ApiVersion::CURRENT.to_string().as_str()))
+            .respond_with(ResponseTemplate::new(400).set_body_json(ManagementErrorBody { error: error.clone() }))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = DashboardBackendClient::new_with_network_url(format!("{}/", server.uri()));
+        let result = client.subnet(PrincipalId::new_subnet_test_id(1)).await;
+        assert_eq!(result.unwrap_err().downcast::<ManagementError>().unwrap(), error);
+    }
+
+    #[tokio::test]
+    async fn retries_reuse_the_idempotency_key() {
+        let server = MockServer::start().await;
+        let unavailable = ManagementError::RegistryUnavailable {
+            details: "registry is syncing".to_string(),
+        };
+        Mock::given(method("POST"))
+            .and(path("/subnet/membership/resize"))
+            .respond_with(ResponseTemplate::new(503).set_body_json(ManagementErrorBody { error: unavailable }))
+            .up_to_n_times(2)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/subnet/membership/resize"))
+            .respond_with(|request: &wiremock::Request| {
+                let request: SubnetResizeRequest = serde_json::from_slice(&request.body).unwrap();
+                ResponseTemplate::new(200)
+                    .set_body_json(MembershipChangeResponse::default().with_idempotency_key(request.idempotency_key))
+            })
+            .mount(&server)
+            .await;
+        let request = || {
+            SubnetResizeRequest::builder(PrincipalId::new_subnet_test_id(1))
+                .add(1)
+                .build()
+                .unwrap()
+        };
+
+        // Without retries the request fails right away, and has no key.
+        let client = DashboardBackendClient::new_with_network_url(format!("{}/", server.uri()));
+        assert!(client.subnet_resize(request()).await.is_err());
+        let response = client.with_retries(2).subnet_resize(request()).await.unwrap();
+
+        let keys = server
+            .received_requests()
+            .await
+            .unwrap()
+            .iter()
+            .map(|r| {
+                serde_json::from_slice::<SubnetResizeRequest>(&r.body)
+                    .unwrap()
+                    .idempotency_key
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(keys.len(), 3);
+        assert_eq!(keys[0], None);
+        assert!(keys[1].is_some());
+        assert_eq!(keys[2], keys[1]);
+        assert_eq!(response.idempotency_key, keys[1]);
+    }
+
+    #[tokio::test]
+    async fn responses_with_warnings_are_decoded() {
+        let server = MockServer::start().await;
+        let subnet = Subnet {
+            principal: PrincipalId::new_subnet_test_id(1),
+            ..Default::default()
+        };
+        let body = unknown_fields::add_warnings(
+            serde_json::to_value(&subnet).unwrap(),
+            vec!["unknown field 'motiviation'".to_string()],
+        );
+        Mock::given(path("/subnet"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(body))
+            .mount(&server)
+            .await;
+
+        let client = DashboardBackendClient::new_with_network_url(format!("{}/", server.uri()));
+        assert_eq!(
+            client.subnet(subnet.principal).await.unwrap().principal,
+            subnet.principal
+        );
+    }
 }
@@ -0,0 +1,219 @@
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use clap::ValueEnum;
+use ic_base_types::PrincipalId;
+use ic_management_types::{Node, NodeFeature};
+use serde::Serialize;
+use tabled::builder::Builder;
+use tabled::settings::Style;
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default)]
+pub enum OutputFormat {
+    #[default]
+    Table,
+    Json,
+    Csv,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct FeatureValueCount {
+    pub value: String,
+    pub nodes: usize,
+}
+
+/// Distinct values of `feature` across `nodes`, with the number of nodes
+/// having each value, sorted by value.
+pub fn feature_value_counts<'a>(
+    nodes: impl IntoIterator<Item = &'a Node>,
+    feature: &NodeFeature,
+) -> Vec<FeatureValueCount> {
+    nodes
+        .into_iter()
+        .map(|n| decentralization::network::Node::from(n).get_feature(feature))
+        .fold(BTreeMap::<String, usize>::new(), |mut acc, value| {
+            *acc.entry(value).or_default() += 1;
+            acc
+        })
+        .into_iter()
+        .map(|(value, nodes)| FeatureValueCount { value, nodes })
+        .collect()
+}
+
+pub fn format_feature_value_counts(
+    feature: &NodeFeature,
+    counts: &[FeatureValueCount],
+    output: OutputFormat,
+) -> anyhow::Result<String> {
+    Ok(match output {
+        OutputFormat::Table => {
+            let mut builder = Builder::default();
+            builder.push_record([feature.to_string(), "nodes".to_string()]);
+            for count in counts {
+                builder.push_record([count.value.clone(), count.nodes.to_string()]);
+            }
+            let mut table = builder.build();
+            table.with(Style::rounded());
+            table.to_string()
+        }
+        OutputFormat::Json => serde_json::to_string_pretty(counts)?,
+        OutputFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(vec![]);
+            writer.write_record([feature.to_string().as_str(), "nodes"])?;
+            for count in counts {
+                writer.write_record([count.value.as_str(), count.nodes.to_string().as_str()])?;
+            }
+            String::from_utf8(writer.into_inner()?)?
+        }
+    })
+}
+
+const FEATURE_OVERRIDES_HEADER: [&str; 3] = ["node_principal", "feature", "value"];
+
+/// Feature values to score the nodes with instead of their own, to model
+/// hypothetical networks.
+#[derive(Debug, Default)]
+pub struct FeatureOverrides {
+    overrides: BTreeMap<PrincipalId, Vec<(NodeFeature, String)>>,
+}
+
+impl FeatureOverrides {
+    /// Read the overrides from a CSV of `node_principal,feature,value` rows,
+    /// with that header. A later row for the same node and feature wins.
+    pub fn from_csv(reader: impl Read) -> anyhow::Result<Self> {
+        let mut reader = csv::Reader::from_reader(reader);
+        if !reader.headers()?.iter().map(str::trim).eq(FEATURE_OVERRIDES_HEADER) {
+            return Err(anyhow!(
+                "Expected the header {}, got {:?}",
+                FEATURE_OVERRIDES_HEADER.join(","),
+                reader.headers()?
+            ));
+        }
+
+        let mut overrides = BTreeMap::<PrincipalId, Vec<(NodeFeature, String)>>::new();
+        for record in reader.records() {
+            let record = record?;
+            let line = record.position().map_or(0, |p| p.line());
+            let node = PrincipalId::from_str(record[0].trim())
+                .map_err(|e| anyhow!("Line {}: invalid node principal '{}': {}", line, &record[0], e))?;
+            let feature =
+                NodeFeature::parse_with_suggestion(record[1].trim()).map_err(|e| anyhow!("Line {}: {}", line, e))?;
+            overrides
+                .entry(node)
+                .or_default()
+                .push((feature, record[2].trim().to_string()));
+        }
+        Ok(Self { overrides })
+    }
+
+    /// The overridden nodes that are not among `nodes`.
+    pub fn unknown_nodes<'a>(&self, nodes: impl IntoIterator<Item = &'a PrincipalId>) -> Vec<PrincipalId> {
+        let nodes = nodes.into_iter().collect::<Vec<_>>();
+        self.overrides.keys().filter(|p| !nodes.contains(p)).copied().collect()
+    }
+
+    /// `node` with its feature values overridden.
+    pub fn apply(&self, node: decentralization::network::Node) -> decentralization::network::Node {
+        match self.overrides.get(&node.id) {
+            Some(overrides) => decentralization::network::Node {
+                features: overrides
+                    .iter()
+                    .fold(node.features.clone(), |features, (feature, value)| {
+                        features.with_feature_value(feature, value)
+                    }),
+                ..node
+            },
+            None => node,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv6Addr;
+
+    use ic_management_types::{Datacenter, Operator};
+
+    use super::*;
+
+    fn node(id: u64, country: &str) -> Node {
+        Node {
+            principal: PrincipalId::new_node_test_id(id),
+            ip_addr: Ipv6Addr::LOCALHOST,
+            operator: Operator {
+                datacenter: Some(Datacenter {
+                    country: country.to_string(),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+            hostname: None,
+            subnet_id: None,
+            hostos_release: None,
+            hostos_version: String::new(),
+            dfinity_owned: None,
+            proposal: None,
+            label: None,
+            decentralized: true,
+            duplicates: None,
+            annotations: Default::default(),
+        }
+    }
+
+    #[test]
+    fn counts_distinct_countries() {
+        let nodes = [
+            node(1, "US"),
+            node(2, "CH"),
+            node(3, "US"),
+            node(4, "BE"),
+            node(5, "CH"),
+            node(6, "US"),
+        ];
+        let feature = NodeFeature::from_str("country").unwrap();
+
+        let counts = feature_value_counts(&nodes, &feature);
+
+        assert_eq!(
+            counts,
+            vec![
+                FeatureValueCount {
+                    value: "BE".to_string(),
+                    nodes: 1
+                },
+                FeatureValueCount {
+                    value: "CH".to_string(),
+                    nodes: 2
+                },
+                FeatureValueCount {
+                    value: "US".to_string(),
+                    nodes: 3
+                },
+            ]
+        );
+        assert_eq!(
+            format_feature_value_counts(&feature, &counts, OutputFormat::Csv).unwrap(),
+            "country,nodes\nBE,1\nCH,2\nUS,3\n"
+        );
+    }
+
+    #[test]
+    fn invalid_feature_overrides_are_reported_with_their_line() {
+        let node = PrincipalId::new_node_test_id(1);
+        let err = FeatureOverrides::from_csv(format!("node_principal,feature,value\n{},contry,DE\n", node).as_bytes())
+            .unwrap_err();
+        assert!(
+            err.to_string().starts_with("Line 2: invalid feature 'contry'"),
+            "{}",
+            err
+        );
+
+        assert!(FeatureOverrides::from_csv("node,feature,value\n".as_bytes()).is_err());
+        assert!(FeatureOverrides::from_csv("node_principal,feature,value\nnode,country,DE\n".as_bytes()).is_err());
+        assert!(
+            FeatureOverrides::from_csv(format!("node_principal,feature,value\n{},country\n", node).as_bytes()).is_err()
+        );
+    }
+}
@@ -6,15 +6,17 @@ use flate2::read::GzDecoder;
 use futures::stream::{self, StreamExt};
 use futures::Future;
 use ic_base_types::PrincipalId;
-use ic_management_types::Artifact;
+use ic_management_types::{Artifact, SubnetKind};
 use itertools::Itertools;
 use log::{error, info, warn};
 use regex::Regex;
 use reqwest::StatusCode;
 use sha2::{Digest, Sha256};
+use std::ffi::OsString;
 use std::fs::File;
 use std::io::Write;
 use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
 use std::{path::Path, process::Command};
 use strum::Display;
 
@@ -29,6 +31,7 @@ pub struct IcAdminWrapper {
     ic_admin: Option<String>,
     nns_url: url::Url,
     yes: bool,
+    read_only: bool,
     neuron: Option<Neuron>,
 }
 
@@ -38,6 +41,7 @@ impl From<Cli> for IcAdminWrapper {
             ic_admin: cli.ic_admin,
             nns_url: cli.nns_url,
             yes: cli.yes,
+            read_only: cli.read_only,
             neuron: cli.neuron,
         }
     }
@@ -130,6 +134,11 @@ impl IcAdminWrapper {
             return exec(self, cmd, opts, simulate);
         }
 
+        // Read-only mode never submits, whatever the command.
+        if self.read_only {
+            return Err(anyhow::anyhow!("Submitting proposals is not allowed with --read-only"));
+        }
+
         // If --yes was not specified, ask the user if they want to proceed
         if !self.yes {
             exec(self, cmd.clone(), opts.clone(), true)?;
@@ -154,7 +163,7 @@ impl IcAdminWrapper {
     }
 
     fn _run_ic_admin_with_args(&self, ic_admin_args: &[String], with_auth: bool) -> anyhow::Result<()> {
-        let ic_admin_path = self.ic_admin.clone().unwrap_or_else(|| "ic-admin".to_string());
+        let ic_admin_path = find_ic_admin(self.ic_admin.as_deref(), std::env::var_os("PATH"), dirs::home_dir())?;
         let mut cmd = Command::new(ic_admin_path);
         let auth_options = if with_auth {
             self.neuron.as_ref().map(|n| n.auth.as_arg_vec()).unwrap_or_default()
@@ -193,7 +202,13 @@ impl IcAdminWrapper {
     /// extract the ones matching `needle_regex` and return them as a
     /// `Vec<String>`
     fn grep_subcommands(&self, needle_regex: &str) -> Vec<String> {
-        let ic_admin_path = self.ic_admin.clone().unwrap_or_else(|| "ic-admin".to_string());
+        let ic_admin_path = match find_ic_admin(self.ic_admin.as_deref(), std::env::var_os("PATH"), dirs::home_dir()) {
+            Ok(path) => path,
+            Err(err) => {
+                error!("{}", err);
+                return vec![];
+            }
+        };
         let cmd_result = Command::new(ic_admin_path).args(["--help"]).output();
         match cmd_result.map_err(|e| e.to_string()) {
             Ok(output) => {
@@ -561,6 +576,7 @@ pub(crate) enum ProposeCommand {
     CreateSubnet {
         node_ids: Vec<PrincipalId>,
         replica_version: String,
+        subnet_kind: SubnetKind,
     },
 }
 
@@ -627,8 +643,13 @@ impl ProposeCommand {
             Self::CreateSubnet {
                 node_ids,
                 replica_version,
+                subnet_kind,
             } => {
-                let mut args = vec!["--subnet-type".to_string(), "application".to_string()];
+                let subnet_type = subnet_kind
+                    .registry_subnet_type()
+                    .map(SubnetKind::from)
+                    .unwrap_or_else(|| subnet_kind.clone());
+                let mut args = vec!["--subnet-type".to_string(), subnet_type.to_string()];
 
                 args.push("--replica-version-id".to_string());
                 args.push(replica_version.to_string());
@@ -649,6 +670,71 @@ pub struct ProposeOptions {
     pub motivation: Option<String>,
 }
 
+fn is_executable(path: &Path) -> bool {
+    std::fs::metadata(path)
+        .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+/// Find the ic-admin binary to run. An explicitly provided path is used as
+/// is and an explicitly provided command name is looked up on `PATH`.
+/// Otherwise `PATH` is searched, followed by the location where
+/// [`download_ic_admin`] stores the default version. The error lists every
+/// location that was searched.
+fn find_ic_admin(explicit: Option<&str>, path_var: Option<OsString>, home_dir: Option<PathBuf>) -> Result<PathBuf> {
+    if let Some(explicit) = explicit {
+        // A command name without a path separator is looked up the way the
+        // shell does.
+        if !explicit.contains('/') {
+            let candidates = path_candidates(path_var, explicit);
+            return candidates.iter().find(|p| is_executable(p)).cloned().ok_or_else(|| {
+                anyhow!(
+                    "{} not found on PATH (provided with --ic-admin). Searched:\n{}",
+                    explicit,
+                    candidates.iter().map(|p| format!("  {}", p.display())).join("\n")
+                )
+            });
+        }
+        let path = PathBuf::from(explicit);
+        if !path.exists() {
+            return Err(anyhow!(
+                "ic-admin not found at {} (provided with --ic-admin)",
+                path.display()
+            ));
+        }
+        if !is_executable(&path) {
+            return Err(anyhow!("ic-admin at {} is not an executable file", path.display()));
+        }
+        return Ok(path);
+    }
+
+    let mut candidates = path_candidates(path_var, "ic-admin");
+    if let Some(home_dir) = home_dir {
+        candidates.push(
+            home_dir
+                .join("bin")
+                .join("ic-admin.revisions")
+                .join(defaults::DEFAULT_IC_ADMIN_VERSION)
+                .join("ic-admin"),
+        );
+    }
+
+    if let Some(path) = candidates.iter().find(|p| is_executable(p)) {
+        return Ok(path.clone());
+    }
+    Err(anyhow!(
+        "ic-admin not found, provide its path with --ic-admin or the IC_ADMIN environment variable. Searched:\n{}",
+        candidates.iter().map(|p| format!("  {}", p.display())).join("\n")
+    ))
+}
+
+/// The locations of the command `name` in each directory of `PATH`.
+fn path_candidates(path_var: Option<OsString>, name: &str) -> Vec<PathBuf> {
+    path_var
+        .map(|p| std::env::split_paths(&p).map(|dir| dir.join(name)).collect::<Vec<_>>())
+        .unwrap_or_default()
+}
+
 /// Returns a path to downloaded ic-admin binary
 async fn download_ic_admin(version: Option<String>) -> Result<String> {
     let version = version
@@ -704,6 +790,74 @@ mod tests {
     use tempfile::NamedTempFile;
     use wiremock::MockServer;
 
+    #[test]
+    fn missing_ic_admin_lists_searched_locations() {
+        let home = tempfile::tempdir().unwrap();
+        let bin = tempfile::tempdir().unwrap();
+
+        let err = find_ic_admin(
+            None,
+            Some(bin.path().as_os_str().to_owned()),
+            Some(home.path().to_path_buf()),
+        )
+        .unwrap_err()
+        .to_string();
+
+        assert!(err.starts_with("ic-admin not found"), "unexpected error: {}", err);
+        assert!(err.contains(&bin.path().join("ic-admin").display().to_string()));
+        assert!(err.contains(&home.path().join("bin").join("ic-admin.revisions").display().to_string()));
+    }
+
+    #[test]
+    fn ic_admin_must_be_executable() {
+        let file = NamedTempFile::new().unwrap();
+        std::fs::set_permissions(file.path(), std::fs::Permissions::from_mode(0o644)).unwrap();
+
+        let err = find_ic_admin(file.path().to_str(), None, None).unwrap_err().to_string();
+        assert!(err.contains("is not an executable file"), "unexpected error: {}", err);
+
+        std::fs::set_permissions(file.path(), std::fs::Permissions::from_mode(0o755)).unwrap();
+        assert_eq!(find_ic_admin(file.path().to_str(), None, None).unwrap(), file.path());
+    }
+
+    #[test]
+    fn ic_admin_command_name_is_looked_up_on_path() {
+        let bin = tempfile::tempdir().unwrap();
+        let path = bin.path().join("ic-admin");
+        std::fs::write(&path, "").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let path_var = || Some(bin.path().as_os_str().to_owned());
+
+        assert_eq!(find_ic_admin(Some("ic-admin"), path_var(), None).unwrap(), path);
+
+        let err = find_ic_admin(Some("other-ic-admin"), path_var(), None)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.starts_with("other-ic-admin not found on PATH"),
+            "unexpected error: {}",
+            err
+        );
+        assert!(err.contains(&bin.path().join("other-ic-admin").display().to_string()));
+    }
+
+    #[test]
+    fn nothing_is_proposed_in_read_only_mode() {
+        let cli = IcAdminWrapper {
+            ic_admin: None,
+            nns_url: url::Url::from_str("http://localhost:8080").unwrap(),
+            yes: true,
+            read_only: true,
+            neuron: None,
+        };
+        let cmd = ProposeCommand::Raw {
+            command: "propose-to-update-subnet".to_string(),
+            args: vec![],
+        };
+        let err = cli.propose_run(cmd, Default::default(), false).unwrap_err();
+        assert!(err.to_string().contains("--read-only"), "unexpected error: {}", err);
+    }
+
     #[ignore]
     #[tokio::test]
     async fn test_propose_dry_run() -> Result<()> {
@@ -735,6 +889,7 @@ oSMDIQBa2NLmSmaqjDXej4rrJEuEhKIz7/pGXpxztViWhB+X9Q==
             let cli = IcAdminWrapper {
                 nns_url: url::Url::from_str(&mock_server.uri()).unwrap(),
                 yes: false,
+                read_only: false,
                 neuron: Neuron {
                     id: 3,
                     auth: Auth::Keyfile {
@@ -0,0 +1,174 @@
+use std::collections::BTreeMap;
+
+use ic_base_types::PrincipalId;
+use ic_management_types::versions::NetworkRolloutStatus;
+use ic_management_types::{OperatorDetails, ProviderDetails, Subnet};
+use tabled::builder::Builder;
+use tabled::settings::Style;
+
+use crate::features::OutputFormat;
+
+const PROVIDERS_HEADER: [&str; 4] = ["provider", "name", "nodes", "subnets"];
+const OPERATORS_HEADER: [&str; 5] = ["operator", "provider", "data center", "allowance", "rewardable nodes"];
+const SUBNETS_HEADER: [&str; 5] = ["subnet", "name", "kind", "nodes", "replica version"];
+
+fn provider_row(provider: &ProviderDetails) -> [String; 4] {
+    [
+        provider.principal.to_string(),
+        provider.name.clone().unwrap_or_default(),
+        provider.total_nodes.to_string(),
+        provider.nodes_per_subnet.len().to_string(),
+    ]
+}
+
+fn operator_row(operator: &OperatorDetails) -> [String; 5] {
+    [
+        operator.principal.to_string(),
+        operator.provider.to_string(),
+        operator.dc_id.clone(),
+        operator.allowance.to_string(),
+        operator
+            .rewardable_nodes
+            .iter()
+            .map(|(node_type, count)| format!("{}: {}", node_type, count))
+            .collect::<Vec<_>>()
+            .join(", "),
+    ]
+}
+
+fn subnet_row(subnet: &Subnet) -> [String; 5] {
+    [
+        subnet.principal.to_string(),
+        subnet.metadata.name.clone(),
+        subnet.kind.to_string(),
+        subnet.nodes.len().to_string(),
+        subnet.replica_version.clone(),
+    ]
+}
+
+fn format_rows<T: serde::Serialize, const N: usize>(
+    header: [&str; N],
+    items: &[T],
+    row: fn(&T) -> [String; N],
+    output: OutputFormat,
+) -> anyhow::Result<String> {
+    Ok(match output {
+        OutputFormat::Table => {
+            let mut builder = Builder::default();
+            builder.push_record(header);
+            for item in items {
+                builder.push_record(row(item));
+            }
+            let mut table = builder.build();
+            table.with(Style::rounded());
+            table.to_string()
+        }
+        OutputFormat::Json => serde_json::to_string_pretty(items)?,
+        OutputFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(vec![]);
+            writer.write_record(header)?;
+            for item in items {
+                writer.write_record(row(item))?;
+            }
+            String::from_utf8(writer.into_inner()?)?
+        }
+    })
+}
+
+pub fn format_providers(providers: &[ProviderDetails], output: OutputFormat) -> anyhow::Result<String> {
+    format_rows(PROVIDERS_HEADER, providers, provider_row, output)
+}
+
+pub fn format_operators(operators: &[OperatorDetails], output: OutputFormat) -> anyhow::Result<String> {
+    format_rows(OPERATORS_HEADER, operators, operator_row, output)
+}
+
+pub fn format_subnets(subnets: &[Subnet], output: OutputFormat) -> anyhow::Result<String> {
+    format_rows(SUBNETS_HEADER, subnets, subnet_row, output)
+}
+
+/// A row per subnet and a column per version, the most recently adopted
+/// first, with since when the subnet runs the version if it is known.
+pub fn format_rollout_status(status: &NetworkRolloutStatus, output: OutputFormat) -> anyhow::Result<String> {
+    let header = std::iter::once("subnet".to_string())
+        .chain(status.versions.iter().map(|adoption| format!("{:#}", adoption.version)))
+        .collect::<Vec<_>>();
+    let mut rows = BTreeMap::<PrincipalId, Vec<String>>::new();
+    for (column, adoption) in status.versions.iter().enumerate() {
+        for info in &adoption.subnets {
+            let cell = match info.since {
+                Some(since) => since.datetime().format("%Y-%m-%d %H:%M").to_string(),
+                None => "running".to_string(),
+            };
+            let row = rows
+                .entry(info.subnet)
+                .or_insert_with(|| vec![String::new(); status.versions.len()]);
+            row[column] = cell;
+        }
+    }
+    let rows = rows
+        .into_iter()
+        .map(|(subnet, cells)| std::iter::once(subnet.to_string()).chain(cells).collect::<Vec<_>>());
+
+    Ok(match output {
+        OutputFormat::Table => {
+            let mut builder = Builder::default();
+            builder.push_record(header);
+            for row in rows {
+                builder.push_record(row);
+            }
+            let mut table = builder.build();
+            table.with(Style::rounded());
+            table.to_string()
+        }
+        OutputFormat::Json => serde_json::to_string_pretty(status)?,
+        OutputFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(vec![]);
+            writer.write_record(header)?;
+            for row in rows {
+                writer.write_record(row)?;
+            }
+            String::from_utf8(writer.into_inner()?)?
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use ic_management_types::timestamp::Timestamp;
+    use ic_management_types::versions::{SubnetVersionInfo, VersionAdoption};
+
+    use super::*;
+
+    #[test]
+    fn rollout_status_is_a_matrix_of_subnets_and_versions() {
+        let info = |subnet: u64, since: Option<i64>| SubnetVersionInfo {
+            subnet: PrincipalId::new_subnet_test_id(subnet),
+            since: since.and_then(Timestamp::from_unix_seconds),
+            proposal_id: since.map(|_| subnet),
+        };
+        let status = NetworkRolloutStatus {
+            versions: vec![
+                VersionAdoption {
+                    version: "85bd56a70e55b2cea75cae6405ae11243e5fdad8".parse().unwrap(),
+                    subnets: vec![info(2, Some(1_700_000_000))],
+                },
+                VersionAdoption {
+                    version: "2e921c9adfc71f3edc96a9eb5d85fc742e7d8a9f".parse().unwrap(),
+                    subnets: vec![info(1, None), info(3, Some(1_600_000_000))],
+                },
+            ],
+        };
+        let csv = format_rollout_status(&status, OutputFormat::Csv).unwrap();
+        let subnet = |id| PrincipalId::new_subnet_test_id(id).to_string();
+        assert_eq!(
+            csv.lines().collect::<Vec<_>>(),
+            vec![
+                "subnet,85bd56a,2e921c9".to_string(),
+                format!("{},,running", subnet(1)),
+                format!("{},2023-11-14 22:13,", subnet(2)),
+                format!("{},,2020-09-13 12:26", subnet(3)),
+            ]
+        );
+    }
+}
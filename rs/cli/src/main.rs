@@ -2,10 +2,13 @@ use crate::general::{get_node_metrics_history, vote_on_proposals};
 use crate::ic_admin::IcAdminWrapper;
 use clap::{error::ErrorKind, CommandFactory, Parser};
 use dotenv::dotenv;
-use ic_base_types::CanisterId;
+use ic_base_types::{CanisterId, PrincipalId};
 use ic_canisters::governance::governance_canister_version;
 use ic_management_backend::endpoints;
-use ic_management_types::requests::NodesRemoveRequest;
+use ic_management_types::motivation::{Motivation, MotivationCategory, MotivationInput};
+use ic_management_types::requests::{
+    split_exclusions, DecommissionReason, FeatureMatch, NodeRemovalRequest, NodesRemoveRequest,
+};
 use ic_management_types::{Artifact, MinNakamotoCoefficients, Network, NodeFeature, NodeGroupUpdate, NumberOfNodes};
 use log::info;
 use std::collections::BTreeMap;
@@ -15,14 +18,22 @@ use std::thread;
 
 mod cli;
 mod clients;
+mod credentials;
 pub(crate) mod defaults;
 mod detect_neuron;
+mod features;
 mod general;
 mod ic_admin;
+mod listings;
 mod ops_subnet_node_replace;
+mod report;
 mod runner;
+mod selfcheck;
+mod verify;
 
 const STAGING_NEURON_ID: u64 = 49;
+/// The `--motivation` asking for a structured motivation.
+const AUTO_MOTIVATION: &str = "auto";
 
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
@@ -32,8 +43,14 @@ async fn main() -> Result<(), anyhow::Error> {
 
     let mut cli_opts = cli::Opts::parse();
     let mut cmd = cli::Opts::command();
+    cli_opts.check_read_only()?;
 
-    let governance_canister_v = governance_canister_version(cli_opts.network.get_url()).await?;
+    // Needs neither the network nor the backend.
+    if let cli::Commands::Selfcheck { snapshot } = &cli_opts.subcommand {
+        return selfcheck::run(snapshot);
+    }
+
+    let governance_canister_v = governance_canister_version(cli_opts.network.get_url()?).await?;
     let governance_canister_version = governance_canister_v.stringified_hash;
 
     let target_network = cli_opts.network.clone();
@@ -57,6 +74,9 @@ async fn main() -> Result<(), anyhow::Error> {
             cli_opts.private_key_pem = Some(std::env::var("HOME").expect("Please set HOME env var") + "/.config/dfx/identity/bootstrap-super-leader/identity.pem");
             cli_opts.neuron_id = Some(STAGING_NEURON_ID);
         }
+        // Keeps a private key read from the keyring or the credential command
+        // on disk until all commands are done.
+        let _key_file = credentials::resolve_credentials(&mut cli_opts)?;
 
         let simulate = cli_opts.simulate;
 
@@ -69,7 +89,7 @@ async fn main() -> Result<(), anyhow::Error> {
 
             cli::Commands::Subnet(subnet) => {
                 match &subnet.subcommand {
-                    cli::subnet::Commands::Deploy { .. } | cli::subnet::Commands::Resize { .. } => {
+                    cli::subnet::Commands::Deploy { .. } | cli::subnet::Commands::Resize { .. } | cli::subnet::Commands::Verify { .. } => {
                         if subnet.id.is_none() {
                             cmd.error(ErrorKind::MissingRequiredArgument, "Required argument `id` not found")
                                 .exit();
@@ -107,24 +127,30 @@ async fn main() -> Result<(), anyhow::Error> {
                         optimize,
                         motivation,
                         exclude,
+                        exclude_annotated,
                         only,
                         include,
                         min_nakamoto_coefficients,
+                        min_retained,
+                        save_expected_score,
                     } => {
                         let min_nakamoto_coefficients = parse_min_nakamoto_coefficients(&mut cmd, min_nakamoto_coefficients);
-                            let runner = runner::Runner::new_with_network_url(cli::Cli::from_opts(&cli_opts, true).await?.into(), backend_port).await?;
+                        let (exclude_nodes, exclude_features) = parse_exclude(&mut cmd, exclude);
+                            let runner = runner::Runner::new_with_network_url(cli::Cli::from_opts(&cli_opts, true).await?.into(), backend_port).await?.with_backend_retries(cli_opts.backend_retries);
                             runner
-                                .membership_replace(ic_management_types::requests::MembershipReplaceRequest {
-                                    target: match &subnet.id {
+                                .membership_replace(ic_management_types::requests::MembershipReplaceRequest::builder()
+                                    .target(match &subnet.id {
                                         Some(subnet) => {
                                             ic_management_types::requests::ReplaceTarget::Subnet(*subnet)
                                         }
                                         None => {
                                             if let Some(motivation) = motivation.clone() {
-                                                ic_management_types::requests::ReplaceTarget::Nodes {
-                                                    nodes: nodes.clone(),
-                                                    motivation,
-                                                }
+                                                let category = if optimize.is_some() { MotivationCategory::Optimize } else { MotivationCategory::Heal };
+                                                let motivation = parse_motivation(&motivation, category, || match nodes.len() {
+                                                    1 => "Replacing 1 node".to_string(),
+                                                    n => format!("Replacing {} nodes", n),
+                                                });
+                                                ic_management_types::requests::ReplaceTarget::nodes(nodes.clone(), motivation)
                                             } else {
                                                 cmd.error(
                                                     ErrorKind::MissingRequiredArgument,
@@ -133,27 +159,31 @@ async fn main() -> Result<(), anyhow::Error> {
                                                 .exit();
                                             }
                                         }
-                                    },
-                                    heal: !no_heal,
-                                    optimize: *optimize,
-                                    exclude: exclude.clone().into(),
-                                    only: only.clone(),
-                                    include: include.clone().into(),
-                                    min_nakamoto_coefficients,
-                                }, cli_opts.verbose, simulate)
+                                    })
+                                    .heal_if(!no_heal)
+                                    .optimize(*optimize)
+                                    .exclude_nodes(exclude_nodes)
+                                    .exclude_features(exclude_features)
+                                    .exclude_annotated(exclude_annotated.clone())
+                                    .only(only.clone())
+                                    .include(include.clone())
+                                    .min_nakamoto(min_nakamoto_coefficients)
+                                    .seed(cli_opts.seed)
+                                    .min_retained(*min_retained)
+                                    .build()?, save_expected_score.as_deref(), cli_opts.verbose, simulate)
                                 .await
                     }
-                    cli::subnet::Commands::Resize { add, remove, include, only, exclude, motivation, } => {
+                    cli::subnet::Commands::Resize { add, remove, include, only, exclude, motivation, save_expected_score } => {
                         if let Some(motivation) = motivation.clone() {
-                            let runner = runner::Runner::new_with_network_url(cli::Cli::from_opts(&cli_opts, true).await?.into(), backend_port).await?;
-                            runner.subnet_resize(ic_management_types::requests::SubnetResizeRequest {
-                                subnet: subnet.id.unwrap(),
-                                add: *add,
-                                remove: *remove,
-                                only: only.clone().into(),
-                                exclude: exclude.clone().into(),
-                                include: include.clone().into(),
-                            }, motivation, cli_opts.verbose, simulate).await
+                            let runner = runner::Runner::new_with_network_url(cli::Cli::from_opts(&cli_opts, true).await?.into(), backend_port).await?.with_backend_retries(cli_opts.backend_retries);
+                            runner.subnet_resize(ic_management_types::requests::SubnetResizeRequest::builder(subnet.id.unwrap())
+                                .add(*add)
+                                .remove(*remove)
+                                .only(only.clone())
+                                .exclude(exclude.clone())
+                                .include(include.clone())
+                                .seed(cli_opts.seed)
+                                .build()?, motivation, save_expected_score.as_deref(), cli_opts.verbose, simulate).await
                         } else {
                             cmd.error(
                                 ErrorKind::MissingRequiredArgument,
@@ -162,17 +192,19 @@ async fn main() -> Result<(), anyhow::Error> {
                             .exit();
                         }
                     }
-                    cli::subnet::Commands::Create { size, min_nakamoto_coefficients, exclude, only, include, motivation, replica_version } => {
+                    cli::subnet::Commands::Create { size, min_nakamoto_coefficients, exclude, only, include, motivation, replica_version, subnet_kind } => {
                         let min_nakamoto_coefficients = parse_min_nakamoto_coefficients(&mut cmd, min_nakamoto_coefficients);
                         if let Some(motivation) = motivation.clone() {
-                            let runner = runner::Runner::new_with_network_url(cli::Cli::from_opts(&cli_opts, true).await?.into(), backend_port).await?;
-                            runner.subnet_create(ic_management_types::requests::SubnetCreateRequest {
-                                size: *size,
-                                min_nakamoto_coefficients,
-                                only: only.clone().into(),
-                                exclude: exclude.clone().into(),
-                                include: include.clone().into(),
-                            }, motivation, cli_opts.verbose, simulate, replica_version.clone()).await
+                            let runner = runner::Runner::new_with_network_url(cli::Cli::from_opts(&cli_opts, true).await?.into(), backend_port).await?.with_backend_retries(cli_opts.backend_retries);
+                            runner.subnet_create(ic_management_types::requests::SubnetCreateRequest::builder(*size)
+                                .min_nakamoto(min_nakamoto_coefficients)
+                                .only(only.clone())
+                                .exclude(exclude.clone())
+                                .include(include.clone())
+                                .seed(cli_opts.seed)
+                                .replica_version(replica_version.as_ref().map(|version| version.to_string()))
+                                .subnet_kind(subnet_kind.clone())
+                                .build()?, motivation, cli_opts.verbose, simulate).await
                         } else {
                             cmd.error(
                                 ErrorKind::MissingRequiredArgument,
@@ -181,6 +213,10 @@ async fn main() -> Result<(), anyhow::Error> {
                             .exit();
                         }
                     }
+                    cli::subnet::Commands::Verify { expected, epsilon } => {
+                        let runner = runner::Runner::new_with_network_url(cli::Cli::from_opts(&cli_opts, false).await?.into(), backend_port).await?;
+                        runner.subnet_verify(subnet.id.unwrap(), expected, *epsilon).await
+                    }
                 }
             }
 
@@ -202,7 +238,15 @@ async fn main() -> Result<(), anyhow::Error> {
                         let release_artifact: &Artifact = &update_command.subcommand.clone().into();
 
                         let update_version = match &update_command.subcommand {
-                            cli::version::UpdateCommands::Replica { version, release_tag} | cli::version::UpdateCommands::HostOS { version, release_tag} => {
+                            cli::version::UpdateCommands::Replica { version, release_tag} => {
+                                ic_admin::IcAdminWrapper::prepare_to_propose_to_update_elected_versions(
+                                    release_artifact,
+                                    &version.to_string(),
+                                    release_tag,
+                                    runner.prepare_versions_to_retire(release_artifact, false).await.map(|res| res.1)?,
+                                )
+                            }
+                            cli::version::UpdateCommands::HostOS { version, release_tag} => {
                                 ic_admin::IcAdminWrapper::prepare_to_propose_to_update_elected_versions(
                                     release_artifact,
                                     version,
@@ -257,9 +301,20 @@ async fn main() -> Result<(), anyhow::Error> {
                             no_auto: *no_auto,
                             remove_degraded: *remove_degraded,
                             exclude: Some(exclude.clone()),
-                            motivation: motivation.clone().unwrap_or_default(),
+                            motivation: motivation.clone().unwrap_or_default().into(),
                         }, simulate).await
                     },
+                    cli::nodes::Commands::Decommission { nodes, reason, motivation, check_subnet_impact } => {
+                        let runner = runner::Runner::new_with_network_url(cli::Cli::from_opts(&cli_opts, true).await?.into(), backend_port).await?;
+                        runner.decommission_nodes(NodeRemovalRequest::builder(reason.clone())
+                            .nodes(nodes.clone())
+                            .motivation(parse_motivation(motivation, match reason {
+                                DecommissionReason::Offboarding => MotivationCategory::Offboard,
+                                _ => MotivationCategory::Other,
+                            }, || reason.to_string()))
+                            .check_subnet_impact_if(*check_subnet_impact)
+                            .build()?, simulate).await
+                    },
                 }
             },
 
@@ -271,6 +326,42 @@ async fn main() -> Result<(), anyhow::Error> {
                 }, cli.get_nns_url(), accepted_neurons, accepted_topics, simulate).await
             },
 
+            cli::Commands::Features { feature, output } => {
+                let runner = runner::Runner::new_with_network_url(cli::Cli::from_opts(&cli_opts, false).await?.into(), backend_port).await?;
+                runner.feature_values(feature, *output).await
+            },
+
+            cli::Commands::Providers { output } => {
+                let runner = runner::Runner::new_with_network_url(cli::Cli::from_opts(&cli_opts, false).await?.into(), backend_port).await?;
+                runner.providers(*output).await
+            },
+
+            cli::Commands::Operators { output } => {
+                let runner = runner::Runner::new_with_network_url(cli::Cli::from_opts(&cli_opts, false).await?.into(), backend_port).await?;
+                runner.operators(*output).await
+            },
+
+            cli::Commands::Subnets { output } => {
+                let runner = runner::Runner::new_with_network_url(cli::Cli::from_opts(&cli_opts, false).await?.into(), backend_port).await?;
+                runner.subnets(*output).await
+            },
+
+            cli::Commands::RolloutStatus { output } => {
+                let runner = runner::Runner::new_with_network_url(cli::Cli::from_opts(&cli_opts, false).await?.into(), backend_port).await?;
+                runner.rollout_status(*output).await
+            },
+
+            cli::Commands::Report { format, feature_overrides, subnet, providers } => {
+                let overrides = match feature_overrides {
+                    Some(path) => features::FeatureOverrides::from_csv(std::fs::File::open(path)?)?,
+                    None => features::FeatureOverrides::default(),
+                };
+                let runner = runner::Runner::new_with_network_url(cli::Cli::from_opts(&cli_opts, false).await?.into(), backend_port).await?;
+                runner.report(*format, overrides, *subnet, *providers).await
+            },
+
+            cli::Commands::Selfcheck { .. } => unreachable!("Checked before connecting to the network"),
+
             cli::Commands::TrustworthyMetrics { wallet, start_at_timestamp, subnet_ids } => {
                 let cli = cli::Cli::from_opts(&cli_opts, true).await?;
                 get_node_metrics_history(CanisterId::from_str(wallet)?, subnet_ids.clone(), *start_at_timestamp, match cli.get_neuron() {
@@ -307,6 +398,12 @@ async fn main() -> Result<(), anyhow::Error> {
 //           -> "data_centers" NC >= 4.0
 //           -> "node_provider" NC >= 5.0 (default)
 //           -> average NC >= 3.0 (default)
+//
+// ["distinct_owners=4"] => require at least 4 distinct data center owners
+//           -> average NC >= 3.0 (default)
+//
+// ["max_per_data_center=1"] => allow at most 1 node per data center
+//           -> average NC >= 3.0 (default)
 fn parse_min_nakamoto_coefficients(
     cmd: &mut clap::Command,
     min_nakamoto_coefficients: &[String],
@@ -321,6 +418,8 @@ fn parse_min_nakamoto_coefficients(
     };
 
     let mut average = 3.0;
+    let mut min_distinct_owners = None;
+    let mut max_nodes_per_data_center = None;
     let min_nakamoto_coefficients = min_nakamoto_coefficients
         .iter()
         .filter_map(|s| {
@@ -339,12 +438,36 @@ fn parse_min_nakamoto_coefficients(
                     })
                     .unwrap();
                 None
+            } else if key.to_lowercase() == "distinct_owners" {
+                min_distinct_owners = Some(
+                    val.parse::<usize>()
+                        .map_err(|_| {
+                            cmd.error(
+                                ErrorKind::ValueValidation,
+                                "Failed to parse the number of distinct owners",
+                            )
+                            .exit()
+                        })
+                        .unwrap(),
+                );
+                None
+            } else if key.to_lowercase() == "max_per_data_center" {
+                max_nodes_per_data_center = Some(
+                    val.parse::<usize>()
+                        .map_err(|_| {
+                            cmd.error(
+                                ErrorKind::ValueValidation,
+                                "Failed to parse the maximum number of nodes per data center",
+                            )
+                            .exit()
+                        })
+                        .unwrap(),
+                );
+                None
             } else {
-                let feature = match NodeFeature::from_str(key) {
+                let feature = match NodeFeature::parse_with_suggestion(key) {
                     Ok(v) => v,
-                    Err(_) => cmd
-                        .error(ErrorKind::ValueValidation, "Failed to parse feature from string")
-                        .exit(),
+                    Err(e) => cmd.error(ErrorKind::ValueValidation, e).exit(),
                 };
                 let val: f64 = val
                     .parse::<f64>()
@@ -361,9 +484,36 @@ fn parse_min_nakamoto_coefficients(
     Some(MinNakamotoCoefficients {
         coefficients: min_nakamoto_coefficients,
         average,
+        min_distinct_owners,
+        max_nodes_per_data_center,
     })
 }
 
+/// Split the values of `--exclude` into node IDs and feature values. A value
+/// given without its feature, e.g. `CH` rather than `country=CH`, excludes
+/// the nodes having it as the value of any feature.
+fn parse_exclude(cmd: &mut clap::Command, exclude: &[String]) -> (Vec<PrincipalId>, Vec<FeatureMatch>) {
+    match split_exclusions(exclude) {
+        Ok(exclusions) => exclusions,
+        Err(e) => cmd.error(ErrorKind::ValueValidation, e).exit(),
+    }
+}
+
+/// The `--motivation` as given or, if it is `auto`, a structured motivation
+/// of `category`. The backend adds to it how the change affects the
+/// decentralization of the subnet, where it can.
+fn parse_motivation(
+    motivation: &str,
+    category: MotivationCategory,
+    details: impl FnOnce() -> String,
+) -> MotivationInput {
+    if motivation.trim() == AUTO_MOTIVATION {
+        Motivation::new(category, details()).into()
+    } else {
+        motivation.into()
+    }
+}
+
 /// Get a localhost socket address with random, unused port.
 fn local_unused_port() -> u16 {
     let addr: std::net::SocketAddr = "127.0.0.1:0".parse().unwrap();
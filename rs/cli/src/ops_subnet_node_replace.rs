@@ -1,10 +1,10 @@
 use crate::ic_admin;
-use decentralization::SubnetChangeResponse;
+use ic_management_types::requests::MembershipChangeResponse;
 
 #[cfg(test)]
 mod tests;
 
-pub fn replace_proposal_options(change: &SubnetChangeResponse) -> anyhow::Result<ic_admin::ProposeOptions> {
+pub fn replace_proposal_options(change: &MembershipChangeResponse) -> anyhow::Result<ic_admin::ProposeOptions> {
     let subnet_id = change
         .subnet_id
         .ok_or_else(|| anyhow::anyhow!("subnet_id is required"))?
@@ -20,6 +20,6 @@ pub fn replace_proposal_options(change: &SubnetChangeResponse) -> anyhow::Result
     Ok(ic_admin::ProposeOptions {
         title: format!("Replace {replace_target} in subnet {subnet_id_short}",).into(),
         summary: format!("# Replace {replace_target} in subnet {subnet_id_short}",).into(),
-        motivation: change.motivation.clone(),
+        motivation: Some(change.motivation.clone()).filter(|m| !m.is_empty()),
     })
 }
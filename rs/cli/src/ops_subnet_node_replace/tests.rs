@@ -1,21 +1,26 @@
 use std::str::FromStr;
 
-use decentralization::SubnetChangeResponse;
 use ic_base_types::PrincipalId;
+use ic_management_types::requests::{MembershipChangeResponse, NodeSummary};
 
 use crate::ops_subnet_node_replace;
 
+fn node(principal: &str) -> NodeSummary {
+    NodeSummary {
+        principal: PrincipalId::from_str(principal).unwrap(),
+        ..Default::default()
+    }
+}
+
 #[test]
 fn replace_proposal_options_1_node() {
-    let change = SubnetChangeResponse {
+    let change = MembershipChangeResponse {
         subnet_id: PrincipalId::from_str("tdb26-jop6k-aogll-7ltgs-eruif-6kk7m-qpktf-gdiqx-mxtrf-vb5e6-eqe")
             .unwrap()
             .into(),
-        added: vec![PrincipalId::from_str("afx6y-22h67-ct72t-etddn-t2jaz-gfsrz-u3yxw-oocjp-gj3za-de3ot-2ae").unwrap()],
-        removed: vec![
-            PrincipalId::from_str("z3tum-w7bue-lt6ca-qgynf-us6oq-nc3qc-7miiq-34rbp-ekuoa-g6cqr-wqe").unwrap(),
-        ],
-        motivation: Some("For testing purposes".to_string()),
+        added: vec![node("afx6y-22h67-ct72t-etddn-t2jaz-gfsrz-u3yxw-oocjp-gj3za-de3ot-2ae")],
+        removed: vec![node("z3tum-w7bue-lt6ca-qgynf-us6oq-nc3qc-7miiq-34rbp-ekuoa-g6cqr-wqe")],
+        motivation: "For testing purposes".to_string(),
         ..Default::default()
     };
 
@@ -27,19 +32,19 @@ fn replace_proposal_options_1_node() {
 
 #[test]
 fn replace_proposal_options_2_nodes() {
-    let change = SubnetChangeResponse {
+    let change = MembershipChangeResponse {
         subnet_id: PrincipalId::from_str("tdb26-jop6k-aogll-7ltgs-eruif-6kk7m-qpktf-gdiqx-mxtrf-vb5e6-eqe")
             .unwrap()
             .into(),
         added: vec![
-            PrincipalId::from_str("afx6y-22h67-ct72t-etddn-t2jaz-gfsrz-u3yxw-oocjp-gj3za-de3ot-2ae").unwrap(),
-            PrincipalId::from_str("dsthq-itfw5-zkibk-chtl5-u7afl-xvxva-7swke-tvqif-vq3t2-wvp7x-mae").unwrap(),
+            node("afx6y-22h67-ct72t-etddn-t2jaz-gfsrz-u3yxw-oocjp-gj3za-de3ot-2ae"),
+            node("dsthq-itfw5-zkibk-chtl5-u7afl-xvxva-7swke-tvqif-vq3t2-wvp7x-mae"),
         ],
         removed: vec![
-            PrincipalId::from_str("z3tum-w7bue-lt6ca-qgynf-us6oq-nc3qc-7miiq-34rbp-ekuoa-g6cqr-wqe").unwrap(),
-            PrincipalId::from_str("ktrkp-ccur6-nvpyb-sokhh-exg7x-pfuds-4jxmw-n2r5m-vj5yt-aqzc4-vae").unwrap(),
+            node("z3tum-w7bue-lt6ca-qgynf-us6oq-nc3qc-7miiq-34rbp-ekuoa-g6cqr-wqe"),
+            node("ktrkp-ccur6-nvpyb-sokhh-exg7x-pfuds-4jxmw-n2r5m-vj5yt-aqzc4-vae"),
         ],
-        motivation: Some("For testing purposes".to_string()),
+        motivation: "For testing purposes".to_string(),
         ..Default::default()
     };
 
@@ -0,0 +1,284 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use clap::ValueEnum;
+use decentralization::nakamoto::NakamotoScore;
+use decentralization::network::{overrepresented_providers, DecentralizedSubnet, ProviderConcentration};
+use ic_base_types::PrincipalId;
+use ic_management_types::Node;
+use serde::Serialize;
+use tabled::builder::Builder;
+use tabled::settings::Style;
+
+use crate::features::FeatureOverrides;
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default)]
+pub enum ReportFormat {
+    #[default]
+    Table,
+    Json,
+    /// One JSON object per line, written as soon as its subnet is scored
+    Jsonl,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ScoredSubnetRecord {
+    pub subnet_id: PrincipalId,
+    pub nodes: usize,
+    pub score: NakamotoScore,
+}
+
+/// Score the subnets the `nodes` are assigned to, one subnet at a time and
+/// in the order of their ids, with the feature values of the nodes taken
+/// from `overrides` where given.
+pub fn scored_subnets<'a>(
+    nodes: impl IntoIterator<Item = &'a Node>,
+    overrides: &FeatureOverrides,
+) -> impl Iterator<Item = ScoredSubnetRecord> {
+    subnet_nodes(nodes, overrides)
+        .into_iter()
+        .map(|(subnet_id, nodes)| ScoredSubnetRecord {
+            subnet_id,
+            nodes: nodes.len(),
+            score: NakamotoScore::new_from_nodes(&nodes),
+        })
+}
+
+/// The node providers ranked by their nodes across all subnets the `nodes`
+/// are assigned to, with the feature values taken from `overrides` where
+/// given.
+pub fn provider_concentration<'a>(
+    nodes: impl IntoIterator<Item = &'a Node>,
+    overrides: &FeatureOverrides,
+) -> Vec<ProviderConcentration> {
+    let subnets = subnet_nodes(nodes, overrides)
+        .into_iter()
+        .map(|(id, nodes)| DecentralizedSubnet {
+            id,
+            nodes,
+            ..Default::default()
+        })
+        .collect::<Vec<_>>();
+    overrepresented_providers(&subnets)
+}
+
+fn subnet_nodes<'a>(
+    nodes: impl IntoIterator<Item = &'a Node>,
+    overrides: &FeatureOverrides,
+) -> BTreeMap<PrincipalId, Vec<decentralization::network::Node>> {
+    nodes
+        .into_iter()
+        .filter_map(|n| n.subnet_id.map(|subnet_id| (subnet_id, n)))
+        .fold(BTreeMap::new(), |mut acc, (subnet_id, node)| {
+            acc.entry(subnet_id)
+                .or_default()
+                .push(overrides.apply(decentralization::network::Node::from(node)));
+            acc
+        })
+}
+
+/// Write the `records` to `writer`. The JSON Lines format writes and flushes
+/// each record as it comes, the other formats need all of them first.
+pub fn write_report(
+    records: impl IntoIterator<Item = ScoredSubnetRecord>,
+    format: ReportFormat,
+    writer: impl Write,
+) -> anyhow::Result<()> {
+    write_records(
+        records,
+        &["subnet", "nodes", "score"],
+        |record| {
+            vec![
+                record.subnet_id.to_string(),
+                record.nodes.to_string(),
+                record.score.to_string(),
+            ]
+        },
+        format,
+        writer,
+    )
+}
+
+pub fn write_provider_report(
+    providers: Vec<ProviderConcentration>,
+    format: ReportFormat,
+    writer: impl Write,
+) -> anyhow::Result<()> {
+    write_records(
+        providers,
+        &["provider", "nodes", "subnets"],
+        |provider| {
+            vec![
+                provider.provider.clone(),
+                provider.nodes.to_string(),
+                provider.subnets.to_string(),
+            ]
+        },
+        format,
+        writer,
+    )
+}
+
+fn write_records<T: Serialize>(
+    records: impl IntoIterator<Item = T>,
+    header: &[&str],
+    row: impl Fn(&T) -> Vec<String>,
+    format: ReportFormat,
+    mut writer: impl Write,
+) -> anyhow::Result<()> {
+    match format {
+        ReportFormat::Table => {
+            let mut builder = Builder::default();
+            builder.push_record(header.iter().copied());
+            for record in records {
+                builder.push_record(row(&record));
+            }
+            let mut table = builder.build();
+            table.with(Style::rounded());
+            writeln!(writer, "{}", table)?;
+        }
+        ReportFormat::Json => {
+            serde_json::to_writer_pretty(&mut writer, &records.into_iter().collect::<Vec<_>>())?;
+            writeln!(writer)?;
+        }
+        ReportFormat::Jsonl => {
+            for record in records {
+                serde_json::to_writer(&mut writer, &record)?;
+                writeln!(writer)?;
+                writer.flush()?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv6Addr;
+
+    use ic_management_types::{Datacenter, NodeFeature, Operator};
+
+    use super::*;
+
+    fn node(id: u64, subnet: Option<u64>, country: &str) -> Node {
+        Node {
+            principal: PrincipalId::new_node_test_id(id),
+            ip_addr: Ipv6Addr::LOCALHOST,
+            operator: Operator {
+                datacenter: Some(Datacenter {
+                    country: country.to_string(),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+            hostname: None,
+            subnet_id: subnet.map(PrincipalId::new_subnet_test_id),
+            hostos_release: None,
+            hostos_version: String::new(),
+            dfinity_owned: None,
+            proposal: None,
+            label: None,
+            decentralized: true,
+            duplicates: None,
+            annotations: Default::default(),
+        }
+    }
+
+    #[test]
+    fn jsonl_lines_are_independent_json_objects() {
+        let nodes = [
+            node(1, Some(1), "US"),
+            node(2, Some(1), "CH"),
+            node(3, Some(2), "US"),
+            node(4, None, "BE"),
+            node(5, Some(2), "US"),
+            node(6, Some(3), "CH"),
+        ];
+
+        let mut output = vec![];
+        write_report(
+            scored_subnets(&nodes, &FeatureOverrides::default()),
+            ReportFormat::Jsonl,
+            &mut output,
+        )
+        .unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        let records = output
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(records.len(), 3);
+        assert!(records.iter().all(|r| r.is_object()));
+        assert_eq!(
+            records.iter().map(|r| r["nodes"].as_u64().unwrap()).collect::<Vec<_>>(),
+            vec![2, 2, 1]
+        );
+        assert_eq!(
+            records[0]["subnet_id"],
+            serde_json::to_value(PrincipalId::new_subnet_test_id(1)).unwrap()
+        );
+    }
+
+    #[test]
+    fn feature_overrides_change_the_scored_countries() {
+        let nodes = [node(1, Some(1), "US"), node(2, Some(1), "CH"), node(3, Some(1), "CH")];
+        let overrides = FeatureOverrides::from_csv(
+            format!(
+                "node_principal,feature,value\n{},country,DE\n{}, country ,DE\n",
+                nodes[1].principal, nodes[2].principal
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+
+        let country_counts = |overrides: &FeatureOverrides| {
+            scored_subnets(&nodes, overrides)
+                .next()
+                .unwrap()
+                .score
+                .feature_value_counts(&NodeFeature::Country)
+        };
+        assert_eq!(
+            country_counts(&FeatureOverrides::default()),
+            vec![("CH".to_string(), 2), ("US".to_string(), 1)]
+        );
+        assert_eq!(
+            country_counts(&overrides),
+            vec![("DE".to_string(), 2), ("US".to_string(), 1)]
+        );
+        assert!(overrides.unknown_nodes(nodes.iter().map(|n| &n.principal)).is_empty());
+        assert_eq!(
+            overrides.unknown_nodes(nodes[..2].iter().map(|n| &n.principal)),
+            vec![nodes[2].principal]
+        );
+    }
+
+    #[test]
+    fn providers_are_ranked_across_subnets() {
+        let mut nodes = [
+            node(1, Some(1), "US"),
+            node(2, Some(1), "CH"),
+            node(3, Some(2), "US"),
+            node(4, None, "BE"),
+        ];
+        nodes[1].operator.provider.principal = PrincipalId::new_user_test_id(1);
+
+        let providers = provider_concentration(&nodes, &FeatureOverrides::default());
+        assert_eq!(
+            providers
+                .iter()
+                .map(|p| (p.provider.clone(), p.nodes, p.subnets))
+                .collect::<Vec<_>>(),
+            vec![
+                (PrincipalId::default().to_string(), 2, 2),
+                (PrincipalId::new_user_test_id(1).to_string(), 1, 1),
+            ]
+        );
+
+        let mut output = vec![];
+        write_provider_report(providers, ReportFormat::Json, &mut output).unwrap();
+        let records = serde_json::from_slice::<serde_json::Value>(&output).unwrap();
+        assert_eq!(records[0]["nodes"], 2);
+    }
+}
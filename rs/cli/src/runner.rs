@@ -1,14 +1,23 @@
 use crate::clients::DashboardBackendClient;
+use crate::features::{feature_value_counts, format_feature_value_counts, FeatureOverrides, OutputFormat};
 use crate::ic_admin;
 use crate::ic_admin::ProposeOptions;
+use crate::listings::{format_operators, format_providers, format_rollout_status, format_subnets};
 use crate::ops_subnet_node_replace;
-use decentralization::SubnetChangeResponse;
+use crate::report::{provider_concentration, scored_subnets, write_provider_report, write_report, ReportFormat};
+use crate::verify;
 use ic_base_types::PrincipalId;
-use ic_management_types::requests::{HostosRolloutRequest, HostosRolloutResponse, NodesRemoveRequest};
+use ic_management_types::preview::ChangePreview;
+use ic_management_types::requests::{
+    HostosRolloutRequest, HostosRolloutResponse, MembershipChangeResponse, NodeRemovalRequest, NodesRemoveRequest,
+};
+use ic_management_types::score::ScoreSummary;
+use ic_management_types::versions::ReplicaVersion;
 use ic_management_types::{Artifact, Node, NodeFeature, NodeGroupUpdate};
 use itertools::Itertools;
 use log::{info, warn};
 use std::collections::BTreeMap;
+use std::path::Path;
 use tabled::builder::Builder;
 use tabled::settings::Style;
 
@@ -19,7 +28,7 @@ pub struct Runner {
 }
 
 impl Runner {
-    pub fn deploy(&self, subnet: &PrincipalId, version: &str, simulate: bool) -> anyhow::Result<()> {
+    pub fn deploy(&self, subnet: &PrincipalId, version: &ReplicaVersion, simulate: bool) -> anyhow::Result<()> {
         self.ic_admin
             .propose_run(
                 ic_admin::ProposeCommand::UpdateSubnetReplicaVersion {
@@ -42,17 +51,19 @@ impl Runner {
         &self,
         request: ic_management_types::requests::SubnetResizeRequest,
         motivation: String,
+        save_expected_score: Option<&Path>,
         verbose: bool,
         simulate: bool,
     ) -> anyhow::Result<()> {
         let subnet = request.subnet;
         let change = self.dashboard_backend_client.subnet_resize(request).await?;
         if verbose {
-            if let Some(run_log) = &change.run_log {
-                println!("{}\n", run_log.join("\n"));
-            }
+            println!("{}\n", change.run_log.join("\n"));
+        }
+        println!("{}", ChangePreview::from(&change));
+        if let Some(path) = save_expected_score {
+            verify::save_expected_score(path, &change.score_after)?;
         }
-        println!("{}", change);
 
         if change.added.is_empty() && change.removed.is_empty() {
             return Ok(());
@@ -89,27 +100,28 @@ impl Runner {
         motivation: String,
         verbose: bool,
         simulate: bool,
-        replica_version: Option<String>,
     ) -> anyhow::Result<()> {
+        let replica_version = request.replica_version.clone();
+        let subnet_kind = request.subnet_kind.clone().unwrap_or_default();
         let subnet_creation_data = self.dashboard_backend_client.subnet_create(request).await?;
         if verbose {
-            if let Some(run_log) = &subnet_creation_data.run_log {
-                println!("{}\n", run_log.join("\n"));
-            }
+            println!("{}\n", subnet_creation_data.run_log.join("\n"));
         }
-        println!("{}", subnet_creation_data);
+        println!("{}", ChangePreview::from(&subnet_creation_data));
 
         let replica_version = replica_version.unwrap_or(
             self.dashboard_backend_client
                 .get_nns_replica_version()
                 .await
-                .expect("Should get a replica version"),
+                .expect("Should get a replica version")
+                .to_string(),
         );
 
         self.ic_admin.propose_run(
             ic_admin::ProposeCommand::CreateSubnet {
-                node_ids: subnet_creation_data.added,
+                node_ids: subnet_creation_data.added.iter().map(|n| n.principal).collect(),
                 replica_version,
+                subnet_kind,
             },
             ic_admin::ProposeOptions {
                 title: Some("Creating new subnet".into()),
@@ -123,16 +135,18 @@ impl Runner {
     pub async fn membership_replace(
         &self,
         request: ic_management_types::requests::MembershipReplaceRequest,
+        save_expected_score: Option<&Path>,
         verbose: bool,
         simulate: bool,
     ) -> anyhow::Result<()> {
         let change = self.dashboard_backend_client.membership_replace(request).await?;
         if verbose {
-            if let Some(run_log) = &change.run_log {
-                println!("{}\n", run_log.join("\n"));
-            }
+            println!("{}\n", change.run_log.join("\n"));
+        }
+        println!("{}", ChangePreview::from(&change));
+        if let Some(path) = save_expected_score {
+            verify::save_expected_score(path, &change.score_after)?;
         }
-        println!("{}", change);
 
         if change.added.is_empty() && change.removed.is_empty() {
             return Ok(());
@@ -147,14 +161,17 @@ impl Runner {
 
     async fn run_membership_change(
         &self,
-        change: SubnetChangeResponse,
+        change: MembershipChangeResponse,
         options: ProposeOptions,
         simulate: bool,
     ) -> anyhow::Result<()> {
-        let subnet_id = change
-            .subnet_id
+        let payload = change
+            .proposal_payload
             .ok_or_else(|| anyhow::anyhow!("subnet_id is required"))?;
-        let pending_action = self.dashboard_backend_client.subnet_pending_action(subnet_id).await?;
+        let pending_action = self
+            .dashboard_backend_client
+            .subnet_pending_action(payload.subnet_id)
+            .await?;
         if let Some(proposal) = pending_action {
             return Err(anyhow::anyhow!(format!(
                 "There is a pending proposal for this subnet: https://dashboard.internetcomputer.org/proposal/{}",
@@ -165,9 +182,9 @@ impl Runner {
         self.ic_admin
             .propose_run(
                 ic_admin::ProposeCommand::ChangeSubnetMembership {
-                    subnet_id,
-                    node_ids_add: change.added.clone(),
-                    node_ids_remove: change.removed.clone(),
+                    subnet_id: payload.subnet_id,
+                    node_ids_add: payload.node_ids_add,
+                    node_ids_remove: payload.node_ids_remove,
                 },
                 options,
                 simulate,
@@ -175,6 +192,104 @@ impl Runner {
             .map_err(|e| anyhow::anyhow!(e))
     }
 
+    /// Print the distinct values of `feature` across all nodes in the network
+    pub async fn feature_values(&self, feature: &NodeFeature, output: OutputFormat) -> anyhow::Result<()> {
+        let nodes = self.dashboard_backend_client.nodes().await?;
+        let counts = feature_value_counts(nodes.values(), feature);
+        println!("{}", format_feature_value_counts(feature, &counts, output)?);
+        Ok(())
+    }
+
+    pub async fn providers(&self, output: OutputFormat) -> anyhow::Result<()> {
+        let providers = self.dashboard_backend_client.providers().await?;
+        println!(
+            "{}",
+            format_providers(&providers.into_values().collect::<Vec<_>>(), output)?
+        );
+        Ok(())
+    }
+
+    pub async fn operators(&self, output: OutputFormat) -> anyhow::Result<()> {
+        let operators = self.dashboard_backend_client.operators().await?;
+        println!(
+            "{}",
+            format_operators(&operators.into_values().collect::<Vec<_>>(), output)?
+        );
+        Ok(())
+    }
+
+    pub async fn subnets(&self, output: OutputFormat) -> anyhow::Result<()> {
+        let subnets = self.dashboard_backend_client.subnets().await?;
+        println!(
+            "{}",
+            format_subnets(&subnets.into_values().collect::<Vec<_>>(), output)?
+        );
+        Ok(())
+    }
+
+    /// Print which subnets run which replica version.
+    pub async fn rollout_status(&self, output: OutputFormat) -> anyhow::Result<()> {
+        let status = self.dashboard_backend_client.rollout_status().await?;
+        println!("{}", format_rollout_status(&status, output)?);
+        Ok(())
+    }
+
+    /// Print the decentralization score of every subnet in the network, or
+    /// only of `subnet`, fetching only its nodes. Overrides of nodes outside
+    /// of `subnet` are ignored.
+    pub async fn report(
+        &self,
+        format: ReportFormat,
+        overrides: FeatureOverrides,
+        subnet: Option<PrincipalId>,
+        providers: bool,
+    ) -> anyhow::Result<()> {
+        let nodes = self.dashboard_backend_client.nodes_of(subnet).await?;
+        let unknown_nodes = overrides.unknown_nodes(nodes.keys());
+        if subnet.is_none() && !unknown_nodes.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Feature overrides for unknown nodes: {:?}",
+                unknown_nodes
+            ));
+        }
+        if providers {
+            return write_provider_report(
+                provider_concentration(nodes.values(), &overrides),
+                format,
+                std::io::stdout().lock(),
+            );
+        }
+        write_report(
+            scored_subnets(nodes.values(), &overrides),
+            format,
+            std::io::stdout().lock(),
+        )
+    }
+
+    /// Compare the current score of the subnet with the expected one saved
+    /// in the file at `expected`.
+    pub async fn subnet_verify(&self, subnet: PrincipalId, expected: &Path, epsilon: f64) -> anyhow::Result<()> {
+        let expected = verify::load_expected_score(expected)?;
+        let nodes = self.dashboard_backend_client.nodes_of(Some(subnet)).await?;
+        let actual = scored_subnets(nodes.values(), &FeatureOverrides::default())
+            .find(|record| record.subnet_id == subnet)
+            .map(|record| ScoreSummary::from(&record.score))
+            .ok_or_else(|| anyhow::anyhow!("Subnet {} has no nodes", subnet))?;
+        let mismatches = verify::score_mismatches(&expected, &actual, epsilon);
+        if !mismatches.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Subnet {} does not have the expected score:\n{}",
+                subnet,
+                mismatches.join("\n")
+            ));
+        }
+        println!(
+            "Subnet {} has the expected score, average {:.2}",
+            subnet, actual.average_linear
+        );
+        Ok(())
+    }
+
     pub async fn new_with_network_url(ic_admin: ic_admin::IcAdminWrapper, backend_port: u16) -> anyhow::Result<Self> {
         let dashboard_backend_client =
             DashboardBackendClient::new_with_network_url(format!("http://localhost:{}/", backend_port));
@@ -184,6 +299,15 @@ impl Runner {
         })
     }
 
+    /// Retry the requests computing changes, see
+    /// [DashboardBackendClient::with_retries].
+    pub fn with_backend_retries(self, retries: usize) -> Self {
+        Self {
+            dashboard_backend_client: self.dashboard_backend_client.with_retries(retries),
+            ..self
+        }
+    }
+
     pub(crate) async fn prepare_versions_to_retire(
         &self,
         release_artifact: &Artifact,
@@ -406,6 +530,7 @@ impl Runner {
             .into_iter()
             .chain(NodeFeature::variants().iter().map(|nf| nf.to_string()))
             .chain(vec!["Hostname".to_string()].into_iter())
+            .chain(vec!["Annotations".to_string()].into_iter())
             .chain(vec!["Reason".to_string()].into_iter())
             .collect::<Vec<_>>();
         let mut table = tabular::Table::new(&headers.iter().map(|_| "    {:<}").collect::<Vec<_>>().join(""));
@@ -425,6 +550,7 @@ impl Runner {
                 row.add_cell(decentralization_node.get_feature(&nf));
             }
             row.add_cell(nr.node.hostname.clone().unwrap_or_else(|| "N/A".to_string()));
+            row.add_cell(&nr.node.annotations);
             row.add_cell(nr.reason.message());
             table.add_row(row);
         }
@@ -442,4 +568,56 @@ impl Runner {
             simulate,
         )
     }
+
+    pub async fn decommission_nodes(&self, request: NodeRemovalRequest, simulate: bool) -> anyhow::Result<()> {
+        let response = self.dashboard_backend_client.decommission_nodes(request).await?;
+
+        let score = |score: &Option<ScoreSummary>| {
+            score
+                .as_ref()
+                .map(|s| format!("{:.2} (min {})", s.average_linear, s.min))
+                .unwrap_or_else(|| "N/A".to_string())
+        };
+        let mut builder = Builder::default();
+        builder.push_record(["node", "subnet", "score before", "score after"]);
+        for impact in &response.nodes {
+            builder.push_record([
+                impact.node_id.to_string(),
+                impact
+                    .subnet_id
+                    .map(|s| s.to_string())
+                    .unwrap_or_else(|| "N/A".to_string()),
+                score(&impact.score_before),
+                score(&impact.score_after),
+            ]);
+        }
+        let mut table = builder.build();
+        table.with(Style::rounded());
+        println!("{}", table);
+
+        let assigned = response
+            .nodes
+            .iter()
+            .filter(|n| n.subnet_id.is_some())
+            .map(|n| n.node_id)
+            .collect::<Vec<_>>();
+        if !assigned.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Nodes {:?} are still in subnets, replace them before decommissioning them",
+                assigned
+            ));
+        }
+
+        self.ic_admin.propose_run(
+            ic_admin::ProposeCommand::RemoveNodes {
+                nodes: response.nodes.iter().map(|n| n.node_id).collect(),
+            },
+            ProposeOptions {
+                title: "Decommission nodes".to_string().into(),
+                summary: "Remove decommissioned nodes from the network".to_string().into(),
+                motivation: response.motivation.into(),
+            },
+            simulate,
+        )
+    }
 }
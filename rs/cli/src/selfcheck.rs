@@ -0,0 +1,225 @@
+//! Check the invariants of the decentralization scores against a snapshot of
+//! the registry, to catch data-quality issues and regressions in the scoring
+//! without access to the network.
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::io::Read;
+use std::path::Path;
+
+use decentralization::nakamoto::NakamotoScore;
+use ic_base_types::PrincipalId;
+use ic_management_types::{Node, Subnet};
+use serde::Deserialize;
+
+/// A snapshot of the registry: a subnet, a list of subnets or a list of
+/// nodes, as returned by the backend.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum Snapshot {
+    Subnet(Subnet),
+    Subnets(Vec<Subnet>),
+    Nodes(Vec<Node>),
+}
+
+impl Snapshot {
+    pub fn from_json(reader: impl Read) -> anyhow::Result<Self> {
+        serde_json::from_reader(reader)
+            .map_err(|e| anyhow::anyhow!("Not a subnet, a list of subnets or a list of nodes: {}", e))
+    }
+
+    /// The nodes of each subnet. Nodes of a list that are not assigned to a
+    /// subnet are grouped under `None`.
+    fn subnet_nodes(&self) -> BTreeMap<Option<PrincipalId>, Vec<&Node>> {
+        match self {
+            Snapshot::Subnet(subnet) => BTreeMap::from([(Some(subnet.principal), subnet.nodes.iter().collect())]),
+            Snapshot::Subnets(subnets) => subnets
+                .iter()
+                .map(|subnet| (Some(subnet.principal), subnet.nodes.iter().collect()))
+                .collect(),
+            Snapshot::Nodes(nodes) => nodes.iter().fold(BTreeMap::new(), |mut acc, node| {
+                acc.entry(node.subnet_id).or_insert_with(Vec::new).push(node);
+                acc
+            }),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Violation {
+    /// None for the nodes not assigned to a subnet.
+    pub subnet: Option<PrincipalId>,
+    pub message: String,
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.subnet {
+            Some(subnet) => write!(f, "subnet {}: {}", subnet, self.message),
+            None => write!(f, "unassigned nodes: {}", self.message),
+        }
+    }
+}
+
+/// Score every subnet of the snapshot and return the invariants its score
+/// violates, in the order of the subnet ids.
+pub fn check(snapshot: &Snapshot) -> Vec<Violation> {
+    snapshot
+        .subnet_nodes()
+        .into_iter()
+        .flat_map(|(subnet, nodes)| {
+            let nodes = nodes
+                .into_iter()
+                .map(decentralization::network::Node::from)
+                .collect::<Vec<_>>();
+            score_violations(&NakamotoScore::new_from_nodes(&nodes), nodes.len())
+                .into_iter()
+                .map(move |message| Violation { subnet, message })
+        })
+        .collect()
+}
+
+/// Check the snapshot in the file at `path` and print the violations. Fails
+/// if there are any, for use in CI.
+pub fn run(path: &Path) -> anyhow::Result<()> {
+    let snapshot = Snapshot::from_json(std::fs::File::open(path)?)?;
+    let violations = check(&snapshot);
+    for violation in &violations {
+        println!("{}", violation);
+    }
+    if !violations.is_empty() {
+        return Err(anyhow::anyhow!("{} invariant violations", violations.len()));
+    }
+    println!("All invariants hold for {} subnets", snapshot.subnet_nodes().len());
+    Ok(())
+}
+
+/// The invariants `score` of `size` nodes violates:
+/// - every coefficient is at least 1 and at most the number of nodes, unless
+///   there are no nodes
+/// - the top actors of a feature control at most all nodes
+/// - the minimum is the smallest coefficient and the averages are between
+///   the smallest and the largest one
+fn score_violations(score: &NakamotoScore, size: usize) -> Vec<String> {
+    let mut violations = vec![];
+    let coefficients = score.scores_individual();
+    for (feature, coefficient) in &coefficients {
+        if size > 0 && !(1. ..=size as f64).contains(coefficient) {
+            violations.push(format!(
+                "the {} coefficient {} is not between 1 and the {} nodes",
+                feature, coefficient, size
+            ));
+        }
+        let controlled_nodes = score.controlled_nodes(feature).unwrap_or_default();
+        if controlled_nodes > size {
+            violations.push(format!(
+                "the top actors of {} control {} nodes, more than the {} nodes",
+                feature, controlled_nodes, size
+            ));
+        }
+    }
+    if size == 0 || coefficients.is_empty() {
+        return violations;
+    }
+
+    let min = coefficients.values().copied().fold(f64::INFINITY, f64::min);
+    let max = coefficients.values().copied().fold(f64::NEG_INFINITY, f64::max);
+    if score.score_min() != min {
+        violations.push(format!(
+            "the minimum {} is not the smallest coefficient {}",
+            score.score_min(),
+            min
+        ));
+    }
+    if !(min..=max).contains(&score.score_avg_linear()) {
+        violations.push(format!(
+            "the linear average {} is not between the coefficients {} and {}",
+            score.score_avg_linear(),
+            min,
+            max
+        ));
+    }
+    match score.score_avg_log2() {
+        Some(avg_log2) if !(min.log2()..=max.log2()).contains(&avg_log2) => violations.push(format!(
+            "the log2 average {} is not between the log2 of the coefficients {} and {}",
+            avg_log2,
+            min.log2(),
+            max.log2()
+        )),
+        None if min > 0. => violations.push("the log2 average is missing".to_string()),
+        _ => {}
+    }
+    violations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshots() -> Vec<(&'static str, Snapshot)> {
+        [
+            (
+                "subnet-uzr34.json",
+                include_str!("../../decentralization/test_data/subnet-uzr34.json"),
+            ),
+            (
+                "available-nodes.json",
+                include_str!("../../decentralization/test_data/available-nodes.json"),
+            ),
+        ]
+        .into_iter()
+        .map(|(name, json)| (name, Snapshot::from_json(json.as_bytes()).unwrap()))
+        .collect()
+    }
+
+    #[test]
+    fn bundled_snapshots_hold_the_invariants() {
+        for (name, snapshot) in snapshots() {
+            assert!(!snapshot.subnet_nodes().is_empty(), "{}", name);
+            let violations = check(&snapshot);
+            assert!(
+                violations.is_empty(),
+                "{}: {}",
+                name,
+                violations.iter().map(|v| v.to_string()).collect::<Vec<_>>().join("\n")
+            );
+        }
+    }
+
+    #[test]
+    fn violations_are_reported() {
+        let subnet: Subnet =
+            serde_json::from_str(include_str!("../../decentralization/test_data/subnet-uzr34.json")).unwrap();
+        let nodes = subnet
+            .nodes
+            .iter()
+            .map(decentralization::network::Node::from)
+            .collect::<Vec<_>>();
+        let score = NakamotoScore::new_from_nodes(&nodes);
+        assert!(score_violations(&score, nodes.len()).is_empty());
+
+        // The same score taken for a subnet of fewer nodes than its actors
+        // control.
+        let violations = score_violations(&score, 1);
+        assert!(
+            violations
+                .iter()
+                .any(|v| v.contains("is not between 1 and the 1 nodes")),
+            "{:?}",
+            violations
+        );
+        assert!(
+            violations.iter().any(|v| v.contains("more than the 1 nodes")),
+            "{:?}",
+            violations
+        );
+        assert_eq!(
+            Violation {
+                subnet: Some(subnet.principal),
+                message: violations[0].clone(),
+            }
+            .to_string(),
+            format!("subnet {}: {}", subnet.principal, violations[0])
+        );
+    }
+}
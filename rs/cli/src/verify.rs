@@ -0,0 +1,130 @@
+use std::path::Path;
+
+use decentralization::nakamoto::NakamotoScore;
+use ic_management_types::score::ScoreSummary;
+use serde::Deserialize;
+
+/// The score a subnet is expected to converge to once a proposal is
+/// executed: the `score_after` summary of a membership change, as saved with
+/// `--save-expected-score`, or a full score, e.g. of a report.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ExpectedScore {
+    Score(NakamotoScore),
+    Summary(ScoreSummary),
+}
+
+pub fn save_expected_score(path: &Path, score: &ScoreSummary) -> anyhow::Result<()> {
+    std::fs::write(path, serde_json::to_string_pretty(score)? + "\n")
+        .map_err(|e| anyhow::anyhow!("Failed to save the expected score to {}: {}", path.display(), e))
+}
+
+pub fn load_expected_score(path: &Path) -> anyhow::Result<ScoreSummary> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read the expected score from {}: {}", path.display(), e))?;
+    match serde_json::from_str::<ExpectedScore>(&content)
+        .map_err(|e| anyhow::anyhow!("{} is not a saved score: {}", path.display(), e))?
+    {
+        ExpectedScore::Score(score) => Ok((&score).into()),
+        ExpectedScore::Summary(summary) => Ok(summary),
+    }
+}
+
+/// The coefficients of `actual` that differ from `expected` and the
+/// aggregates that differ by more than `epsilon`, empty if the scores match.
+pub fn score_mismatches(expected: &ScoreSummary, actual: &ScoreSummary, epsilon: f64) -> Vec<String> {
+    let differs = |expected: f64, actual: f64| (expected - actual).abs() > epsilon;
+    let mut mismatches = vec![];
+    for (feature, expected_coefficient) in &expected.coefficients {
+        match actual.coefficients.get(feature) {
+            Some(actual_coefficient) if actual_coefficient == expected_coefficient => {}
+            Some(actual_coefficient) => mismatches.push(format!(
+                "{}: expected {}, got {}",
+                feature, expected_coefficient, actual_coefficient
+            )),
+            None => mismatches.push(format!("{}: expected {}, got none", feature, expected_coefficient)),
+        }
+    }
+    if differs(expected.average_linear, actual.average_linear) {
+        mismatches.push(format!(
+            "average: expected {:.2}, got {:.2}",
+            expected.average_linear, actual.average_linear
+        ));
+    }
+    match (expected.average_log2, actual.average_log2) {
+        (Some(expected), Some(actual)) if !differs(expected, actual) => {}
+        (None, None) => {}
+        (expected, actual) => mismatches.push(format!("log2 average: expected {:?}, got {:?}", expected, actual)),
+    }
+    if expected.min != actual.min {
+        mismatches.push(format!("min: expected {}, got {}", expected.min, actual.min));
+    }
+    mismatches
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use decentralization::nakamoto::NodeFeatures;
+    use decentralization::network::Node;
+    use ic_base_types::PrincipalId;
+    use ic_management_types::NodeFeature;
+
+    use super::*;
+
+    fn summary(country: u64) -> ScoreSummary {
+        ScoreSummary {
+            coefficients: BTreeMap::from([(NodeFeature::NodeProvider, 3), (NodeFeature::Country, country)]),
+            min: country.min(3),
+            average_linear: (3 + country) as f64 / 2.0,
+            average_log2: Some(1.5),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn saved_scores_are_compared_within_epsilon() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("score.json");
+        save_expected_score(&path, &summary(2)).unwrap();
+        let expected = load_expected_score(&path).unwrap();
+
+        assert!(score_mismatches(&expected, &summary(2), 0.01).is_empty());
+        let mut close = summary(2);
+        close.average_linear += 0.005;
+        assert!(score_mismatches(&expected, &close, 0.01).is_empty());
+        assert_eq!(
+            score_mismatches(&expected, &summary(1), 0.01),
+            vec![
+                "country: expected 2, got 1".to_string(),
+                "average: expected 2.50, got 2.00".to_string(),
+                "min: expected 2, got 1".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn full_scores_are_accepted_as_expected_scores() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("score.json");
+        let nodes = ["CH", "CH", "DE", "US"]
+            .iter()
+            .enumerate()
+            .map(|(i, country)| {
+                let features = NodeFeatures::from_iter([
+                    (NodeFeature::NodeProvider, format!("provider {}", i)),
+                    (NodeFeature::Country, country.to_string()),
+                ]);
+                Node::new(PrincipalId::new_node_test_id(i as u64), features, false)
+            })
+            .collect::<Vec<_>>();
+        let score = NakamotoScore::new_from_nodes(&nodes);
+        std::fs::write(&path, serde_json::to_string(&score).unwrap()).unwrap();
+        assert_eq!(load_expected_score(&path).unwrap(), ScoreSummary::from(&score));
+
+        std::fs::write(&path, r#"{"subnet_id": "yndj2-3ybaa-aaaaa-aaaap-yai"}"#).unwrap();
+        let err = load_expected_score(&path).unwrap_err();
+        assert!(err.to_string().contains("is not a saved score"), "{}", err);
+    }
+}
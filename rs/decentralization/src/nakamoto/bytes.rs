@@ -0,0 +1,306 @@
+//! A compact binary encoding of [NakamotoScore], for storing many scores,
+//! e.g. the history of the scores of all subnets. The first byte is the
+//! version of the format, the rest is laid out as follows in version 1, with
+//! all numbers little-endian:
+//!
+//! - the linear average, `f64`
+//! - the log2 average: `0u8` if there is none, else `1u8` and the `f64`
+//! - the minimum coefficient, `f64`
+//! - the coefficients: a `u16` count, then the feature and the `f64` of each
+//! - the value counts: a `u16` count of features, then for each the feature
+//!   and a `u32` count of values, then each value and its `u32` count
+//! - the controlled nodes: a `u16` count, then the feature and the `u32` of
+//!   each
+//!
+//! Features and values are strings: a `u16` length followed by the UTF-8
+//! bytes. Features are written by name, so that adding features does not
+//! change the format.
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::str::FromStr;
+
+use ic_management_types::NodeFeature;
+
+use super::NakamotoScore;
+
+/// The version of the format written by [NakamotoScore::to_bytes].
+pub const SCORE_BYTES_VERSION: u8 = 1;
+
+/// Why bytes cannot be decoded into a [NakamotoScore].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ScoreDecodeError {
+    /// The bytes are of a format this version does not know, e.g. written
+    /// by a newer version.
+    UnsupportedVersion(u8),
+    /// The bytes end in the middle of the score.
+    Truncated,
+    /// There are bytes left after the score.
+    TrailingBytes(usize),
+    InvalidUtf8,
+    UnknownFeature(String),
+}
+
+impl Display for ScoreDecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnsupportedVersion(version) => write!(
+                f,
+                "unsupported score format version {}, expected {}",
+                version, SCORE_BYTES_VERSION
+            ),
+            Self::Truncated => write!(f, "truncated score"),
+            Self::TrailingBytes(count) => write!(f, "{} bytes after the score", count),
+            Self::InvalidUtf8 => write!(f, "invalid UTF-8 in the score"),
+            Self::UnknownFeature(feature) => write!(f, "unknown feature '{}' in the score", feature),
+        }
+    }
+}
+
+impl std::error::Error for ScoreDecodeError {}
+
+impl NakamotoScore {
+    /// The score in a compact binary format, starting with [SCORE_BYTES_VERSION].
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer(vec![SCORE_BYTES_VERSION]);
+        writer.f64(self.avg_linear);
+        match self.avg_log2 {
+            Some(avg_log2) => {
+                writer.u8(1);
+                writer.f64(avg_log2);
+            }
+            None => writer.u8(0),
+        }
+        writer.f64(self.min);
+
+        writer.len_u16(self.coefficients.len());
+        for (feature, coefficient) in &self.coefficients {
+            writer.str(&feature.to_string());
+            writer.f64(*coefficient);
+        }
+        writer.len_u16(self.value_counts.len());
+        for (feature, counts) in &self.value_counts {
+            writer.str(&feature.to_string());
+            writer.len_u32(counts.len());
+            for (value, count) in counts {
+                writer.str(value);
+                writer.len_u32(*count);
+            }
+        }
+        writer.len_u16(self.controlled_nodes.len());
+        for (feature, nodes) in &self.controlled_nodes {
+            writer.str(&feature.to_string());
+            writer.len_u32(*nodes);
+        }
+        writer.0
+    }
+
+    /// Decode a score written by [NakamotoScore::to_bytes].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ScoreDecodeError> {
+        let mut reader = Reader(bytes);
+        match reader.u8()? {
+            SCORE_BYTES_VERSION => {}
+            version => return Err(ScoreDecodeError::UnsupportedVersion(version)),
+        }
+        let avg_linear = reader.f64()?;
+        let avg_log2 = match reader.u8()? {
+            0 => None,
+            _ => Some(reader.f64()?),
+        };
+        let min = reader.f64()?;
+
+        let mut coefficients = BTreeMap::new();
+        for _ in 0..reader.u16()? {
+            coefficients.insert(reader.feature()?, reader.f64()?);
+        }
+        let mut value_counts = BTreeMap::new();
+        for _ in 0..reader.u16()? {
+            let feature = reader.feature()?;
+            let counts = (0..reader.u32()?)
+                .map(|_| Ok((reader.str()?, reader.u32()? as usize)))
+                .collect::<Result<Vec<_>, ScoreDecodeError>>()?;
+            value_counts.insert(feature, counts);
+        }
+        let mut controlled_nodes = BTreeMap::new();
+        for _ in 0..reader.u16()? {
+            controlled_nodes.insert(reader.feature()?, reader.u32()? as usize);
+        }
+
+        if !reader.0.is_empty() {
+            return Err(ScoreDecodeError::TrailingBytes(reader.0.len()));
+        }
+        Ok(Self {
+            coefficients,
+            value_counts,
+            controlled_nodes,
+            avg_linear,
+            avg_log2,
+            min,
+        })
+    }
+}
+
+struct Writer(Vec<u8>);
+
+/// Counts are far from the limits of their fields: there are a handful of
+/// features, and a score is of at most all nodes of the network.
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.0.push(value);
+    }
+
+    fn len_u16(&mut self, value: usize) {
+        let value = u16::try_from(value).expect("count does not fit in u16");
+        self.0.extend(value.to_le_bytes());
+    }
+
+    fn len_u32(&mut self, value: usize) {
+        let value = u32::try_from(value).expect("count does not fit in u32");
+        self.0.extend(value.to_le_bytes());
+    }
+
+    fn f64(&mut self, value: f64) {
+        self.0.extend(value.to_le_bytes());
+    }
+
+    /// Strings longer than a `u16` length are cut, on a character boundary.
+    fn str(&mut self, value: &str) {
+        let mut len = value.len().min(u16::MAX as usize);
+        while !value.is_char_boundary(len) {
+            len -= 1;
+        }
+        self.len_u16(len);
+        self.0.extend(&value.as_bytes()[..len]);
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], ScoreDecodeError> {
+        let bytes = self.bytes(N)?;
+        Ok(bytes.try_into().expect("length checked"))
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&[u8], ScoreDecodeError> {
+        if self.0.len() < len {
+            return Err(ScoreDecodeError::Truncated);
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, ScoreDecodeError> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, ScoreDecodeError> {
+        Ok(u16::from_le_bytes(self.take()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, ScoreDecodeError> {
+        Ok(u32::from_le_bytes(self.take()?))
+    }
+
+    fn f64(&mut self) -> Result<f64, ScoreDecodeError> {
+        Ok(f64::from_le_bytes(self.take()?))
+    }
+
+    fn str(&mut self) -> Result<String, ScoreDecodeError> {
+        let len = self.u16()? as usize;
+        String::from_utf8(self.bytes(len)?.to_vec()).map_err(|_| ScoreDecodeError::InvalidUtf8)
+    }
+
+    fn feature(&mut self) -> Result<NodeFeature, ScoreDecodeError> {
+        let name = self.str()?;
+        NodeFeature::from_str(&name).map_err(|_| ScoreDecodeError::UnknownFeature(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ic_base_types::PrincipalId;
+
+    use super::*;
+    use crate::nakamoto::NodeFeatures;
+    use crate::network::Node;
+
+    fn score() -> NakamotoScore {
+        let nodes = ["CH", "CH", "DE", "US", "Zürich"]
+            .iter()
+            .enumerate()
+            .map(|(i, country)| {
+                let features = NodeFeatures::from_iter([
+                    (NodeFeature::NodeProvider, format!("provider {}", i % 3)),
+                    (NodeFeature::Country, country.to_string()),
+                ]);
+                Node::new(PrincipalId::new_node_test_id(i as u64), features, false)
+            })
+            .collect::<Vec<_>>();
+        NakamotoScore::new_from_nodes(&nodes)
+    }
+
+    fn assert_same(decoded: &NakamotoScore, score: &NakamotoScore) {
+        assert_eq!(
+            serde_json::to_value(decoded).unwrap(),
+            serde_json::to_value(score).unwrap()
+        );
+    }
+
+    #[test]
+    fn scores_round_trip_through_bytes() {
+        for score in [score(), NakamotoScore::new_from_nodes(&[]), NakamotoScore::default()] {
+            let bytes = score.to_bytes();
+            assert_eq!(bytes[0], SCORE_BYTES_VERSION);
+            assert_same(&NakamotoScore::from_bytes(&bytes).unwrap(), &score);
+        }
+        let score = score();
+        assert!(score.to_bytes().len() < serde_json::to_vec(&score).unwrap().len() / 2);
+    }
+
+    #[test]
+    fn unknown_versions_are_rejected() {
+        let mut bytes = score().to_bytes();
+        for version in [0, SCORE_BYTES_VERSION + 1, u8::MAX] {
+            bytes[0] = version;
+            let err = NakamotoScore::from_bytes(&bytes).unwrap_err();
+            assert_eq!(err, ScoreDecodeError::UnsupportedVersion(version));
+            assert!(err.to_string().contains("unsupported score format version"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn malformed_bytes_are_rejected() {
+        let bytes = score().to_bytes();
+        assert_eq!(NakamotoScore::from_bytes(&[]), Err(ScoreDecodeError::Truncated));
+        for len in 1..bytes.len() {
+            assert_eq!(
+                NakamotoScore::from_bytes(&bytes[..len]),
+                Err(ScoreDecodeError::Truncated),
+                "{} bytes",
+                len
+            );
+        }
+        assert_eq!(
+            NakamotoScore::from_bytes(&[bytes.as_slice(), &[0, 0]].concat()),
+            Err(ScoreDecodeError::TrailingBytes(2))
+        );
+
+        // The first feature of the coefficients, after the version, the
+        // averages, the minimum, the count of coefficients and its length.
+        let feature_at = 1 + 8 + 1 + 8 + 8 + 2 + 2;
+        let len = u16::from_le_bytes([bytes[feature_at - 2], bytes[feature_at - 1]]) as usize;
+        let feature = std::str::from_utf8(&bytes[feature_at..feature_at + len]).unwrap();
+        assert!(NodeFeature::from_str(feature).is_ok(), "{}", feature);
+        let mut unknown = bytes.clone();
+        unknown[feature_at] = b'X';
+        assert_eq!(
+            NakamotoScore::from_bytes(&unknown),
+            Err(ScoreDecodeError::UnknownFeature(format!("X{}", &feature[1..])))
+        );
+        let mut invalid = bytes;
+        invalid[feature_at] = 0xff;
+        assert_eq!(NakamotoScore::from_bytes(&invalid), Err(ScoreDecodeError::InvalidUtf8));
+    }
+}
@@ -9,7 +9,12 @@ use std::fmt::{Debug, Display, Formatter};
 use std::hash::Hasher;
 use std::iter::{FromIterator, IntoIterator};
 
-use ic_management_types::NodeFeature;
+use ic_management_types::iso::CityNames;
+use ic_management_types::score::ScoreSummary;
+use ic_management_types::{MinNakamotoCoefficients, NodeFeature};
+
+mod bytes;
+pub use bytes::{ScoreDecodeError, SCORE_BYTES_VERSION};
 
 #[derive(Eq, PartialEq, Clone, Serialize, Deserialize, Debug)]
 pub struct NodeFeatures {
@@ -30,12 +35,68 @@ impl NodeFeatures {
         NodeFeatures { feature_map: result }
     }
 
-    #[cfg(test)]
-    fn with_feature_value(&self, feature: &NodeFeature, value: &str) -> Self {
+    /// The features with `value` as the value of `feature`.
+    pub fn with_feature_value(&self, feature: &NodeFeature, value: &str) -> Self {
         let mut feature_map = self.feature_map.clone();
         feature_map.insert(feature.clone(), value.to_string());
         NodeFeatures { feature_map }
     }
+
+    /// The features with the value of each parent feature replaced by the
+    /// parent of the value of its child feature in `parents`, where known.
+    fn with_parents(&self, parents: &BTreeMap<(NodeFeature, String), String>) -> Self {
+        FEATURE_PARENTS.iter().fold(self.clone(), |features, (child, parent)| {
+            match features
+                .get(child)
+                .and_then(|value| parents.get(&(child.clone(), value)))
+            {
+                Some(parent_value) => features.with_feature_value(parent, parent_value),
+                None => features,
+            }
+        })
+    }
+
+    /// The features with the city replaced by its canonical name in
+    /// `city_names`, if it has one.
+    pub fn with_city_names(&self, city_names: &CityNames) -> Self {
+        match self.get(&NodeFeature::City) {
+            Some(city) => self.with_feature_value(&NodeFeature::City, city_names.canonical(&city)),
+            None => self.clone(),
+        }
+    }
+}
+
+/// Pairs of features where each value of the first belongs to a value of the
+/// second, e.g. a data center belongs to its owner.
+const FEATURE_PARENTS: [(NodeFeature, NodeFeature); 1] = [(NodeFeature::DataCenter, NodeFeature::DataCenterOwner)];
+
+/// The parent of each value of a child feature in [FEATURE_PARENTS]: the
+/// parent value most of the `features` with that child value carry, and the
+/// smallest one on ties.
+fn feature_parents<'a>(
+    features: impl IntoIterator<Item = &'a NodeFeatures>,
+) -> BTreeMap<(NodeFeature, String), String> {
+    let mut counts = BTreeMap::<(NodeFeature, String), BTreeMap<String, usize>>::new();
+    for features in features {
+        for (child, parent) in FEATURE_PARENTS.iter() {
+            if let (Some(child_value), Some(parent_value)) = (features.get(child), features.get(parent)) {
+                *counts
+                    .entry((child.clone(), child_value))
+                    .or_default()
+                    .entry(parent_value)
+                    .or_default() += 1;
+            }
+        }
+    }
+    counts
+        .into_iter()
+        .filter_map(|(child, parents)| {
+            parents
+                .into_iter()
+                .max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(&a.0)))
+                .map(|(parent, _)| (child, parent))
+        })
+        .collect()
 }
 
 impl FromIterator<(NodeFeature, &'static str)> for NodeFeatures {
@@ -62,6 +123,10 @@ thread_local! {
     pub static MEMOIZE_HIT_RATES: RefCell<VecDeque<u32>> = RefCell::new(VecDeque::new());
 }
 
+/// The features whose actors are most likely to collude, so their
+/// coefficients matter most for the security of a subnet.
+pub const CRITICAL_FEATURES: [NodeFeature; 2] = [NodeFeature::NodeProvider, NodeFeature::Country];
+
 #[derive(Clone, Debug, Serialize, Deserialize, Default)]
 /// This struct keeps the Nakamoto coefficients for each feature that we track
 /// for the IC nodes https://crosstower.com/resources/education/nakamoto-coefficient/
@@ -92,52 +157,82 @@ pub struct NakamotoScore {
 impl NakamotoScore {
     /// Build a new NakamotoScore object from a slice of [NodeFeatures].
     pub fn new_from_slice_node_features(slice_node_features: &[NodeFeatures]) -> Self {
+        Self::new_from_slice_node_features_restricted(slice_node_features, &NodeFeature::variants())
+    }
+
+    /// Build a new NakamotoScore object from a slice of [NodeFeatures],
+    /// considering only the given `features`.
+    fn new_from_slice_node_features_restricted(slice_node_features: &[NodeFeatures], features: &[NodeFeature]) -> Self {
+        Self::new_from_weighted_node_features_restricted(
+            &slice_node_features.iter().map(|f| (f.clone(), 1.)).collect::<Vec<_>>(),
+            features,
+        )
+    }
+
+    /// Build a new NakamotoScore object from [NodeFeatures] and the weight of
+    /// each node, e.g. its stake or capacity. The coefficients count the
+    /// actors controlling more than 1/3 of the total weight rather than of
+    /// the nodes, the value counts and controlled nodes remain numbers of
+    /// nodes. With all weights 1 this is [Self::new_from_slice_node_features].
+    pub fn new_from_weighted_node_features(weighted_node_features: &[(NodeFeatures, f64)]) -> Self {
+        Self::new_from_weighted_node_features_restricted(weighted_node_features, &NodeFeature::variants())
+    }
+
+    fn new_from_weighted_node_features_restricted(
+        weighted_node_features: &[(NodeFeatures, f64)],
+        features: &[NodeFeature],
+    ) -> Self {
         let mut features_to_nodes_map = BTreeMap::new();
 
-        for feature in NodeFeature::variants() {
-            features_to_nodes_map.insert(feature, Vec::new());
+        for feature in features {
+            features_to_nodes_map.insert(feature.clone(), Vec::new());
         }
 
         // Convert a Vec<BTreeMap<NodeFeature, Value>> into a Vec<BTreeMap<NodeFeature,
-        // Vec<Values>>
-        for node_features in slice_node_features.iter() {
-            for feature in NodeFeature::variants() {
-                let curr = features_to_nodes_map.get_mut(&feature).unwrap();
-                curr.push(node_features.get(&feature));
+        // Vec<(Value, Weight)>>
+        for (node_features, weight) in weighted_node_features.iter() {
+            for (feature, curr) in features_to_nodes_map.iter_mut() {
+                curr.push((node_features.get(feature), *weight));
             }
         }
 
         let nakamoto_calc = features_to_nodes_map.iter().map(|value| {
-            // Turns a Vec<Features> into a Vec<(NodeFeature, Number)>
-            // where "Number" is the count of objects with the feature
-            let counters: Vec<(String, usize)> = value
+            // Turns a Vec<Features> into a Vec<(NodeFeature, Number, Weight)>
+            // where "Number" is the count of objects with the feature and
+            // "Weight" their total weight
+            let counters: Vec<(String, usize, f64)> = value
                 .1
                 .iter()
                 // AHashMap is a very fast HashMap implementation https://github.com/tkaitchuck/aHash
                 // We use it here to count the number of times each value appears in the input vector
                 // Doing this with a fold instead of using https://github.com/coriolinus/counter-rs is faster
-                .fold(AHashMap::new(), |mut acc: AHashMap<String, u32>, s| {
+                .fold(AHashMap::new(), |mut acc: AHashMap<String, (u32, f64)>, (s, weight)| {
                     if let Some(s) = s {
-                        acc.entry(s.to_string()).and_modify(|v| *v += 1).or_insert(1);
+                        let entry = acc.entry(s.to_string()).or_insert((0, 0.));
+                        entry.0 += 1;
+                        entry.1 += weight;
                     }
                     acc
                 })
                 .into_iter()
-                .map(|(feat, cnt)| (feat, cnt as usize))
+                .map(|(feat, (cnt, weight))| (feat, cnt as usize, weight))
                 .collect::<Vec<_>>();
 
-            // We only care about the counts to calculate the Nakamoto Coefficient, so we
-            // discard the feature names
-            let only_counter = counters.iter().map(|(_feat, cnt)| *cnt).collect::<Vec<_>>();
+            // We only care about the counts and weights to calculate the Nakamoto
+            // Coefficient, so we discard the feature names
+            let only_counter = counters
+                .iter()
+                .map(|(_feat, cnt, weight)| (*cnt, *weight))
+                .collect::<Vec<_>>();
             // But for deeper understanding (logging and debugging) we also keep track of
             // all strings and their counts
             let value_counts = counters
                 .into_iter()
-                .map(|(feat, cnt)| (feat, cnt))
+                .map(|(feat, cnt, _weight)| (feat, cnt))
                 .sorted_by_key(|(_feat, cnt)| -(*cnt as isize))
                 .collect::<Vec<_>>();
 
-            (value.0.clone(), Self::nakamoto(&only_counter), value_counts)
+            (value.0.clone(), Self::nakamoto_weighted(&only_counter), value_counts)
         });
 
         let scores = nakamoto_calc
@@ -180,6 +275,7 @@ impl NakamotoScore {
             for byte in node.id.0.as_slice() {
                 memoize_key.write_u8(*byte);
             }
+            memoize_key.write_u64(node.weight.to_bits());
         }
         let memoize_key = memoize_key.finish();
         NAKAMOTOSCORE_CACHE.with(|memoize_cache| {
@@ -207,8 +303,8 @@ impl NakamotoScore {
                                 score.clone()
                             }
                             None => {
-                                let score = Self::new_from_slice_node_features(
-                                    &nodes.iter().map(|n| n.features.clone()).collect::<Vec<_>>(),
+                                let score = Self::new_from_weighted_node_features(
+                                    &nodes.iter().map(|n| (n.features.clone(), n.weight)).collect::<Vec<_>>(),
                                 );
                                 memoize_cache.insert(memoize_key, score.clone());
                                 score
@@ -220,6 +316,46 @@ impl NakamotoScore {
         })
     }
 
+    /// Build a new NakamotoScore object from a slice of [Node]s, considering
+    /// only the given `features`, e.g. only the geographic ones. The averages
+    /// and the minimum are taken over these features only.
+    pub fn new_from_nodes_features(nodes: &[Node], features: &[NodeFeature]) -> Self {
+        Self::new_from_weighted_node_features_restricted(
+            &nodes.iter().map(|n| (n.features.clone(), n.weight)).collect::<Vec<_>>(),
+            features,
+        )
+    }
+
+    /// Build a new NakamotoScore object from a slice of [Node]s, respecting the
+    /// hierarchy of their features: a node counts towards the owner of its
+    /// data center rather than the owner it names itself, so data centers
+    /// with nodes naming different owners are not split across several
+    /// actors.
+    pub fn new_from_nodes_hierarchical(nodes: &[Node]) -> Self {
+        let parents = feature_parents(nodes.iter().map(|n| &n.features));
+        Self::new_from_weighted_node_features(
+            &nodes
+                .iter()
+                .map(|n| (n.features.with_parents(&parents), n.weight))
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    /// Build a new NakamotoScore object from a slice of [Node]s, counting the
+    /// nodes in differently spelled cities towards their canonical city in
+    /// `city_names`. With no city names this is [Self::new_from_nodes].
+    pub fn new_from_nodes_with_city_names(nodes: &[Node], city_names: &CityNames) -> Self {
+        if city_names.is_empty() {
+            return Self::new_from_nodes(nodes);
+        }
+        Self::new_from_weighted_node_features(
+            &nodes
+                .iter()
+                .map(|n| (n.features.with_city_names(city_names), n.weight))
+                .collect::<Vec<_>>(),
+        )
+    }
+
     /// The Nakamoto Coefficient represents the number of actors that would have
     /// to collude together to attack a subnet if they wanted to.
     /// This function takes a vector of numbers, where each number is the count
@@ -229,9 +365,17 @@ impl NakamotoScore {
     ///    how many top actors would be needed to break the consensus
     ///    requirements
     /// 2) the number of nodes that the top actors control
-    fn nakamoto(values: &[usize]) -> (usize, usize) {
+    pub(crate) fn nakamoto(values: &[usize]) -> (usize, usize) {
+        Self::nakamoto_weighted(&values.iter().map(|v| (*v, *v as f64)).collect::<Vec<_>>())
+    }
+
+    /// As [Self::nakamoto], for actors given as the number of nodes they
+    /// control and the total weight of these nodes. The top actors are those
+    /// with the most weight, and must together control more than 1/3 of the
+    /// total weight.
+    fn nakamoto_weighted(values: &[(usize, f64)]) -> (usize, usize) {
         let mut values = values.to_owned();
-        let total_subnet_nodes: usize = values.iter().sum();
+        let total_subnet_weight: f64 = values.iter().map(|(_, weight)| weight).sum();
 
         // The number of non-malicious actors that the consensus requires => 2f + 1
         // so at most 1/3 of the subnet nodes (actors) can be malicious (actually 1/3 -
@@ -241,19 +385,23 @@ impl NakamotoScore {
         // things like certification / CUPs > need to use 2f+1 (even if we only
         // assume that f can be corrupt) because we want to be more > resilient
         // against non-deterministic execution.
-        let max_malicious_nodes = total_subnet_nodes / 3;
+        // For weights counting nodes, exceeding a third of them is the same
+        // as exceeding the third rounded down.
+        let max_malicious_weight = total_subnet_weight / 3.;
 
-        // Reverse sort, go from actor with most to fewest repetitions.
+        // Reverse sort, go from actor with most to least weight.
         // The ultimate nakamoto coefficient is the number of different actors necessary
         // to reach max_malicious_actors
-        values.sort_by(|a, b| b.cmp(a));
+        values.sort_by(|a, b| b.1.total_cmp(&a.1).then(b.0.cmp(&a.0)));
 
         let mut sum_actors: usize = 0;
         let mut sum_nodes: usize = 0;
-        for actor_nodes in values {
+        let mut sum_weight: f64 = 0.;
+        for (actor_nodes, actor_weight) in values {
             sum_actors += 1;
             sum_nodes = sum_nodes.saturating_add(actor_nodes);
-            if sum_nodes > max_malicious_nodes {
+            sum_weight += actor_weight;
+            if sum_weight > max_malicious_weight {
                 // Adding the current actor would break the consensus requirements, so stop
                 // here.
                 break;
@@ -309,7 +457,7 @@ impl NakamotoScore {
     /// - Top Countries control 7 nodes
     /// In that case we would return (5, 7)
     pub fn critical_features_num_nodes(&self) -> Vec<usize> {
-        [NodeFeature::NodeProvider, NodeFeature::Country]
+        CRITICAL_FEATURES
             .iter()
             .map(|feat| self.controlled_nodes.get(feat).cloned().unwrap_or_default())
             .collect()
@@ -318,16 +466,122 @@ impl NakamotoScore {
     /// Number of unique actors for the critical features.
     /// E.g. if there are 5 unique (different) NPs in a subnet ==> return 5
     pub fn critical_features_unique_actors(&self) -> Vec<usize> {
-        [NodeFeature::NodeProvider, NodeFeature::Country]
+        CRITICAL_FEATURES
             .iter()
             .map(|feat| self.feature_value_counts(feat).len())
             .collect()
     }
 
+    /// How far the coefficients fall short of the `targets`, 0 if all targets
+    /// are met and higher the worse the shortfall, for alerting on a single
+    /// threshold. Shortfalls of the critical features weigh double, and a
+    /// feature without a coefficient falls short by its whole target.
+    pub fn deficiency(&self, targets: &MinNakamotoCoefficients) -> f64 {
+        targets
+            .coefficients
+            .iter()
+            .map(|(feature, target)| {
+                let criticality = if CRITICAL_FEATURES.contains(feature) { 2. } else { 1. };
+                let coefficient = self.score_feature(feature).unwrap_or_default();
+                criticality * (target - coefficient).max(0.)
+            })
+            .sum()
+    }
+
+    /// Whether the coefficients, their average, the number of distinct data
+    /// center owners and the nodes per data center all reach the `targets`.
+    pub fn meets(&self, targets: &MinNakamotoCoefficients) -> bool {
+        self.deficiency(targets) == 0.
+            && self.score_avg_linear() >= targets.average
+            && targets.min_distinct_owners.map_or(true, |min| {
+                self.feature_value_counts(&NodeFeature::DataCenterOwner).len() >= min
+            })
+            && targets.max_nodes_per_data_center.map_or(true, |max| {
+                self.feature_value_counts(&NodeFeature::DataCenter)
+                    .iter()
+                    .all(|(_, count)| *count <= max)
+            })
+    }
+
     /// Return the number of nodes that the top actors control
     pub fn controlled_nodes(&self, feature: &NodeFeature) -> Option<usize> {
         self.controlled_nodes.get(feature).copied()
     }
+
+    /// The number of nodes the top actor of the feature could still acquire
+    /// without controlling more than the 1/3 of the nodes that the consensus
+    /// tolerates, i.e. the slack before the coefficient degrades.
+    /// E.g. if the top actor controls 2 of 13 nodes ==> return 4 - 2 = 2
+    pub fn headroom(&self, feature: &NodeFeature) -> Option<usize> {
+        let value_counts = self.value_counts.get(feature)?;
+        let total_nodes: usize = value_counts.iter().map(|(_, count)| count).sum();
+        let max_malicious_nodes = total_nodes / 3;
+        let top_actor_nodes = value_counts.iter().map(|(_, count)| *count).max().unwrap_or_default();
+        Some(max_malicious_nodes.saturating_sub(top_actor_nodes))
+    }
+
+    /// The Shannon entropy in bits of the shares of the nodes the actors of
+    /// the feature have, `-Σ p_i log2 p_i`. Unlike the coefficient it changes
+    /// with every node moved between actors: it is 0 for a single actor and
+    /// log2 of the number of actors if they have the same number of nodes.
+    /// None if no node has a value for the feature.
+    pub fn entropy(&self, feature: &NodeFeature) -> Option<f64> {
+        let value_counts = self.value_counts.get(feature)?;
+        let total_nodes = value_counts.iter().map(|(_, count)| count).sum::<usize>();
+        if total_nodes == 0 {
+            return None;
+        }
+        Some(
+            value_counts
+                .iter()
+                .filter(|(_, count)| *count > 0)
+                .map(|(_, count)| {
+                    let share = *count as f64 / total_nodes as f64;
+                    -share * share.log2()
+                })
+                .sum(),
+        )
+    }
+
+    /// The average entropy over the features with an entropy, see
+    /// [NakamotoScore::entropy].
+    pub fn avg_entropy(&self) -> Option<f64> {
+        let entropies = self
+            .value_counts
+            .keys()
+            .filter_map(|feature| self.entropy(feature))
+            .collect::<Vec<_>>();
+        if entropies.is_empty() {
+            return None;
+        }
+        Some(entropies.iter().sum::<f64>() / entropies.len() as f64)
+    }
+
+    /// The score as a fixed-length vector, e.g. for external ranking. The
+    /// components follow the priority order of the comparison of scores, so
+    /// that comparing the vectors lexicographically gives the same result:
+    /// - min score
+    /// - log2 average, -inf if undefined
+    /// - negated number of nodes controlled by the top actors of each
+    ///   critical feature
+    /// - number of unique actors of each critical feature
+    /// - negated count of coefficients below 3
+    /// - for each feature the negated coefficient, capped at 3 since larger
+    ///   coefficients are not compared
+    /// - linear average
+    pub fn as_ranking_vector(&self) -> Vec<f64> {
+        let mut vector = vec![self.score_min(), self.score_avg_log2().unwrap_or(f64::NEG_INFINITY)];
+        vector.extend(self.critical_features_num_nodes().into_iter().map(|n| -(n as f64)));
+        vector.extend(self.critical_features_unique_actors().into_iter().map(|n| n as f64));
+        vector.push(-(self.coefficients.values().filter(|c| **c < 3.0).count() as f64));
+        vector.extend(
+            NodeFeature::variants()
+                .iter()
+                .map(|feature| -self.coefficients.get(feature).unwrap_or(&1.0).min(3.0)),
+        );
+        vector.push(self.score_avg_linear());
+        vector
+    }
 }
 
 impl Ord for NakamotoScore {
@@ -339,18 +593,36 @@ impl Ord for NakamotoScore {
 impl PartialOrd for NakamotoScore {
     /// By default, the higher value will take the precedence
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.compare_by_criterion(other).0
+    }
+}
+
+/// The criterion that decided the comparison of two [NakamotoScore]s.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum ScoreCriterion {
+    Min,
+    AvgLog2,
+    CriticalFeaturesNumNodes,
+    CriticalFeaturesUniqueActors,
+    LowCoefficients,
+    Coefficient(NodeFeature),
+    AvgLinear,
+}
+
+impl NakamotoScore {
+    fn compare_by_criterion(&self, other: &Self) -> (Option<Ordering>, ScoreCriterion) {
         // Prefer higher score across all features
         let mut cmp = self.score_min().partial_cmp(&other.score_min());
 
         if cmp != Some(Ordering::Equal) {
-            return cmp;
+            return (cmp, ScoreCriterion::Min);
         }
 
         // Then try to increase the log2 avg
         cmp = self.score_avg_log2().partial_cmp(&other.score_avg_log2());
 
         if cmp != Some(Ordering::Equal) {
-            return cmp;
+            return (cmp, ScoreCriterion::AvgLog2);
         }
 
         // Try to pick the candidate that *reduces* the number of nodes
@@ -360,7 +632,7 @@ impl PartialOrd for NakamotoScore {
             .partial_cmp(&self.critical_features_num_nodes());
 
         if cmp != Some(Ordering::Equal) {
-            return cmp;
+            return (cmp, ScoreCriterion::CriticalFeaturesNumNodes);
         }
 
         // Compare the number of unique actors for the critical features
@@ -370,17 +642,15 @@ impl PartialOrd for NakamotoScore {
             .partial_cmp(&other.critical_features_unique_actors());
 
         if cmp != Some(Ordering::Equal) {
-            return cmp;
+            return (cmp, ScoreCriterion::CriticalFeaturesUniqueActors);
         }
 
         // Compare the count of below-average coefficients
         // and prefer candidates that decrease the number of low-value coefficients
-        let c1 = self.coefficients.values().filter(|c| **c < 3.0).count();
-        let c2 = other.coefficients.values().filter(|c| **c < 3.0).count();
-        cmp = c2.partial_cmp(&c1);
+        cmp = other.low_coefficients().partial_cmp(&self.low_coefficients());
 
         if cmp != Some(Ordering::Equal) {
-            return cmp;
+            return (cmp, ScoreCriterion::LowCoefficients);
         }
 
         // If the worst feature is the same for both candidates
@@ -394,13 +664,63 @@ impl PartialOrd for NakamotoScore {
                 cmp = c2.partial_cmp(c1);
 
                 if cmp != Some(Ordering::Equal) {
-                    return cmp;
+                    return (cmp, ScoreCriterion::Coefficient(feature));
                 }
             }
         }
 
         // And finally try to increase the linear average
-        self.score_avg_linear().partial_cmp(&other.score_avg_linear())
+        (
+            self.score_avg_linear().partial_cmp(&other.score_avg_linear()),
+            ScoreCriterion::AvgLinear,
+        )
+    }
+
+    /// The number of coefficients below 3.
+    fn low_coefficients(&self) -> usize {
+        self.coefficients.values().filter(|c| **c < 3.0).count()
+    }
+
+    /// Compare the scores as [PartialOrd] does, along with an explanation of
+    /// the criterion that decided the comparison, e.g.
+    /// `min score: 2 vs 1`.
+    pub fn compare_explain(&self, other: &Self) -> (Option<Ordering>, String) {
+        let (cmp, criterion) = self.compare_by_criterion(other);
+        let explanation = match criterion {
+            ScoreCriterion::Min => format!("min score: {} vs {}", self.score_min(), other.score_min()),
+            ScoreCriterion::AvgLog2 => format!(
+                "log2 average: {:.4} vs {:.4}",
+                self.score_avg_log2().unwrap_or(f64::NAN),
+                other.score_avg_log2().unwrap_or(f64::NAN)
+            ),
+            ScoreCriterion::CriticalFeaturesNumNodes => format!(
+                "nodes controlled by the top node provider and country: {:?} vs {:?}",
+                self.critical_features_num_nodes(),
+                other.critical_features_num_nodes()
+            ),
+            ScoreCriterion::CriticalFeaturesUniqueActors => format!(
+                "distinct node providers and countries: {:?} vs {:?}",
+                self.critical_features_unique_actors(),
+                other.critical_features_unique_actors()
+            ),
+            ScoreCriterion::LowCoefficients => format!(
+                "coefficients below 3: {} vs {}",
+                self.low_coefficients(),
+                other.low_coefficients()
+            ),
+            ScoreCriterion::Coefficient(feature) => format!(
+                "{} coefficient: {} vs {}",
+                feature,
+                self.coefficients.get(&feature).unwrap_or(&1.0),
+                other.coefficients.get(&feature).unwrap_or(&1.0)
+            ),
+            ScoreCriterion::AvgLinear => format!(
+                "linear average: {:.4} vs {:.4}",
+                self.score_avg_linear(),
+                other.score_avg_linear()
+            ),
+        };
+        (cmp, explanation)
     }
 }
 
@@ -412,31 +732,92 @@ impl PartialEq for NakamotoScore {
 
 impl Eq for NakamotoScore {}
 
-impl Display for NakamotoScore {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+/// How a [`NakamotoScore`] is rendered by [`NakamotoScore::display_with`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DisplayConfig {
+    /// Decimal places of the averages, and of coefficients that are not
+    /// whole numbers.
+    pub precision: usize,
+}
+
+impl Default for DisplayConfig {
+    fn default() -> Self {
+        Self { precision: 2 }
+    }
+}
+
+impl NakamotoScore {
+    /// Render the score like [`Display`] does, with the precision of
+    /// `config`. Coefficients are whole numbers and rendered without
+    /// decimals.
+    pub fn display_with(&self, config: &DisplayConfig) -> String {
+        let precision = config.precision;
         let avg_log2_str = match self.avg_log2 {
-            Some(v) => format!("{:0.2}", v),
+            Some(v) => format!("{:.*}", precision, v),
             None => "undefined".to_string(),
         };
-        write!(
-            f,
-            "NakamotoScore: min {:0.2} avg log2 {} #crit nodes {:?} # crit uniq {:?} #crit coeff {} avg linear {:0.2}",
-            self.min,
+        let min_str = if self.min.is_finite() && self.min.fract() == 0.0 {
+            format!("{}", self.min as i64)
+        } else {
+            format!("{:.*}", precision, self.min)
+        };
+        format!(
+            "NakamotoScore: min {} avg log2 {} #crit nodes {:?} # crit uniq {:?} #crit coeff {} avg linear {:.*}",
+            min_str,
             avg_log2_str,
             self.critical_features_num_nodes(),
             self.critical_features_unique_actors(),
             self.coefficients.values().filter(|c| **c < 3.0).count(),
+            precision,
             self.avg_linear,
         )
     }
 }
 
+impl Display for NakamotoScore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.display_with(&DisplayConfig::default()))
+    }
+}
+
+impl From<&NakamotoScore> for ScoreSummary {
+    fn from(score: &NakamotoScore) -> Self {
+        // The coefficients count actors, so they are integers.
+        let coefficients = score
+            .coefficients
+            .iter()
+            .map(|(feature, coefficient)| (feature.clone(), *coefficient as u64))
+            .collect::<BTreeMap<_, _>>();
+        Self {
+            min: coefficients.values().min().copied().unwrap_or_default(),
+            coefficients,
+            average_linear: score.score_avg_linear(),
+            average_log2: score.score_avg_log2(),
+            critical_features: CRITICAL_FEATURES.to_vec(),
+            controlled_nodes: score.controlled_nodes.clone(),
+            headroom: NodeFeature::variants()
+                .into_iter()
+                .filter_map(|f| score.headroom(&f).map(|h| (f, h)))
+                .collect(),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::str::FromStr;
+    use std::time::Duration;
 
-    use crate::network::{DecentralizedSubnet, SubnetChangeRequest};
+    use crate::network::{
+        optimize_subnets, overrepresented_providers, BusinessRuleViolation, DecentralizedSubnet, DecommissionPolicy,
+        FleetProviderStats, NodeBudget, RemovalReason, SubnetChange, SubnetChangeRequest, SubnetOptimizeRequest,
+    };
     use ic_base_types::PrincipalId;
+    use ic_management_types::annotations::NodeAnnotations;
+    use ic_management_types::preview::ChangePreview;
+    use ic_management_types::requests::FeatureMatch;
+    use ic_management_types::timestamp::Timestamp;
+    use ic_management_types::{HealthStatus, NetworkError};
     use itertools::Itertools;
     use regex::Regex;
 
@@ -470,6 +851,105 @@ mod tests {
         assert_eq!((2, 8), NakamotoScore::nakamoto(&[1, 1, 2, 3, 5, 1, 2])); // two top actors control 8/15 nodes
     }
 
+    #[test]
+    fn computes_weighted_nakamoto_scores() {
+        // Weights counting the nodes give the unweighted coefficients.
+        assert_eq!(
+            NakamotoScore::nakamoto(&[1, 2, 1, 2, 1]),
+            NakamotoScore::nakamoto_weighted(&[(1, 1.), (2, 2.), (1, 1.), (2, 2.), (1, 1.)])
+        );
+        // One actor controls half of the weight with one of the five nodes.
+        assert_eq!(
+            (1, 1),
+            NakamotoScore::nakamoto_weighted(&[(1, 6.), (1, 1.), (1, 1.), (1, 2.), (1, 2.)])
+        );
+        assert_eq!(
+            (1, 2),
+            NakamotoScore::nakamoto_weighted(&[(3, 0.1), (2, 0.5), (1, 0.4)])
+        );
+        // Among actors of the same weight, the one with the most nodes is on top.
+        assert_eq!((1, 3), NakamotoScore::nakamoto_weighted(&[(1, 1.), (3, 1.)]));
+        assert_eq!((0, 0), NakamotoScore::nakamoto_weighted(&[]));
+    }
+
+    #[test]
+    fn entropy_of_uniform_distributions_is_log2_of_the_actors() {
+        for actors in [1, 2, 5, 13] {
+            let features = (0..actors * 3)
+                .map(|i| NodeFeatures::new_test_feature_set(&format!("actor {}", i % actors)))
+                .collect::<Vec<_>>();
+            let score = NakamotoScore::new_from_slice_node_features(&features);
+            let expected = (actors as f64).log2();
+            for feature in NodeFeature::variants() {
+                let entropy = score.entropy(&feature).unwrap();
+                assert!(
+                    (entropy - expected).abs() < 1e-9,
+                    "{}: {} != {}",
+                    feature,
+                    entropy,
+                    expected
+                );
+            }
+            assert!((score.avg_entropy().unwrap() - expected).abs() < 1e-9);
+        }
+
+        // Moving a node from one actor to another changes the entropy, but
+        // not the coefficient.
+        let features = ["a", "a", "a", "b", "b", "b", "c", "c", "d", "d"]
+            .iter()
+            .map(|actor| NodeFeatures::new_test_feature_set(actor))
+            .collect::<Vec<_>>();
+        let skewed = ["a", "a", "a", "b", "b", "b", "c", "c", "c", "d"]
+            .iter()
+            .map(|actor| NodeFeatures::new_test_feature_set(actor))
+            .collect::<Vec<_>>();
+        let (score, skewed) = (
+            NakamotoScore::new_from_slice_node_features(&features),
+            NakamotoScore::new_from_slice_node_features(&skewed),
+        );
+        assert_eq!(score.score_min(), skewed.score_min());
+        assert!(skewed.avg_entropy().unwrap() < score.avg_entropy().unwrap());
+
+        let empty = NakamotoScore::new_from_slice_node_features(&[]);
+        assert_eq!(empty.entropy(&NodeFeature::Country), None);
+        assert_eq!(empty.avg_entropy(), None);
+    }
+
+    #[test]
+    fn heavy_nodes_lower_the_coefficients() {
+        let nodes = new_test_nodes("weighted", 4, 0);
+        let score = NakamotoScore::new_from_nodes(&nodes);
+        assert_eq!(score.score_feature(&NodeFeature::NodeProvider), Some(2.));
+        let unit_weights = nodes.iter().map(|n| n.clone().with_weight(1.)).collect::<Vec<_>>();
+        assert_eq!(
+            NakamotoScore::new_from_nodes_features(&unit_weights, &NodeFeature::variants()),
+            score
+        );
+
+        // The first node alone controls half of the capacity.
+        let mut weighted = nodes.clone();
+        weighted[0] = weighted[0].clone().with_weight(3.);
+        let weighted_score = NakamotoScore::new_from_nodes(&weighted);
+        for feature in NodeFeature::variants() {
+            assert_eq!(weighted_score.score_feature(&feature), Some(1.), "{}", feature);
+        }
+        assert_eq!(weighted_score.controlled_nodes(&NodeFeature::NodeProvider), Some(1));
+        // The value counts are still of nodes.
+        assert!(weighted_score
+            .feature_value_counts(&NodeFeature::NodeProvider)
+            .iter()
+            .all(|(_, count)| *count == 1));
+
+        // Light nodes matter less.
+        let light = nodes
+            .iter()
+            .skip(1)
+            .map(|n| n.clone().with_weight(0.1))
+            .collect::<Vec<_>>();
+        let light_score = NakamotoScore::new_from_nodes(&[&nodes[..1], &light].concat());
+        assert_eq!(light_score.score_feature(&NodeFeature::NodeProvider), Some(1.));
+    }
+
     #[test]
     fn score_from_features() {
         let features = vec![NodeFeatures::new_test_feature_set("foo")];
@@ -500,6 +980,166 @@ mod tests {
         assert_eq!(score, score_expected);
     }
 
+    #[test]
+    fn score_from_a_subset_of_features() {
+        let nodes = ["CH", "DE", "JP", "US"]
+            .iter()
+            .enumerate()
+            .map(|(i, country)| {
+                let features = NodeFeatures::new_test_feature_set("foo")
+                    .with_feature_value(&NodeFeature::City, &format!("city {}", i))
+                    .with_feature_value(&NodeFeature::Country, country);
+                Node::new(PrincipalId::new_node_test_id(i as u64), features, false)
+            })
+            .collect::<Vec<_>>();
+        let geography = [NodeFeature::City, NodeFeature::Country, NodeFeature::Continent];
+        let score = NakamotoScore::new_from_nodes_features(&nodes, &geography);
+
+        assert_eq!(
+            score.scores_individual(),
+            BTreeMap::from([
+                (NodeFeature::City, 2.),
+                (NodeFeature::Country, 2.),
+                (NodeFeature::Continent, 1.),
+            ])
+        );
+        assert_eq!(score.score_feature(&NodeFeature::NodeProvider), None);
+        assert_eq!(score.score_avg_linear(), 5. / 3.);
+        assert_eq!(score.score_min(), 1.);
+        assert!(score.score_avg_linear() > NakamotoScore::new_from_nodes(&nodes).score_avg_linear());
+    }
+
+    #[test]
+    fn display_with_precision() {
+        let score = NakamotoScore {
+            coefficients: BTreeMap::from([
+                (NodeFeature::City, 2.),
+                (NodeFeature::Country, 1.),
+                (NodeFeature::Continent, 2.),
+                (NodeFeature::DataCenterOwner, 2.),
+                (NodeFeature::NodeProvider, 2.),
+                (NodeFeature::DataCenter, 2.),
+            ]),
+            value_counts: BTreeMap::new(),
+            controlled_nodes: BTreeMap::new(),
+            avg_linear: 11. / 6.,
+            avg_log2: Some(5. / 6.),
+            min: 1.,
+        };
+
+        let precise = score.display_with(&DisplayConfig { precision: 4 });
+        assert!(
+            precise.starts_with("NakamotoScore: min 1 avg log2 0.8333 "),
+            "{}",
+            precise
+        );
+        assert!(precise.ends_with(" avg linear 1.8333"), "{}", precise);
+
+        let default = score.to_string();
+        assert_eq!(default, score.display_with(&DisplayConfig::default()));
+        assert!(
+            default.starts_with("NakamotoScore: min 1 avg log2 0.83 "),
+            "{}",
+            default
+        );
+        assert!(default.ends_with(" avg linear 1.83"), "{}", default);
+    }
+
+    #[test]
+    fn headroom_of_top_actor() {
+        // 13 nodes tolerate 4 malicious ones, the top provider controls 2.
+        let features = (0..13)
+            .map(|i| {
+                NodeFeatures::from_iter([
+                    (NodeFeature::NodeProvider, format!("np {}", i / 2)),
+                    (NodeFeature::Country, "CH".to_string()),
+                ])
+            })
+            .collect::<Vec<_>>();
+        let score = NakamotoScore::new_from_slice_node_features(&features);
+
+        assert_eq!(score.feature_value_counts_max(&NodeFeature::NodeProvider).unwrap().1, 2);
+        assert_eq!(score.headroom(&NodeFeature::NodeProvider), Some(2));
+        // A single country already controls all nodes.
+        assert_eq!(score.headroom(&NodeFeature::Country), Some(0));
+        // Features without values have no actors at all.
+        assert_eq!(score.headroom(&NodeFeature::City), Some(0));
+    }
+
+    #[test]
+    fn score_summaries_are_converted_from_scores() {
+        // 13 nodes tolerate 4 malicious ones: 3 providers with 2 nodes each,
+        // or the country with 5 nodes, exceed them.
+        let features = (0..13)
+            .map(|i| {
+                NodeFeatures::from_iter([
+                    (NodeFeature::NodeProvider, format!("np {}", i / 2)),
+                    (NodeFeature::Country, ["CH", "DE", "US"][i % 3].to_string()),
+                ])
+            })
+            .collect::<Vec<_>>();
+        let score = NakamotoScore::new_from_slice_node_features_restricted(
+            &features,
+            &[NodeFeature::NodeProvider, NodeFeature::Country],
+        );
+
+        assert_eq!(
+            ScoreSummary::from(&score),
+            ScoreSummary {
+                coefficients: BTreeMap::from([(NodeFeature::NodeProvider, 3), (NodeFeature::Country, 1)]),
+                min: 1,
+                average_linear: 2.,
+                average_log2: Some(3f64.log2() / 2.),
+                critical_features: vec![NodeFeature::NodeProvider, NodeFeature::Country],
+                controlled_nodes: BTreeMap::from([(NodeFeature::NodeProvider, 6), (NodeFeature::Country, 5)]),
+                headroom: BTreeMap::from([(NodeFeature::NodeProvider, 2), (NodeFeature::Country, 0)]),
+            }
+        );
+        // An empty subnet has no log2 average, and the coefficients all 0.
+        let empty = ScoreSummary::from(&NakamotoScore::new_from_nodes(&[]));
+        assert_eq!(empty.min, 0);
+        assert_eq!(empty.average_log2, None);
+        assert!(empty.coefficients.values().all(|c| *c == 0));
+    }
+
+    #[test]
+    fn ranking_vector_is_consistent_with_cmp() {
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(0);
+        let random_score = |rng: &mut StdRng| {
+            let num_nodes = rng.gen_range(0..20);
+            let features = (0..num_nodes)
+                .map(|_| {
+                    NodeFeature::variants()
+                        .into_iter()
+                        .map(|feature| {
+                            let num_values = rng.gen_range(1..8);
+                            (feature, format!("value {}", rng.gen_range(0..num_values)))
+                        })
+                        .collect::<NodeFeatures>()
+                })
+                .collect::<Vec<_>>();
+            NakamotoScore::new_from_slice_node_features(&features)
+        };
+
+        for _ in 0..2000 {
+            let (a, b) = (random_score(&mut rng), random_score(&mut rng));
+            let (va, vb) = (a.as_ranking_vector(), b.as_ranking_vector());
+            assert_eq!(va.len(), vb.len());
+            assert_eq!(
+                va.partial_cmp(&vb),
+                Some(a.cmp(&b)),
+                "{} vs {}: {:?} vs {:?}",
+                a,
+                b,
+                va,
+                vb
+            );
+        }
+    }
+
     /// Generate a new Vec<Node> of len num_nodes, out of which
     /// num_dfinity_nodes are DFINITY-owned
     fn new_test_nodes(feat_prefix: &str, num_nodes: usize, num_dfinity_nodes: usize) -> Vec<Node> {
@@ -546,6 +1186,11 @@ mod tests {
             min_nakamoto_coefficients: None,
             comment: None,
             run_log: Vec::new(),
+            seed: 0,
+            skip_dfinity_node_requirement: false,
+            budget: None,
+            decommission_policy: Default::default(),
+            fleet_providers: None,
         }
     }
 
@@ -570,6 +1215,11 @@ mod tests {
             min_nakamoto_coefficients: None,
             comment: None,
             run_log: Vec::new(),
+            seed: 0,
+            skip_dfinity_node_requirement: false,
+            budget: None,
+            decommission_policy: Default::default(),
+            fleet_providers: None,
         }
     }
 
@@ -596,6 +1246,130 @@ mod tests {
         new_test_subnet(0, 53, 4).check_business_rules().unwrap();
     }
 
+    #[test]
+    fn subnets_with_too_few_distinct_owners_fail() {
+        let owners = [
+            "owner a", "owner a", "owner a", "owner a", "owner b", "owner b", "owner b", "owner b",
+        ];
+        let subnet = new_test_subnet_with_overrides(0, 0, 8, 1, (&NodeFeature::DataCenterOwner, &owners));
+        assert_eq!(subnet.distinct_owners(), 2);
+        let (penalties, _) = subnet.check_business_rules().unwrap();
+        assert_eq!(penalties, 0);
+
+        let subnet = subnet.with_min_nakamoto_coefficients(&Some(MinNakamotoCoefficients {
+            min_distinct_owners: Some(4),
+            ..Default::default()
+        }));
+        let (penalties, checks) = subnet.check_business_rules().unwrap();
+        assert!(penalties > 0);
+        assert_eq!(
+            checks,
+            vec!["Subnet has 2 distinct data center owners, expected at least 4".to_string()]
+        );
+    }
+
+    #[test]
+    fn subnets_with_too_many_nodes_in_a_data_center_fail() {
+        let data_centers = ["dc a", "dc a", "dc a"];
+        let subnet = new_test_subnet_with_overrides(0, 0, 7, 1, (&NodeFeature::DataCenter, &data_centers));
+        let (penalties, _) = subnet.check_business_rules().unwrap();
+        assert_eq!(penalties, 0);
+
+        let max_nodes_per_data_center = |max| {
+            Some(MinNakamotoCoefficients {
+                max_nodes_per_data_center: Some(max),
+                ..Default::default()
+            })
+        };
+        let (penalties, checks) = subnet
+            .clone()
+            .with_min_nakamoto_coefficients(&max_nodes_per_data_center(2))
+            .check_business_rules()
+            .unwrap();
+        assert_eq!(penalties, 1000);
+        assert_eq!(
+            checks,
+            vec!["Data center 'dc a' hosts 3 nodes, expected at most 2".to_string()]
+        );
+
+        let subnet = subnet.with_min_nakamoto_coefficients(&max_nodes_per_data_center(3));
+        assert_eq!(subnet.check_business_rules().unwrap(), (0, vec![]));
+    }
+
+    #[test]
+    fn removal_fragility_of_sole_dfinity_node() {
+        let subnet = new_test_subnet(0, 7, 1);
+        assert_eq!(
+            subnet.removal_fragility().unwrap(),
+            vec![(
+                PrincipalId::new_node_test_id(0),
+                BusinessRuleViolation {
+                    message: "Subnet should have 1 DFINITY-owned nodes, got 0".to_string()
+                }
+            )]
+        );
+
+        // With two DFINITY-owned nodes the rule is already violated, so no
+        // single removal introduces a new violation.
+        assert!(new_test_subnet(0, 7, 2).removal_fragility().unwrap().is_empty());
+    }
+
+    #[test]
+    fn change_previews_warn_about_broken_business_rules() {
+        let subnet = new_test_subnet(0, 7, 1);
+        let change = |new_nodes: &[Node]| SubnetChange {
+            id: subnet.id,
+            old_nodes: subnet.nodes.clone(),
+            new_nodes: new_nodes.to_vec(),
+            min_nakamoto_coefficients: None,
+            comment: None,
+            run_log: vec![],
+            removal_reasons: BTreeMap::new(),
+            skip_dfinity_node_requirement: false,
+        };
+
+        let unchanged = ChangePreview::from(&change(&subnet.nodes));
+        assert!(unchanged.added.is_empty() && unchanged.removed.is_empty());
+        assert!(unchanged.warnings.is_empty());
+
+        // The first node is the only DFINITY-owned one.
+        let preview = ChangePreview::from(&change(&subnet.nodes[1..]));
+        assert_eq!(preview.subnet_id, Some(subnet.id));
+        assert_eq!(
+            preview.removed.iter().map(|n| n.principal).collect::<Vec<_>>(),
+            vec![PrincipalId::new_node_test_id(0)]
+        );
+        assert_eq!(
+            preview.warnings,
+            vec!["Subnet should have 1 DFINITY-owned nodes, got 0".to_string()]
+        );
+        assert_ne!(preview.content_hash, unchanged.content_hash);
+    }
+
+    #[test]
+    fn change_previews_of_community_subnets_need_no_dfinity_node() {
+        let subnet = new_test_subnet_with_overrides(0, 0, 7, 0, (&NodeFeature::Country, &["CH"]));
+        let nodes_available = new_test_nodes_with_overrides("spare", 7, 1, 0, (&NodeFeature::Country, &["JP"]));
+        let change = SubnetChangeRequest::new(subnet, nodes_available, vec![], vec![], None)
+            .with_dfinity_node_requirement(false)
+            .resize(1, 0)
+            .unwrap();
+        assert!(change.skip_dfinity_node_requirement);
+
+        let preview = ChangePreview::from(&change);
+        assert_eq!(preview.added.len(), 1);
+        assert!(preview.warnings.is_empty(), "{:?}", preview.warnings);
+
+        let required = SubnetChange {
+            skip_dfinity_node_requirement: false,
+            ..change
+        };
+        assert_eq!(
+            ChangePreview::from(&required).warnings,
+            vec!["Subnet should have 1 DFINITY-owned nodes, got 0".to_string()]
+        );
+    }
+
     #[test]
     fn test_business_rules_fail() {
         // If there are no DFINITY-owned node in a small subnet ==> fail with an
@@ -609,6 +1383,21 @@ mod tests {
         );
     }
 
+    #[test]
+    fn community_subnet_without_dfinity_node_requirement() {
+        let subnet = new_test_subnet(0, 13, 0);
+        assert_eq!(
+            subnet.check_business_rules().unwrap(),
+            (
+                1000,
+                vec!["Subnet should have 1 DFINITY-owned nodes, got 0".to_string()]
+            )
+        );
+
+        let subnet = subnet.with_dfinity_node_requirement(false);
+        assert_eq!(subnet.check_business_rules().unwrap(), (0, vec![]));
+    }
+
     #[test]
     fn extend_feature_set_group() {
         let subnet_initial = new_test_subnet(0, 12, 1);
@@ -816,6 +1605,11 @@ mod tests {
             min_nakamoto_coefficients: None,
             comment: None,
             run_log: Vec::new(),
+            seed: 0,
+            skip_dfinity_node_requirement: false,
+            budget: None,
+            decommission_policy: Default::default(),
+            fleet_providers: None,
         };
 
         let available_nodes = serde_json::from_str::<Vec<ic_management_types::Node>>(include_str!(
@@ -943,4 +1737,774 @@ mod tests {
             (5000, vec!["European subnet has 5 non-European node(s)".to_string()])
         );
     }
+
+    #[test]
+    fn subnet_resize_excluding_country() {
+        let subnet_initial = new_test_subnet(0, 7, 1);
+        let nodes_available =
+            new_test_nodes_with_overrides("spare", 7, 4, 0, (&NodeFeature::Country, &["US", "RO", "US", "JP"]));
+
+        let subnet_change =
+            SubnetChangeRequest::new(subnet_initial.clone(), nodes_available.clone(), vec![], vec![], None)
+                .with_exclude_countries(vec!["us".to_string()])
+                .resize(2, 0)
+                .unwrap();
+        let added_countries = subnet_change
+            .added()
+            .iter()
+            .map(|n| n.get_feature(&NodeFeature::Country))
+            .sorted()
+            .collect::<Vec<_>>();
+        assert_eq!(added_countries, vec!["JP", "RO"]);
+
+        // Only two nodes remain outside of the US
+        assert!(
+            SubnetChangeRequest::new(subnet_initial.clone(), nodes_available.clone(), vec![], vec![], None)
+                .with_exclude_countries(vec!["US".to_string()])
+                .resize(3, 0)
+                .is_err()
+        );
+        // Not an ISO 3166-1 alpha-2 code
+        assert!(
+            SubnetChangeRequest::new(subnet_initial, nodes_available, vec![], vec![], None)
+                .with_exclude_countries(vec!["USA".to_string()])
+                .resize(1, 0)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn subnet_resize_excluding_nodes_and_feature_values() {
+        let subnet_initial = new_test_subnet(0, 7, 1);
+        let nodes_available =
+            new_test_nodes_with_overrides("spare", 7, 4, 0, (&NodeFeature::Country, &["US", "RO", "US", "JP"]));
+
+        let subnet_change = SubnetChangeRequest::new(subnet_initial, nodes_available.clone(), vec![], vec![], None)
+            .with_exclude_nodes(vec![nodes_available[1].id.to_string()])
+            .with_exclude_features(vec![FeatureMatch::new(NodeFeature::Country, " us")])
+            .resize(1, 0)
+            .unwrap();
+        assert_eq!(subnet_change.added(), vec![nodes_available[3].clone()]);
+    }
+
+    #[test]
+    fn subnet_resize_excluding_annotated_nodes() {
+        let subnet_initial = new_test_subnet(0, 7, 1);
+        let annotations = |key: &str| NodeAnnotations::new(BTreeMap::from([(key.to_string(), String::new())])).unwrap();
+        let mut nodes_available = new_test_nodes_with_overrides(
+            "spare",
+            7,
+            3,
+            0,
+            (&NodeFeature::NodeProvider, &["spare 0", "spare 1", "spare 2"]),
+        );
+        nodes_available[0] = nodes_available[0].clone().with_annotations(annotations("rma"));
+        nodes_available[1] = nodes_available[1].clone().with_annotations(annotations("note"));
+
+        let subnet_change = SubnetChangeRequest::new(subnet_initial, nodes_available.clone(), vec![], vec![], None)
+            .with_exclude_annotated(vec!["rma".to_string(), "reserved".to_string()])
+            .resize(2, 0)
+            .unwrap();
+        assert_eq!(
+            subnet_change
+                .added()
+                .into_iter()
+                .map(|n| n.id)
+                .sorted()
+                .collect::<Vec<_>>(),
+            vec![nodes_available[1].id, nodes_available[2].id]
+                .into_iter()
+                .sorted()
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn explicit_replacements_are_validated() {
+        let subnet = new_test_subnet(0, 4, 1);
+        let spare = new_test_nodes_with_overrides("spare", 4, 1, 0, (&NodeFeature::NodeProvider, &["spare 0"]));
+        let ids = |subnet: &DecentralizedSubnet| subnet.nodes.iter().map(|n| n.id).collect::<Vec<_>>();
+
+        let replaced = subnet.replace_nodes(&[subnet.nodes[1].id], &spare).unwrap();
+        assert_eq!(
+            ids(&replaced),
+            vec![subnet.nodes[0].id, subnet.nodes[2].id, subnet.nodes[3].id, spare[0].id]
+        );
+        assert_eq!(replaced.removed_nodes, vec![subnet.nodes[1].clone()]);
+
+        // Without its only DFINITY-owned node the subnet is invalid.
+        let invalid = subnet.replace_nodes(&[subnet.nodes[0].id], &spare);
+        assert!(matches!(invalid, Err(NetworkError::IllegalRequest(e)) if e.contains("DFINITY-owned")));
+        assert!(matches!(
+            subnet.replace_nodes(&[spare[0].id], &[]),
+            Err(NetworkError::NodeNotFound(_))
+        ));
+        assert!(subnet.replace_nodes(&[], &subnet.nodes[1..2]).is_err());
+    }
+
+    #[test]
+    fn nodes_decommissioned_soon_are_deprioritized() {
+        let subnet_initial = new_test_subnet(0, 4, 1);
+        // Both nodes are equally decentralizing, the first is decommissioned soon.
+        let mut nodes_available =
+            new_test_nodes_with_overrides("spare", 4, 2, 0, (&NodeFeature::NodeProvider, &["spare 0", "spare 1"]));
+        nodes_available[0].decommission_at = Timestamp::now().checked_add(Duration::from_secs(24 * 60 * 60));
+        let stable_node = nodes_available[1].id;
+        let extend = |policy: DecommissionPolicy, nodes: &[Node], seed: u64| {
+            SubnetChangeRequest::new(subnet_initial.clone(), nodes.to_vec(), vec![], vec![], None)
+                .with_decommission_policy(policy)
+                .with_seed(seed)
+                .resize(1, 0)
+        };
+
+        for seed in 0..10 {
+            let change = extend(
+                DecommissionPolicy::deprioritize_within(Duration::from_secs(7 * 24 * 60 * 60)),
+                &nodes_available,
+                seed,
+            )
+            .unwrap();
+            assert_eq!(
+                change.added().iter().map(|n| n.id).collect::<Vec<_>>(),
+                vec![stable_node]
+            );
+        }
+        // Deprioritized nodes are still added if there is nothing else,
+        // avoided ones are not.
+        let soon = &nodes_available[..1];
+        let week = Duration::from_secs(7 * 24 * 60 * 60);
+        assert!(extend(DecommissionPolicy::deprioritize_within(week), soon, 0).is_ok());
+        assert!(extend(DecommissionPolicy::avoid_within(week), soon, 0).is_err());
+        assert!(extend(DecommissionPolicy::avoid_within(Duration::from_secs(60 * 60)), soon, 0).is_ok());
+    }
+
+    #[test]
+    fn fleet_aware_extension_avoids_concentrated_providers() {
+        let subnet_initial = new_test_subnet(0, 4, 1);
+        // The first three nodes are equally decentralizing, the last one
+        // shares its provider with a node of the subnet.
+        let nodes_available = new_test_nodes_with_overrides(
+            "spare",
+            4,
+            4,
+            0,
+            (&NodeFeature::NodeProvider, &["spare 0", "spare 1", "spare 2", "feat 1"]),
+        );
+        let extend = |fleet_providers: Option<FleetProviderStats>| {
+            let request =
+                SubnetChangeRequest::new(subnet_initial.clone(), nodes_available.clone(), vec![], vec![], None);
+            let request = match fleet_providers {
+                Some(fleet_providers) => request.with_fleet_providers(fleet_providers),
+                None => request,
+            };
+            request.resize(1, 0).unwrap().added()
+        };
+
+        // The provider of the node the naive optimizer picks has the most
+        // nodes in the network, the node of another has the fewest.
+        let naive = extend(None);
+        let concentrated = naive[0].get_feature(&NodeFeature::NodeProvider);
+        let others = nodes_available[..3]
+            .iter()
+            .filter(|n| n.id != naive[0].id)
+            .collect::<Vec<_>>();
+        let fleet_providers = FleetProviderStats {
+            nodes: BTreeMap::from([
+                (concentrated, 20),
+                (others[0].get_feature(&NodeFeature::NodeProvider), 5),
+                (others[1].get_feature(&NodeFeature::NodeProvider), 3),
+                ("feat 1".to_string(), 0),
+            ]),
+        };
+        assert_eq!(extend(Some(fleet_providers)), vec![others[1].clone()]);
+
+        // The providers of the spare nodes have no nodes in the network yet,
+        // so the choice does not change.
+        assert_eq!(
+            FleetProviderStats::from_subnets(&[subnet_initial.clone()]).provider_nodes(&nodes_available[3]),
+            1
+        );
+        assert_eq!(
+            extend(Some(FleetProviderStats::from_subnets(&[subnet_initial.clone()]))),
+            naive
+        );
+    }
+
+    #[test]
+    fn fleet_aware_extension_outweighs_secondary_features() {
+        // All nodes of the subnet are in the same country, which bounds the
+        // minimum coefficient whatever node is added.
+        let subnet_initial = new_test_subnet_with_overrides(0, 0, 4, 1, (&NodeFeature::Country, &["CH"; 4]));
+        // The second node shares its city with a node of the subnet.
+        let nodes_available =
+            new_test_nodes_with_overrides("spare", 4, 2, 0, (&NodeFeature::City, &["spare 0", "feat 0"]));
+        let extend = |fleet_providers: Option<FleetProviderStats>| {
+            let request =
+                SubnetChangeRequest::new(subnet_initial.clone(), nodes_available.clone(), vec![], vec![], None);
+            let request = match fleet_providers {
+                Some(fleet_providers) => request.with_fleet_providers(fleet_providers),
+                None => request,
+            };
+            request.resize(1, 0).unwrap().added()
+        };
+
+        assert_eq!(extend(None), vec![nodes_available[0].clone()]);
+        let fleet_providers = FleetProviderStats {
+            nodes: BTreeMap::from([("spare 0".to_string(), 20)]),
+        };
+        assert_eq!(extend(Some(fleet_providers)), vec![nodes_available[1].clone()]);
+    }
+
+    #[test]
+    fn changes_concentrating_the_fleet_are_rejected() {
+        let subnet_initial = new_test_subnet(0, 4, 1);
+        let nodes_available =
+            new_test_nodes_with_overrides("spare", 4, 1, 0, (&NodeFeature::NodeProvider, &["spare 0"]));
+        let request = SubnetChangeRequest::new(subnet_initial, nodes_available, vec![], vec![], None);
+        assert!(request.clone().resize(1, 0).is_ok());
+
+        // Each provider has a third of the nodes of the network, so it takes
+        // two of them to have more. One more node gives the first one alone
+        // more than a third.
+        let fleet_providers = FleetProviderStats {
+            nodes: BTreeMap::from([
+                ("spare 0".to_string(), 3),
+                ("other 1".to_string(), 3),
+                ("other 2".to_string(), 3),
+            ]),
+        };
+        assert_eq!(fleet_providers.nakamoto_coefficient(), 2);
+        let rejected = request.with_fleet_providers(fleet_providers).resize(1, 0);
+        assert!(matches!(rejected, Err(NetworkError::ResizeFailed(e)) if e.contains("from 2 to 1")));
+    }
+
+    #[test]
+    fn subnet_extension_stays_within_budget() {
+        let subnet_initial = new_test_subnet(0, 4, 1);
+        // The cheapest node shares its provider with a node of the subnet.
+        let nodes_available = new_test_nodes_with_overrides(
+            "spare",
+            4,
+            4,
+            0,
+            (&NodeFeature::NodeProvider, &["feat 0", "cheap", "pricey 1", "pricey 2"]),
+        );
+        let costs = BTreeMap::from([
+            ("feat 0".to_string(), 0.0),
+            ("cheap".to_string(), 1.0),
+            ("pricey 1".to_string(), 5.0),
+            ("pricey 2".to_string(), 5.0),
+        ]);
+        let extend = |budget: f64| {
+            SubnetChangeRequest::new(subnet_initial.clone(), nodes_available.clone(), vec![], vec![], None)
+                .with_budget(NodeBudget::new(costs.clone(), budget))
+                .resize(2, 0)
+        };
+        let added_providers = |change: SubnetChange| {
+            change
+                .added()
+                .iter()
+                .map(|n| n.get_feature(&NodeFeature::NodeProvider))
+                .sorted()
+                .collect::<Vec<_>>()
+        };
+
+        // Both pricey nodes together exceed the budget, so the cheapest of
+        // the diverse nodes is chosen along with one of them.
+        let providers = added_providers(extend(6.0).unwrap());
+        assert_eq!(providers[0], "cheap");
+        assert!(providers[1].starts_with("pricey"), "{:?}", providers);
+        // Only the cheapest nodes fit, although one of them is less diverse.
+        assert_eq!(added_providers(extend(1.0).unwrap()), vec!["cheap", "feat 0"]);
+        assert!(extend(0.5).is_err());
+    }
+
+    #[test]
+    fn subnet_change_cost_of_two_node_swap() {
+        let subnet_initial =
+            new_test_subnet_with_overrides(0, 0, 4, 0, (&NodeFeature::Country, &["CH", "DE", "CH", "US"]));
+        // Nodes in CH and US are replaced by other nodes in US and CH
+        let nodes_available = new_test_nodes_with_overrides("spare", 4, 2, 0, (&NodeFeature::Country, &["US", "CH"]));
+        let removed = subnet_initial.nodes[2..].to_vec();
+
+        let change = SubnetChangeRequest::new(subnet_initial, nodes_available.clone(), vec![], vec![], None)
+            .evaluate()
+            .unwrap()
+            .without_nodes(removed)
+            .with_nodes(nodes_available);
+
+        let cost = change.cost();
+        assert_eq!(
+            cost,
+            crate::network::ChangeCost {
+                nodes_added: 2,
+                nodes_removed: 2,
+                providers_affected: 4,
+                countries_affected: 2,
+            }
+        );
+        assert_eq!(cost.total(), 10);
+    }
+
+    #[test]
+    fn pending_swap_is_applied_before_scoring() {
+        let subnet = new_test_subnet_with_overrides(0, 0, 4, 1, (&NodeFeature::Country, &["CH", "CH", "DE", "US"]));
+        let spare = new_test_nodes_with_overrides("spare", 4, 1, 0, (&NodeFeature::Country, &["JP"]));
+        // Swap the second node in CH for the one in JP.
+        let pending_swap = SubnetChange {
+            id: subnet.id,
+            old_nodes: subnet.nodes.clone(),
+            new_nodes: [&subnet.nodes[..1], &subnet.nodes[2..], &spare].concat(),
+            min_nakamoto_coefficients: None,
+            comment: None,
+            run_log: vec![],
+            removal_reasons: BTreeMap::new(),
+            skip_dfinity_node_requirement: false,
+        };
+        let other_subnet_change = SubnetChange {
+            id: PrincipalId::new_subnet_test_id(1),
+            old_nodes: vec![],
+            new_nodes: new_test_nodes_with_overrides("other", 5, 1, 0, (&NodeFeature::Country, &["CH"])),
+            min_nakamoto_coefficients: None,
+            comment: None,
+            run_log: vec![],
+            removal_reasons: BTreeMap::new(),
+            skip_dfinity_node_requirement: false,
+        };
+
+        let projected = subnet.with_pending_applied(&[other_subnet_change, pending_swap.clone()]);
+
+        let mut node_ids = projected.nodes.iter().map(|n| n.id).collect::<Vec<_>>();
+        node_ids.sort();
+        let mut expected_ids = pending_swap.new_nodes.iter().map(|n| n.id).collect::<Vec<_>>();
+        expected_ids.sort();
+        assert_eq!(node_ids, expected_ids);
+        assert_eq!(projected.removed_nodes, vec![subnet.nodes[1].clone()]);
+        assert_eq!(projected.nakamoto_score(), pending_swap.after().nakamoto_score());
+        assert!(projected.nakamoto_score() > subnet.nakamoto_score());
+        // Without pending changes the subnet stays as it is.
+        assert_eq!(subnet.with_pending_applied(&[]).nodes, subnet.nodes);
+    }
+
+    #[test]
+    fn optimize_subnets_shares_spare_nodes() {
+        // Both subnets have two nodes in the same country and would improve by
+        // swapping one of them for the single spare node.
+        let subnet_a = new_test_subnet_with_overrides(0, 0, 7, 1, (&NodeFeature::Country, &["CH", "CH"]));
+        let subnet_b = new_test_subnet_with_overrides(1, 7, 7, 1, (&NodeFeature::Country, &["US", "US"]));
+        let nodes_available = new_test_nodes_with_overrides("spare", 14, 1, 0, (&NodeFeature::Country, &["JP"]));
+        let requests = [subnet_a, subnet_b]
+            .into_iter()
+            .map(|subnet| SubnetOptimizeRequest {
+                request: SubnetChangeRequest::new(subnet, nodes_available.clone(), vec![], vec![], None),
+                optimize_count: 1,
+                replacements_unhealthy: vec![],
+            })
+            .collect::<Vec<_>>();
+
+        let changes = optimize_subnets(requests).unwrap();
+
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].id, PrincipalId::new_subnet_test_id(0));
+        assert_eq!(changes[0].added(), nodes_available);
+        assert_eq!(
+            changes[0].removed().iter().map(|n| n.id).collect::<Vec<_>>(),
+            vec![PrincipalId::new_node_test_id(1)]
+        );
+        assert_eq!(changes[1].id, PrincipalId::new_subnet_test_id(1));
+        assert!(changes[1].added().is_empty());
+        assert!(changes[1].removed().is_empty());
+    }
+
+    #[test]
+    fn frozen_subnet_is_not_optimized() {
+        let subnet_initial = new_test_subnet_with_overrides(0, 0, 7, 1, (&NodeFeature::Country, &["CH", "CH"]));
+        let nodes_available = new_test_nodes_with_overrides("spare", 7, 1, 0, (&NodeFeature::Country, &["JP"]));
+        let request = SubnetChangeRequest::new(subnet_initial.clone(), nodes_available, vec![], vec![], None)
+            .with_frozen_subnets(vec![subnet_initial.id]);
+
+        let err = request.clone().optimize(1, &vec![]).unwrap_err();
+        assert_eq!(err.to_string(), "subnet is frozen");
+        assert!(request.clone().resize(1, 0).is_err());
+        assert!(request
+            .clone()
+            .optimize(0, &vec![subnet_initial.nodes[0].clone()])
+            .is_err());
+
+        // The subnet is still reported, just never changed.
+        assert!(request.clone().evaluate().unwrap().added().is_empty());
+        let changes = optimize_subnets(vec![SubnetOptimizeRequest {
+            request,
+            optimize_count: 1,
+            replacements_unhealthy: vec![],
+        }])
+        .unwrap();
+        assert!(changes[0].added().is_empty());
+        assert!(changes[0].removed().is_empty());
+        assert_eq!(changes[0].comment, Some("Subnet is frozen".to_string()));
+    }
+
+    #[test]
+    fn replacing_nodes_keeps_the_subnet_size() {
+        let subnet_initial = new_test_subnet_with_overrides(0, 0, 7, 1, (&NodeFeature::Country, &["CH", "CH", "CH"]));
+        let nodes_available =
+            new_test_nodes_with_overrides("spare", 7, 3, 0, (&NodeFeature::Country, &["JP", "BR", "AU"]));
+        let request = SubnetChangeRequest::new(subnet_initial.clone(), nodes_available, vec![], vec![], None);
+
+        let change = request.clone().optimize(2, &vec![]).unwrap();
+        assert_eq!(change.removed().len(), 2);
+        assert_eq!(change.after().nodes.len(), subnet_initial.nodes.len());
+
+        let change = request.resize(3, 1).unwrap();
+        assert_eq!(change.after().nodes.len(), subnet_initial.nodes.len() + 2);
+    }
+
+    #[test]
+    fn changes_keep_the_minimum_of_current_nodes() {
+        let subnet_initial = new_test_subnet_with_overrides(0, 0, 7, 1, (&NodeFeature::Country, &["CH", "CH", "CH"]));
+        let nodes_available =
+            new_test_nodes_with_overrides("spare", 7, 3, 0, (&NodeFeature::Country, &["JP", "BR", "AU"]));
+        let unhealthy = subnet_initial.nodes[6].clone();
+        let request = |min_retained: Option<usize>| {
+            SubnetChangeRequest::new(subnet_initial.clone(), nodes_available.clone(), vec![], vec![], None)
+                .with_min_retained(min_retained)
+        };
+
+        let change = request(Some(5)).optimize(2, &vec![]).unwrap();
+        assert_eq!(change.removed().len(), 2);
+        let err = request(Some(6)).optimize(2, &vec![]).unwrap_err();
+        assert!(
+            matches!(&err, NetworkError::ResizeFailed(e) if e.contains("keeps 5 of the 7 current nodes")),
+            "{:?}",
+            err
+        );
+
+        // The replaced unhealthy nodes are not kept either.
+        assert!(request(Some(5)).optimize(1, &vec![unhealthy.clone()]).is_ok());
+        assert!(request(Some(6)).optimize(1, &vec![unhealthy]).is_err());
+
+        assert!(request(Some(6)).resize(2, 1).is_ok());
+        assert!(request(Some(7)).resize(2, 1).is_err());
+        assert!(request(Some(7)).resize(2, 0).is_ok());
+
+        // Optimizing several subnets fails rather than leaving the subnet
+        // unchanged, since the pool of available nodes is not the reason.
+        let err = optimize_subnets(vec![SubnetOptimizeRequest {
+            request: request(Some(6)),
+            optimize_count: 2,
+            replacements_unhealthy: vec![],
+        }])
+        .unwrap_err();
+        assert!(matches!(err, NetworkError::ResizeFailed(_)), "{:?}", err);
+    }
+
+    #[test]
+    fn optimize_subnets_leaves_subnets_unchanged_once_the_pool_is_exhausted() {
+        let subnet_initial = new_test_subnet_with_overrides(0, 0, 7, 1, (&NodeFeature::Country, &["CH", "CH"]));
+        let request = SubnetChangeRequest::new(subnet_initial.clone(), vec![], vec![], vec![], None);
+
+        let err = request.clone().optimize(1, &vec![]).unwrap_err();
+        assert!(matches!(err, NetworkError::PoolExhausted(_)), "{:?}", err);
+
+        let changes = optimize_subnets(vec![SubnetOptimizeRequest {
+            request,
+            optimize_count: 1,
+            replacements_unhealthy: vec![],
+        }])
+        .unwrap();
+        assert!(changes[0].added().is_empty());
+        assert_eq!(
+            changes[0].comment,
+            Some("No suitable nodes left in the shared pool of available nodes".to_string())
+        );
+    }
+
+    #[test]
+    fn mixed_change_labels_each_removed_node_with_its_reason() {
+        let subnet_initial = new_test_subnet_with_overrides(0, 0, 7, 1, (&NodeFeature::Country, &["CH", "CH"]));
+        let nodes_available = new_test_nodes_with_overrides("spare", 7, 2, 0, (&NodeFeature::Country, &["JP", "BR"]));
+        let unhealthy = subnet_initial.nodes[5].clone();
+        let request = SubnetChangeRequest::new(subnet_initial.clone(), nodes_available, vec![], vec![], None);
+
+        let change = request.optimize(1, &vec![unhealthy.clone()]).unwrap();
+
+        // The second node in CH is replaced to improve the decentralization.
+        let optimized = subnet_initial.nodes[1].clone();
+        assert_eq!(change.removed().len(), 2);
+        assert_eq!(
+            change.removal_reasons,
+            BTreeMap::from([
+                (unhealthy.id, RemovalReason::Unhealthy),
+                (optimized.id, RemovalReason::Optimization)
+            ])
+        );
+        assert_eq!(change.removed_for(RemovalReason::Unhealthy), vec![unhealthy.clone()]);
+        assert_eq!(change.removed_for(RemovalReason::Optimization), vec![optimized]);
+
+        let change = change.with_removal_reasons(&[unhealthy.clone()], RemovalReason::Requested);
+        assert_eq!(change.removed_for(RemovalReason::Requested), vec![unhealthy]);
+        assert!(change.removed_for(RemovalReason::Unhealthy).is_empty());
+    }
+
+    #[test]
+    fn same_seed_produces_same_change() {
+        let subnet_initial = new_test_subnet(0, 7, 1);
+        // All spare nodes improve the subnet equally, so the choice between
+        // them is a tie-break.
+        let nodes_available = new_test_nodes_with_overrides(
+            "spare",
+            7,
+            10,
+            0,
+            (
+                &NodeFeature::Country,
+                &["AT", "BE", "CA", "DK", "EE", "FI", "GR", "HU", "IE", "JP"],
+            ),
+        );
+        let extend = |seed| {
+            SubnetChangeRequest::new(subnet_initial.clone(), nodes_available.clone(), vec![], vec![], None)
+                .with_seed(seed)
+                .resize(2, 0)
+                .unwrap()
+                .added()
+                .iter()
+                .map(|n| n.id)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(extend(7), extend(7));
+        assert_eq!(extend(0), extend(0));
+        let distinct_choices = (0..10).map(extend).unique().count();
+        assert!(distinct_choices > 1, "The seed does not affect the tie-break");
+    }
+
+    #[test]
+    fn hierarchical_scores_count_data_centers_towards_their_owner() {
+        // Nodes 0 to 2 are in the same data center, but node 2 names another
+        // owner than the other two.
+        let nodes = (0..7)
+            .map(|i| {
+                let features = NodeFeatures::new_test_feature_set(&format!("feat {}", i));
+                let features = match i {
+                    0 | 1 => features
+                        .with_feature_value(&NodeFeature::DataCenter, "dc 1")
+                        .with_feature_value(&NodeFeature::DataCenterOwner, "owner A"),
+                    2 => features
+                        .with_feature_value(&NodeFeature::DataCenter, "dc 1")
+                        .with_feature_value(&NodeFeature::DataCenterOwner, "owner B"),
+                    _ => features,
+                };
+                Node::new_test_node(i, features, false, true)
+            })
+            .collect::<Vec<_>>();
+
+        let flat = NakamotoScore::new_from_nodes(&nodes);
+        let hierarchical = NakamotoScore::new_from_nodes_hierarchical(&nodes);
+
+        assert_eq!(flat.score_feature(&NodeFeature::DataCenterOwner), Some(2.));
+        assert_eq!(hierarchical.score_feature(&NodeFeature::DataCenterOwner), Some(1.));
+        assert_eq!(
+            hierarchical.feature_value_counts_max(&NodeFeature::DataCenterOwner),
+            Some(("owner A".to_string(), 3))
+        );
+        assert_eq!(
+            flat.score_feature(&NodeFeature::DataCenter),
+            hierarchical.score_feature(&NodeFeature::DataCenter)
+        );
+    }
+
+    #[test]
+    fn city_spellings_are_merged_into_one_actor() {
+        let nodes = ["Zürich", "Zurich", "Geneva", "Basel"]
+            .iter()
+            .enumerate()
+            .map(|(i, city)| {
+                let features = NodeFeatures::new_test_feature_set(&format!("feat {}", i))
+                    .with_feature_value(&NodeFeature::City, city);
+                Node::new_test_node(i as u64, features, false, true)
+            })
+            .collect::<Vec<_>>();
+        let city_names = CityNames::default().with_spellings("Zürich", &["Zurich", "ZH"]);
+
+        let merged = NakamotoScore::new_from_nodes_with_city_names(&nodes, &city_names);
+        assert_eq!(
+            merged.feature_value_counts_max(&NodeFeature::City),
+            Some(("Zürich".to_string(), 2))
+        );
+        assert_eq!(merged.score_feature(&NodeFeature::City), Some(1.));
+
+        let unmerged = NakamotoScore::new_from_nodes_with_city_names(&nodes, &CityNames::default());
+        assert_eq!(unmerged.score_feature(&NodeFeature::City), Some(2.));
+        assert_eq!(unmerged.feature_value_counts(&NodeFeature::City).len(), 4);
+    }
+
+    #[test]
+    fn deficiency_sums_the_weighted_shortfalls() {
+        let score = NakamotoScore::new_from_slice_node_features(&[
+            NodeFeatures::new_test_feature_set("foo"),
+            NodeFeatures::new_test_feature_set("bar"),
+            NodeFeatures::new_test_feature_set("baz"),
+            NodeFeatures::new_test_feature_set("qux").with_feature_value(&NodeFeature::Country, "bar"),
+        ]);
+        let targets = |country: f64, city: f64| MinNakamotoCoefficients {
+            coefficients: BTreeMap::from([(NodeFeature::Country, country), (NodeFeature::City, city)]),
+            average: 1.,
+            min_distinct_owners: None,
+            max_nodes_per_data_center: None,
+        };
+
+        assert_eq!(score.score_feature(&NodeFeature::Country), Some(1.));
+        assert_eq!(score.score_feature(&NodeFeature::City), Some(2.));
+        assert_eq!(score.deficiency(&targets(1., 2.)), 0.);
+        assert_eq!(score.deficiency(&targets(1., 3.)), 1.);
+        assert_eq!(score.deficiency(&targets(2., 1.)), 2.);
+    }
+
+    #[test]
+    fn better_of_prefers_the_change_with_the_higher_min_score() {
+        let subnet = new_test_subnet_with_overrides(0, 0, 7, 1, (&NodeFeature::Country, &["CH", "CH"]));
+        let spares = new_test_nodes_with_overrides("spare", 7, 2, 0, (&NodeFeature::Country, &["JP", "CH"]));
+        let change = |removed: &Node, added: &Node| {
+            SubnetChange {
+                id: subnet.id,
+                old_nodes: subnet.nodes.clone(),
+                new_nodes: subnet.nodes.clone(),
+                min_nakamoto_coefficients: None,
+                comment: None,
+                run_log: vec![],
+                removal_reasons: BTreeMap::new(),
+                skip_dfinity_node_requirement: false,
+            }
+            .without_nodes(vec![removed.clone()])
+            .with_nodes(vec![added.clone()])
+        };
+        // Replacing the second node in CH spreads the subnet over 7 countries,
+        // adding a third node in CH lets CH control a third of the subnet.
+        let spread = change(&subnet.nodes[1], &spares[0]);
+        let concentrated = change(&subnet.nodes[2], &spares[1]);
+
+        let (better, reason) = SubnetChange::better_of(&concentrated, &spread);
+        assert_eq!(better.added(), vec![spares[0].clone()]);
+        assert_eq!(reason, "The second change results in a better min score: 3 vs 1");
+        let (better, reason) = SubnetChange::better_of(&spread, &concentrated);
+        assert_eq!(better.added(), vec![spares[0].clone()]);
+        assert_eq!(reason, "The first change results in a better min score: 3 vs 1");
+    }
+
+    #[test]
+    fn nodes_without_a_healthy_status_are_unhealthy() {
+        let subnet = new_test_subnet(0, 4, 0);
+        let healths = BTreeMap::from([
+            (subnet.nodes[0].id, HealthStatus::Healthy),
+            (subnet.nodes[1].id, HealthStatus::Degraded),
+            (subnet.nodes[2].id, HealthStatus::Unknown),
+        ]);
+        assert_eq!(
+            subnet.unhealthy_nodes(&healths),
+            vec![
+                subnet.nodes[1].clone(),
+                subnet.nodes[2].clone(),
+                subnet.nodes[3].clone()
+            ]
+        );
+    }
+
+    #[test]
+    fn search_space_size_counts_the_memberships_to_choose_from() {
+        let subnet = new_test_subnet(0, 4, 0);
+        let available = new_test_nodes("spare", 6, 0);
+        let request = SubnetChangeRequest::new(subnet, available.clone(), vec![], vec![], None);
+        // 6 choose 2 ways to add, then 6 choose 2 ways to remove.
+        assert_eq!(request.search_space_size(2, 2).unwrap(), 15 * 15);
+        assert_eq!(request.search_space_size(0, 0).unwrap(), 1);
+        assert_eq!(request.search_space_size(7, 0).unwrap(), 0);
+
+        // The included node is always added, which leaves 5 choose 1 ways to
+        // add and 6 choose 1 ways to remove.
+        let request = request.with_include_nodes(vec![available[0].id]);
+        assert_eq!(request.search_space_size(1, 1).unwrap(), 5 * 6);
+    }
+
+    #[test]
+    fn extension_scores_show_where_the_diversity_plateaus() {
+        let country = NodeFeature::Country;
+        let subnet = new_test_subnet_with_overrides(0, 0, 4, 1, (&country, &["CH", "CH", "US", "US"]));
+        let available_nodes = new_test_nodes_with_overrides("spare", 10, 4, 0, (&country, &["DE", "BE", "CH", "US"]));
+
+        let (extended, scores) = subnet.subnet_with_more_nodes_scored(4, &available_nodes).unwrap();
+
+        let countries = scores
+            .iter()
+            .map(|s| s.score_feature(&country).unwrap())
+            .collect::<Vec<_>>();
+        // The new countries are added first and raise the country coefficient
+        // to 2, which the remaining nodes in CH and US bring back down to 1.
+        assert_eq!(countries, vec![1.0, 2.0, 1.0, 1.0]);
+        assert_eq!(scores.last(), Some(&extended.nakamoto_score()));
+    }
+
+    #[test]
+    fn min_changes_for_a_country_target() {
+        let country = NodeFeature::Country;
+        let subnet = new_test_subnet_with_overrides(0, 0, 4, 1, (&country, &["CH", "CH", "CH", "US"]));
+        let available_nodes = new_test_nodes_with_overrides("spare", 10, 4, 0, (&country, &["DE", "BE", "FR", "IT"]));
+        let request = SubnetChangeRequest::new(subnet, available_nodes, vec![], vec![], None);
+        let target = |coefficient| MinNakamotoCoefficients {
+            coefficients: BTreeMap::from([(country.clone(), coefficient)]),
+            average: 0.,
+            min_distinct_owners: None,
+            max_nodes_per_data_center: None,
+        };
+
+        // Replacing a single node in CH leaves two of the four nodes in CH,
+        // more than a third of the subnet.
+        let change = request.min_changes_for(target(2.)).unwrap();
+        assert_eq!(change.removed().len(), 2);
+        assert_eq!(change.after().nakamoto_score().score_feature(&country), Some(2.));
+
+        let change = request.min_changes_for(target(1.)).unwrap();
+        assert!(change.removed().is_empty());
+
+        // A subnet of four nodes has a country coefficient of at most 2.
+        let err = request.min_changes_for(target(3.)).unwrap_err();
+        assert!(
+            matches!(&err, NetworkError::ResizeFailed(e) if e.contains("does not reach the target")),
+            "{:?}",
+            err
+        );
+
+        // With a single spare node, replacing two or more nodes fails, which
+        // does not end the search early but leaves the target unreachable.
+        let subnet = new_test_subnet_with_overrides(0, 0, 4, 1, (&country, &["CH", "CH", "CH", "US"]));
+        let available_nodes = new_test_nodes_with_overrides("spare", 10, 1, 0, (&country, &["DE"]));
+        let request = SubnetChangeRequest::new(subnet, available_nodes, vec![], vec![], None);
+        let err = request.min_changes_for(target(2.)).unwrap_err();
+        assert!(
+            matches!(&err, NetworkError::ResizeFailed(e) if e.contains("does not reach the target")),
+            "{:?}",
+            err
+        );
+    }
+
+    #[test]
+    fn providers_dominant_across_subnets_come_first() {
+        let provider = NodeFeature::NodeProvider;
+        let subnets = [
+            new_test_subnet_with_overrides(1, 0, 4, 0, (&provider, &["big", "big", "a", "b"])),
+            new_test_subnet_with_overrides(2, 4, 4, 0, (&provider, &["big", "c", "d", "e"])),
+            new_test_subnet_with_overrides(3, 8, 4, 0, (&provider, &["big", "a", "f", "g"])),
+        ];
+
+        let providers = overrepresented_providers(&subnets);
+
+        let ranking = providers
+            .iter()
+            .take(2)
+            .map(|p| (p.provider.as_str(), p.nodes, p.subnets))
+            .collect::<Vec<_>>();
+        assert_eq!(ranking, vec![("big", 4, 3), ("a", 2, 2)]);
+        assert_eq!(providers.len(), 8);
+        assert_eq!(providers.iter().map(|p| p.nodes).sum::<usize>(), 12);
+        assert!(overrepresented_providers(&[]).is_empty());
+    }
 }
@@ -1,18 +1,27 @@
 use crate::nakamoto::{self, NakamotoScore};
-use crate::SubnetChangeResponse;
 use actix_web::http::StatusCode;
 use actix_web::{HttpResponse, ResponseError};
 use anyhow::anyhow;
 use async_trait::async_trait;
 use ic_base_types::PrincipalId;
-use ic_management_types::{MinNakamotoCoefficients, NetworkError, NodeFeature};
+use ic_management_types::annotations::NodeAnnotations;
+use ic_management_types::preview::ChangePreview;
+pub use ic_management_types::requests::ChangeCost;
+use ic_management_types::requests::{FeatureMatch, NodeSummary, SubnetCreateRequest};
+use ic_management_types::timestamp::Timestamp;
+use ic_management_types::validation::Validate;
+use ic_management_types::{
+    iso, HealthStatus, ManagementError, MinNakamotoCoefficients, NetworkError, NodeFeature, SubnetKind,
+};
 use itertools::Itertools;
-use log::{debug, info};
+use log::{debug, info, warn};
 use rand::{seq::SliceRandom, SeedableRng};
 use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
+use std::collections::BTreeMap;
 use std::fmt::{Debug, Display, Formatter};
 use std::hash::Hash;
+use std::time::Duration;
 
 #[derive(Clone, Serialize, Deserialize, Default)]
 pub struct DataCenterInfo {
@@ -21,15 +30,63 @@ pub struct DataCenterInfo {
     continent: String,
 }
 
-#[derive(Clone, Serialize, Deserialize, Debug, Eq)]
+#[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct Node {
     pub id: PrincipalId,
     pub features: nakamoto::NodeFeatures,
     pub dfinity_owned: bool,
     pub decentralized: bool,
+    /// When the node is scheduled to be decommissioned, if at all, as
+    /// annotated by operations.
+    #[serde(default)]
+    pub decommission_at: Option<Timestamp>,
+    /// How much the node counts when scoring, e.g. by its stake or capacity,
+    /// relative to nodes of weight 1.
+    #[serde(default = "Node::default_weight")]
+    pub weight: f64,
+    /// Annotations operations attached to the node.
+    #[serde(default)]
+    pub annotations: NodeAnnotations,
 }
 
 impl Node {
+    /// A decentralized node with the given feature values, for scoring nodes
+    /// that do not come from the registry.
+    ///
+    /// ```
+    /// use decentralization::nakamoto::{NakamotoScore, NodeFeatures};
+    /// use decentralization::network::Node;
+    /// use ic_base_types::PrincipalId;
+    /// use ic_management_types::NodeFeature;
+    ///
+    /// let nodes = ["CH", "DE", "US", "US"]
+    ///     .iter()
+    ///     .enumerate()
+    ///     .map(|(i, country)| {
+    ///         let features = NodeFeatures::from_iter([
+    ///             (NodeFeature::NodeProvider, format!("provider {}", i)),
+    ///             (NodeFeature::Country, country.to_string()),
+    ///         ]);
+    ///         Node::new(PrincipalId::new_node_test_id(i as u64), features, false)
+    ///     })
+    ///     .collect::<Vec<_>>();
+    ///
+    /// let score = NakamotoScore::new_from_nodes(&nodes);
+    /// assert_eq!(score.score_feature(&NodeFeature::NodeProvider), Some(2.));
+    /// assert_eq!(score.score_feature(&NodeFeature::Country), Some(1.));
+    /// ```
+    pub fn new(id: PrincipalId, features: nakamoto::NodeFeatures, dfinity_owned: bool) -> Self {
+        Node {
+            id,
+            features,
+            dfinity_owned,
+            decentralized: true,
+            decommission_at: None,
+            weight: Self::default_weight(),
+            annotations: NodeAnnotations::default(),
+        }
+    }
+
     pub fn new_test_node(
         node_number: u64,
         features: nakamoto::NodeFeatures,
@@ -41,6 +98,38 @@ impl Node {
             features,
             dfinity_owned,
             decentralized,
+            decommission_at: None,
+            weight: Self::default_weight(),
+            annotations: NodeAnnotations::default(),
+        }
+    }
+
+    /// The weight of nodes not weighted otherwise, with which all nodes count
+    /// the same.
+    fn default_weight() -> f64 {
+        1.
+    }
+
+    /// The node with a non-negative `weight` when scoring.
+    pub fn with_weight(self, weight: f64) -> Self {
+        Self { weight, ..self }
+    }
+
+    pub fn with_annotations(self, annotations: NodeAnnotations) -> Self {
+        Self { annotations, ..self }
+    }
+
+    /// Whether the node is scheduled to be decommissioned before `deadline`.
+    pub fn decommissions_before(&self, deadline: Timestamp) -> bool {
+        self.decommission_at.map_or(false, |at| at < deadline)
+    }
+
+    /// Whether the node is scheduled to be decommissioned within `horizon`
+    /// from now.
+    pub fn decommissions_within(&self, horizon: Duration) -> bool {
+        match Timestamp::now().checked_add(horizon) {
+            Some(deadline) => self.decommissions_before(deadline),
+            None => self.decommission_at.is_some(),
         }
     }
 
@@ -74,6 +163,17 @@ impl PartialEq for Node {
     }
 }
 
+impl Eq for Node {}
+
+impl From<&Node> for NodeSummary {
+    fn from(node: &Node) -> Self {
+        Self {
+            principal: node.id,
+            features: node.features.feature_map.clone(),
+        }
+    }
+}
+
 impl From<&ic_management_types::Node> for Node {
     fn from(n: &ic_management_types::Node) -> Self {
         Self {
@@ -123,6 +223,9 @@ impl From<&ic_management_types::Node> for Node {
             ]),
             dfinity_owned: n.dfinity_owned.unwrap_or_default(),
             decentralized: n.decentralized,
+            decommission_at: n.annotations.decommission_at(),
+            weight: Node::default_weight(),
+            annotations: n.annotations.clone(),
         }
     }
 }
@@ -135,6 +238,156 @@ pub struct DecentralizedSubnet {
     pub min_nakamoto_coefficients: Option<MinNakamotoCoefficients>,
     pub comment: Option<String>,
     pub run_log: Vec<String>,
+    /// Seed for breaking ties between equally good candidates.
+    #[serde(default)]
+    pub seed: u64,
+    /// Do not require DFINITY-owned nodes in the subnet, e.g. for testnets
+    /// and community subnets.
+    #[serde(default)]
+    pub skip_dfinity_node_requirement: bool,
+    /// Only add nodes as long as the subnet stays within this budget.
+    #[serde(default)]
+    pub budget: Option<NodeBudget>,
+    /// How nodes scheduled for decommission are treated when adding nodes.
+    #[serde(default)]
+    pub decommission_policy: DecommissionPolicy,
+    /// The nodes of the providers across the network. If set, nodes of the
+    /// providers with fewer nodes in the network are preferred when adding
+    /// nodes.
+    #[serde(default)]
+    pub fleet_providers: Option<FleetProviderStats>,
+}
+
+/// How nodes that are decommissioned soon are treated when choosing the
+/// nodes to add to a subnet, since adding them only causes more churn.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DecommissionPolicy {
+    /// Decommission dates do not influence the choice.
+    #[default]
+    Ignore,
+    /// Among equally decentralizing nodes, prefer those that are not
+    /// decommissioned within the horizon from the time the nodes are chosen.
+    Deprioritize { horizon: Duration },
+    /// Never add nodes that are decommissioned within the horizon from the
+    /// time the nodes are chosen.
+    Avoid { horizon: Duration },
+}
+
+impl DecommissionPolicy {
+    /// Deprioritize the nodes decommissioned within `horizon` from now.
+    pub fn deprioritize_within(horizon: Duration) -> Self {
+        Self::Deprioritize { horizon }
+    }
+
+    /// Avoid the nodes decommissioned within `horizon` from now.
+    pub fn avoid_within(horizon: Duration) -> Self {
+        Self::Avoid { horizon }
+    }
+}
+
+/// What the nodes of each node provider cost, e.g. in node rewards, and how
+/// much all nodes of a subnet may cost together.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct NodeBudget {
+    /// The cost of a single node, by node provider. The nodes of providers
+    /// without a cost are free.
+    pub costs: BTreeMap<String, f64>,
+    pub budget: f64,
+}
+
+impl NodeBudget {
+    pub fn new(costs: BTreeMap<String, f64>, budget: f64) -> Self {
+        Self { costs, budget }
+    }
+
+    pub fn node_cost(&self, node: &Node) -> f64 {
+        self.costs
+            .get(&node.get_feature(&NodeFeature::NodeProvider))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn total_cost(&self, nodes: &[Node]) -> f64 {
+        nodes.iter().map(|n| self.node_cost(n)).sum()
+    }
+
+    /// The lowest cost at which `count` of the `available` nodes can be
+    /// added.
+    fn cheapest_cost(&self, count: usize, available: &[Node]) -> f64 {
+        available
+            .iter()
+            .map(|n| self.node_cost(n))
+            .sorted_by(|a, b| a.total_cmp(b))
+            .take(count)
+            .sum()
+    }
+
+    /// Whether `node` can be added to `nodes` such that `remaining` more of
+    /// the `available` nodes can still be added within the budget.
+    fn allows(&self, nodes: &[Node], node: &Node, remaining: usize, available: &[Node]) -> bool {
+        let others = available
+            .iter()
+            .filter(|n| n.id != node.id)
+            .cloned()
+            .collect::<Vec<_>>();
+        self.total_cost(nodes) + self.node_cost(node) + self.cheapest_cost(remaining, &others) <= self.budget
+    }
+}
+
+/// The number of nodes each node provider has in the subnets of the network.
+/// A change that improves one subnet by adding the nodes of a provider that
+/// is already concentrated in the network worsens the decentralization of
+/// the network as a whole.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FleetProviderStats {
+    pub nodes: BTreeMap<String, usize>,
+}
+
+impl FleetProviderStats {
+    pub fn from_subnets(subnets: &[DecentralizedSubnet]) -> Self {
+        Self {
+            nodes: overrepresented_providers(subnets)
+                .into_iter()
+                .map(|c| (c.provider, c.nodes))
+                .collect(),
+        }
+    }
+
+    /// The nodes the provider of `node` has in the network.
+    pub fn provider_nodes(&self, node: &Node) -> usize {
+        self.nodes
+            .get(&node.get_feature(&NodeFeature::NodeProvider))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// The nodes of the providers once `change` is made.
+    pub fn after(&self, change: &SubnetChange) -> Self {
+        let mut nodes = self.nodes.clone();
+        for node in change.removed() {
+            if let Some(count) = nodes.get_mut(&node.get_feature(&NodeFeature::NodeProvider)) {
+                *count = count.saturating_sub(1);
+            }
+        }
+        for node in change.added() {
+            *nodes.entry(node.get_feature(&NodeFeature::NodeProvider)).or_default() += 1;
+        }
+        Self { nodes }
+    }
+
+    /// The fewest providers that together have more than a third of the
+    /// nodes of the network.
+    pub fn nakamoto_coefficient(&self) -> usize {
+        NakamotoScore::nakamoto(&self.nodes.values().copied().collect::<Vec<_>>()).0
+    }
+}
+
+/// A business rule that a subnet does not satisfy, as reported by
+/// [`DecentralizedSubnet::check_business_rules`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BusinessRuleViolation {
+    pub message: String,
 }
 
 #[derive(Clone, Debug)]
@@ -179,6 +432,11 @@ impl DecentralizedSubnet {
                     run_log
                 }
             },
+            seed: self.seed,
+            skip_dfinity_node_requirement: false,
+            budget: self.budget.clone(),
+            decommission_policy: self.decommission_policy,
+            fleet_providers: self.fleet_providers.clone(),
         })
     }
 
@@ -203,9 +461,72 @@ impl DecentralizedSubnet {
                     run_log
                 }
             },
+            seed: self.seed,
+            skip_dfinity_node_requirement: false,
+            budget: self.budget,
+            decommission_policy: self.decommission_policy,
+            fleet_providers: self.fleet_providers,
         }
     }
 
+    /// Return the subnet with exactly the nodes `remove` removed and the nodes
+    /// `add` added. Fails if a node to remove is not in the subnet, a node to
+    /// add already is, or the resulting subnet breaks a business rule.
+    pub fn replace_nodes(&self, remove: &[PrincipalId], add: &[Node]) -> Result<Self, NetworkError> {
+        let removed = remove
+            .iter()
+            .map(|id| {
+                self.nodes
+                    .iter()
+                    .find(|n| n.id == *id)
+                    .cloned()
+                    .ok_or(NetworkError::NodeNotFound(*id))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if let Some(node) = add.iter().find(|node| self.nodes.iter().any(|n| n.id == node.id)) {
+            return Err(NetworkError::IllegalRequest(format!(
+                "Node {} is already a member of subnet {}",
+                node.id, self.id
+            )));
+        }
+
+        let replaced = self.without_nodes(removed)?.with_nodes(add.to_vec());
+        match replaced.check_business_rules() {
+            Ok((0, _)) => Ok(replaced),
+            Ok((_, checks)) => Err(NetworkError::IllegalRequest(format!(
+                "Subnet {} would break business rules: {}",
+                self.id,
+                checks.join(", ")
+            ))),
+            Err(e) => Err(NetworkError::IllegalRequest(e.to_string())),
+        }
+    }
+
+    /// Return the subnet as it will be once the pending `proposals` are
+    /// executed, so that it can be scored before proposing another change.
+    /// The changes of this subnet are applied in order, the changes of other
+    /// subnets are ignored.
+    pub fn with_pending_applied(&self, proposals: &[SubnetChange]) -> Self {
+        let mut projected = self.clone();
+        for change in proposals.iter().filter(|change| change.id == self.id) {
+            let removed = change.removed();
+            let added = change.added();
+            projected.nodes.retain(|n| !removed.contains(n));
+            for node in &added {
+                if !projected.nodes.contains(node) {
+                    projected.nodes.push(node.clone());
+                }
+            }
+            projected.removed_nodes.extend(removed.iter().cloned());
+            projected.run_log.push(format!(
+                "Applied pending change removing nodes {:?} and adding nodes {:?}",
+                removed.iter().map(|n| n.id).collect::<Vec<_>>(),
+                added.iter().map(|n| n.id).collect::<Vec<_>>()
+            ));
+        }
+        projected
+    }
+
     /// Return a list of nodes that are under control of the most dominant
     /// feature value. For instance with the argument NodeProvider, it will
     /// return the nodes that are under control of the most dominant
@@ -224,6 +545,26 @@ impl DecentralizedSubnet {
             .collect()
     }
 
+    /// Return the nodes that are not healthy according to `healths`. Nodes
+    /// without a known health are assumed to be unhealthy.
+    pub fn unhealthy_nodes(&self, healths: &BTreeMap<PrincipalId, HealthStatus>) -> Vec<Node> {
+        self.nodes
+            .iter()
+            .filter(|n| match healths.get(&n.id) {
+                Some(HealthStatus::Healthy) => false,
+                Some(HealthStatus::Unknown) | None => {
+                    warn!("Node {} has no known health, assuming unhealthy", n.id);
+                    true
+                }
+                Some(health) => {
+                    info!("Node {} is {}", n.id, health);
+                    true
+                }
+            })
+            .cloned()
+            .collect()
+    }
+
     pub fn with_min_nakamoto_coefficients(self, min_nakamoto_coefficients: &Option<MinNakamotoCoefficients>) -> Self {
         Self {
             min_nakamoto_coefficients: min_nakamoto_coefficients.clone(),
@@ -231,18 +572,95 @@ impl DecentralizedSubnet {
         }
     }
 
+    pub fn with_seed(self, seed: u64) -> Self {
+        Self { seed, ..self }
+    }
+
+    /// Whether the subnet needs DFINITY-owned nodes, which is the case unless
+    /// disabled for testnets or community subnets.
+    pub fn with_dfinity_node_requirement(self, required: bool) -> Self {
+        Self {
+            skip_dfinity_node_requirement: !required,
+            ..self
+        }
+    }
+
+    pub fn with_budget(self, budget: Option<NodeBudget>) -> Self {
+        Self { budget, ..self }
+    }
+
+    pub fn with_decommission_policy(self, decommission_policy: DecommissionPolicy) -> Self {
+        Self {
+            decommission_policy,
+            ..self
+        }
+    }
+
+    pub fn with_fleet_providers(self, fleet_providers: Option<FleetProviderStats>) -> Self {
+        Self {
+            fleet_providers,
+            ..self
+        }
+    }
+
     /// Ensure "business rules" or constraints for the subnet nodes are met.
     /// For instance, there needs to be at least one DFINITY-owned node in each
     /// subnet. For the mainnet NNS there needs to be at least 3
     /// DFINITY-owned nodes.
     pub fn check_business_rules(&self) -> anyhow::Result<(usize, Vec<String>)> {
-        Self::_check_business_rules_for_nodes(&self.id, &self.nodes, &self.min_nakamoto_coefficients)
+        self.check_business_rules_for_nodes(&self.nodes)
+    }
+
+    fn check_business_rules_for_nodes(&self, nodes: &[Node]) -> anyhow::Result<(usize, Vec<String>)> {
+        Self::_check_business_rules_for_nodes(
+            &self.id,
+            nodes,
+            &self.min_nakamoto_coefficients,
+            !self.skip_dfinity_node_requirement,
+        )
+    }
+
+    /// Return the nodes whose removal alone would make the subnet break a
+    /// business rule it currently satisfies, together with the violations the
+    /// removal would introduce. Violations the subnet already has are not
+    /// reported. Fails if the business rules cannot be checked, for the
+    /// subnet or without one of its nodes.
+    pub fn removal_fragility(&self) -> anyhow::Result<Vec<(PrincipalId, BusinessRuleViolation)>> {
+        let (penalties_before, checks_before) = self.check_business_rules()?;
+
+        let mut fragility = vec![];
+        for node in &self.nodes {
+            let remaining = self
+                .nodes
+                .iter()
+                .filter(|n| n.id != node.id)
+                .cloned()
+                .collect::<Vec<_>>();
+            let (penalties, checks) = self.check_business_rules_for_nodes(&remaining)?;
+            if penalties > penalties_before {
+                fragility.extend(
+                    checks
+                        .into_iter()
+                        .filter(|check| !checks_before.contains(check))
+                        .map(|message| (node.id, BusinessRuleViolation { message })),
+                );
+            }
+        }
+        Ok(fragility)
+    }
+
+    /// The number of distinct data center owners of the subnet nodes.
+    pub fn distinct_owners(&self) -> usize {
+        Self::_calc_nakamoto_score(&self.nodes)
+            .feature_value_counts(&NodeFeature::DataCenterOwner)
+            .len()
     }
 
     fn _check_business_rules_for_nodes(
         subnet_id: &PrincipalId,
         nodes: &[Node],
         min_nakamoto_coefficients: &Option<MinNakamotoCoefficients>,
+        require_dfinity_node: bool,
     ) -> anyhow::Result<(usize, Vec<String>)> {
         let mut checks = Vec::new();
         let mut penalties = 0;
@@ -252,7 +670,8 @@ impl DecentralizedSubnet {
 
         let nakamoto_scores = Self::_calc_nakamoto_score(nodes);
         let subnet_id_str = subnet_id.to_string();
-        let is_european_subnet = subnet_id_str == *"bkfrj-6k62g-dycql-7h53p-atvkj-zg4to-gaogh-netha-ptybj-ntsgw-rqe";
+        let is_european_subnet =
+            SubnetKind::of_subnet(subnet_id).map_or(false, |kind| kind.rule_profile().european_nodes_only);
 
         let dfinity_owned_nodes_count: usize = nodes.iter().map(|n| n.dfinity_owned as usize).sum();
         let target_dfinity_owned_nodes_count =
@@ -262,7 +681,7 @@ impl DecentralizedSubnet {
                 1
             };
 
-        if dfinity_owned_nodes_count != target_dfinity_owned_nodes_count {
+        if require_dfinity_node && dfinity_owned_nodes_count != target_dfinity_owned_nodes_count {
             checks.push(format!(
                 "Subnet should have {} DFINITY-owned nodes, got {}",
                 target_dfinity_owned_nodes_count, dfinity_owned_nodes_count
@@ -340,6 +759,38 @@ impl DecentralizedSubnet {
             None => return Err(anyhow::anyhow!("Missing the Nakamoto score for the Node Provider")),
         }
 
+        if let Some(min_distinct_owners) = min_nakamoto_coefficients.as_ref().and_then(|m| m.min_distinct_owners) {
+            // A subnet hosted by a few colocation providers is fragile even
+            // if the coefficients look fine.
+            let distinct_owners = nakamoto_scores
+                .feature_value_counts(&NodeFeature::DataCenterOwner)
+                .len();
+            if distinct_owners < min_distinct_owners {
+                checks.push(format!(
+                    "Subnet has {} distinct data center owners, expected at least {}",
+                    distinct_owners, min_distinct_owners
+                ));
+                penalties += (min_distinct_owners - distinct_owners) * 1000;
+            }
+        }
+
+        if let Some(max_nodes) = min_nakamoto_coefficients
+            .as_ref()
+            .and_then(|m| m.max_nodes_per_data_center)
+        {
+            // Unlike an owner, which may run several data centers, a data
+            // center is a single physical failure domain.
+            for (data_center, count) in nakamoto_scores.feature_value_counts(&NodeFeature::DataCenter) {
+                if count > max_nodes {
+                    checks.push(format!(
+                        "Data center '{}' hosts {} nodes, expected at most {}",
+                        data_center, count, max_nodes
+                    ));
+                    penalties += (count - max_nodes) * 1000;
+                }
+            }
+        }
+
         if let Some(min_nakamoto_coefficients) = min_nakamoto_coefficients {
             for (feature, min_coeff) in min_nakamoto_coefficients.coefficients.iter() {
                 match nakamoto_scores.score_feature(feature) {
@@ -413,11 +864,15 @@ impl DecentralizedSubnet {
 
     /// Deterministically choose a result in the list based on the list
     /// of current nodes.  Since the node IDs are unique, we seed a PRNG
-    /// with the sorted joined node IDs. We then choose a result
-    /// randomly but deterministically using this seed.
+    /// with the sorted joined node IDs and the subnet's seed. We then choose a
+    /// result randomly but deterministically using this seed.
+    ///
+    /// The default seed of 0 keeps the choices made before the seed could be
+    /// set.
     fn choose_deterministic_random(
         best_results: &Vec<ReplacementCandidate>,
         current_nodes: &[Node],
+        seed: u64,
     ) -> Option<ReplacementCandidate> {
         if best_results.is_empty() {
             None
@@ -427,14 +882,14 @@ impl DecentralizedSubnet {
             // same machines in the subnet
             let mut id_sorted_current_nodes = current_nodes.to_owned();
             id_sorted_current_nodes.sort_by(|n1, n2| std::cmp::Ord::cmp(&n1.id.to_string(), &n2.id.to_string()));
-            let seed = rand_seeder::Seeder::from(
-                id_sorted_current_nodes
-                    .iter()
-                    .map(|n| n.id.to_string())
-                    .collect::<Vec<String>>()
-                    .join("_"),
-            )
-            .make_seed();
+            let mut seed_parts = id_sorted_current_nodes
+                .iter()
+                .map(|n| n.id.to_string())
+                .collect::<Vec<String>>();
+            if seed != 0 {
+                seed_parts.push(seed.to_string());
+            }
+            let seed = rand_seeder::Seeder::from(seed_parts.join("_")).make_seed();
             let mut rng = rand::rngs::StdRng::from_seed(seed);
 
             // We sort the best results the same way to ensure that for
@@ -521,7 +976,102 @@ impl DecentralizedSubnet {
         //
         // This approach also has the advantage of not favoring one NP over
         // an other, regardless of the Node PrincipalID
-        DecentralizedSubnet::choose_deterministic_random(&best_results, &self.nodes)
+        DecentralizedSubnet::choose_deterministic_random(&best_results, &self.nodes, self.seed)
+    }
+
+    /// Pick the best node to add like [`Self::choose_best_candidate`], but
+    /// with [`DecommissionPolicy::Deprioritize`] prefer an equally good node
+    /// that is not decommissioned soon, and with the fleet providers set one
+    /// of a provider with fewer nodes in the network.
+    fn choose_best_addition(
+        &self,
+        candidates: Vec<ReplacementCandidate>,
+        run_log: &mut Vec<String>,
+    ) -> Option<ReplacementCandidate> {
+        let best = self.choose_best_candidate(candidates.clone(), run_log)?;
+        let best = self.prefer_not_decommissioned(best, &candidates, run_log);
+        Some(self.prefer_diverse_fleet(best, &candidates, run_log))
+    }
+
+    fn decommissions_soon(&self, node: &Node) -> bool {
+        match self.decommission_policy {
+            DecommissionPolicy::Deprioritize { horizon } => node.decommissions_within(horizon),
+            _ => false,
+        }
+    }
+
+    fn prefer_not_decommissioned(
+        &self,
+        best: ReplacementCandidate,
+        candidates: &[ReplacementCandidate],
+        run_log: &mut Vec<String>,
+    ) -> ReplacementCandidate {
+        if !self.decommissions_soon(&best.node) {
+            return best;
+        }
+        let stable = candidates
+            .iter()
+            .filter(|c| !self.decommissions_soon(&c.node) && c.score == best.score && c.penalty == best.penalty)
+            .cloned()
+            .collect::<Vec<_>>();
+        match self.choose_best_candidate(stable, &mut Vec::new()) {
+            Some(stable) => {
+                run_log.push(format!(
+                    "Preferring node {} over node {}, which is decommissioned soon",
+                    stable.node.id, best.node.id
+                ));
+                stable
+            }
+            None => best,
+        }
+    }
+
+    /// Among the nodes as good as `best` for the least decentralized feature
+    /// of the subnet, and not decommissioned sooner, prefer the ones of the
+    /// providers with the fewest nodes in the network, even if they improve
+    /// the other features less. Among those, the best for the subnet wins.
+    fn prefer_diverse_fleet(
+        &self,
+        best: ReplacementCandidate,
+        candidates: &[ReplacementCandidate],
+        run_log: &mut Vec<String>,
+    ) -> ReplacementCandidate {
+        let fleet_providers = match &self.fleet_providers {
+            Some(fleet_providers) => fleet_providers,
+            None => return best,
+        };
+        let equally_good = candidates
+            .iter()
+            .filter(|c| {
+                c.score.score_min() == best.score.score_min()
+                    && c.penalty == best.penalty
+                    && self.decommissions_soon(&c.node) <= self.decommissions_soon(&best.node)
+            })
+            .collect::<Vec<_>>();
+        let fewest = equally_good
+            .iter()
+            .map(|c| fleet_providers.provider_nodes(&c.node))
+            .min()
+            .unwrap_or_default();
+        let best_nodes = fleet_providers.provider_nodes(&best.node);
+        if best_nodes <= fewest {
+            return best;
+        }
+        let diverse = equally_good
+            .into_iter()
+            .filter(|c| fleet_providers.provider_nodes(&c.node) == fewest)
+            .cloned()
+            .collect::<Vec<_>>();
+        match self.choose_best_candidate(diverse, &mut Vec::new()) {
+            Some(diverse) => {
+                run_log.push(format!(
+                    "Preferring node {} over node {}, whose provider has {} instead of {} nodes in the network",
+                    diverse.node.id, best.node.id, best_nodes, fewest
+                ));
+                diverse
+            }
+            None => best,
+        }
     }
 
     /// Add nodes to a subnet in a way that provides the best decentralization.
@@ -530,7 +1080,20 @@ impl DecentralizedSubnet {
         how_many_nodes: usize,
         available_nodes: &[Node],
     ) -> anyhow::Result<DecentralizedSubnet> {
+        self.subnet_with_more_nodes_scored(how_many_nodes, available_nodes)
+            .map(|(subnet, _)| subnet)
+    }
+
+    /// Like [DecentralizedSubnet::subnet_with_more_nodes], but also return
+    /// the Nakamoto score of the subnet after each added node, to see where
+    /// the greedy extension stops improving the decentralization.
+    pub fn subnet_with_more_nodes_scored(
+        self,
+        how_many_nodes: usize,
+        available_nodes: &[Node],
+    ) -> anyhow::Result<(DecentralizedSubnet, Vec<NakamotoScore>)> {
         let mut run_log = self.run_log.clone();
+        let mut scores = Vec::with_capacity(how_many_nodes);
 
         let mut nodes_initial = self.nodes.clone();
         let mut available_nodes = available_nodes.to_vec();
@@ -542,6 +1105,18 @@ impl DecentralizedSubnet {
 
         run_log.push(format!("Nakamoto score before extension {}", self.nakamoto_score()));
 
+        if let Some(budget) = &self.budget {
+            let lowest_cost = budget.total_cost(&self.nodes) + budget.cheapest_cost(how_many_nodes, &available_nodes);
+            if lowest_cost > budget.budget {
+                return Err(anyhow!(
+                    "Adding {} nodes to the subnet costs at least {}, which exceeds the budget of {}",
+                    how_many_nodes,
+                    lowest_cost,
+                    budget.budget
+                ));
+            }
+        }
+
         for i in 0..how_many_nodes {
             run_log.push("***********************************************************".to_string());
             run_log.push(format!("***  Adding node {}/{}", i + 1, how_many_nodes));
@@ -550,13 +1125,26 @@ impl DecentralizedSubnet {
             let suitable_candidates: Vec<ReplacementCandidate> = available_nodes
                 .iter()
                 .filter_map(|node| {
+                    if let DecommissionPolicy::Avoid { horizon } = self.decommission_policy {
+                        if node.decommissions_within(horizon) {
+                            run_log.push(format!("Node {} is decommissioned too soon", node.id));
+                            return None;
+                        }
+                    }
+                    // The nodes added after this one must fit into the budget as well.
+                    if let Some(budget) = &self.budget {
+                        if !budget.allows(&nodes_initial, node, how_many_nodes - i - 1, &available_nodes) {
+                            run_log.push(format!("Node {} does not fit into the budget", node.id));
+                            return None;
+                        }
+                    }
                     let subnet_nodes: Vec<Node> = nodes_initial.iter().chain([node]).cloned().collect();
                     self._node_to_replacement_candidate(&subnet_nodes, node, &mut run_log)
                 })
                 .collect();
 
             let mut candidate_run_log = Vec::new();
-            match self.choose_best_candidate(suitable_candidates, &mut candidate_run_log) {
+            match self.choose_best_addition(suitable_candidates, &mut candidate_run_log) {
                 Some(best_result) => {
                     // Append the complete run log
                     run_log.extend(
@@ -566,6 +1154,14 @@ impl DecentralizedSubnet {
                             .collect::<Vec<String>>(),
                     );
                     run_log.push(format!("Nakamoto score after extension {}", best_result.score));
+                    debug!(
+                        "Nakamoto score after adding node {}/{} ({}): {}",
+                        i + 1,
+                        how_many_nodes,
+                        best_result.node.id,
+                        best_result.score
+                    );
+                    scores.push(best_result.score.clone());
                     available_nodes.retain(|n| n.id != best_result.node.id);
                     nodes_after_extension.push(best_result.node.clone());
                     nodes_initial.push(best_result.node.clone());
@@ -604,14 +1200,22 @@ impl DecentralizedSubnet {
         assert_eq!(nodes_after_extension.len(), self.nodes.len() + how_many_nodes);
         assert_eq!(orig_available_nodes_len - available_nodes.len(), how_many_nodes);
 
-        Ok(Self {
-            id: self.id,
-            nodes: nodes_after_extension,
-            removed_nodes: self.removed_nodes,
-            min_nakamoto_coefficients: self.min_nakamoto_coefficients,
-            comment,
-            run_log,
-        })
+        Ok((
+            Self {
+                id: self.id,
+                nodes: nodes_after_extension,
+                removed_nodes: self.removed_nodes,
+                min_nakamoto_coefficients: self.min_nakamoto_coefficients,
+                comment,
+                run_log,
+                seed: self.seed,
+                skip_dfinity_node_requirement: false,
+                budget: self.budget,
+                decommission_policy: self.decommission_policy,
+                fleet_providers: self.fleet_providers,
+            },
+            scores,
+        ))
     }
 
     /// Remove nodes from a subnet in a way that provides the best
@@ -694,6 +1298,11 @@ impl DecentralizedSubnet {
             min_nakamoto_coefficients: self.min_nakamoto_coefficients,
             comment,
             run_log,
+            seed: self.seed,
+            skip_dfinity_node_requirement: false,
+            budget: self.budget,
+            decommission_policy: self.decommission_policy,
+            fleet_providers: self.fleet_providers,
         })
     }
 
@@ -703,7 +1312,7 @@ impl DecentralizedSubnet {
         touched_node: &Node,
         err_log: &mut Vec<String>,
     ) -> Option<ReplacementCandidate> {
-        match Self::_check_business_rules_for_nodes(&self.id, subnet_nodes, &self.min_nakamoto_coefficients) {
+        match self.check_business_rules_for_nodes(subnet_nodes) {
             Ok((penalty, business_rules_log)) => {
                 let new_score = Self::_calc_nakamoto_score(subnet_nodes);
                 Some(ReplacementCandidate {
@@ -748,6 +1357,11 @@ impl From<&ic_management_types::Subnet> for DecentralizedSubnet {
             min_nakamoto_coefficients: None,
             comment: None,
             run_log: Vec::new(),
+            seed: 0,
+            skip_dfinity_node_requirement: false,
+            budget: None,
+            decommission_policy: DecommissionPolicy::default(),
+            fleet_providers: None,
         }
     }
 }
@@ -758,6 +1372,44 @@ impl From<ic_management_types::Subnet> for DecentralizedSubnet {
     }
 }
 
+/// The nodes a node provider has across all subnets.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProviderConcentration {
+    pub provider: String,
+    pub nodes: usize,
+    pub subnets: usize,
+}
+
+/// The node providers of the `subnets`, the most represented across the
+/// whole network first: by their number of nodes in all subnets, then by
+/// the number of subnets they have nodes in. Providers that are not
+/// dominant in any single subnet can still be systemically important.
+pub fn overrepresented_providers(subnets: &[DecentralizedSubnet]) -> Vec<ProviderConcentration> {
+    let mut providers = BTreeMap::<String, ProviderConcentration>::new();
+    for subnet in subnets {
+        let counts = subnet
+            .nodes
+            .iter()
+            .map(|n| n.get_feature(&NodeFeature::NodeProvider))
+            .counts();
+        for (provider, nodes) in counts {
+            let concentration = providers
+                .entry(provider.clone())
+                .or_insert_with(|| ProviderConcentration {
+                    provider,
+                    nodes: 0,
+                    subnets: 0,
+                });
+            concentration.nodes += nodes;
+            concentration.subnets += 1;
+        }
+    }
+    providers
+        .into_values()
+        .sorted_by(|a, b| b.nodes.cmp(&a.nodes).then(b.subnets.cmp(&a.subnets)))
+        .collect()
+}
+
 #[async_trait]
 pub trait AvailableNodesQuerier {
     async fn available_nodes(&self) -> Result<Vec<Node>, NetworkError>;
@@ -780,46 +1432,59 @@ pub enum DecentralizationError {
 
 impl ResponseError for DecentralizationError {
     fn error_response(&self) -> HttpResponse {
-        let out: serde_json::Value =
-            serde_json::from_str("{\"message\": \"NodeFeature not available. For access contact the administrator\"}")
-                .unwrap();
-        HttpResponse::BadRequest().json(out)
+        ManagementError::from(self.clone()).error_response()
     }
 
     fn status_code(&self) -> StatusCode {
-        match self {
-            Self::FeatureNotAvailable => StatusCode::BAD_REQUEST,
+        ManagementError::from(self.clone()).status_code()
+    }
+}
+
+impl From<DecentralizationError> for ManagementError {
+    fn from(err: DecentralizationError) -> Self {
+        match err {
+            DecentralizationError::FeatureNotAvailable => ManagementError::ConstraintUnsatisfiable {
+                details: "NodeFeature not available. For access contact the administrator".to_string(),
+            },
         }
     }
 }
 
 #[async_trait]
 pub trait TopologyManager: SubnetQuerier + AvailableNodesQuerier {
+    /// Subnets that must not be changed, e.g. during an incident.
+    fn frozen_subnets(&self) -> Vec<PrincipalId> {
+        vec![]
+    }
+
+    /// The nodes each node provider has in the subnets of the network.
+    fn fleet_providers(&self) -> FleetProviderStats {
+        FleetProviderStats::default()
+    }
+
     async fn modify_subnet_nodes(&self, by: SubnetQueryBy) -> Result<SubnetChangeRequest, NetworkError> {
         Ok(SubnetChangeRequest {
             available_nodes: self.available_nodes().await?,
             subnet: self.subnet(by).await?,
+            frozen_subnets: self.frozen_subnets(),
             ..Default::default()
         })
     }
 
-    async fn create_subnet(
-        &self,
-        size: usize,
-        min_nakamoto_coefficients: Option<MinNakamotoCoefficients>,
-        include_nodes: Vec<PrincipalId>,
-        exclude_nodes: Vec<String>,
-        only_nodes: Vec<String>,
-    ) -> Result<SubnetChange, NetworkError> {
+    async fn create_subnet(&self, request: &SubnetCreateRequest) -> Result<SubnetChange, NetworkError> {
+        request.check()?;
         SubnetChangeRequest {
             available_nodes: self.available_nodes().await?,
-            min_nakamoto_coefficients,
+            min_nakamoto_coefficients: request.min_nakamoto_coefficients.clone(),
+            seed: request.seed.unwrap_or_default(),
+            skip_dfinity_node_requirement: !request.require_dfinity_node.unwrap_or(true),
             ..Default::default()
         }
-        .with_include_nodes(include_nodes.clone())
-        .with_exclude_nodes(exclude_nodes.clone())
-        .with_only_nodes_that_have_features(only_nodes.clone())
-        .resize(size, 0)
+        .with_include_nodes(request.include.clone().unwrap_or_default())
+        .with_exclude_nodes(request.exclude.clone().unwrap_or_default())
+        .with_only_nodes_that_have_features(request.only.clone().unwrap_or_default())
+        .with_fleet_providers_if(request.fleet_aware, || self.fleet_providers())
+        .resize(request.size, 0)
     }
 }
 
@@ -830,6 +1495,15 @@ pub struct SubnetChangeRequest {
     include_nodes: Vec<PrincipalId>,
     removed_nodes: Vec<Node>,
     min_nakamoto_coefficients: Option<MinNakamotoCoefficients>,
+    exclude_countries: Vec<String>,
+    exclude_continents: Vec<String>,
+    seed: u64,
+    skip_dfinity_node_requirement: bool,
+    frozen_subnets: Vec<PrincipalId>,
+    budget: Option<NodeBudget>,
+    decommission_policy: DecommissionPolicy,
+    min_retained: Option<usize>,
+    fleet_providers: Option<FleetProviderStats>,
 }
 
 impl SubnetChangeRequest {
@@ -846,6 +1520,7 @@ impl SubnetChangeRequest {
             include_nodes,
             removed_nodes,
             min_nakamoto_coefficients,
+            ..Default::default()
         }
     }
 
@@ -884,6 +1559,30 @@ impl SubnetChangeRequest {
         }
     }
 
+    /// Exclude the available nodes having any of the feature values.
+    pub fn with_exclude_features(self, features: Vec<FeatureMatch>) -> Self {
+        Self {
+            available_nodes: self
+                .available_nodes
+                .into_iter()
+                .filter(|n| !features.iter().any(|m| m.matches(&n.get_feature(&m.feature))))
+                .collect(),
+            ..self
+        }
+    }
+
+    /// Exclude the available nodes having an annotation with any of the keys.
+    pub fn with_exclude_annotated(self, keys: Vec<String>) -> Self {
+        Self {
+            available_nodes: self
+                .available_nodes
+                .into_iter()
+                .filter(|n| !n.annotations.has_any(&keys))
+                .collect(),
+            ..self
+        }
+    }
+
     pub fn with_only_nodes_that_have_features(self, only_nodes_or_features: Vec<String>) -> Self {
         let available_nodes = if only_nodes_or_features.is_empty() {
             self.available_nodes.into_iter().collect()
@@ -899,6 +1598,52 @@ impl SubnetChangeRequest {
         }
     }
 
+    /// Do not place new nodes in any of the listed countries (ISO 3166-1
+    /// alpha-2 codes).
+    pub fn with_exclude_countries(self, countries: Vec<String>) -> Self {
+        Self {
+            exclude_countries: self.exclude_countries.into_iter().chain(countries).collect(),
+            ..self
+        }
+    }
+
+    /// Do not place new nodes in any of the listed continents.
+    pub fn with_exclude_continents(self, continents: Vec<String>) -> Self {
+        Self {
+            exclude_continents: self.exclude_continents.into_iter().chain(continents).collect(),
+            ..self
+        }
+    }
+
+    /// Available nodes without the ones located in the excluded countries and
+    /// continents.
+    fn available_nodes_outside_excluded_locations(&self) -> Result<Vec<Node>, NetworkError> {
+        if let Some(country) = self.exclude_countries.iter().find(|c| !iso::is_country_code(c)) {
+            return Err(NetworkError::IllegalRequest(format!(
+                "Invalid country code {}, expected an ISO 3166-1 alpha-2 code",
+                country
+            )));
+        }
+        if let Some(continent) = self.exclude_continents.iter().find(|c| !iso::is_continent(c)) {
+            return Err(NetworkError::IllegalRequest(format!("Invalid continent {}", continent)));
+        }
+
+        Ok(self
+            .available_nodes
+            .iter()
+            .filter(|n| {
+                let country = n.get_feature(&NodeFeature::Country);
+                let continent = n.get_feature(&NodeFeature::Continent);
+                !self.exclude_countries.iter().any(|c| c.eq_ignore_ascii_case(&country))
+                    && !self
+                        .exclude_continents
+                        .iter()
+                        .any(|c| c.eq_ignore_ascii_case(&continent))
+            })
+            .cloned()
+            .collect())
+    }
+
     pub fn with_custom_available_nodes(self, nodes: Vec<Node>) -> Self {
         Self {
             available_nodes: nodes,
@@ -913,6 +1658,125 @@ impl SubnetChangeRequest {
         }
     }
 
+    /// Seed for breaking ties between equally good nodes. The same request
+    /// with the same seed always results in the same change.
+    pub fn with_seed(self, seed: u64) -> Self {
+        Self { seed, ..self }
+    }
+
+    /// Whether the subnet needs DFINITY-owned nodes. Only testnets and
+    /// community subnets should go without.
+    pub fn with_dfinity_node_requirement(self, required: bool) -> Self {
+        Self {
+            skip_dfinity_node_requirement: !required,
+            ..self
+        }
+    }
+
+    /// Subnets for which no change may be generated. They can still be
+    /// evaluated.
+    pub fn with_frozen_subnets(self, subnets: Vec<PrincipalId>) -> Self {
+        Self {
+            frozen_subnets: self.frozen_subnets.into_iter().chain(subnets).collect(),
+            ..self
+        }
+    }
+
+    /// Choose the nodes to add such that the subnet stays within the budget.
+    /// Among the nodes that fit, the ones providing the best decentralization
+    /// are chosen as usual. Nodes are only added, not removed, within the
+    /// budget, so a subnet that already exceeds it cannot be resized.
+    pub fn with_budget(self, budget: NodeBudget) -> Self {
+        Self {
+            budget: Some(budget),
+            ..self
+        }
+    }
+
+    /// How nodes scheduled for decommission are treated when choosing the
+    /// nodes to add. By default their decommission dates are ignored.
+    pub fn with_decommission_policy(self, decommission_policy: DecommissionPolicy) -> Self {
+        Self {
+            decommission_policy,
+            ..self
+        }
+    }
+
+    /// Among the nodes to add that are as good for the least decentralized
+    /// feature of the subnet, prefer the ones of the providers with the
+    /// fewest nodes in the network, and fail changes that lower the Nakamoto
+    /// coefficient of the providers across the network, so that improving
+    /// this subnet does not concentrate the network further. By default the
+    /// nodes of the providers elsewhere in the network are ignored.
+    pub fn with_fleet_providers(self, fleet_providers: FleetProviderStats) -> Self {
+        Self {
+            fleet_providers: Some(fleet_providers),
+            ..self
+        }
+    }
+
+    /// [Self::with_fleet_providers] if `fleet_aware`, as requested by the
+    /// `fleet_aware` field of the requests to the management API.
+    pub fn with_fleet_providers_if(
+        self,
+        fleet_aware: bool,
+        fleet_providers: impl FnOnce() -> FleetProviderStats,
+    ) -> Self {
+        if fleet_aware {
+            self.with_fleet_providers(fleet_providers())
+        } else {
+            self
+        }
+    }
+
+    /// The change must keep at least `min_retained` of the current nodes of
+    /// the subnet, counting the nodes replaced for being unhealthy as not
+    /// kept. Changes replacing more nodes fail rather than replacing fewer.
+    pub fn with_min_retained(self, min_retained: Option<usize>) -> Self {
+        Self { min_retained, ..self }
+    }
+
+    fn check_not_frozen(&self) -> Result<(), NetworkError> {
+        if self.frozen_subnets.contains(&self.subnet.id) {
+            return Err(NetworkError::SubnetFrozen(self.subnet.id));
+        }
+        Ok(())
+    }
+
+    fn check_fleet_diversity(&self, change: &SubnetChange) -> Result<(), NetworkError> {
+        let fleet_providers = match &self.fleet_providers {
+            Some(fleet_providers) => fleet_providers,
+            None => return Ok(()),
+        };
+        let before = fleet_providers.nakamoto_coefficient();
+        let after = fleet_providers.after(change).nakamoto_coefficient();
+        if after < before {
+            return Err(NetworkError::ResizeFailed(format!(
+                "The change of subnet {} lowers the Nakamoto coefficient of the node providers across the network from {} to {}",
+                change.id, before, after
+            )));
+        }
+        Ok(())
+    }
+
+    fn check_retained(&self, change: &SubnetChange) -> Result<(), NetworkError> {
+        let min_retained = match self.min_retained {
+            Some(min_retained) => min_retained,
+            None => return Ok(()),
+        };
+        let retained = change.old_nodes.iter().filter(|n| change.new_nodes.contains(n)).count();
+        if retained < min_retained {
+            return Err(NetworkError::ResizeFailed(format!(
+                "The change keeps {} of the {} current nodes of subnet {}, at least {} must be kept",
+                retained,
+                change.old_nodes.len(),
+                change.id,
+                min_retained
+            )));
+        }
+        Ok(())
+    }
+
     /// Optimize is implemented by removing a certain number of nodes and then
     /// adding the same number back.
     pub fn optimize(
@@ -920,10 +1784,18 @@ impl SubnetChangeRequest {
         optimize_count: usize,
         replacements_unhealthy: &Vec<Node>,
     ) -> Result<SubnetChange, NetworkError> {
+        if optimize_count > 0 || !replacements_unhealthy.is_empty() {
+            self.check_not_frozen()?;
+        }
         let old_nodes = self.subnet.nodes.clone();
         self.subnet = self.subnet.without_nodes(replacements_unhealthy.clone())?;
         let result = self.resize(optimize_count + replacements_unhealthy.len(), optimize_count)?;
-        Ok(SubnetChange { old_nodes, ..result })
+        let change = SubnetChange { old_nodes, ..result };
+        self.check_retained(&change)?;
+        let removed = change.removed();
+        Ok(change
+            .with_removal_reasons(&removed, RemovalReason::Optimization)
+            .with_removal_reasons(replacements_unhealthy, RemovalReason::Unhealthy))
     }
 
     /// Add or remove nodes from the subnet.
@@ -936,32 +1808,52 @@ impl SubnetChangeRequest {
             "Resizing subnet {} by adding {} nodes and removing {} nodes",
             self.subnet.id, how_many_nodes_to_add, how_many_nodes_to_remove
         );
+        if how_many_nodes_to_add > 0 || how_many_nodes_to_remove > 0 {
+            self.check_not_frozen()?;
+        }
         let old_nodes = self.subnet.nodes.clone();
 
+        let all_available_nodes = self.available_nodes_outside_excluded_locations()?;
+        if all_available_nodes.len() < how_many_nodes_to_add
+            && (!self.exclude_countries.is_empty() || !self.exclude_continents.is_empty())
+        {
+            return Err(NetworkError::PoolExhausted(format!(
+                "Only {} available nodes remain after excluding countries {:?} and continents {:?}, {} are needed",
+                all_available_nodes.len(),
+                self.exclude_countries,
+                self.exclude_continents,
+                how_many_nodes_to_add
+            )));
+        }
+
         let included_nodes = if self.include_nodes.is_empty() {
             Vec::new()
         } else {
-            self.available_nodes
+            all_available_nodes
                 .iter()
                 .filter(|n| self.include_nodes.contains(&n.id))
                 .cloned()
                 .collect::<Vec<_>>()
         };
 
-        let available_nodes = self
-            .available_nodes
-            .clone()
+        let available_nodes = all_available_nodes
             .into_iter()
             .filter(|n| !included_nodes.contains(n))
             .collect::<Vec<_>>();
+        let included_nodes_count = included_nodes.len();
 
         let resized_subnet = self
             .subnet
             .clone()
             .with_nodes(included_nodes)
             .with_min_nakamoto_coefficients(&self.min_nakamoto_coefficients)
+            .with_seed(self.seed)
+            .with_dfinity_node_requirement(!self.skip_dfinity_node_requirement)
+            .with_budget(self.budget.clone())
+            .with_decommission_policy(self.decommission_policy)
+            .with_fleet_providers(self.fleet_providers.clone())
             .subnet_with_more_nodes(how_many_nodes_to_add, &available_nodes)
-            .map_err(|e| NetworkError::ResizeFailed(e.to_string()))?;
+            .map_err(|e| NetworkError::PoolExhausted(e.to_string()))?;
 
         let resized_subnet = if how_many_nodes_to_remove > 0 {
             resized_subnet
@@ -978,7 +1870,24 @@ impl SubnetChangeRequest {
             min_nakamoto_coefficients: self.min_nakamoto_coefficients.clone(),
             comment: resized_subnet.comment,
             run_log: resized_subnet.run_log,
+            removal_reasons: BTreeMap::new(),
+            skip_dfinity_node_requirement: false,
         };
+        let removed = subnet_change.removed();
+        let subnet_change = subnet_change.with_removal_reasons(&removed, RemovalReason::Optimization);
+
+        // Catch bugs in choosing the nodes before they end up in a proposal.
+        let expected_size = (self.subnet.nodes.len() + included_nodes_count + how_many_nodes_to_add)
+            .saturating_sub(how_many_nodes_to_remove);
+        let size = subnet_change.after().nodes.len();
+        if size != expected_size {
+            return Err(NetworkError::ResizeFailed(format!(
+                "Subnet {} would have {} nodes instead of the expected {}",
+                self.subnet.id, size, expected_size
+            )));
+        }
+        self.check_retained(&subnet_change)?;
+        self.check_fleet_diversity(&subnet_change)?;
         let node_add_count = subnet_change.added().len();
         let node_remove_count = subnet_change.removed().len();
         info!(
@@ -988,6 +1897,67 @@ impl SubnetChangeRequest {
         Ok(subnet_change)
     }
 
+    /// The change replacing the fewest nodes after which the subnet meets
+    /// the `target`, trying one more replaced node at a time. The nodes are
+    /// chosen as by [SubnetChangeRequest::optimize] with `target` as the
+    /// minimum Nakamoto coefficients, so a smaller change could exist that
+    /// the greedy selection misses. A number of replacements for which no
+    /// change can be computed, e.g. for lack of available nodes, does not
+    /// reach the target.
+    pub fn min_changes_for(&self, target: MinNakamotoCoefficients) -> Result<SubnetChange, NetworkError> {
+        let request = self.clone().with_min_nakamoto_coefficients(Some(target.clone()));
+        for replacements in 0..=self.subnet.nodes.len() {
+            let change = match request.clone().optimize(replacements, &vec![]) {
+                Ok(change) => change,
+                Err(NetworkError::PoolExhausted(e)) | Err(NetworkError::ResizeFailed(e)) => {
+                    debug!(
+                        "Replacing {} nodes of subnet {} fails: {}",
+                        replacements, self.subnet.id, e
+                    );
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            if change.after().nakamoto_score().meets(&target) {
+                return Ok(change);
+            }
+        }
+        Err(NetworkError::ResizeFailed(format!(
+            "Replacing up to all {} nodes of subnet {} does not reach the target {:?}",
+            self.subnet.nodes.len(),
+            self.subnet.id,
+            target
+        )))
+    }
+
+    /// The number of memberships a [SubnetChangeRequest::resize] adding and
+    /// removing the given numbers of nodes chooses from: the ways to pick the
+    /// added nodes from the pool of available nodes, times the ways to pick
+    /// the removed nodes from the extended subnet. Included nodes are always
+    /// added and are not part of the choice. The count saturates at
+    /// [u128::MAX].
+    ///
+    /// The numbers of nodes to add and remove are arguments rather than read
+    /// from the request because the request does not hold them, they are
+    /// only passed to [SubnetChangeRequest::resize]. Computing the pool of
+    /// available nodes can fail, hence the `Result`.
+    pub fn search_space_size(
+        &self,
+        how_many_nodes_to_add: usize,
+        how_many_nodes_to_remove: usize,
+    ) -> Result<u128, NetworkError> {
+        let available_nodes = self.available_nodes_outside_excluded_locations()?;
+        let included_nodes_count = available_nodes
+            .iter()
+            .filter(|n| self.include_nodes.contains(&n.id))
+            .count();
+        let pool_size = available_nodes.len() - included_nodes_count;
+        let extended_size = self.subnet.nodes.len() + included_nodes_count + how_many_nodes_to_add;
+        let additions = binomial(pool_size, how_many_nodes_to_add);
+        let removals = binomial(extended_size, how_many_nodes_to_remove);
+        Ok(additions.saturating_mul(removals))
+    }
+
     /// Evaluates the subnet change request to simulate the requested topology
     /// change. Command returns all the information about the subnet before
     /// and after the change.
@@ -996,6 +1966,89 @@ impl SubnetChangeRequest {
     }
 }
 
+/// The number of ways to choose `k` out of `n` items, saturating at
+/// [u128::MAX].
+fn binomial(n: usize, k: usize) -> u128 {
+    if k > n {
+        return 0;
+    }
+    let k = k.min(n - k) as u128;
+    let n = n as u128;
+    (0..k)
+        .try_fold(1u128, |acc, i| acc.checked_mul(n - i).map(|c| c / (i + 1)))
+        .unwrap_or(u128::MAX)
+}
+
+/// One subnet to optimize as part of [optimize_subnets].
+#[derive(Clone, Debug)]
+pub struct SubnetOptimizeRequest {
+    pub request: SubnetChangeRequest,
+    pub optimize_count: usize,
+    pub replacements_unhealthy: Vec<Node>,
+}
+
+/// Optimize several subnets whose change requests were built from the same
+/// pool of available nodes, without handing the same node to more than one
+/// subnet.
+///
+/// The requests are handled greedily in the given order, so earlier subnets
+/// get the first pick. A node added to a subnet is taken out of the pool for
+/// all the following ones, and nodes removed from a subnet are not added back
+/// since they only become available once the proposal is executed. A subnet
+/// for which no suitable node is left in the pool is returned unchanged, and
+/// so is a frozen subnet.
+pub fn optimize_subnets(requests: Vec<SubnetOptimizeRequest>) -> Result<Vec<SubnetChange>, NetworkError> {
+    let mut taken: Vec<PrincipalId> = Vec::new();
+    let mut changes = Vec::new();
+    for SubnetOptimizeRequest {
+        request,
+        optimize_count,
+        replacements_unhealthy,
+    } in requests
+    {
+        let available_nodes = request
+            .available_nodes
+            .iter()
+            .filter(|n| !taken.contains(&n.id))
+            .cloned()
+            .collect();
+        let request = request.with_custom_available_nodes(available_nodes);
+        let change = match request.clone().optimize(optimize_count, &replacements_unhealthy) {
+            Ok(change) => change,
+            Err(NetworkError::PoolExhausted(e)) => {
+                info!("Leaving subnet {} unchanged: {}", request.subnet.id, e);
+                SubnetChange {
+                    comment: Some("No suitable nodes left in the shared pool of available nodes".to_string()),
+                    ..request.evaluate()?
+                }
+            }
+            Err(NetworkError::SubnetFrozen(id)) => {
+                info!("Leaving subnet {} unchanged: subnet is frozen", id);
+                SubnetChange {
+                    comment: Some("Subnet is frozen".to_string()),
+                    ..request.evaluate()?
+                }
+            }
+            Err(e) => return Err(e),
+        };
+        taken.extend(change.added().iter().map(|n| n.id));
+        changes.push(change);
+    }
+    Ok(changes)
+}
+
+/// Why a node is removed from a subnet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RemovalReason {
+    /// The node is not healthy.
+    Unhealthy,
+    /// The node was explicitly asked to be replaced.
+    Requested,
+    /// Replacing the node improves the decentralization of the subnet.
+    Optimization,
+}
+
 #[derive(Debug, Clone)]
 pub struct SubnetChange {
     pub id: PrincipalId,
@@ -1004,9 +2057,31 @@ pub struct SubnetChange {
     pub min_nakamoto_coefficients: Option<MinNakamotoCoefficients>,
     pub comment: Option<String>,
     pub run_log: Vec<String>,
+    /// Why each of the removed nodes is removed, where known.
+    pub removal_reasons: BTreeMap<PrincipalId, RemovalReason>,
+    /// Whether the subnet may do without a DFINITY-owned node, as requested
+    /// when the change was computed.
+    pub skip_dfinity_node_requirement: bool,
 }
 
 impl SubnetChange {
+    /// Record `reason` as the reason of removal of the `nodes` that are
+    /// removed by the change, replacing the reasons recorded before.
+    pub fn with_removal_reasons(mut self, nodes: &[Node], reason: RemovalReason) -> Self {
+        for node in nodes.iter().filter(|n| !self.new_nodes.contains(n)) {
+            self.removal_reasons.insert(node.id, reason);
+        }
+        self
+    }
+
+    /// The removed nodes that are removed for `reason`.
+    pub fn removed_for(&self, reason: RemovalReason) -> Vec<Node> {
+        self.removed()
+            .into_iter()
+            .filter(|n| self.removal_reasons.get(&n.id) == Some(&reason))
+            .collect()
+    }
+
     pub fn with_nodes(self, nodes: Vec<Node>) -> Self {
         Self {
             new_nodes: [self.new_nodes, nodes].concat(),
@@ -1034,6 +2109,38 @@ impl SubnetChange {
             .collect()
     }
 
+    /// The change resulting in the better subnet, `a` if both are equally
+    /// good, and the reason it is better.
+    pub fn better_of<'a>(a: &'a SubnetChange, b: &'a SubnetChange) -> (&'a SubnetChange, String) {
+        let (score_a, score_b) = (a.after().nakamoto_score(), b.after().nakamoto_score());
+        match score_a.compare_explain(&score_b) {
+            (Some(Ordering::Greater), explanation) => {
+                (a, format!("The first change results in a better {}", explanation))
+            }
+            (Some(Ordering::Less), _) => (
+                b,
+                format!(
+                    "The second change results in a better {}",
+                    score_b.compare_explain(&score_a).1
+                ),
+            ),
+            _ => (a, "Both changes result in equally good subnets".to_string()),
+        }
+    }
+
+    /// How disruptive the change is, in terms of moved nodes and the
+    /// providers and countries they belong to.
+    pub fn cost(&self) -> ChangeCost {
+        let moved_nodes = self.added().into_iter().chain(self.removed()).collect::<Vec<_>>();
+        let distinct = |feature: &NodeFeature| moved_nodes.iter().map(|n| n.get_feature(feature)).unique().count();
+        ChangeCost {
+            nodes_added: self.added().len(),
+            nodes_removed: self.removed().len(),
+            providers_affected: distinct(&NodeFeature::NodeProvider),
+            countries_affected: distinct(&NodeFeature::Country),
+        }
+    }
+
     pub fn before(&self) -> DecentralizedSubnet {
         DecentralizedSubnet {
             id: self.id,
@@ -1042,6 +2149,11 @@ impl SubnetChange {
             min_nakamoto_coefficients: self.min_nakamoto_coefficients.clone(),
             comment: self.comment.clone(),
             run_log: Vec::new(),
+            seed: 0,
+            skip_dfinity_node_requirement: false,
+            budget: None,
+            decommission_policy: DecommissionPolicy::default(),
+            fleet_providers: None,
         }
     }
 
@@ -1058,12 +2170,17 @@ impl SubnetChange {
             min_nakamoto_coefficients: self.min_nakamoto_coefficients.clone(),
             comment: self.comment.clone(),
             run_log: self.run_log.clone(),
+            seed: 0,
+            skip_dfinity_node_requirement: false,
+            budget: None,
+            decommission_policy: DecommissionPolicy::default(),
+            fleet_providers: None,
         }
     }
 }
 
 impl Display for SubnetChange {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", SubnetChangeResponse::from(self))
+        write!(f, "{}", ChangePreview::from(self))
     }
 }
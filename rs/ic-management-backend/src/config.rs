@@ -1,4 +1,8 @@
+use ic_management_types::annotations::{parse_node_annotations, NodeAnnotations};
 use ic_management_types::Network;
+use ic_types::PrincipalId;
+use log::error;
+use std::collections::BTreeMap;
 use std::str::FromStr;
 use url::Url;
 
@@ -13,7 +17,10 @@ pub fn get_nns_url_string_from_target_network(target_network: &Network) -> Strin
         Err(_) => match target_network {
             Network::Mainnet => "https://ic0.app".to_string(),
             Network::Staging => "http://[2600:3004:1200:1200:5000:11ff:fe37:c55d]:8080".to_string(),
-            _ => panic!(
+            Network::Custom { nns_urls, .. } if !nns_urls.is_empty() => {
+                nns_urls.iter().map(|url| url.to_string()).collect::<Vec<_>>().join(",")
+            }
+            Network::Custom { .. } => panic!(
                 "Cannot get NNS URL for target network {}. Please set NNS_URL environment variable",
                 target_network
             ),
@@ -27,3 +34,52 @@ pub fn get_nns_url_vec_from_target_network(target_network: &Network) -> Vec<Url>
         .map(|s| Url::parse(s).unwrap_or_else(|_| panic!("Cannot parse {} as a valid NNS URL", s)))
         .collect()
 }
+
+/// Subnets that must not be changed, from the comma separated FROZEN_SUBNETS
+/// environment variable and the file at FROZEN_SUBNETS_FILE, one subnet per
+/// line. Both are read on every call, so that subnets can be frozen during an
+/// incident without a restart.
+pub fn frozen_subnets() -> Vec<PrincipalId> {
+    let mut entries = std::env::var("FROZEN_SUBNETS")
+        .map(|subnets| subnets.split(',').map(|s| s.to_string()).collect::<Vec<_>>())
+        .unwrap_or_default();
+    if let Ok(path) = std::env::var("FROZEN_SUBNETS_FILE") {
+        match std::fs::read_to_string(&path) {
+            Ok(content) => entries.extend(content.lines().map(|l| l.to_string())),
+            Err(e) => error!("Failed to read frozen subnets from {}: {}", path, e),
+        }
+    }
+    entries
+        .iter()
+        .map(|e| e.trim())
+        .filter(|e| !e.is_empty() && !e.starts_with('#'))
+        .filter_map(|e| match PrincipalId::from_str(e) {
+            Ok(subnet) => Some(subnet),
+            Err(err) => {
+                error!("Ignoring invalid frozen subnet {}: {}", e, err);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Annotations of nodes, from the JSON file at NODE_ANNOTATIONS_FILE mapping
+/// node ids to their annotations. Read whenever the nodes are updated, so
+/// that nodes can be annotated without a restart. An invalid file is ignored
+/// as a whole rather than leaving out some of the annotations.
+pub fn node_annotations() -> BTreeMap<PrincipalId, NodeAnnotations> {
+    let path = match std::env::var("NODE_ANNOTATIONS_FILE") {
+        Ok(path) => path,
+        Err(_) => return BTreeMap::new(),
+    };
+    match std::fs::read_to_string(&path)
+        .map_err(|e| e.to_string())
+        .and_then(|content| parse_node_annotations(&content))
+    {
+        Ok(annotations) => annotations,
+        Err(e) => {
+            error!("Failed to read node annotations from {}: {}", path, e);
+            BTreeMap::new()
+        }
+    }
+}
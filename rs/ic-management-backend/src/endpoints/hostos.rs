@@ -3,11 +3,14 @@ use ic_management_types::requests::HostosRolloutRequest;
 use super::*;
 use crate::hostos_rollout;
 
+/// Requests with unknown fields are rejected, e.g. a mistyped `exclude`
+/// would roll out to the nodes it was meant to exclude.
 #[post("/hostos/rollout_nodes")]
 async fn rollout_nodes(
-    request: web::Json<HostosRolloutRequest>,
+    body: web::Bytes,
     registry: web::Data<Arc<RwLock<RegistryState>>>,
 ) -> Result<HttpResponse, Error> {
+    let request = from_json::<HostosRolloutRequest>(&body, UnknownFieldPolicy::Reject)?.value;
     let registry = registry.read().await;
     let proposal_agent = proposal::ProposalAgent::new(registry.nns_url());
     let network = registry.network();
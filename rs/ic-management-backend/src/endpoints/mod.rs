@@ -6,13 +6,17 @@ pub mod release;
 pub mod subnet;
 
 use crate::{
-    config::get_nns_url_vec_from_target_network, gitlab_dfinity, health, prometheus, proposal, registry,
-    registry::RegistryState, release::list_subnets_release_statuses, release::RolloutBuilder,
+    config::get_nns_url_vec_from_target_network, gitlab_dfinity, health, idempotency::IdempotentResponses, prometheus,
+    proposal, registry, registry::RegistryState, release::list_subnets_release_statuses, release::RolloutBuilder,
 };
 use actix_web::dev::Service;
+use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue};
 use actix_web::{get, post, web, App, Error, HttpResponse, HttpServer, Responder, Result};
 use decentralization::network::AvailableNodesQuerier;
-use ic_management_types::Network;
+use ic_management_types::api_version::{ApiVersion, API_VERSION_HEADER};
+use ic_management_types::pagination::{paginate, PageRequest};
+use ic_management_types::unknown_fields::{add_warnings, from_json, Parsed, UnknownFieldPolicy};
+use ic_management_types::{ManagementError, Network};
 use ic_registry_nns_data_provider::registry::RegistryCanister;
 use ic_types::PrincipalId;
 use log::{debug, error, info};
@@ -63,6 +67,8 @@ pub async fn run_backend(
     }
 
     let num_workers = if run_from_cli { 1 } else { 8 };
+    // Shared by the workers, so that a retry is answered by any of them.
+    let idempotent_responses = web::Data::new(IdempotentResponses::default());
 
     let mut srv = HttpServer::new(move || {
         let network = target_network.clone();
@@ -71,11 +77,14 @@ pub async fn run_backend(
         let middleware_registry_state = registry_state.clone();
         App::new()
             .app_data(web::Data::new(registry_state.clone()))
+            .app_data(idempotent_responses.clone())
             .wrap_fn(move |req, srv| {
+                let api_version = check_api_version(req.headers());
                 let fut = srv.call(req);
                 let registry_state = middleware_registry_state.clone();
                 let network = network.clone();
                 async move {
+                    api_version?;
                     let nns_urls = get_nns_url_vec_from_target_network(&network);
                     let registry_canister = RegistryCanister::new(nns_urls.clone());
                     let registry_reader = registry_state.read().await;
@@ -87,20 +96,28 @@ pub async fn run_backend(
                     {
                         Err(actix_web::error::ErrorServiceUnavailable("version updating"))
                     } else {
-                        let res = fut.await?;
+                        let mut res = fut.await?;
+                        res.headers_mut().insert(
+                            HeaderName::from_static(API_VERSION_HEADER_NAME),
+                            HeaderValue::from_str(&ApiVersion::CURRENT.to_string()).expect("valid header value"),
+                        );
                         Ok(res)
                     }
                 }
             })
             .service(rollout)
+            .service(pending_proposals)
             .service(subnets_release)
             .service(version)
             .service(list_subnets)
             .service(nodes)
+            .service(nodes_page)
             .service(available_nodes)
             .service(missing_guests)
             .service(guests)
             .service(operators)
+            .service(operator_details)
+            .service(providers)
             .service(nodes_healths)
             .service(get_subnet)
             .service(self::subnet::pending_action)
@@ -109,6 +126,7 @@ pub async fn run_backend(
             .service(self::subnet::resize)
             .service(self::subnet::change_preview)
             .service(self::nodes_ops::remove)
+            .service(self::nodes_ops::decommission)
             .service(self::hostos::rollout_nodes)
             .service(self::query_decentralization::decentralization_subnet_query)
             .service(self::query_decentralization::decentralization_whatif_query)
@@ -116,6 +134,7 @@ pub async fn run_backend(
             .service(self::release::retireable)
             .service(self::release::blessed)
             .service(self::release::get_nns_replica_version)
+            .service(self::release::rollout_status)
             .service(self::governance_canister::governance_canister_version_endpoint)
     })
     .shutdown_timeout(10)
@@ -195,6 +214,24 @@ async fn rollout(registry: web::Data<Arc<RwLock<registry::RegistryState>>>) -> R
     response_from_result(service.build().await)
 }
 
+/// A page of the open and adopted proposals, in the order of their ids. See
+/// [ic_management_types::pagination] for the limits of the page size.
+#[get("/proposals/pending")]
+async fn pending_proposals(
+    registry: web::Data<Arc<RwLock<registry::RegistryState>>>,
+    web::Query(page): web::Query<PageRequest>,
+) -> Result<HttpResponse, Error> {
+    let proposal_agent = proposal::ProposalAgent::new(registry.read().await.nns_url());
+    let proposals = proposal_agent
+        .list_pending_proposals()
+        .await
+        .map_err(ManagementError::internal)?
+        .into_iter()
+        .map(|p| (p.id, p))
+        .collect::<BTreeMap<_, _>>();
+    Ok(HttpResponse::Ok().json(paginate(&proposals, &page)?))
+}
+
 #[get("/subnets/versions")]
 async fn subnets_release(registry: web::Data<Arc<RwLock<registry::RegistryState>>>) -> Result<HttpResponse, Error> {
     let registry = registry.read().await;
@@ -230,10 +267,25 @@ async fn nodes(registry: web::Data<Arc<RwLock<registry::RegistryState>>>) -> Res
     response_from_result(registry.nodes_with_proposals().await)
 }
 
+/// A page of the nodes, in the order of their principals. See
+/// [ic_management_types::pagination] for the limits of the page size.
+#[get("/nodes/page")]
+async fn nodes_page(
+    registry: web::Data<Arc<RwLock<registry::RegistryState>>>,
+    web::Query(page): web::Query<PageRequest>,
+) -> Result<HttpResponse, Error> {
+    let registry = registry.read().await;
+    let nodes = registry
+        .nodes_with_proposals()
+        .await
+        .map_err(ManagementError::internal)?;
+    Ok(HttpResponse::Ok().json(paginate(&nodes, &page)?))
+}
+
 #[get("/nodes/available")]
 async fn available_nodes(registry: web::Data<Arc<RwLock<registry::RegistryState>>>) -> Result<HttpResponse, Error> {
     let registry = registry.read().await;
-    response_from_result(registry.available_nodes().await)
+    Ok(HttpResponse::Ok().json(registry.available_nodes().await?))
 }
 
 #[get("/nodes/healths")]
@@ -249,7 +301,7 @@ async fn nodes_healths(registry: web::Data<Arc<RwLock<registry::RegistryState>>>
                     n.principal,
                     healths
                         .remove(&n.principal)
-                        .unwrap_or(ic_management_types::Status::Unknown),
+                        .unwrap_or(ic_management_types::HealthStatus::Unknown),
                 )
             })
             .collect::<BTreeMap<_, _>>()
@@ -271,15 +323,52 @@ async fn operators(registry: web::Data<Arc<RwLock<registry::RegistryState>>>) ->
     query_registry(registry, |r| r.operators()).await
 }
 
+#[get("/operators/details")]
+async fn operator_details(registry: web::Data<Arc<RwLock<registry::RegistryState>>>) -> Result<HttpResponse, Error> {
+    response_from_result(registry.read().await.operator_details())
+}
+
+#[get("/providers")]
+async fn providers(registry: web::Data<Arc<RwLock<registry::RegistryState>>>) -> impl Responder {
+    query_registry(registry, |r| r.providers()).await
+}
+
+/// [API_VERSION_HEADER] as the lowercase name headers are stored under.
+const API_VERSION_HEADER_NAME: &str = "x-dre-api-version";
+
+/// Refuse requests declaring an [ApiVersion] incompatible with the one of the
+/// backend. Requests declaring no version, e.g. of the dashboard, are served.
+fn check_api_version(headers: &HeaderMap) -> Result<(), ManagementError> {
+    let requested = match headers.get(API_VERSION_HEADER) {
+        Some(requested) => requested.to_str().unwrap_or_default(),
+        None => return Ok(()),
+    };
+    match ApiVersion::from_str(requested) {
+        Ok(version) if version.is_compatible(&ApiVersion::CURRENT) => Ok(()),
+        _ => Err(ManagementError::IncompatibleApiVersion {
+            requested: requested.to_string(),
+            supported: ApiVersion::CURRENT.compatible_range(),
+        }),
+    }
+}
+
+/// Reply with `result`, or with an internal [ManagementError].
 fn response_from_result<T: Serialize, E: std::fmt::Debug + std::fmt::Display + 'static>(
     result: Result<T, E>,
 ) -> Result<HttpResponse, Error> {
     match result {
         Ok(data) => Ok(HttpResponse::Ok().json(data)),
-        Err(e) => Err(actix_web::error::ErrorInternalServerError(e)),
+        Err(e) => Err(ManagementError::internal(e).into()),
     }
 }
 
+/// Reply with `data` and the `warnings` about the request, see
+/// [ic_management_types::unknown_fields].
+fn response_with_warnings<T: Serialize>(data: T, warnings: Vec<String>) -> Result<HttpResponse, Error> {
+    let data = serde_json::to_value(data).map_err(ManagementError::internal)?;
+    Ok(HttpResponse::Ok().json(add_warnings(data, warnings)))
+}
+
 async fn query_registry<T: Serialize>(
     registry: web::Data<Arc<RwLock<registry::RegistryState>>>,
     query: fn(&registry::RegistryState) -> T,
@@ -1,18 +1,25 @@
 use futures_util::future::try_join;
-use ic_management_types::requests::{NodeRemoval, NodeRemovalReason, NodesRemoveRequest, NodesRemoveResponse};
+use ic_management_types::requests::{
+    NodeRemoval, NodeRemovalImpact, NodeRemovalReason, NodeRemovalRequest, NodeRemovalResponse, NodesRemoveRequest,
+    NodesRemoveResponse,
+};
+use ic_management_types::score::ScoreSummary;
+use ic_management_types::validation::Validate;
+use ic_management_types::NetworkError;
 use itertools::Itertools;
 
 use super::*;
 use crate::health;
+use decentralization::nakamoto::NakamotoScore;
 use decentralization::network::Node as DecentralizationNode;
 
 /// Finds all nodes that need to be removed from the network either because
-/// they're offline or duplicated
+/// they're offline or duplicated. Requests with unknown fields are rejected,
+/// as a mistyped filter could remove nodes it was meant to keep.
 #[post("/nodes/remove")]
-async fn remove(
-    request: web::Json<NodesRemoveRequest>,
-    registry: web::Data<Arc<RwLock<RegistryState>>>,
-) -> Result<HttpResponse, Error> {
+async fn remove(body: web::Bytes, registry: web::Data<Arc<RwLock<RegistryState>>>) -> Result<HttpResponse, Error> {
+    let request = from_json::<NodesRemoveRequest>(&body, UnknownFieldPolicy::Reject)?.value;
+    let motivation = request.motivation.render_markdown();
     let registry = registry.read().await;
     let health_client = health::HealthClient::new(registry.network());
     let nodes_with_proposals = registry.nodes_with_proposals();
@@ -28,7 +35,7 @@ async fn remove(
                     .map(|n| {
                         let status = healths
                             .remove(&n.principal)
-                            .unwrap_or(ic_management_types::Status::Unknown);
+                            .unwrap_or(ic_management_types::HealthStatus::Unknown);
                         (n, status)
                     })
                     .filter(|(n, _)| n.proposal.is_none())
@@ -66,10 +73,10 @@ async fn remove(
                                 });
                             }
                             let should_remove_node = if request.remove_degraded {
-                                matches!(status, ic_management_types::Status::Dead)
-                                    || matches!(status, ic_management_types::Status::Degraded)
+                                matches!(status, ic_management_types::HealthStatus::Dead)
+                                    || matches!(status, ic_management_types::HealthStatus::Degraded)
                             } else {
-                                matches!(status, ic_management_types::Status::Dead)
+                                matches!(status, ic_management_types::HealthStatus::Dead)
                             };
                             if should_remove_node {
                                 return Some(NodeRemoval {
@@ -92,9 +99,7 @@ async fn remove(
                             | ic_management_types::requests::NodeRemovalReason::Unhealthy(_) => {
                                 "Removing unhealthy nodes from the network, for redeployment"
                             }
-                            ic_management_types::requests::NodeRemovalReason::MatchedFilter(_) => {
-                                request.motivation.as_str()
-                            }
+                            ic_management_types::requests::NodeRemovalReason::MatchedFilter(_) => motivation.as_str(),
                         })
                         .unique()
                         .map(|m| format!(" * {m}"))
@@ -104,3 +109,55 @@ async fn remove(
             }),
     )
 }
+
+/// Lists the subnets of the nodes to decommission and, if asked for, how
+/// removing each node affects the decentralization of its subnet. Requests
+/// with unknown fields are rejected.
+#[post("/nodes/decommission")]
+async fn decommission(
+    body: web::Bytes,
+    registry: web::Data<Arc<RwLock<RegistryState>>>,
+) -> Result<HttpResponse, Error> {
+    let request = from_json::<NodeRemovalRequest>(&body, UnknownFieldPolicy::Reject)?.value;
+    request.check()?;
+    let registry = registry.read().await;
+    let registry_nodes = registry.nodes();
+    let subnets = registry.subnets();
+
+    let nodes = request
+        .nodes
+        .iter()
+        .map(|node_id| {
+            let node = registry_nodes
+                .get(node_id)
+                .ok_or(NetworkError::NodeNotFound(*node_id))?;
+            let scores = node
+                .subnet_id
+                .and_then(|subnet_id| subnets.get(&subnet_id))
+                .filter(|_| request.check_subnet_impact)
+                .map(|subnet| {
+                    let subnet_nodes = subnet.nodes.iter().map(DecentralizationNode::from).collect::<Vec<_>>();
+                    let remaining_nodes = subnet_nodes
+                        .iter()
+                        .filter(|n| n.id != *node_id)
+                        .cloned()
+                        .collect::<Vec<_>>();
+                    (
+                        ScoreSummary::from(&NakamotoScore::new_from_nodes(&subnet_nodes)),
+                        ScoreSummary::from(&NakamotoScore::new_from_nodes(&remaining_nodes)),
+                    )
+                });
+            Ok(NodeRemovalImpact {
+                node_id: *node_id,
+                subnet_id: node.subnet_id,
+                score_before: scores.as_ref().map(|(before, _)| before).cloned(),
+                score_after: scores.map(|(_, after)| after),
+            })
+        })
+        .collect::<Result<Vec<_>, NetworkError>>()?;
+
+    Ok(HttpResponse::Ok().json(NodeRemovalResponse {
+        nodes,
+        motivation: format!("{}: {}", request.reason, request.motivation.render_markdown()),
+    }))
+}
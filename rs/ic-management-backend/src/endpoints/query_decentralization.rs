@@ -1,7 +1,8 @@
 use super::*;
 use decentralization::network::{DecentralizedSubnet, SubnetChange};
-use decentralization::SubnetChangeResponse;
 use ic_base_types::PrincipalId;
+use ic_management_types::preview::ChangePreview;
+use ic_management_types::score::ScoreSummary;
 use ic_management_types::MinNakamotoCoefficients;
 use serde::{Deserialize, Serialize};
 
@@ -15,7 +16,7 @@ struct DecentralizedSubnetResponse {
     id: PrincipalId,
     message: String,
     run_log: String,
-    nakamoto: decentralization::nakamoto::NakamotoScore,
+    nakamoto: ScoreSummary,
 }
 
 /// Get the decentralization coefficients for a subnet
@@ -70,6 +71,11 @@ async fn get_decentralization_analysis(
                 min_nakamoto_coefficients: min_nakamoto_coefficients.clone(),
                 comment: None,
                 run_log: Vec::new(),
+                seed: 0,
+                skip_dfinity_node_requirement: false,
+                budget: None,
+                decommission_policy: Default::default(),
+                fleet_providers: None,
             },
             None => DecentralizedSubnet {
                 id: PrincipalId::new_subnet_test_id(0),
@@ -78,6 +84,11 @@ async fn get_decentralization_analysis(
                 min_nakamoto_coefficients: min_nakamoto_coefficients.clone(),
                 comment: None,
                 run_log: Vec::new(),
+                seed: 0,
+                skip_dfinity_node_requirement: false,
+                budget: None,
+                decommission_policy: Default::default(),
+                fleet_providers: None,
             },
         })
         .unwrap_or_else(|| DecentralizedSubnet {
@@ -87,6 +98,11 @@ async fn get_decentralization_analysis(
             min_nakamoto_coefficients: min_nakamoto_coefficients.clone(),
             comment: None,
             run_log: Vec::new(),
+            seed: 0,
+            skip_dfinity_node_requirement: false,
+            budget: None,
+            decommission_policy: Default::default(),
+            fleet_providers: None,
         });
 
     let nodes_to_remove = node_ids_to_remove.map(|node_ids_to_remove| {
@@ -119,12 +135,14 @@ async fn get_decentralization_analysis(
         min_nakamoto_coefficients: updated_subnet.min_nakamoto_coefficients.clone(),
         comment: updated_subnet.comment.clone(),
         run_log: updated_subnet.run_log.clone(),
+        removal_reasons: Default::default(),
+        skip_dfinity_node_requirement: updated_subnet.skip_dfinity_node_requirement,
     };
 
     let response = DecentralizedSubnetResponse {
         id: subnet.unwrap_or_else(|| PrincipalId::new_subnet_test_id(0)),
-        message: format!("{}", SubnetChangeResponse::from(&subnet_change)),
-        nakamoto: updated_subnet.nakamoto_score(),
+        message: format!("{}", ChangePreview::from(&subnet_change)),
+        nakamoto: ScoreSummary::from(&updated_subnet.nakamoto_score()),
         run_log: subnet_change.run_log.join("\n"),
     };
     Ok(HttpResponse::Ok().json(&response))
@@ -1,4 +1,5 @@
 use super::*;
+use ic_management_types::versions::NetworkRolloutStatus;
 use ic_management_types::Artifact;
 use serde::Deserialize;
 
@@ -34,5 +35,21 @@ async fn blessed(
 #[get("/release/versions/nns")]
 async fn get_nns_replica_version(registry: web::Data<Arc<RwLock<RegistryState>>>) -> Result<HttpResponse, Error> {
     let registry = registry.read().await;
-    Ok(HttpResponse::Ok().json(registry.nns_replica_version().await))
+    response_from_result(registry.nns_replica_version().await)
+}
+
+/// Which subnets run which replica version, with the proposals that updated
+/// them.
+#[get("/release/rollout/status")]
+async fn rollout_status(registry: web::Data<Arc<RwLock<RegistryState>>>) -> Result<HttpResponse, Error> {
+    let registry = registry.read().await;
+    let proposal_agent = proposal::ProposalAgent::new(registry.nns_url());
+    let updates = proposal_agent
+        .list_update_subnet_version_proposals()
+        .await
+        .map_err(ManagementError::internal)?
+        .iter()
+        .filter_map(|proposal| proposal.executed_update())
+        .collect::<Vec<_>>();
+    response_from_result(NetworkRolloutStatus::new(registry.subnets().values(), &updates))
 }
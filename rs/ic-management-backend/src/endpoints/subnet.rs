@@ -1,12 +1,14 @@
 use super::*;
+use crate::idempotency::IdempotentResponses;
 use crate::{health, subnets::get_proposed_subnet_changes};
-use decentralization::network::{SubnetQueryBy, TopologyManager};
+use decentralization::network::{RemovalReason, SubnetQueryBy, TopologyManager};
 use ic_base_types::PrincipalId;
+use ic_management_types::motivation::ScoreSummaryDelta;
 use ic_management_types::requests::{
-    MembershipReplaceRequest, ReplaceTarget, SubnetCreateRequest, SubnetResizeRequest,
+    MembershipChangeResponse, MembershipReplaceRequest, ReplaceTarget, SubnetCreateRequest, SubnetResizeRequest,
 };
-use ic_management_types::Node;
-use log::warn;
+use ic_management_types::validation::{common_subnet, Validate};
+use ic_management_types::{ManagementError, Node};
 use serde::Deserialize;
 use std::collections::BTreeMap;
 
@@ -25,16 +27,16 @@ async fn pending_action(
             if let Some(subnet) = subnets.get(&request.subnet) {
                 Ok(HttpResponse::Ok().json(&subnet.proposal))
             } else {
-                Err(actix_web::error::ErrorNotFound(anyhow::format_err!(
-                    "subnet {} not found",
-                    request.subnet
-                )))
+                Err(ManagementError::SubnetNotFound {
+                    subnet_id: request.subnet,
+                }
+                .into())
             }
         }
-        Err(e) => Err(actix_web::error::ErrorInternalServerError(format!(
-            "failed to fetch subnets: {}",
-            e
-        ))),
+        Err(e) => Err(ManagementError::RegistryUnavailable {
+            details: format!("failed to fetch subnets: {}", e),
+        }
+        .into()),
     }
 }
 
@@ -45,19 +47,19 @@ async fn change_preview(
 ) -> Result<HttpResponse, Error> {
     match registry.read().await.subnets_with_proposals().await {
         Ok(subnets) => {
-            let subnet = subnets.get(&request.subnet).ok_or_else(|| {
-                actix_web::error::ErrorNotFound(anyhow::format_err!("subnet {} not found", request.subnet))
+            let subnet = subnets.get(&request.subnet).ok_or(ManagementError::SubnetNotFound {
+                subnet_id: request.subnet,
             })?;
             let registry_nodes: BTreeMap<PrincipalId, Node> = registry.read().await.nodes();
 
             get_proposed_subnet_changes(&registry_nodes, subnet)
-                .map_err(actix_web::error::ErrorBadRequest)
+                .map_err(|e| ManagementError::ConstraintUnsatisfiable { details: e.to_string() })
                 .map(|r| HttpResponse::Ok().json(r))
         }
-        Err(e) => Err(actix_web::error::ErrorInternalServerError(format!(
-            "failed to fetch subnets: {}",
-            e
-        ))),
+        Err(e) => Err(ManagementError::RegistryUnavailable {
+            details: format!("failed to fetch subnets: {}", e),
+        }
+        .into()),
     }
 }
 
@@ -68,13 +70,36 @@ async fn change_preview(
 ///    3. Explicitly add or remove nodes from the subnet specifying their
 /// Principals.
 ///
-/// All nodes in the request must belong to exactly one subnet.
+/// All nodes in the request must belong to exactly one subnet, the one the
+/// request names if it does.
+///
+/// A retried request with the same idempotency key gets the response of the
+/// first one, see [IdempotentResponses].
 #[post("/subnet/membership/replace")]
 async fn replace(
-    request: web::Json<MembershipReplaceRequest>,
+    body: web::Bytes,
     registry: web::Data<Arc<RwLock<RegistryState>>>,
+    responses: web::Data<IdempotentResponses>,
 ) -> Result<HttpResponse, Error> {
+    let Parsed {
+        value: request,
+        warnings,
+    } = from_json::<MembershipReplaceRequest>(&body, UnknownFieldPolicy::Warn)?;
+    request.check()?;
     let registry = registry.read().await;
+    let key = request.idempotency_key;
+    let response = responses
+        .get_or_compute(key, &body, || async move {
+            Ok::<_, Error>(replace_change(request, &registry).await?.with_idempotency_key(key))
+        })
+        .await?;
+    response_with_warnings(response, warnings)
+}
+
+async fn replace_change(
+    mut request: MembershipReplaceRequest,
+    registry: &RegistryState,
+) -> Result<MembershipChangeResponse, Error> {
     let all_nodes = registry.nodes();
 
     let mut motivations: Vec<String> = vec![];
@@ -85,23 +110,32 @@ async fn replace(
         ReplaceTarget::Subnet(subnet) => registry.modify_subnet_nodes(SubnetQueryBy::SubnetId(*subnet)).await?,
         ReplaceTarget::Nodes {
             nodes: nodes_to_replace,
-            motivation,
+            subnet,
+            ..
         } => {
-            motivations.push(motivation.clone());
-            let nodes_to_replace = nodes_to_replace
+            let node_subnets = nodes_to_replace
                 .iter()
-                .filter_map(|n| all_nodes.get(n))
-                .map(decentralization::network::Node::from)
-                .collect::<Vec<_>>();
-            registry
-                .modify_subnet_nodes(SubnetQueryBy::NodeList(nodes_to_replace))
-                .await?
+                .map(|n| {
+                    all_nodes
+                        .get(n)
+                        .map(|node| (*n, node.subnet_id))
+                        .ok_or(ManagementError::NodeNotFound { node_id: *n })
+                })
+                .collect::<Result<BTreeMap<_, _>, _>>()?;
+            let subnet_id = common_subnet(&node_subnets, *subnet)?;
+            registry.modify_subnet_nodes(SubnetQueryBy::SubnetId(subnet_id)).await?
         }
     }
-    .with_exclude_nodes(request.exclude.clone().unwrap_or_default())
+    .with_exclude_nodes(request.exclude_nodes.iter().map(|n| n.to_string()).collect())
+    .with_exclude_features(request.exclude_features.clone())
+    .with_exclude_annotated(request.exclude_annotated.clone())
     .with_only_nodes_that_have_features(request.only.clone())
     .with_include_nodes(request.include.clone().unwrap_or_default())
-    .with_min_nakamoto_coefficients(request.min_nakamoto_coefficients.clone());
+    .with_min_nakamoto_coefficients(request.min_nakamoto_coefficients.clone())
+    .with_seed(request.seed.unwrap_or_default())
+    .with_dfinity_node_requirement(request.require_dfinity_node.unwrap_or(true))
+    .with_min_retained(request.min_retained)
+    .with_fleet_providers_if(request.fleet_aware, || registry.fleet_providers());
 
     let mut replacements_unhealthy: Vec<decentralization::network::Node> = Vec::new();
     if request.heal {
@@ -110,25 +144,10 @@ async fn replace(
         let healths = health_client
             .subnet(subnet.id)
             .await
-            .map_err(|_| actix_web::error::ErrorInternalServerError("failed to fetch subnet health".to_string()))?;
-        let unhealthy: Vec<decentralization::network::Node> = subnet
-            .nodes
-            .into_iter()
-            .filter_map(|n| match healths.get(&n.id) {
-                Some(health) => {
-                    if *health == ic_management_types::Status::Healthy {
-                        None
-                    } else {
-                        info!("Node {} is {:?}", n.id, health);
-                        Some(n)
-                    }
-                }
-                None => {
-                    warn!("Node {} has no known health, assuming unhealthy", n.id);
-                    Some(n)
-                }
-            })
-            .collect::<Vec<_>>();
+            .map_err(|_| ManagementError::RegistryUnavailable {
+                details: "failed to fetch subnet health".to_string(),
+            })?;
+        let unhealthy = subnet.unhealthy_nodes(&healths);
 
         if !unhealthy.is_empty() {
             replacements_unhealthy.extend(unhealthy);
@@ -136,7 +155,7 @@ async fn replace(
     }
     let req_replace_nodes = if let ReplaceTarget::Nodes {
         nodes: req_replace_node_ids,
-        motivation: _,
+        ..
     } = &request.target
     {
         let req_replace_nodes = req_replace_node_ids
@@ -157,9 +176,14 @@ async fn replace(
     }
     // Optimize the requested number of nodes, and remove unhealthy nodes if there
     // are any
-    let replacements = replacements_unhealthy.into_iter().chain(req_replace_nodes).collect();
-    let change = change_request.optimize(request.optimize.unwrap_or(0), &replacements)?;
-    let num_optimized = change.removed().len() - replacements.len();
+    let replacements = replacements_unhealthy
+        .into_iter()
+        .chain(req_replace_nodes.clone())
+        .collect();
+    let change = change_request
+        .optimize(request.optimize.unwrap_or(0), &replacements)?
+        .with_removal_reasons(&req_replace_nodes, RemovalReason::Requested);
+    let num_optimized = change.removed_for(RemovalReason::Optimization).len();
     if num_optimized > 0 {
         let replace_target = if num_optimized == 1 { "node" } else { "nodes" };
         motivations.push(format!(
@@ -167,51 +191,82 @@ async fn replace(
         ));
     }
 
-    Ok(HttpResponse::Ok()
-        .json(decentralization::SubnetChangeResponse::from(&change).with_motivation(motivations.join("; "))))
+    let response = MembershipChangeResponse::from(&change);
+    // Structured motivations show how the change affects the subnet.
+    if let ReplaceTarget::Nodes { motivation, .. } = &mut request.target {
+        *motivation = std::mem::take(motivation).with_default_score_delta(ScoreSummaryDelta {
+            before: response.score_before.clone(),
+            after: response.score_after.clone(),
+        });
+        motivations.insert(0, request.target.motivation());
+    }
+
+    Ok(response.with_motivation(motivations.join("; ")))
 }
 
 /// Simulates creation of a new subnet
 #[post("/subnet/create")]
 async fn create_subnet(
     registry: web::Data<Arc<RwLock<RegistryState>>>,
-    request: web::Json<SubnetCreateRequest>,
+    responses: web::Data<IdempotentResponses>,
+    body: web::Bytes,
 ) -> Result<HttpResponse, Error> {
+    let Parsed {
+        value: request,
+        warnings,
+    } = from_json::<SubnetCreateRequest>(&body, UnknownFieldPolicy::Warn)?;
     let registry = registry.read().await;
     println!(
-        "Received a request to create a subnet of size {:?} and MinNakamotoCoefficients {}",
+        "Received a request to create a{} subnet of size {:?} and MinNakamotoCoefficients {}",
+        request
+            .subnet_kind
+            .as_ref()
+            .map(|k| format!(" {}", k))
+            .unwrap_or_default(),
         request.size,
         serde_json::to_string(&request.min_nakamoto_coefficients).unwrap()
     );
 
-    Ok(HttpResponse::Ok().json(decentralization::SubnetChangeResponse::from(
-        &registry
-            .create_subnet(
-                request.size,
-                request.min_nakamoto_coefficients.clone(),
-                request.include.clone().unwrap_or_default(),
-                request.exclude.clone().unwrap_or_default(),
-                request.only.clone().unwrap_or_default(),
+    let key = request.idempotency_key;
+    let response = responses
+        .get_or_compute(key, &body, || async {
+            Ok::<_, Error>(
+                MembershipChangeResponse::from(&registry.create_subnet(&request).await?).with_idempotency_key(key),
             )
-            .await?,
-    )))
+        })
+        .await?;
+    response_with_warnings(response, warnings)
 }
 
 /// Simulates resizing the subnet, i.e. adding or removing nodes to a subnet.
 #[post("/subnet/membership/resize")]
 async fn resize(
-    request: web::Json<SubnetResizeRequest>,
+    body: web::Bytes,
     registry: web::Data<Arc<RwLock<RegistryState>>>,
+    responses: web::Data<IdempotentResponses>,
 ) -> Result<HttpResponse, Error> {
+    let Parsed {
+        value: request,
+        warnings,
+    } = from_json::<SubnetResizeRequest>(&body, UnknownFieldPolicy::Warn)?;
+    request.check()?;
     let registry = registry.read().await;
 
-    let change = registry
-        .modify_subnet_nodes(SubnetQueryBy::SubnetId(request.subnet))
-        .await?
-        .with_exclude_nodes(request.exclude.clone().unwrap_or_default())
-        .with_include_nodes(request.include.clone().unwrap_or_default())
-        .with_only_nodes_that_have_features(request.only.clone().unwrap_or_default())
-        .resize(request.add, request.remove)?;
-
-    Ok(HttpResponse::Ok().json(decentralization::SubnetChangeResponse::from(&change)))
+    let key = request.idempotency_key;
+    let response = responses
+        .get_or_compute(key, &body, || async {
+            let change = registry
+                .modify_subnet_nodes(SubnetQueryBy::SubnetId(request.subnet))
+                .await?
+                .with_exclude_nodes(request.exclude.clone().unwrap_or_default())
+                .with_include_nodes(request.include.clone().unwrap_or_default())
+                .with_only_nodes_that_have_features(request.only.clone().unwrap_or_default())
+                .with_seed(request.seed.unwrap_or_default())
+                .with_dfinity_node_requirement(request.require_dfinity_node.unwrap_or(true))
+                .with_fleet_providers_if(request.fleet_aware, || registry.fleet_providers())
+                .resize(request.add, request.remove)?;
+            Ok::<_, Error>(MembershipChangeResponse::from(&change).with_idempotency_key(key))
+        })
+        .await?;
+    response_with_warnings(response, warnings)
 }
@@ -5,7 +5,7 @@ use std::{
 };
 
 use ic_base_types::PrincipalId;
-use ic_management_types::{Network, Status};
+use ic_management_types::{HealthStatus, Network};
 use prometheus_http_query::{Client, InstantVector, Selector};
 
 use crate::prometheus;
@@ -23,7 +23,7 @@ impl HealthClient {
         }
     }
 
-    pub async fn subnet(&self, subnet: PrincipalId) -> anyhow::Result<BTreeMap<PrincipalId, Status>> {
+    pub async fn subnet(&self, subnet: PrincipalId) -> anyhow::Result<BTreeMap<PrincipalId, HealthStatus>> {
         let query_up: InstantVector = Selector::new()
             .metric("up")
             .with("ic", &self.network.legacy_name())
@@ -57,12 +57,12 @@ impl HealthClient {
                     .map(|id| {
                         let status = if r.sample().value() == 1.0 {
                             if node_ids_with_alerts.contains(&id) {
-                                Status::Degraded
+                                HealthStatus::Degraded
                             } else {
-                                Status::Healthy
+                                HealthStatus::Healthy
                             }
                         } else {
-                            Status::Dead
+                            HealthStatus::Dead
                         };
                         (id, status)
                     })
@@ -70,7 +70,7 @@ impl HealthClient {
             .collect())
     }
 
-    pub async fn nodes(&self) -> anyhow::Result<BTreeMap<PrincipalId, Status>> {
+    pub async fn nodes(&self) -> anyhow::Result<BTreeMap<PrincipalId, HealthStatus>> {
         let query: InstantVector = InstantVector(format!(
             r#"ic_replica_orchestrator:health_state:bottomk_1{{ic="{network}"}}"#,
             network = self.network.legacy_name(),
@@ -80,8 +80,9 @@ impl HealthClient {
         Ok(results
             .iter()
             .filter_map(|r| {
-                let status = Status::from_str(r.metric().get("state").expect("all vectors should have a state label"))
-                    .expect("all vectors should have a valid label");
+                let status =
+                    HealthStatus::from_str(r.metric().get("state").expect("all vectors should have a state label"))
+                        .expect("all vectors should have a valid label");
                 r.metric()
                     .get("ic_node")
                     .map(|id| (PrincipalId::from_str(id).unwrap(), status))
@@ -4,7 +4,7 @@ use futures_util::future::try_join;
 use ic_base_types::{NodeId, PrincipalId};
 use ic_management_types::requests::{HostosRolloutReason, HostosRolloutResponse, HostosRolloutSubnetAffected};
 use ic_management_types::{
-    Network, Node, NodeAssignment, NodeGroup, NodeGroupUpdate, NodeOwner, Status, Subnet,
+    HealthStatus, Network, Node, NodeAssignment, NodeGroup, NodeGroupUpdate, NodeOwner, Subnet,
     UpdateNodesHostosVersionsProposal,
 };
 use log::{debug, info};
@@ -121,20 +121,23 @@ impl HostosRollout {
     async fn nodes_by_status(
         &self,
         nodes: Vec<Node>,
-        nodes_health: BTreeMap<PrincipalId, Status>,
-    ) -> BTreeMap<Status, Vec<Node>> {
+        nodes_health: BTreeMap<PrincipalId, HealthStatus>,
+    ) -> BTreeMap<HealthStatus, Vec<Node>> {
         let nodes_by_status = nodes
             .iter()
             .cloned()
             .map(|node| {
                 (
-                    nodes_health.get(&node.principal).cloned().unwrap_or(Status::Unknown),
+                    nodes_health
+                        .get(&node.principal)
+                        .cloned()
+                        .unwrap_or(HealthStatus::Unknown),
                     node,
                 )
             })
             .fold(
                 BTreeMap::new(),
-                |mut acc: BTreeMap<Status, Vec<Node>>, (status, node)| {
+                |mut acc: BTreeMap<HealthStatus, Vec<Node>>, (status, node)| {
                     acc.entry(status).or_default().push(node);
                     acc
                 },
@@ -227,7 +230,7 @@ impl HostosRollout {
 
     async fn candidates_selection(
         &self,
-        nodes_health: BTreeMap<PrincipalId, Status>,
+        nodes_health: BTreeMap<PrincipalId, HealthStatus>,
         nodes_with_open_proposals: Vec<UpdateNodesHostosVersionsProposal>,
         nodes_in_group: Vec<Node>,
     ) -> anyhow::Result<CandidatesSelection> {
@@ -235,7 +238,7 @@ impl HostosRollout {
         let nodes_by_status = self.nodes_by_status(nodes_in_group.clone(), nodes_health).await;
 
         info!("Selecting healthy candidate nodes");
-        let nodes_healthy = match nodes_by_status.get(&Status::Healthy) {
+        let nodes_healthy = match nodes_by_status.get(&HealthStatus::Healthy) {
             Some(nodes_by_status) => nodes_by_status.clone(),
             None => {
                 return Ok(CandidatesSelection::None(HostosRolloutReason::NoNodeHealthy));
@@ -278,7 +281,7 @@ impl HostosRollout {
     #[async_recursion]
     async fn with_nodes_health_and_open_proposals(
         &self,
-        nodes_health: BTreeMap<PrincipalId, Status>,
+        nodes_health: BTreeMap<PrincipalId, HealthStatus>,
         nodes_with_open_proposals: Vec<UpdateNodesHostosVersionsProposal>,
         update_group: NodeGroupUpdate,
     ) -> anyhow::Result<HostosRolloutResponse> {
@@ -427,8 +430,8 @@ pub mod test {
         let healthy_nodes = union
             .keys()
             .cloned()
-            .map(|principal| (principal, Status::Healthy))
-            .collect::<BTreeMap<PrincipalId, Status>>();
+            .map(|principal| (principal, HealthStatus::Healthy))
+            .collect::<BTreeMap<PrincipalId, HealthStatus>>();
 
         let open_proposals: Vec<UpdateNodesHostosVersionsProposal> = vec![];
 
@@ -551,6 +554,7 @@ pub mod test {
                 subnet_id,
                 hostos_version: hostos_version.clone(),
                 dfinity_owned: Some(dfinity_owned),
+                annotations: Default::default(),
             };
             n.insert(node.principal, node);
         }
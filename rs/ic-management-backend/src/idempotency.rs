@@ -0,0 +1,155 @@
+//! The responses to the requests with an idempotency key, so that a client
+//! retrying a request gets the response of the first attempt rather than
+//! having the change computed again, possibly differently.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use actix_web::Error;
+use ic_management_types::idempotency::{IdempotencyKey, IDEMPOTENCY_KEY_TTL};
+use ic_management_types::ManagementError;
+use serde::Serialize;
+use tokio::sync::OnceCell;
+
+struct Entry {
+    /// Hash of the body of the first request with the key.
+    body: u64,
+    received: Instant,
+    response: Arc<OnceCell<serde_json::Value>>,
+}
+
+#[derive(Default)]
+pub struct IdempotentResponses {
+    entries: Mutex<BTreeMap<IdempotencyKey, Entry>>,
+}
+
+impl IdempotentResponses {
+    /// The response to the request with `body` and `key`. Within
+    /// [IDEMPOTENCY_KEY_TTL] of the first request with the key, this is the
+    /// response to that request, and a retry arriving while it is still
+    /// computed waits for it. Failed requests are not remembered, so their
+    /// retries compute the response again. Requests without a key are always
+    /// computed.
+    pub async fn get_or_compute<T, F, Fut>(
+        &self,
+        key: Option<IdempotencyKey>,
+        body: &[u8],
+        compute: F,
+    ) -> Result<serde_json::Value, Error>
+    where
+        T: Serialize,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        let compute = || async move {
+            serde_json::to_value(compute().await?).map_err(|e| Error::from(ManagementError::internal(e)))
+        };
+        match key {
+            Some(key) => self
+                .response(key, body, Instant::now())?
+                .get_or_try_init(compute)
+                .await
+                .cloned(),
+            None => compute().await,
+        }
+    }
+
+    /// The response of the request with `key`, expiring the other entries.
+    fn response(
+        &self,
+        key: IdempotencyKey,
+        body: &[u8],
+        now: Instant,
+    ) -> Result<Arc<OnceCell<serde_json::Value>>, ManagementError> {
+        let mut hasher = DefaultHasher::new();
+        body.hash(&mut hasher);
+        let body = hasher.finish();
+
+        let mut entries = self.entries.lock().expect("idempotent responses lock poisoned");
+        entries.retain(|_, entry| now.duration_since(entry.received) < IDEMPOTENCY_KEY_TTL);
+        let entry = entries.entry(key).or_insert_with(|| Entry {
+            body,
+            received: now,
+            response: Default::default(),
+        });
+        if entry.body != body {
+            return Err(ManagementError::InvalidRequest {
+                details: format!("The idempotency key {} was already used for another request", key),
+            });
+        }
+        Ok(entry.response.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use serde_json::json;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn requests_with_the_same_key_are_computed_once() {
+        let responses = IdempotentResponses::default();
+        let computed = AtomicUsize::new(0);
+        let compute = || async { Ok::<_, Error>(json!({ "attempt": computed.fetch_add(1, Ordering::SeqCst) })) };
+        let key = Some(IdempotencyKey::from_u128(1));
+
+        let first = responses.get_or_compute(key, b"{}", compute).await.unwrap();
+        let retry = responses.get_or_compute(key, b"{}", compute).await.unwrap();
+        assert_eq!(first, json!({ "attempt": 0 }));
+        assert_eq!(retry, first);
+        // Requests without or with other keys are computed again.
+        let other = responses.get_or_compute(None, b"{}", compute).await.unwrap();
+        assert_eq!(other, json!({ "attempt": 1 }));
+        let other = responses
+            .get_or_compute(Some(IdempotencyKey::from_u128(2)), b"{}", compute)
+            .await
+            .unwrap();
+        assert_eq!(other, json!({ "attempt": 2 }));
+
+        // The key cannot be reused for another request.
+        assert!(responses.get_or_compute(key, b"{\"size\": 1}", compute).await.is_err());
+        assert_eq!(computed.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn failures_are_not_remembered() {
+        let responses = IdempotentResponses::default();
+        let key = Some(IdempotencyKey::from_u128(1));
+        let failure = responses
+            .get_or_compute(key, b"{}", || async {
+                Err::<serde_json::Value, Error>(ManagementError::internal("registry unavailable").into())
+            })
+            .await;
+        assert!(failure.is_err());
+        let retry = responses
+            .get_or_compute(key, b"{}", || async { Ok::<_, Error>(json!("done")) })
+            .await;
+        assert_eq!(retry.unwrap(), json!("done"));
+    }
+
+    #[test]
+    fn responses_expire_after_the_ttl() {
+        let responses = IdempotentResponses::default();
+        let key = IdempotencyKey::from_u128(1);
+        let start = Instant::now();
+        let response = responses.response(key, b"{}", start).unwrap();
+        response.set(json!("first")).unwrap();
+
+        let later = start + IDEMPOTENCY_KEY_TTL - Duration::from_secs(1);
+        assert_eq!(
+            responses.response(key, b"{}", later).unwrap().get(),
+            Some(&json!("first"))
+        );
+        // Expired, so the key can even be used for another request.
+        let expired = start + IDEMPOTENCY_KEY_TTL;
+        assert_eq!(responses.response(key, b"{\"size\": 1}", expired).unwrap().get(), None);
+    }
+}
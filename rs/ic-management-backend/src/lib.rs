@@ -5,6 +5,7 @@ pub mod git_ic_repo;
 pub mod gitlab_dfinity;
 pub mod health;
 pub mod hostos_rollout;
+pub mod idempotency;
 pub mod prometheus;
 pub mod proposal;
 pub mod public_dashboard;
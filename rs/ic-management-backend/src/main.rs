@@ -5,6 +5,7 @@ mod git_ic_repo;
 mod gitlab_dfinity;
 mod health;
 mod hostos_rollout;
+mod idempotency;
 mod prometheus;
 mod proposal;
 mod public_dashboard;
@@ -6,6 +6,9 @@ use candid::{Decode, Encode};
 use futures_util::future::try_join_all;
 use ic_agent::agent::http_transport::reqwest_transport::ReqwestHttpReplicaV2Transport;
 use ic_agent::Agent;
+use ic_management_types::proposals;
+use ic_management_types::timestamp::Timestamp;
+use ic_management_types::versions::SubnetVersionUpdate;
 use ic_management_types::{NnsFunctionProposal, TopologyChangePayload, TopologyChangeProposal};
 use ic_management_types::{
     UpdateElectedHostosVersionsProposal, UpdateElectedReplicaVersionsProposal, UpdateNodesHostosVersionsProposal,
@@ -74,6 +77,21 @@ pub struct SubnetUpdateProposal {
     pub payload: UpdateSubnetReplicaVersionPayload,
 }
 
+impl SubnetUpdateProposal {
+    /// The update of the subnet version, if the proposal was executed.
+    pub fn executed_update(&self) -> Option<SubnetVersionUpdate> {
+        if !self.info.executed {
+            return None;
+        }
+        Some(SubnetVersionUpdate {
+            proposal_id: self.info.id,
+            subnet: self.payload.subnet_id,
+            version: self.payload.replica_version_id.clone(),
+            executed_at: Timestamp::from_unix_seconds(self.info.executed_timestamp_seconds as i64)?,
+        })
+    }
+}
+
 impl ProposalAgent {
     pub fn new(url: String) -> Self {
         let agent = Agent::builder()
@@ -200,6 +218,17 @@ impl ProposalAgent {
             .collect::<Vec<_>>())
     }
 
+    /// The open and adopted proposals not yet executed, as tracked by the
+    /// tooling.
+    pub async fn list_pending_proposals(&self) -> Result<Vec<proposals::ProposalInfo>> {
+        Ok(self
+            .list_proposals(vec![ProposalStatus::Open, ProposalStatus::Adopted])
+            .await?
+            .iter()
+            .filter_map(|p| proposals::ProposalInfo::try_from(p).ok())
+            .collect())
+    }
+
     async fn list_proposals(&self, include_status: Vec<ProposalStatus>) -> Result<Vec<ProposalInfo>> {
         let mut proposals = vec![];
         loop {
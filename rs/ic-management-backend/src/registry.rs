@@ -4,16 +4,17 @@ use crate::git_ic_repo::IcRepo;
 use crate::proposal;
 use crate::public_dashboard::query_ic_dashboard_list;
 use async_trait::async_trait;
-use decentralization::network::{AvailableNodesQuerier, SubnetQuerier, SubnetQueryBy};
+use decentralization::network::{AvailableNodesQuerier, FleetProviderStats, SubnetQuerier, SubnetQueryBy};
 use futures::TryFutureExt;
 use gitlab::AsyncGitlab;
 use ic_base_types::NodeId;
 use ic_base_types::{RegistryVersion, SubnetId};
 use ic_interfaces_registry::{RegistryClient, RegistryValue, ZERO_REGISTRY_VERSION};
+use ic_management_types::versions::ReplicaVersion;
 use ic_management_types::{
     Artifact, ArtifactReleases, Datacenter, DatacenterOwner, Guest, Network, NetworkError, Node, NodeProviderDetails,
-    NodeProvidersResponse, Operator, Provider, Release, Subnet, SubnetMetadata, UpdateElectedHostosVersionsProposal,
-    UpdateElectedReplicaVersionsProposal,
+    NodeProvidersResponse, Operator, OperatorDetails, Provider, ProviderDetails, Release, Subnet, SubnetKind,
+    SubnetMetadata, UpdateElectedHostosVersionsProposal, UpdateElectedReplicaVersionsProposal,
 };
 use ic_protobuf::registry::crypto::v1::PublicKey;
 use ic_protobuf::registry::hostos_version::v1::HostosVersionRecord;
@@ -377,7 +378,7 @@ impl RegistryState {
                                             branch: release_branch.to_string(),
                                             commit_hash: commit_hash.clone(),
                                             previous_patch_release: None,
-                                            time: release_datetime,
+                                            time: release_datetime.into(),
                                         },
                                     );
                                     break;
@@ -478,6 +479,7 @@ impl RegistryState {
             .split(' ')
             .map(|dc| dc.to_string().to_lowercase())
             .collect::<HashSet<_>>();
+        let annotations = crate::config::node_annotations();
         self.nodes = node_entries
             .iter()
             // Skipping nodes without operator. This should only occur at version 1
@@ -550,6 +552,7 @@ impl RegistryState {
                                     Some(PrincipalId::from_str(p2).expect("invalid node principal id"))
                                 }
                             }),
+                        annotations: annotations.get(&principal).cloned().unwrap_or_default(),
                     },
                 )
             })
@@ -579,6 +582,7 @@ impl RegistryState {
                         nodes: subnet_nodes,
                         principal,
                         subnet_type,
+                        kind: SubnetKind::from_registry(principal, sr.subnet_type),
                         metadata: SubnetMetadata {
                             name: if let Some(name) = self.known_subnets.get(&principal) {
                                 name.clone()
@@ -766,21 +770,35 @@ impl RegistryState {
             .collect())
     }
 
-    pub async fn nns_replica_version(&self) -> Option<String> {
-        Some(
-            self.subnets()
-                .get(
-                    &PrincipalId::from_str("tdb26-jop6k-aogll-7ltgs-eruif-6kk7m-qpktf-gdiqx-mxtrf-vb5e6-eqe").unwrap(),
-                )?
-                .replica_version
-                .clone(),
-        )
+    pub async fn nns_replica_version(&self) -> Result<Option<ReplicaVersion>> {
+        self.subnets()
+            .get(&PrincipalId::from_str("tdb26-jop6k-aogll-7ltgs-eruif-6kk7m-qpktf-gdiqx-mxtrf-vb5e6-eqe").unwrap())
+            .map(|subnet| ReplicaVersion::from_str(&subnet.replica_version).map_err(|e| anyhow::anyhow!(e)))
+            .transpose()
     }
 
     pub fn operators(&self) -> BTreeMap<PrincipalId, Operator> {
         self.operators.clone()
     }
 
+    /// The node operators with the details of their registry records.
+    pub fn operator_details(&self) -> Result<BTreeMap<PrincipalId, OperatorDetails>> {
+        let operator_records: BTreeMap<String, NodeOperatorRecord> = self.local_registry.get_family_entries()?;
+        operator_records
+            .values()
+            .map(|or| {
+                OperatorDetails::try_from(or)
+                    .map(|details| (details.principal, details))
+                    .map_err(|e| anyhow::anyhow!("invalid operator record for data center {}: {}", or.dc_id, e))
+            })
+            .collect()
+    }
+
+    /// The node providers with the number of nodes they run.
+    pub fn providers(&self) -> BTreeMap<PrincipalId, ProviderDetails> {
+        ProviderDetails::from_nodes(self.nodes.values())
+    }
+
     pub fn guests(&self) -> Vec<Guest> {
         self.factsdb_guests.clone()
     }
@@ -842,7 +860,20 @@ impl RegistryState {
     }
 }
 
-impl decentralization::network::TopologyManager for RegistryState {}
+impl decentralization::network::TopologyManager for RegistryState {
+    fn frozen_subnets(&self) -> Vec<PrincipalId> {
+        crate::config::frozen_subnets()
+    }
+
+    fn fleet_providers(&self) -> FleetProviderStats {
+        let subnets = self
+            .subnets
+            .values()
+            .map(decentralization::network::DecentralizedSubnet::from)
+            .collect::<Vec<_>>();
+        FleetProviderStats::from_subnets(&subnets)
+    }
+}
 
 #[async_trait]
 impl SubnetQuerier for RegistryState {
@@ -858,6 +889,11 @@ impl SubnetQuerier for RegistryState {
                     min_nakamoto_coefficients: None,
                     comment: None,
                     run_log: Vec::new(),
+                    seed: 0,
+                    skip_dfinity_node_requirement: false,
+                    budget: None,
+                    decommission_policy: Default::default(),
+                    fleet_providers: None,
                 })
                 .ok_or(NetworkError::SubnetNotFound(id)),
             SubnetQueryBy::NodeList(nodes) => {
@@ -886,6 +922,11 @@ impl SubnetQuerier for RegistryState {
                         min_nakamoto_coefficients: None,
                         comment: None,
                         run_log: Vec::new(),
+                        seed: 0,
+                        skip_dfinity_node_requirement: false,
+                        budget: None,
+                        decommission_policy: Default::default(),
+                        fleet_providers: None,
                     })
                 } else {
                     Err(NetworkError::IllegalRequest("no subnet found".to_string()))
@@ -917,7 +958,7 @@ impl AvailableNodesQuerier for RegistryState {
                 // Keep only healthy nodes.
                 healths
                     .get(&n.principal)
-                    .map(|s| matches!(*s, ic_management_types::Status::Healthy))
+                    .map(|s| matches!(*s, ic_management_types::HealthStatus::Healthy))
                     .unwrap_or(false)
             })
             .filter(|n| {
@@ -1,13 +1,14 @@
 use std::collections::BTreeMap;
 
-use decentralization::{network::SubnetChange, SubnetChangeResponse};
+use decentralization::network::SubnetChange;
 use ic_base_types::PrincipalId;
+use ic_management_types::requests::MembershipChangeResponse;
 use ic_management_types::{Node, TopologyChangeProposal};
 
 pub fn get_proposed_subnet_changes(
     all_nodes: &BTreeMap<PrincipalId, Node>,
     subnet: &ic_management_types::Subnet,
-) -> Result<SubnetChangeResponse, anyhow::Error> {
+) -> Result<MembershipChangeResponse, anyhow::Error> {
     if let Some(proposal) = &subnet.proposal {
         let proposal: &TopologyChangeProposal = proposal;
         let change = SubnetChange {
@@ -17,6 +18,8 @@ pub fn get_proposed_subnet_changes(
             min_nakamoto_coefficients: None,
             comment: None,
             run_log: vec![],
+            removal_reasons: BTreeMap::new(),
+            skip_dfinity_node_requirement: false,
         }
         .with_nodes(
             proposal
@@ -32,9 +35,10 @@ pub fn get_proposed_subnet_changes(
                 .map(|p| decentralization::network::Node::from(all_nodes.get(p).unwrap()))
                 .collect::<Vec<_>>(),
         );
-        let mut response = SubnetChangeResponse::from(&change);
-        response.proposal_id = Some(proposal.id);
-        Ok(response)
+        Ok(MembershipChangeResponse {
+            proposal_id: Some(proposal.id),
+            ..MembershipChangeResponse::from(&change)
+        })
     } else {
         Err(anyhow::format_err!(
             "subnet {} does not have open membership change proposals",
@@ -48,6 +52,7 @@ pub fn get_proposed_subnet_changes(
 mod tests {
     use std::net::Ipv6Addr;
 
+    use ic_management_types::requests::NodeSummary;
     use ic_management_types::{Datacenter, DatacenterOwner, Operator, Provider};
 
     use super::*;
@@ -90,8 +95,9 @@ mod tests {
             ..Default::default()
         };
         let change = get_proposed_subnet_changes(&all_nodes, &subnet).unwrap();
-        assert_eq!(change.added, node_ids_added);
-        assert_eq!(change.removed, vec![]);
+        assert_eq!(principals(&change.added), node_ids_added);
+        assert!(change.removed.is_empty());
+        assert_eq!(change.proposal_id, Some(12345));
     }
 
     #[test]
@@ -114,8 +120,12 @@ mod tests {
             ..Default::default()
         };
         let change = get_proposed_subnet_changes(&all_nodes, &subnet).unwrap();
-        assert_eq!(change.added, node_ids_added);
-        assert_eq!(change.removed, node_ids_removed);
+        assert_eq!(principals(&change.added), node_ids_added);
+        assert_eq!(principals(&change.removed), node_ids_removed);
+    }
+
+    fn principals(nodes: &[NodeSummary]) -> Vec<PrincipalId> {
+        nodes.iter().map(|n| n.principal).collect()
     }
 
     fn gen_test_nodes(subnet_id: PrincipalId, num_nodes: u64, start_at_number: u64) -> BTreeMap<PrincipalId, Node> {
@@ -153,6 +163,7 @@ mod tests {
                 duplicates: None,
                 label: None,
                 hostos_version: "".to_string(),
+                annotations: Default::default(),
             };
             nodes.insert(node.principal, node);
         }
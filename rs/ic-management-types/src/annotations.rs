@@ -0,0 +1,198 @@
+//! Free-form annotations operations attach to nodes, e.g. `rma=pending`, to
+//! keep the tooling away from them. They are not part of the registry but
+//! maintained in a file mapping node ids to their annotations:
+//!
+//! ```json
+//! { "<node id>": { "rma": "pending", "note": "do not use for system subnets" } }
+//! ```
+//!
+//! The [`DECOMMISSION_AT`] annotation schedules the decommission of a node,
+//! e.g. `"decommission_at": "2024-06-30T00:00:00Z"`.
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::str::FromStr;
+
+use ic_types::PrincipalId;
+use serde::{Deserialize, Serialize};
+
+use crate::timestamp::Timestamp;
+
+/// The most annotations a node can have.
+pub const MAX_ANNOTATIONS: usize = 16;
+/// The longest key of an annotation.
+pub const MAX_ANNOTATION_KEY_LEN: usize = 32;
+/// The longest value of an annotation.
+pub const MAX_ANNOTATION_VALUE_LEN: usize = 256;
+/// The key of the annotation with the RFC 3339 timestamp at which the node
+/// is scheduled to be decommissioned.
+pub const DECOMMISSION_AT: &str = "decommission_at";
+
+/// The annotations of a node, by key. Keys match `[a-z][a-z0-9_]*`, so that
+/// they can be used in the names of Prometheus labels, and values are
+/// printable text. Deserializing checks both.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(try_from = "BTreeMap<String, String>")]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct NodeAnnotations(BTreeMap<String, String>);
+
+impl NodeAnnotations {
+    pub fn new(annotations: BTreeMap<String, String>) -> Result<Self, String> {
+        let annotations = Self(annotations);
+        annotations.validate()?;
+        Ok(annotations)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.0.len() > MAX_ANNOTATIONS {
+            return Err(format!(
+                "Too many annotations, {} of at most {}",
+                self.0.len(),
+                MAX_ANNOTATIONS
+            ));
+        }
+        for (key, value) in &self.0 {
+            if !is_valid_annotation_key(key) {
+                return Err(format!(
+                    "Invalid annotation key '{}', expected at most {} characters matching [a-z][a-z0-9_]*",
+                    key, MAX_ANNOTATION_KEY_LEN
+                ));
+            }
+            if value.chars().count() > MAX_ANNOTATION_VALUE_LEN || value.chars().any(char::is_control) {
+                return Err(format!(
+                    "Invalid value of annotation '{}', expected at most {} printable characters",
+                    key, MAX_ANNOTATION_VALUE_LEN
+                ));
+            }
+        }
+        if let Some(value) = self.get(DECOMMISSION_AT) {
+            value
+                .parse::<Timestamp>()
+                .map_err(|e| format!("Invalid value of annotation '{}': {}", DECOMMISSION_AT, e))?;
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(|v| v.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.0.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// When the node is scheduled to be decommissioned, if it is.
+    pub fn decommission_at(&self) -> Option<Timestamp> {
+        self.get(DECOMMISSION_AT).and_then(|value| value.parse().ok())
+    }
+
+    /// Whether the node has an annotation with any of the keys.
+    pub fn has_any(&self, keys: &[String]) -> bool {
+        keys.iter().any(|k| self.0.contains_key(k))
+    }
+}
+
+/// Whether `key` matches `[a-z][a-z0-9_]*` and is not too long.
+pub fn is_valid_annotation_key(key: &str) -> bool {
+    let mut chars = key.chars();
+    key.len() <= MAX_ANNOTATION_KEY_LEN
+        && chars.next().is_some_and(|c| c.is_ascii_lowercase())
+        && chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+impl TryFrom<BTreeMap<String, String>> for NodeAnnotations {
+    type Error = String;
+
+    fn try_from(annotations: BTreeMap<String, String>) -> Result<Self, Self::Error> {
+        Self::new(annotations)
+    }
+}
+
+impl Display for NodeAnnotations {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let annotations = self.0.iter().map(|(k, v)| format!("{}={}", k, v)).collect::<Vec<_>>();
+        write!(f, "{}", annotations.join(", "))
+    }
+}
+
+/// Parse the annotations of nodes as maintained in the annotations file.
+pub fn parse_node_annotations(content: &str) -> Result<BTreeMap<PrincipalId, NodeAnnotations>, String> {
+    serde_json::from_str::<BTreeMap<String, NodeAnnotations>>(content)
+        .map_err(|e| format!("Invalid node annotations: {}", e))?
+        .into_iter()
+        .map(|(node, annotations)| {
+            PrincipalId::from_str(&node)
+                .map(|node| (node, annotations))
+                .map_err(|e| format!("Invalid node id '{}' in the node annotations: {}", node, e))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn annotations(entries: &[(&str, &str)]) -> BTreeMap<String, String> {
+        entries.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn annotations_are_validated() {
+        let valid = NodeAnnotations::new(annotations(&[("rma", "pending"), ("note_2", "do not use")])).unwrap();
+        assert_eq!(valid.get("rma"), Some("pending"));
+        assert_eq!(valid.to_string(), "note_2=do not use, rma=pending");
+        assert!(valid.has_any(&["other".to_string(), "rma".to_string()]));
+        assert!(!valid.has_any(&[]));
+
+        for key in [
+            "",
+            "RMA",
+            "2rma",
+            "rma-pending",
+            "rma.pending",
+            "ärma",
+            "r".repeat(33).as_str(),
+        ] {
+            assert!(NodeAnnotations::new(annotations(&[(key, "")])).is_err(), "{}", key);
+        }
+        for value in ["line\nbreak", "v".repeat(257).as_str()] {
+            assert!(
+                NodeAnnotations::new(annotations(&[("rma", value)])).is_err(),
+                "{}",
+                value
+            );
+        }
+        let too_many = (0..=MAX_ANNOTATIONS)
+            .map(|i| (format!("k{}", i), String::new()))
+            .collect();
+        assert!(NodeAnnotations::new(too_many).is_err());
+    }
+
+    #[test]
+    fn annotations_file_is_parsed() {
+        let node = PrincipalId::new_node_test_id(1);
+        let content = json!({ node.to_string(): { "rma": "pending" } }).to_string();
+        let parsed = parse_node_annotations(&content).unwrap();
+        assert_eq!(parsed[&node].get("rma"), Some("pending"));
+
+        assert!(parse_node_annotations(&json!({ "not a node": {} }).to_string()).is_err());
+        assert!(parse_node_annotations(&json!({ node.to_string(): { "RMA": "" } }).to_string()).is_err());
+    }
+
+    #[test]
+    fn decommission_dates_are_annotated() {
+        let scheduled = NodeAnnotations::new(annotations(&[(DECOMMISSION_AT, "2024-06-30T00:00:00Z")])).unwrap();
+        assert_eq!(
+            scheduled.decommission_at(),
+            Some("2024-06-30T00:00:00.000Z".parse().unwrap())
+        );
+        assert_eq!(NodeAnnotations::default().decommission_at(), None);
+        assert!(NodeAnnotations::new(annotations(&[(DECOMMISSION_AT, "soon")])).is_err());
+    }
+}
@@ -0,0 +1,157 @@
+//! The version of the management API, which the CLI declares on its requests
+//! so that the backend can refuse requests it would misunderstand.
+
+use std::fmt::Display;
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// The header carrying the [ApiVersion] of requests and responses.
+pub const API_VERSION_HEADER: &str = "X-DRE-API-Version";
+
+/// A `<major>.<minor>` version of the management API. Minor versions only
+/// add to the API, e.g. optional fields, while major versions change or
+/// remove parts of it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ApiVersion {
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl ApiVersion {
+    /// The version this build of the API speaks.
+    pub const CURRENT: ApiVersion = ApiVersion { major: 1, minor: 0 };
+
+    pub const fn new(major: u32, minor: u32) -> Self {
+        Self { major, minor }
+    }
+
+    /// Whether a peer speaking this version understands one speaking
+    /// `other`, which is the case if both have the same major version.
+    /// Either side ignores the additions of a newer minor version.
+    pub fn is_compatible(&self, other: &ApiVersion) -> bool {
+        self.major == other.major
+    }
+
+    /// The versions compatible with this one, e.g. `>=1.0, <2.0`.
+    pub fn compatible_range(&self) -> String {
+        format!(">={}.0, <{}.0", self.major, self.major + 1)
+    }
+}
+
+impl Display for ApiVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+impl FromStr for ApiVersion {
+    type Err = String;
+
+    /// Parse `<major>.<minor>`, or just `<major>` for minor version 0.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid API version '{}', expected <major>.<minor>", s);
+        let (major, minor) = s.trim().split_once('.').unwrap_or((s.trim(), "0"));
+        Ok(Self {
+            major: major.parse().map_err(|_| invalid())?,
+            minor: minor.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+impl Serialize for ApiVersion {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for ApiVersion {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(feature = "schema")]
+impl schemars::JsonSchema for ApiVersion {
+    fn schema_name() -> String {
+        "ApiVersion".to_string()
+    }
+
+    fn json_schema(_: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        schemars::schema::SchemaObject {
+            instance_type: Some(schemars::schema::InstanceType::String.into()),
+            string: Some(Box::new(schemars::schema::StringValidation {
+                pattern: Some(r"^\d+(\.\d+)?$".to_string()),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
+/// A response along with the version of the API it was made with.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct VersionedResponse<T> {
+    pub api_version: ApiVersion,
+    pub data: T,
+}
+
+impl<T> VersionedResponse<T> {
+    pub fn new(data: T) -> Self {
+        Self {
+            api_version: ApiVersion::CURRENT,
+            data,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compatibility_matrix() {
+        let versions = [
+            ApiVersion::new(0, 9),
+            ApiVersion::new(1, 0),
+            ApiVersion::new(1, 3),
+            ApiVersion::new(2, 0),
+        ];
+        let expected = [
+            [true, false, false, false],
+            [false, true, true, false],
+            [false, true, true, false],
+            [false, false, false, true],
+        ];
+        for (a, row) in versions.iter().zip(expected) {
+            for (b, compatible) in versions.iter().zip(row) {
+                assert_eq!(a.is_compatible(b), compatible, "{} and {}", a, b);
+            }
+        }
+        assert_eq!(ApiVersion::new(1, 3).compatible_range(), ">=1.0, <2.0");
+    }
+
+    #[test]
+    fn versions_round_trip_through_headers() {
+        for version in [ApiVersion::CURRENT, ApiVersion::new(12, 34)] {
+            assert_eq!(version.to_string().parse::<ApiVersion>(), Ok(version));
+            let json = serde_json::to_string(&version).unwrap();
+            assert_eq!(json, format!("\"{}\"", version));
+            assert_eq!(serde_json::from_str::<ApiVersion>(&json).unwrap(), version);
+        }
+        assert_eq!(" 2 ".parse::<ApiVersion>(), Ok(ApiVersion::new(2, 0)));
+        for invalid in ["", "one", "1.x", "1.2.3", "-1.0"] {
+            assert!(invalid.parse::<ApiVersion>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn versioned_responses_carry_the_current_version() {
+        let response = serde_json::to_value(VersionedResponse::new(vec![1, 2])).unwrap();
+        assert_eq!(response, serde_json::json!({ "api_version": "1.0", "data": [1, 2] }));
+    }
+}
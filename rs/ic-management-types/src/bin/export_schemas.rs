@@ -0,0 +1,15 @@
+//! Write the JSON Schemas of the management API types to the directory given
+//! as argument, by default the `schemas` directory of this crate.
+
+use std::path::PathBuf;
+
+fn main() -> std::io::Result<()> {
+    let dir = std::env::args()
+        .nth(1)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("schemas"));
+    for path in ic_management_types::schema::export_schemas(&dir)? {
+        println!("{}", path.display());
+    }
+    Ok(())
+}
@@ -0,0 +1,628 @@
+//! Builders of the requests to the management API. Fields left out keep the
+//! defaults the backend applies to a missing field, and building a request
+//! checks it with [Validate], so that only valid requests are sent.
+//!
+//! ```
+//! use ic_base_types::PrincipalId;
+//! use ic_management_types::requests::MembershipReplaceRequest;
+//! use ic_management_types::NodeFeature;
+//!
+//! let request = MembershipReplaceRequest::builder()
+//!     .subnet(PrincipalId::new_subnet_test_id(1))
+//!     .heal()
+//!     .optimize(2)
+//!     .exclude_feature(NodeFeature::Country, "US")
+//!     .build()
+//!     .unwrap();
+//! assert!(request.heal);
+//! assert_eq!(request.optimize, Some(2));
+//! assert_eq!(request.exclude_features[0].to_string(), "country=US");
+//! ```
+//!
+//! Requests with issues are not built:
+//!
+//! ```
+//! use ic_base_types::PrincipalId;
+//! use ic_management_types::requests::MembershipReplaceRequest;
+//!
+//! let err = MembershipReplaceRequest::builder()
+//!     .subnet(PrincipalId::new_subnet_test_id(1))
+//!     .build()
+//!     .unwrap_err();
+//! assert_eq!(err.issues[0].field, "optimize");
+//! ```
+
+use ic_base_types::PrincipalId;
+
+use crate::motivation::MotivationInput;
+use crate::requests::{
+    DecommissionReason, FeatureMatch, MembershipReplaceRequest, NodeRemovalRequest, ReplaceTarget, SubnetCreateRequest,
+    SubnetResizeRequest,
+};
+use crate::validation::{InvalidRequest, Validate, ValidationIssue};
+use crate::{MinNakamotoCoefficients, NodeFeature, SubnetKind};
+
+impl MembershipReplaceRequest {
+    pub fn builder() -> MembershipReplaceRequestBuilder {
+        MembershipReplaceRequestBuilder::default()
+    }
+}
+
+/// Builds a [MembershipReplaceRequest]. The subnet or the nodes to replace
+/// must be given, and healing or optimizing the subnet asked for if the
+/// whole subnet is the target.
+#[derive(Default)]
+pub struct MembershipReplaceRequestBuilder {
+    target: Option<ReplaceTarget>,
+    heal: bool,
+    optimize: Option<usize>,
+    exclude_nodes: Vec<PrincipalId>,
+    exclude_features: Vec<FeatureMatch>,
+    exclude_annotated: Vec<String>,
+    only: Vec<String>,
+    include: Option<Vec<PrincipalId>>,
+    min_nakamoto_coefficients: Option<MinNakamotoCoefficients>,
+    seed: Option<u64>,
+    require_dfinity_node: Option<bool>,
+    min_retained: Option<usize>,
+    fleet_aware: bool,
+}
+
+impl MembershipReplaceRequestBuilder {
+    /// Replace nodes of the subnet, as asked for by [Self::heal] and
+    /// [Self::optimize].
+    pub fn subnet(self, subnet: PrincipalId) -> Self {
+        self.target(ReplaceTarget::Subnet(subnet))
+    }
+
+    /// Replace the nodes, all for the same reason.
+    pub fn nodes(self, nodes: Vec<PrincipalId>, motivation: impl Into<MotivationInput>) -> Self {
+        self.target(ReplaceTarget::nodes(nodes, motivation))
+    }
+
+    pub fn target(self, target: ReplaceTarget) -> Self {
+        Self {
+            target: Some(target),
+            ..self
+        }
+    }
+
+    /// Replace the unhealthy nodes of the subnet.
+    pub fn heal(self) -> Self {
+        self.heal_if(true)
+    }
+
+    pub fn heal_if(self, heal: bool) -> Self {
+        Self { heal, ..self }
+    }
+
+    /// Replace up to `optimize` nodes to improve the decentralization of the
+    /// subnet.
+    pub fn optimize(self, optimize: impl Into<Option<usize>>) -> Self {
+        Self {
+            optimize: optimize.into(),
+            ..self
+        }
+    }
+
+    pub fn exclude_node(self, node: PrincipalId) -> Self {
+        self.exclude_nodes([node])
+    }
+
+    pub fn exclude_nodes(mut self, nodes: impl IntoIterator<Item = PrincipalId>) -> Self {
+        self.exclude_nodes.extend(nodes);
+        self
+    }
+
+    /// Do not add nodes having the value of the feature, e.g. in a country.
+    pub fn exclude_feature(self, feature: NodeFeature, value: &str) -> Self {
+        self.exclude_features([FeatureMatch::new(feature, value)])
+    }
+
+    pub fn exclude_features(mut self, features: impl IntoIterator<Item = FeatureMatch>) -> Self {
+        self.exclude_features.extend(features);
+        self
+    }
+
+    /// Do not add nodes having an annotation with any of the keys.
+    pub fn exclude_annotated(mut self, keys: impl IntoIterator<Item = String>) -> Self {
+        self.exclude_annotated.extend(keys);
+        self
+    }
+
+    /// Only add nodes matching the node IDs or feature values.
+    pub fn only(mut self, only: impl IntoIterator<Item = String>) -> Self {
+        self.only.extend(only);
+        self
+    }
+
+    /// Add the nodes if the subnet gets any.
+    pub fn include(mut self, nodes: impl IntoIterator<Item = PrincipalId>) -> Self {
+        self.include.get_or_insert_with(Vec::new).extend(nodes);
+        self
+    }
+
+    pub fn min_nakamoto(self, min_nakamoto_coefficients: impl Into<Option<MinNakamotoCoefficients>>) -> Self {
+        Self {
+            min_nakamoto_coefficients: min_nakamoto_coefficients.into(),
+            ..self
+        }
+    }
+
+    pub fn seed(self, seed: impl Into<Option<u64>>) -> Self {
+        Self {
+            seed: seed.into(),
+            ..self
+        }
+    }
+
+    pub fn require_dfinity_node(self, require_dfinity_node: bool) -> Self {
+        Self {
+            require_dfinity_node: Some(require_dfinity_node),
+            ..self
+        }
+    }
+
+    pub fn min_retained(self, min_retained: impl Into<Option<usize>>) -> Self {
+        Self {
+            min_retained: min_retained.into(),
+            ..self
+        }
+    }
+
+    /// Take the nodes the providers have across the network into account.
+    pub fn fleet_aware(self) -> Self {
+        Self {
+            fleet_aware: true,
+            ..self
+        }
+    }
+
+    pub fn build(self) -> Result<MembershipReplaceRequest, InvalidRequest> {
+        let target = self.target.ok_or_else(|| InvalidRequest {
+            issues: vec![ValidationIssue::new("target", "No subnet or nodes to replace")],
+        })?;
+        MembershipReplaceRequest {
+            target,
+            heal: self.heal,
+            optimize: self.optimize,
+            exclude_nodes: self.exclude_nodes,
+            exclude_features: self.exclude_features,
+            exclude_annotated: self.exclude_annotated,
+            only: self.only,
+            include: self.include,
+            min_nakamoto_coefficients: self.min_nakamoto_coefficients,
+            seed: self.seed,
+            require_dfinity_node: self.require_dfinity_node,
+            min_retained: self.min_retained,
+            fleet_aware: self.fleet_aware,
+            idempotency_key: None,
+        }
+        .validated()
+    }
+}
+
+impl SubnetCreateRequest {
+    pub fn builder(size: usize) -> SubnetCreateRequestBuilder {
+        SubnetCreateRequestBuilder {
+            size,
+            min_nakamoto_coefficients: None,
+            exclude: None,
+            only: None,
+            include: None,
+            seed: None,
+            require_dfinity_node: None,
+            replica_version: None,
+            subnet_kind: None,
+            fleet_aware: false,
+        }
+    }
+}
+
+/// Builds a [SubnetCreateRequest] for a subnet of a given size.
+pub struct SubnetCreateRequestBuilder {
+    size: usize,
+    min_nakamoto_coefficients: Option<MinNakamotoCoefficients>,
+    exclude: Option<Vec<String>>,
+    only: Option<Vec<String>>,
+    include: Option<Vec<PrincipalId>>,
+    seed: Option<u64>,
+    require_dfinity_node: Option<bool>,
+    replica_version: Option<String>,
+    subnet_kind: Option<SubnetKind>,
+    fleet_aware: bool,
+}
+
+impl SubnetCreateRequestBuilder {
+    pub fn min_nakamoto(self, min_nakamoto_coefficients: impl Into<Option<MinNakamotoCoefficients>>) -> Self {
+        Self {
+            min_nakamoto_coefficients: min_nakamoto_coefficients.into(),
+            ..self
+        }
+    }
+
+    /// Do not choose nodes matching the node IDs or feature values.
+    pub fn exclude(mut self, exclude: impl IntoIterator<Item = String>) -> Self {
+        self.exclude.get_or_insert_with(Vec::new).extend(exclude);
+        self
+    }
+
+    /// Only choose nodes matching the node IDs or feature values.
+    pub fn only(mut self, only: impl IntoIterator<Item = String>) -> Self {
+        self.only.get_or_insert_with(Vec::new).extend(only);
+        self
+    }
+
+    pub fn include(mut self, nodes: impl IntoIterator<Item = PrincipalId>) -> Self {
+        self.include.get_or_insert_with(Vec::new).extend(nodes);
+        self
+    }
+
+    pub fn seed(self, seed: impl Into<Option<u64>>) -> Self {
+        Self {
+            seed: seed.into(),
+            ..self
+        }
+    }
+
+    pub fn require_dfinity_node(self, require_dfinity_node: bool) -> Self {
+        Self {
+            require_dfinity_node: Some(require_dfinity_node),
+            ..self
+        }
+    }
+
+    pub fn replica_version(self, replica_version: impl Into<Option<String>>) -> Self {
+        Self {
+            replica_version: replica_version.into(),
+            ..self
+        }
+    }
+
+    pub fn subnet_kind(self, subnet_kind: impl Into<Option<SubnetKind>>) -> Self {
+        Self {
+            subnet_kind: subnet_kind.into(),
+            ..self
+        }
+    }
+
+    /// Take the nodes the providers have across the network into account.
+    pub fn fleet_aware(self) -> Self {
+        Self {
+            fleet_aware: true,
+            ..self
+        }
+    }
+
+    pub fn build(self) -> Result<SubnetCreateRequest, InvalidRequest> {
+        SubnetCreateRequest {
+            size: self.size,
+            min_nakamoto_coefficients: self.min_nakamoto_coefficients,
+            exclude: self.exclude,
+            only: self.only,
+            include: self.include,
+            seed: self.seed,
+            require_dfinity_node: self.require_dfinity_node,
+            replica_version: self.replica_version,
+            subnet_kind: self.subnet_kind,
+            fleet_aware: self.fleet_aware,
+            idempotency_key: None,
+        }
+        .validated()
+    }
+}
+
+impl SubnetResizeRequest {
+    pub fn builder(subnet: PrincipalId) -> SubnetResizeRequestBuilder {
+        SubnetResizeRequestBuilder {
+            subnet,
+            add: 0,
+            remove: 0,
+            exclude: None,
+            only: None,
+            include: None,
+            seed: None,
+            require_dfinity_node: None,
+            fleet_aware: false,
+        }
+    }
+}
+
+/// Builds a [SubnetResizeRequest] of a subnet, which must add or remove at
+/// least one node.
+pub struct SubnetResizeRequestBuilder {
+    subnet: PrincipalId,
+    add: usize,
+    remove: usize,
+    exclude: Option<Vec<String>>,
+    only: Option<Vec<String>>,
+    include: Option<Vec<PrincipalId>>,
+    seed: Option<u64>,
+    require_dfinity_node: Option<bool>,
+    fleet_aware: bool,
+}
+
+impl SubnetResizeRequestBuilder {
+    pub fn add(self, add: usize) -> Self {
+        Self { add, ..self }
+    }
+
+    pub fn remove(self, remove: usize) -> Self {
+        Self { remove, ..self }
+    }
+
+    /// Do not add nodes matching the node IDs or feature values.
+    pub fn exclude(mut self, exclude: impl IntoIterator<Item = String>) -> Self {
+        self.exclude.get_or_insert_with(Vec::new).extend(exclude);
+        self
+    }
+
+    /// Only add nodes matching the node IDs or feature values.
+    pub fn only(mut self, only: impl IntoIterator<Item = String>) -> Self {
+        self.only.get_or_insert_with(Vec::new).extend(only);
+        self
+    }
+
+    pub fn include(mut self, nodes: impl IntoIterator<Item = PrincipalId>) -> Self {
+        self.include.get_or_insert_with(Vec::new).extend(nodes);
+        self
+    }
+
+    pub fn seed(self, seed: impl Into<Option<u64>>) -> Self {
+        Self {
+            seed: seed.into(),
+            ..self
+        }
+    }
+
+    pub fn require_dfinity_node(self, require_dfinity_node: bool) -> Self {
+        Self {
+            require_dfinity_node: Some(require_dfinity_node),
+            ..self
+        }
+    }
+
+    /// Take the nodes the providers have across the network into account.
+    pub fn fleet_aware(self) -> Self {
+        Self {
+            fleet_aware: true,
+            ..self
+        }
+    }
+
+    pub fn build(self) -> Result<SubnetResizeRequest, InvalidRequest> {
+        SubnetResizeRequest {
+            subnet: self.subnet,
+            add: self.add,
+            remove: self.remove,
+            exclude: self.exclude,
+            only: self.only,
+            include: self.include,
+            seed: self.seed,
+            require_dfinity_node: self.require_dfinity_node,
+            fleet_aware: self.fleet_aware,
+            idempotency_key: None,
+        }
+        .validated()
+    }
+}
+
+impl NodeRemovalRequest {
+    pub fn builder(reason: DecommissionReason) -> NodeRemovalRequestBuilder {
+        NodeRemovalRequestBuilder {
+            nodes: vec![],
+            reason,
+            motivation: MotivationInput::default(),
+            check_subnet_impact: false,
+        }
+    }
+}
+
+/// Builds a [NodeRemovalRequest], which needs nodes and a motivation.
+pub struct NodeRemovalRequestBuilder {
+    nodes: Vec<PrincipalId>,
+    reason: DecommissionReason,
+    motivation: MotivationInput,
+    check_subnet_impact: bool,
+}
+
+impl NodeRemovalRequestBuilder {
+    pub fn node(self, node: PrincipalId) -> Self {
+        self.nodes([node])
+    }
+
+    pub fn nodes(mut self, nodes: impl IntoIterator<Item = PrincipalId>) -> Self {
+        self.nodes.extend(nodes);
+        self
+    }
+
+    pub fn motivation(self, motivation: impl Into<MotivationInput>) -> Self {
+        Self {
+            motivation: motivation.into(),
+            ..self
+        }
+    }
+
+    /// Score the subnets of the nodes without them.
+    pub fn check_subnet_impact(self) -> Self {
+        self.check_subnet_impact_if(true)
+    }
+
+    pub fn check_subnet_impact_if(self, check_subnet_impact: bool) -> Self {
+        Self {
+            check_subnet_impact,
+            ..self
+        }
+    }
+
+    pub fn build(self) -> Result<NodeRemovalRequest, InvalidRequest> {
+        NodeRemovalRequest {
+            nodes: self.nodes,
+            reason: self.reason,
+            motivation: self.motivation,
+            check_subnet_impact: self.check_subnet_impact,
+        }
+        .validated()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::NetworkError;
+
+    use super::*;
+
+    fn node(id: u64) -> PrincipalId {
+        PrincipalId::new_node_test_id(id)
+    }
+
+    fn subnet() -> PrincipalId {
+        PrincipalId::new_subnet_test_id(1)
+    }
+
+    fn fields(err: InvalidRequest) -> Vec<String> {
+        err.issues.into_iter().map(|i| i.field).collect()
+    }
+
+    #[test]
+    fn replace_requests_are_built_with_defaults() {
+        let request = MembershipReplaceRequest::builder()
+            .nodes(vec![node(1)], "Dead node")
+            .build()
+            .unwrap();
+        assert!(matches!(&request.target, ReplaceTarget::Nodes { nodes, motivation, .. }
+            if nodes == &[node(1)] && *motivation == MotivationInput::from("Dead node")));
+        assert!(!request.heal);
+        assert_eq!(request.optimize, None);
+        assert!(request.exclude_nodes.is_empty() && request.exclude_features.is_empty());
+        assert!(request.exclude_annotated.is_empty());
+        assert!(request.only.is_empty());
+        assert_eq!(request.include, None);
+        assert_eq!(request.min_nakamoto_coefficients, None);
+        assert_eq!(
+            (request.seed, request.require_dfinity_node, request.min_retained),
+            (None, None, None)
+        );
+
+        let request = MembershipReplaceRequest::builder()
+            .subnet(subnet())
+            .heal()
+            .optimize(None)
+            .exclude_node(node(2))
+            .exclude_feature(NodeFeature::Country, "US")
+            .exclude_annotated(["rma".to_string()])
+            .include([node(3)])
+            .include([node(4)])
+            .min_nakamoto(MinNakamotoCoefficients::default())
+            .seed(7)
+            .require_dfinity_node(false)
+            .min_retained(10)
+            .build()
+            .unwrap();
+        assert!(matches!(request.target, ReplaceTarget::Subnet(s) if s == subnet()));
+        assert!(request.heal);
+        assert_eq!(request.exclude_nodes, vec![node(2)]);
+        assert_eq!(
+            request.exclude_features,
+            vec![FeatureMatch::new(NodeFeature::Country, "US")]
+        );
+        assert_eq!(request.exclude_annotated, vec!["rma"]);
+        assert_eq!(request.include, Some(vec![node(3), node(4)]));
+        assert_eq!(
+            request.min_nakamoto_coefficients,
+            Some(MinNakamotoCoefficients::default())
+        );
+        assert_eq!(request.seed, Some(7));
+        assert_eq!(request.require_dfinity_node, Some(false));
+        assert_eq!(request.min_retained, Some(10));
+    }
+
+    #[test]
+    fn invalid_replace_requests_are_not_built() {
+        let err = MembershipReplaceRequest::builder().heal().build().unwrap_err();
+        assert_eq!(fields(err), vec!["target"]);
+
+        let err = MembershipReplaceRequest::builder()
+            .subnet(subnet())
+            .optimize(0)
+            .build()
+            .unwrap_err();
+        assert_eq!(fields(err), vec!["optimize"]);
+        assert!(MembershipReplaceRequest::builder()
+            .subnet(subnet())
+            .heal_if(false)
+            .optimize(2)
+            .build()
+            .is_ok());
+
+        let err = MembershipReplaceRequest::builder()
+            .nodes(vec![node(1), node(1)], " ")
+            .include([node(2)])
+            .exclude_node(node(2))
+            .build()
+            .unwrap_err();
+        assert_eq!(fields(err), vec!["target.nodes", "target.motivation", "include"]);
+    }
+
+    #[test]
+    fn subnet_requests_are_built() {
+        let request = SubnetCreateRequest::builder(13)
+            .exclude(["CH".to_string()])
+            .include([node(1)])
+            .seed(None)
+            .replica_version("0123456789abcdef".to_string())
+            .subnet_kind(SubnetKind::European)
+            .build()
+            .unwrap();
+        assert_eq!(request.exclude, Some(vec!["CH".to_string()]));
+        assert_eq!(request.only, None);
+        assert_eq!(request.include, Some(vec![node(1)]));
+        assert_eq!(request.replica_version.as_deref(), Some("0123456789abcdef"));
+        assert_eq!(request.subnet_kind, Some(SubnetKind::European));
+
+        let err = SubnetCreateRequest::builder(0)
+            .subnet_kind(SubnetKind::Other("cloud_engine".to_string()))
+            .build()
+            .unwrap_err();
+        assert_eq!(fields(err), vec!["size", "subnet_kind"]);
+
+        let request = SubnetResizeRequest::builder(subnet()).add(2).remove(1).build().unwrap();
+        assert_eq!((request.subnet, request.add, request.remove), (subnet(), 2, 1));
+        assert_eq!(request.exclude, None);
+        let err = SubnetResizeRequest::builder(subnet()).build().unwrap_err();
+        assert_eq!(fields(err), vec!["add"]);
+        let err = SubnetResizeRequest::builder(subnet())
+            .add(1)
+            .include([node(1), node(2)])
+            .build()
+            .unwrap_err();
+        assert_eq!(fields(err), vec!["include"]);
+    }
+
+    #[test]
+    fn removal_requests_are_built() {
+        let request = NodeRemovalRequest::builder(DecommissionReason::Broken)
+            .node(node(1))
+            .nodes([node(2)])
+            .motivation("Broken disks")
+            .check_subnet_impact()
+            .build()
+            .unwrap();
+        assert_eq!(request.nodes, vec![node(1), node(2)]);
+        assert_eq!(request.reason, DecommissionReason::Broken);
+        assert!(request.check_subnet_impact);
+
+        let err = NodeRemovalRequest::builder(DecommissionReason::Offboarding)
+            .build()
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid request: nodes: No nodes given; motivation: The motivation must not be empty"
+        );
+        assert!(matches!(
+            NetworkError::from(err),
+            NetworkError::IllegalRequest(message)
+                if message == "nodes: No nodes given; motivation: The motivation must not be empty"
+        ));
+    }
+}
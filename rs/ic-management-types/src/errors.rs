@@ -2,34 +2,29 @@ use actix_web::http::StatusCode;
 use actix_web::{HttpResponse, ResponseError};
 use ic_types::PrincipalId;
 use serde::{Deserialize, Serialize};
-use std::fmt::Debug;
+use std::collections::BTreeMap;
+use std::fmt::{Debug, Display};
 
 #[derive(Serialize, Deserialize, Clone, Debug, strum_macros::Display)]
 pub enum NetworkError {
     NodeNotFound(PrincipalId),
     SubnetNotFound(PrincipalId),
     ResizeFailed(String),
+    /// Not enough of the available nodes are left to choose from.
+    PoolExhausted(String),
     DataRequestError(String),
     IllegalRequest(String),
+    #[strum(to_string = "subnet is frozen")]
+    SubnetFrozen(PrincipalId),
 }
 
 impl ResponseError for NetworkError {
     fn error_response(&self) -> HttpResponse {
-        match self {
-            NetworkError::IllegalRequest(_input) => HttpResponse::build(StatusCode::BAD_REQUEST).json(self),
-            NetworkError::ResizeFailed(_) => HttpResponse::InternalServerError().json(self),
-            NetworkError::DataRequestError(_) => HttpResponse::build(StatusCode::FAILED_DEPENDENCY).json(self),
-            NetworkError::SubnetNotFound(_) | NetworkError::NodeNotFound(_) => HttpResponse::NotFound().json(self),
-        }
+        ManagementError::from(self.clone()).error_response()
     }
 
     fn status_code(&self) -> StatusCode {
-        match self {
-            Self::NodeNotFound(_) | Self::SubnetNotFound(_) => StatusCode::NOT_FOUND,
-            Self::IllegalRequest(_) => StatusCode::BAD_REQUEST,
-            Self::ResizeFailed(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            Self::DataRequestError(_) => StatusCode::INTERNAL_SERVER_ERROR,
-        }
+        ManagementError::from(self.clone()).status_code()
     }
 }
 
@@ -44,3 +39,213 @@ impl From<serde_json::Error> for NetworkError {
         NetworkError::DataRequestError(err.to_string())
     }
 }
+
+/// An error of the management API, as the backend replies it and the CLI
+/// reads it back.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "code", rename_all = "snake_case")]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum ManagementError {
+    SubnetNotFound {
+        #[cfg_attr(feature = "schema", schemars(with = "String"))]
+        subnet_id: PrincipalId,
+    },
+    NodeNotFound {
+        #[cfg_attr(feature = "schema", schemars(with = "String"))]
+        node_id: PrincipalId,
+    },
+    /// The request cannot be met, e.g. no nodes satisfy its constraints.
+    ConstraintUnsatisfiable {
+        details: String,
+    },
+    /// Nodes of a request are not in the subnet it names or, if it names
+    /// none, not all in the same subnet.
+    NodesNotInSubnet {
+        /// The subnet the nodes are expected in, None if they are in several.
+        #[cfg_attr(feature = "schema", schemars(with = "Option<String>"))]
+        subnet_id: Option<PrincipalId>,
+        /// The subnet of each node not in the expected one, None for
+        /// unassigned nodes.
+        #[cfg_attr(feature = "schema", schemars(with = "BTreeMap<String, Option<String>>"))]
+        offenders: BTreeMap<PrincipalId, Option<PrincipalId>>,
+    },
+    /// The registry or the services the backend gets its data from cannot be
+    /// reached.
+    RegistryUnavailable {
+        details: String,
+    },
+    /// The request declares a version of the API the backend does not
+    /// speak, see [crate::api_version::ApiVersion].
+    IncompatibleApiVersion {
+        requested: String,
+        supported: String,
+    },
+    /// The request is malformed, or names fields a strictly parsed request
+    /// does not have, see [crate::unknown_fields].
+    InvalidRequest {
+        details: String,
+    },
+    Internal {
+        message: String,
+    },
+}
+
+/// The body of error responses: `{"error": {"code": ..., ...}}`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ManagementErrorBody {
+    pub error: ManagementError,
+}
+
+impl ManagementError {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            Self::SubnetNotFound { .. } | Self::NodeNotFound { .. } => StatusCode::NOT_FOUND,
+            Self::ConstraintUnsatisfiable { .. } | Self::NodesNotInSubnet { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::RegistryUnavailable { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            Self::IncompatibleApiVersion { .. } | Self::InvalidRequest { .. } => StatusCode::BAD_REQUEST,
+            Self::Internal { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn internal(message: impl Display) -> Self {
+        Self::Internal {
+            message: message.to_string(),
+        }
+    }
+}
+
+impl Display for ManagementError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::SubnetNotFound { subnet_id } => write!(f, "subnet {} not found", subnet_id),
+            Self::NodeNotFound { node_id } => write!(f, "node {} not found", node_id),
+            Self::ConstraintUnsatisfiable { details } => write!(f, "cannot satisfy the request: {}", details),
+            Self::NodesNotInSubnet { subnet_id, offenders } => {
+                let offenders = offenders
+                    .iter()
+                    .map(|(node, subnet)| match subnet {
+                        Some(subnet) => format!("{} (in subnet {})", node, subnet),
+                        None => format!("{} (unassigned)", node),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                match subnet_id {
+                    Some(subnet_id) => write!(f, "nodes not in subnet {}: {}", subnet_id, offenders),
+                    None => write!(f, "nodes not in a single subnet: {}", offenders),
+                }
+            }
+            Self::RegistryUnavailable { details } => write!(f, "registry unavailable: {}", details),
+            Self::IncompatibleApiVersion { requested, supported } => write!(
+                f,
+                "API version {} is not supported, the backend supports {}",
+                requested, supported
+            ),
+            Self::InvalidRequest { details } => write!(f, "invalid request: {}", details),
+            Self::Internal { message } => write!(f, "internal error: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ManagementError {}
+
+impl ResponseError for ManagementError {
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(ManagementErrorBody { error: self.clone() })
+    }
+
+    fn status_code(&self) -> StatusCode {
+        ManagementError::status_code(self)
+    }
+}
+
+impl From<NetworkError> for ManagementError {
+    fn from(err: NetworkError) -> Self {
+        match err {
+            NetworkError::SubnetNotFound(subnet_id) => Self::SubnetNotFound { subnet_id },
+            NetworkError::NodeNotFound(node_id) => Self::NodeNotFound { node_id },
+            NetworkError::ResizeFailed(details)
+            | NetworkError::PoolExhausted(details)
+            | NetworkError::IllegalRequest(details) => Self::ConstraintUnsatisfiable { details },
+            NetworkError::SubnetFrozen(subnet_id) => Self::ConstraintUnsatisfiable {
+                details: format!("subnet {} is frozen", subnet_id),
+            },
+            NetworkError::DataRequestError(details) => Self::RegistryUnavailable { details },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn error_bodies_wire_format() {
+        let subnet_id = PrincipalId::new_subnet_test_id(1);
+        let cases = [
+            (
+                ManagementError::SubnetNotFound { subnet_id },
+                json!({ "error": { "code": "subnet_not_found", "subnet_id": subnet_id.to_string() } }),
+            ),
+            (
+                ManagementError::ConstraintUnsatisfiable {
+                    details: "no nodes left".to_string(),
+                },
+                json!({ "error": { "code": "constraint_unsatisfiable", "details": "no nodes left" } }),
+            ),
+            (
+                ManagementError::RegistryUnavailable {
+                    details: "timeout".to_string(),
+                },
+                json!({ "error": { "code": "registry_unavailable", "details": "timeout" } }),
+            ),
+            (
+                ManagementError::IncompatibleApiVersion {
+                    requested: "2.0".to_string(),
+                    supported: ">=1.0, <2.0".to_string(),
+                },
+                json!({ "error": { "code": "incompatible_api_version", "requested": "2.0", "supported": ">=1.0, <2.0" } }),
+            ),
+            (
+                ManagementError::InvalidRequest {
+                    details: "unknown field 'exlude'".to_string(),
+                },
+                json!({ "error": { "code": "invalid_request", "details": "unknown field 'exlude'" } }),
+            ),
+            (
+                ManagementError::internal("oops"),
+                json!({ "error": { "code": "internal", "message": "oops" } }),
+            ),
+        ];
+        for (error, body) in cases {
+            let serialized = serde_json::to_value(ManagementErrorBody { error: error.clone() }).unwrap();
+            assert_eq!(serialized, body);
+            assert_eq!(
+                serde_json::from_value::<ManagementErrorBody>(serialized).unwrap().error,
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn network_errors_keep_their_status() {
+        let node_id = PrincipalId::new_node_test_id(1);
+        let error = ManagementError::from(NetworkError::NodeNotFound(node_id));
+        assert_eq!(error, ManagementError::NodeNotFound { node_id });
+        assert_eq!(error.status_code(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            ResponseError::status_code(&NetworkError::ResizeFailed("no nodes left".to_string())),
+            StatusCode::UNPROCESSABLE_ENTITY
+        );
+        assert_eq!(
+            ResponseError::status_code(&NetworkError::PoolExhausted("no nodes left".to_string())),
+            StatusCode::UNPROCESSABLE_ENTITY
+        );
+        assert_eq!(
+            ManagementError::from(NetworkError::SubnetFrozen(PrincipalId::new_subnet_test_id(1))).status_code(),
+            StatusCode::UNPROCESSABLE_ENTITY
+        );
+    }
+}
@@ -0,0 +1,114 @@
+//! Idempotency keys of the requests computing changes, so that a client
+//! retrying a request, e.g. after a timeout, gets the response of the first
+//! attempt instead of having the backend compute the change again.
+
+use std::fmt::Display;
+use std::str::FromStr;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// How long the backend keeps the response to a request with an idempotency
+/// key. Retrying the request within this time returns the same response.
+pub const IDEMPOTENCY_KEY_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// A key identifying a request across its retries, a UUID such as
+/// `3f2b8c1e-5d4a-4e6f-9a7b-0c1d2e3f4a5b`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(transparent)]
+pub struct IdempotencyKey(Uuid);
+
+impl IdempotencyKey {
+    /// A new random key, a version 4 UUID.
+    pub fn new_v4() -> Self {
+        Self(Uuid::new_v4())
+    }
+
+    pub const fn from_u128(value: u128) -> Self {
+        Self(Uuid::from_u128(value))
+    }
+
+    pub const fn as_u128(&self) -> u128 {
+        self.0.as_u128()
+    }
+}
+
+impl Display for IdempotencyKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0.hyphenated())
+    }
+}
+
+impl FromStr for IdempotencyKey {
+    type Err = String;
+
+    /// Parse a UUID, with or without the hyphens.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Uuid::try_parse(s)
+            .map(Self)
+            .map_err(|e| format!("Invalid idempotency key '{}', expected a UUID: {}", s, e))
+    }
+}
+
+#[cfg(feature = "schema")]
+impl schemars::JsonSchema for IdempotencyKey {
+    fn schema_name() -> String {
+        "IdempotencyKey".to_string()
+    }
+
+    fn json_schema(_: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        schemars::schema::SchemaObject {
+            instance_type: Some(schemars::schema::InstanceType::String.into()),
+            format: Some("uuid".to_string()),
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn keys_round_trip_as_uuids() {
+        let key = IdempotencyKey::from_u128(0x3f2b8c1e_5d4a_4e6f_9a7b_0c1d2e3f4a5b);
+        assert_eq!(key.to_string(), "3f2b8c1e-5d4a-4e6f-9a7b-0c1d2e3f4a5b");
+        assert_eq!(
+            serde_json::to_value(key).unwrap(),
+            json!("3f2b8c1e-5d4a-4e6f-9a7b-0c1d2e3f4a5b")
+        );
+        for uuid in [
+            "3f2b8c1e-5d4a-4e6f-9a7b-0c1d2e3f4a5b",
+            "3F2B8C1E-5D4A-4E6F-9A7B-0C1D2E3F4A5B",
+            "3f2b8c1e5d4a4e6f9a7b0c1d2e3f4a5b",
+        ] {
+            assert_eq!(uuid.parse::<IdempotencyKey>(), Ok(key), "{}", uuid);
+        }
+        for invalid in [
+            "",
+            "3f2b8c1e-5d4a-4e6f-9a7b",
+            "3f2b8c1e-5d4a-4e6f-9a7b-0c1d2e3f4a5g",
+            "3f2b8c1e5-d4a-4e6f-9a7b-0c1d2e3f4a5b",
+            "+f2b8c1e5d4a4e6f9a7b0c1d2e3f4a5b",
+        ] {
+            assert!(invalid.parse::<IdempotencyKey>().is_err(), "{}", invalid);
+        }
+        assert!(serde_json::from_value::<IdempotencyKey>(json!(42)).is_err());
+    }
+
+    #[test]
+    fn generated_keys_are_distinct_version_4_uuids() {
+        let keys = (0..100).map(|_| IdempotencyKey::new_v4()).collect::<Vec<_>>();
+        for key in &keys {
+            let uuid = key.to_string();
+            assert_eq!(&uuid[14..15], "4", "{}", uuid);
+            assert!("89ab".contains(&uuid[19..20]), "{}", uuid);
+        }
+        let distinct = keys.iter().collect::<std::collections::BTreeSet<_>>();
+        assert_eq!(distinct.len(), keys.len());
+    }
+}
@@ -0,0 +1,112 @@
+//! Helpers for validating the location values used in the registry data
+//! center records (`<continent>,<country>,<city>`).
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::requests::normalize_feature_value;
+
+/// ISO 3166-1 alpha-2 country codes.
+const COUNTRY_CODES: &[&str] = &[
+    "AD", "AE", "AF", "AG", "AI", "AL", "AM", "AO", "AQ", "AR", "AS", "AT", "AU", "AW", "AX", "AZ", "BA", "BB", "BD",
+    "BE", "BF", "BG", "BH", "BI", "BJ", "BL", "BM", "BN", "BO", "BQ", "BR", "BS", "BT", "BV", "BW", "BY", "BZ", "CA",
+    "CC", "CD", "CF", "CG", "CH", "CI", "CK", "CL", "CM", "CN", "CO", "CR", "CU", "CV", "CW", "CX", "CY", "CZ", "DE",
+    "DJ", "DK", "DM", "DO", "DZ", "EC", "EE", "EG", "EH", "ER", "ES", "ET", "FI", "FJ", "FK", "FM", "FO", "FR", "GA",
+    "GB", "GD", "GE", "GF", "GG", "GH", "GI", "GL", "GM", "GN", "GP", "GQ", "GR", "GS", "GT", "GU", "GW", "GY", "HK",
+    "HM", "HN", "HR", "HT", "HU", "ID", "IE", "IL", "IM", "IN", "IO", "IQ", "IR", "IS", "IT", "JE", "JM", "JO", "JP",
+    "KE", "KG", "KH", "KI", "KM", "KN", "KP", "KR", "KW", "KY", "KZ", "LA", "LB", "LC", "LI", "LK", "LR", "LS", "LT",
+    "LU", "LV", "LY", "MA", "MC", "MD", "ME", "MF", "MG", "MH", "MK", "ML", "MM", "MN", "MO", "MP", "MQ", "MR", "MS",
+    "MT", "MU", "MV", "MW", "MX", "MY", "MZ", "NA", "NC", "NE", "NF", "NG", "NI", "NL", "NO", "NP", "NR", "NU", "NZ",
+    "OM", "PA", "PE", "PF", "PG", "PH", "PK", "PL", "PM", "PN", "PR", "PS", "PT", "PW", "PY", "QA", "RE", "RO", "RS",
+    "RU", "RW", "SA", "SB", "SC", "SD", "SE", "SG", "SH", "SI", "SJ", "SK", "SL", "SM", "SN", "SO", "SR", "SS", "ST",
+    "SV", "SX", "SY", "SZ", "TC", "TD", "TF", "TG", "TH", "TJ", "TK", "TL", "TM", "TN", "TO", "TR", "TT", "TV", "TW",
+    "TZ", "UA", "UG", "UM", "US", "UY", "UZ", "VA", "VC", "VE", "VG", "VI", "VN", "VU", "WF", "WS", "YE", "YT", "ZA",
+    "ZM", "ZW",
+];
+
+/// Continent names as they appear in the registry data center records.
+const CONTINENTS: &[&str] = &[
+    "Africa",
+    "Antarctica",
+    "Asia",
+    "Europe",
+    "North America",
+    "Oceania",
+    "South America",
+];
+
+/// Returns true if `code` is an ISO 3166-1 alpha-2 country code. The check
+/// is case insensitive.
+pub fn is_country_code(code: &str) -> bool {
+    COUNTRY_CODES.iter().any(|c| c.eq_ignore_ascii_case(code))
+}
+
+/// Returns true if `name` is one of the known continent names. The check is
+/// case insensitive.
+pub fn is_continent(name: &str) -> bool {
+    CONTINENTS.iter().any(|c| c.eq_ignore_ascii_case(name))
+}
+
+/// Canonical names of the cities spelled differently by different sources,
+/// e.g. `Zürich` for `Zurich` and `ZH`. Spellings are compared like feature
+/// values are matched, ignoring case and surrounding whitespace. Empty by
+/// default, merging no cities.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CityNames {
+    /// The canonical name of each normalized spelling.
+    canonical: BTreeMap<String, String>,
+}
+
+impl CityNames {
+    /// The names with `spellings` merged into `canonical`.
+    pub fn with_spellings(mut self, canonical: &str, spellings: &[&str]) -> Self {
+        for spelling in spellings.iter().chain([&canonical]) {
+            self.canonical
+                .insert(normalize_feature_value(spelling), canonical.to_string());
+        }
+        self
+    }
+
+    /// The canonical name of `city`, or `city` itself if it has none.
+    pub fn canonical<'a>(&'a self, city: &'a str) -> &'a str {
+        self.canonical
+            .get(&normalize_feature_value(city))
+            .map(String::as_str)
+            .unwrap_or(city)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.canonical.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validates_country_codes() {
+        assert!(is_country_code("CH"));
+        assert!(is_country_code("us"));
+        assert!(!is_country_code("XX"));
+        assert!(!is_country_code("Switzerland"));
+    }
+
+    #[test]
+    fn validates_continents() {
+        assert!(is_continent("Europe"));
+        assert!(is_continent("north america"));
+        assert!(!is_continent("Atlantis"));
+    }
+
+    #[test]
+    fn merges_city_spellings() {
+        let names = CityNames::default().with_spellings("Zürich", &["Zurich", "ZH"]);
+        assert_eq!(names.canonical("Zurich"), "Zürich");
+        assert_eq!(names.canonical(" zh "), "Zürich");
+        assert_eq!(names.canonical("zürich"), "Zürich");
+        assert_eq!(names.canonical("Geneva"), "Geneva");
+        assert_eq!(CityNames::default().canonical("Zurich"), "Zurich");
+    }
+}
@@ -1,7 +1,25 @@
+pub mod annotations;
+pub mod api_version;
+pub mod builders;
 pub mod errors;
+pub mod idempotency;
+pub mod iso;
+pub mod motivation;
+pub mod pagination;
+pub mod payloads;
+pub mod preview;
+pub mod proposals;
 pub mod requests;
+#[cfg(feature = "schema")]
+pub mod schema;
+pub mod score;
+pub mod timestamp;
+pub mod unknown_fields;
+pub mod validation;
+pub mod versions;
 pub use crate::errors::*;
 
+use crate::annotations::NodeAnnotations;
 use anyhow::anyhow;
 use candid::{CandidType, Decode};
 use clap::{Parser, ValueEnum};
@@ -10,6 +28,7 @@ use ic_base_types::NodeId;
 use ic_nns_governance::pb::v1::NnsFunction;
 use ic_nns_governance::pb::v1::ProposalInfo;
 use ic_nns_governance::pb::v1::ProposalStatus;
+use ic_protobuf::registry::node_operator::v1::NodeOperatorRecord;
 use ic_registry_subnet_type::SubnetType;
 use ic_types::PrincipalId;
 use registry_canister::mutations::do_add_nodes_to_subnet::AddNodesToSubnetPayload;
@@ -157,9 +176,13 @@ impl TopologyChangePayload for RemoveNodesPayload {
 }
 
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct TopologyChangeProposal {
+    #[cfg_attr(feature = "schema", schemars(with = "Vec<String>"))]
     pub node_ids_added: Vec<PrincipalId>,
+    #[cfg_attr(feature = "schema", schemars(with = "Vec<String>"))]
     pub node_ids_removed: Vec<PrincipalId>,
+    #[cfg_attr(feature = "schema", schemars(with = "Option<String>"))]
     pub subnet_id: Option<PrincipalId>,
     pub id: u64,
 }
@@ -201,6 +224,8 @@ pub struct Subnet {
     pub principal: PrincipalId,
     pub nodes: Vec<Node>,
     pub subnet_type: SubnetType,
+    #[serde(default)]
+    pub kind: SubnetKind,
     pub metadata: SubnetMetadata,
     pub replica_version: String,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -208,6 +233,152 @@ pub struct Subnet {
     pub replica_release: Option<Release>,
 }
 
+/// The subnet the business rules only allow European nodes in.
+pub const EUROPEAN_SUBNET_ID: &str = "bkfrj-6k62g-dycql-7h53p-atvkj-zg4to-gaogh-netha-ptybj-ntsgw-rqe";
+
+/// The kind of a subnet: the `subnet_type` of its registry record, or the
+/// group of subnets with their own business rules it belongs to. Serialized
+/// as its snake case name, e.g. `verified_application`. Names of kinds this
+/// version does not know, e.g. of subnet types added to the registry later,
+/// are kept as [SubnetKind::Other].
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum SubnetKind {
+    System,
+    #[default]
+    Application,
+    VerifiedApplication,
+    /// An application subnet of European nodes only.
+    European,
+    Other(String),
+}
+
+/// The business rules specific to the subnets of a [SubnetKind], on top of
+/// those the decentralization crate checks for all subnets.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RuleProfile {
+    /// All nodes must be in Europe. A continent controlling most of the
+    /// nodes is then not a violation.
+    pub european_nodes_only: bool,
+}
+
+impl SubnetKind {
+    /// The kind of the subnet `subnet_id` with the `subnet_type` of its
+    /// registry record. Unknown subnet types are [SubnetKind::Other].
+    pub fn from_registry(subnet_id: PrincipalId, subnet_type: i32) -> Self {
+        if subnet_id.to_string() == EUROPEAN_SUBNET_ID {
+            return Self::European;
+        }
+        SubnetType::try_from(subnet_type)
+            .map(Self::from)
+            .unwrap_or_else(|_| Self::Other(format!("subnet_type_{}", subnet_type)))
+    }
+
+    /// The kind of the subnet `subnet_id`, if it belongs to a group of
+    /// subnets with their own business rules.
+    pub fn of_subnet(subnet_id: &PrincipalId) -> Option<Self> {
+        (subnet_id.to_string() == EUROPEAN_SUBNET_ID).then_some(Self::European)
+    }
+
+    /// The subnet type to create a subnet of this kind with, if it can be
+    /// created.
+    pub fn registry_subnet_type(&self) -> Option<SubnetType> {
+        match self {
+            Self::System => Some(SubnetType::System),
+            Self::Application | Self::European => Some(SubnetType::Application),
+            Self::VerifiedApplication => Some(SubnetType::VerifiedApplication),
+            Self::Other(_) => None,
+        }
+    }
+
+    pub fn rule_profile(&self) -> RuleProfile {
+        match self {
+            Self::European => RuleProfile {
+                european_nodes_only: true,
+            },
+            Self::System | Self::Application | Self::VerifiedApplication | Self::Other(_) => RuleProfile::default(),
+        }
+    }
+}
+
+impl From<SubnetType> for SubnetKind {
+    fn from(subnet_type: SubnetType) -> Self {
+        match subnet_type {
+            SubnetType::System => Self::System,
+            SubnetType::Application => Self::Application,
+            SubnetType::VerifiedApplication => Self::VerifiedApplication,
+        }
+    }
+}
+
+impl std::fmt::Display for SubnetKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::System => write!(f, "system"),
+            Self::Application => write!(f, "application"),
+            Self::VerifiedApplication => write!(f, "verified_application"),
+            Self::European => write!(f, "european"),
+            Self::Other(kind) => write!(f, "{}", kind),
+        }
+    }
+}
+
+impl FromStr for SubnetKind {
+    type Err = String;
+
+    /// Parse the snake case name of a kind, or `verified` for verified
+    /// application subnets. Other names are [SubnetKind::Other].
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "" => Err("Empty subnet kind".to_string()),
+            "system" => Ok(Self::System),
+            "application" => Ok(Self::Application),
+            "verified_application" | "verified" => Ok(Self::VerifiedApplication),
+            "european" => Ok(Self::European),
+            other => Ok(Self::Other(other.to_string())),
+        }
+    }
+}
+
+impl Serialize for SubnetKind {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for SubnetKind {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(feature = "schema")]
+impl schemars::JsonSchema for SubnetKind {
+    fn schema_name() -> String {
+        "SubnetKind".to_string()
+    }
+
+    fn json_schema(_: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        schemars::schema::SchemaObject {
+            metadata: Some(Box::new(schemars::schema::Metadata {
+                description: Some(
+                    "The kind of a subnet, e.g. `system`, `application`, `verified_application` or `european`."
+                        .to_string(),
+                ),
+                ..Default::default()
+            })),
+            instance_type: Some(schemars::schema::InstanceType::String.into()),
+            string: Some(Box::new(schemars::schema::StringValidation {
+                min_length: Some(1),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
 type Application = String;
 type Label = String;
 
@@ -221,13 +392,16 @@ pub struct SubnetMetadata {
 }
 
 #[derive(Clone, Serialize, Debug, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Node {
+    #[cfg_attr(feature = "schema", schemars(with = "String"))]
     pub principal: PrincipalId,
     pub ip_addr: Ipv6Addr,
     pub operator: Operator,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub hostname: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "schema", schemars(with = "Option<String>"))]
     pub subnet_id: Option<PrincipalId>,
     pub hostos_release: Option<Release>,
     pub hostos_version: String,
@@ -237,7 +411,11 @@ pub struct Node {
     pub label: Option<String>,
     #[serde(default)]
     pub decentralized: bool,
+    #[cfg_attr(feature = "schema", schemars(with = "Option<String>"))]
     pub duplicates: Option<PrincipalId>,
+    /// Annotations operations attached to the node, e.g. `rma=pending`.
+    #[serde(default, skip_serializing_if = "NodeAnnotations::is_empty")]
+    pub annotations: NodeAnnotations,
 }
 
 #[derive(
@@ -245,6 +423,7 @@ pub struct Node {
 )]
 #[strum(serialize_all = "snake_case")]
 #[serde(rename_all = "snake_case")]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum NodeFeature {
     NodeProvider,
     DataCenter,
@@ -261,12 +440,51 @@ impl NodeFeature {
             .map(|f| NodeFeature::from_str(f).unwrap())
             .collect()
     }
+
+    /// Parse a feature name given by a user, e.g. on the command line. If it
+    /// is not a valid name, the error suggests the closest one.
+    pub fn parse_with_suggestion(s: &str) -> Result<Self, String> {
+        NodeFeature::from_str(s).map_err(|_| {
+            let closest = NodeFeature::VARIANTS
+                .iter()
+                .min_by_key(|v| edit_distance(&s.to_lowercase(), v))
+                .expect("there are node features");
+            format!(
+                "invalid feature '{}', did you mean '{}'? Valid features are: {}",
+                s,
+                closest,
+                NodeFeature::VARIANTS.join(", ")
+            )
+        })
+    }
+}
+
+/// The Levenshtein distance between two strings.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, Default, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct MinNakamotoCoefficients {
     pub coefficients: BTreeMap<NodeFeature, f64>,
     pub average: f64,
+    /// Minimum number of distinct data center owners among the subnet nodes
+    #[serde(default)]
+    pub min_distinct_owners: Option<usize>,
+    /// Maximum number of subnet nodes in any single data center
+    #[serde(default)]
+    pub max_nodes_per_data_center: Option<usize>,
 }
 
 #[derive(Clone, Serialize, Debug, Deserialize)]
@@ -314,7 +532,9 @@ pub struct CreateSubnetProposalInfo {
 }
 
 #[derive(Clone, Serialize, Default, Debug, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Operator {
+    #[cfg_attr(feature = "schema", schemars(with = "String"))]
     pub principal: PrincipalId,
     pub provider: Provider,
     pub allowance: u64,
@@ -324,7 +544,9 @@ pub struct Operator {
 }
 
 #[derive(Clone, Serialize, Default, Debug, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Provider {
+    #[cfg_attr(feature = "schema", schemars(with = "String"))]
     pub principal: PrincipalId,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub name: Option<String>,
@@ -332,6 +554,7 @@ pub struct Provider {
 }
 
 #[derive(Clone, Serialize, Default, Debug, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Datacenter {
     pub name: String,
     pub owner: DatacenterOwner,
@@ -345,6 +568,7 @@ pub struct Datacenter {
 }
 
 #[derive(Clone, Serialize, Default, Debug, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct DatacenterOwner {
     pub name: String,
 }
@@ -413,6 +637,74 @@ pub struct NodeProviderDetails {
     pub website: Option<String>,
 }
 
+/// A node provider with the number of nodes it runs, in total and in each
+/// subnet.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ProviderDetails {
+    #[cfg_attr(feature = "schema", schemars(with = "String"))]
+    pub principal: PrincipalId,
+    pub name: Option<String>,
+    pub total_nodes: usize,
+    /// The number of nodes in each subnet, without the unassigned nodes.
+    #[cfg_attr(feature = "schema", schemars(with = "BTreeMap<String, usize>"))]
+    pub nodes_per_subnet: BTreeMap<PrincipalId, usize>,
+    pub website: Option<String>,
+}
+
+impl ProviderDetails {
+    /// The providers of the `nodes`, by principal.
+    pub fn from_nodes<'a>(nodes: impl IntoIterator<Item = &'a Node>) -> BTreeMap<PrincipalId, ProviderDetails> {
+        nodes.into_iter().fold(BTreeMap::new(), |mut acc, node| {
+            let provider = &node.operator.provider;
+            let details = acc.entry(provider.principal).or_insert_with(|| ProviderDetails {
+                principal: provider.principal,
+                name: provider.name.clone(),
+                total_nodes: 0,
+                nodes_per_subnet: BTreeMap::new(),
+                website: provider.website.clone(),
+            });
+            details.total_nodes += 1;
+            if let Some(subnet_id) = node.subnet_id {
+                *details.nodes_per_subnet.entry(subnet_id).or_default() += 1;
+            }
+            acc
+        })
+    }
+}
+
+/// A node operator as recorded in the registry.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct OperatorDetails {
+    #[cfg_attr(feature = "schema", schemars(with = "String"))]
+    pub principal: PrincipalId,
+    #[cfg_attr(feature = "schema", schemars(with = "String"))]
+    pub provider: PrincipalId,
+    /// The number of nodes the operator may still add.
+    pub allowance: u64,
+    pub dc_id: String,
+    /// The number of nodes the operator is rewarded for, by node type.
+    pub rewardable_nodes: BTreeMap<String, u32>,
+}
+
+impl TryFrom<&NodeOperatorRecord> for OperatorDetails {
+    type Error = String;
+
+    fn try_from(record: &NodeOperatorRecord) -> Result<Self, Self::Error> {
+        let principal = |bytes: &[u8]| PrincipalId::try_from(bytes).map_err(|e| e.to_string());
+        Ok(Self {
+            principal: principal(&record.node_operator_principal_id)?,
+            provider: principal(&record.node_provider_principal_id)?,
+            allowance: record.node_allowance,
+            dc_id: record.dc_id.clone(),
+            rewardable_nodes: record.rewardable_nodes.iter().map(|(k, v)| (k.clone(), *v)).collect(),
+        })
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct NodeReplacements {
     pub removed: Vec<PrincipalId>,
@@ -427,12 +719,66 @@ pub enum Health {
     Unknown,
 }
 
-#[derive(PartialOrd, Ord, Eq, PartialEq, EnumString, Serialize, Display, Deserialize, Debug, Clone, Hash)]
-pub enum Status {
-    Healthy,
-    Degraded,
-    Dead,
+/// The health of a node. Healthier statuses compare greater, and an unknown
+/// status compares below all known ones.
+#[derive(PartialOrd, Ord, Eq, PartialEq, EnumString, Serialize, Display, Deserialize, Debug, Clone, Copy, Hash)]
+#[serde(rename_all = "lowercase")]
+#[strum(ascii_case_insensitive)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum HealthStatus {
+    #[serde(alias = "Unknown")]
     Unknown,
+    #[serde(alias = "Dead")]
+    Dead,
+    #[serde(alias = "Degraded")]
+    Degraded,
+    #[serde(alias = "Healthy")]
+    Healthy,
+}
+
+impl HealthStatus {
+    /// The worst of the known `statuses`, e.g. of the same node reported by
+    /// several sources, or unknown if none is known.
+    pub fn merge(statuses: impl IntoIterator<Item = HealthStatus>) -> HealthStatus {
+        statuses
+            .into_iter()
+            .filter(|s| *s != HealthStatus::Unknown)
+            .min()
+            .unwrap_or(HealthStatus::Unknown)
+    }
+}
+
+/// Where the health of a node is observed.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthSource {
+    /// Whether the replica is up and free of alerts.
+    Replica,
+    /// The health state the orchestrator reports.
+    Orchestrator,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct NodeHealth {
+    pub principal: PrincipalId,
+    pub status: HealthStatus,
+    pub observed_at: timestamp::Timestamp,
+    pub source: HealthSource,
+}
+
+impl NodeHealth {
+    /// The merged status of each node of the `healths`.
+    pub fn merge_by_node<'a>(healths: impl IntoIterator<Item = &'a NodeHealth>) -> BTreeMap<PrincipalId, HealthStatus> {
+        healths
+            .into_iter()
+            .fold(BTreeMap::<PrincipalId, Vec<HealthStatus>>::new(), |mut acc, h| {
+                acc.entry(h.principal).or_default().push(h.status);
+                acc
+            })
+            .into_iter()
+            .map(|(principal, statuses)| (principal, HealthStatus::merge(statuses)))
+            .collect()
+    }
 }
 
 impl From<i64> for Health {
@@ -465,11 +811,12 @@ pub struct NodeVersion {
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Release {
     pub commit_hash: String,
     pub branch: String,
     pub name: String,
-    pub time: chrono::NaiveDateTime,
+    pub time: timestamp::Timestamp,
     pub previous_patch_release: Option<Box<Release>>,
 }
 
@@ -558,33 +905,109 @@ impl Artifact {
     }
 }
 
-#[derive(Clone, PartialEq, Eq, strum_macros::Display)]
-#[strum(serialize_all = "lowercase")]
+/// The DER encoded root public key of the IC mainnet, which the responses of
+/// the mainnet NNS are certified with.
+pub const MAINNET_ROOT_KEY_DER: [u8; 133] = [
+    0x30, 0x81, 0x82, 0x30, 0x1d, 0x06, 0x0d, 0x2b, 0x06, 0x01, 0x04, 0x01, 0x82, 0xdc, 0x7c, 0x05, 0x03, 0x01, 0x02,
+    0x01, 0x06, 0x0c, 0x2b, 0x06, 0x01, 0x04, 0x01, 0x82, 0xdc, 0x7c, 0x05, 0x03, 0x02, 0x01, 0x03, 0x61, 0x00, 0x81,
+    0x4c, 0x0e, 0x6e, 0xc7, 0x1f, 0xab, 0x58, 0x3b, 0x08, 0xbd, 0x81, 0x37, 0x3c, 0x25, 0x5c, 0x3c, 0x37, 0x1b, 0x2e,
+    0x84, 0x86, 0x3c, 0x98, 0xa4, 0xf1, 0xe0, 0x8b, 0x74, 0x23, 0x5d, 0x14, 0xfb, 0x5d, 0x9c, 0x0c, 0xd5, 0x46, 0xd9,
+    0x68, 0x5f, 0x91, 0x3a, 0x0c, 0x0b, 0x2c, 0xc5, 0x34, 0x15, 0x83, 0xbf, 0x4b, 0x43, 0x92, 0xe4, 0x67, 0xdb, 0x96,
+    0xd6, 0x5b, 0x9b, 0xb4, 0xcb, 0x71, 0x71, 0x12, 0xf8, 0x47, 0x2e, 0x0d, 0x5a, 0x4d, 0x14, 0x50, 0x5f, 0xfd, 0x74,
+    0x84, 0xb0, 0x12, 0x91, 0x09, 0x1c, 0x5f, 0x87, 0xb9, 0x88, 0x83, 0x46, 0x3f, 0x98, 0x09, 0x1a, 0x0b, 0xaa, 0xae,
+];
+
+/// An IC network the tools work with: mainnet, staging, or any other network
+/// such as a testnet, given by the URLs of its NNS.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Network {
     Staging,
     Mainnet,
-    Url(url::Url),
+    Custom {
+        name: String,
+        #[serde(deserialize_with = "deserialize_nns_urls")]
+        nns_urls: Vec<Url>,
+        /// The root public key to verify the NNS responses with, if known.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        root_key_der: Option<Vec<u8>>,
+    },
+}
+
+/// The NNS URLs of a custom network, of which there has to be at least one.
+fn deserialize_nns_urls<'de, D>(deserializer: D) -> Result<Vec<Url>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let nns_urls = Vec::<Url>::deserialize(deserializer)?;
+    if nns_urls.is_empty() {
+        return Err(serde::de::Error::invalid_length(0, &"at least one NNS URL"));
+    }
+    Ok(nns_urls)
 }
 
 impl FromStr for Network {
     type Err = String;
 
+    /// Parse `mainnet`, `staging`, or the comma separated NNS URLs of a
+    /// custom network, which is then named after them.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         Ok(match s {
             "mainnet" => Self::Mainnet,
             "staging" => Self::Staging,
-            _ => Self::Url(url::Url::from_str(s).map_err(|e| format!("{}", e))?),
+            _ => {
+                let nns_urls = s
+                    .split(',')
+                    .map(|url| Url::from_str(url.trim()).map_err(|e| format!("Invalid NNS URL '{}': {}", url, e)))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Self::Custom {
+                    name: nns_urls.iter().map(|url| url.to_string()).collect::<Vec<_>>().join(","),
+                    nns_urls,
+                    root_key_der: None,
+                }
+            }
         })
     }
 }
 
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Network::Staging => write!(f, "staging"),
+            Network::Mainnet => write!(f, "mainnet"),
+            Network::Custom { name, .. } => write!(f, "{}", name),
+        }
+    }
+}
+
 impl Network {
-    pub fn get_url(&self) -> Url {
+    /// The NNS URLs of the network, the ones to use by default for mainnet
+    /// and staging.
+    pub fn get_nns_urls(&self) -> Vec<Url> {
         match self {
-            Network::Mainnet => Url::from_str("https://ic0.app").unwrap(),
+            Network::Mainnet => vec![Url::from_str("https://ic0.app").unwrap()],
             // Workaround for staging boundary node not working properly (503 Service unavailable)
-            Network::Staging => Url::from_str("https://[2600:3000:6100:200:5000:b0ff:fe8e:6b7b]:8080").unwrap(),
-            Self::Url(url) => url.clone(),
+            Network::Staging => vec![Url::from_str("https://[2600:3000:6100:200:5000:b0ff:fe8e:6b7b]:8080").unwrap()],
+            Network::Custom { nns_urls, .. } => nns_urls.clone(),
+        }
+    }
+
+    /// The first of the NNS URLs of the network. Only a custom network
+    /// built without any NNS URL has none.
+    pub fn get_url(&self) -> anyhow::Result<Url> {
+        self.get_nns_urls()
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("Network {} has no NNS URLs", self))
+    }
+
+    /// The DER encoded root public key of the network, if known. Without
+    /// one, the key has to be fetched from the NNS.
+    pub fn root_key_der(&self) -> Option<&[u8]> {
+        match self {
+            Network::Mainnet => Some(&MAINNET_ROOT_KEY_DER),
+            Network::Staging => None,
+            Network::Custom { root_key_der, .. } => root_key_der.as_deref(),
         }
     }
 
@@ -592,12 +1015,13 @@ impl Network {
         match self {
             Network::Mainnet => "mercury".to_string(),
             Network::Staging => "staging".to_string(),
-            Self::Url(url) => format!("testnet-{url}"),
+            Network::Custom { name, .. } => format!("testnet-{name}"),
         }
     }
 }
 
 #[derive(ValueEnum, Copy, Clone, Debug, Ord, Eq, PartialEq, PartialOrd, Serialize, Deserialize, Parser, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum NodeOwner {
     Dfinity,
     Others,
@@ -606,6 +1030,7 @@ pub enum NodeOwner {
 }
 
 #[derive(ValueEnum, Copy, Clone, Debug, Ord, Eq, PartialEq, PartialOrd, Serialize, Deserialize, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum NodeAssignment {
     Unassigned,
     Assigned,
@@ -614,6 +1039,7 @@ pub enum NodeAssignment {
 }
 
 #[derive(Copy, Clone, Debug, Ord, Eq, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct NodeGroup {
     pub assignment: NodeAssignment,
     pub owner: NodeOwner,
@@ -630,6 +1056,7 @@ impl fmt::Display for NodeGroup {
     }
 }
 #[derive(Copy, Clone, Debug, Ord, Eq, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum NumberOfNodes {
     Percentage(i32),
     Absolute(i32),
@@ -671,6 +1098,7 @@ impl fmt::Display for NumberOfNodes {
     }
 }
 #[derive(Copy, Clone, Debug, Ord, Eq, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct NodeGroupUpdate {
     pub node_group: NodeGroup,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -708,3 +1136,313 @@ impl NodeGroupUpdate {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use ic_protobuf::registry::subnet::v1::SubnetType as SubnetTypePb;
+
+    use super::*;
+
+    #[test]
+    fn misspelled_features_get_suggestions() {
+        let err = NodeFeature::parse_with_suggestion("contnent").unwrap_err();
+        assert!(err.contains("did you mean 'continent'?"), "{}", err);
+        let err = NodeFeature::parse_with_suggestion("provider").unwrap_err();
+        assert!(err.contains("did you mean 'node_provider'?"), "{}", err);
+        assert_eq!(
+            NodeFeature::parse_with_suggestion("data_center_owner"),
+            Ok(NodeFeature::DataCenterOwner)
+        );
+    }
+
+    #[test]
+    fn edit_distances() {
+        assert_eq!(edit_distance("", "city"), 4);
+        assert_eq!(edit_distance("city", "city"), 0);
+        assert_eq!(edit_distance("contnent", "continent"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn health_statuses_read_the_dashboard_strings() {
+        for (text, status) in [
+            ("\"Healthy\"", HealthStatus::Healthy),
+            ("\"Degraded\"", HealthStatus::Degraded),
+            ("\"Dead\"", HealthStatus::Dead),
+            ("\"Unknown\"", HealthStatus::Unknown),
+            ("\"healthy\"", HealthStatus::Healthy),
+        ] {
+            assert_eq!(serde_json::from_str::<HealthStatus>(text).unwrap(), status);
+        }
+        assert_eq!(serde_json::to_string(&HealthStatus::Degraded).unwrap(), "\"degraded\"");
+        assert_eq!(HealthStatus::from_str("Dead"), Ok(HealthStatus::Dead));
+        assert_eq!(HealthStatus::from_str("dead"), Ok(HealthStatus::Dead));
+    }
+
+    #[test]
+    fn merged_health_is_the_worst_known_status() {
+        assert!(HealthStatus::Healthy > HealthStatus::Degraded);
+        assert!(HealthStatus::Degraded > HealthStatus::Dead);
+        assert_eq!(
+            HealthStatus::merge([HealthStatus::Healthy, HealthStatus::Unknown, HealthStatus::Degraded]),
+            HealthStatus::Degraded
+        );
+        assert_eq!(HealthStatus::merge([HealthStatus::Unknown]), HealthStatus::Unknown);
+
+        let observed_at = timestamp::Timestamp::default();
+        let health = |id: u64, status, source| NodeHealth {
+            principal: PrincipalId::new_node_test_id(id),
+            status,
+            observed_at,
+            source,
+        };
+        let healths = [
+            health(1, HealthStatus::Healthy, HealthSource::Orchestrator),
+            health(1, HealthStatus::Dead, HealthSource::Replica),
+            health(2, HealthStatus::Healthy, HealthSource::Replica),
+        ];
+        assert_eq!(
+            NodeHealth::merge_by_node(&healths),
+            BTreeMap::from([
+                (PrincipalId::new_node_test_id(1), HealthStatus::Dead),
+                (PrincipalId::new_node_test_id(2), HealthStatus::Healthy),
+            ])
+        );
+    }
+
+    fn provider_node(id: u64, provider: u64, subnet: Option<u64>) -> Node {
+        Node {
+            principal: PrincipalId::new_node_test_id(id),
+            ip_addr: Ipv6Addr::LOCALHOST,
+            operator: Operator {
+                provider: Provider {
+                    principal: PrincipalId::new_user_test_id(provider),
+                    name: Some(format!("provider {}", provider)),
+                    website: None,
+                },
+                ..Default::default()
+            },
+            hostname: None,
+            subnet_id: subnet.map(PrincipalId::new_subnet_test_id),
+            hostos_release: None,
+            hostos_version: String::new(),
+            dfinity_owned: None,
+            proposal: None,
+            label: None,
+            decentralized: true,
+            duplicates: None,
+            annotations: NodeAnnotations::default(),
+        }
+    }
+
+    #[test]
+    fn providers_count_their_nodes_per_subnet() {
+        let nodes = [
+            provider_node(1, 1, Some(1)),
+            provider_node(2, 1, Some(1)),
+            provider_node(3, 1, Some(2)),
+            provider_node(4, 1, None),
+            provider_node(5, 2, Some(2)),
+        ];
+
+        let providers = ProviderDetails::from_nodes(&nodes);
+
+        assert_eq!(providers.len(), 2);
+        let first = &providers[&PrincipalId::new_user_test_id(1)];
+        assert_eq!(first.name.as_deref(), Some("provider 1"));
+        assert_eq!(first.total_nodes, 4);
+        assert_eq!(
+            first.nodes_per_subnet,
+            BTreeMap::from([
+                (PrincipalId::new_subnet_test_id(1), 2),
+                (PrincipalId::new_subnet_test_id(2), 1),
+            ])
+        );
+        let second = &providers[&PrincipalId::new_user_test_id(2)];
+        assert_eq!(second.total_nodes, 1);
+        assert_eq!(
+            second.nodes_per_subnet,
+            BTreeMap::from([(PrincipalId::new_subnet_test_id(2), 1)])
+        );
+    }
+
+    #[test]
+    fn operator_details_from_a_registry_record() {
+        let record = NodeOperatorRecord {
+            node_operator_principal_id: PrincipalId::new_user_test_id(1).to_vec(),
+            node_provider_principal_id: PrincipalId::new_user_test_id(2).to_vec(),
+            node_allowance: 3,
+            dc_id: "zh1".to_string(),
+            rewardable_nodes: [("type1".to_string(), 4)].into_iter().collect(),
+            ..Default::default()
+        };
+
+        let details = OperatorDetails::try_from(&record).unwrap();
+
+        assert_eq!(details.principal, PrincipalId::new_user_test_id(1));
+        assert_eq!(details.provider, PrincipalId::new_user_test_id(2));
+        assert_eq!(details.allowance, 3);
+        assert_eq!(details.rewardable_nodes, BTreeMap::from([("type1".to_string(), 4)]));
+        assert!(OperatorDetails::try_from(&NodeOperatorRecord {
+            node_provider_principal_id: vec![0; 30],
+            ..record
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn networks_are_parsed_from_names_or_nns_urls() {
+        assert_eq!(Network::from_str("mainnet"), Ok(Network::Mainnet));
+        assert_eq!(Network::from_str("staging"), Ok(Network::Staging));
+        let testnet = Network::from_str("http://[2001:db8::1]:8080, http://[2001:db8::2]:8080").unwrap();
+        assert_eq!(
+            testnet,
+            Network::Custom {
+                name: "http://[2001:db8::1]:8080/,http://[2001:db8::2]:8080/".to_string(),
+                nns_urls: vec![
+                    Url::parse("http://[2001:db8::1]:8080").unwrap(),
+                    Url::parse("http://[2001:db8::2]:8080").unwrap()
+                ],
+                root_key_der: None,
+            }
+        );
+        assert_eq!(
+            testnet.get_url().unwrap(),
+            Url::parse("http://[2001:db8::1]:8080").unwrap()
+        );
+        assert_eq!(Network::from_str(&testnet.to_string()), Ok(testnet));
+        assert_eq!(Network::Mainnet.to_string(), "mainnet");
+        assert_eq!(Network::Mainnet.legacy_name(), "mercury");
+        assert!(Network::from_str("testnet").is_err());
+        assert!(Network::from_str("").is_err());
+        let empty = Network::Custom {
+            name: "testnet-0".to_string(),
+            nns_urls: vec![],
+            root_key_der: None,
+        };
+        assert!(empty.get_url().is_err());
+    }
+
+    #[test]
+    fn mainnet_root_key() {
+        use base64::engine::general_purpose::STANDARD;
+        use base64::Engine;
+
+        assert_eq!(
+            STANDARD.encode(Network::Mainnet.root_key_der().unwrap()),
+            "MIGCMB0GDSsGAQQBgtx8BQMBAgEGDCsGAQQBgtx8BQMCAQNhAIFMDm7HH6tYOwi9gTc8JVw8NxsuhIY8mKTx4It0I10U+12cDNVG2WhfkToMCyzFNBWDv0tDkuRn25bWW5u0y3FxEvhHLg1aTRRQX/10hLASkQkcX4e5iINGP5gJGguqrg=="
+        );
+        assert_eq!(Network::Staging.root_key_der(), None);
+    }
+
+    #[test]
+    fn custom_networks_round_trip_through_json() {
+        let network = Network::Custom {
+            name: "testnet-1".to_string(),
+            nns_urls: vec![Url::parse("http://[2001:db8::1]:8080").unwrap()],
+            root_key_der: Some(vec![1, 2, 3]),
+        };
+        let json = serde_json::to_value(&network).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "custom": {
+                    "name": "testnet-1",
+                    "nns_urls": ["http://[2001:db8::1]:8080/"],
+                    "root_key_der": [1, 2, 3]
+                }
+            })
+        );
+        assert_eq!(serde_json::from_value::<Network>(json).unwrap(), network);
+        assert_eq!(network.root_key_der(), Some([1, 2, 3].as_slice()));
+        assert_eq!(network.to_string(), "testnet-1");
+
+        let without_key =
+            serde_json::json!({ "custom": { "name": "testnet-2", "nns_urls": ["http://[2001:db8::2]:8080/"] } });
+        let network = serde_json::from_value::<Network>(without_key.clone()).unwrap();
+        assert_eq!(network.root_key_der(), None);
+        assert_eq!(serde_json::to_value(&network).unwrap(), without_key);
+        let without_urls = serde_json::json!({ "custom": { "name": "testnet-3", "nns_urls": [] } });
+        assert!(serde_json::from_value::<Network>(without_urls).is_err());
+        assert_eq!(
+            serde_json::to_value(Network::Mainnet).unwrap(),
+            serde_json::json!("mainnet")
+        );
+    }
+
+    #[test]
+    fn subnet_kinds_from_registry_records() {
+        let subnet_id = PrincipalId::new_subnet_test_id(1);
+        assert_eq!(
+            SubnetKind::from_registry(subnet_id, SubnetTypePb::System as i32),
+            SubnetKind::System
+        );
+        assert_eq!(
+            SubnetKind::from_registry(subnet_id, SubnetTypePb::VerifiedApplication as i32),
+            SubnetKind::VerifiedApplication
+        );
+        assert_eq!(
+            SubnetKind::from_registry(subnet_id, 42),
+            SubnetKind::Other("subnet_type_42".to_string())
+        );
+        let european = PrincipalId::from_str(EUROPEAN_SUBNET_ID).unwrap();
+        assert_eq!(
+            SubnetKind::from_registry(european, SubnetTypePb::Application as i32),
+            SubnetKind::European
+        );
+        assert_eq!(SubnetKind::of_subnet(&european), Some(SubnetKind::European));
+        assert_eq!(SubnetKind::of_subnet(&subnet_id), None);
+    }
+
+    #[test]
+    fn unknown_subnet_kinds_deserialize() {
+        for kind in [
+            SubnetKind::System,
+            SubnetKind::Application,
+            SubnetKind::VerifiedApplication,
+            SubnetKind::European,
+            SubnetKind::Other("cloud_engine".to_string()),
+        ] {
+            let json = serde_json::to_value(&kind).unwrap();
+            assert_eq!(serde_json::from_value::<SubnetKind>(json).unwrap(), kind);
+        }
+        assert_eq!(
+            serde_json::from_str::<SubnetKind>(r#""cloud_engine""#).unwrap(),
+            SubnetKind::Other("cloud_engine".to_string())
+        );
+        assert_eq!(
+            serde_json::to_value(SubnetKind::VerifiedApplication).unwrap(),
+            serde_json::json!("verified_application")
+        );
+        assert_eq!("Verified".parse(), Ok(SubnetKind::VerifiedApplication));
+        assert!(serde_json::from_str::<SubnetKind>(r#""""#).is_err());
+
+        let mut subnet = serde_json::to_value(Subnet {
+            kind: SubnetKind::System,
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(subnet["kind"], serde_json::json!("system"));
+        subnet.as_object_mut().unwrap().remove("kind");
+        let subnet = serde_json::from_value::<Subnet>(subnet).unwrap();
+        assert_eq!(subnet.kind, SubnetKind::Application);
+    }
+
+    #[test]
+    fn subnet_kinds_have_rule_profiles() {
+        assert!(SubnetKind::European.rule_profile().european_nodes_only);
+        assert_eq!(SubnetKind::Application.rule_profile(), RuleProfile::default());
+        assert_eq!(
+            SubnetKind::Other("cloud_engine".to_string()).rule_profile(),
+            RuleProfile::default()
+        );
+        assert_eq!(
+            SubnetKind::European.registry_subnet_type(),
+            Some(SubnetType::Application)
+        );
+        assert_eq!(
+            SubnetKind::Other("cloud_engine".to_string()).registry_subnet_type(),
+            None
+        );
+    }
+}
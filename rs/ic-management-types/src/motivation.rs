@@ -0,0 +1,307 @@
+//! Motivations of the proposals, structured so that governance reviewers
+//! find the same information in the same place in every proposal. Requests
+//! still accept a bare string as the motivation, as the clients sent before.
+
+use std::collections::BTreeSet;
+use std::fmt::{Display, Write};
+
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::score::ScoreSummary;
+
+/// Why a proposal changes the nodes of the network.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum MotivationCategory {
+    /// Replacing unhealthy nodes.
+    Heal,
+    /// Improving the decentralization.
+    Optimize,
+    /// Removing nodes from the network.
+    Offboard,
+    /// Adding nodes to the network.
+    Onboard,
+    Other,
+}
+
+impl Display for MotivationCategory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Heal => write!(f, "Heal"),
+            Self::Optimize => write!(f, "Optimize"),
+            Self::Offboard => write!(f, "Offboard"),
+            Self::Onboard => write!(f, "Onboard"),
+            Self::Other => write!(f, "Other"),
+        }
+    }
+}
+
+/// The scores of a subnet before and after the proposed change.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ScoreSummaryDelta {
+    pub before: ScoreSummary,
+    pub after: ScoreSummary,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Motivation {
+    pub category: MotivationCategory,
+    pub details: String,
+    /// How the change affects the decentralization of the subnet.
+    #[serde(default)]
+    pub score_delta: Option<ScoreSummaryDelta>,
+    /// Links to e.g. the forum post or the incident of the change.
+    #[serde(default)]
+    #[cfg_attr(feature = "schema", schemars(with = "Vec<String>"))]
+    pub references: Vec<Url>,
+}
+
+impl Motivation {
+    pub fn new(category: MotivationCategory, details: impl Into<String>) -> Self {
+        Self {
+            category,
+            details: details.into(),
+            score_delta: None,
+            references: vec![],
+        }
+    }
+
+    pub fn with_score_delta(self, score_delta: ScoreSummaryDelta) -> Self {
+        Self {
+            score_delta: Some(score_delta),
+            ..self
+        }
+    }
+
+    pub fn with_reference(mut self, reference: Url) -> Self {
+        self.references.push(reference);
+        self
+    }
+
+    /// The motivation as placed into proposals: the category and details,
+    /// followed by a table of the scores before and after the change and the
+    /// list of references, if there are any.
+    pub fn render_markdown(&self) -> String {
+        let mut text = match self.details.trim() {
+            "" => format!("**{}**", self.category),
+            details => format!("**{}:** {}", self.category, details),
+        };
+        if let Some(delta) = &self.score_delta {
+            text.push_str("\n\nDecentralization impact:\n\n| | Before | After |\n| --- | ---: | ---: |\n");
+            let features = delta
+                .before
+                .coefficients
+                .keys()
+                .chain(delta.after.coefficients.keys())
+                .collect::<BTreeSet<_>>();
+            let coefficient = |summary: &ScoreSummary, feature| {
+                summary
+                    .coefficients
+                    .get(feature)
+                    .map_or("-".to_string(), |c| c.to_string())
+            };
+            for feature in features {
+                let _ = writeln!(
+                    text,
+                    "| {} | {} | {} |",
+                    feature,
+                    coefficient(&delta.before, feature),
+                    coefficient(&delta.after, feature)
+                );
+            }
+            let _ = writeln!(text, "| minimum | {} | {} |", delta.before.min, delta.after.min);
+            let _ = writeln!(
+                text,
+                "| linear average | {:.2} | {:.2} |",
+                delta.before.average_linear, delta.after.average_linear
+            );
+            let log2 = |average: Option<f64>| average.map_or("-".to_string(), |a| format!("{:.2}", a));
+            let _ = write!(
+                text,
+                "| log2 average | {} | {} |",
+                log2(delta.before.average_log2),
+                log2(delta.after.average_log2)
+            );
+        }
+        if !self.references.is_empty() {
+            text.push_str("\n\nReferences:\n");
+            for reference in &self.references {
+                let _ = write!(text, "\n- {}", reference);
+            }
+        }
+        text
+    }
+}
+
+/// A motivation in a request: structured, or a bare string as the clients
+/// sent before.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(untagged)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum MotivationInput {
+    Structured(Motivation),
+    Text(String),
+}
+
+impl MotivationInput {
+    /// The motivation as placed into proposals. Bare strings are taken as
+    /// they are.
+    pub fn render_markdown(&self) -> String {
+        match self {
+            Self::Structured(motivation) => motivation.render_markdown(),
+            Self::Text(text) => text.clone(),
+        }
+    }
+
+    /// Whether there are no details, for a structured motivation, or no
+    /// text.
+    pub fn is_empty(&self) -> bool {
+        match self {
+            Self::Structured(motivation) => motivation.details.trim().is_empty(),
+            Self::Text(text) => text.trim().is_empty(),
+        }
+    }
+
+    /// Add the score delta to a structured motivation that has none yet.
+    pub fn with_default_score_delta(self, score_delta: ScoreSummaryDelta) -> Self {
+        match self {
+            Self::Structured(motivation) if motivation.score_delta.is_none() => {
+                Self::Structured(motivation.with_score_delta(score_delta))
+            }
+            other => other,
+        }
+    }
+}
+
+impl Default for MotivationInput {
+    fn default() -> Self {
+        Self::Text(String::new())
+    }
+}
+
+impl From<Motivation> for MotivationInput {
+    fn from(motivation: Motivation) -> Self {
+        Self::Structured(motivation)
+    }
+}
+
+impl From<String> for MotivationInput {
+    fn from(text: String) -> Self {
+        Self::Text(text)
+    }
+}
+
+impl From<&str> for MotivationInput {
+    fn from(text: &str) -> Self {
+        Self::Text(text.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::NodeFeature;
+
+    fn summary(node_provider: u64, country: u64) -> ScoreSummary {
+        ScoreSummary {
+            coefficients: BTreeMap::from([
+                (NodeFeature::NodeProvider, node_provider),
+                (NodeFeature::Country, country),
+            ]),
+            min: node_provider.min(country),
+            average_linear: (node_provider + country) as f64 / 2.,
+            average_log2: Some(((node_provider as f64).log2() + (country as f64).log2()) / 2.),
+            ..Default::default()
+        }
+    }
+
+    fn motivation() -> Motivation {
+        Motivation::new(MotivationCategory::Heal, "Replacing 1 dead node")
+            .with_score_delta(ScoreSummaryDelta {
+                before: summary(3, 2),
+                after: summary(4, 3),
+            })
+            .with_reference("https://forum.dfinity.org/t/subnet-management/20000".parse().unwrap())
+    }
+
+    #[test]
+    fn motivations_are_rendered_as_markdown() {
+        assert_eq!(
+            motivation().render_markdown(),
+            include_str!("testdata/motivation.md").trim_end()
+        );
+        assert_eq!(
+            Motivation::new(MotivationCategory::Offboard, " ").render_markdown(),
+            "**Offboard**"
+        );
+        assert_eq!(
+            MotivationInput::from("Moving nodes\n to another DC").render_markdown(),
+            "Moving nodes\n to another DC"
+        );
+    }
+
+    #[test]
+    fn structured_motivations_and_bare_strings_are_accepted() {
+        let input = serde_json::from_value::<MotivationInput>(json!("Broken disks")).unwrap();
+        assert_eq!(input, MotivationInput::Text("Broken disks".to_string()));
+        assert_eq!(serde_json::to_value(&input).unwrap(), json!("Broken disks"));
+
+        let input = serde_json::from_value::<MotivationInput>(json!({
+            "category": "offboard",
+            "details": "The provider leaves the network",
+        }))
+        .unwrap();
+        assert_eq!(
+            input,
+            MotivationInput::Structured(Motivation::new(
+                MotivationCategory::Offboard,
+                "The provider leaves the network"
+            ))
+        );
+
+        let input = MotivationInput::from(motivation());
+        let serialized = serde_json::to_value(&input).unwrap();
+        assert_eq!(serialized["category"], json!("heal"));
+        assert_eq!(
+            serialized["references"],
+            json!(["https://forum.dfinity.org/t/subnet-management/20000"])
+        );
+        assert_eq!(serde_json::from_value::<MotivationInput>(serialized).unwrap(), input);
+
+        for invalid in [
+            json!({ "category": "vacation", "details": "" }),
+            json!({ "details": "No category" }),
+            json!(42),
+        ] {
+            assert!(
+                serde_json::from_value::<MotivationInput>(invalid.clone()).is_err(),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn score_deltas_are_only_added_to_structured_motivations_without_one() {
+        let delta = ScoreSummaryDelta::default();
+        let input = MotivationInput::from(Motivation::new(MotivationCategory::Heal, "Dead node"))
+            .with_default_score_delta(delta.clone());
+        assert!(matches!(&input, MotivationInput::Structured(m) if m.score_delta == Some(delta.clone())));
+        assert_eq!(
+            MotivationInput::from(motivation()).with_default_score_delta(delta.clone()),
+            MotivationInput::from(motivation())
+        );
+        assert_eq!(
+            MotivationInput::from("Dead node").with_default_score_delta(delta),
+            MotivationInput::from("Dead node")
+        );
+    }
+}
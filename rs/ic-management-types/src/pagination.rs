@@ -0,0 +1,155 @@
+//! Pages of the listings of the management API, so that large listings such
+//! as the nodes of mainnet are not sent in a single response.
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::ops::Bound;
+use std::str::FromStr;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+use crate::NetworkError;
+
+/// The number of items in a page unless the request asks otherwise.
+pub const DEFAULT_PAGE_LIMIT: usize = 500;
+/// The largest number of items in a page, whatever the request asks for.
+pub const MAX_PAGE_LIMIT: usize = 5_000;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// The cursor of the next page, if there is one.
+    #[serde(default)]
+    pub next_cursor: Option<String>,
+    /// The number of items in all pages, if known.
+    #[serde(default)]
+    pub total: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct PageRequest {
+    /// The `next_cursor` of the previous page, or none for the first page.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<usize>,
+}
+
+impl PageRequest {
+    pub fn after(cursor: Option<String>) -> Self {
+        Self { cursor, limit: None }
+    }
+
+    /// The number of items of the requested page, [DEFAULT_PAGE_LIMIT] if
+    /// not given and at most [MAX_PAGE_LIMIT].
+    pub fn limit(&self) -> usize {
+        self.limit.unwrap_or(DEFAULT_PAGE_LIMIT).clamp(1, MAX_PAGE_LIMIT)
+    }
+}
+
+/// The opaque cursor pointing after the item with the sort key `key`.
+pub fn encode_cursor(key: &impl Display) -> String {
+    URL_SAFE_NO_PAD.encode(key.to_string())
+}
+
+/// The sort key of the item `cursor` points after.
+pub fn decode_cursor<K: FromStr>(cursor: &str) -> Result<K, NetworkError> {
+    URL_SAFE_NO_PAD
+        .decode(cursor)
+        .ok()
+        .and_then(|key| String::from_utf8(key).ok())
+        .and_then(|key| K::from_str(&key).ok())
+        .ok_or_else(|| NetworkError::IllegalRequest(format!("Invalid page cursor {}", cursor)))
+}
+
+/// The page of `items`, in the order of their keys, requested by `request`.
+pub fn paginate<K, T>(items: &BTreeMap<K, T>, request: &PageRequest) -> Result<Page<T>, NetworkError>
+where
+    K: Ord + Display + FromStr,
+    T: Clone,
+{
+    let after = match &request.cursor {
+        Some(cursor) => Bound::Excluded(decode_cursor::<K>(cursor)?),
+        None => Bound::Unbounded,
+    };
+    let mut remaining = items.range((after, Bound::Unbounded));
+    let page = remaining.by_ref().take(request.limit()).collect::<Vec<_>>();
+    let next_cursor = match (remaining.next(), page.last()) {
+        (Some(_), Some((last, _))) => Some(encode_cursor(last)),
+        _ => None,
+    };
+    Ok(Page {
+        items: page.into_iter().map(|(_, item)| item.clone()).collect(),
+        next_cursor,
+        total: Some(items.len()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use ic_base_types::PrincipalId;
+
+    use super::*;
+
+    #[test]
+    fn cursors_round_trip() {
+        let principal = PrincipalId::new_node_test_id(7);
+        let cursor = encode_cursor(&principal);
+        assert_ne!(cursor, principal.to_string());
+        assert_eq!(decode_cursor::<PrincipalId>(&cursor).unwrap(), principal);
+        assert_eq!(decode_cursor::<u64>(&encode_cursor(&42)).unwrap(), 42);
+
+        assert!(matches!(
+            decode_cursor::<PrincipalId>("not base64!"),
+            Err(NetworkError::IllegalRequest(_))
+        ));
+        assert!(matches!(
+            decode_cursor::<PrincipalId>(&encode_cursor(&"not a principal")),
+            Err(NetworkError::IllegalRequest(_))
+        ));
+    }
+
+    #[test]
+    fn limits_default_and_are_bounded() {
+        assert_eq!(PageRequest::default().limit(), DEFAULT_PAGE_LIMIT);
+        let request = |limit| PageRequest {
+            cursor: None,
+            limit: Some(limit),
+        };
+        assert_eq!(request(10).limit(), 10);
+        assert_eq!(request(0).limit(), 1);
+        assert_eq!(request(MAX_PAGE_LIMIT + 1).limit(), MAX_PAGE_LIMIT);
+    }
+
+    #[test]
+    fn concatenated_pages_equal_all_items() {
+        for size in 0..20 {
+            let items = (0..size)
+                .map(|i| (PrincipalId::new_node_test_id(i), i))
+                .collect::<BTreeMap<_, _>>();
+            for limit in 1..=size as usize + 1 {
+                let mut request = PageRequest {
+                    cursor: None,
+                    limit: Some(limit),
+                };
+                let mut pages = vec![];
+                loop {
+                    let page = paginate(&items, &request).unwrap();
+                    assert!(page.items.len() <= limit);
+                    assert_eq!(page.total, Some(items.len()));
+                    pages.push(page.items);
+                    match page.next_cursor {
+                        Some(cursor) => request.cursor = Some(cursor),
+                        None => break,
+                    }
+                }
+                assert!(pages.iter().all(|p| !p.is_empty()) || size == 0);
+                assert_eq!(pages.concat(), items.values().cloned().collect::<Vec<_>>());
+            }
+        }
+    }
+}
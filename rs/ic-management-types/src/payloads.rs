@@ -0,0 +1,214 @@
+//! Candid payloads of the proposals the management API prepares, for
+//! submitting them to governance directly rather than through the backend or
+//! ic-admin. The payloads are Candid encoded as they are, and convert into
+//! the payloads of the NNS functions the registry canister executes.
+
+use candid::CandidType;
+use ic_base_types::{NodeId, PrincipalId};
+use ic_registry_subnet_type::SubnetType;
+use registry_canister::mutations::do_change_subnet_membership::ChangeSubnetMembershipPayload;
+use registry_canister::mutations::do_create_subnet::CreateSubnetPayload;
+use registry_canister::mutations::do_update_elected_replica_versions::UpdateElectedReplicaVersionsPayload;
+use serde::{Deserialize, Serialize};
+
+use crate::requests::{MembershipChangePayload, SubnetCreateRequest};
+use crate::NetworkError;
+
+impl From<&MembershipChangePayload> for ChangeSubnetMembershipPayload {
+    fn from(payload: &MembershipChangePayload) -> Self {
+        Self {
+            subnet_id: payload.subnet_id,
+            node_ids_add: payload.node_ids_add.iter().copied().map(NodeId::from).collect(),
+            node_ids_remove: payload.node_ids_remove.iter().copied().map(NodeId::from).collect(),
+        }
+    }
+}
+
+impl From<&ChangeSubnetMembershipPayload> for MembershipChangePayload {
+    fn from(payload: &ChangeSubnetMembershipPayload) -> Self {
+        Self {
+            subnet_id: payload.subnet_id,
+            node_ids_add: payload.node_ids_add.iter().map(|node_id| node_id.get()).collect(),
+            node_ids_remove: payload.node_ids_remove.iter().map(|node_id| node_id.get()).collect(),
+        }
+    }
+}
+
+/// The parameters of a subnet to create out of the chosen nodes. The other
+/// parameters of the subnet keep the defaults of the registry.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SubnetCreationPayload {
+    pub node_ids: Vec<PrincipalId>,
+    pub replica_version_id: String,
+    pub subnet_type: SubnetType,
+}
+
+impl SubnetCreationPayload {
+    /// The payload creating the subnet of `request` out of `node_ids`, with
+    /// the replica version of the request or else `nns_replica_version`.
+    pub fn new(
+        request: &SubnetCreateRequest,
+        node_ids: Vec<PrincipalId>,
+        nns_replica_version: &str,
+    ) -> Result<Self, NetworkError> {
+        let subnet_kind = request.subnet_kind.clone().unwrap_or_default();
+        let subnet_type = subnet_kind
+            .registry_subnet_type()
+            .ok_or_else(|| NetworkError::IllegalRequest(format!("Cannot create a subnet of kind '{}'", subnet_kind)))?;
+        Ok(Self {
+            node_ids,
+            replica_version_id: request
+                .replica_version
+                .clone()
+                .unwrap_or_else(|| nns_replica_version.to_string()),
+            subnet_type,
+        })
+    }
+}
+
+impl From<&SubnetCreationPayload> for CreateSubnetPayload {
+    fn from(payload: &SubnetCreationPayload) -> Self {
+        Self {
+            node_ids: payload.node_ids.iter().copied().map(NodeId::from).collect(),
+            replica_version_id: payload.replica_version_id.clone(),
+            subnet_type: payload.subnet_type,
+            ..Default::default()
+        }
+    }
+}
+
+/// Elect a replica version and retire others, as proposed when releasing a
+/// new version.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ReplicaVersionElectionPayload {
+    pub version: String,
+    pub release_package_sha256_hex: String,
+    pub release_package_urls: Vec<String>,
+    pub versions_to_unelect: Vec<String>,
+}
+
+impl From<&ReplicaVersionElectionPayload> for UpdateElectedReplicaVersionsPayload {
+    fn from(payload: &ReplicaVersionElectionPayload) -> Self {
+        Self {
+            replica_version_to_elect: Some(payload.version.clone()),
+            release_package_sha256_hex: Some(payload.release_package_sha256_hex.clone()),
+            release_package_urls: payload.release_package_urls.clone(),
+            replica_versions_to_unelect: payload.versions_to_unelect.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use candid::{Decode, Encode};
+    use ic_nns_governance::pb::v1::NnsFunction;
+
+    use super::*;
+    use crate::{NnsFunctionProposal, SubnetKind, TopologyChangePayload};
+
+    fn node(id: u64) -> PrincipalId {
+        PrincipalId::new_node_test_id(id)
+    }
+
+    fn membership_change() -> MembershipChangePayload {
+        MembershipChangePayload {
+            subnet_id: PrincipalId::new_subnet_test_id(1),
+            node_ids_add: vec![node(3), node(4)],
+            node_ids_remove: vec![node(1)],
+        }
+    }
+
+    fn election() -> ReplicaVersionElectionPayload {
+        ReplicaVersionElectionPayload {
+            version: "0123456789abcdef0123456789abcdef01234567".to_string(),
+            release_package_sha256_hex: "ab".repeat(32),
+            release_package_urls: vec!["https://download.dfinity.systems/update-img.tar.zst".to_string()],
+            versions_to_unelect: vec!["fedcba9876543210fedcba9876543210fedcba98".to_string()],
+        }
+    }
+
+    #[test]
+    fn payloads_round_trip_through_candid() {
+        let payload = membership_change();
+        let encoded = Encode!(&payload).unwrap();
+        assert_eq!(Decode!(&encoded, MembershipChangePayload).unwrap(), payload);
+
+        let payload = SubnetCreationPayload {
+            node_ids: vec![node(1), node(2)],
+            replica_version_id: "0123456789abcdef".to_string(),
+            subnet_type: SubnetType::VerifiedApplication,
+        };
+        let encoded = Encode!(&payload).unwrap();
+        assert_eq!(Decode!(&encoded, SubnetCreationPayload).unwrap(), payload);
+
+        let payload = election();
+        let encoded = Encode!(&payload).unwrap();
+        assert_eq!(Decode!(&encoded, ReplicaVersionElectionPayload).unwrap(), payload);
+    }
+
+    #[test]
+    fn membership_changes_are_read_by_governance() {
+        let payload = membership_change();
+        let encoded = Encode!(&ChangeSubnetMembershipPayload::from(&payload)).unwrap();
+        let decoded = ChangeSubnetMembershipPayload::decode(NnsFunction::ChangeSubnetMembership, &encoded).unwrap();
+        assert_eq!(decoded.get_subnet(), Some(payload.subnet_id));
+        assert_eq!(decoded.get_added_node_ids(), payload.node_ids_add);
+        assert_eq!(decoded.get_removed_node_ids(), payload.node_ids_remove);
+        assert_eq!(MembershipChangePayload::from(&decoded), payload);
+    }
+
+    #[test]
+    fn subnet_creations_are_read_by_governance() {
+        let request = SubnetCreateRequest::builder(2)
+            .subnet_kind(SubnetKind::European)
+            .build()
+            .unwrap();
+        let payload = SubnetCreationPayload::new(&request, vec![node(1), node(2)], "nns-version").unwrap();
+        assert_eq!(payload.replica_version_id, "nns-version");
+        assert_eq!(payload.subnet_type, SubnetType::Application);
+
+        let encoded = Encode!(&CreateSubnetPayload::from(&payload)).unwrap();
+        let decoded = CreateSubnetPayload::decode(NnsFunction::CreateSubnet, &encoded).unwrap();
+        assert_eq!(decoded.get_added_node_ids(), vec![node(1), node(2)]);
+        assert_eq!(decoded.replica_version_id, "nns-version");
+        assert_eq!(decoded.subnet_type, SubnetType::Application);
+
+        let request = SubnetCreateRequest::builder(2)
+            .replica_version("0123456789abcdef".to_string())
+            .build()
+            .unwrap();
+        let payload = SubnetCreationPayload::new(&request, vec![node(1)], "nns-version").unwrap();
+        assert_eq!(payload.replica_version_id, "0123456789abcdef");
+
+        // As received by the backend, not checked when built.
+        let request: SubnetCreateRequest = serde_json::from_value(serde_json::json!({
+            "size": 1,
+            "min_nakamoto_coefficients": null,
+            "exclude": null,
+            "only": null,
+            "include": null,
+            "subnet_kind": "cloud_engine",
+        }))
+        .unwrap();
+        assert!(matches!(
+            SubnetCreationPayload::new(&request, vec![node(1)], "nns-version"),
+            Err(NetworkError::IllegalRequest(_))
+        ));
+    }
+
+    #[test]
+    fn version_elections_are_read_by_governance() {
+        let payload = election();
+        let encoded = Encode!(&UpdateElectedReplicaVersionsPayload::from(&payload)).unwrap();
+        let decoded =
+            UpdateElectedReplicaVersionsPayload::decode(NnsFunction::UpdateElectedReplicaVersions, &encoded).unwrap();
+        assert_eq!(decoded.replica_version_to_elect, Some(payload.version));
+        assert_eq!(
+            decoded.release_package_sha256_hex,
+            Some(payload.release_package_sha256_hex)
+        );
+        assert_eq!(decoded.release_package_urls, payload.release_package_urls);
+        assert_eq!(decoded.replica_versions_to_unelect, payload.versions_to_unelect);
+    }
+}
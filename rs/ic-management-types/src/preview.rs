@@ -0,0 +1,361 @@
+//! What a change to the membership of a subnet does, in the form the backend
+//! returns it, the CLI shows it to the operator and the change is recorded
+//! once proposed.
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+
+use colored::Colorize;
+use ic_types::PrincipalId;
+use itertools::{EitherOrBoth::*, Itertools};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::requests::{MembershipChangeResponse, NodeSummary};
+use crate::score::ScoreSummary;
+use crate::NodeFeature;
+
+/// The nodes a change adds to and removes from a subnet, how it changes the
+/// decentralization of the subnet and why it is made.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ChangePreview {
+    /// None if the change is not to an existing subnet, e.g. when creating
+    /// one.
+    #[cfg_attr(feature = "schema", schemars(with = "Option<String>"))]
+    pub subnet_id: Option<PrincipalId>,
+    pub added: Vec<NodeSummary>,
+    pub removed: Vec<NodeSummary>,
+    pub score_before: ScoreSummary,
+    pub score_after: ScoreSummary,
+    /// For each feature, the number of nodes having each value before and
+    /// after the change.
+    pub feature_diff: BTreeMap<NodeFeature, BTreeMap<String, (usize, usize)>>,
+    /// The business rules the subnet breaks after the change.
+    #[serde(default)]
+    pub warnings: Vec<String>,
+    pub comment: Option<String>,
+    /// The motivation of the proposal, empty if not known.
+    #[serde(default)]
+    pub motivation: String,
+    /// Hex encoded SHA-256 of the subnet and of the added and removed nodes,
+    /// identifying the change independently of its scores and motivation.
+    pub content_hash: String,
+}
+
+impl ChangePreview {
+    pub fn new(
+        subnet_id: Option<PrincipalId>,
+        added: Vec<NodeSummary>,
+        removed: Vec<NodeSummary>,
+        score_before: ScoreSummary,
+        score_after: ScoreSummary,
+        feature_diff: BTreeMap<NodeFeature, BTreeMap<String, (usize, usize)>>,
+    ) -> Self {
+        let content_hash = content_hash(subnet_id, &added, &removed);
+        Self {
+            subnet_id,
+            added,
+            removed,
+            score_before,
+            score_after,
+            feature_diff,
+            warnings: vec![],
+            comment: None,
+            motivation: String::new(),
+            content_hash,
+        }
+    }
+
+    pub fn with_warnings(self, warnings: Vec<String>) -> Self {
+        Self { warnings, ..self }
+    }
+
+    pub fn with_comment(self, comment: Option<String>) -> Self {
+        Self { comment, ..self }
+    }
+
+    pub fn with_motivation(self, motivation: String) -> Self {
+        Self { motivation, ..self }
+    }
+}
+
+/// The hash does not depend on the order of the nodes.
+fn content_hash(subnet_id: Option<PrincipalId>, added: &[NodeSummary], removed: &[NodeSummary]) -> String {
+    let principals = |nodes: &[NodeSummary]| {
+        nodes
+            .iter()
+            .map(|n| n.principal.to_string())
+            .sorted()
+            .collect::<Vec<_>>()
+    };
+    let content = serde_json::json!({
+        "subnet_id": subnet_id.map(|id| id.to_string()),
+        "added": principals(added),
+        "removed": principals(removed),
+    });
+    hex::encode(Sha256::digest(content.to_string()))
+}
+
+/// Business rule warnings are not known to the clients of the backend.
+impl From<&MembershipChangeResponse> for ChangePreview {
+    fn from(change: &MembershipChangeResponse) -> Self {
+        Self::new(
+            change.subnet_id,
+            change.added.clone(),
+            change.removed.clone(),
+            change.score_before.clone(),
+            change.score_after.clone(),
+            change.feature_diff.clone(),
+        )
+        .with_comment(change.comment.clone())
+        .with_motivation(change.motivation.clone())
+    }
+}
+
+/// Displays the score changes, the feature values and the moved nodes, as the
+/// CLI shows them before proposing the change.
+impl Display for ChangePreview {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Decentralization score changes:\n")?;
+        let before_individual = &self.score_before.coefficients;
+        let after_individual = &self.score_after.coefficients;
+        before_individual
+            .keys()
+            .sorted()
+            .map(|k| {
+                let before = *before_individual.get(k).unwrap() as f64;
+                let after = *after_individual.get(k).unwrap() as f64;
+                let headroom = self
+                    .score_after
+                    .headroom
+                    .get(k)
+                    .map(|h| h.to_string())
+                    .unwrap_or_else(|| "-".to_string());
+                let output = format!(
+                    "{}: {:.2} -> {:.2}  {:>7}  headroom {:>2}",
+                    k,
+                    before,
+                    after,
+                    format_args!("({:+.0}%)", ((after - before) / before) * 100.).to_string(),
+                    headroom
+                );
+                if before > after {
+                    output.bright_red()
+                } else if after > before {
+                    output.bright_green()
+                } else {
+                    output.dimmed()
+                }
+            })
+            .for_each(|s| writeln!(f, "{: >40}", s).expect("write failed"));
+
+        let total_before = self.score_before.average_linear;
+        let total_after = self.score_after.average_linear;
+        let output = format!(
+            "\tTotal: {:.2} -> {:.2}  ({:+.0}%)",
+            total_before,
+            total_after,
+            ((total_after - total_before) / total_before) * 100.
+        )
+        .bold();
+
+        writeln!(
+            f,
+            "\n{}\n",
+            if total_before > total_after {
+                output.red()
+            } else if total_after > total_before {
+                output.green()
+            } else {
+                output.dimmed()
+            }
+        )?;
+
+        let rows = self.feature_diff.values().map(|diff| diff.len()).max().unwrap_or(0);
+        let mut table = tabular::Table::new(
+            &self
+                .feature_diff
+                .keys()
+                .map(|_| "    {:<}  {:>}")
+                .collect::<Vec<_>>()
+                .join(""),
+        );
+        table.add_row(
+            self.feature_diff
+                .keys()
+                .fold(tabular::Row::new(), |acc, k| acc.with_cell(k.to_string()).with_cell("")),
+        );
+        table.add_row(self.feature_diff.keys().fold(tabular::Row::new(), |acc, k| {
+            acc.with_cell("-".repeat(k.to_string().len())).with_cell("")
+        }));
+        for i in 0..rows {
+            table.add_row(self.feature_diff.values().fold(tabular::Row::new(), |acc, v| {
+                let (value, diff) = v
+                    .iter()
+                    .sorted()
+                    .nth(i)
+                    .map(|(k, (before, after))| {
+                        (
+                            k.to_string(),
+                            match before.cmp(after) {
+                                std::cmp::Ordering::Equal => format!("{}", before),
+                                std::cmp::Ordering::Greater => format!("{} -> {}", before, after),
+                                std::cmp::Ordering::Less => format!("{} -> {}", before, after),
+                            },
+                        )
+                    })
+                    .unwrap_or_default();
+                acc.with_cell(value).with_cell(diff)
+            }));
+        }
+
+        writeln!(f, "{}", table)?;
+        for pair in self.added.iter().zip_longest(self.removed.iter()) {
+            match pair {
+                Both(a, r) => {
+                    writeln!(
+                        f,
+                        "{}{}",
+                        format!("  - {}", r.principal).red(),
+                        format!("    + {}", a.principal).green()
+                    )
+                    .expect("write failed");
+                }
+                Left(a) => {
+                    writeln!(
+                        f,
+                        "                                                                   {}",
+                        format!("    + {}", a.principal).green()
+                    )
+                    .expect("write failed");
+                }
+                Right(r) => {
+                    writeln!(f, "{}", format!("  - {}", r.principal).red()).expect("write failed");
+                }
+            }
+        }
+        writeln!(f)?;
+
+        if let Some(comment) = &self.comment {
+            writeln!(f, "{}", format!("*** Note ***\n{}", comment).red())?;
+        }
+        for warning in &self.warnings {
+            writeln!(f, "{}", format!("Warning: {}", warning).yellow())?;
+        }
+        if !self.motivation.is_empty() {
+            writeln!(f, "Motivation: {}", self.motivation)?;
+        }
+        writeln!(f, "{}", format!("Change {}", self.content_hash).dimmed())?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change_preview() -> ChangePreview {
+        let node = |id: u64, country: &str| NodeSummary {
+            principal: PrincipalId::new_node_test_id(id),
+            features: BTreeMap::from([
+                (NodeFeature::NodeProvider, format!("provider {}", id)),
+                (NodeFeature::Country, country.to_string()),
+            ]),
+        };
+        let score = |country: u64| ScoreSummary {
+            coefficients: BTreeMap::from([(NodeFeature::NodeProvider, 2), (NodeFeature::Country, country)]),
+            min: 1,
+            average_linear: (2 + country) as f64 / 2.0,
+            average_log2: Some(1.0),
+            critical_features: vec![NodeFeature::NodeProvider, NodeFeature::Country],
+            controlled_nodes: BTreeMap::from([(NodeFeature::NodeProvider, 2), (NodeFeature::Country, 2)]),
+            headroom: BTreeMap::from([(NodeFeature::NodeProvider, 1), (NodeFeature::Country, 0)]),
+        };
+        ChangePreview::new(
+            Some(PrincipalId::new_subnet_test_id(1)),
+            vec![node(3, "JP"), node(4, "US")],
+            vec![node(1, "CH")],
+            score(1),
+            score(2),
+            BTreeMap::from([(
+                NodeFeature::Country,
+                BTreeMap::from([
+                    ("CH".to_string(), (2, 1)),
+                    ("JP".to_string(), (0, 1)),
+                    ("US".to_string(), (0, 1)),
+                ]),
+            )]),
+        )
+        .with_warnings(vec!["Subnet should have at least 1 DFINITY-owned node".to_string()])
+        .with_motivation("replacing 1 unhealthy node".to_string())
+    }
+
+    /// Changing the wire format breaks the clients of the backend and the
+    /// recorded changes, update the snapshot only along with them.
+    #[test]
+    fn change_preview_wire_format_snapshot() {
+        let preview = change_preview();
+        let serialized = serde_json::to_string_pretty(&preview).unwrap() + "\n";
+        assert_eq!(serialized, include_str!("testdata/change_preview.json"));
+        assert_eq!(serde_json::from_str::<ChangePreview>(&serialized).unwrap(), preview);
+    }
+
+    #[test]
+    fn change_preview_display_snapshot() {
+        colored::control::set_override(false);
+        assert_eq!(
+            change_preview().to_string(),
+            include_str!("testdata/change_preview_display.txt")
+        );
+    }
+
+    #[test]
+    fn content_hash_only_depends_on_the_moved_nodes() {
+        let preview = change_preview();
+        let mut reordered = preview.clone();
+        reordered.added.reverse();
+        let reordered = ChangePreview::new(
+            reordered.subnet_id,
+            reordered.added,
+            reordered.removed,
+            ScoreSummary::default(),
+            ScoreSummary::default(),
+            BTreeMap::new(),
+        );
+        assert_eq!(reordered.content_hash, preview.content_hash);
+
+        let other_subnet = ChangePreview::new(
+            Some(PrincipalId::new_subnet_test_id(2)),
+            preview.added.clone(),
+            preview.removed.clone(),
+            preview.score_before.clone(),
+            preview.score_after.clone(),
+            preview.feature_diff.clone(),
+        );
+        assert_ne!(other_subnet.content_hash, preview.content_hash);
+        let swapped = ChangePreview::new(
+            preview.subnet_id,
+            preview.removed.clone(),
+            preview.added.clone(),
+            preview.score_before.clone(),
+            preview.score_after.clone(),
+            preview.feature_diff.clone(),
+        );
+        assert_ne!(swapped.content_hash, preview.content_hash);
+    }
+
+    #[test]
+    fn membership_changes_are_previewed() {
+        let change: MembershipChangeResponse =
+            serde_json::from_str(include_str!("testdata/membership_change_response.json")).unwrap();
+        let preview = ChangePreview::from(&change);
+        assert_eq!(preview.subnet_id, change.subnet_id);
+        assert_eq!(preview.added, change.added);
+        assert_eq!(preview.removed, change.removed);
+        assert_eq!(preview.motivation, change.motivation);
+        assert!(preview.warnings.is_empty());
+        assert_eq!(preview.content_hash.len(), 64);
+    }
+}
@@ -0,0 +1,430 @@
+//! The proposals the backend and the CLI keep track of, read from the
+//! governance canister but without its protobuf types.
+
+use ic_nns_governance::pb::v1 as pb;
+use ic_nns_governance::pb::v1::proposal::Action;
+use ic_types::PrincipalId;
+use registry_canister::mutations::do_change_subnet_membership::ChangeSubnetMembershipPayload;
+use registry_canister::mutations::do_update_subnet_replica::UpdateSubnetReplicaVersionPayload;
+use serde::{Deserialize, Serialize};
+use strum_macros::Display;
+
+use crate::timestamp::Timestamp;
+use crate::{NnsFunctionProposal, TopologyChangePayload};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ProposalInfo {
+    pub id: u64,
+    /// The name of the governance topic, e.g. `SubnetManagement`.
+    pub topic: String,
+    pub status: ProposalStatus,
+    pub summary: String,
+    pub payload_kind: PayloadKind,
+    /// The subnets the proposal changes, as far as the payload tells.
+    #[cfg_attr(feature = "schema", schemars(with = "Vec<String>"))]
+    pub affected_subnets: Vec<PrincipalId>,
+    pub submitted_at: Timestamp,
+    /// When the proposal was adopted or rejected, if it was.
+    pub decided_at: Option<Timestamp>,
+}
+
+#[derive(Display, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum ProposalStatus {
+    Open,
+    Adopted,
+    Rejected,
+    Executed,
+    Failed,
+}
+
+impl ProposalStatus {
+    /// Whether the proposal will not change anymore.
+    pub fn is_final(&self) -> bool {
+        matches!(self, Self::Rejected | Self::Executed | Self::Failed)
+    }
+}
+
+impl TryFrom<pb::ProposalStatus> for ProposalStatus {
+    type Error = String;
+
+    fn try_from(value: pb::ProposalStatus) -> Result<Self, Self::Error> {
+        match value {
+            pb::ProposalStatus::Open => Ok(Self::Open),
+            pb::ProposalStatus::Adopted => Ok(Self::Adopted),
+            pb::ProposalStatus::Rejected => Ok(Self::Rejected),
+            pb::ProposalStatus::Executed => Ok(Self::Executed),
+            pb::ProposalStatus::Failed => Ok(Self::Failed),
+            pb::ProposalStatus::Unspecified => Err("unspecified proposal status".to_string()),
+        }
+    }
+}
+
+/// The kind of payload of a proposal, for the payloads the tooling submits.
+#[derive(Display, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum PayloadKind {
+    ChangeSubnetMembership,
+    UpdateSubnetReplicaVersion,
+    ElectReplicaVersions,
+    Other,
+}
+
+impl From<pb::NnsFunction> for PayloadKind {
+    fn from(function: pb::NnsFunction) -> Self {
+        match function {
+            pb::NnsFunction::ChangeSubnetMembership => Self::ChangeSubnetMembership,
+            pb::NnsFunction::UpdateSubnetReplicaVersion => Self::UpdateSubnetReplicaVersion,
+            pb::NnsFunction::UpdateElectedReplicaVersions => Self::ElectReplicaVersions,
+            _ => Self::Other,
+        }
+    }
+}
+
+fn timestamp(seconds: u64) -> Option<Timestamp> {
+    match seconds {
+        0 => None,
+        seconds => Timestamp::from_unix_seconds(seconds as i64),
+    }
+}
+
+impl TryFrom<&pb::ProposalInfo> for ProposalInfo {
+    type Error = String;
+
+    fn try_from(info: &pb::ProposalInfo) -> Result<Self, Self::Error> {
+        let id = info.id.ok_or("proposal without an id")?.id;
+        let status = pb::ProposalStatus::try_from(info.status)
+            .map_err(|_| format!("unknown status {} of proposal {}", info.status, id))
+            .and_then(ProposalStatus::try_from)?;
+        let topic = pb::Topic::try_from(info.topic)
+            .map(|topic| format!("{:?}", topic))
+            .unwrap_or_else(|_| format!("Topic{}", info.topic));
+        let proposal = info.proposal.as_ref();
+
+        let (payload_kind, affected_subnets) = match proposal.and_then(|p| p.action.as_ref()) {
+            Some(Action::ExecuteNnsFunction(function)) => match pb::NnsFunction::try_from(function.nns_function) {
+                Ok(nns_function) => {
+                    let payload = function.payload.as_slice();
+                    let affected_subnets = match nns_function {
+                        pb::NnsFunction::ChangeSubnetMembership => {
+                            ChangeSubnetMembershipPayload::decode(nns_function, payload)
+                                .ok()
+                                .and_then(|p| p.get_subnet())
+                        }
+                        pb::NnsFunction::UpdateSubnetReplicaVersion => {
+                            UpdateSubnetReplicaVersionPayload::decode(nns_function, payload)
+                                .ok()
+                                .map(|p| p.subnet_id)
+                        }
+                        _ => None,
+                    };
+                    (PayloadKind::from(nns_function), affected_subnets.into_iter().collect())
+                }
+                Err(_) => (PayloadKind::Other, vec![]),
+            },
+            _ => (PayloadKind::Other, vec![]),
+        };
+
+        Ok(Self {
+            id,
+            topic,
+            status,
+            summary: proposal.map(|p| p.summary.clone()).unwrap_or_default(),
+            payload_kind,
+            affected_subnets,
+            submitted_at: timestamp(info.proposal_timestamp_seconds)
+                .ok_or_else(|| format!("proposal {} without a submission time", id))?,
+            decided_at: timestamp(info.decided_timestamp_seconds),
+        })
+    }
+}
+
+/// The phases of a replacement of nodes of a subnet: a first proposal adds
+/// the new nodes, and once they are up a second one finalizes the
+/// replacement by removing the replaced nodes.
+#[derive(Display, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum ChangePhase {
+    Proposed,
+    Adopted,
+    NodesAdded,
+    AwaitingFinalize,
+    Finalized,
+}
+
+impl ChangePhase {
+    /// The phase following this one, none once finalized.
+    pub fn next(&self) -> Option<ChangePhase> {
+        match self {
+            Self::Proposed => Some(Self::Adopted),
+            Self::Adopted => Some(Self::NodesAdded),
+            Self::NodesAdded => Some(Self::AwaitingFinalize),
+            Self::AwaitingFinalize => Some(Self::Finalized),
+            Self::Finalized => None,
+        }
+    }
+}
+
+/// A change of phase a [TopologyChangeState] cannot make.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IllegalTransition {
+    pub from: ChangePhase,
+    pub to: ChangePhase,
+}
+
+impl std::fmt::Display for IllegalTransition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "a topology change cannot go from {} to {}", self.from, self.to)
+    }
+}
+
+impl std::error::Error for IllegalTransition {}
+
+/// An in-flight replacement of nodes of a subnet.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct TopologyChangeState {
+    #[cfg_attr(feature = "schema", schemars(with = "String"))]
+    pub subnet: PrincipalId,
+    pub phase: ChangePhase,
+    #[cfg_attr(feature = "schema", schemars(with = "Vec<String>"))]
+    pub added: Vec<PrincipalId>,
+    /// The replaced nodes, removed from the subnet when finalizing.
+    #[cfg_attr(feature = "schema", schemars(with = "Vec<String>"))]
+    pub removed_pending: Vec<PrincipalId>,
+    /// The proposals of the change, in the order they were submitted.
+    pub proposal_ids: Vec<u64>,
+    pub updated_at: Timestamp,
+}
+
+impl TopologyChangeState {
+    pub fn proposed(
+        subnet: PrincipalId,
+        added: Vec<PrincipalId>,
+        removed: Vec<PrincipalId>,
+        proposal_id: u64,
+        now: Timestamp,
+    ) -> Self {
+        Self {
+            subnet,
+            phase: ChangePhase::Proposed,
+            added,
+            removed_pending: removed,
+            proposal_ids: vec![proposal_id],
+            updated_at: now,
+        }
+    }
+
+    /// Move on to the phase `to`, which must follow the current one.
+    pub fn transition(&mut self, to: ChangePhase, now: Timestamp) -> Result<(), IllegalTransition> {
+        if self.phase.next() != Some(to) {
+            return Err(IllegalTransition { from: self.phase, to });
+        }
+        self.phase = to;
+        self.updated_at = now;
+        Ok(())
+    }
+
+    /// Record the proposal removing the replaced nodes.
+    pub fn await_finalize(&mut self, proposal_id: u64, now: Timestamp) -> Result<(), IllegalTransition> {
+        self.transition(ChangePhase::AwaitingFinalize, now)?;
+        self.proposal_ids.push(proposal_id);
+        Ok(())
+    }
+
+    /// Record that the replaced nodes left the subnet.
+    pub fn finalize(&mut self, now: Timestamp) -> Result<(), IllegalTransition> {
+        self.transition(ChangePhase::Finalized, now)?;
+        self.removed_pending.clear();
+        Ok(())
+    }
+
+    pub fn is_in_flight(&self) -> bool {
+        self.phase != ChangePhase::Finalized
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use candid::Encode;
+    use ic_nns_common::pb::v1::ProposalId;
+
+    use super::*;
+
+    fn governance_proposal(status: pb::ProposalStatus, decided: u64, action: Option<Action>) -> pb::ProposalInfo {
+        pb::ProposalInfo {
+            id: Some(ProposalId { id: 125_000 }),
+            topic: pb::Topic::SubnetManagement as i32,
+            status: status as i32,
+            proposal_timestamp_seconds: 1_700_000_000,
+            decided_timestamp_seconds: decided,
+            proposal: Some(pb::Proposal {
+                summary: "Replace a node of subnet yndj2".to_string(),
+                action,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn execute(nns_function: pb::NnsFunction, payload: Vec<u8>) -> Option<Action> {
+        Some(Action::ExecuteNnsFunction(pb::ExecuteNnsFunction {
+            nns_function: nns_function as i32,
+            payload,
+        }))
+    }
+
+    #[test]
+    fn membership_changes_read_from_governance() {
+        let subnet_id = PrincipalId::new_subnet_test_id(1);
+        let payload = Encode!(&ChangeSubnetMembershipPayload {
+            subnet_id,
+            node_ids_add: vec![],
+            node_ids_remove: vec![],
+        })
+        .unwrap();
+        let proposal = governance_proposal(
+            pb::ProposalStatus::Executed,
+            1_700_003_600,
+            execute(pb::NnsFunction::ChangeSubnetMembership, payload),
+        );
+
+        let info = ProposalInfo::try_from(&proposal).unwrap();
+
+        assert_eq!(
+            serde_json::to_string_pretty(&info).unwrap() + "\n",
+            include_str!("testdata/proposal_info.json")
+        );
+        assert_eq!(
+            serde_json::from_str::<ProposalInfo>(include_str!("testdata/proposal_info.json")).unwrap(),
+            info
+        );
+        // Proposals recorded by previous versions keep their timestamps.
+        let mut legacy = serde_json::to_value(&info).unwrap();
+        legacy["submitted_at"] = serde_json::json!("2023-11-14T22:13:20");
+        legacy["decided_at"] = serde_json::json!(1_700_003_600);
+        assert_eq!(serde_json::from_value::<ProposalInfo>(legacy).unwrap(), info);
+        assert!(info.status.is_final());
+    }
+
+    #[test]
+    fn other_payloads_and_open_proposals() {
+        let proposal = governance_proposal(
+            pb::ProposalStatus::Open,
+            0,
+            execute(pb::NnsFunction::UpdateElectedReplicaVersions, vec![]),
+        );
+        let info = ProposalInfo::try_from(&proposal).unwrap();
+        assert_eq!(info.payload_kind, PayloadKind::ElectReplicaVersions);
+        assert!(info.affected_subnets.is_empty());
+        assert_eq!(info.decided_at, None);
+        assert!(!info.status.is_final());
+
+        let motion = governance_proposal(pb::ProposalStatus::Rejected, 1_700_003_600, None);
+        assert_eq!(
+            ProposalInfo::try_from(&motion).unwrap().payload_kind,
+            PayloadKind::Other
+        );
+
+        let unspecified = governance_proposal(pb::ProposalStatus::Unspecified, 0, None);
+        assert!(ProposalInfo::try_from(&unspecified).is_err());
+    }
+
+    #[test]
+    fn statuses_keep_their_names_on_the_wire() {
+        for status in [
+            ProposalStatus::Open,
+            ProposalStatus::Adopted,
+            ProposalStatus::Rejected,
+            ProposalStatus::Executed,
+            ProposalStatus::Failed,
+        ] {
+            let json = serde_json::to_string(&status).unwrap();
+            assert_eq!(json, format!("\"{}\"", status));
+            assert_eq!(serde_json::from_str::<ProposalStatus>(&json).unwrap(), status);
+        }
+    }
+
+    const PHASES: [ChangePhase; 5] = [
+        ChangePhase::Proposed,
+        ChangePhase::Adopted,
+        ChangePhase::NodesAdded,
+        ChangePhase::AwaitingFinalize,
+        ChangePhase::Finalized,
+    ];
+
+    fn at(seconds: i64) -> Timestamp {
+        Timestamp::from_unix_seconds(1_700_000_000 + seconds).unwrap()
+    }
+
+    fn replacement() -> TopologyChangeState {
+        TopologyChangeState::proposed(
+            PrincipalId::new_subnet_test_id(1),
+            vec![PrincipalId::new_node_test_id(2)],
+            vec![PrincipalId::new_node_test_id(1)],
+            100,
+            at(0),
+        )
+    }
+
+    #[test]
+    fn only_the_next_phase_is_legal() {
+        for from in PHASES {
+            for to in PHASES {
+                let mut state = TopologyChangeState {
+                    phase: from,
+                    ..replacement()
+                };
+                let legal = PHASES.windows(2).any(|pair| pair == [from, to]);
+                match state.transition(to, at(1)) {
+                    Ok(()) => {
+                        assert!(legal, "{} to {} should be illegal", from, to);
+                        assert_eq!(state.phase, to);
+                        assert_eq!(state.updated_at, at(1));
+                    }
+                    Err(e) => {
+                        assert!(!legal, "{} to {} should be legal", from, to);
+                        assert_eq!(e, IllegalTransition { from, to });
+                        assert_eq!(state.phase, from);
+                        assert_eq!(state.updated_at, at(0));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn replacements_are_finalized_by_a_second_proposal() {
+        let mut state = replacement();
+        assert_eq!(
+            state.finalize(at(1)),
+            Err(IllegalTransition {
+                from: ChangePhase::Proposed,
+                to: ChangePhase::Finalized
+            })
+        );
+        assert_eq!(
+            state.await_finalize(101, at(1)).unwrap_err().from,
+            ChangePhase::Proposed
+        );
+        assert_eq!(state.proposal_ids, vec![100]);
+
+        state.transition(ChangePhase::Adopted, at(1)).unwrap();
+        state.transition(ChangePhase::NodesAdded, at(2)).unwrap();
+        state.await_finalize(101, at(3)).unwrap();
+        assert_eq!(state.proposal_ids, vec![100, 101]);
+        assert!(state.is_in_flight());
+
+        state.finalize(at(4)).unwrap();
+        assert!(!state.is_in_flight());
+        assert!(state.removed_pending.is_empty());
+        assert_eq!(state.added, vec![PrincipalId::new_node_test_id(2)]);
+        assert_eq!(state.finalize(at(5)).unwrap_err().from, ChangePhase::Finalized);
+
+        let json = serde_json::to_value(&state).unwrap();
+        assert_eq!(json["phase"], "finalized");
+        assert_eq!(serde_json::from_value::<TopologyChangeState>(json).unwrap(), state);
+    }
+}
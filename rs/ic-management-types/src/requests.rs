@@ -1,17 +1,58 @@
-use crate::{MinNakamotoCoefficients, Node, NodeGroup, NodeGroupUpdate, Status};
+use candid::CandidType;
+
+use crate::idempotency::IdempotencyKey;
+use crate::motivation::MotivationInput;
+use crate::score::ScoreSummary;
+use crate::{HealthStatus, MinNakamotoCoefficients, Node, NodeFeature, NodeGroup, NodeGroupUpdate, SubnetKind};
 use ic_base_types::PrincipalId;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fmt::Display;
+use std::str::FromStr;
 
 #[derive(Serialize, Deserialize)]
+#[serde(try_from = "MembershipReplacePayload")]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct MembershipReplaceRequest {
     pub target: ReplaceTarget,
     pub heal: bool,
     pub optimize: Option<usize>,
-    pub exclude: Option<Vec<String>>,
+    /// Nodes not to add to the subnet. Until the clients have moved to this
+    /// field and `exclude_features`, `exclude`, their untyped predecessor, is
+    /// accepted as well.
+    #[serde(default)]
+    #[cfg_attr(feature = "schema", schemars(with = "Vec<String>"))]
+    pub exclude_nodes: Vec<PrincipalId>,
+    /// Nodes having any of the feature values are not added to the subnet.
+    #[serde(default)]
+    pub exclude_features: Vec<FeatureMatch>,
+    /// Nodes having an annotation with any of the keys, e.g. `rma`, are not
+    /// added to the subnet.
+    #[serde(default)]
+    pub exclude_annotated: Vec<String>,
     pub only: Vec<String>,
+    #[cfg_attr(feature = "schema", schemars(with = "Option<Vec<String>>"))]
     pub include: Option<Vec<PrincipalId>>,
     pub min_nakamoto_coefficients: Option<MinNakamotoCoefficients>,
+    /// Seed for breaking ties between equally good nodes.
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// Whether the subnet needs DFINITY-owned nodes, true if not provided.
+    #[serde(default)]
+    pub require_dfinity_node: Option<bool>,
+    /// The minimum number of the current nodes of the subnet the replacement
+    /// must keep, unhealthy nodes replaced by healing not counting as kept.
+    #[serde(default)]
+    pub min_retained: Option<usize>,
+    /// Whether to take the nodes the providers have across the network into
+    /// account: prefer the providers with fewer nodes and refuse changes that
+    /// concentrate the network on fewer providers.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub fleet_aware: bool,
+    /// Identifies the request across its retries, so that the backend
+    /// computes the change only once.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idempotency_key: Option<IdempotencyKey>,
 }
 
 // impl Display for MembershipReplaceRequest
@@ -19,9 +60,10 @@ impl std::fmt::Display for MembershipReplaceRequest {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let target = match &self.target {
             ReplaceTarget::Subnet(subnet) => format!("subnet {}", subnet),
-            ReplaceTarget::Nodes { nodes, motivation } => {
-                format!("nodes {:?} ({})", nodes, motivation)
-            }
+            ReplaceTarget::Nodes { nodes, subnet, .. } => match subnet {
+                Some(subnet) => format!("nodes {:?} of subnet {} ({})", nodes, subnet, self.target.motivation()),
+                None => format!("nodes {:?} ({})", nodes, self.target.motivation()),
+            },
         };
         write!(f, "target: {}", target)?;
         if self.heal {
@@ -30,10 +72,15 @@ impl std::fmt::Display for MembershipReplaceRequest {
         if let Some(optimize) = self.optimize {
             write!(f, " optimize: {}", optimize)?;
         }
-        if let Some(exclude) = &self.exclude {
-            if !exclude.is_empty() {
-                write!(f, " exclude: {:?}", self.exclude)?;
-            }
+        if !self.exclude_nodes.is_empty() {
+            write!(f, " exclude_nodes: {:?}", self.exclude_nodes)?;
+        }
+        if !self.exclude_features.is_empty() {
+            let features = self.exclude_features.iter().map(|m| m.to_string()).collect::<Vec<_>>();
+            write!(f, " exclude_features: {:?}", features)?;
+        }
+        if !self.exclude_annotated.is_empty() {
+            write!(f, " exclude_annotated: {:?}", self.exclude_annotated)?;
         }
         if !self.only.is_empty() {
             write!(f, " only: {:?}", self.only)?;
@@ -46,49 +93,358 @@ impl std::fmt::Display for MembershipReplaceRequest {
         if let Some(min_nakamoto_coefficients) = &self.min_nakamoto_coefficients {
             write!(f, " min_nakamoto_coefficients: {:?}", min_nakamoto_coefficients)?;
         }
+        if let Some(seed) = self.seed {
+            write!(f, " seed: {}", seed)?;
+        }
+        if let Some(require_dfinity_node) = self.require_dfinity_node {
+            write!(f, " require_dfinity_node: {}", require_dfinity_node)?;
+        }
+        if let Some(min_retained) = self.min_retained {
+            write!(f, " min_retained: {}", min_retained)?;
+        }
+        if let Some(idempotency_key) = self.idempotency_key {
+            write!(f, " idempotency_key: {}", idempotency_key)?;
+        }
         Ok(())
     }
 }
 
+/// A [MembershipReplaceRequest] as sent by the clients, which may still
+/// send `exclude` with node IDs and feature values mixed.
+#[derive(Deserialize)]
+struct MembershipReplacePayload {
+    target: ReplaceTarget,
+    heal: bool,
+    optimize: Option<usize>,
+    /// `exclude` was optional, so older clients may send `null`.
+    #[serde(default)]
+    exclude: Option<Vec<String>>,
+    #[serde(default)]
+    exclude_nodes: Vec<PrincipalId>,
+    #[serde(default)]
+    exclude_features: Vec<FeatureMatch>,
+    #[serde(default)]
+    exclude_annotated: Vec<String>,
+    only: Vec<String>,
+    include: Option<Vec<PrincipalId>>,
+    min_nakamoto_coefficients: Option<MinNakamotoCoefficients>,
+    #[serde(default)]
+    seed: Option<u64>,
+    #[serde(default)]
+    require_dfinity_node: Option<bool>,
+    #[serde(default)]
+    min_retained: Option<usize>,
+    #[serde(default)]
+    fleet_aware: bool,
+    #[serde(default)]
+    idempotency_key: Option<IdempotencyKey>,
+}
+
+impl TryFrom<MembershipReplacePayload> for MembershipReplaceRequest {
+    type Error = String;
+
+    fn try_from(payload: MembershipReplacePayload) -> Result<Self, Self::Error> {
+        let (exclude_nodes, exclude_features) = split_exclusions(&payload.exclude.unwrap_or_default())?;
+        Ok(Self {
+            target: payload.target,
+            heal: payload.heal,
+            optimize: payload.optimize,
+            exclude_nodes: payload.exclude_nodes.into_iter().chain(exclude_nodes).collect(),
+            exclude_features: payload.exclude_features.into_iter().chain(exclude_features).collect(),
+            exclude_annotated: payload.exclude_annotated,
+            only: payload.only,
+            include: payload.include,
+            min_nakamoto_coefficients: payload.min_nakamoto_coefficients,
+            seed: payload.seed,
+            require_dfinity_node: payload.require_dfinity_node,
+            min_retained: payload.min_retained,
+            fleet_aware: payload.fleet_aware,
+            idempotency_key: payload.idempotency_key,
+        })
+    }
+}
+
+/// Split values each naming either a node or a feature value into node IDs
+/// and feature values. A value given without its feature, e.g. `CH` rather
+/// than `country=CH`, matches the nodes having it as the value of any
+/// feature.
+pub fn split_exclusions(values: &[String]) -> Result<(Vec<PrincipalId>, Vec<FeatureMatch>), String> {
+    let mut nodes = vec![];
+    let mut features = vec![];
+    for value in values {
+        if let Ok(node) = PrincipalId::from_str(value) {
+            nodes.push(node);
+        } else if value.contains('=') {
+            features.push(FeatureMatch::from_str(value)?);
+        } else {
+            features.extend(NodeFeature::variants().into_iter().map(|f| FeatureMatch::new(f, value)));
+        }
+    }
+    Ok((nodes, features))
+}
+
+/// A value of a node feature. Values are normalized before they are
+/// compared, ignoring case and surrounding whitespace, and must then be
+/// equal: `CH` matches nodes in `ch` but not in `CHE`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct FeatureMatch {
+    pub feature: NodeFeature,
+    pub value: String,
+}
+
+impl FeatureMatch {
+    pub fn new(feature: NodeFeature, value: &str) -> Self {
+        Self {
+            feature,
+            value: value.to_string(),
+        }
+    }
+
+    /// Whether `value`, a value of `self.feature`, matches.
+    pub fn matches(&self, value: &str) -> bool {
+        normalize_feature_value(&self.value) == normalize_feature_value(value)
+    }
+}
+
+/// The spelling of feature values compared when matching them.
+pub(crate) fn normalize_feature_value(value: &str) -> String {
+    value.trim().to_lowercase()
+}
+
+impl Display for FeatureMatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}={}", self.feature, self.value)
+    }
+}
+
+/// Parse a feature value given as `<feature>=<value>`, e.g. `country=CH`.
+impl FromStr for FeatureMatch {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (feature, value) = s
+            .split_once('=')
+            .ok_or_else(|| format!("Expected <feature>=<value>, got '{}'", s))?;
+        Ok(Self::new(NodeFeature::parse_with_suggestion(feature)?, value))
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum ReplaceTarget {
     /// Subnet targeted for replacements
-    Subnet(PrincipalId),
+    Subnet(#[cfg_attr(feature = "schema", schemars(with = "String"))] PrincipalId),
     /// Nodes on the same subnet that need to be replaced for other reasons
     Nodes {
+        #[cfg_attr(feature = "schema", schemars(with = "Vec<String>"))]
         nodes: Vec<PrincipalId>,
-        motivation: String,
+        /// Why the nodes are replaced, may be empty if each node has a reason
+        /// in `node_reasons`.
+        #[serde(default)]
+        motivation: MotivationInput,
+        /// The subnet the nodes are expected to be in. The request fails if
+        /// any of them is not.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        #[cfg_attr(feature = "schema", schemars(with = "Option<String>"))]
+        subnet: Option<PrincipalId>,
+        /// Why each node is replaced, for reasons differing between the
+        /// nodes.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        #[cfg_attr(feature = "schema", schemars(with = "Option<BTreeMap<String, String>>"))]
+        node_reasons: Option<BTreeMap<PrincipalId, String>>,
     },
 }
 
+impl ReplaceTarget {
+    /// A target of nodes replaced for the same reason.
+    pub fn nodes(nodes: Vec<PrincipalId>, motivation: impl Into<MotivationInput>) -> Self {
+        Self::Nodes {
+            nodes,
+            motivation: motivation.into(),
+            subnet: None,
+            node_reasons: None,
+        }
+    }
+
+    /// The motivation followed by the reason of each node having one, e.g.
+    /// `offboarding; <node>: disk failure`. Empty for subnet targets.
+    pub fn motivation(&self) -> String {
+        match self {
+            Self::Subnet(_) => String::new(),
+            Self::Nodes {
+                motivation,
+                node_reasons,
+                ..
+            } => std::iter::once(motivation.render_markdown().trim().to_string())
+                .chain(
+                    node_reasons
+                        .iter()
+                        .flatten()
+                        .map(|(node, reason)| format!("{}: {}", node, reason.trim())),
+                )
+                .filter(|m| !m.is_empty())
+                .collect::<Vec<_>>()
+                .join("; "),
+        }
+    }
+}
+
+/// A node added to or removed from a subnet, with the feature values it is
+/// scored by.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct NodeSummary {
+    #[cfg_attr(feature = "schema", schemars(with = "String"))]
+    pub principal: PrincipalId,
+    pub features: BTreeMap<NodeFeature, String>,
+}
+
+/// The payload of the proposal to change the membership of a subnet.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct MembershipChangePayload {
+    #[cfg_attr(feature = "schema", schemars(with = "String"))]
+    pub subnet_id: PrincipalId,
+    #[cfg_attr(feature = "schema", schemars(with = "Vec<String>"))]
+    pub node_ids_add: Vec<PrincipalId>,
+    #[cfg_attr(feature = "schema", schemars(with = "Vec<String>"))]
+    pub node_ids_remove: Vec<PrincipalId>,
+}
+
+/// The change the endpoints replacing nodes of a subnet or resizing it
+/// propose.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct MembershipChangeResponse {
+    #[cfg_attr(feature = "schema", schemars(with = "Option<String>"))]
+    pub subnet_id: Option<PrincipalId>,
+    pub added: Vec<NodeSummary>,
+    pub removed: Vec<NodeSummary>,
+    pub score_before: ScoreSummary,
+    pub score_after: ScoreSummary,
+    /// Why the nodes are changed, empty if not known.
+    #[serde(default)]
+    pub motivation: String,
+    pub comment: Option<String>,
+    #[serde(default)]
+    pub run_log: Vec<String>,
+    /// For each feature, the number of nodes having each value before and
+    /// after the change.
+    pub feature_diff: BTreeMap<NodeFeature, BTreeMap<String, (usize, usize)>>,
+    /// None if the change is not to an existing subnet.
+    pub proposal_payload: Option<MembershipChangePayload>,
+    /// How disruptive the change is.
+    #[serde(default)]
+    pub cost: ChangeCost,
+    /// The proposal making the change, if it is already proposed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proposal_id: Option<u64>,
+    /// The idempotency key of the request, if it had one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idempotency_key: Option<IdempotencyKey>,
+}
+
+/// How disruptive a change is, in terms of moved nodes and the providers and
+/// countries they belong to.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ChangeCost {
+    pub nodes_added: usize,
+    pub nodes_removed: usize,
+    pub providers_affected: usize,
+    pub countries_affected: usize,
+}
+
+impl ChangeCost {
+    /// Single number to compare proposals by, lower is less disruptive.
+    pub fn total(&self) -> usize {
+        self.nodes_added + self.nodes_removed + self.providers_affected + self.countries_affected
+    }
+}
+
+impl MembershipChangeResponse {
+    pub fn with_motivation(self, motivation: String) -> Self {
+        Self { motivation, ..self }
+    }
+
+    pub fn with_idempotency_key(self, idempotency_key: Option<IdempotencyKey>) -> Self {
+        Self {
+            idempotency_key,
+            ..self
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct SubnetCreateRequest {
     pub size: usize,
     pub min_nakamoto_coefficients: Option<MinNakamotoCoefficients>,
+    /// Node IDs or feature values not to choose the nodes from.
     pub exclude: Option<Vec<String>>,
     pub only: Option<Vec<String>>,
+    #[cfg_attr(feature = "schema", schemars(with = "Option<Vec<String>>"))]
     pub include: Option<Vec<PrincipalId>>,
+    #[serde(default)]
+    pub seed: Option<u64>,
+    #[serde(default)]
+    pub require_dfinity_node: Option<bool>,
+    /// The replica version of the subnet, the one of the NNS if not provided.
+    #[serde(default)]
+    pub replica_version: Option<String>,
+    /// An application subnet if not provided.
+    #[serde(default)]
+    pub subnet_kind: Option<SubnetKind>,
+    /// Whether to take the nodes the providers have across the network into
+    /// account: prefer the providers with fewer nodes and refuse changes that
+    /// concentrate the network on fewer providers.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub fleet_aware: bool,
+    /// Identifies the request across its retries, so that the backend
+    /// computes the change only once.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idempotency_key: Option<IdempotencyKey>,
 }
 
 #[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct SubnetResizeRequest {
+    #[cfg_attr(feature = "schema", schemars(with = "String"))]
     pub subnet: PrincipalId,
     pub add: usize,
     pub remove: usize,
     pub exclude: Option<Vec<String>>,
     pub only: Option<Vec<String>>,
+    #[cfg_attr(feature = "schema", schemars(with = "Option<Vec<String>>"))]
     pub include: Option<Vec<PrincipalId>>,
+    #[serde(default)]
+    pub seed: Option<u64>,
+    #[serde(default)]
+    pub require_dfinity_node: Option<bool>,
+    /// Whether to take the nodes the providers have across the network into
+    /// account: prefer the providers with fewer nodes and refuse changes that
+    /// concentrate the network on fewer providers.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub fleet_aware: bool,
+    /// Identifies the request across its retries, so that the backend
+    /// computes the change only once.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idempotency_key: Option<IdempotencyKey>,
 }
 
 #[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct HostosRolloutRequest {
+    #[cfg_attr(feature = "schema", schemars(with = "Option<Vec<String>>"))]
     pub exclude: Option<Vec<PrincipalId>>,
     pub version: String,
     pub node_group: NodeGroupUpdate,
 }
 
 #[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum HostosRolloutResponse {
     Ok(Vec<Node>, Option<Vec<HostosRolloutSubnetAffected>>),
     None(Vec<(NodeGroup, HostosRolloutReason)>),
@@ -104,12 +460,15 @@ impl HostosRolloutResponse {
 }
 
 #[derive(Serialize, Deserialize, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct HostosRolloutSubnetAffected {
+    #[cfg_attr(feature = "schema", schemars(with = "String"))]
     pub subnet_id: PrincipalId,
     pub subnet_size: usize,
 }
 
 #[derive(Serialize, Deserialize, Eq, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum HostosRolloutReason {
     NoNodeHealthy,
     NoNodeWithoutProposal,
@@ -129,30 +488,34 @@ impl Display for HostosRolloutReason {
 }
 
 #[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct NodesRemoveRequest {
     pub no_auto: bool,
     pub remove_degraded: bool,
     pub extra_nodes_filter: Vec<String>,
     pub exclude: Option<Vec<String>>,
-    pub motivation: String,
+    pub motivation: MotivationInput,
 }
 
 #[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct NodesRemoveResponse {
     pub removals: Vec<NodeRemoval>,
     pub motivation: String,
 }
 
 #[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct NodeRemoval {
     pub node: Node,
     pub reason: NodeRemovalReason,
 }
 
 #[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum NodeRemovalReason {
-    Duplicates(PrincipalId),
-    Unhealthy(Status),
+    Duplicates(#[cfg_attr(feature = "schema", schemars(with = "String"))] PrincipalId),
+    Unhealthy(HealthStatus),
     MatchedFilter(String),
 }
 
@@ -165,3 +528,472 @@ impl NodeRemovalReason {
         }
     }
 }
+
+/// Decommission the given nodes, e.g. when their provider offboards or
+/// their hardware is broken.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct NodeRemovalRequest {
+    #[cfg_attr(feature = "schema", schemars(with = "Vec<String>"))]
+    pub nodes: Vec<PrincipalId>,
+    pub reason: DecommissionReason,
+    pub motivation: MotivationInput,
+    /// Score the subnets of the nodes without them.
+    #[serde(default)]
+    pub check_subnet_impact: bool,
+}
+
+/// Why nodes are decommissioned.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum DecommissionReason {
+    /// The node provider leaves the network.
+    Offboarding,
+    /// The hardware of the nodes is broken.
+    Broken,
+    /// The nodes duplicate other nodes.
+    Duplicate,
+    Other(String),
+}
+
+impl Display for DecommissionReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Offboarding => write!(f, "The node provider is offboarding"),
+            Self::Broken => write!(f, "The node hardware is broken"),
+            Self::Duplicate => write!(f, "The nodes are duplicates"),
+            Self::Other(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl FromStr for DecommissionReason {
+    type Err = std::convert::Infallible;
+
+    /// Any text other than the name of a reason is taken as the reason.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.trim().to_lowercase().as_str() {
+            "offboarding" => Self::Offboarding,
+            "broken" => Self::Broken,
+            "duplicate" => Self::Duplicate,
+            _ => Self::Other(s.to_string()),
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct NodeRemovalResponse {
+    pub nodes: Vec<NodeRemovalImpact>,
+    pub motivation: String,
+}
+
+/// The subnet a node to remove is in and how removing it affects the
+/// decentralization of the subnet.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct NodeRemovalImpact {
+    #[cfg_attr(feature = "schema", schemars(with = "String"))]
+    pub node_id: PrincipalId,
+    #[cfg_attr(feature = "schema", schemars(with = "Option<String>"))]
+    pub subnet_id: Option<PrincipalId>,
+    /// The scores of the subnet with and without the node, if the subnet
+    /// impact was asked for and the node is in a subnet.
+    #[serde(default)]
+    pub score_before: Option<ScoreSummary>,
+    #[serde(default)]
+    pub score_after: Option<ScoreSummary>,
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::validation::{Validate, ValidationIssue};
+    use crate::NetworkError;
+
+    fn node_id(id: u64) -> serde_json::Value {
+        serde_json::to_value(PrincipalId::new_node_test_id(id)).unwrap()
+    }
+
+    #[test]
+    fn old_cli_payloads_deserialize() {
+        let payload = json!({
+            "target": { "subnet": serde_json::to_value(PrincipalId::new_subnet_test_id(1)).unwrap() },
+            "heal": true,
+            "optimize": 2,
+            "exclude": [node_id(1), node_id(2)],
+            "only": [],
+            "include": [],
+            "min_nakamoto_coefficients": null,
+            "seed": 7,
+            "require_dfinity_node": null,
+        });
+        let request: MembershipReplaceRequest = serde_json::from_value(payload.clone()).unwrap();
+        assert_eq!(
+            request.exclude_nodes,
+            vec![PrincipalId::new_node_test_id(1), PrincipalId::new_node_test_id(2)]
+        );
+        assert!(request.exclude_features.is_empty());
+
+        let mut payload = payload;
+        payload["exclude"] = serde_json::Value::Null;
+        let request: MembershipReplaceRequest = serde_json::from_value(payload.clone()).unwrap();
+        assert!(request.exclude_nodes.is_empty());
+
+        payload["exclude"] = json!([node_id(1), "CH", "data_center=zh1"]);
+        let request: MembershipReplaceRequest = serde_json::from_value(payload.clone()).unwrap();
+        assert_eq!(request.exclude_nodes, vec![PrincipalId::new_node_test_id(1)]);
+        let mut expected = NodeFeature::variants()
+            .into_iter()
+            .map(|f| FeatureMatch::new(f, "CH"))
+            .collect::<Vec<_>>();
+        expected.push(FeatureMatch::new(NodeFeature::DataCenter, "zh1"));
+        assert_eq!(request.exclude_features, expected);
+
+        payload["exclude"] = json!(["colour=red"]);
+        assert!(serde_json::from_value::<MembershipReplaceRequest>(payload).is_err());
+    }
+
+    #[test]
+    fn typed_exclusions_round_trip() {
+        let payload = json!({
+            "target": { "nodes": { "nodes": [node_id(3)], "motivation": "test" } },
+            "heal": false,
+            "optimize": null,
+            "exclude_nodes": [node_id(1)],
+            "exclude_features": [{ "feature": "country", "value": "CH" }],
+            "only": [],
+            "include": null,
+            "min_nakamoto_coefficients": null,
+        });
+        let request: MembershipReplaceRequest = serde_json::from_value(payload).unwrap();
+        assert_eq!(request.exclude_nodes, vec![PrincipalId::new_node_test_id(1)]);
+        assert_eq!(
+            request.exclude_features,
+            vec![FeatureMatch::new(NodeFeature::Country, "CH")]
+        );
+
+        let serialized = serde_json::to_value(&request).unwrap();
+        assert_eq!(
+            serialized["exclude_features"],
+            json!([{ "feature": "country", "value": "CH" }])
+        );
+        assert!(serialized.get("exclude").is_none());
+    }
+
+    #[test]
+    fn node_targets_with_subnet_and_reasons() {
+        let node = PrincipalId::new_node_test_id;
+        let old = json!({ "nodes": { "nodes": [node_id(1), node_id(2)], "motivation": "dead" } });
+        let target: ReplaceTarget = serde_json::from_value(old.clone()).unwrap();
+        assert!(matches!(
+            &target,
+            ReplaceTarget::Nodes {
+                subnet: None,
+                node_reasons: None,
+                ..
+            }
+        ));
+        assert_eq!(target.motivation(), "dead");
+        // Old backends read the requests of targets without the new fields.
+        assert_eq!(serde_json::to_value(&target).unwrap(), old);
+
+        let subnet = PrincipalId::new_subnet_test_id(1);
+        let new = json!({ "nodes": {
+            "nodes": [node_id(1), node_id(2)],
+            "subnet": serde_json::to_value(subnet).unwrap(),
+            "node_reasons": { node(2).to_string(): "disk failure " },
+        } });
+        let target: ReplaceTarget = serde_json::from_value(new.clone()).unwrap();
+        match &target {
+            ReplaceTarget::Nodes {
+                motivation,
+                subnet: target_subnet,
+                node_reasons,
+                ..
+            } => {
+                assert!(motivation.is_empty());
+                assert_eq!(*target_subnet, Some(subnet));
+                assert_eq!(
+                    node_reasons,
+                    &Some(BTreeMap::from([(node(2), "disk failure ".to_string())]))
+                );
+            }
+            ReplaceTarget::Subnet(_) => panic!("not a node target"),
+        }
+        assert_eq!(target.motivation(), format!("{}: disk failure", node(2)));
+        let mut serialized = new;
+        serialized["nodes"]["motivation"] = json!("");
+        assert_eq!(serde_json::to_value(&target).unwrap(), serialized);
+
+        let target = ReplaceTarget::Nodes {
+            motivation: "offboarding".into(),
+            node_reasons: Some(BTreeMap::from([(node(1), "disk failure".to_string())])),
+            ..ReplaceTarget::nodes(vec![node(1), node(2)], String::new())
+        };
+        assert_eq!(target.motivation(), format!("offboarding; {}: disk failure", node(1)));
+        assert_eq!(ReplaceTarget::Subnet(subnet).motivation(), "");
+    }
+
+    #[test]
+    fn size_only_create_requests_deserialize() {
+        let request: SubnetCreateRequest = serde_json::from_value(json!({ "size": 13 })).unwrap();
+        assert_eq!(request.size, 13);
+        assert!(request.min_nakamoto_coefficients.is_none());
+        assert!(request.include.is_none());
+        assert!(request.replica_version.is_none());
+        assert!(request.subnet_kind.is_none());
+        assert!(request.validate().is_empty());
+    }
+
+    #[test]
+    fn create_requests_with_constraints_and_version() {
+        let payload = json!({
+            "size": 4,
+            "min_nakamoto_coefficients": { "coefficients": { "country": 2.0 }, "average": 3.0 },
+            "exclude": [PrincipalId::new_node_test_id(2).to_string(), "CH"],
+            "include": [node_id(1)],
+            "replica_version": "0123456789abcdef",
+            "subnet_kind": "verified_application",
+        });
+        let request: SubnetCreateRequest = serde_json::from_value(payload).unwrap();
+        assert_eq!(request.include, Some(vec![PrincipalId::new_node_test_id(1)]));
+        assert_eq!(request.replica_version.as_deref(), Some("0123456789abcdef"));
+        assert_eq!(request.subnet_kind, Some(SubnetKind::VerifiedApplication));
+        assert!(request.validate().is_empty());
+
+        let request = SubnetCreateRequest {
+            include: Some(vec![PrincipalId::new_node_test_id(1), PrincipalId::new_node_test_id(2)]),
+            ..request
+        };
+        assert_eq!(
+            request.validate(),
+            vec![ValidationIssue::new(
+                "include",
+                format!(
+                    "Nodes [{}] are both included and excluded",
+                    PrincipalId::new_node_test_id(2)
+                )
+            )]
+        );
+        let request = SubnetCreateRequest { size: 1, ..request };
+        assert_eq!(request.validate().len(), 2);
+        let request = SubnetCreateRequest {
+            size: 0,
+            include: None,
+            ..request
+        };
+        assert!(matches!(request.check(), Err(NetworkError::IllegalRequest(_))));
+    }
+
+    fn membership_change() -> MembershipChangeResponse {
+        let subnet_id = PrincipalId::new_subnet_test_id(1);
+        let node = |id: u64, country: &str| NodeSummary {
+            principal: PrincipalId::new_node_test_id(id),
+            features: BTreeMap::from([
+                (NodeFeature::NodeProvider, format!("provider {}", id)),
+                (NodeFeature::Country, country.to_string()),
+            ]),
+        };
+        let score = |country: u64| ScoreSummary {
+            coefficients: BTreeMap::from([(NodeFeature::NodeProvider, 2), (NodeFeature::Country, country)]),
+            min: 1,
+            average_linear: (2 + country) as f64 / 2.0,
+            average_log2: Some(1.0),
+            critical_features: vec![NodeFeature::NodeProvider, NodeFeature::Country],
+            controlled_nodes: BTreeMap::from([(NodeFeature::NodeProvider, 2), (NodeFeature::Country, 2)]),
+            headroom: BTreeMap::from([(NodeFeature::NodeProvider, 1), (NodeFeature::Country, 0)]),
+        };
+        MembershipChangeResponse {
+            subnet_id: Some(subnet_id),
+            added: vec![node(2, "JP")],
+            removed: vec![node(1, "CH")],
+            score_before: score(1),
+            score_after: score(2),
+            motivation: "replacing 1 unhealthy node".to_string(),
+            comment: None,
+            run_log: vec!["Nakamoto score before extension".to_string()],
+            feature_diff: BTreeMap::from([(
+                NodeFeature::Country,
+                BTreeMap::from([("CH".to_string(), (2, 1)), ("JP".to_string(), (0, 1))]),
+            )]),
+            proposal_payload: Some(MembershipChangePayload {
+                subnet_id,
+                node_ids_add: vec![PrincipalId::new_node_test_id(2)],
+                node_ids_remove: vec![PrincipalId::new_node_test_id(1)],
+            }),
+            cost: ChangeCost {
+                nodes_added: 1,
+                nodes_removed: 1,
+                providers_affected: 2,
+                countries_affected: 2,
+            },
+            proposal_id: None,
+            idempotency_key: None,
+        }
+    }
+
+    #[test]
+    fn membership_changes_round_trip() {
+        let change = membership_change();
+        let serialized = serde_json::to_string(&change).unwrap();
+        assert_eq!(
+            serde_json::from_str::<MembershipChangeResponse>(&serialized).unwrap(),
+            change
+        );
+
+        let empty = MembershipChangeResponse::default();
+        let serialized = serde_json::to_string(&empty).unwrap();
+        assert_eq!(
+            serde_json::from_str::<MembershipChangeResponse>(&serialized).unwrap(),
+            empty
+        );
+    }
+
+    /// Changing the wire format breaks the clients of the backend, update the
+    /// snapshot only along with them.
+    #[test]
+    fn membership_change_wire_format_snapshot() {
+        assert_eq!(
+            serde_json::to_string_pretty(&membership_change()).unwrap() + "\n",
+            include_str!("testdata/membership_change_response.json")
+        );
+    }
+
+    #[test]
+    fn idempotency_keys_are_optional_and_echoed() {
+        let request = SubnetResizeRequest::builder(PrincipalId::new_subnet_test_id(1))
+            .add(1)
+            .build()
+            .unwrap();
+        // Older backends do not know the key or fleet awareness, so they are
+        // only sent if set.
+        assert!(serde_json::to_value(&request).unwrap().get("idempotency_key").is_none());
+        assert!(serde_json::to_value(&request).unwrap().get("fleet_aware").is_none());
+        let fleet_aware = SubnetResizeRequest::builder(request.subnet)
+            .add(1)
+            .fleet_aware()
+            .build()
+            .unwrap();
+        assert_eq!(serde_json::to_value(&fleet_aware).unwrap()["fleet_aware"], json!(true));
+
+        let key = IdempotencyKey::from_u128(0x3f2b8c1e_5d4a_4e6f_9a7b_0c1d2e3f4a5b);
+        let request = SubnetResizeRequest {
+            idempotency_key: Some(key),
+            ..request
+        };
+        let serialized = serde_json::to_value(&request).unwrap();
+        assert_eq!(serialized["idempotency_key"], json!(key.to_string()));
+        let request: SubnetResizeRequest = serde_json::from_value(serialized).unwrap();
+        assert_eq!(request.idempotency_key, Some(key));
+
+        let response = membership_change().with_idempotency_key(request.idempotency_key);
+        let serialized = serde_json::to_string(&response).unwrap();
+        assert_eq!(
+            serde_json::from_str::<MembershipChangeResponse>(&serialized)
+                .unwrap()
+                .idempotency_key,
+            Some(key)
+        );
+    }
+
+    #[test]
+    fn feature_values_match_after_normalization() {
+        let country = FeatureMatch::from_str("country= CH").unwrap();
+        assert_eq!(country, FeatureMatch::new(NodeFeature::Country, " CH"));
+        assert!(country.matches("ch"));
+        assert!(country.matches("CH "));
+        assert!(!country.matches("CHE"));
+
+        assert!(FeatureMatch::from_str("CH").is_err());
+        assert!(FeatureMatch::from_str("contnent=Europe")
+            .unwrap_err()
+            .contains("did you mean 'continent'?"));
+    }
+
+    #[test]
+    fn node_removal_requests_round_trip() {
+        let payload = json!({
+            "nodes": [node_id(1), node_id(2)],
+            "reason": { "other": "relocation" },
+            "motivation": "Moving the nodes to another data center",
+        });
+        let request: NodeRemovalRequest = serde_json::from_value(payload).unwrap();
+        assert_eq!(request.reason, DecommissionReason::Other("relocation".to_string()));
+        assert!(!request.check_subnet_impact);
+        assert!(request.validate().is_empty());
+
+        let request = NodeRemovalRequest {
+            reason: DecommissionReason::Offboarding,
+            check_subnet_impact: true,
+            ..request
+        };
+        let serialized = serde_json::to_value(&request).unwrap();
+        assert_eq!(serialized["reason"], json!("offboarding"));
+        assert_eq!(
+            serde_json::from_value::<NodeRemovalRequest>(serialized).unwrap(),
+            request
+        );
+        assert_eq!("Broken".parse(), Ok(DecommissionReason::Broken));
+        assert_eq!(
+            "Relocation".parse(),
+            Ok(DecommissionReason::Other("Relocation".to_string()))
+        );
+    }
+
+    #[test]
+    fn motivations_are_structured_or_bare_strings() {
+        let structured = json!({ "category": "offboard", "details": "The provider leaves the network" });
+        for (motivation, expected) in [
+            (structured.clone(), "**Offboard:** The provider leaves the network"),
+            (json!("Moving the nodes"), "Moving the nodes"),
+        ] {
+            let request: NodeRemovalRequest = serde_json::from_value(json!({
+                "nodes": [node_id(1)],
+                "reason": "offboarding",
+                "motivation": motivation,
+            }))
+            .unwrap();
+            assert_eq!(request.motivation.render_markdown(), expected);
+            assert_eq!(serde_json::to_value(&request).unwrap()["motivation"], motivation);
+
+            let target: ReplaceTarget =
+                serde_json::from_value(json!({ "nodes": { "nodes": [node_id(1)], "motivation": motivation } }))
+                    .unwrap();
+            assert_eq!(target.motivation(), expected);
+        }
+        assert!(matches!(
+            serde_json::from_value::<NodesRemoveRequest>(json!({
+                "no_auto": false,
+                "remove_degraded": false,
+                "extra_nodes_filter": [],
+                "exclude": null,
+                "motivation": structured,
+            }))
+            .unwrap()
+            .motivation,
+            MotivationInput::Structured(_)
+        ));
+    }
+
+    #[test]
+    fn node_removal_requests_need_distinct_nodes() {
+        let request = NodeRemovalRequest::builder(DecommissionReason::Broken).motivation("Broken disks");
+        assert!(request.build().is_err());
+
+        let request = NodeRemovalRequest::builder(DecommissionReason::Broken)
+            .motivation("Broken disks")
+            .nodes([
+                PrincipalId::new_node_test_id(1),
+                PrincipalId::new_node_test_id(2),
+                PrincipalId::new_node_test_id(1),
+            ]);
+        let issues = request.build().unwrap_err().issues;
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].field, "nodes");
+        assert!(issues[0].message.contains("more than once"), "{}", issues[0]);
+    }
+}
@@ -0,0 +1,248 @@
+//! JSON Schemas of the requests and responses of the management API, for
+//! clients not written in Rust to validate their payloads. The schemas are
+//! checked in under `schemas/`, regenerate them after changing any of the
+//! types with:
+//!
+//! ```sh
+//! cargo run -p ic-management-types --features schema --bin export-schemas
+//! ```
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use schemars::schema::RootSchema;
+use schemars::schema_for;
+use serde::Serialize;
+
+use crate::errors::ManagementErrorBody;
+use crate::pagination::{Page, PageRequest};
+use crate::preview::ChangePreview;
+use crate::proposals::{ProposalInfo, TopologyChangeState};
+use crate::requests::{
+    HostosRolloutRequest, HostosRolloutResponse, MembershipChangeResponse, MembershipReplaceRequest,
+    NodeRemovalRequest, NodeRemovalResponse, NodesRemoveRequest, NodesRemoveResponse, SubnetCreateRequest,
+    SubnetResizeRequest,
+};
+use crate::{Node, OperatorDetails, ProviderDetails};
+
+/// The name of the file listing the schema file of each type.
+pub const INDEX_FILE: &str = "index.json";
+
+/// The schema of each request and response type, by the name of the type.
+pub fn schemas() -> BTreeMap<&'static str, RootSchema> {
+    BTreeMap::from([
+        ("ChangePreview", schema_for!(ChangePreview)),
+        ("HostosRolloutRequest", schema_for!(HostosRolloutRequest)),
+        ("HostosRolloutResponse", schema_for!(HostosRolloutResponse)),
+        ("ManagementErrorBody", schema_for!(ManagementErrorBody)),
+        ("MembershipChangeResponse", schema_for!(MembershipChangeResponse)),
+        ("MembershipReplaceRequest", schema_for!(MembershipReplaceRequest)),
+        ("Node", schema_for!(Node)),
+        ("NodePage", schema_for!(Page<Node>)),
+        ("NodeRemovalRequest", schema_for!(NodeRemovalRequest)),
+        ("NodeRemovalResponse", schema_for!(NodeRemovalResponse)),
+        ("NodesRemoveRequest", schema_for!(NodesRemoveRequest)),
+        ("NodesRemoveResponse", schema_for!(NodesRemoveResponse)),
+        ("OperatorDetails", schema_for!(OperatorDetails)),
+        ("PageRequest", schema_for!(PageRequest)),
+        ("ProposalInfo", schema_for!(ProposalInfo)),
+        ("ProposalPage", schema_for!(Page<ProposalInfo>)),
+        ("ProviderDetails", schema_for!(ProviderDetails)),
+        ("SubnetCreateRequest", schema_for!(SubnetCreateRequest)),
+        ("SubnetResizeRequest", schema_for!(SubnetResizeRequest)),
+        ("TopologyChangeState", schema_for!(TopologyChangeState)),
+    ])
+}
+
+/// The files of the schemas and of their index, by file name.
+pub fn schema_files() -> serde_json::Result<BTreeMap<String, String>> {
+    let mut files = BTreeMap::new();
+    let mut index = BTreeMap::new();
+    for (name, schema) in schemas() {
+        let file = format!("{}.json", name);
+        files.insert(file.clone(), to_json(&schema)?);
+        index.insert(name, file);
+    }
+    files.insert(INDEX_FILE.to_string(), to_json(&index)?);
+    Ok(files)
+}
+
+/// Write a `<type>.json` file with the schema of each type to `dir`, along
+/// with an index of the files. Returns the written files.
+pub fn export_schemas(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    std::fs::create_dir_all(dir)?;
+    schema_files()?
+        .into_iter()
+        .map(|(file, content)| {
+            let path = dir.join(file);
+            std::fs::write(&path, content)?;
+            Ok(path)
+        })
+        .collect()
+}
+
+fn to_json(value: &impl Serialize) -> serde_json::Result<String> {
+    Ok(serde_json::to_string_pretty(value)? + "\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use ic_types::PrincipalId;
+    use jsonschema::JSONSchema;
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::motivation::{Motivation, MotivationCategory};
+    use crate::proposals::ChangePhase;
+    use crate::requests::{DecommissionReason, ReplaceTarget};
+    use crate::timestamp::Timestamp;
+    use crate::ManagementError;
+
+    fn schema(name: &str) -> JSONSchema {
+        let schema = serde_json::to_value(&schemas()[name]).unwrap();
+        JSONSchema::compile(&schema).unwrap_or_else(|e| panic!("invalid schema of {}: {}", name, e))
+    }
+
+    fn assert_valid(name: &str, value: &Value) {
+        if let Err(errors) = schema(name).validate(value) {
+            let errors = errors.map(|e| e.to_string()).collect::<Vec<_>>();
+            panic!("{} does not match the schema of {}: {:?}", value, name, errors);
+        }
+    }
+
+    /// The checked in schemas must be regenerated along with the types, see
+    /// the module documentation. Bazel runs the test from the runfiles and
+    /// points `SCHEMAS_DIR` at the schemas there.
+    #[test]
+    fn schemas_are_up_to_date() {
+        let dir = std::env::var_os("SCHEMAS_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| Path::new(env!("CARGO_MANIFEST_DIR")).join("schemas"));
+        let files = schema_files().unwrap();
+        for (file, content) in &files {
+            let checked_in = std::fs::read_to_string(dir.join(file)).unwrap_or_default();
+            assert!(
+                &checked_in == content,
+                "schemas/{} is out of date, regenerate the schemas with `cargo run -p ic-management-types --features schema --bin export-schemas`",
+                file
+            );
+        }
+        let checked_in = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .filter(|file| !files.contains_key(file))
+            .collect::<Vec<_>>();
+        assert!(checked_in.is_empty(), "schemas of removed types: {:?}", checked_in);
+    }
+
+    #[test]
+    fn serialized_responses_match_their_schemas() {
+        assert_valid(
+            "MembershipChangeResponse",
+            &serde_json::from_str(include_str!("testdata/membership_change_response.json")).unwrap(),
+        );
+        assert_valid(
+            "ChangePreview",
+            &serde_json::from_str(include_str!("testdata/change_preview.json")).unwrap(),
+        );
+        assert_valid(
+            "ProposalInfo",
+            &serde_json::from_str(include_str!("testdata/proposal_info.json")).unwrap(),
+        );
+
+        let error = serde_json::to_value(ManagementErrorBody {
+            error: ManagementError::SubnetNotFound {
+                subnet_id: PrincipalId::new_subnet_test_id(1),
+            },
+        })
+        .unwrap();
+        assert_valid("ManagementErrorBody", &error);
+
+        let mut state = TopologyChangeState::proposed(
+            PrincipalId::new_subnet_test_id(1),
+            vec![PrincipalId::new_node_test_id(2)],
+            vec![PrincipalId::new_node_test_id(1)],
+            100,
+            Timestamp::default(),
+        );
+        state.phase = ChangePhase::AwaitingFinalize;
+        assert_valid("TopologyChangeState", &serde_json::to_value(&state).unwrap());
+    }
+
+    #[test]
+    fn serialized_requests_match_their_schemas() {
+        for target in [
+            ReplaceTarget::Subnet(PrincipalId::new_subnet_test_id(1)),
+            ReplaceTarget::nodes(vec![PrincipalId::new_node_test_id(1)], "dead".to_string()),
+            ReplaceTarget::Nodes {
+                nodes: vec![PrincipalId::new_node_test_id(1), PrincipalId::new_node_test_id(2)],
+                motivation: Motivation::new(MotivationCategory::Heal, "").into(),
+                subnet: Some(PrincipalId::new_subnet_test_id(1)),
+                node_reasons: Some(BTreeMap::from([
+                    (PrincipalId::new_node_test_id(1), "dead".to_string()),
+                    (PrincipalId::new_node_test_id(2), "slow".to_string()),
+                ])),
+            },
+        ] {
+            let request = MembershipReplaceRequest::builder()
+                .target(target)
+                .heal()
+                .optimize(2)
+                .exclude_node(PrincipalId::new_node_test_id(3))
+                .exclude_features(["country=CH".parse().unwrap()])
+                .seed(7)
+                .min_retained(10)
+                .build()
+                .unwrap();
+            assert_valid("MembershipReplaceRequest", &serde_json::to_value(&request).unwrap());
+        }
+
+        for reason in [
+            DecommissionReason::Broken,
+            DecommissionReason::Other("moved".to_string()),
+        ] {
+            let request = NodeRemovalRequest::builder(reason)
+                .node(PrincipalId::new_node_test_id(1))
+                .motivation("hardware")
+                .check_subnet_impact()
+                .build()
+                .unwrap();
+            assert_valid("NodeRemovalRequest", &serde_json::to_value(&request).unwrap());
+        }
+        assert_valid("PageRequest", &json!({ "cursor": "MQ", "limit": 10 }));
+    }
+
+    #[test]
+    fn schemas_follow_the_serde_attributes() {
+        let error = schema("ManagementErrorBody");
+        assert!(error.is_valid(&json!({ "error": { "code": "internal", "message": "boom" } })));
+        assert!(!error.is_valid(&json!({ "error": { "code": "Internal", "message": "boom" } })));
+        assert!(!error.is_valid(&json!({ "error": { "Internal": { "message": "boom" } } })));
+
+        let request =
+            |reason: Value| json!({ "nodes": [], "reason": reason, "motivation": "", "check_subnet_impact": false });
+        let removal = schema("NodeRemovalRequest");
+        assert!(removal.is_valid(&request(json!("offboarding"))));
+        assert!(removal.is_valid(&request(json!({ "other": "moved" }))));
+        assert!(!removal.is_valid(&request(json!("Offboarding"))));
+        let mut structured = request(json!("offboarding"));
+        structured["motivation"] = json!({ "category": "offboard", "details": "Leaving" });
+        assert!(removal.is_valid(&structured));
+        structured["motivation"]["category"] = json!("leave");
+        assert!(!removal.is_valid(&structured));
+
+        let state = schema("TopologyChangeState");
+        let mut value = serde_json::to_value(TopologyChangeState::proposed(
+            PrincipalId::new_subnet_test_id(1),
+            vec![],
+            vec![],
+            1,
+            Timestamp::default(),
+        ))
+        .unwrap();
+        value["phase"] = json!("NodesAdded");
+        assert!(!state.is_valid(&value));
+        value["phase"] = json!("nodes_added");
+        assert!(state.is_valid(&value));
+    }
+}
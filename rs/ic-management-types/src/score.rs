@@ -0,0 +1,120 @@
+//! The decentralization score of a subnet as the management API serves it.
+//! The score the decentralization crate computes is internal and changes
+//! along with the crate, while the field names and types of the summary are
+//! part of the wire format of the API.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::NodeFeature;
+
+/// The Nakamoto coefficients of a subnet and their aggregates.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ScoreSummary {
+    /// The Nakamoto coefficient of each feature: the smallest number of
+    /// actors, e.g. node providers, that together control more than a third
+    /// of the nodes.
+    #[serde(deserialize_with = "deserialize_coefficients")]
+    pub coefficients: BTreeMap<NodeFeature, u64>,
+    /// The lowest coefficient.
+    #[serde(deserialize_with = "deserialize_coefficient")]
+    pub min: u64,
+    /// The average of the coefficients.
+    pub average_linear: f64,
+    /// The average of the log2 of the coefficients. None if undefined, i.e.
+    /// if a coefficient is 0, e.g. for an empty subnet.
+    pub average_log2: Option<f64>,
+    /// The features whose coefficients matter most for the security of the
+    /// subnet.
+    #[serde(default)]
+    pub critical_features: Vec<NodeFeature>,
+    /// The number of nodes controlled by the actors counted in the
+    /// coefficient of each feature.
+    #[serde(default)]
+    pub controlled_nodes: BTreeMap<NodeFeature, usize>,
+    /// How many more nodes the top actor of each feature can control before
+    /// the coefficient degrades.
+    pub headroom: BTreeMap<NodeFeature, usize>,
+}
+
+/// A coefficient, also accepting the floats that summaries had before the
+/// coefficients were integers, e.g. `3.0`.
+fn deserialize_coefficient<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    let coefficient = f64::deserialize(deserializer)?;
+    if coefficient >= 0. && coefficient.fract() == 0. && coefficient <= u64::MAX as f64 {
+        Ok(coefficient as u64)
+    } else {
+        Err(serde::de::Error::custom(format!(
+            "Invalid coefficient {}, expected a non-negative integer",
+            coefficient
+        )))
+    }
+}
+
+fn deserialize_coefficients<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<BTreeMap<NodeFeature, u64>, D::Error> {
+    #[derive(Deserialize)]
+    struct Coefficient(#[serde(deserialize_with = "deserialize_coefficient")] u64);
+
+    Ok(BTreeMap::<NodeFeature, Coefficient>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(feature, Coefficient(coefficient))| (feature, coefficient))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn summary() -> ScoreSummary {
+        ScoreSummary {
+            coefficients: BTreeMap::from([(NodeFeature::NodeProvider, 5), (NodeFeature::Country, 3)]),
+            min: 3,
+            average_linear: 4.,
+            average_log2: Some(1.95),
+            critical_features: vec![NodeFeature::NodeProvider, NodeFeature::Country],
+            controlled_nodes: BTreeMap::from([(NodeFeature::NodeProvider, 5), (NodeFeature::Country, 6)]),
+            headroom: BTreeMap::from([(NodeFeature::NodeProvider, 3), (NodeFeature::Country, 0)]),
+        }
+    }
+
+    /// Changing the wire format breaks the dashboard and the other clients of
+    /// the backend, update the snapshot only along with them.
+    #[test]
+    fn score_summary_wire_format_snapshot() {
+        let serialized = serde_json::to_string_pretty(&summary()).unwrap() + "\n";
+        assert_eq!(serialized, include_str!("testdata/score_summary.json"));
+        assert_eq!(serde_json::from_str::<ScoreSummary>(&serialized).unwrap(), summary());
+    }
+
+    #[test]
+    fn summaries_with_float_coefficients_deserialize() {
+        let summary = serde_json::from_value::<ScoreSummary>(json!({
+            "coefficients": { "node_provider": 5.0, "country": 3.0 },
+            "headroom": { "node_provider": 3, "country": 0 },
+            "average_linear": 4.0,
+            "average_log2": 1.95,
+            "min": 3.0,
+        }))
+        .unwrap();
+        assert_eq!(summary.coefficients, self::summary().coefficients);
+        assert_eq!(summary.min, 3);
+        assert!(summary.critical_features.is_empty());
+
+        for invalid in [json!(2.5), json!(-1), json!("3")] {
+            let summary = json!({
+                "coefficients": { "country": invalid },
+                "headroom": {},
+                "average_linear": 0.0,
+                "average_log2": null,
+                "min": 0,
+            });
+            assert!(serde_json::from_value::<ScoreSummary>(summary).is_err(), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,280 @@
+//! The timestamps of the management API. They are always sent as RFC 3339
+//! in UTC with millisecond precision, e.g. `2023-11-14T22:13:20.000Z`, but
+//! the formats older clients and recorded data use are still accepted: unix
+//! seconds, unix milliseconds and RFC 3339 without a time zone, taken as UTC.
+
+use std::fmt::Display;
+use std::str::FromStr;
+
+use chrono::{DateTime, NaiveDateTime, SecondsFormat, SubsecRound, TimeZone, Utc};
+use serde::de::{Error, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Unix timestamps from this one on are in milliseconds, earlier ones in
+/// seconds. As seconds it is in the year 5138, as milliseconds in 1973.
+const MIN_UNIX_MILLIS: i64 = 100_000_000_000;
+
+/// A point in time, with millisecond precision.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Timestamp(DateTime<Utc>);
+
+impl Timestamp {
+    pub fn now() -> Self {
+        Utc::now().into()
+    }
+
+    /// None if `seconds` is out of range.
+    pub fn from_unix_seconds(seconds: i64) -> Option<Self> {
+        Utc.timestamp_opt(seconds, 0).single().map(Self)
+    }
+
+    /// None if `millis` is out of range.
+    pub fn from_unix_millis(millis: i64) -> Option<Self> {
+        Utc.timestamp_millis_opt(millis).single().map(Self)
+    }
+
+    /// A unix timestamp in seconds or, if it is too large to be one, in
+    /// milliseconds.
+    pub fn from_unix(timestamp: i64) -> Option<Self> {
+        if timestamp.unsigned_abs() >= MIN_UNIX_MILLIS as u64 {
+            Self::from_unix_millis(timestamp)
+        } else {
+            Self::from_unix_seconds(timestamp)
+        }
+    }
+
+    pub fn datetime(&self) -> DateTime<Utc> {
+        self.0
+    }
+
+    pub fn unix_millis(&self) -> i64 {
+        self.0.timestamp_millis()
+    }
+
+    /// The timestamp `duration` later, None if it is out of range.
+    pub fn checked_add(&self, duration: std::time::Duration) -> Option<Self> {
+        chrono::Duration::from_std(duration)
+            .ok()
+            .and_then(|duration| self.0.checked_add_signed(duration))
+            .map(Self::from)
+    }
+}
+
+/// Anything below milliseconds is dropped.
+impl From<DateTime<Utc>> for Timestamp {
+    fn from(datetime: DateTime<Utc>) -> Self {
+        Self(datetime.trunc_subsecs(3))
+    }
+}
+
+/// The date and time are taken as UTC.
+impl From<NaiveDateTime> for Timestamp {
+    fn from(datetime: NaiveDateTime) -> Self {
+        Utc.from_utc_datetime(&datetime).into()
+    }
+}
+
+impl Display for Timestamp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0.to_rfc3339_opts(SecondsFormat::Millis, true))
+    }
+}
+
+impl FromStr for Timestamp {
+    type Err = String;
+
+    /// Parse RFC 3339, in any time zone or without one for UTC.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        DateTime::parse_from_rfc3339(s)
+            .map(|datetime| datetime.with_timezone(&Utc).into())
+            .or_else(|_| s.parse::<NaiveDateTime>().map(Self::from))
+            .map_err(|e| format!("Invalid timestamp '{}': {}", s, e))
+    }
+}
+
+impl Serialize for Timestamp {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Timestamp {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(TimestampVisitor)
+    }
+}
+
+struct TimestampVisitor;
+
+impl<'de> Visitor<'de> for TimestampVisitor {
+    type Value = Timestamp;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "an RFC 3339 timestamp or a unix timestamp in seconds or milliseconds"
+        )
+    }
+
+    fn visit_str<E: Error>(self, v: &str) -> Result<Self::Value, E> {
+        v.parse().map_err(E::custom)
+    }
+
+    fn visit_i64<E: Error>(self, v: i64) -> Result<Self::Value, E> {
+        Timestamp::from_unix(v).ok_or_else(|| E::custom(format!("unix timestamp {} is out of range", v)))
+    }
+
+    fn visit_u64<E: Error>(self, v: u64) -> Result<Self::Value, E> {
+        i64::try_from(v)
+            .map_err(|_| E::custom(format!("unix timestamp {} is out of range", v)))
+            .and_then(|v| self.visit_i64(v))
+    }
+
+    /// Fractions of seconds are kept down to milliseconds.
+    fn visit_f64<E: Error>(self, v: f64) -> Result<Self::Value, E> {
+        let millis = if v.abs() >= MIN_UNIX_MILLIS as f64 {
+            v
+        } else {
+            v * 1000.
+        };
+        if !millis.is_finite() || millis.abs() >= i64::MAX as f64 {
+            return Err(E::custom(format!("unix timestamp {} is out of range", v)));
+        }
+        Timestamp::from_unix_millis(millis.trunc() as i64)
+            .ok_or_else(|| E::custom(format!("unix timestamp {} is out of range", v)))
+    }
+}
+
+#[cfg(feature = "schema")]
+impl schemars::JsonSchema for Timestamp {
+    fn schema_name() -> String {
+        "Timestamp".to_string()
+    }
+
+    fn json_schema(_: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        schemars::schema::SchemaObject {
+            metadata: Some(Box::new(schemars::schema::Metadata {
+                description: Some("RFC 3339 in UTC with millisecond precision.".to_string()),
+                ..Default::default()
+            })),
+            instance_type: Some(schemars::schema::InstanceType::String.into()),
+            format: Some("date-time".to_string()),
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    const SECONDS: i64 = 1_700_000_000;
+
+    #[test]
+    fn timestamps_are_serialized_as_rfc3339_with_millis() {
+        let timestamps = [
+            (
+                Timestamp::from_unix_seconds(SECONDS).unwrap(),
+                "2023-11-14T22:13:20.000Z",
+            ),
+            (
+                Timestamp::from_unix_millis(SECONDS * 1000 + 7).unwrap(),
+                "2023-11-14T22:13:20.007Z",
+            ),
+            (Timestamp::default(), "1970-01-01T00:00:00.000Z"),
+            (Timestamp::from_unix_seconds(-1).unwrap(), "1969-12-31T23:59:59.000Z"),
+        ];
+        for (timestamp, golden) in timestamps {
+            assert_eq!(serde_json::to_value(timestamp).unwrap(), json!(golden));
+            assert_eq!(timestamp.to_string(), golden);
+        }
+    }
+
+    #[test]
+    fn sub_millisecond_precision_is_dropped() {
+        let datetime = Utc.timestamp_opt(SECONDS, 123_456_789).unwrap();
+        let timestamp = Timestamp::from(datetime);
+        assert_eq!(timestamp.to_string(), "2023-11-14T22:13:20.123Z");
+        assert_eq!(timestamp.unix_millis(), SECONDS * 1000 + 123);
+        let json = serde_json::to_string(&timestamp).unwrap();
+        assert_eq!(serde_json::from_str::<Timestamp>(&json).unwrap(), timestamp);
+    }
+
+    #[test]
+    fn legacy_formats_are_deserialized() {
+        let expected = Timestamp::from_unix_millis(SECONDS * 1000).unwrap();
+        let with_millis = Timestamp::from_unix_millis(SECONDS * 1000 + 250).unwrap();
+        for (legacy, expected) in [
+            // Unix seconds.
+            (json!(SECONDS), expected),
+            (json!(SECONDS as u64), expected),
+            (json!(SECONDS as f64 + 0.25), with_millis),
+            // Unix milliseconds.
+            (json!(SECONDS * 1000), expected),
+            (json!(SECONDS * 1000 + 250), with_millis),
+            (json!((SECONDS * 1000) as f64), expected),
+            // RFC 3339.
+            (json!("2023-11-14T22:13:20Z"), expected),
+            (json!("2023-11-14T22:13:20.000Z"), expected),
+            (json!("2023-11-14T22:13:20.250+00:00"), with_millis),
+            (json!("2023-11-15T00:13:20+02:00"), expected),
+            (json!("2023-11-14 22:13:20Z"), expected),
+            // RFC 3339 without a time zone, as the previous versions sent it.
+            (json!("2023-11-14T22:13:20"), expected),
+            (json!("2023-11-14T22:13:20.25"), with_millis),
+        ] {
+            assert_eq!(
+                serde_json::from_value::<Timestamp>(legacy.clone()).unwrap(),
+                expected,
+                "{}",
+                legacy
+            );
+        }
+        assert_eq!(
+            serde_json::from_value::<Timestamp>(json!(0)).unwrap(),
+            Timestamp::default()
+        );
+        assert_eq!(
+            serde_json::from_value::<Timestamp>(json!(MIN_UNIX_MILLIS - 1)).unwrap(),
+            Timestamp::from_unix_seconds(MIN_UNIX_MILLIS - 1).unwrap()
+        );
+        assert_eq!(
+            serde_json::from_value::<Timestamp>(json!(MIN_UNIX_MILLIS)).unwrap(),
+            Timestamp::from_unix_millis(MIN_UNIX_MILLIS).unwrap()
+        );
+    }
+
+    #[test]
+    fn invalid_timestamps_are_rejected() {
+        for invalid in [
+            json!(""),
+            json!("yesterday"),
+            json!("2023-11-14"),
+            json!("2023-13-14T22:13:20Z"),
+            json!("1700000000"),
+            json!(u64::MAX),
+            json!(i64::MIN),
+            json!(1e300),
+            json!(true),
+            json!(null),
+            json!({ "seconds": SECONDS }),
+        ] {
+            assert!(
+                serde_json::from_value::<Timestamp>(invalid.clone()).is_err(),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn timestamps_are_ordered_in_time() {
+        let earlier: Timestamp = "2023-11-14T22:13:20+01:00".parse().unwrap();
+        let later: Timestamp = "2023-11-14T22:13:20Z".parse().unwrap();
+        assert!(earlier < later);
+        assert_eq!(later.datetime(), Utc.timestamp_opt(SECONDS, 0).unwrap());
+    }
+}
@@ -0,0 +1,179 @@
+//! Requests are parsed with a policy for the fields the request types do not
+//! know, which are most likely typos of the clients, e.g. `"motiviation"`.
+//! Leniently parsed requests are served with a warning about each unknown
+//! field in the `warnings` array of the response, strictly parsed ones are
+//! rejected.
+
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::ManagementError;
+
+/// The field of the responses listing the warnings about their request.
+pub const WARNINGS_FIELD: &str = "warnings";
+
+/// What to do with the unknown fields of a request.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UnknownFieldPolicy {
+    /// Ignore them, with a warning about each of them.
+    #[default]
+    Warn,
+    /// Reject the request.
+    Reject,
+}
+
+/// A parsed request and the warnings about its unknown fields.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Parsed<T> {
+    pub value: T,
+    pub warnings: Vec<String>,
+}
+
+/// Parse the JSON `body` of a request. Unknown fields are named by their
+/// path in the request, e.g. `target.motiviation` or
+/// `exclude_features.0.vlaue`.
+pub fn from_json<T: DeserializeOwned>(body: &[u8], policy: UnknownFieldPolicy) -> Result<Parsed<T>, ManagementError> {
+    let invalid = |e: serde_json::Error| ManagementError::InvalidRequest { details: e.to_string() };
+    let mut unknown_fields = vec![];
+    let mut deserializer = serde_json::Deserializer::from_slice(body);
+    let value =
+        serde_ignored::deserialize(&mut deserializer, |path| unknown_fields.push(path.to_string())).map_err(invalid)?;
+    deserializer.end().map_err(invalid)?;
+
+    let warnings = unknown_fields
+        .iter()
+        .map(|field| format!("unknown field '{}'", field))
+        .collect::<Vec<_>>();
+    match policy {
+        UnknownFieldPolicy::Reject if !warnings.is_empty() => Err(ManagementError::InvalidRequest {
+            details: warnings.join(", "),
+        }),
+        _ => Ok(Parsed { value, warnings }),
+    }
+}
+
+/// Add the `warnings` to those of the `response`, if it is a JSON object.
+pub fn add_warnings(mut response: Value, warnings: Vec<String>) -> Value {
+    if warnings.is_empty() {
+        return response;
+    }
+    if let Some(Value::Array(existing)) = response
+        .as_object_mut()
+        .map(|fields| fields.entry(WARNINGS_FIELD).or_insert_with(|| Value::Array(vec![])))
+    {
+        existing.extend(warnings.into_iter().map(Value::String));
+    }
+    response
+}
+
+/// The warnings of a `response`.
+pub fn warnings(response: &Value) -> Vec<String> {
+    response
+        .get(WARNINGS_FIELD)
+        .and_then(Value::as_array)
+        .map(|warnings| warnings.iter().filter_map(|w| w.as_str().map(str::to_string)).collect())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use ic_types::PrincipalId;
+    use serde_json::json;
+
+    use super::*;
+    use crate::requests::{MembershipReplaceRequest, NodeRemovalRequest, ReplaceTarget};
+
+    fn replace_request_with_typos() -> Vec<u8> {
+        json!({
+            "target": { "nodes": {
+                "nodes": [PrincipalId::new_node_test_id(1).to_string()],
+                "motivation": "dead",
+                "motiviation": "dead",
+            } },
+            "heal": true,
+            "optimize": null,
+            "exlude": [PrincipalId::new_node_test_id(2).to_string()],
+            "exclude_features": [{ "feature": "country", "value": "CH", "vlaue": "DE" }],
+            "only": [],
+            "include": null,
+            "min_nakamoto_coefficients": null,
+        })
+        .to_string()
+        .into_bytes()
+    }
+
+    #[test]
+    fn unknown_fields_are_warned_about_in_lenient_mode() {
+        let parsed =
+            from_json::<MembershipReplaceRequest>(&replace_request_with_typos(), UnknownFieldPolicy::Warn).unwrap();
+        assert_eq!(
+            parsed.warnings,
+            vec![
+                "unknown field 'target.motiviation'".to_string(),
+                "unknown field 'exlude'".to_string(),
+                "unknown field 'exclude_features.0.vlaue'".to_string(),
+            ]
+        );
+        assert!(
+            matches!(&parsed.value.target, ReplaceTarget::Nodes { motivation, .. } if motivation.render_markdown() == "dead")
+        );
+        assert!(parsed.value.exclude_nodes.is_empty());
+        assert_eq!(parsed.value.exclude_features[0].value, "CH");
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected_in_strict_mode() {
+        let err = from_json::<MembershipReplaceRequest>(&replace_request_with_typos(), UnknownFieldPolicy::Reject)
+            .err()
+            .unwrap();
+        assert_eq!(
+            err,
+            ManagementError::InvalidRequest {
+                details: "unknown field 'target.motiviation', unknown field 'exlude', unknown field 'exclude_features.0.vlaue'"
+                    .to_string()
+            }
+        );
+
+        let request = json!({
+            "nodes": [PrincipalId::new_node_test_id(1).to_string()],
+            "reason": "broken",
+            "motivation": "hardware",
+            "check_subnet_impact": true,
+        });
+        for policy in [UnknownFieldPolicy::Warn, UnknownFieldPolicy::Reject] {
+            let parsed = from_json::<NodeRemovalRequest>(request.to_string().as_bytes(), policy).unwrap();
+            assert!(parsed.warnings.is_empty());
+            assert!(parsed.value.check_subnet_impact);
+        }
+    }
+
+    #[test]
+    fn malformed_requests_are_invalid() {
+        let trailing = [replace_request_with_typos(), b" {}".to_vec()].concat();
+        for body in [b"".as_slice(), b"{", br#"{"heal": "yes"}"#, &trailing] {
+            let err = from_json::<MembershipReplaceRequest>(body, UnknownFieldPolicy::Warn)
+                .err()
+                .unwrap();
+            assert!(matches!(err, ManagementError::InvalidRequest { .. }), "{:?}", err);
+        }
+    }
+
+    #[test]
+    fn warnings_are_added_to_the_response() {
+        let warnings = vec!["unknown field 'exlude'".to_string()];
+        let response = add_warnings(json!({ "added": [] }), warnings.clone());
+        assert_eq!(response, json!({ "added": [], "warnings": ["unknown field 'exlude'"] }));
+        assert_eq!(super::warnings(&response), warnings);
+
+        let response = add_warnings(json!({ "warnings": ["no DFINITY-owned node"] }), warnings);
+        assert_eq!(
+            super::warnings(&response),
+            vec![
+                "no DFINITY-owned node".to_string(),
+                "unknown field 'exlude'".to_string()
+            ]
+        );
+        assert_eq!(add_warnings(json!([1]), vec!["w".to_string()]), json!([1]));
+        assert_eq!(add_warnings(json!({}), vec![]), json!({}));
+    }
+}
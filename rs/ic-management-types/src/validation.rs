@@ -0,0 +1,642 @@
+//! Checks of the requests to the management API, run by the backend before
+//! doing any work and by the CLI before sending them.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Display;
+
+use ic_base_types::PrincipalId;
+use serde::{Deserialize, Serialize};
+
+use crate::annotations::is_valid_annotation_key;
+use crate::motivation::MotivationInput;
+use crate::requests::{
+    MembershipReplaceRequest, NodeRemovalRequest, ReplaceTarget, SubnetCreateRequest, SubnetResizeRequest,
+};
+use crate::{ManagementError, NetworkError};
+
+/// The longest motivation that fits in the summary of a proposal, next to
+/// the description of the change.
+pub const MAX_MOTIVATION_LEN: usize = 15_000;
+/// The largest subnet that can be created.
+pub const MAX_SUBNET_SIZE: usize = 64;
+
+/// A problem with a field of a request.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ValidationIssue {
+    /// The path of the field, e.g. `target.nodes`.
+    pub field: String,
+    pub message: String,
+}
+
+impl ValidationIssue {
+    pub fn new(field: &str, message: impl Display) -> Self {
+        Self {
+            field: field.to_string(),
+            message: message.to_string(),
+        }
+    }
+}
+
+impl Display for ValidationIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+/// The issues joined into a single message.
+pub fn describe_issues(issues: &[ValidationIssue]) -> String {
+    issues.iter().map(|i| i.to_string()).collect::<Vec<_>>().join("; ")
+}
+
+/// A request with issues, e.g. returned when building one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InvalidRequest {
+    pub issues: Vec<ValidationIssue>,
+}
+
+impl Display for InvalidRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid request: {}", describe_issues(&self.issues))
+    }
+}
+
+impl std::error::Error for InvalidRequest {}
+
+impl From<InvalidRequest> for NetworkError {
+    fn from(err: InvalidRequest) -> Self {
+        NetworkError::IllegalRequest(describe_issues(&err.issues))
+    }
+}
+
+pub trait Validate {
+    /// The issues of the request, empty if it is valid.
+    fn validate(&self) -> Vec<ValidationIssue>;
+
+    /// An [NetworkError::IllegalRequest] describing the issues of the
+    /// request, if any.
+    fn check(&self) -> Result<(), NetworkError> {
+        let issues = self.validate();
+        if issues.is_empty() {
+            Ok(())
+        } else {
+            Err(NetworkError::IllegalRequest(describe_issues(&issues)))
+        }
+    }
+
+    /// The request if it is valid, else its issues.
+    fn validated(self) -> Result<Self, InvalidRequest>
+    where
+        Self: Sized,
+    {
+        let issues = self.validate();
+        if issues.is_empty() {
+            Ok(self)
+        } else {
+            Err(InvalidRequest { issues })
+        }
+    }
+}
+
+impl Validate for MembershipReplaceRequest {
+    fn validate(&self) -> Vec<ValidationIssue> {
+        let mut issues = vec![];
+        match &self.target {
+            ReplaceTarget::Subnet(_) => {
+                if !self.heal && self.optimize.unwrap_or_default() == 0 {
+                    issues.push(ValidationIssue::new(
+                        "optimize",
+                        "Nothing to replace without healing or optimizing nodes",
+                    ));
+                }
+            }
+            ReplaceTarget::Nodes {
+                nodes,
+                motivation,
+                node_reasons,
+                ..
+            } => {
+                issues.extend(check_node_list("target.nodes", nodes));
+                // The motivation is only needed for the nodes without a reason.
+                let all_nodes_have_reasons = node_reasons
+                    .as_ref()
+                    .map_or(false, |reasons| nodes.iter().all(|n| reasons.contains_key(n)));
+                if !(all_nodes_have_reasons && motivation.is_empty()) {
+                    issues.extend(check_motivation_input("target.motivation", motivation));
+                }
+                issues.extend(check_node_reasons("target.node_reasons", nodes, node_reasons));
+            }
+        }
+        issues.extend(check_disjoint(
+            "include",
+            self.include.iter().flatten(),
+            &self.exclude_nodes.iter().map(|n| n.to_string()).collect(),
+        ));
+        if let Some(key) = self.exclude_annotated.iter().find(|k| !is_valid_annotation_key(k)) {
+            issues.push(ValidationIssue::new(
+                "exclude_annotated",
+                format!("Invalid annotation key '{}', no node can have it", key),
+            ));
+        }
+        issues
+    }
+}
+
+impl Validate for SubnetCreateRequest {
+    fn validate(&self) -> Vec<ValidationIssue> {
+        let mut issues = vec![];
+        if self.size == 0 {
+            issues.push(ValidationIssue::new("size", "The subnet must have at least one node"));
+        } else if self.size > MAX_SUBNET_SIZE {
+            issues.push(ValidationIssue::new(
+                "size",
+                format!("The subnet cannot have more than {} nodes", MAX_SUBNET_SIZE),
+            ));
+        }
+        let include = self.include.clone().unwrap_or_default();
+        if include.len() > self.size {
+            issues.push(ValidationIssue::new(
+                "include",
+                format!(
+                    "Cannot include {} nodes in a subnet of {} nodes",
+                    include.len(),
+                    self.size
+                ),
+            ));
+        }
+        issues.extend(check_disjoint(
+            "include",
+            &include,
+            &self.exclude.iter().flatten().cloned().collect(),
+        ));
+        if let Some(kind) = self.subnet_kind.as_ref().filter(|k| k.registry_subnet_type().is_none()) {
+            issues.push(ValidationIssue::new(
+                "subnet_kind",
+                format!("Cannot create a subnet of kind '{}'", kind),
+            ));
+        }
+        issues
+    }
+}
+
+impl Validate for SubnetResizeRequest {
+    fn validate(&self) -> Vec<ValidationIssue> {
+        let mut issues = vec![];
+        if self.add == 0 && self.remove == 0 {
+            issues.push(ValidationIssue::new("add", "Nothing to add or remove"));
+        }
+        let include = self.include.clone().unwrap_or_default();
+        if include.len() > self.add {
+            issues.push(ValidationIssue::new(
+                "include",
+                format!("Cannot include {} nodes when adding {}", include.len(), self.add),
+            ));
+        }
+        issues.extend(check_disjoint(
+            "include",
+            &include,
+            &self.exclude.iter().flatten().cloned().collect(),
+        ));
+        issues
+    }
+}
+
+impl Validate for NodeRemovalRequest {
+    fn validate(&self) -> Vec<ValidationIssue> {
+        let mut issues = check_node_list("nodes", &self.nodes).into_iter().collect::<Vec<_>>();
+        issues.extend(check_motivation_input("motivation", &self.motivation));
+        issues
+    }
+}
+
+/// The node list must not be empty and list each node once.
+fn check_node_list(field: &str, nodes: &[PrincipalId]) -> Option<ValidationIssue> {
+    if nodes.is_empty() {
+        return Some(ValidationIssue::new(field, "No nodes given"));
+    }
+    let mut seen = BTreeSet::new();
+    let duplicates = nodes.iter().filter(|n| !seen.insert(**n)).collect::<BTreeSet<_>>();
+    if duplicates.is_empty() {
+        None
+    } else {
+        Some(ValidationIssue::new(
+            field,
+            format!("Nodes {:?} are listed more than once", duplicates),
+        ))
+    }
+}
+
+fn check_motivation(field: &str, motivation: &str) -> Option<ValidationIssue> {
+    if motivation.trim().is_empty() {
+        Some(ValidationIssue::new(field, "The motivation must not be empty"))
+    } else if motivation.len() > MAX_MOTIVATION_LEN {
+        Some(ValidationIssue::new(
+            field,
+            format!("The motivation must not be longer than {} bytes", MAX_MOTIVATION_LEN),
+        ))
+    } else {
+        None
+    }
+}
+
+/// A structured motivation needs details, and must fit once rendered.
+fn check_motivation_input(field: &str, motivation: &MotivationInput) -> Option<ValidationIssue> {
+    if motivation.is_empty() {
+        return Some(ValidationIssue::new(field, "The motivation must not be empty"));
+    }
+    check_motivation(field, &motivation.render_markdown())
+}
+
+/// Each reason must be of one of the `nodes`, and a valid motivation.
+fn check_node_reasons(
+    field: &str,
+    nodes: &[PrincipalId],
+    node_reasons: &Option<BTreeMap<PrincipalId, String>>,
+) -> Vec<ValidationIssue> {
+    let node_reasons = node_reasons.iter().flatten();
+    let unknown = node_reasons
+        .clone()
+        .filter(|(node, _)| !nodes.contains(node))
+        .map(|(node, _)| node)
+        .collect::<Vec<_>>();
+    let mut issues = vec![];
+    if !unknown.is_empty() {
+        issues.push(ValidationIssue::new(
+            field,
+            format!("Nodes {:?} are not replaced but have a reason", unknown),
+        ));
+    }
+    issues.extend(node_reasons.filter_map(|(node, reason)| check_motivation(&format!("{}.{}", field, node), reason)));
+    issues
+}
+
+/// The subnet all the nodes of a request belong to, given the subnet of
+/// each node, None for unassigned nodes. Fails listing the nodes outside of
+/// the `expected` subnet, or, if none is expected, all nodes if they are not
+/// in a single subnet.
+pub fn common_subnet(
+    node_subnets: &BTreeMap<PrincipalId, Option<PrincipalId>>,
+    expected: Option<PrincipalId>,
+) -> Result<PrincipalId, ManagementError> {
+    let subnets = node_subnets.values().collect::<BTreeSet<_>>();
+    let subnet_id = match (expected, subnets.into_iter().collect::<Vec<_>>().as_slice()) {
+        (Some(expected), _) => Some(expected),
+        (None, [Some(subnet)]) => Some(*subnet),
+        (None, _) => None,
+    };
+    let offenders = node_subnets
+        .iter()
+        .filter(|(_, subnet)| subnet_id.is_none() || **subnet != subnet_id)
+        .map(|(node, subnet)| (*node, *subnet))
+        .collect::<BTreeMap<_, _>>();
+    match subnet_id {
+        Some(subnet_id) if offenders.is_empty() => Ok(subnet_id),
+        _ => Err(ManagementError::NodesNotInSubnet { subnet_id, offenders }),
+    }
+}
+
+/// No included node may be excluded, by node id.
+fn check_disjoint<'a>(
+    field: &str,
+    include: impl IntoIterator<Item = &'a PrincipalId>,
+    exclude: &BTreeSet<String>,
+) -> Option<ValidationIssue> {
+    let overlap = include
+        .into_iter()
+        .filter(|n| exclude.contains(&n.to_string()))
+        .collect::<Vec<_>>();
+    if overlap.is_empty() {
+        None
+    } else {
+        Some(ValidationIssue::new(
+            field,
+            format!("Nodes {:?} are both included and excluded", overlap),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builders::{MembershipReplaceRequestBuilder, SubnetCreateRequestBuilder};
+    use crate::motivation::{Motivation, MotivationCategory};
+    use crate::requests::DecommissionReason;
+    use crate::SubnetKind;
+
+    use super::*;
+
+    fn node(id: u64) -> PrincipalId {
+        PrincipalId::new_node_test_id(id)
+    }
+
+    /// The fields of the issues of each request, in order.
+    fn issue_fields<R>(cases: Vec<(&str, Result<R, InvalidRequest>, Vec<&str>)>) {
+        for (name, request, fields) in cases {
+            let issues = request.err().map(|e| e.issues).unwrap_or_default();
+            assert_eq!(
+                issues.iter().map(|i| i.field.as_str()).collect::<Vec<_>>(),
+                fields,
+                "{}: {}",
+                name,
+                describe_issues(&issues)
+            );
+        }
+    }
+
+    fn replace_request(target: ReplaceTarget, heal: bool, optimize: Option<usize>) -> MembershipReplaceRequestBuilder {
+        MembershipReplaceRequest::builder()
+            .target(target)
+            .heal_if(heal)
+            .optimize(optimize)
+    }
+
+    fn nodes_target(nodes: Vec<PrincipalId>, motivation: &str) -> ReplaceTarget {
+        ReplaceTarget::nodes(nodes, motivation.to_string())
+    }
+
+    #[test]
+    fn membership_replace_requests() {
+        let subnet = || ReplaceTarget::Subnet(PrincipalId::new_subnet_test_id(1));
+        issue_fields(vec![
+            ("heal", replace_request(subnet(), true, None).build(), vec![]),
+            ("optimize", replace_request(subnet(), false, Some(2)).build(), vec![]),
+            (
+                "nothing to do",
+                replace_request(subnet(), false, Some(0)).build(),
+                vec!["optimize"],
+            ),
+            (
+                "nodes",
+                replace_request(nodes_target(vec![node(1)], "Dead node"), false, None).build(),
+                vec![],
+            ),
+            (
+                "no nodes",
+                replace_request(nodes_target(vec![], "Dead node"), false, None).build(),
+                vec!["target.nodes"],
+            ),
+            (
+                "duplicate nodes and no motivation",
+                replace_request(nodes_target(vec![node(1), node(1)], " "), true, None).build(),
+                vec!["target.nodes", "target.motivation"],
+            ),
+            (
+                "long motivation",
+                replace_request(
+                    nodes_target(vec![node(1)], &"x".repeat(MAX_MOTIVATION_LEN + 1)),
+                    true,
+                    None,
+                )
+                .build(),
+                vec!["target.motivation"],
+            ),
+            (
+                "included and excluded",
+                replace_request(nodes_target(vec![node(1)], "Dead node"), true, None)
+                    .include([node(2)])
+                    .exclude_node(node(2))
+                    .build(),
+                vec!["include"],
+            ),
+            (
+                "invalid annotation key",
+                replace_request(subnet(), true, None)
+                    .exclude_annotated(["rma".to_string(), "RMA".to_string()])
+                    .build(),
+                vec!["exclude_annotated"],
+            ),
+        ]);
+    }
+
+    #[test]
+    fn node_reasons_replace_the_motivation() {
+        let target = |motivation: &str, node_reasons: Vec<(PrincipalId, &str)>| {
+            let target = ReplaceTarget::Nodes {
+                node_reasons: Some(
+                    node_reasons
+                        .into_iter()
+                        .map(|(node, reason)| (node, reason.to_string()))
+                        .collect(),
+                ),
+                ..nodes_target(vec![node(1), node(2)], motivation)
+            };
+            replace_request(target, false, None).build()
+        };
+        let reason_field = format!("target.node_reasons.{}", node(2));
+        issue_fields(vec![
+            (
+                "reasons of all nodes",
+                target("", vec![(node(1), "dead"), (node(2), "slow")]),
+                vec![],
+            ),
+            (
+                "reasons and motivation",
+                target("offboarding", vec![(node(1), "dead")]),
+                vec![],
+            ),
+            (
+                "reasons of some nodes",
+                target("", vec![(node(1), "dead")]),
+                vec!["target.motivation"],
+            ),
+            ("no reasons", target("", vec![]), vec!["target.motivation"]),
+            (
+                "blank motivation",
+                target(" ", vec![(node(1), "dead"), (node(2), "slow")]),
+                vec!["target.motivation"],
+            ),
+            (
+                "blank reason",
+                target("offboarding", vec![(node(2), " ")]),
+                vec![reason_field.as_str()],
+            ),
+            (
+                "reason of another node",
+                target("offboarding", vec![(node(3), "dead")]),
+                vec!["target.node_reasons"],
+            ),
+        ]);
+    }
+
+    #[test]
+    fn nodes_must_share_the_subnet() {
+        let subnet = PrincipalId::new_subnet_test_id;
+        let nodes = |subnets: &[Option<PrincipalId>]| {
+            subnets
+                .iter()
+                .enumerate()
+                .map(|(i, subnet)| (node(i as u64), *subnet))
+                .collect::<BTreeMap<_, _>>()
+        };
+
+        let same = nodes(&[Some(subnet(1)), Some(subnet(1))]);
+        assert_eq!(common_subnet(&same, None), Ok(subnet(1)));
+        assert_eq!(common_subnet(&same, Some(subnet(1))), Ok(subnet(1)));
+        assert_eq!(
+            common_subnet(&same, Some(subnet(2))),
+            Err(ManagementError::NodesNotInSubnet {
+                subnet_id: Some(subnet(2)),
+                offenders: same.clone(),
+            })
+        );
+
+        let mixed = nodes(&[Some(subnet(1)), Some(subnet(2)), None]);
+        assert_eq!(
+            common_subnet(&mixed, None),
+            Err(ManagementError::NodesNotInSubnet {
+                subnet_id: None,
+                offenders: mixed.clone(),
+            })
+        );
+        let err = common_subnet(&mixed, Some(subnet(1))).unwrap_err();
+        assert_eq!(
+            err,
+            ManagementError::NodesNotInSubnet {
+                subnet_id: Some(subnet(1)),
+                offenders: BTreeMap::from([(node(1), Some(subnet(2))), (node(2), None)]),
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "nodes not in subnet {}: {} (in subnet {}), {} (unassigned)",
+                subnet(1),
+                node(1),
+                subnet(2),
+                node(2)
+            )
+        );
+
+        let unassigned = nodes(&[None]);
+        assert!(common_subnet(&unassigned, None).is_err());
+    }
+
+    fn create_request(size: usize, include: Vec<PrincipalId>, exclude: Vec<String>) -> SubnetCreateRequestBuilder {
+        SubnetCreateRequest::builder(size).include(include).exclude(exclude)
+    }
+
+    #[test]
+    fn subnet_create_requests() {
+        issue_fields(vec![
+            (
+                "size",
+                create_request(13, vec![node(1)], vec!["CH".to_string()]).build(),
+                vec![],
+            ),
+            ("empty", create_request(0, vec![], vec![]).build(), vec!["size"]),
+            (
+                "too large",
+                create_request(MAX_SUBNET_SIZE + 1, vec![], vec![]).build(),
+                vec!["size"],
+            ),
+            (
+                "too many included",
+                create_request(1, vec![node(1), node(2)], vec![]).build(),
+                vec!["include"],
+            ),
+            (
+                "included and excluded",
+                create_request(4, vec![node(1)], vec![node(1).to_string()]).build(),
+                vec!["include"],
+            ),
+            (
+                "european",
+                create_request(13, vec![], vec![])
+                    .subnet_kind(SubnetKind::European)
+                    .build(),
+                vec![],
+            ),
+            (
+                "unknown kind",
+                create_request(13, vec![], vec![])
+                    .subnet_kind(SubnetKind::Other("cloud_engine".to_string()))
+                    .build(),
+                vec!["subnet_kind"],
+            ),
+        ]);
+    }
+
+    fn resize_request(
+        add: usize,
+        remove: usize,
+        include: Vec<PrincipalId>,
+    ) -> Result<SubnetResizeRequest, InvalidRequest> {
+        SubnetResizeRequest::builder(PrincipalId::new_subnet_test_id(1))
+            .add(add)
+            .remove(remove)
+            .exclude([node(3).to_string()])
+            .include(include)
+            .build()
+    }
+
+    #[test]
+    fn subnet_resize_requests() {
+        issue_fields(vec![
+            ("add", resize_request(2, 0, vec![node(1)]), vec![]),
+            ("remove", resize_request(0, 2, vec![]), vec![]),
+            ("nothing to do", resize_request(0, 0, vec![]), vec!["add"]),
+            (
+                "too many included",
+                resize_request(1, 0, vec![node(1), node(2)]),
+                vec!["include"],
+            ),
+            (
+                "included and excluded",
+                resize_request(1, 0, vec![node(3)]),
+                vec!["include"],
+            ),
+        ]);
+    }
+
+    fn removal_request(nodes: Vec<PrincipalId>, motivation: &str) -> Result<NodeRemovalRequest, InvalidRequest> {
+        NodeRemovalRequest::builder(DecommissionReason::Offboarding)
+            .nodes(nodes)
+            .motivation(motivation)
+            .build()
+    }
+
+    #[test]
+    fn node_removal_requests() {
+        issue_fields(vec![
+            ("nodes", removal_request(vec![node(1), node(2)], "Offboarding"), vec![]),
+            ("no nodes", removal_request(vec![], "Offboarding"), vec!["nodes"]),
+            (
+                "duplicate nodes",
+                removal_request(vec![node(1), node(1)], "Offboarding"),
+                vec!["nodes"],
+            ),
+            ("no motivation", removal_request(vec![node(1)], ""), vec!["motivation"]),
+            (
+                "structured motivation",
+                NodeRemovalRequest::builder(DecommissionReason::Offboarding)
+                    .node(node(1))
+                    .motivation(Motivation::new(MotivationCategory::Offboard, "Leaving"))
+                    .build(),
+                vec![],
+            ),
+            (
+                "structured motivation without details",
+                NodeRemovalRequest::builder(DecommissionReason::Offboarding)
+                    .node(node(1))
+                    .motivation(Motivation::new(MotivationCategory::Offboard, ""))
+                    .build(),
+                vec!["motivation"],
+            ),
+        ]);
+    }
+
+    #[test]
+    fn issues_are_checked_as_illegal_requests() {
+        // As received by the backend, not checked when built.
+        let request: NodeRemovalRequest = serde_json::from_value(serde_json::json!({
+            "nodes": [],
+            "reason": "offboarding",
+            "motivation": "",
+        }))
+        .unwrap();
+        assert!(matches!(
+            request.check(),
+            Err(NetworkError::IllegalRequest(message))
+                if message == "nodes: No nodes given; motivation: The motivation must not be empty"
+        ));
+        assert!(removal_request(vec![node(1)], "Offboarding").unwrap().check().is_ok());
+    }
+}
@@ -0,0 +1,362 @@
+//! Replica versions, the releases publishing them and the progress of their
+//! rollout over the subnets.
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::str::FromStr;
+
+use ic_types::PrincipalId;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::timestamp::Timestamp;
+use crate::Subnet;
+
+/// A replica version: the 40 hex digit hash of the commit it is built from.
+/// Versions are validated when parsed or deserialized, so that malformed
+/// hashes never make it into a proposal.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct ReplicaVersion(String);
+
+impl ReplicaVersion {
+    const LENGTH: usize = 40;
+    const SHORT_LENGTH: usize = 7;
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The abbreviated commit hash, as `git log --oneline` shows it.
+    pub fn short(&self) -> &str {
+        &self.0[..Self::SHORT_LENGTH]
+    }
+}
+
+impl Display for ReplicaVersion {
+    /// The full commit hash, or the abbreviated one with `{:#}`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if f.alternate() {
+            write!(f, "{}", self.short())
+        } else {
+            write!(f, "{}", self.0)
+        }
+    }
+}
+
+impl FromStr for ReplicaVersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() != Self::LENGTH {
+            return Err(format!(
+                "Invalid replica version '{}': expected a commit hash of {} hex digits, got {} characters",
+                s,
+                Self::LENGTH,
+                s.len()
+            ));
+        }
+        if let Some(c) = s.chars().find(|c| !matches!(c, '0'..='9' | 'a'..='f')) {
+            return Err(format!(
+                "Invalid replica version '{}': '{}' is not a lowercase hex digit",
+                s, c
+            ));
+        }
+        Ok(Self(s.to_string()))
+    }
+}
+
+impl TryFrom<String> for ReplicaVersion {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<ReplicaVersion> for String {
+    fn from(version: ReplicaVersion) -> Self {
+        version.0
+    }
+}
+
+/// A published release of a replica version.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Release {
+    pub name: String,
+    pub version: ReplicaVersion,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub release_notes_url: Option<Url>,
+    pub published_at: Timestamp,
+}
+
+/// How far a version is rolled out: the subnets running it and the ones
+/// still to be updated to it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RolloutStatus {
+    pub version: ReplicaVersion,
+    pub subnets_on_version: Vec<PrincipalId>,
+    pub subnets_pending: Vec<PrincipalId>,
+}
+
+impl RolloutStatus {
+    pub fn is_complete(&self) -> bool {
+        self.subnets_pending.is_empty()
+    }
+}
+
+/// An executed proposal that updated the replica version of a subnet.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SubnetVersionUpdate {
+    pub proposal_id: u64,
+    pub subnet: PrincipalId,
+    pub version: String,
+    pub executed_at: Timestamp,
+}
+
+/// Since when a subnet runs its version, if the proposal that updated it is
+/// known.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SubnetVersionInfo {
+    pub subnet: PrincipalId,
+    pub since: Option<Timestamp>,
+    pub proposal_id: Option<u64>,
+}
+
+/// The subnets running a version, in the order of their ids.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VersionAdoption {
+    pub version: ReplicaVersion,
+    pub subnets: Vec<SubnetVersionInfo>,
+}
+
+/// Which subnets run which version, for following a rollout over the whole
+/// network. The most recently adopted version comes first.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NetworkRolloutStatus {
+    pub versions: Vec<VersionAdoption>,
+}
+
+impl NetworkRolloutStatus {
+    /// The versions the `subnets` run, with the time and proposal of the
+    /// latest of the `updates` of each subnet if it updated the subnet to the
+    /// version it runs. Fails on a subnet with a malformed version.
+    pub fn new<'a>(
+        subnets: impl IntoIterator<Item = &'a Subnet>,
+        updates: &[SubnetVersionUpdate],
+    ) -> Result<Self, String> {
+        let mut latest_updates = BTreeMap::new();
+        for update in updates {
+            latest_updates
+                .entry(update.subnet)
+                .and_modify(|latest: &mut &SubnetVersionUpdate| {
+                    if (update.executed_at, update.proposal_id) > (latest.executed_at, latest.proposal_id) {
+                        *latest = update;
+                    }
+                })
+                .or_insert(update);
+        }
+
+        let mut adoptions = BTreeMap::<ReplicaVersion, Vec<SubnetVersionInfo>>::new();
+        for subnet in subnets {
+            let version = subnet
+                .replica_version
+                .parse::<ReplicaVersion>()
+                .map_err(|e| format!("Subnet {}: {}", subnet.principal, e))?;
+            let update = latest_updates
+                .get(&subnet.principal)
+                .filter(|update| update.version == subnet.replica_version);
+            adoptions.entry(version).or_default().push(SubnetVersionInfo {
+                subnet: subnet.principal,
+                since: update.map(|update| update.executed_at),
+                proposal_id: update.map(|update| update.proposal_id),
+            });
+        }
+
+        let mut versions = adoptions
+            .into_iter()
+            .map(|(version, mut subnets)| {
+                subnets.sort_by_key(|info| info.subnet);
+                VersionAdoption { version, subnets }
+            })
+            .collect::<Vec<_>>();
+        // Stable, so that versions adopted at the same time stay in order.
+        versions.sort_by_key(|adoption| std::cmp::Reverse(adoption.latest_adoption()));
+        Ok(Self { versions })
+    }
+
+    /// The version `subnet` runs, if it is known.
+    pub fn version_of(&self, subnet: &PrincipalId) -> Option<&ReplicaVersion> {
+        self.versions
+            .iter()
+            .find(|adoption| adoption.subnets.iter().any(|info| info.subnet == *subnet))
+            .map(|adoption| &adoption.version)
+    }
+}
+
+impl VersionAdoption {
+    /// When the last subnet was updated to the version, if it is known.
+    pub fn latest_adoption(&self) -> Option<Timestamp> {
+        self.subnets.iter().filter_map(|info| info.since).max()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VERSION: &str = "85bd56a70e55b2cea75cae6405ae11243e5fdad8";
+
+    #[test]
+    fn malformed_versions_are_rejected() {
+        assert_eq!(VERSION.parse::<ReplicaVersion>().unwrap().as_str(), VERSION);
+        for (invalid, reason) in [
+            ("", "got 0 characters"),
+            ("85bd56a", "got 7 characters"),
+            ("85bd56a70e55b2cea75cae6405ae11243e5fdad8a", "got 41 characters"),
+            (
+                "85BD56A70E55B2CEA75CAE6405AE11243E5FDAD8",
+                "'B' is not a lowercase hex digit",
+            ),
+            (
+                "85bd56a70e55b2cea75cae6405ae11243e5fdadg",
+                "'g' is not a lowercase hex digit",
+            ),
+        ] {
+            let err = invalid.parse::<ReplicaVersion>().unwrap_err();
+            assert!(err.contains(reason), "{}: {}", invalid, err);
+        }
+    }
+
+    #[test]
+    fn versions_are_validated_when_deserialized() {
+        let version = VERSION.parse::<ReplicaVersion>().unwrap();
+        let json = serde_json::to_string(&version).unwrap();
+        assert_eq!(json, format!("\"{}\"", VERSION));
+        assert_eq!(serde_json::from_str::<ReplicaVersion>(&json).unwrap(), version);
+        assert!(serde_json::from_str::<ReplicaVersion>("\"85bd56a\"").is_err());
+
+        let release = serde_json::json!({
+            "name": "rc--2024-01-25_23-01",
+            "version": VERSION,
+            "release_notes_url": "https://github.com/dfinity/ic/releases/tag/rc--2024-01-25_23-01",
+            "published_at": "2024-01-25T23:01:00",
+        });
+        let parsed = serde_json::from_value::<Release>(release.clone()).unwrap();
+        assert_eq!(parsed.version, version);
+        let mut serialized = release.clone();
+        serialized["published_at"] = serde_json::json!("2024-01-25T23:01:00.000Z");
+        assert_eq!(serde_json::to_value(&parsed).unwrap(), serialized);
+
+        let mut malformed = release;
+        malformed["version"] = serde_json::json!("rc--2024-01-25_23-01");
+        let err = serde_json::from_value::<Release>(malformed).unwrap_err();
+        assert!(err.to_string().contains("Invalid replica version"), "{}", err);
+    }
+
+    #[test]
+    fn versions_are_shortened_with_the_alternate_format() {
+        let version = VERSION.parse::<ReplicaVersion>().unwrap();
+        assert_eq!(version.short(), "85bd56a");
+        assert_eq!(format!("{:#}", version), "85bd56a");
+        assert_eq!(version.to_string(), VERSION);
+    }
+
+    const MID_VERSION: &str = "2e921c9adfc71f3edc96a9eb5d85fc742e7d8a9f";
+    const OLD_VERSION: &str = "0a9f7b9d25f4e1aa1e0e2d3e5b2b7c4f8a6d3c21";
+
+    fn subnet(id: u64, version: &str) -> Subnet {
+        Subnet {
+            principal: PrincipalId::new_subnet_test_id(id),
+            replica_version: version.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn update(proposal_id: u64, subnet: u64, version: &str, hours: i64) -> SubnetVersionUpdate {
+        SubnetVersionUpdate {
+            proposal_id,
+            subnet: PrincipalId::new_subnet_test_id(subnet),
+            version: version.to_string(),
+            executed_at: Timestamp::from_unix_seconds(1_700_000_000 + hours * 3600).unwrap(),
+        }
+    }
+
+    /// Mid-rollout: two subnets are on the new version, two still on the
+    /// previous one and two on a version updated before the known proposals.
+    fn mid_rollout() -> (Vec<Subnet>, Vec<SubnetVersionUpdate>) {
+        let subnets = vec![
+            subnet(1, VERSION),
+            subnet(2, VERSION),
+            subnet(3, MID_VERSION),
+            subnet(4, MID_VERSION),
+            subnet(5, OLD_VERSION),
+            subnet(6, OLD_VERSION),
+        ];
+        let updates = vec![
+            update(100, 3, MID_VERSION, 0),
+            update(101, 4, MID_VERSION, 1),
+            update(102, 2, MID_VERSION, 2),
+            update(110, 1, VERSION, 168),
+            update(111, 2, VERSION, 169),
+            // Executed but not reflected in the registry yet.
+            update(112, 5, VERSION, 170),
+        ];
+        (subnets, updates)
+    }
+
+    #[test]
+    fn rollout_status_lists_coexisting_versions() {
+        let (subnets, updates) = mid_rollout();
+        let status = NetworkRolloutStatus::new(&subnets, &updates).unwrap();
+        assert_eq!(
+            serde_json::to_string_pretty(&status).unwrap(),
+            include_str!("testdata/rollout_status.json").trim_end()
+        );
+        assert_eq!(
+            status
+                .versions
+                .iter()
+                .map(|adoption| adoption.version.as_str())
+                .collect::<Vec<_>>(),
+            vec![VERSION, MID_VERSION, OLD_VERSION]
+        );
+        assert_eq!(
+            status
+                .version_of(&PrincipalId::new_subnet_test_id(5))
+                .map(|v| v.as_str()),
+            Some(OLD_VERSION)
+        );
+        assert_eq!(status.version_of(&PrincipalId::new_subnet_test_id(7)), None);
+
+        // The order of the inputs does not matter.
+        let reversed =
+            NetworkRolloutStatus::new(subnets.iter().rev(), &updates.iter().cloned().rev().collect::<Vec<_>>());
+        assert_eq!(reversed.unwrap(), status);
+    }
+
+    #[test]
+    fn rollout_status_rejects_malformed_versions() {
+        let (mut subnets, updates) = mid_rollout();
+        subnets.push(subnet(7, "rc--2024-01-25_23-01"));
+        let err = NetworkRolloutStatus::new(&subnets, &updates).unwrap_err();
+        assert!(err.contains("Invalid replica version"), "{}", err);
+        assert!(err.contains(&PrincipalId::new_subnet_test_id(7).to_string()), "{}", err);
+        assert_eq!(
+            NetworkRolloutStatus::new(&[], &updates).unwrap(),
+            NetworkRolloutStatus::default()
+        );
+    }
+
+    #[test]
+    fn rollouts_are_complete_without_pending_subnets() {
+        let mut status = RolloutStatus {
+            version: VERSION.parse().unwrap(),
+            subnets_on_version: vec![PrincipalId::new_subnet_test_id(1)],
+            subnets_pending: vec![PrincipalId::new_subnet_test_id(2)],
+        };
+        assert!(!status.is_complete());
+        status.subnets_on_version.append(&mut status.subnets_pending);
+        assert!(status.is_complete());
+    }
+}
@@ -73,6 +73,7 @@ mod tests {
             dc_id: "test".to_string(),
             operator_id: PrincipalId::new_anonymous(),
             node_provider_id: PrincipalId::new_anonymous(),
+            public_ipv4: None,
         }
     }
 
@@ -93,6 +94,7 @@ mod tests {
             dc_id: "test".to_string(),
             operator_id: PrincipalId::new_anonymous(),
             node_provider_id: PrincipalId::new_anonymous(),
+            public_ipv4: None,
         };
         assert!(filter.filter(accepted_tg));
 
@@ -109,6 +111,7 @@ mod tests {
             dc_id: "test".to_string(),
             operator_id: PrincipalId::new_anonymous(),
             node_provider_id: PrincipalId::new_anonymous(),
+            public_ipv4: None,
         };
         assert!(!filter.filter(rejected_tg));
     }
@@ -132,6 +135,7 @@ mod tests {
             dc_id: "test".to_string(),
             operator_id: PrincipalId::new_anonymous(),
             node_provider_id: PrincipalId::new_anonymous(),
+            public_ipv4: None,
         };
         assert!(filterlist.filter(accepted_tg));
 
@@ -148,6 +152,7 @@ mod tests {
             dc_id: "test".to_string(),
             operator_id: PrincipalId::new_anonymous(),
             node_provider_id: PrincipalId::new_anonymous(),
+            public_ipv4: None,
         };
         assert!(!filterlist.filter(rejected_tg_1));
 
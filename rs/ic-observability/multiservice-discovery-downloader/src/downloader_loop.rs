@@ -182,7 +182,7 @@ fn generate_config(cli: &CliArgs, targets: Vec<TargetDto>, logger: Logger) {
                 }
                 .build(targets_with_job),
             },
-            crate::Generator::Metric => PrometheusConfigBuilder {}.build(targets_with_job),
+            crate::Generator::Metric => PrometheusConfigBuilder::default().build(targets_with_job),
         };
 
         let path = cli.output_dir.join(format!("{}.json", job));
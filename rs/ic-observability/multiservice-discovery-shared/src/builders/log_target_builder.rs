@@ -0,0 +1,160 @@
+use std::collections::BTreeSet;
+use std::net::SocketAddr;
+
+use crate::contracts::log_target::{LogTargetDto, NodeType};
+use crate::contracts::target::TargetDto;
+
+/// Builds the targets of the journald log fetcher: one entry per node, with
+/// the URL of its systemd-journal-gatewayd instead of its metrics ports.
+#[derive(Debug, Clone)]
+pub struct LogTargetBuilder {
+    port: u16,
+}
+
+impl LogTargetBuilder {
+    /// `port` is the one systemd-journal-gatewayd listens on, on all nodes.
+    pub fn new(port: u16) -> Self {
+        Self { port }
+    }
+
+    /// Map every target to a log target. A node is reached at the first
+    /// address of its target, targets without an address are left out.
+    /// Targets reached at the same address, e.g. a boundary node added once
+    /// per job, get a single entry: the one of the first of them.
+    pub fn build<I>(&self, targets: I) -> BTreeSet<LogTargetDto>
+    where
+        I: IntoIterator<Item = (TargetDto, NodeType)>,
+    {
+        let mut seen = BTreeSet::new();
+        targets
+            .into_iter()
+            .filter_map(|(target, node_type)| {
+                let address = target.targets.first()?;
+                if !seen.insert(address.ip()) {
+                    return None;
+                }
+                Some(LogTargetDto {
+                    node_id: target.node_id,
+                    name: target.name,
+                    ic_name: target.ic_name,
+                    subnet_id: target.subnet_id,
+                    dc_id: target.dc_id,
+                    node_provider_id: target.node_provider_id,
+                    node_type,
+                    journald_url: format!("http://{}", SocketAddr::new(address.ip(), self.port)),
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use ic_types::{NodeId, PrincipalId, SubnetId};
+    use serde::Serialize;
+    use serde_json::{json, Value};
+    use service_discovery::job_types::{JobType, NodeOS};
+
+    use super::*;
+
+    fn target(node_id: NodeId, name: &str, address: &str) -> TargetDto {
+        TargetDto {
+            node_id,
+            ic_name: "mercury".to_string(),
+            targets: BTreeSet::from([address.parse().unwrap()]),
+            subnet_id: None,
+            dc_id: "".to_string(),
+            operator_id: PrincipalId::new_anonymous(),
+            node_provider_id: PrincipalId::new_anonymous(),
+            jobs: vec![JobType::NodeExporter(NodeOS::Guest)],
+            custom_labels: BTreeMap::new(),
+            name: name.to_string(),
+            public_ipv4: None,
+        }
+    }
+
+    fn json_of<T: Serialize>(value: &T) -> Value {
+        serde_json::to_value(value).unwrap()
+    }
+
+    fn by_name(values: Value) -> Vec<Value> {
+        let mut values = values.as_array().unwrap().clone();
+        values.sort_by_key(|v| v["name"].as_str().unwrap().to_string());
+        values
+    }
+
+    #[test]
+    fn log_targets_golden() {
+        let node_id = NodeId::from(PrincipalId::new_node_test_id(1));
+        let subnet_id = SubnetId::from(PrincipalId::new_subnet_test_id(2));
+        let provider_id = PrincipalId::new_user_test_id(3);
+        let anonymous = NodeId::from(PrincipalId::new_anonymous());
+        let replica = TargetDto {
+            subnet_id: Some(subnet_id),
+            dc_id: "zh1".to_string(),
+            node_provider_id: provider_id,
+            ..target(node_id, &node_id.to_string(), "[2001:db8::1]:9100")
+        };
+        let targets = vec![
+            (replica, NodeType::Replica),
+            (
+                target(anonymous, "bn-1", "[2001:db8::2]:9100"),
+                NodeType::BoundaryNode,
+            ),
+            (
+                target(anonymous, "api-bn-1", "192.0.2.1:9100"),
+                NodeType::ApiBoundaryNode,
+            ),
+            (
+                target(anonymous, "bn-1-process-exporter", "[2001:db8::2]:9102"),
+                NodeType::BoundaryNode,
+            ),
+        ];
+
+        let log_targets = LogTargetBuilder::new(19531).build(targets);
+
+        let expected = json!([
+            {
+                "node_id": json_of(&anonymous),
+                "name": "api-bn-1",
+                "ic_name": "mercury",
+                "subnet_id": null,
+                "dc_id": "",
+                "node_provider_id": json_of(&PrincipalId::new_anonymous()),
+                "node_type": "api_boundary_node",
+                "journald_url": "http://192.0.2.1:19531",
+            },
+            {
+                "node_id": json_of(&anonymous),
+                "name": "bn-1",
+                "ic_name": "mercury",
+                "subnet_id": null,
+                "dc_id": "",
+                "node_provider_id": json_of(&PrincipalId::new_anonymous()),
+                "node_type": "boundary_node",
+                "journald_url": "http://[2001:db8::2]:19531",
+            },
+            {
+                "node_id": json_of(&node_id),
+                "name": node_id.to_string(),
+                "ic_name": "mercury",
+                "subnet_id": json_of(&subnet_id),
+                "dc_id": "zh1",
+                "node_provider_id": json_of(&provider_id),
+                "node_type": "replica",
+                "journald_url": "http://[2001:db8::1]:19531",
+            },
+        ]);
+        assert_eq!(
+            by_name(serde_json::to_value(&log_targets).unwrap()),
+            by_name(expected)
+        );
+
+        // The fetcher reads the entries back with the same type.
+        let parsed: BTreeSet<LogTargetDto> =
+            serde_json::from_str(&serde_json::to_string(&log_targets).unwrap()).unwrap();
+        assert_eq!(parsed, log_targets);
+    }
+}
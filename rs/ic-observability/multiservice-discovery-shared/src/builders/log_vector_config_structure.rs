@@ -247,6 +247,7 @@ mod tests {
             jobs: jobs.clone(),
             operator_id: PrincipalId::new_anonymous(),
             custom_labels: custom_labels.clone(),
+            public_ipv4: None,
         });
         target_dto.insert(TargetDto {
             node_id: PrincipalId::new_anonymous().into(),
@@ -259,6 +260,7 @@ mod tests {
             jobs: jobs.clone(),
             operator_id: PrincipalId::new_anonymous(),
             custom_labels: custom_labels.clone(),
+            public_ipv4: None,
         });
 
         let config = builder.build(target_dto);
@@ -1,10 +1,12 @@
 use std::collections::{BTreeMap, BTreeSet};
+use std::net::{Ipv4Addr, SocketAddr};
 
 use ic_types::PrincipalId;
-use serde::{Serialize, Serializer};
+use serde::{Deserialize, Serialize, Serializer};
 use service_discovery::job_types::JobType;
 use service_discovery::jobs::Job;
 
+use crate::builders::transformers::{TargetTransformer, TransformerPipeline};
 use crate::{builders::ConfigBuilder, contracts::target::TargetDto};
 
 #[derive(Serialize, Debug, Clone, PartialEq, PartialOrd, Ord, Eq)]
@@ -13,6 +15,33 @@ pub struct PrometheusStaticConfig {
     pub labels: BTreeMap<String, String>,
 }
 
+impl PrometheusStaticConfig {
+    /// Check that Prometheus accepts the config, which it only does if it
+    /// has a target and its label names match `[a-zA-Z_][a-zA-Z0-9_]*`.
+    /// Prometheus rejects the whole file of a single invalid config.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.targets.is_empty() {
+            return Err(format!("no targets for the labels {:?}", self.labels));
+        }
+        match self.labels.keys().find(|name| !is_valid_label_name(name)) {
+            Some(name) => Err(format!(
+                "invalid label name {:?} of {:?}",
+                name, self.targets
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Whether `name` matches `[a-zA-Z_][a-zA-Z0-9_]*`.
+pub fn is_valid_label_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub struct PrometheusFileSdConfig {
     configs: BTreeSet<PrometheusStaticConfig>,
@@ -28,27 +57,117 @@ impl Serialize for PrometheusFileSdConfig {
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct PrometheusConfigBuilder {}
-
-fn get_endpoints(target_group: TargetDto, job: JobType) -> BTreeSet<String> {
-    let binding = Job::all();
-    let job = binding.iter().find(|j| j._type == job).unwrap();
-
-    target_group
-        .targets
-        .iter()
-        .map(|g| {
-            let mut g = *g;
-            g.set_port(job.port);
-            format!(
-                "{}://{}/{}",
-                job.scheme,
-                g,
-                job.endpoint.trim_start_matches('/'),
-            )
-        })
-        .collect()
+#[derive(Debug, Clone, Default)]
+pub struct PrometheusConfigBuilder {
+    /// Applied to the static configs before they are serialized. The default,
+    /// empty pipeline outputs them unchanged.
+    pub transformers: TransformerPipeline,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Scheme {
+    Http,
+    Https,
+}
+
+impl Scheme {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scheme::Http => "http",
+            Scheme::Https => "https",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum AddressFamily {
+    Ipv6,
+    Ipv4,
+    /// IPv4 for the nodes that have an IPv4 address, IPv6 for the others.
+    PreferIpv4,
+}
+
+/// How the targets of a definition are rendered. Unset options keep the
+/// scheme of the job and the addresses of the node's connection endpoint.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct ScrapeOptions {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scheme: Option<Scheme>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address_family: Option<AddressFamily>,
+}
+
+impl ScrapeOptions {
+    /// The addresses to scrape out of `targets` and the node's public IPv4
+    /// address. Empty if there is none of the requested family.
+    pub fn select_addresses(
+        &self,
+        targets: &BTreeSet<SocketAddr>,
+        public_ipv4: Option<Ipv4Addr>,
+    ) -> BTreeSet<SocketAddr> {
+        let ipv6 = || targets.iter().filter(|a| a.is_ipv6()).cloned().collect();
+        let ipv4 = || -> BTreeSet<SocketAddr> {
+            targets
+                .iter()
+                .filter(|a| a.is_ipv4())
+                .cloned()
+                .chain(public_ipv4.map(|ip| SocketAddr::new(ip.into(), 0)))
+                .collect()
+        };
+        match self.address_family {
+            None => targets.clone(),
+            Some(AddressFamily::Ipv6) => ipv6(),
+            Some(AddressFamily::Ipv4) => ipv4(),
+            Some(AddressFamily::PreferIpv4) => {
+                let ipv4 = ipv4();
+                if ipv4.is_empty() {
+                    ipv6()
+                } else {
+                    ipv4
+                }
+            }
+        }
+    }
+
+    /// The URLs to scrape for `job` on the given addresses.
+    pub fn endpoints(
+        &self,
+        targets: &BTreeSet<SocketAddr>,
+        public_ipv4: Option<Ipv4Addr>,
+        job: JobType,
+    ) -> BTreeSet<String> {
+        self.endpoints_on_port(targets, public_ipv4, job, None)
+    }
+
+    /// The URLs to scrape for `job` on the given addresses, on `port` instead
+    /// of the default port of the job if given.
+    pub fn endpoints_on_port(
+        &self,
+        targets: &BTreeSet<SocketAddr>,
+        public_ipv4: Option<Ipv4Addr>,
+        job: JobType,
+        port: Option<u16>,
+    ) -> BTreeSet<String> {
+        let binding = Job::all();
+        let job = binding.iter().find(|j| j._type == job).unwrap();
+        let scheme = self.scheme.map_or(job.scheme, |s| s.as_str());
+        let port = port.unwrap_or(job.port);
+
+        self.select_addresses(targets, public_ipv4)
+            .into_iter()
+            .map(|mut g| {
+                g.set_port(port);
+                format!(
+                    "{}://{}/{}",
+                    scheme,
+                    g,
+                    job.endpoint.trim_start_matches('/'),
+                )
+            })
+            .collect()
+    }
 }
 
 const IC_NAME: &str = "ic";
@@ -62,13 +181,36 @@ const JOB: &str = "job";
 // const NODE_OPERATOR_ID: &str = "node_operator_id";
 
 pub fn map_target_group(target_groups: BTreeSet<TargetDto>) -> BTreeSet<PrometheusStaticConfig> {
-    target_groups
+    map_target_group_with_options(target_groups, &ScrapeOptions::default(), &BTreeMap::new()).0
+}
+
+/// Map the target groups to static configs rendered according to `options`,
+/// together with the number of target groups left out because they have no
+/// address of the requested family. The jobs in `ports` are scraped on the
+/// given port instead of their default one.
+pub fn map_target_group_with_options(
+    target_groups: BTreeSet<TargetDto>,
+    options: &ScrapeOptions,
+    ports: &BTreeMap<JobType, u16>,
+) -> (BTreeSet<PrometheusStaticConfig>, usize) {
+    let mut skipped = 0;
+    let configs = target_groups
         .into_iter()
         .flat_map(|tg| {
             let mut ret = vec![];
             for job in &tg.jobs {
+                let targets = options.endpoints_on_port(
+                    &tg.targets,
+                    tg.public_ipv4,
+                    *job,
+                    ports.get(job).copied(),
+                );
+                if targets.is_empty() {
+                    skipped += 1;
+                    continue;
+                }
                 ret.push(PrometheusStaticConfig {
-                    targets: get_endpoints(tg.clone(), *job),
+                    targets,
                     labels: {
                         let anonymous = PrincipalId::new_anonymous().to_string();
                         let mut node_id = tg.node_id.to_string();
@@ -94,13 +236,176 @@ pub fn map_target_group(target_groups: BTreeSet<TargetDto>) -> BTreeSet<Promethe
             }
             ret
         })
-        .collect()
+        .collect();
+    (configs, skipped)
 }
 
 impl ConfigBuilder for PrometheusConfigBuilder {
     fn build(&self, target_groups: BTreeSet<TargetDto>) -> String {
-        let new_configs: BTreeSet<PrometheusStaticConfig> = map_target_group(target_groups);
+        let new_configs: BTreeSet<PrometheusStaticConfig> = self
+            .transformers
+            .transform(map_target_group(target_groups).into_iter().collect())
+            .into_iter()
+            .collect();
 
         serde_json::to_string_pretty(&new_configs).unwrap()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use ic_types::NodeId;
+    use service_discovery::job_types::NodeOS;
+
+    use super::*;
+
+    fn node(ipv6: Option<&str>, public_ipv4: Option<&str>) -> TargetDto {
+        TargetDto {
+            node_id: NodeId::from(PrincipalId::new_node_test_id(1)),
+            ic_name: "mercury".to_string(),
+            targets: ipv6
+                .map(|a| SocketAddr::from_str(a).unwrap())
+                .into_iter()
+                .collect(),
+            subnet_id: None,
+            dc_id: "dc1".to_string(),
+            operator_id: PrincipalId::new_anonymous(),
+            node_provider_id: PrincipalId::new_anonymous(),
+            jobs: vec![JobType::Orchestrator, JobType::NodeExporter(NodeOS::Guest)],
+            custom_labels: BTreeMap::new(),
+            name: "node".to_string(),
+            public_ipv4: public_ipv4.map(|a| Ipv4Addr::from_str(a).unwrap()),
+        }
+    }
+
+    fn targets(
+        nodes: Vec<TargetDto>,
+        options: ScrapeOptions,
+    ) -> (BTreeSet<BTreeSet<String>>, usize) {
+        let (configs, skipped) =
+            map_target_group_with_options(nodes.into_iter().collect(), &options, &BTreeMap::new());
+        (configs.into_iter().map(|c| c.targets).collect(), skipped)
+    }
+
+    fn urls(urls: &[&[&str]]) -> BTreeSet<BTreeSet<String>> {
+        urls.iter()
+            .map(|u| u.iter().map(|u| u.to_string()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn default_options_keep_current_rendering() {
+        let nodes = vec![node(Some("[2001:db8::1]:9090"), Some("192.0.2.1"))];
+
+        assert_eq!(
+            targets(nodes.clone(), ScrapeOptions::default()),
+            (
+                urls(&[
+                    &["http://[2001:db8::1]:9091/"],
+                    &["https://[2001:db8::1]:9100/metrics"]
+                ]),
+                0
+            )
+        );
+        assert_eq!(map_target_group(nodes.into_iter().collect()).len(), 2);
+    }
+
+    #[test]
+    fn address_family_selects_addresses() {
+        let both = node(Some("[2001:db8::1]:9090"), Some("192.0.2.1"));
+        let only_ipv6 = node(Some("[2001:db8::2]:9090"), None);
+
+        let ipv4 = ScrapeOptions {
+            scheme: Some(Scheme::Https),
+            address_family: Some(AddressFamily::Ipv4),
+        };
+        assert_eq!(
+            targets(vec![both.clone(), only_ipv6.clone()], ipv4),
+            (
+                urls(&[
+                    &["https://192.0.2.1:9091/"],
+                    &["https://192.0.2.1:9100/metrics"]
+                ]),
+                2
+            )
+        );
+
+        let prefer_ipv4 = ScrapeOptions {
+            scheme: None,
+            address_family: Some(AddressFamily::PreferIpv4),
+        };
+        assert_eq!(
+            targets(vec![both.clone(), only_ipv6.clone()], prefer_ipv4),
+            (
+                urls(&[
+                    &["http://192.0.2.1:9091/"],
+                    &["https://192.0.2.1:9100/metrics"],
+                    &["http://[2001:db8::2]:9091/"],
+                    &["https://[2001:db8::2]:9100/metrics"]
+                ]),
+                0
+            )
+        );
+
+        let ipv6 = ScrapeOptions {
+            scheme: Some(Scheme::Http),
+            address_family: Some(AddressFamily::Ipv6),
+        };
+        assert_eq!(
+            targets(vec![both, node(None, Some("192.0.2.3"))], ipv6),
+            (
+                urls(&[
+                    &["http://[2001:db8::1]:9091/"],
+                    &["http://[2001:db8::1]:9100/metrics"]
+                ]),
+                2
+            )
+        );
+    }
+    #[test]
+    fn invalid_configs_are_caught() {
+        let configs = map_target_group(BTreeSet::from([node(Some("[2001:db8::1]:9090"), None)]));
+        assert!(configs.iter().all(|c| c.validate().is_ok()));
+
+        let mut config = configs.into_iter().next().unwrap();
+        config
+            .labels
+            .insert("node-provider".to_string(), "provider 1".to_string());
+        assert!(config.validate().unwrap_err().contains("node-provider"));
+
+        config.labels.remove("node-provider");
+        config.targets.clear();
+        assert!(config.validate().is_err());
+
+        for name in ["ic", "_ic", "ic_node2", "__address__"] {
+            assert!(is_valid_label_name(name), "{}", name);
+        }
+        for name in ["", "2ic", "ic-node", "ic.node", "ïc"] {
+            assert!(!is_valid_label_name(name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn port_overrides_apply_to_their_job_only() {
+        let nodes = vec![node(Some("[2001:db8::1]:9090"), None)];
+        let ports = BTreeMap::from([(JobType::Orchestrator, 19091)]);
+
+        let (configs, _) = map_target_group_with_options(
+            nodes.into_iter().collect(),
+            &ScrapeOptions::default(),
+            &ports,
+        );
+        assert_eq!(
+            configs
+                .into_iter()
+                .map(|c| c.targets)
+                .collect::<BTreeSet<_>>(),
+            urls(&[
+                &["http://[2001:db8::1]:19091/"],
+                &["https://[2001:db8::1]:9100/metrics"]
+            ])
+        );
+    }
+}
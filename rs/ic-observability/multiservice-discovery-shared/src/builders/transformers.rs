@@ -0,0 +1,342 @@
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use regex::Regex;
+
+use crate::builders::prometheus_config_structure::PrometheusStaticConfig;
+
+/// A step applied to the static configs before they are written out, e.g. to
+/// drop some of them or to adjust their targets or labels.
+pub trait TargetTransformer: Send + Sync + Debug {
+    fn transform(&self, target_groups: Vec<PrometheusStaticConfig>) -> Vec<PrometheusStaticConfig>;
+}
+
+/// Transformers applied one after the other, in the order they were added.
+/// An empty pipeline leaves the target groups untouched.
+#[derive(Debug, Clone, Default)]
+pub struct TransformerPipeline {
+    transformers: Vec<Arc<dyn TargetTransformer>>,
+}
+
+impl TransformerPipeline {
+    pub fn new(transformers: Vec<Arc<dyn TargetTransformer>>) -> Self {
+        Self { transformers }
+    }
+
+    pub fn add(&mut self, transformer: Arc<dyn TargetTransformer>) {
+        self.transformers.push(transformer);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transformers.is_empty()
+    }
+}
+
+impl TargetTransformer for TransformerPipeline {
+    fn transform(&self, target_groups: Vec<PrometheusStaticConfig>) -> Vec<PrometheusStaticConfig> {
+        self.transformers
+            .iter()
+            .fold(target_groups, |target_groups, t| t.transform(target_groups))
+    }
+}
+
+/// Drops the target groups whose `label` matches `value` entirely. Groups
+/// without the label are kept.
+#[derive(Debug, Clone)]
+pub struct DropByLabelTransformer {
+    label: String,
+    value: Regex,
+}
+
+impl DropByLabelTransformer {
+    pub fn new(label: String, value: &str) -> Result<Self, regex::Error> {
+        Ok(Self {
+            label,
+            value: Regex::new(&format!("^(?:{})$", value))?,
+        })
+    }
+}
+
+impl FromStr for DropByLabelTransformer {
+    type Err = String;
+
+    /// Parses `<label>=<regex>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (label, value) = s
+            .split_once('=')
+            .ok_or_else(|| format!("Expected <label>=<regex>, got '{}'", s))?;
+        Self::new(label.to_string(), value).map_err(|e| e.to_string())
+    }
+}
+
+impl TargetTransformer for DropByLabelTransformer {
+    fn transform(&self, target_groups: Vec<PrometheusStaticConfig>) -> Vec<PrometheusStaticConfig> {
+        target_groups
+            .into_iter()
+            .filter(|tg| match tg.labels.get(&self.label) {
+                Some(value) => !self.value.is_match(value),
+                None => true,
+            })
+            .collect()
+    }
+}
+
+/// Adds the same labels to every target group. Labels the group already has
+/// are left as they are.
+#[derive(Debug, Clone, Default)]
+pub struct StaticLabelsTransformer {
+    labels: BTreeMap<String, String>,
+}
+
+impl StaticLabelsTransformer {
+    pub fn new(labels: BTreeMap<String, String>) -> Self {
+        Self { labels }
+    }
+}
+
+impl TargetTransformer for StaticLabelsTransformer {
+    fn transform(&self, target_groups: Vec<PrometheusStaticConfig>) -> Vec<PrometheusStaticConfig> {
+        target_groups
+            .into_iter()
+            .map(|mut tg| {
+                for (name, value) in &self.labels {
+                    tg.labels
+                        .entry(name.clone())
+                        .or_insert_with(|| value.clone());
+                }
+                tg
+            })
+            .collect()
+    }
+}
+
+/// Rewrites every target with a regex replacement, e.g. to point the scrapes
+/// at the addresses of a VPN overlay. `replacement` may refer to capture
+/// groups as `$1` or `$name`.
+#[derive(Debug, Clone)]
+pub struct AddressRewriteTransformer {
+    pattern: Regex,
+    replacement: String,
+}
+
+impl AddressRewriteTransformer {
+    pub fn new(pattern: &str, replacement: String) -> Result<Self, regex::Error> {
+        Ok(Self {
+            pattern: Regex::new(pattern)?,
+            replacement,
+        })
+    }
+}
+
+impl FromStr for AddressRewriteTransformer {
+    type Err = String;
+
+    /// Parses `<regex>=<replacement>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (pattern, replacement) = s
+            .split_once('=')
+            .ok_or_else(|| format!("Expected <regex>=<replacement>, got '{}'", s))?;
+        Self::new(pattern, replacement.to_string()).map_err(|e| e.to_string())
+    }
+}
+
+impl TargetTransformer for AddressRewriteTransformer {
+    fn transform(&self, target_groups: Vec<PrometheusStaticConfig>) -> Vec<PrometheusStaticConfig> {
+        target_groups
+            .into_iter()
+            .map(|tg| PrometheusStaticConfig {
+                targets: tg
+                    .targets
+                    .iter()
+                    .map(|t| {
+                        self.pattern
+                            .replace_all(t, self.replacement.as_str())
+                            .into_owned()
+                    })
+                    .collect(),
+                ..tg
+            })
+            .collect()
+    }
+}
+
+/// The prefix of the labels the annotations of nodes are exported as, e.g.
+/// `annotation_rma` for the annotation `rma`.
+pub const ANNOTATION_LABEL_PREFIX: &str = "annotation_";
+
+/// Adds the annotations of each node, by the id in its `ic_node` label, as
+/// labels named after [ANNOTATION_LABEL_PREFIX] and the annotation key. Labels
+/// the group already has are left as they are.
+#[derive(Debug, Clone, Default)]
+pub struct NodeAnnotationLabelsTransformer {
+    annotations: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+impl NodeAnnotationLabelsTransformer {
+    pub fn new(annotations: BTreeMap<String, BTreeMap<String, String>>) -> Self {
+        Self { annotations }
+    }
+}
+
+impl TargetTransformer for NodeAnnotationLabelsTransformer {
+    fn transform(&self, target_groups: Vec<PrometheusStaticConfig>) -> Vec<PrometheusStaticConfig> {
+        target_groups
+            .into_iter()
+            .map(|mut tg| {
+                let annotations = tg
+                    .labels
+                    .get("ic_node")
+                    .and_then(|node| self.annotations.get(node));
+                for (key, value) in annotations.into_iter().flatten() {
+                    tg.labels
+                        .entry(format!("{}{}", ANNOTATION_LABEL_PREFIX, key))
+                        .or_insert_with(|| value.clone());
+                }
+                tg
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::*;
+
+    fn group(target: &str, labels: &[(&str, &str)]) -> PrometheusStaticConfig {
+        PrometheusStaticConfig {
+            targets: BTreeSet::from([target.to_string()]),
+            labels: labels
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn drop_by_label_matches_whole_value() {
+        let transformer: DropByLabelTransformer = "dc=zh1|fr1".parse().unwrap();
+        let groups = vec![
+            group("http://[2001:db8::1]:9100/metrics", &[("dc", "zh1")]),
+            group("http://[2001:db8::2]:9100/metrics", &[("dc", "zh10")]),
+            group("http://[2001:db8::3]:9100/metrics", &[("dc", "fr1")]),
+            group("http://[2001:db8::4]:9100/metrics", &[]),
+        ];
+
+        let kept = transformer.transform(groups);
+
+        assert_eq!(
+            kept,
+            vec![
+                group("http://[2001:db8::2]:9100/metrics", &[("dc", "zh10")]),
+                group("http://[2001:db8::4]:9100/metrics", &[]),
+            ]
+        );
+        assert!("dc".parse::<DropByLabelTransformer>().is_err());
+        assert!("dc=(".parse::<DropByLabelTransformer>().is_err());
+    }
+
+    #[test]
+    fn static_labels_do_not_override_existing_ones() {
+        let transformer = StaticLabelsTransformer::new(BTreeMap::from([
+            ("env".to_string(), "prod".to_string()),
+            ("ic".to_string(), "other".to_string()),
+        ]));
+
+        let groups = transformer.transform(vec![group("a", &[("ic", "mercury")])]);
+
+        assert_eq!(
+            groups,
+            vec![group("a", &[("env", "prod"), ("ic", "mercury")])]
+        );
+    }
+
+    #[test]
+    fn address_rewrite_uses_capture_groups() {
+        let transformer: AddressRewriteTransformer =
+            r"\[2001:db8::(\w+)\]=[fd00::$1]".parse().unwrap();
+
+        let groups = transformer.transform(vec![group(
+            "http://[2001:db8::7]:9100/metrics",
+            &[("job", "node_exporter")],
+        )]);
+
+        assert_eq!(
+            groups,
+            vec![group(
+                "http://[fd00::7]:9100/metrics",
+                &[("job", "node_exporter")]
+            )]
+        );
+    }
+
+    #[test]
+    fn node_annotations_are_added_as_labels() {
+        let transformer = NodeAnnotationLabelsTransformer::new(BTreeMap::from([(
+            "node-1".to_string(),
+            BTreeMap::from([
+                ("rma".to_string(), "pending".to_string()),
+                ("note".to_string(), "spare".to_string()),
+            ]),
+        )]));
+
+        let groups = transformer.transform(vec![
+            group("a", &[("ic_node", "node-1"), ("annotation_note", "set")]),
+            group("b", &[("ic_node", "node-2")]),
+            group("c", &[("name", "node-1")]),
+        ]);
+
+        assert_eq!(
+            groups,
+            vec![
+                group(
+                    "a",
+                    &[
+                        ("annotation_note", "set"),
+                        ("annotation_rma", "pending"),
+                        ("ic_node", "node-1")
+                    ]
+                ),
+                group("b", &[("ic_node", "node-2")]),
+                group("c", &[("name", "node-1")]),
+            ]
+        );
+    }
+
+    #[test]
+    fn pipeline_applies_transformers_in_order() {
+        let groups = vec![
+            group("http://[2001:db8::1]:9100/metrics", &[("ic", "mercury")]),
+            group("http://[2001:db8::2]:9100/metrics", &[("ic", "staging")]),
+        ];
+        assert_eq!(
+            TransformerPipeline::default().transform(groups.clone()),
+            groups
+        );
+
+        let mut pipeline = TransformerPipeline::new(vec![Arc::new(
+            "ic=staging".parse::<DropByLabelTransformer>().unwrap(),
+        )]);
+        pipeline.add(Arc::new(
+            r"2001:db8::=fd00::"
+                .parse::<AddressRewriteTransformer>()
+                .unwrap(),
+        ));
+        // Added after the drop, so it cannot be used to filter on.
+        pipeline.add(Arc::new(StaticLabelsTransformer::new(BTreeMap::from([
+            ("ic".to_string(), "staging".to_string()),
+            ("overlay".to_string(), "vpn".to_string()),
+        ]))));
+
+        assert_eq!(
+            pipeline.transform(groups),
+            vec![group(
+                "http://[fd00::1]:9100/metrics",
+                &[("ic", "mercury"), ("overlay", "vpn")]
+            )]
+        );
+    }
+}
@@ -0,0 +1,39 @@
+use ic_types::{NodeId, PrincipalId, SubnetId};
+use serde::{Deserialize, Serialize};
+
+use super::DataContract;
+
+/// What kind of node a log target is.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum NodeType {
+    Replica,
+    BoundaryNode,
+    ApiBoundaryNode,
+}
+
+/// A node whose journal is fetched from its systemd-journal-gatewayd, as
+/// served by `/log_targets`.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LogTargetDto {
+    /// Anonymous for boundary nodes, which are identified by their name.
+    pub node_id: NodeId,
+    pub name: String,
+    pub ic_name: String,
+    pub subnet_id: Option<SubnetId>,
+    pub dc_id: String,
+    pub node_provider_id: PrincipalId,
+    pub node_type: NodeType,
+    /// The base URL of the systemd-journal-gatewayd of the node.
+    pub journald_url: String,
+}
+
+impl DataContract for LogTargetDto {
+    fn get_name(&self) -> String {
+        self.ic_name.to_string()
+    }
+
+    fn get_id(&self) -> String {
+        self.name.to_string()
+    }
+}
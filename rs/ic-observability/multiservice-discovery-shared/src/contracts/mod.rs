@@ -1,3 +1,4 @@
+pub mod log_target;
 pub mod sns;
 pub mod target;
 
@@ -1,7 +1,7 @@
 use std::{
     collections::{BTreeMap, BTreeSet},
     hash::Hash,
-    net::SocketAddr,
+    net::{Ipv4Addr, SocketAddr},
 };
 
 use ic_types::{NodeId, PrincipalId, SubnetId};
@@ -26,6 +26,9 @@ pub struct TargetDto {
     pub jobs: Vec<JobType>,
     pub custom_labels: BTreeMap<String, String>,
     pub name: String,
+    /// The public IPv4 address of the node, if it has one.
+    #[serde(default)]
+    pub public_ipv4: Option<Ipv4Addr>,
 }
 
 impl DataContract for TargetDto {
@@ -56,6 +59,7 @@ pub fn map_to_target_dto(
         node_provider_id: value.node_provider_id,
         jobs: vec![job_type],
         custom_labels,
+        public_ipv4: value.public_ipv4,
     }
 }
 
@@ -69,6 +73,7 @@ impl From<&TargetDto> for TargetGroup {
             operator_id: value.operator_id,
             subnet_id: value.subnet_id,
             targets: value.targets.clone(),
+            public_ipv4: value.public_ipv4,
         }
     }
 }
@@ -86,6 +91,7 @@ impl From<&TargetGroup> for TargetDto {
             dc_id: value.dc_id.clone(),
             operator_id: value.operator_id,
             node_provider_id: value.node_provider_id,
+            public_ipv4: value.public_ipv4,
         }
     }
 }
@@ -102,5 +108,6 @@ impl Hash for TargetDto {
         self.jobs.hash(state);
         self.name.hash(state);
         self.custom_labels.hash(state);
+        self.public_ipv4.hash(state);
     }
 }
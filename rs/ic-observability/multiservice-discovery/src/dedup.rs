@@ -0,0 +1,184 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::SocketAddr;
+
+use multiservice_discovery_shared::contracts::target::TargetDto;
+use service_discovery::job_types::JobType;
+
+use crate::definition::Definition;
+use crate::metrics::Metrics;
+
+/// [deduplicate_targets] with the opt-outs of the running definitions,
+/// counting the suppressed duplicates in `metrics`.
+pub fn deduplicate_definition_targets(
+    targets: Vec<TargetDto>,
+    definitions: &[Definition],
+    metrics: &Metrics,
+) -> Vec<TargetDto> {
+    let allow_duplicates = definitions
+        .iter()
+        .filter(|d| d.allow_duplicate_targets)
+        .map(|d| d.name.clone())
+        .collect();
+    let (targets, suppressed) = deduplicate_targets(targets, &allow_duplicates);
+    for (definition, count) in suppressed {
+        metrics
+            .suppressed_duplicate_targets
+            .with_label_values(&[&definition])
+            .inc_by(count);
+    }
+    targets
+}
+
+/// Drop the targets that another definition already exports, e.g. when two
+/// definitions point to the same NNS.
+///
+/// A target is identified by its job and address. Definitions are processed
+/// in lexicographic order of their names, so the first of them keeps the
+/// target. The definitions in `allow_duplicates` keep all their targets and
+/// do not suppress the ones of other definitions either.
+///
+/// Returns the remaining targets in their original order, and the number of
+/// suppressed duplicates by definition.
+pub fn deduplicate_targets(
+    targets: Vec<TargetDto>,
+    allow_duplicates: &BTreeSet<String>,
+) -> (Vec<TargetDto>, BTreeMap<String, u64>) {
+    let mut order = (0..targets.len()).collect::<Vec<_>>();
+    order.sort_by(|a, b| targets[*a].ic_name.cmp(&targets[*b].ic_name));
+
+    let mut seen: BTreeSet<(JobType, SocketAddr)> = BTreeSet::new();
+    let mut suppressed: BTreeMap<String, u64> = BTreeMap::new();
+    let mut deduplicated: Vec<Option<TargetDto>> = targets.into_iter().map(Some).collect();
+    for index in order {
+        let target = deduplicated[index].as_mut().unwrap();
+        if allow_duplicates.contains(&target.ic_name) {
+            continue;
+        }
+
+        let new = |job: &JobType, addr: &SocketAddr| !seen.contains(&(*job, *addr));
+        let jobs = target
+            .jobs
+            .iter()
+            .filter(|job| target.targets.iter().any(|addr| new(job, addr)))
+            .cloned()
+            .collect::<Vec<_>>();
+        let addrs = target
+            .targets
+            .iter()
+            .filter(|addr| jobs.iter().any(|job| new(job, addr)))
+            .cloned()
+            .collect::<BTreeSet<_>>();
+
+        let duplicates = target
+            .jobs
+            .iter()
+            .flat_map(|job| target.targets.iter().map(move |addr| (*job, *addr)))
+            .filter(|(job, addr)| !new(job, addr))
+            .count();
+        if duplicates > 0 {
+            *suppressed.entry(target.ic_name.clone()).or_default() += duplicates as u64;
+        }
+        seen.extend(
+            jobs.iter()
+                .flat_map(|job| addrs.iter().map(move |addr| (*job, *addr))),
+        );
+
+        if jobs.is_empty() {
+            deduplicated[index] = None;
+        } else {
+            target.jobs = jobs;
+            target.targets = addrs;
+        }
+    }
+
+    (deduplicated.into_iter().flatten().collect(), suppressed)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use ic_types::{NodeId, PrincipalId};
+    use service_discovery::job_types::NodeOS;
+
+    use super::*;
+
+    fn target(definition: &str, node: u64, addr: &str, jobs: &[JobType]) -> TargetDto {
+        TargetDto {
+            node_id: NodeId::from(PrincipalId::new_node_test_id(node)),
+            ic_name: definition.to_string(),
+            targets: BTreeSet::from([SocketAddr::from_str(addr).unwrap()]),
+            subnet_id: None,
+            dc_id: "dc1".to_string(),
+            operator_id: PrincipalId::new_anonymous(),
+            node_provider_id: PrincipalId::new_anonymous(),
+            jobs: jobs.to_vec(),
+            custom_labels: BTreeMap::new(),
+            name: node.to_string(),
+            public_ipv4: None,
+        }
+    }
+
+    /// Two definitions of the same NNS, where the backup one lags behind and
+    /// does not know node 3 yet.
+    fn overlapping_definitions() -> Vec<TargetDto> {
+        let jobs = [JobType::Replica, JobType::NodeExporter(NodeOS::Guest)];
+        vec![
+            target("mainnet-backup", 1, "[2001:db8::1]:9090", &jobs),
+            target("mainnet-backup", 2, "[2001:db8::2]:9090", &jobs[..1]),
+            target("mainnet", 1, "[2001:db8::1]:9090", &jobs),
+            target("mainnet", 2, "[2001:db8::2]:9090", &jobs),
+            target("mainnet", 3, "[2001:db8::3]:9090", &jobs),
+            target("testnet", 4, "[2001:db8::4]:9090", &jobs),
+        ]
+    }
+
+    #[test]
+    fn first_definition_keeps_overlapping_targets() {
+        let (targets, suppressed) =
+            deduplicate_targets(overlapping_definitions(), &BTreeSet::new());
+
+        let mut expected = overlapping_definitions();
+        expected.drain(..2);
+        assert_eq!(targets, expected);
+        assert_eq!(
+            suppressed,
+            BTreeMap::from([("mainnet-backup".to_string(), 3)])
+        );
+    }
+
+    #[test]
+    fn partially_overlapping_jobs_are_kept() {
+        let only_replica = vec![
+            target("a", 1, "[2001:db8::1]:9090", &[JobType::Replica]),
+            target(
+                "b",
+                1,
+                "[2001:db8::1]:9090",
+                &[JobType::Replica, JobType::Orchestrator],
+            ),
+        ];
+
+        let (targets, suppressed) = deduplicate_targets(only_replica, &BTreeSet::new());
+
+        assert_eq!(
+            targets,
+            vec![
+                target("a", 1, "[2001:db8::1]:9090", &[JobType::Replica]),
+                target("b", 1, "[2001:db8::1]:9090", &[JobType::Orchestrator]),
+            ]
+        );
+        assert_eq!(suppressed, BTreeMap::from([("b".to_string(), 1)]));
+    }
+
+    #[test]
+    fn definitions_can_opt_out() {
+        let allow = BTreeSet::from(["mainnet".to_string()]);
+
+        let (targets, suppressed) = deduplicate_targets(overlapping_definitions(), &allow);
+
+        // The targets of "mainnet" do not suppress the ones of the backup.
+        assert_eq!(targets, overlapping_definitions());
+        assert!(suppressed.is_empty());
+    }
+}
@@ -1,35 +1,130 @@
 use crossbeam_channel::Receiver;
 use crossbeam_channel::Sender;
+use ic_management_types::Network;
 use ic_registry_client::client::ThresholdSigPublicKey;
+use ic_types::NodeId;
+use multiservice_discovery_shared::builders::prometheus_config_structure::ScrapeOptions;
+use serde::{Deserialize, Serialize};
 use service_discovery::job_types::map_jobs;
 use service_discovery::job_types::JobType;
-use service_discovery::{
-    job_types::JobAndPort, registry_sync::sync_local_registry, IcServiceDiscoveryImpl,
-};
+use service_discovery::{job_types::JobAndPort, IcServiceDiscovery, IcServiceDiscoveryImpl};
 use slog::{debug, info, warn, Logger};
 use std::collections::BTreeMap;
 use std::collections::BTreeSet;
 use std::net::SocketAddr;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::thread::JoinHandle;
 use std::{
-    path::PathBuf,
-    time::{Duration, Instant},
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
 };
+use tokio::sync::{oneshot, Mutex};
 use url::Url;
 
+use crate::jitter::Jitter;
+use crate::metrics::Metrics;
+use crate::registry_source::{NnsRegistrySource, RegistrySource};
+
+/// How often a paused definition checks whether it was resumed before its
+/// initial sync.
+const PAUSED_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often a stopping definition checks whether its thread exited.
+pub(crate) const THREAD_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How the registry responses of the NNS of a definition are verified.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VerificationPolicy {
+    /// Verify with the configured public key, syncs fail on bad signatures.
+    Strict,
+    /// Fetch the public key from the NNS on the first sync and pin it.
+    #[default]
+    Fetch,
+    /// Trust whatever public key the NNS reports, on every sync.
+    None,
+}
+
+/// The verification policy of a definition together with the public key it
+/// verifies with, if it has one yet.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Verification {
+    pub policy: VerificationPolicy,
+    pub public_key: Option<ThresholdSigPublicKey>,
+}
+
+impl Verification {
+    /// Without an explicit policy, a definition with a public key is verified
+    /// strictly and one without fetches its key. A key given with `fetch` is
+    /// taken as already pinned.
+    pub fn new(
+        policy: Option<VerificationPolicy>,
+        public_key: Option<ThresholdSigPublicKey>,
+    ) -> Result<Self, String> {
+        let policy = policy.unwrap_or(match public_key {
+            Some(_) => VerificationPolicy::Strict,
+            None => VerificationPolicy::Fetch,
+        });
+        match (policy, public_key) {
+            (VerificationPolicy::Strict, None) => {
+                Err("Verification policy strict requires a public key".to_string())
+            }
+            (VerificationPolicy::None, Some(_)) => {
+                Err("Verification policy none does not take a public key".to_string())
+            }
+            _ => Ok(Self { policy, public_key }),
+        }
+    }
+}
+
+/// Why a sync requested with [`Definition::sync_now`] did not complete.
+#[derive(Debug, PartialEq, Eq)]
+pub enum SyncError {
+    Paused,
+    Stopped,
+    Timeout,
+}
+
 #[derive(Clone)]
 pub struct Definition {
     pub nns_urls: Vec<Url>,
     pub registry_path: PathBuf,
     pub name: String,
-    log: Logger,
-    pub public_key: Option<ThresholdSigPublicKey>,
+    pub(crate) log: Logger,
     pub poll_interval: Duration,
     stop_signal: Receiver<()>,
     pub registry_query_timeout: Duration,
     pub stop_signal_sender: Sender<()>,
     pub ic_discovery: Arc<IcServiceDiscoveryImpl>,
     pub boundary_nodes: Vec<BoundaryNode>,
+    pub registry_source: Arc<dyn RegistrySource>,
+    pub scrape_options: ScrapeOptions,
+    /// Keep the targets of this definition in the aggregate endpoints even
+    /// when another definition exports them too.
+    pub allow_duplicate_targets: bool,
+    /// Whether this definition was added although another definition syncs
+    /// from some of the same NNS urls.
+    pub allow_overlap: bool,
+    pub metrics: Metrics,
+    jitter: Jitter,
+    /// When the registry is synced next, shared with the clones of the
+    /// definition so that it can be listed.
+    next_sync: Arc<RwLock<Option<SystemTime>>>,
+    /// Whether the registry sync is paused, shared with the clones of the
+    /// definition so that the running one can be paused.
+    paused: Arc<AtomicBool>,
+    /// Requests for an immediate poll, each answered once its poll completed.
+    sync_requests: Receiver<oneshot::Sender<()>>,
+    sync_request_sender: Sender<oneshot::Sender<()>>,
+    /// The nodes the definition had targets for after its last poll.
+    targeted_nodes: BTreeSet<NodeId>,
+    /// How the registry is verified, shared with the clones of the definition
+    /// so that a key pinned by the running one can be listed.
+    verification: Arc<RwLock<Verification>>,
+    /// The ports that replace the default port of a job for the targets of
+    /// this definition.
+    port_overrides: BTreeMap<JobType, u16>,
 }
 
 impl Definition {
@@ -38,24 +133,41 @@ impl Definition {
         global_registry_path: PathBuf,
         name: String,
         log: Logger,
-        public_key: Option<ThresholdSigPublicKey>,
+        verification: Verification,
         poll_interval: Duration,
         stop_signal: Receiver<()>,
         registry_query_timeout: Duration,
         stop_signal_sender: Sender<()>,
+        jitter: Jitter,
     ) -> Self {
         let global_registry_path =
             std::fs::canonicalize(global_registry_path).expect("Invalid global registry path");
         let registry_path = global_registry_path.join(name.clone());
-        if std::fs::metadata(&registry_path).is_err() {
-            std::fs::create_dir_all(registry_path.clone()).unwrap();
+        // `create_dir` fails atomically if the directory appeared in the
+        // meantime, in which case it is reused.
+        if let Err(e) = std::fs::create_dir(&registry_path) {
+            if e.kind() != std::io::ErrorKind::AlreadyExists {
+                panic!(
+                    "Failed to create registry dir {}: {:?}",
+                    registry_path.display(),
+                    e
+                );
+            }
+        }
+        if let Err(e) = std::fs::write(registry_path.join(REGISTRY_DIR_MARKER), "") {
+            warn!(
+                log,
+                "Failed to mark registry dir {}: {:?}",
+                registry_path.display(),
+                e
+            );
         }
+        let (sync_request_sender, sync_requests) = crossbeam_channel::unbounded();
         Self {
             nns_urls,
             registry_path: registry_path.clone(),
             name,
             log: log.clone(),
-            public_key,
             poll_interval,
             stop_signal,
             registry_query_timeout,
@@ -70,9 +182,133 @@ impl Definition {
                 .unwrap(),
             ),
             boundary_nodes: vec![],
+            registry_source: Arc::new(NnsRegistrySource),
+            scrape_options: ScrapeOptions::default(),
+            allow_duplicate_targets: false,
+            allow_overlap: false,
+            metrics: Metrics::new(),
+            jitter,
+            next_sync: Arc::new(RwLock::new(None)),
+            paused: Arc::new(AtomicBool::new(false)),
+            sync_requests,
+            sync_request_sender,
+            targeted_nodes: BTreeSet::new(),
+            verification: Arc::new(RwLock::new(verification)),
+            port_overrides: BTreeMap::new(),
         }
     }
 
+    pub fn port_overrides(&self) -> &BTreeMap<JobType, u16> {
+        &self.port_overrides
+    }
+
+    /// Serve the targets of the given jobs on other than their default
+    /// ports. Must be set before the definition is spawned.
+    pub fn set_port_overrides(&mut self, port_overrides: BTreeMap<JobType, u16>) {
+        self.port_overrides = port_overrides;
+        self.ic_discovery = Arc::new(
+            IcServiceDiscoveryImpl::new(
+                self.log.clone(),
+                self.registry_path.clone(),
+                self.registry_query_timeout,
+                self.ports().into_iter().collect(),
+            )
+            .unwrap(),
+        );
+    }
+
+    /// The port the targets of each job are served on.
+    pub fn ports(&self) -> BTreeMap<JobType, u16> {
+        JobAndPort::all()
+            .into_iter()
+            .map(|job| {
+                let port = self.port_overrides.get(&job.job_type).copied();
+                (job.job_type, port.unwrap_or(job.port))
+            })
+            .collect()
+    }
+
+    pub fn verification(&self) -> Verification {
+        *self.verification.read().unwrap()
+    }
+
+    /// Change how the registry is verified, from the next sync on.
+    pub fn set_verification(&self, verification: Verification) {
+        *self.verification.write().unwrap() = verification;
+    }
+
+    /// The public key to verify the registry with. With the `fetch` policy
+    /// the key fetched on the first sync is pinned for all later ones.
+    async fn root_public_key(&self) -> Result<ThresholdSigPublicKey, String> {
+        let verification = self.verification();
+        if let Some(public_key) = verification.public_key {
+            return Ok(public_key);
+        }
+        let public_key = self.registry_source.public_key(&self.nns_urls).await?;
+        if verification.policy == VerificationPolicy::Fetch {
+            info!(
+                self.log,
+                "Pinning the public key of the NNS of {}", self.name
+            );
+            let mut current = self.verification.write().unwrap();
+            // Unless the policy was changed while the key was fetched.
+            if *current == verification {
+                current.public_key = Some(public_key);
+            }
+        }
+        Ok(public_key)
+    }
+
+    fn warn_if_unverified(&self) {
+        if self.verification().policy == VerificationPolicy::None {
+            warn!(
+                self.log,
+                "Syncing the registry of {} without verifying it with a trusted public key",
+                self.name
+            );
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    /// Pause or resume the registry sync, from the next poll on. A paused
+    /// definition keeps its last synced registry and its targets are still
+    /// exported.
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+    }
+
+    /// Poll right away instead of waiting for the next tick and wait until
+    /// that poll completed, or `timeout` elapsed. The periodic polls keep
+    /// their schedule.
+    pub async fn sync_now(&self, timeout: Duration) -> Result<(), SyncError> {
+        if self.is_paused() {
+            return Err(SyncError::Paused);
+        }
+        let (reply_sender, reply) = oneshot::channel();
+        self.sync_request_sender
+            .send(reply_sender)
+            .map_err(|_| SyncError::Stopped)?;
+        match tokio::time::timeout(timeout, reply).await {
+            Ok(Ok(())) => Ok(()),
+            // The poll loop dropped the request because it was stopped.
+            Ok(Err(_)) => Err(SyncError::Stopped),
+            Err(_) => Err(SyncError::Timeout),
+        }
+    }
+
+    /// The time of the next registry sync, if one is scheduled.
+    pub fn next_sync(&self) -> Option<SystemTime> {
+        *self.next_sync.read().unwrap()
+    }
+
+    fn schedule_next_sync(&self, at: Instant) {
+        let at = SystemTime::now() + at.saturating_duration_since(Instant::now());
+        *self.next_sync.write().unwrap() = Some(at);
+    }
+
     async fn initial_registry_sync(&self) {
         info!(self.log, "Syncing local registry for {} started", self.name);
         info!(
@@ -81,57 +317,155 @@ impl Definition {
             self.registry_path.display()
         );
 
-        sync_local_registry(
-            self.log.clone(),
-            self.registry_path.join("targets"),
-            self.nns_urls.clone(),
-            false,
-            self.public_key,
-        )
-        .await;
+        self.warn_if_unverified();
+        let result = match self.root_public_key().await {
+            Ok(public_key) => self.registry_source.initial_sync(self, public_key).await,
+            Err(e) => Err(format!("Unable to get the public key of the NNS: {}", e)),
+        };
 
-        info!(
-            self.log,
-            "Syncing local registry for {} completed", self.name
-        );
+        match result {
+            Ok(()) => info!(
+                self.log,
+                "Syncing local registry for {} completed", self.name
+            ),
+            Err(e) => warn!(
+                self.log,
+                "Syncing local registry for {} failed: {}", self.name, e
+            ),
+        }
     }
 
     async fn poll_loop(&mut self) {
-        let interval = crossbeam::channel::tick(self.poll_interval);
-        let mut tick = Instant::now();
+        // Deadlines are derived from the previous one rather than from the
+        // end of the previous sync, so that the average interval stays equal
+        // to the configured one.
+        let mut deadline = Instant::now();
+        let mut tick = deadline;
+        let mut sync_request: Option<oneshot::Sender<()>> = None;
         loop {
-            debug!(
-                self.log,
-                "Loading new scraping targets for {}, (tick: {:?})", self.name, tick
-            );
-            if let Err(e) = self.ic_discovery.load_new_ics(self.log.clone()) {
-                warn!(
-                    self.log,
-                    "Failed to load new scraping targets for {} @ interval {:?}: {:?}",
-                    self.name,
-                    tick,
-                    e
-                );
-            }
-            debug!(self.log, "Update registries for {}", self.name);
-            if let Err(e) = self.ic_discovery.update_registries().await {
-                warn!(
-                    self.log,
-                    "Failed to sync registry for {} @ interval {:?}: {:?}", self.name, tick, e
-                );
-            }
+            self.poll(tick).await;
 
+            match sync_request.take() {
+                Some(reply) => {
+                    reply.send(()).ok();
+                }
+                None => {
+                    // Polls missed because the sync took too long are skipped.
+                    deadline =
+                        (deadline + self.jitter.interval(self.poll_interval)).max(Instant::now());
+                    self.schedule_next_sync(deadline);
+                }
+            }
             tick = crossbeam::select! {
                 recv(self.stop_signal) -> _ => {
                     info!(self.log, "Received shutdown signal in poll_loop for {}", self.name);
                     return
                 },
-                recv(interval) -> msg => msg.expect("tick failed!")
+                recv(self.sync_requests) -> msg => {
+                    debug!(self.log, "Sync of {} requested", self.name);
+                    sync_request = msg.ok();
+                    Instant::now()
+                },
+                recv(crossbeam::channel::at(deadline)) -> msg => msg.expect("tick failed!")
             }
         }
     }
 
+    /// Load new ICs and sync their registries, unless the definition is
+    /// paused.
+    async fn poll(&mut self, tick: Instant) {
+        if self.is_paused() {
+            debug!(self.log, "Definition {} is paused, not syncing", self.name);
+            return;
+        }
+        debug!(
+            self.log,
+            "Loading new scraping targets for {}, (tick: {:?})", self.name, tick
+        );
+        if let Err(e) = self.ic_discovery.load_new_ics(self.log.clone()) {
+            warn!(
+                self.log,
+                "Failed to load new scraping targets for {} @ interval {:?}: {:?}",
+                self.name,
+                tick,
+                e
+            );
+        }
+        debug!(self.log, "Update registries for {}", self.name);
+        self.warn_if_unverified();
+        if let Err(e) = self.registry_source.update(self).await {
+            warn!(
+                self.log,
+                "Failed to sync registry for {} @ interval {:?}: {:?}", self.name, tick, e
+            );
+        }
+        self.count_evicted_targets();
+    }
+
+    /// Count the nodes that had targets before the poll and are gone from
+    /// the registry since. The endpoints build the targets from the latest
+    /// registry version, so these are no longer served by any of them.
+    fn count_evicted_targets(&mut self) {
+        let targeted_nodes = JobAndPort::all()
+            .into_iter()
+            .filter_map(|job| {
+                self.ic_discovery
+                    .get_target_groups(job.job_type, self.log.clone())
+                    .ok()
+            })
+            .flatten()
+            .map(|target_group| target_group.node_id)
+            .collect::<BTreeSet<_>>();
+        let evicted = self.targeted_nodes.difference(&targeted_nodes).count();
+        if evicted > 0 {
+            info!(
+                self.log,
+                "Evicted the targets of {} nodes removed from the registry of {}",
+                evicted,
+                self.name
+            );
+            self.metrics
+                .evicted_targets
+                .with_label_values(&[&self.name])
+                .inc_by(evicted as u64);
+        }
+        self.targeted_nodes = targeted_nodes;
+    }
+
+    /// Wait until the definition is not paused. Returns false if it is
+    /// stopped in the meantime.
+    fn wait_until_resumed(&self) -> bool {
+        while self.is_paused() {
+            crossbeam::select! {
+                recv(self.stop_signal) -> _ => return false,
+                recv(crossbeam::channel::after(PAUSED_CHECK_INTERVAL)) -> _ => {}
+            }
+        }
+        true
+    }
+
     async fn run(&mut self) {
+        // Definitions started together, e.g. at startup, would otherwise sync
+        // at the same time forever.
+        let start = Instant::now() + self.jitter.initial_offset(self.poll_interval);
+        self.schedule_next_sync(start);
+        crossbeam::select! {
+            recv(self.stop_signal) -> _ => {
+                info!(self.log, "Received shutdown signal before the initial sync of {}", self.name);
+                return
+            },
+            recv(crossbeam::channel::at(start)) -> _ => {}
+        }
+
+        // A definition added paused has no registry yet, so it only starts
+        // syncing once resumed.
+        if !self.wait_until_resumed() {
+            info!(
+                self.log,
+                "Received shutdown signal while {} was paused", self.name
+            );
+            return;
+        }
         self.initial_registry_sync().await;
 
         info!(
@@ -140,21 +474,26 @@ impl Definition {
         );
 
         self.poll_loop().await;
+    }
 
-        if self.name != "mercury" {
-            info!(
+    /// Remove the local registry of the definition. Must only be called once
+    /// the thread running the definition has been joined.
+    fn remove_registry_dir(&self) {
+        if self.name == Network::Mainnet.legacy_name() {
+            return;
+        }
+        info!(
+            self.log,
+            "Removing registry dir '{}' for definition {}...",
+            self.registry_path.display(),
+            self.name
+        );
+
+        if let Err(e) = std::fs::remove_dir_all(self.registry_path.clone()) {
+            warn!(
                 self.log,
-                "Removing registry dir '{}' for definition {}...",
-                self.registry_path.display(),
-                self.name
+                "Failed to remove registry dir for definition {}: {:?}", self.name, e
             );
-
-            if let Err(e) = std::fs::remove_dir_all(self.registry_path.clone()) {
-                warn!(
-                    self.log,
-                    "Failed to remove registry dir for definition {}: {:?}", self.name, e
-                );
-            }
         }
     }
 
@@ -169,10 +508,409 @@ pub fn wrap(mut definition: Definition, rt: tokio::runtime::Handle) -> impl FnMu
     }
 }
 
+/// Run the definition on its own thread, named after the definition so that
+/// it can be found again by [`stop_definition_within`].
+pub fn spawn(definition: Definition, rt: tokio::runtime::Handle) -> JoinHandle<()> {
+    std::thread::Builder::new()
+        .name(definition.name.clone())
+        .spawn(wrap(definition, rt))
+        .expect("Failed to spawn definition thread")
+}
+
+/// Stop the definition, wait for its thread to finish and only then remove
+/// its local registry, so that a new definition with the same name never
+/// sees a partially deleted directory. Waiting gives up after `timeout`: a
+/// thread that did not stop in time is detached, its handle stays in
+/// `handles`, which keeps the name reserved, and its registry is removed once
+/// the thread exits.
+pub async fn stop_definition_within(
+    definition: Definition,
+    handles: &Arc<Mutex<Vec<JoinHandle<()>>>>,
+    timeout: Duration,
+) -> Result<(), String> {
+    let stopped = tokio::time::timeout(timeout, stop_thread(&definition, handles)).await;
+    if stopped.is_ok() {
+        release_name(&definition, handles).await;
+        return Ok(());
+    }
+
+    let error = format!(
+        "Thread of definition {} did not stop within {:?}",
+        definition.name, timeout
+    );
+    let handles = handles.clone();
+    tokio::spawn(async move {
+        stop_thread(&definition, &handles).await;
+        release_name(&definition, &handles).await;
+    });
+    Err(error)
+}
+
+/// Signal the thread of the definition to stop and wait until it exited. A
+/// thread that is busy syncing does not wait for the signal, so it is sent
+/// again until the thread takes it. The thread is polled so that its handle
+/// never leaves `handles` while it runs.
+async fn stop_thread(definition: &Definition, handles: &Mutex<Vec<JoinHandle<()>>>) {
+    while thread_running(&definition.name, handles).await {
+        definition.stop_signal_sender.try_send(()).ok();
+        tokio::time::sleep(THREAD_POLL_INTERVAL).await;
+    }
+}
+
+async fn thread_running(name: &str, handles: &Mutex<Vec<JoinHandle<()>>>) -> bool {
+    let handles = handles.lock().await;
+    handles
+        .iter()
+        .any(|h| h.thread().name() == Some(name) && !h.is_finished())
+}
+
+/// Remove the registry of a definition whose thread exited and only then its
+/// handle, while holding the lock, so that a new definition with the same
+/// name never sees a partially deleted directory.
+async fn release_name(definition: &Definition, handles: &Mutex<Vec<JoinHandle<()>>>) {
+    let mut handles = handles.lock().await;
+    definition.remove_registry_dir();
+    handles.retain(|h| h.thread().name() != Some(definition.name.as_str()));
+}
+
+/// Whether `name` is still taken by a definition thread, including one that
+/// was detached by [`stop_definition_within`] and has not exited yet.
+pub async fn name_reserved(name: &str, handles: &Mutex<Vec<JoinHandle<()>>>) -> bool {
+    let handles = handles.lock().await;
+    handles.iter().any(|h| h.thread().name() == Some(name))
+}
+
+/// Definition names end up as directory names, so only plain names are
+/// accepted.
+pub fn validate_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || name.starts_with('.') || name.contains(&['/', '\\', '\0'][..]) {
+        return Err(format!(
+            "Invalid definition name '{}', it must not be empty, start with a dot or contain path separators",
+            name
+        ));
+    }
+    Ok(())
+}
+
+/// The file that marks a directory in the targets dir as a registry dir
+/// created by this service.
+pub const REGISTRY_DIR_MARKER: &str = ".multiservice-discovery";
+
+/// Remove the registry dirs in `targets_dir` that do not belong to any of the
+/// `definitions`. They are left behind by definitions that were running when
+/// the service was stopped. Directories without [`REGISTRY_DIR_MARKER`] were
+/// not created by this service and are left alone.
+pub fn remove_orphaned_registry_dirs(targets_dir: &Path, definitions: &[String], log: &Logger) {
+    let entries = match std::fs::read_dir(targets_dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            warn!(
+                log,
+                "Failed to scan {} for orphaned registries: {:?}",
+                targets_dir.display(),
+                e
+            );
+            return;
+        }
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !path.join(REGISTRY_DIR_MARKER).is_file() || definitions.contains(&name) {
+            continue;
+        }
+        info!(log, "Removing orphaned registry dir '{}'", path.display());
+        if let Err(e) = std::fs::remove_dir_all(&path) {
+            warn!(
+                log,
+                "Failed to remove orphaned registry dir '{}': {:?}",
+                path.display(),
+                e
+            );
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct BoundaryNode {
     pub name: String,
     pub targets: BTreeSet<SocketAddr>,
     pub custom_labels: BTreeMap<String, String>,
     pub job_type: JobType,
+    /// Whether this is an API boundary node rather than an HTTP gateway.
+    pub api_boundary: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use service_discovery::mainnet_registry::{
+        create_local_store_from_changelog, get_mainnet_delta_6d_c1,
+    };
+    use service_discovery::IcServiceDiscovery;
+    use slog::o;
+
+    use crate::registry_source::{test_public_key, MockRegistrySource};
+
+    use super::*;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn paused_definition_keeps_its_targets_until_resumed() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = Logger::root(slog::Discard, o!());
+        let (stop_signal_sender, stop_signal) = crossbeam::channel::bounded::<()>(0);
+        let mut definition = Definition::new(
+            vec![Url::parse("http://[::1]:1").unwrap()],
+            dir.path().to_path_buf(),
+            "paused".to_string(),
+            log.clone(),
+            Verification::default(),
+            Duration::from_secs(30),
+            stop_signal,
+            Duration::from_millis(100),
+            stop_signal_sender,
+            Jitter::none(),
+        );
+        let targets = |definition: &Definition| {
+            definition
+                .ic_discovery
+                .get_target_groups(JobType::Replica, log.clone())
+                .unwrap()
+                .len()
+        };
+
+        definition.set_paused(true);
+        // The registry advances while the definition is paused.
+        create_local_store_from_changelog(
+            definition.registry_path.join("targets"),
+            get_mainnet_delta_6d_c1(),
+        );
+        definition.poll(Instant::now()).await;
+        assert!(definition.is_paused());
+        assert_eq!(targets(&definition), 0);
+
+        definition.set_paused(false);
+        definition.poll(Instant::now()).await;
+        assert!(targets(&definition) > 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn sync_now_polls_before_the_next_tick() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = Logger::root(slog::Discard, o!());
+        let (stop_signal_sender, stop_signal) = crossbeam::channel::bounded::<()>(0);
+        let definition = Definition::new(
+            vec![Url::parse("http://[::1]:1").unwrap()],
+            dir.path().to_path_buf(),
+            "sync".to_string(),
+            log.clone(),
+            Verification::default(),
+            Duration::from_secs(3600),
+            stop_signal,
+            Duration::from_millis(100),
+            stop_signal_sender.clone(),
+            Jitter::none(),
+        );
+        let targets = |definition: &Definition| {
+            definition
+                .ic_discovery
+                .get_target_groups(JobType::Replica, log.clone())
+                .unwrap()
+                .len()
+        };
+        let handle = spawn(definition.clone(), tokio::runtime::Handle::current());
+        let timeout = Duration::from_secs(30);
+
+        // Waits for the initial sync and the first poll.
+        definition.sync_now(timeout).await.unwrap();
+        assert_eq!(targets(&definition), 0);
+
+        create_local_store_from_changelog(
+            definition.registry_path.join("targets"),
+            get_mainnet_delta_6d_c1(),
+        );
+        definition.sync_now(timeout).await.unwrap();
+        assert!(targets(&definition) > 0);
+
+        definition.set_paused(true);
+        assert_eq!(definition.sync_now(timeout).await, Err(SyncError::Paused));
+
+        stop_signal_sender.send(()).unwrap();
+        tokio::task::spawn_blocking(move || handle.join())
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    fn mocked_definition(dir: &tempfile::TempDir, verification: Verification) -> Definition {
+        let (stop_signal_sender, stop_signal) = crossbeam::channel::bounded::<()>(0);
+        let mut definition = Definition::new(
+            vec![Url::parse("http://[::1]:1").unwrap()],
+            dir.path().to_path_buf(),
+            "verified".to_string(),
+            Logger::root(slog::Discard, o!()),
+            verification,
+            Duration::from_secs(30),
+            stop_signal,
+            Duration::from_millis(100),
+            stop_signal_sender,
+            Jitter::none(),
+        );
+        definition.registry_source = Arc::new(MockRegistrySource::default());
+        definition
+    }
+
+    fn replica_targets(definition: &Definition) -> usize {
+        definition
+            .ic_discovery
+            .get_target_groups(JobType::Replica, definition.log.clone())
+            .unwrap()
+            .len()
+    }
+
+    #[test]
+    fn verification_defaults_to_the_given_key() {
+        let key = test_public_key(1);
+        assert_eq!(
+            Verification::new(None, Some(key)).unwrap().policy,
+            VerificationPolicy::Strict
+        );
+        assert_eq!(
+            Verification::new(None, None).unwrap().policy,
+            VerificationPolicy::Fetch
+        );
+        assert!(Verification::new(Some(VerificationPolicy::Strict), None).is_err());
+        assert!(Verification::new(Some(VerificationPolicy::None), Some(key)).is_err());
+        assert_eq!(
+            Verification::new(Some(VerificationPolicy::Fetch), Some(key)).unwrap(),
+            Verification {
+                policy: VerificationPolicy::Fetch,
+                public_key: Some(key),
+            }
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn strict_sync_fails_with_a_wrong_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let strict = |key| Verification::new(Some(VerificationPolicy::Strict), Some(key)).unwrap();
+
+        let mut definition = mocked_definition(&dir, strict(test_public_key(2)));
+        definition.initial_registry_sync().await;
+        definition.poll(Instant::now()).await;
+        assert_eq!(replica_targets(&definition), 0);
+
+        let mut definition = mocked_definition(&dir, strict(test_public_key(1)));
+        definition.initial_registry_sync().await;
+        definition.poll(Instant::now()).await;
+        assert!(replica_targets(&definition) > 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn fetch_pins_the_key_of_the_first_sync() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut definition = mocked_definition(&dir, Verification::default());
+
+        definition.initial_registry_sync().await;
+        definition.poll(Instant::now()).await;
+        assert!(replica_targets(&definition) > 0);
+        assert_eq!(
+            definition.verification(),
+            Verification {
+                policy: VerificationPolicy::Fetch,
+                public_key: Some(test_public_key(1)),
+            }
+        );
+
+        let unverified = mocked_definition(
+            &dir,
+            Verification::new(Some(VerificationPolicy::None), None).unwrap(),
+        );
+        unverified.initial_registry_sync().await;
+        assert_eq!(unverified.verification().public_key, None);
+    }
+
+    #[test]
+    fn rejects_names_escaping_the_targets_dir() {
+        for name in [
+            "",
+            ".",
+            "..",
+            "../mercury",
+            "foo/bar",
+            "foo\\bar",
+            ".hidden",
+            "a\0b",
+        ] {
+            assert!(
+                validate_name(name).is_err(),
+                "{:?} should be rejected",
+                name
+            );
+        }
+        for name in ["mercury", "testnet-1", "my.ic"] {
+            assert!(validate_name(name).is_ok(), "{:?} should be accepted", name);
+        }
+    }
+
+    #[test]
+    fn removes_orphaned_registry_dirs() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["mercury", "orphan"] {
+            std::fs::create_dir_all(dir.path().join(name).join("targets")).unwrap();
+            std::fs::write(dir.path().join(name).join(REGISTRY_DIR_MARKER), "").unwrap();
+        }
+        std::fs::create_dir_all(dir.path().join("unmarked").join("targets")).unwrap();
+        std::fs::write(dir.path().join("file"), "not a registry").unwrap();
+
+        remove_orphaned_registry_dirs(
+            dir.path(),
+            &["mercury".to_string()],
+            &Logger::root(slog::Discard, o!()),
+        );
+
+        assert!(dir.path().join("mercury").exists());
+        assert!(!dir.path().join("orphan").exists());
+        assert!(dir.path().join("unmarked").exists());
+        assert!(dir.path().join("file").exists());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn a_busy_definition_is_stopped_once_it_waits_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let (stop_signal_sender, stop_signal) = crossbeam::channel::bounded::<()>(0);
+        let thread_stop_signal = stop_signal.clone();
+        let definition = Definition::new(
+            vec![Url::parse("http://[::1]:1").unwrap()],
+            dir.path().to_path_buf(),
+            "busy".to_string(),
+            Logger::root(slog::Discard, o!()),
+            Verification::default(),
+            Duration::from_secs(30),
+            stop_signal,
+            Duration::from_millis(100),
+            stop_signal_sender,
+            Jitter::none(),
+        );
+        // Like a definition in a long sync, the thread only waits for the
+        // stop signal after the first attempt to send it timed out.
+        let handle = std::thread::Builder::new()
+            .name("busy".to_string())
+            .spawn(move || {
+                std::thread::sleep(Duration::from_millis(500));
+                thread_stop_signal.recv().ok();
+            })
+            .unwrap();
+        let handles = Arc::new(Mutex::new(vec![handle]));
+
+        let result = stop_definition_within(definition, &handles, Duration::from_millis(100)).await;
+        assert!(result.unwrap_err().contains("did not stop"));
+        assert!(name_reserved("busy", &handles).await);
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while name_reserved("busy", &handles).await {
+            assert!(Instant::now() < deadline, "busy definition never stopped");
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert!(!dir.path().join("busy").exists());
+    }
 }
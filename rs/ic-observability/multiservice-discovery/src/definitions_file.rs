@@ -0,0 +1,99 @@
+//! Keep --definitions-file in sync with the running definitions, so that
+//! they and their state, e.g. whether they are paused, survive a restart.
+
+use std::io::Write;
+use std::path::PathBuf;
+
+use slog::{info, warn, Logger};
+
+use crate::definition::Definition;
+use crate::server_handlers::dto::DefinitionsDocumentDto;
+
+/// Where the definitions are written to after every change, if anywhere.
+#[derive(Clone)]
+pub struct DefinitionsFile {
+    path: Option<PathBuf>,
+    /// Leave the boundary nodes out, see [`DefinitionsDocumentDto::without_secrets`].
+    exclude_secrets: bool,
+    log: Logger,
+}
+
+impl DefinitionsFile {
+    pub fn new(path: Option<PathBuf>, exclude_secrets: bool, log: Logger) -> Self {
+        Self {
+            path,
+            exclude_secrets,
+            log,
+        }
+    }
+
+    /// Definitions that are not written anywhere.
+    pub fn none(log: Logger) -> Self {
+        Self::new(None, false, log)
+    }
+
+    /// Whether the definitions are written anywhere.
+    pub fn persists(&self) -> bool {
+        self.path.is_some()
+    }
+
+    /// Write `definitions` in the format of `GET /definitions/export`. The
+    /// document is written to a temporary file first and then renamed, so
+    /// that a crash never leaves a partially written file behind. Failures
+    /// are logged, the change of the definitions is kept either way.
+    pub fn save(&self, definitions: &[Definition]) {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+        let mut document = DefinitionsDocumentDto {
+            definitions: definitions.iter().map(|d| d.into()).collect(),
+        };
+        if self.exclude_secrets {
+            document = document.without_secrets();
+        }
+        let result = serde_json::to_vec_pretty(&document)
+            .map_err(std::io::Error::from)
+            .and_then(|content| ic_utils::fs::write_atomically(path, |f| f.write_all(&content)));
+        if let Err(e) = result {
+            warn!(
+                self.log,
+                "Failed to write the definitions to {}: {}",
+                path.display(),
+                e
+            );
+        }
+    }
+
+    /// Copy the file to `<file>.bak`, so that the definitions the next
+    /// [`Self::save`] drops are not lost. Returns false if it could not be
+    /// copied.
+    pub fn back_up(&self) -> bool {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return true,
+        };
+        let mut backup = path.clone().into_os_string();
+        backup.push(".bak");
+        let backup = PathBuf::from(backup);
+        match std::fs::copy(path, &backup) {
+            Ok(_) => {
+                info!(
+                    self.log,
+                    "Kept the definitions file as {}",
+                    backup.display()
+                );
+                true
+            }
+            Err(e) => {
+                warn!(
+                    self.log,
+                    "Failed to copy the definitions file to {}: {}",
+                    backup.display(),
+                    e
+                );
+                false
+            }
+        }
+    }
+}
@@ -0,0 +1,379 @@
+//! Write the targets of all definitions as json files for the file-based
+//! service discovery of Prometheus.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use multiservice_discovery_shared::builders::prometheus_config_structure::PrometheusStaticConfig;
+use sha2::{Digest, Sha256};
+use slog::{debug, warn, Logger};
+use tokio::sync::Mutex;
+
+use crate::definition::Definition;
+use crate::metrics::Metrics;
+use crate::server_handlers::targets_fingerprint;
+
+/// Writes one file per definition and job, `<definition>/<job>.json`, below
+/// its directory. The directory is owned by the writer: json files it did not
+/// produce in the last write are removed.
+pub struct FileSdWriter {
+    directory: PathBuf,
+    metrics: Metrics,
+    /// The hash of the content of the files as last written, relative to
+    /// `directory`.
+    written: BTreeMap<PathBuf, [u8; 32]>,
+}
+
+impl FileSdWriter {
+    pub fn new<P: AsRef<Path>>(directory: P, metrics: Metrics) -> Self {
+        Self {
+            directory: directory.as_ref().to_path_buf(),
+            metrics,
+            written: BTreeMap::new(),
+        }
+    }
+
+    /// Write the files of `configs` and remove the ones of definitions and
+    /// jobs without targets, returning the number of files written. Files
+    /// with unchanged content are not touched, even if they were written
+    /// before the writer was created, so that Prometheus does not reload
+    /// them needlessly.
+    ///
+    /// Every file is written to a temporary file first and then renamed, so
+    /// that Prometheus never reads a partially written file.
+    pub fn write(&mut self, configs: BTreeSet<PrometheusStaticConfig>) -> std::io::Result<usize> {
+        let files = group_by_file(configs);
+        let mut written = 0;
+
+        for (file, configs) in &files {
+            let content = serde_json::to_vec_pretty(configs).map_err(|e| {
+                std::io::Error::new(
+                    std::io::ErrorKind::Other,
+                    format!("Serialization error: {:?}", e),
+                )
+            })?;
+            let hash: [u8; 32] = Sha256::digest(&content).into();
+            let path = self.directory.join(file);
+            let previous = match self.written.get(file) {
+                Some(previous) => Some(*previous),
+                None => std::fs::read(&path)
+                    .ok()
+                    .map(|previous| Sha256::digest(previous).into()),
+            };
+            if previous == Some(hash) {
+                self.written.insert(file.clone(), hash);
+                continue;
+            }
+            std::fs::create_dir_all(path.parent().unwrap())?;
+            ic_utils::fs::write_atomically(&path, |f| f.write_all(&content))?;
+            self.written.insert(file.clone(), hash);
+            written += 1;
+            self.metrics
+                .file_sd_last_write
+                .with_label_values(&[&file.to_string_lossy()])
+                .set(
+                    SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap()
+                        .as_secs_f64(),
+                );
+        }
+
+        for file in self.existing_files()? {
+            if files.contains_key(&file) {
+                continue;
+            }
+            let path = self.directory.join(&file);
+            std::fs::remove_file(&path)?;
+            // Only succeeds once the last file of the definition is gone.
+            std::fs::remove_dir(path.parent().unwrap()).ok();
+            self.written.remove(&file);
+            self.metrics
+                .file_sd_last_write
+                .remove_label_values(&[&file.to_string_lossy()])
+                .ok();
+        }
+
+        Ok(written)
+    }
+
+    /// The json files in the subdirectories of the directory, relative to it.
+    fn existing_files(&self) -> std::io::Result<Vec<PathBuf>> {
+        let mut files = vec![];
+        if !self.directory.is_dir() {
+            return Ok(files);
+        }
+        for dir in std::fs::read_dir(&self.directory)? {
+            let dir = dir?.path();
+            if !dir.is_dir() {
+                continue;
+            }
+            for file in std::fs::read_dir(&dir)? {
+                let file = file?.path();
+                if file.extension().map_or(false, |e| e == "json") {
+                    files.push(file.strip_prefix(&self.directory).unwrap().to_path_buf());
+                }
+            }
+        }
+        Ok(files)
+    }
+}
+
+/// The configs of every file, by the `ic` and `job` labels. Configs without
+/// these labels cannot be attributed to a file and are left out.
+fn group_by_file(
+    configs: BTreeSet<PrometheusStaticConfig>,
+) -> BTreeMap<PathBuf, BTreeSet<PrometheusStaticConfig>> {
+    let mut files: BTreeMap<PathBuf, BTreeSet<PrometheusStaticConfig>> = BTreeMap::new();
+    for config in configs {
+        let file = match (config.labels.get("ic"), config.labels.get("job")) {
+            (Some(ic), Some(job)) => {
+                Path::new(&path_component(ic)).join(format!("{}.json", path_component(job)))
+            }
+            _ => continue,
+        };
+        files.entry(file).or_default().insert(config);
+    }
+    files
+}
+
+/// `name` with every character that is not safe in a file name replaced.
+fn path_component(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Write the targets built by `build` whenever the definitions change. The
+/// definitions are checked once per `debounce`, so that a burst of registry
+/// updates results in a single write.
+pub async fn run_file_sd_writer<F>(
+    mut writer: FileSdWriter,
+    definitions: Arc<Mutex<Vec<Definition>>>,
+    debounce: Duration,
+    log: Logger,
+    build: F,
+) where
+    F: Fn(&[Definition]) -> BTreeSet<PrometheusStaticConfig>,
+{
+    let mut last_fingerprint = None;
+    loop {
+        let configs = {
+            let definitions = definitions.lock().await;
+            let fingerprint = targets_fingerprint(&definitions);
+            if last_fingerprint == Some(fingerprint) {
+                None
+            } else {
+                last_fingerprint = Some(fingerprint);
+                Some(build(&definitions))
+            }
+        };
+        if let Some(configs) = configs {
+            match writer.write(configs) {
+                Ok(0) => debug!(log, "file_sd targets unchanged"),
+                Ok(written) => debug!(log, "Wrote {} file_sd files", written),
+                Err(e) => {
+                    warn!(log, "Failed to write file_sd targets: {}", e);
+                    // Retry with the next check.
+                    last_fingerprint = None;
+                }
+            }
+        }
+        tokio::time::sleep(debounce).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use super::*;
+
+    fn config(ic: &str, job: &str, targets: &[&str]) -> PrometheusStaticConfig {
+        PrometheusStaticConfig {
+            targets: targets.iter().map(|t| t.to_string()).collect(),
+            labels: BTreeMap::from([
+                ("ic".to_string(), ic.to_string()),
+                ("job".to_string(), job.to_string()),
+            ]),
+        }
+    }
+
+    fn read(directory: &Path, file: &str) -> Vec<serde_json::Value> {
+        serde_json::from_slice(&std::fs::read(directory.join(file)).unwrap()).unwrap()
+    }
+
+    fn files(directory: &Path) -> Vec<String> {
+        let writer = FileSdWriter::new(directory, Metrics::new());
+        let mut files = writer
+            .existing_files()
+            .unwrap()
+            .into_iter()
+            .map(|f| f.to_string_lossy().to_string())
+            .collect::<Vec<_>>();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn writes_a_file_per_definition_and_job() {
+        let dir = tempfile::tempdir().unwrap();
+        let metrics = Metrics::new();
+        let mut writer = FileSdWriter::new(dir.path(), metrics.clone());
+
+        writer
+            .write(BTreeSet::from([
+                config("mercury", "replica", &["[::1]:9090", "[::2]:9090"]),
+                config("mercury", "orchestrator", &["[::1]:9091"]),
+                config("testnet/1", "replica", &["[::3]:9090"]),
+            ]))
+            .unwrap();
+
+        assert_eq!(
+            files(dir.path()),
+            vec![
+                "mercury/orchestrator.json",
+                "mercury/replica.json",
+                "testnet_1/replica.json"
+            ]
+        );
+        assert_eq!(
+            read(dir.path(), "mercury/replica.json"),
+            vec![serde_json::json!({
+                "targets": ["[::1]:9090", "[::2]:9090"],
+                "labels": {"ic": "mercury", "job": "replica"},
+            })]
+        );
+        assert!(
+            metrics
+                .file_sd_last_write
+                .with_label_values(&["mercury/replica.json"])
+                .get()
+                > 0.0
+        );
+    }
+
+    #[test]
+    fn removes_files_of_removed_definitions_and_jobs() {
+        let dir = tempfile::tempdir().unwrap();
+        // Left over from a previous run.
+        std::fs::create_dir(dir.path().join("old")).unwrap();
+        std::fs::write(dir.path().join("old/replica.json"), "[]").unwrap();
+        let mut writer = FileSdWriter::new(dir.path(), Metrics::new());
+
+        writer
+            .write(BTreeSet::from([
+                config("mercury", "replica", &["[::1]:9090"]),
+                config("mercury", "orchestrator", &["[::1]:9091"]),
+                config("testnet", "replica", &["[::3]:9090"]),
+            ]))
+            .unwrap();
+        assert!(!dir.path().join("old").exists());
+
+        writer
+            .write(BTreeSet::from([config(
+                "mercury",
+                "replica",
+                &["[::1]:9090"],
+            )]))
+            .unwrap();
+        assert_eq!(files(dir.path()), vec!["mercury/replica.json"]);
+        assert!(!dir.path().join("testnet").exists());
+    }
+
+    #[test]
+    fn unchanged_files_are_not_rewritten() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut writer = FileSdWriter::new(dir.path(), Metrics::new());
+        let configs = BTreeSet::from([config("mercury", "replica", &["[::1]:9090"])]);
+
+        writer.write(configs.clone()).unwrap();
+        let path = dir.path().join("mercury/replica.json");
+        let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+        std::thread::sleep(Duration::from_millis(10));
+        writer.write(configs).unwrap();
+
+        assert_eq!(
+            std::fs::metadata(&path).unwrap().modified().unwrap(),
+            modified
+        );
+    }
+
+    #[test]
+    fn identical_cycles_write_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let configs = BTreeSet::from([
+            config("mercury", "replica", &["[::1]:9090"]),
+            config("mercury", "orchestrator", &["[::1]:9091"]),
+        ]);
+
+        let mut writer = FileSdWriter::new(dir.path(), Metrics::new());
+        assert_eq!(writer.write(configs.clone()).unwrap(), 2);
+        assert_eq!(writer.write(configs.clone()).unwrap(), 0);
+
+        // The files a previous writer left are not rewritten either.
+        let mut restarted = FileSdWriter::new(dir.path(), Metrics::new());
+        assert_eq!(restarted.write(configs).unwrap(), 0);
+        assert_eq!(
+            restarted
+                .write(BTreeSet::from([
+                    config("mercury", "replica", &["[::1]:9090"]),
+                    config("mercury", "orchestrator", &["[::2]:9091"]),
+                ]))
+                .unwrap(),
+            1
+        );
+    }
+
+    #[test]
+    fn readers_never_see_partial_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut writer = FileSdWriter::new(dir.path(), Metrics::new());
+        writer
+            .write(BTreeSet::from([config(
+                "mercury",
+                "replica",
+                &["[::1]:9090"],
+            )]))
+            .unwrap();
+
+        let done = Arc::new(AtomicBool::new(false));
+        let reader = {
+            let path = dir.path().join("mercury/replica.json");
+            let done = done.clone();
+            std::thread::spawn(move || {
+                let mut reads = 0;
+                while !done.load(Ordering::Relaxed) {
+                    let content = std::fs::read(&path).unwrap();
+                    serde_json::from_slice::<Vec<serde_json::Value>>(&content)
+                        .unwrap_or_else(|e| panic!("Partially written file: {}", e));
+                    reads += 1;
+                }
+                reads
+            })
+        };
+
+        // Large files of different sizes, so that a write takes a while.
+        for i in 1..200 {
+            let targets = (0..i * 10)
+                .map(|t| format!("[::{:x}]:9090", t))
+                .collect::<Vec<_>>();
+            let targets = targets.iter().map(|t| t.as_str()).collect::<Vec<_>>();
+            writer
+                .write(BTreeSet::from([config("mercury", "replica", &targets)]))
+                .unwrap();
+        }
+        done.store(true, Ordering::Relaxed);
+
+        assert!(reader.join().unwrap() > 0);
+    }
+}
@@ -0,0 +1,139 @@
+use std::collections::BTreeMap;
+
+use multiservice_discovery_shared::builders::prometheus_config_structure::is_valid_label_name;
+use multiservice_discovery_shared::contracts::target::TargetDto;
+
+/// The labels the config builders derive from the targets themselves. They
+/// take precedence over the global labels.
+const BUILT_IN_LABELS: [&str; 8] = [
+    "ic",
+    "ic_node",
+    "ic_subnet",
+    "job",
+    "dc",
+    "address",
+    "node_provider_id",
+    "node_operator_id",
+];
+
+/// Parse a global label given as `<name>=<value>`. The name must be a valid
+/// Prometheus label name that is not reserved for internal use.
+pub fn parse_label(s: &str) -> Result<(String, String), String> {
+    let (name, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("Expected <name>=<value>, got '{}'", s))?;
+    if !is_valid_label_name(name) {
+        return Err(format!(
+            "Invalid label name '{}', it must match [a-zA-Z_][a-zA-Z0-9_]*",
+            name
+        ));
+    }
+    if name.starts_with("__") {
+        return Err(format!(
+            "Invalid label name '{}', names starting with __ are reserved",
+            name
+        ));
+    }
+    Ok((name.to_string(), value.to_string()))
+}
+
+/// Add the global labels to the custom labels of the targets. Labels the
+/// target already has and built-in labels are left as they are, since the
+/// config builders let custom labels override the built-in ones.
+pub fn add_global_labels(targets: &mut [TargetDto], labels: &BTreeMap<String, String>) {
+    for target in targets {
+        for (name, value) in labels {
+            if BUILT_IN_LABELS.contains(&name.as_str()) {
+                continue;
+            }
+            target
+                .custom_labels
+                .entry(name.clone())
+                .or_insert_with(|| value.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+    use std::net::SocketAddr;
+    use std::str::FromStr;
+
+    use ic_types::{NodeId, PrincipalId};
+    use multiservice_discovery_shared::builders::prometheus_config_structure::map_target_group;
+    use multiservice_discovery_shared::builders::transformers::{
+        StaticLabelsTransformer, TargetTransformer,
+    };
+    use service_discovery::job_types::JobType;
+
+    use super::*;
+
+    fn global_labels() -> BTreeMap<String, String> {
+        ["env=global", "cluster=zh1", "ic=global"]
+            .into_iter()
+            .map(|l| parse_label(l).unwrap())
+            .collect()
+    }
+
+    fn boundary_node() -> TargetDto {
+        TargetDto {
+            node_id: NodeId::from(PrincipalId::new_anonymous()),
+            ic_name: "mercury".to_string(),
+            targets: BTreeSet::from([SocketAddr::from_str("[2001:db8::1]:9100").unwrap()]),
+            subnet_id: None,
+            dc_id: "".to_string(),
+            operator_id: PrincipalId::new_anonymous(),
+            node_provider_id: PrincipalId::new_anonymous(),
+            jobs: vec![JobType::Replica],
+            custom_labels: BTreeMap::from([("env".to_string(), "boundary".to_string())]),
+            name: "bn-1".to_string(),
+            public_ipv4: None,
+        }
+    }
+
+    #[test]
+    fn targets_get_global_labels_with_lowest_precedence() {
+        let mut targets = vec![boundary_node()];
+
+        add_global_labels(&mut targets, &global_labels());
+
+        assert_eq!(
+            targets[0].custom_labels,
+            BTreeMap::from([
+                ("env".to_string(), "boundary".to_string()),
+                ("cluster".to_string(), "zh1".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn prometheus_configs_get_global_labels_with_lowest_precedence() {
+        let configs = StaticLabelsTransformer::new(global_labels()).transform(
+            map_target_group(BTreeSet::from([boundary_node()]))
+                .into_iter()
+                .collect(),
+        );
+
+        let labels = &configs[0].labels;
+        assert_eq!(labels["env"], "boundary");
+        assert_eq!(labels["cluster"], "zh1");
+        assert_eq!(labels["ic"], "mercury");
+        assert_eq!(labels["job"], "replica");
+    }
+
+    #[test]
+    fn label_names_are_validated() {
+        assert_eq!(
+            parse_label("env=prod=1"),
+            Ok(("env".to_string(), "prod=1".to_string()))
+        );
+        assert_eq!(
+            parse_label("_cluster="),
+            Ok(("_cluster".to_string(), "".to_string()))
+        );
+        for label in ["env", "=prod", "1env=prod", "env-name=prod", "__env=prod"] {
+            assert!(parse_label(label).is_err(), "{} should be rejected", label);
+        }
+    }
+}
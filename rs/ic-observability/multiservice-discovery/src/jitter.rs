@@ -0,0 +1,143 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// Randomizes the poll intervals of the definitions, so that definitions
+/// started at the same time do not all hit the NNS and the disk at once.
+///
+/// All definitions share one random source, which makes the schedule of
+/// every definition reproducible when a seed is given.
+#[derive(Clone)]
+pub struct Jitter {
+    fraction: f64,
+    rng: Arc<Mutex<StdRng>>,
+}
+
+impl Jitter {
+    /// Intervals vary by up to `fraction` of their value in both directions.
+    pub fn new(fraction: f64, seed: Option<u64>) -> Self {
+        Self {
+            fraction,
+            rng: Arc::new(Mutex::new(match seed {
+                Some(seed) => StdRng::seed_from_u64(seed),
+                None => StdRng::from_entropy(),
+            })),
+        }
+    }
+
+    /// No jitter at all, every interval is used as configured.
+    pub fn none() -> Self {
+        Self::new(0.0, Some(0))
+    }
+
+    /// `interval` scaled by a factor drawn uniformly from
+    /// `[1 - fraction, 1 + fraction]`, so that on average it is unchanged.
+    pub fn interval(&self, interval: Duration) -> Duration {
+        if self.fraction == 0.0 {
+            return interval;
+        }
+        let factor = self
+            .rng
+            .lock()
+            .unwrap()
+            .gen_range(1.0 - self.fraction..=1.0 + self.fraction);
+        interval.mul_f64(factor)
+    }
+
+    /// A delay drawn uniformly from `[0, fraction * interval]` to stagger the
+    /// initial sync of a definition.
+    pub fn initial_offset(&self, interval: Duration) -> Duration {
+        if self.fraction == 0.0 {
+            return Duration::ZERO;
+        }
+        interval.mul_f64(self.rng.lock().unwrap().gen_range(0.0..=self.fraction))
+    }
+}
+
+/// Parse a jitter fraction, which must be in `[0, 1)`.
+pub fn parse_fraction(s: &str) -> Result<f64, String> {
+    let fraction: f64 = s.parse().map_err(|e| format!("{}", e))?;
+    if !(0.0..1.0).contains(&fraction) {
+        return Err(format!("Jitter must be in [0, 1), got {}", fraction));
+    }
+    Ok(fraction)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The times at which `definitions` definitions started together sync,
+    /// following the schedule of `Definition::run`.
+    fn sync_times(
+        jitter: &Jitter,
+        interval: Duration,
+        definitions: usize,
+        polls: usize,
+    ) -> Vec<Vec<Duration>> {
+        (0..definitions)
+            .map(|_| {
+                let mut next = jitter.initial_offset(interval);
+                let mut times = vec![next];
+                for _ in 1..polls {
+                    next += jitter.interval(interval);
+                    times.push(next);
+                }
+                times
+            })
+            .collect()
+    }
+
+    #[test]
+    fn syncs_of_definitions_are_spread_out() {
+        let interval = Duration::from_secs(30);
+        let times = sync_times(&Jitter::new(0.1, Some(42)), interval, 8, 1000);
+
+        // Without jitter all definitions would sync at the same instants.
+        for (poll, min_spread) in [(0, Duration::from_secs(1)), (999, Duration::from_secs(10))] {
+            let mut at_poll = times.iter().map(|t| t[poll]).collect::<Vec<_>>();
+            at_poll.sort();
+            at_poll.dedup();
+            assert_eq!(at_poll.len(), times.len());
+            assert!(
+                at_poll[at_poll.len() - 1] - at_poll[0] > min_spread,
+                "Syncs at poll {} are not spread: {:?}",
+                poll,
+                at_poll
+            );
+        }
+
+        for times in &times {
+            assert!(times[0] <= interval.mul_f64(0.1));
+            let intervals = times.windows(2).map(|w| w[1] - w[0]).collect::<Vec<_>>();
+            assert!(intervals
+                .iter()
+                .all(|i| *i >= interval.mul_f64(0.9) && *i <= interval.mul_f64(1.1)));
+            let average = intervals.iter().sum::<Duration>() / intervals.len() as u32;
+            assert!(
+                average > interval.mul_f64(0.99) && average < interval.mul_f64(1.01),
+                "Average interval {:?} differs from {:?}",
+                average,
+                interval
+            );
+        }
+    }
+
+    #[test]
+    fn seeded_jitter_is_deterministic() {
+        let interval = Duration::from_secs(30);
+        assert_eq!(
+            sync_times(&Jitter::new(0.1, Some(7)), interval, 3, 10),
+            sync_times(&Jitter::new(0.1, Some(7)), interval, 3, 10)
+        );
+        assert_eq!(
+            sync_times(&Jitter::none(), interval, 2, 3),
+            vec![vec![Duration::ZERO, interval, interval * 2]; 2]
+        );
+        assert!(parse_fraction("0.1").is_ok());
+        assert!(parse_fraction("1").is_err());
+        assert!(parse_fraction("-0.1").is_err());
+    }
+}
@@ -0,0 +1,179 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+
+use slog::{Drain, Level, OwnedKVList, Record};
+
+/// Log levels that can be changed while the service is running. Every logger
+/// derived from the root logger shares them, so a change also applies to the
+/// threads of definitions that are already running.
+#[derive(Clone)]
+pub struct LogLevels {
+    inner: Arc<RwLock<LevelSpec>>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LevelSpec {
+    pub default: Level,
+    /// Levels overriding the default for modules starting with the key.
+    pub modules: BTreeMap<String, Level>,
+}
+
+impl LevelSpec {
+    fn level_for(&self, module: &str) -> Level {
+        self.modules
+            .iter()
+            .filter(|(prefix, _)| module.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, level)| *level)
+            .unwrap_or(self.default)
+    }
+
+    /// Apply a spec like `info,service_discovery=debug` on top of this one.
+    /// A bare level changes the default, `module=level` the level of every
+    /// module starting with `module`.
+    pub fn update(&mut self, spec: &str) -> Result<(), String> {
+        let mut updated = self.clone();
+        for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            match directive.split_once('=') {
+                Some((module, level)) => {
+                    updated
+                        .modules
+                        .insert(module.trim().to_string(), parse_level(level)?);
+                }
+                None => updated.default = parse_level(directive)?,
+            }
+        }
+        *self = updated;
+        Ok(())
+    }
+}
+
+impl FromStr for LevelSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut spec = LevelSpec {
+            default: Level::Info,
+            modules: BTreeMap::new(),
+        };
+        spec.update(s)?;
+        Ok(spec)
+    }
+}
+
+fn parse_level(level: &str) -> Result<Level, String> {
+    Level::from_str(level.trim()).map_err(|_| format!("Invalid log level '{}'", level.trim()))
+}
+
+impl LogLevels {
+    pub fn new(spec: LevelSpec) -> Self {
+        Self {
+            inner: Arc::new(RwLock::new(spec)),
+        }
+    }
+
+    pub fn get(&self) -> LevelSpec {
+        self.inner.read().unwrap().clone()
+    }
+
+    pub fn update(&self, spec: &str) -> Result<LevelSpec, String> {
+        let mut current = self.inner.write().unwrap();
+        current.update(spec)?;
+        Ok(current.clone())
+    }
+
+    fn enabled(&self, module: &str, level: Level) -> bool {
+        level.is_at_least(self.inner.read().unwrap().level_for(module))
+    }
+
+    /// Wrap `drain` so that only records allowed by these levels pass.
+    pub fn filter<D: Drain>(&self, drain: D) -> RuntimeLevelFilter<D> {
+        RuntimeLevelFilter {
+            drain,
+            levels: self.clone(),
+        }
+    }
+}
+
+pub struct RuntimeLevelFilter<D: Drain> {
+    drain: D,
+    levels: LogLevels,
+}
+
+impl<D: Drain> Drain for RuntimeLevelFilter<D> {
+    type Ok = Option<D::Ok>;
+    type Err = D::Err;
+
+    fn log(&self, record: &Record, values: &OwnedKVList) -> Result<Self::Ok, Self::Err> {
+        if self.levels.enabled(record.module(), record.level()) {
+            self.drain.log(record, values).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use slog::{debug, info, o, Logger};
+
+    use super::*;
+
+    struct CollectingDrain(Arc<Mutex<Vec<String>>>);
+
+    impl Drain for CollectingDrain {
+        type Ok = ();
+        type Err = slog::Never;
+
+        fn log(&self, record: &Record, _: &OwnedKVList) -> Result<(), slog::Never> {
+            self.0.lock().unwrap().push(record.msg().to_string());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn debug_messages_appear_only_after_raising_the_level() {
+        let messages = Arc::new(Mutex::new(vec![]));
+        let levels = LogLevels::new("info".parse().unwrap());
+        let log = Logger::root(
+            levels.filter(CollectingDrain(messages.clone())).fuse(),
+            o!(),
+        );
+        // Derived loggers, like the ones of running definitions, share the levels.
+        let definition_log = log.new(o!("definition" => "mercury"));
+
+        debug!(definition_log, "hidden");
+        info!(definition_log, "shown");
+        levels.update("debug").unwrap();
+        debug!(definition_log, "now shown");
+
+        assert_eq!(*messages.lock().unwrap(), vec!["shown", "now shown"]);
+    }
+
+    #[test]
+    fn module_prefix_overrides_the_default() {
+        let messages = Arc::new(Mutex::new(vec![]));
+        let levels = LogLevels::new("info".parse().unwrap());
+        let log = Logger::root(
+            levels.filter(CollectingDrain(messages.clone())).fuse(),
+            o!(),
+        );
+
+        levels.update("service_discovery=debug").unwrap();
+        debug!(log, "hidden");
+        levels
+            .update("multiservice_discovery::log_level=debug")
+            .unwrap();
+        debug!(log, "shown");
+
+        assert_eq!(*messages.lock().unwrap(), vec!["shown"]);
+        assert!(levels.update("service_discovery=loud").is_err());
+        assert_eq!(
+            levels.get().modules.keys().collect::<Vec<_>>(),
+            vec!["multiservice_discovery::log_level", "service_discovery"]
+        );
+    }
+}
@@ -1,74 +1,94 @@
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::str::FromStr;
 use std::time::Duration;
 use std::{path::PathBuf, sync::Arc};
 
 use clap::Parser;
 use futures_util::FutureExt;
 use humantime::parse_duration;
-use slog::{o, Drain, Logger};
+use ic_management_types::annotations::parse_node_annotations;
+use multiservice_discovery_shared::builders::transformers::{
+    AddressRewriteTransformer, DropByLabelTransformer, NodeAnnotationLabelsTransformer,
+    StaticLabelsTransformer, TransformerPipeline,
+};
+use service_discovery::job_types::JobType;
+use slog::{error, o, Drain, Logger};
 use tokio::runtime::Runtime;
-use tokio::sync::oneshot::{self};
-use tokio::sync::Mutex;
 use url::Url;
 
-use definition::{wrap, Definition};
 use ic_async_utils::shutdown_signal;
 
-use crate::server_handlers::prepare_server;
+use crate::global_labels::parse_label;
+use crate::jitter::parse_fraction;
+use crate::log_level::{LevelSpec, LogLevels};
+use crate::scrape_configs::{parse_scrape_interval, ScrapeIntervals};
+use crate::server::{start_server, ServerConfig};
+use crate::server_handlers::dto::DefinitionsDocumentDto;
+use crate::tls::TlsPaths;
 
+mod dedup;
 mod definition;
+mod definitions_file;
+mod file_sd;
+mod global_labels;
+mod jitter;
+mod log_level;
+mod metrics;
+mod registry_source;
+mod scrape_configs;
+mod server;
 mod server_handlers;
+mod tls;
 
 fn main() {
     let rt = Runtime::new().unwrap();
-    let log = make_logger();
-    let shutdown_signal = shutdown_signal(log.clone()).shared();
     let cli_args = CliArgs::parse();
-    let mut handles = vec![];
-    let mut definitions = vec![];
-
-    let (oneshot_sender, oneshot_receiver) = oneshot::channel();
-    if !cli_args.start_without_mainnet {
-        let mainnet_definition = get_mainnet_definition(&cli_args, log.clone());
-        definitions.push(mainnet_definition.clone());
-
-        let ic_handle = std::thread::spawn(wrap(mainnet_definition, rt.handle().clone()));
-        handles.push(ic_handle);
+    let log_levels = LogLevels::new(cli_args.log_level.clone());
+    let log = make_logger(&log_levels);
+    let shutdown_signal = shutdown_signal(log.clone()).shared();
+    if let Some(tls_paths) = cli_args.tls_paths() {
+        if let Err(e) = tls_paths.load() {
+            error!(log, "Invalid TLS configuration: {}", e);
+            eprintln!("Invalid TLS configuration: {}", e);
+            std::process::exit(1);
+        }
     }
-    let definitions = Arc::new(Mutex::new(definitions));
-    let handles = Arc::new(Mutex::new(handles));
-
-    //Configure server
-    let server_handle = rt.spawn(prepare_server(
-        oneshot_receiver,
-        log.clone(),
-        definitions.clone(),
-        cli_args,
-        handles.clone(),
-        rt.handle().clone(),
-    ));
 
-    rt.block_on(shutdown_signal);
-    //Stop the server
-    oneshot_sender.send(()).unwrap();
-
-    let mut handles = rt.block_on(handles.lock());
+    let definitions_document = match cli_args.definitions_document() {
+        Ok(document) => document,
+        Err(e) => {
+            error!(log, "{}", e);
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
 
-    for definition in rt.block_on(definitions.lock()).iter() {
-        definition.stop_signal_sender.send(()).unwrap();
-    }
+    let node_annotations = match cli_args.node_annotations() {
+        Ok(annotations) => annotations,
+        Err(e) => {
+            error!(log, "{}", e);
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
 
-    while let Some(handle) = handles.pop() {
-        handle.join().unwrap();
-    }
+    let config = ServerConfig {
+        definitions_document,
+        node_annotations,
+        ..ServerConfig::new(cli_args, log, log_levels)
+    };
+    let server = rt.block_on(start_server(config));
 
-    rt.block_on(server_handle).unwrap();
+    rt.block_on(shutdown_signal);
+    rt.block_on(server.shutdown());
 }
 
-fn make_logger() -> Logger {
+fn make_logger(log_levels: &LogLevels) -> Logger {
     let decorator = slog_term::TermDecorator::new().build();
     let drain = slog_term::FullFormat::new(decorator).build().fuse();
     let drain = slog_async::Async::new(drain).chan_size(8192).build();
-    Logger::root(drain.fuse(), o!())
+    Logger::root(log_levels.filter(drain.fuse()).fuse(), o!())
 }
 
 #[derive(Parser, Debug)]
@@ -98,6 +118,30 @@ The interval at which ICs are polled for updates.
     )]
     poll_interval: Duration,
 
+    #[clap(
+        long = "poll-jitter",
+        default_value = "0.1",
+        value_parser = parse_fraction,
+        help = r#"
+The fraction by which the poll interval of each definition randomly varies in
+both directions, e.g. 0.1 for ±10%. The average interval stays equal to
+--poll-interval. The initial syncs are delayed by up to this fraction of the
+poll interval as well, so that definitions started together do not poll at
+the same time.
+
+"#
+    )]
+    poll_jitter: f64,
+
+    #[clap(
+        long = "poll-jitter-seed",
+        help = r#"
+Seed for the poll jitter, to get the same schedule on every run.
+
+"#
+    )]
+    poll_jitter_seed: Option<u64>,
+
     #[clap(
     long = "query-request-timeout",
     default_value = "5s",
@@ -127,20 +171,289 @@ Start the discovery without the IC Mainnet target.
 "#
     )]
     start_without_mainnet: bool,
+
+    #[clap(
+        long = "definitions-file",
+        help = r#"
+A file written by GET /definitions/export whose definitions are started along
+with mainnet, e.g. to move the definitions of another instance to this one.
+Definitions with the name of one started from the command line are skipped.
+The file is rewritten after every change of the definitions, including pausing
+and resuming them, so that they survive a restart together with their
+registries in --targets-dir. It is created if it does not exist. On startup,
+the registries in --targets-dir of the definitions not in the file are removed.
+Definitions of the file that cannot be started are skipped, the file is then
+kept as <file>.bak before it is rewritten.
+"#
+    )]
+    definitions_file: Option<PathBuf>,
+
+    #[clap(
+        long = "definitions-file-exclude-secrets",
+        default_value = "false",
+        action,
+        help = r#"
+Leave the boundary nodes out of --definitions-file. Their targets and custom
+labels are supplied by operators and may be confidential. Boundary nodes then
+do not survive a restart and must be added again.
+"#
+    )]
+    definitions_file_exclude_secrets: bool,
+
+    #[clap(
+        long = "allow-duplicate-targets",
+        default_value = "false",
+        action,
+        help = r#"
+Export the targets of all definitions as they are, even when several
+definitions cover the same IC. By default a target exported by more than one
+definition is only kept for the definition whose name comes first.
+"#
+    )]
+    allow_duplicate_targets: bool,
+
+    #[clap(
+        long = "file-sd",
+        help = r#"
+A directory to write the targets of all definitions to for the file-based
+service discovery of Prometheus, one file per definition and job as
+`<definition>/<job>.json`. The targets are served over HTTP as well. Files of
+removed definitions and jobs are deleted, so the directory should not be used
+for anything else.
+"#
+    )]
+    file_sd_dir: Option<PathBuf>,
+
+    #[clap(
+        long = "file-sd-debounce",
+        default_value = "5s",
+        value_parser = parse_duration,
+        help = r#"
+The interval at which the definitions are checked for changes to write to
+--file-sd. Changes within an interval result in a single write.
+"#
+    )]
+    file_sd_debounce: Duration,
+
+    #[clap(
+        long = "listen-addr",
+        default_value = "0.0.0.0:8000",
+        help = r#"
+The address the HTTP(S) server listens on.
+"#
+    )]
+    listen_addr: SocketAddr,
+
+    #[clap(
+        long = "tls-cert-path",
+        requires = "tls_key_path",
+        help = r#"
+Path to a PEM encoded certificate chain. If provided together with
+--tls-key-path the server only accepts HTTPS connections. The certificate and
+key are reloaded from disk on SIGHUP.
+"#
+    )]
+    tls_cert_path: Option<PathBuf>,
+
+    #[clap(
+        long = "tls-key-path",
+        requires = "tls_cert_path",
+        help = r#"
+Path to the PEM encoded private key matching --tls-cert-path.
+"#
+    )]
+    tls_key_path: Option<PathBuf>,
+
+    #[clap(
+        long = "auth-token",
+        env = "MSD_AUTH_TOKEN",
+        help = r#"
+Token required as `Authorization: Bearer <token>` by the endpoints that
+modify definitions and by /log_level. If not provided these endpoints are not
+protected.
+"#
+    )]
+    auth_token: Option<String>,
+
+    #[clap(
+        long = "mutation-rate-limit",
+        default_value = "10",
+        help = r#"
+The number of requests per minute accepted by the endpoints that add, change or
+delete definitions, across all clients. Further requests are answered with 429
+and a Retry-After header. 0 disables the limit.
+"#
+    )]
+    mutation_rate_limit: u32,
+
+    #[clap(
+        long = "log-level",
+        default_value = "trace",
+        value_parser = LevelSpec::from_str,
+        help = r#"
+The log level, optionally followed by levels for module prefixes, e.g.
+`info,service_discovery=debug`. Can be changed at runtime with PUT /log_level.
+"#
+    )]
+    log_level: LevelSpec,
+
+    #[clap(
+        long = "drop-targets-with-label",
+        value_parser = DropByLabelTransformer::from_str,
+        help = r#"
+Drop the targets exported on /prom/targets whose label matches a regex, given
+as `<label>=<regex>`. Can be repeated.
+"#
+    )]
+    drop_targets_with_label: Vec<DropByLabelTransformer>,
+
+    #[clap(
+        long = "rewrite-target-address",
+        value_parser = AddressRewriteTransformer::from_str,
+        help = r#"
+Rewrite the targets exported on /prom/targets, given as
+`<regex>=<replacement>`. The replacement can refer to capture groups as `$1`.
+Can be repeated.
+"#
+    )]
+    rewrite_target_address: Vec<AddressRewriteTransformer>,
+
+    #[clap(
+        long = "node-annotations-file",
+        help = r#"
+A JSON file mapping node ids to their annotations, e.g.
+`{"<node id>": {"rma": "pending"}}`. If given, the annotations are added to
+the targets of the nodes exported on /prom/targets, as labels prefixed with
+`annotation_`, e.g. `annotation_rma`.
+"#
+    )]
+    node_annotations_file: Option<PathBuf>,
+
+    #[clap(
+        long = "label",
+        alias = "static-label",
+        value_parser = parse_label,
+        help = r#"
+Add a label, given as `<name>=<value>`, to every target exported by the
+service, e.g. `env=prod`. Labels set on boundary nodes and the built-in labels
+such as `ic` and `job` take precedence. Can be repeated.
+"#
+    )]
+    labels: Vec<(String, String)>,
+
+    #[clap(
+        long = "journald-port",
+        default_value = "19531",
+        help = r#"
+The port systemd-journal-gatewayd listens on, on all nodes. Used for the URLs
+served on /log_targets.
+"#
+    )]
+    journald_port: u16,
+
+    #[clap(
+        long = "scrape-interval",
+        value_parser = parse_scrape_interval,
+        help = r#"
+The scrape interval, and timeout, of a job in the configs served on
+/prom/scrape_configs, given as `<job>=<duration>`, e.g. `replica=15s`. Defaults
+to 10s for replica and 30s for the other jobs. Can be repeated.
+"#
+    )]
+    scrape_intervals: Vec<(JobType, Duration)>,
+
+    #[clap(
+        long = "public-url",
+        help = r#"
+The URL Prometheus reaches the service at, used for the targets URL in the
+configs served on /prom/scrape_configs. Defaults to the URL the configs are
+requested on.
+"#
+    )]
+    public_url: Option<Url>,
 }
 
-fn get_mainnet_definition(cli_args: &CliArgs, log: Logger) -> Definition {
-    let (ic_stop_signal_sender, ic_stop_signal_rcv) = crossbeam::channel::bounded::<()>(0);
-
-    Definition::new(
-        vec![cli_args.nns_url.clone()],
-        cli_args.targets_dir.clone(),
-        "mercury".to_string(),
-        log.clone(),
-        None,
-        cli_args.poll_interval,
-        ic_stop_signal_rcv,
-        cli_args.registry_query_timeout,
-        ic_stop_signal_sender,
-    )
+impl CliArgs {
+    /// The definitions of --definitions-file, if given and written already.
+    fn definitions_document(&self) -> Result<Option<DefinitionsDocumentDto>, String> {
+        let path = match &self.definitions_file {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        let contents = match std::fs::read(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+        };
+        serde_json::from_slice(&contents)
+            .map(Some)
+            .map_err(|e| format!("Invalid definitions file {}: {}", path.display(), e))
+    }
+
+    /// The annotations of --node-annotations-file by node id, if given.
+    fn node_annotations(
+        &self,
+    ) -> Result<Option<BTreeMap<String, BTreeMap<String, String>>>, String> {
+        let path = match &self.node_annotations_file {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let annotations = parse_node_annotations(&contents)
+            .map_err(|e| format!("{} in {}", e, path.display()))?;
+        Ok(Some(
+            annotations
+                .into_iter()
+                .map(|(node, annotations)| {
+                    let labels = annotations
+                        .iter()
+                        .map(|(k, v)| (k.clone(), v.clone()))
+                        .collect();
+                    (node.to_string(), labels)
+                })
+                .collect(),
+        ))
+    }
+
+    fn tls_paths(&self) -> Option<TlsPaths> {
+        match (&self.tls_cert_path, &self.tls_key_path) {
+            (Some(cert_path), Some(key_path)) => Some(TlsPaths {
+                cert_path: cert_path.clone(),
+                key_path: key_path.clone(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// The transformers applied to /prom/targets: targets are dropped first,
+    /// then their addresses are rewritten and finally the annotations of the
+    /// nodes and global labels added.
+    fn transformer_pipeline(
+        &self,
+        node_annotations: Option<BTreeMap<String, BTreeMap<String, String>>>,
+    ) -> TransformerPipeline {
+        let mut pipeline = TransformerPipeline::default();
+        for transformer in &self.drop_targets_with_label {
+            pipeline.add(Arc::new(transformer.clone()));
+        }
+        for transformer in &self.rewrite_target_address {
+            pipeline.add(Arc::new(transformer.clone()));
+        }
+        if let Some(annotations) = node_annotations {
+            pipeline.add(Arc::new(NodeAnnotationLabelsTransformer::new(annotations)));
+        }
+        if !self.labels.is_empty() {
+            pipeline.add(Arc::new(StaticLabelsTransformer::new(self.global_labels())));
+        }
+        pipeline
+    }
+
+    fn global_labels(&self) -> BTreeMap<String, String> {
+        self.labels.iter().cloned().collect()
+    }
+
+    fn scrape_intervals(&self) -> ScrapeIntervals {
+        ScrapeIntervals::new(self.scrape_intervals.iter().cloned())
+    }
 }
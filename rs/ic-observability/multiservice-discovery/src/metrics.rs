@@ -0,0 +1,296 @@
+use std::fmt::Write;
+
+use prometheus::proto::{LabelPair, MetricFamily, MetricType};
+use prometheus::{
+    Encoder, GaugeVec, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+
+const DEFINITION: &str = "definition";
+const ROUTE: &str = "route";
+const METHOD: &str = "method";
+
+/// The content type of the metrics encoded by [`Metrics::encode_openmetrics`].
+pub const OPENMETRICS_CONTENT_TYPE: &str =
+    "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// The metrics of the service, exposed on `/metrics`.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    /// Target groups left out of the Prometheus config because the node has
+    /// no address of the family requested by the definition.
+    pub skipped_targets: IntCounterVec,
+    /// Targets left out of the aggregate endpoints because another
+    /// definition already exports them.
+    pub suppressed_duplicate_targets: IntCounterVec,
+    /// Nodes whose targets were dropped by a sync because the nodes were
+    /// removed from the registry.
+    pub evicted_targets: IntCounterVec,
+    /// Unix time of the last write of every `file_sd` file.
+    pub file_sd_last_write: GaugeVec,
+    /// Requests handled by the server, by route template, method and status
+    /// class.
+    pub http_requests: IntCounterVec,
+    pub http_request_duration: HistogramVec,
+    pub http_requests_in_flight: IntGaugeVec,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new();
+        let skipped_targets = IntCounterVec::new(
+            Opts::new(
+                "msd_skipped_targets_total",
+                "Target groups without an address of the requested family.",
+            ),
+            &[DEFINITION],
+        )
+        .unwrap();
+        let suppressed_duplicate_targets = IntCounterVec::new(
+            Opts::new(
+                "msd_suppressed_duplicate_targets_total",
+                "Targets already exported by another definition.",
+            ),
+            &[DEFINITION],
+        )
+        .unwrap();
+        let evicted_targets = IntCounterVec::new(
+            Opts::new(
+                "msd_evicted_targets_total",
+                "Nodes whose targets were dropped because they left the registry.",
+            ),
+            &[DEFINITION],
+        )
+        .unwrap();
+        let file_sd_last_write = GaugeVec::new(
+            Opts::new(
+                "msd_file_sd_last_write_timestamp_seconds",
+                "Unix time of the last write of a file_sd file.",
+            ),
+            &["file"],
+        )
+        .unwrap();
+        let http_requests = IntCounterVec::new(
+            Opts::new("msd_http_requests_total", "Requests handled by the server."),
+            &[ROUTE, METHOD, "status"],
+        )
+        .unwrap();
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "msd_http_request_duration_seconds",
+                "Time it took to handle a request.",
+            ),
+            &[ROUTE, METHOD],
+        )
+        .unwrap();
+        let http_requests_in_flight = IntGaugeVec::new(
+            Opts::new(
+                "msd_http_requests_in_flight",
+                "Requests currently being handled by the server.",
+            ),
+            &[ROUTE, METHOD],
+        )
+        .unwrap();
+        registry
+            .register(Box::new(skipped_targets.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(suppressed_duplicate_targets.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(evicted_targets.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(file_sd_last_write.clone()))
+            .unwrap();
+        registry.register(Box::new(http_requests.clone())).unwrap();
+        registry
+            .register(Box::new(http_request_duration.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(http_requests_in_flight.clone()))
+            .unwrap();
+
+        Self {
+            registry,
+            skipped_targets,
+            suppressed_duplicate_targets,
+            evicted_targets,
+            file_sd_last_write,
+            http_requests,
+            http_request_duration,
+            http_requests_in_flight,
+        }
+    }
+
+    /// All metrics in the Prometheus text format.
+    pub fn encode(&self) -> String {
+        let mut buffer = vec![];
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+
+    /// All metrics in the OpenMetrics text format. Counter families are
+    /// named without the `_total` suffix, which only their samples carry.
+    pub fn encode_openmetrics(&self) -> String {
+        let mut out = String::new();
+        for family in self.registry.gather() {
+            write_openmetrics_family(&mut out, &family);
+        }
+        out.push_str("# EOF\n");
+        out
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn write_openmetrics_family(out: &mut String, family: &MetricFamily) {
+    let name = family.get_name();
+    let (name, kind) = match family.get_field_type() {
+        MetricType::COUNTER => (name.strip_suffix("_total").unwrap_or(name), "counter"),
+        MetricType::GAUGE => (name, "gauge"),
+        MetricType::HISTOGRAM => (name, "histogram"),
+        MetricType::SUMMARY => (name, "summary"),
+        MetricType::UNTYPED => (name, "unknown"),
+    };
+    if !family.get_help().is_empty() {
+        writeln!(out, "# HELP {} {}", name, escape(family.get_help())).unwrap();
+    }
+    writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+
+    for metric in family.get_metric() {
+        let labels = metric.get_label();
+        match family.get_field_type() {
+            MetricType::COUNTER => {
+                let value = metric.get_counter().get_value();
+                write_sample(out, name, "_total", labels, None, value);
+            }
+            MetricType::GAUGE => {
+                write_sample(out, name, "", labels, None, metric.get_gauge().get_value());
+            }
+            MetricType::UNTYPED => {
+                let value = metric.get_untyped().get_value();
+                write_sample(out, name, "", labels, None, value);
+            }
+            MetricType::HISTOGRAM => {
+                let histogram = metric.get_histogram();
+                for bucket in histogram.get_bucket() {
+                    let le = Some(("le", bucket.get_upper_bound()));
+                    let count = bucket.get_cumulative_count() as f64;
+                    write_sample(out, name, "_bucket", labels, le, count);
+                }
+                let count = histogram.get_sample_count() as f64;
+                let le = Some(("le", f64::INFINITY));
+                write_sample(out, name, "_bucket", labels, le, count);
+                write_sample(out, name, "_count", labels, None, count);
+                write_sample(out, name, "_sum", labels, None, histogram.get_sample_sum());
+            }
+            MetricType::SUMMARY => {
+                let summary = metric.get_summary();
+                for quantile in summary.get_quantile() {
+                    let q = Some(("quantile", quantile.get_quantile()));
+                    write_sample(out, name, "", labels, q, quantile.get_value());
+                }
+                let count = summary.get_sample_count() as f64;
+                write_sample(out, name, "_count", labels, None, count);
+                write_sample(out, name, "_sum", labels, None, summary.get_sample_sum());
+            }
+        }
+    }
+}
+
+/// Write a sample of the metric `name`, with the labels of the metric and
+/// the `bound` label of a histogram bucket or summary quantile.
+fn write_sample(
+    out: &mut String,
+    name: &str,
+    suffix: &str,
+    labels: &[LabelPair],
+    bound: Option<(&str, f64)>,
+    value: f64,
+) {
+    let mut pairs = labels
+        .iter()
+        .map(|l| format!("{}=\"{}\"", l.get_name(), escape(l.get_value())))
+        .chain(bound.map(|(label, bound)| format!("{}=\"{}\"", label, format_float(bound))))
+        .peekable();
+    out.push_str(name);
+    out.push_str(suffix);
+    if pairs.peek().is_some() {
+        write!(out, "{{{}}}", pairs.collect::<Vec<_>>().join(",")).unwrap();
+    }
+    writeln!(out, " {}", format_float(value)).unwrap();
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('"', "\\\"")
+}
+
+/// Floats as OpenMetrics expects them, e.g. `1.0` rather than `1` for a
+/// bucket bound and `+Inf` for the last one.
+fn format_float(value: f64) -> String {
+    if value == f64::INFINITY {
+        "+Inf".to_string()
+    } else if value == f64::NEG_INFINITY {
+        "-Inf".to_string()
+    } else if value.is_nan() {
+        "NaN".to_string()
+    } else {
+        format!("{:?}", value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn openmetrics_counters_are_declared_without_their_suffix() {
+        let metrics = Metrics::new();
+        metrics
+            .http_requests
+            .with_label_values(&["/metrics", "GET", "2xx"])
+            .inc();
+        metrics
+            .http_request_duration
+            .with_label_values(&["/metrics", "GET"])
+            .observe(0.2);
+        metrics
+            .skipped_targets
+            .with_label_values(&["with \"quotes\"\n"])
+            .inc();
+
+        let encoded = metrics.encode_openmetrics();
+        assert!(encoded.ends_with("\n# EOF\n"), "{}", encoded);
+        for line in [
+            "# TYPE msd_http_requests counter",
+            "# HELP msd_http_requests Requests handled by the server.",
+            r#"msd_http_requests_total{method="GET",route="/metrics",status="2xx"} 1.0"#,
+            "# TYPE msd_http_request_duration_seconds histogram",
+            r#"msd_http_request_duration_seconds_bucket{method="GET",route="/metrics",le="0.1"} 0.0"#,
+            r#"msd_http_request_duration_seconds_bucket{method="GET",route="/metrics",le="0.25"} 1.0"#,
+            r#"msd_http_request_duration_seconds_bucket{method="GET",route="/metrics",le="+Inf"} 1.0"#,
+            r#"msd_http_request_duration_seconds_count{method="GET",route="/metrics"} 1.0"#,
+            r#"msd_http_request_duration_seconds_sum{method="GET",route="/metrics"} 0.2"#,
+            r#"msd_skipped_targets_total{definition="with \"quotes\"\n"} 1.0"#,
+        ] {
+            assert!(
+                encoded.lines().any(|l| l == line),
+                "{} not in {}",
+                line,
+                encoded
+            );
+        }
+        assert!(!encoded.contains("# TYPE msd_http_requests_total"));
+    }
+}
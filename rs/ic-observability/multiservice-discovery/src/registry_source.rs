@@ -0,0 +1,248 @@
+//! Where definitions get the content of their local registries from.
+
+use futures_util::future::{BoxFuture, LocalBoxFuture};
+use futures_util::FutureExt;
+use ic_registry_client::client::ThresholdSigPublicKey;
+use service_discovery::registry_sync::{
+    fetch_nns_public_key, nns_reachable, sync_local_registry_with_key, verify_nns_public_key,
+};
+use service_discovery::IcServiceDiscoveryError;
+use url::Url;
+
+use crate::definition::Definition;
+
+/// Only [`RegistrySource::reachable`] is awaited by the server, the syncs are
+/// run by the definitions on their own threads and need not be `Send`.
+pub trait RegistrySource: Send + Sync {
+    /// Whether the registry of a definition with these NNS urls can be
+    /// synced at all. Definitions are only added if it can.
+    fn reachable<'a>(&'a self, nns_urls: &'a [Url]) -> BoxFuture<'a, bool>;
+
+    /// The public key the NNS reports for itself, which is not verified.
+    fn public_key<'a>(
+        &'a self,
+        nns_urls: &'a [Url],
+    ) -> BoxFuture<'a, Result<ThresholdSigPublicKey, String>>;
+
+    /// Check that the registry responses of the NNS verify with `public_key`.
+    fn verify<'a>(
+        &'a self,
+        nns_urls: &'a [Url],
+        public_key: ThresholdSigPublicKey,
+    ) -> BoxFuture<'a, Result<(), String>>;
+
+    /// Populate the local store of the definition before its first poll,
+    /// failing if the responses do not verify with `public_key`.
+    fn initial_sync<'a>(
+        &'a self,
+        definition: &'a Definition,
+        public_key: ThresholdSigPublicKey,
+    ) -> LocalBoxFuture<'a, Result<(), String>>;
+
+    /// Bring the registries loaded by the definition up to date.
+    fn update<'a>(
+        &'a self,
+        definition: &'a Definition,
+    ) -> LocalBoxFuture<'a, Result<(), IcServiceDiscoveryError>>;
+}
+
+/// Sync the registries from the NNS of each definition.
+pub struct NnsRegistrySource;
+
+impl RegistrySource for NnsRegistrySource {
+    fn reachable<'a>(&'a self, nns_urls: &'a [Url]) -> BoxFuture<'a, bool> {
+        nns_reachable(nns_urls.to_vec()).boxed()
+    }
+
+    fn public_key<'a>(
+        &'a self,
+        nns_urls: &'a [Url],
+    ) -> BoxFuture<'a, Result<ThresholdSigPublicKey, String>> {
+        fetch_nns_public_key(nns_urls.to_vec())
+            .map(|result| result.map_err(|e| e.to_string()))
+            .boxed()
+    }
+
+    fn verify<'a>(
+        &'a self,
+        nns_urls: &'a [Url],
+        public_key: ThresholdSigPublicKey,
+    ) -> BoxFuture<'a, Result<(), String>> {
+        async move {
+            verify_nns_public_key(nns_urls.to_vec(), &public_key)
+                .await
+                .map_err(|e| e.to_string())
+        }
+        .boxed()
+    }
+
+    fn initial_sync<'a>(
+        &'a self,
+        definition: &'a Definition,
+        public_key: ThresholdSigPublicKey,
+    ) -> LocalBoxFuture<'a, Result<(), String>> {
+        sync_local_registry_with_key(
+            definition.log.clone(),
+            definition.registry_path.join("targets"),
+            definition.nns_urls.clone(),
+            public_key,
+        )
+        .map(|result| result.map_err(|e| e.to_string()))
+        .boxed_local()
+    }
+
+    fn update<'a>(
+        &'a self,
+        definition: &'a Definition,
+    ) -> LocalBoxFuture<'a, Result<(), IcServiceDiscoveryError>> {
+        definition.ic_discovery.update_registries().boxed_local()
+    }
+}
+
+#[cfg(test)]
+pub use mock::{test_public_key, MockRegistrySource};
+
+#[cfg(test)]
+mod mock {
+    use std::sync::Mutex;
+
+    use ic_crypto_utils_threshold_sig_der::parse_threshold_sig_key_from_der;
+    use ic_registry_local_store::{Changelog, ChangelogEntry, LocalStoreImpl, LocalStoreReader};
+    use ic_types::RegistryVersion;
+    use service_discovery::mainnet_registry::{
+        create_local_store_from_changelog, get_mainnet_delta_6d_c1,
+    };
+
+    use super::*;
+
+    /// A public key made of `byte` only. It is never used to check an
+    /// actual signature, so it need not be a valid key.
+    pub fn test_public_key(byte: u8) -> ThresholdSigPublicKey {
+        // The DER prefix of a BLS12-381 threshold signature public key.
+        let mut der = vec![
+            0x30, 0x81, 0x82, 0x30, 0x1d, 0x06, 0x0d, 0x2b, 0x06, 0x01, 0x04, 0x01, 0x82, 0xdc,
+            0x7c, 0x05, 0x03, 0x01, 0x02, 0x01, 0x06, 0x0c, 0x2b, 0x06, 0x01, 0x04, 0x01, 0x82,
+            0xdc, 0x7c, 0x05, 0x03, 0x02, 0x01, 0x03, 0x61, 0x00,
+        ];
+        der.extend([byte; 96]);
+        parse_threshold_sig_key_from_der(&der).unwrap()
+    }
+
+    /// Populate every local store from the same changelog, without any
+    /// network access. Every NNS is reachable and updates only bring in the
+    /// versions published with [`MockRegistrySource::publish`], so the
+    /// targets of a definition are the same on every run. The responses are
+    /// taken as signed with [`MockRegistrySource::public_key`], so only that
+    /// key verifies them.
+    pub struct MockRegistrySource {
+        changelog: Changelog,
+        /// The versions following the ones of `changelog`.
+        published: Mutex<Changelog>,
+        public_key: ThresholdSigPublicKey,
+    }
+
+    impl MockRegistrySource {
+        pub fn new(changelog: Changelog) -> Self {
+            Self {
+                changelog,
+                published: Mutex::new(vec![]),
+                public_key: test_public_key(1),
+            }
+        }
+
+        /// The key the NNS reports and its responses verify with.
+        pub fn public_key(&self) -> ThresholdSigPublicKey {
+            self.public_key
+        }
+
+        fn check_signature(&self, public_key: ThresholdSigPublicKey) -> Result<(), String> {
+            if public_key != self.public_key {
+                return Err(
+                    "Certified registry changes do not verify with the public key".to_string(),
+                );
+            }
+            Ok(())
+        }
+
+        /// The changelog every local store starts with.
+        pub fn changelog(&self) -> &Changelog {
+            &self.changelog
+        }
+
+        /// Add a registry version, synced by the next update of every
+        /// definition.
+        pub fn publish(&self, entry: ChangelogEntry) {
+            self.published.lock().unwrap().push(entry);
+        }
+
+        /// Write the published versions the local store of the definition
+        /// is missing and load them into its registry.
+        fn apply_published(&self, definition: &Definition) -> Result<(), IcServiceDiscoveryError> {
+            let store = LocalStoreImpl::new(definition.registry_path.join("targets"));
+            let synced = store
+                .get_changelog_since_version(RegistryVersion::from(0))?
+                .len();
+            let published = self.published.lock().unwrap();
+            for (i, entry) in published.iter().enumerate() {
+                let version = self.changelog.len() + i + 1;
+                if version > synced {
+                    store.write_changelog_entry_unsafe(version as u64, entry.clone())?;
+                }
+            }
+            definition.ic_discovery.sync_registries_with_local_store()
+        }
+    }
+
+    impl Default for MockRegistrySource {
+        /// The mainnet registry as of the snapshot bundled with the crates.
+        fn default() -> Self {
+            Self::new(get_mainnet_delta_6d_c1())
+        }
+    }
+
+    impl RegistrySource for MockRegistrySource {
+        fn reachable<'a>(&'a self, _nns_urls: &'a [Url]) -> BoxFuture<'a, bool> {
+            async { true }.boxed()
+        }
+
+        fn public_key<'a>(
+            &'a self,
+            _nns_urls: &'a [Url],
+        ) -> BoxFuture<'a, Result<ThresholdSigPublicKey, String>> {
+            let public_key = self.public_key;
+            async move { Ok(public_key) }.boxed()
+        }
+
+        fn verify<'a>(
+            &'a self,
+            _nns_urls: &'a [Url],
+            public_key: ThresholdSigPublicKey,
+        ) -> BoxFuture<'a, Result<(), String>> {
+            let result = self.check_signature(public_key);
+            async move { result }.boxed()
+        }
+
+        fn initial_sync<'a>(
+            &'a self,
+            definition: &'a Definition,
+            public_key: ThresholdSigPublicKey,
+        ) -> LocalBoxFuture<'a, Result<(), String>> {
+            let result = self.check_signature(public_key).map(|()| {
+                let path = definition.registry_path.join("targets");
+                // A definition restarted with the same name reuses the store.
+                if !path.exists() {
+                    create_local_store_from_changelog(path, self.changelog.clone());
+                }
+            });
+            async move { result }.boxed_local()
+        }
+
+        fn update<'a>(
+            &'a self,
+            definition: &'a Definition,
+        ) -> LocalBoxFuture<'a, Result<(), IcServiceDiscoveryError>> {
+            let result = self.apply_published(definition);
+            async move { result }.boxed_local()
+        }
+    }
+}
@@ -0,0 +1,227 @@
+//! The `scrape_configs` section of a Prometheus config scraping the targets
+//! served on `/prom/targets`, one scrape job per definition and job type.
+
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::time::Duration;
+
+use multiservice_discovery_shared::builders::prometheus_config_structure::ScrapeOptions;
+use serde::Serialize;
+use service_discovery::job_types::{JobType, NodeOS};
+
+/// The jobs targets are exported for, in the order of the scrape jobs.
+const JOBS: [JobType; 5] = [
+    JobType::Replica,
+    JobType::Orchestrator,
+    JobType::NodeExporter(NodeOS::Guest),
+    JobType::NodeExporter(NodeOS::Host),
+    JobType::MetricsProxy,
+];
+
+/// The scrape interval recommended for each job, which is its scrape timeout
+/// as well.
+#[derive(Clone, Debug, Default)]
+pub struct ScrapeIntervals {
+    overrides: BTreeMap<JobType, Duration>,
+}
+
+impl ScrapeIntervals {
+    pub fn new(overrides: impl IntoIterator<Item = (JobType, Duration)>) -> Self {
+        Self {
+            overrides: overrides.into_iter().collect(),
+        }
+    }
+
+    /// The interval of `job`: 10s for replicas and 30s for the other jobs,
+    /// unless overridden.
+    pub fn get(&self, job: JobType) -> Duration {
+        self.overrides.get(&job).copied().unwrap_or(match job {
+            JobType::Replica => Duration::from_secs(10),
+            _ => Duration::from_secs(30),
+        })
+    }
+}
+
+/// Parse a scrape interval given as `<job>=<duration>`, e.g. `replica=15s`.
+pub fn parse_scrape_interval(s: &str) -> Result<(JobType, Duration), String> {
+    let (job, interval) = s
+        .split_once('=')
+        .ok_or_else(|| format!("Expected <job>=<duration>, got '{}'", s))?;
+    let job = JobType::from_str(job).map_err(|e| e.to_string())?;
+    let interval = humantime::parse_duration(interval).map_err(|e| e.to_string())?;
+    if interval.as_millis() == 0 {
+        return Err(format!("The scrape interval of {} must be positive", job));
+    }
+    Ok((job, interval))
+}
+
+#[derive(Serialize, Debug)]
+pub struct ScrapeConfigsDocument {
+    pub scrape_configs: Vec<ScrapeConfig>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ScrapeConfig {
+    pub job_name: String,
+    pub scrape_interval: String,
+    pub scrape_timeout: String,
+    pub scheme: &'static str,
+    pub honor_labels: bool,
+    pub http_sd_configs: Vec<HttpSdConfig>,
+    pub relabel_configs: Vec<RelabelConfig>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct HttpSdConfig {
+    pub url: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct RelabelConfig {
+    pub source_labels: Vec<&'static str>,
+    pub regex: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target_label: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replacement: Option<&'static str>,
+    pub action: &'static str,
+}
+
+impl RelabelConfig {
+    /// Set `target_label` to the first group of `regex` matched against the
+    /// target URL.
+    fn from_url(regex: &str, target_label: &'static str) -> Self {
+        Self {
+            source_labels: vec!["__address__"],
+            regex: regex.to_string(),
+            target_label: Some(target_label),
+            replacement: Some("$1"),
+            action: "replace",
+        }
+    }
+}
+
+/// A scrape job for each job type of each definition, given by its name and
+/// scrape options, discovering its targets on `targets_url`.
+pub fn scrape_configs<'a>(
+    definitions: impl IntoIterator<Item = (&'a str, &'a ScrapeOptions)>,
+    targets_url: &str,
+    intervals: &ScrapeIntervals,
+) -> ScrapeConfigsDocument {
+    let scrape_configs = definitions
+        .into_iter()
+        .flat_map(|(name, options)| {
+            JOBS.iter()
+                .map(move |job| scrape_config(name, options, *job, targets_url, intervals))
+        })
+        .collect();
+    ScrapeConfigsDocument { scrape_configs }
+}
+
+fn scrape_config(
+    name: &str,
+    options: &ScrapeOptions,
+    job: JobType,
+    targets_url: &str,
+    intervals: &ScrapeIntervals,
+) -> ScrapeConfig {
+    let interval = prometheus_duration(intervals.get(job));
+    ScrapeConfig {
+        job_name: format!("{}_{}", name, job),
+        scrape_interval: interval.clone(),
+        scrape_timeout: interval,
+        scheme: options.scheme.map_or(job.scheme(), |s| s.as_str()),
+        // The `ic` and `job` labels of the targets must not be overridden
+        // by the scraped metrics.
+        honor_labels: false,
+        http_sd_configs: vec![HttpSdConfig {
+            url: targets_url.to_string(),
+        }],
+        relabel_configs: vec![
+            RelabelConfig {
+                source_labels: vec!["ic", "job"],
+                regex: format!(
+                    "{};{}",
+                    regex::escape(name),
+                    regex::escape(&job.to_string())
+                ),
+                target_label: None,
+                replacement: None,
+                action: "keep",
+            },
+            // The targets are URLs, which Prometheus takes apart into the
+            // scheme, the path and the address it scrapes.
+            RelabelConfig::from_url("(https?)://.*", "__scheme__"),
+            RelabelConfig::from_url("https?://[^/]+(/.*)", "__metrics_path__"),
+            RelabelConfig::from_url("https?://([^/]+)/.*", "__address__"),
+        ],
+    }
+}
+
+/// A duration in the format of the Prometheus config.
+fn prometheus_duration(duration: Duration) -> String {
+    if duration.subsec_millis() == 0 {
+        format!("{}s", duration.as_secs())
+    } else {
+        format!("{}ms", duration.as_millis())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use multiservice_discovery_shared::builders::prometheus_config_structure::Scheme;
+
+    use super::*;
+
+    #[test]
+    fn scrape_configs_of_two_definitions() {
+        let mainnet = ScrapeOptions::default();
+        let testnet = ScrapeOptions {
+            scheme: Some(Scheme::Http),
+            ..Default::default()
+        };
+        let intervals = ScrapeIntervals::new([(JobType::Replica, Duration::from_secs(15))]);
+
+        let document = scrape_configs(
+            [("mercury", &mainnet), ("test.net", &testnet)],
+            "https://msd.example.com/prom/targets",
+            &intervals,
+        );
+
+        assert_eq!(
+            serde_yaml::to_string(&document).unwrap(),
+            include_str!("testdata/scrape_configs.yaml")
+        );
+    }
+
+    #[test]
+    fn definition_names_are_escaped_in_the_job_filter() {
+        let document = scrape_configs(
+            [("test.net", &ScrapeOptions::default())],
+            "http://localhost:8000/prom/targets",
+            &ScrapeIntervals::default(),
+        );
+        assert_eq!(
+            document.scrape_configs[4].relabel_configs[0].regex,
+            r"test\.net;metrics\-proxy"
+        );
+    }
+
+    #[test]
+    fn scrape_intervals_are_parsed() {
+        assert_eq!(
+            parse_scrape_interval("node_exporter=1m"),
+            Ok((
+                JobType::NodeExporter(NodeOS::Guest),
+                Duration::from_secs(60)
+            ))
+        );
+        assert!(parse_scrape_interval("replica").is_err());
+        assert!(parse_scrape_interval("replicas=10s").is_err());
+        assert!(parse_scrape_interval("replica=0s").is_err());
+        assert_eq!(
+            prometheus_duration(Duration::from_millis(1500)),
+            "1500ms".to_string()
+        );
+    }
+}
@@ -0,0 +1,1307 @@
+//! Start and stop the whole service: the definitions given on the command
+//! line and the server managing all definitions.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+use ic_management_types::Network;
+use slog::{error, info, Logger};
+use tokio::sync::{oneshot, Mutex};
+
+use crate::definition::{
+    remove_orphaned_registry_dirs, spawn, Definition, Verification, THREAD_POLL_INTERVAL,
+};
+use crate::definitions_file::DefinitionsFile;
+use crate::jitter::Jitter;
+use crate::log_level::LogLevels;
+use crate::metrics::Metrics;
+use crate::registry_source::{NnsRegistrySource, RegistrySource};
+use crate::server_handlers::dto::{DefinitionsDocumentDto, ImportStatus};
+use crate::server_handlers::{apply_import, prepare_server, AddDefinitionBinding, ImportMode};
+use crate::CliArgs;
+
+pub struct ServerConfig {
+    pub cli: CliArgs,
+    pub log: Logger,
+    pub log_levels: LogLevels,
+    /// Where all definitions, including mainnet, get their registries from.
+    pub registry_source: Arc<dyn RegistrySource>,
+    /// Exposed on `/metrics`, shared by the server and all definitions.
+    pub metrics: Metrics,
+    /// Written after every change of the definitions.
+    pub definitions_file: DefinitionsFile,
+    /// The contents of the definitions file as read on startup, if any.
+    pub definitions_document: Option<DefinitionsDocumentDto>,
+    /// The annotations of the node annotations file as read on startup, if
+    /// any.
+    pub node_annotations: Option<BTreeMap<String, BTreeMap<String, String>>>,
+}
+
+impl ServerConfig {
+    /// A config syncing the registries from the NNS of each definition.
+    pub fn new(cli: CliArgs, log: Logger, log_levels: LogLevels) -> Self {
+        Self {
+            definitions_file: DefinitionsFile::new(
+                cli.definitions_file.clone(),
+                cli.definitions_file_exclude_secrets,
+                log.clone(),
+            ),
+            definitions_document: None,
+            node_annotations: None,
+            cli,
+            log,
+            log_levels,
+            registry_source: Arc::new(NnsRegistrySource),
+            metrics: Metrics::new(),
+        }
+    }
+}
+
+/// A running service, stopped with [`ServerHandle::shutdown`].
+pub struct ServerHandle {
+    pub definitions: Arc<Mutex<Vec<Definition>>>,
+    handles: Arc<Mutex<Vec<JoinHandle<()>>>>,
+    stop_server: oneshot::Sender<()>,
+    server: tokio::task::JoinHandle<()>,
+}
+
+/// Start the mainnet definition, unless disabled, the definitions of the
+/// definitions file, if any, and the server. The definitions run on the
+/// runtime this is called from.
+pub async fn start_server(mut config: ServerConfig) -> ServerHandle {
+    let rt = tokio::runtime::Handle::current();
+    let mut handles = vec![];
+    let mut definitions = vec![];
+
+    let document = config.definitions_document.take();
+    // Only the registries of mainnet and of the definitions file survive a
+    // restart. Other registry dirs are only removed when every definition
+    // is persisted to the definitions file.
+    let mut kept_registries = if config.cli.start_without_mainnet {
+        vec![]
+    } else {
+        vec![Network::Mainnet.legacy_name()]
+    };
+    if let Some(document) = &document {
+        kept_registries.extend(
+            document
+                .definitions
+                .iter()
+                .map(|d| d.definition.name.clone()),
+        );
+    }
+    if config.definitions_file.persists() {
+        remove_orphaned_registry_dirs(&config.cli.targets_dir, &kept_registries, &config.log);
+    }
+    if !config.cli.start_without_mainnet {
+        let mainnet_definition = get_mainnet_definition(&config);
+        definitions.push(mainnet_definition.clone());
+
+        let ic_handle = spawn(mainnet_definition, rt.clone());
+        handles.push(ic_handle);
+    }
+    let definitions = Arc::new(Mutex::new(definitions));
+    let handles = Arc::new(Mutex::new(handles));
+    if let Some(document) = document {
+        let skipped = start_definitions_file(
+            &config,
+            document,
+            definitions.clone(),
+            handles.clone(),
+            rt.clone(),
+        )
+        .await;
+        // The skipped definitions are kept in the backup only, a definitions
+        // file that cannot be backed up is not overwritten.
+        if skipped && !config.definitions_file.back_up() {
+            config.definitions_file = DefinitionsFile::none(config.log.clone());
+        }
+    }
+    config.definitions_file.save(&definitions.lock().await);
+
+    let (stop_server, stop_server_receiver) = oneshot::channel();
+    let server = tokio::spawn(prepare_server(
+        stop_server_receiver,
+        config,
+        definitions.clone(),
+        handles.clone(),
+        rt,
+    ));
+
+    ServerHandle {
+        definitions,
+        handles,
+        stop_server,
+        server,
+    }
+}
+
+impl ServerHandle {
+    /// Stop the server and all definitions, and wait until they stopped.
+    pub async fn shutdown(self) {
+        self.stop_server.send(()).ok();
+
+        let definitions = self.definitions.lock().await.clone();
+        let handles = self.handles.lock().await.drain(..).collect::<Vec<_>>();
+        // Definitions only receive the stop signal between two polls, and
+        // threads that exited already never do, so it is sent again until all
+        // threads exited instead of waiting for each of them to take it.
+        tokio::task::spawn_blocking(move || {
+            while handles.iter().any(|h| !h.is_finished()) {
+                for definition in &definitions {
+                    let _ = definition.stop_signal_sender.try_send(());
+                }
+                std::thread::sleep(THREAD_POLL_INTERVAL);
+            }
+            for handle in handles {
+                handle.join().unwrap();
+            }
+        })
+        .await
+        .unwrap();
+
+        self.server.await.unwrap();
+    }
+}
+
+/// Start the definitions of the definitions file, except those with the name
+/// of one started already. Invalid definitions are logged and skipped, so
+/// that they do not keep the others from starting. Returns whether any was
+/// skipped.
+async fn start_definitions_file(
+    config: &ServerConfig,
+    mut document: DefinitionsDocumentDto,
+    definitions: Arc<Mutex<Vec<Definition>>>,
+    handles: Arc<Mutex<Vec<JoinHandle<()>>>>,
+    rt: tokio::runtime::Handle,
+) -> bool {
+    let started = definitions
+        .lock()
+        .await
+        .iter()
+        .map(|d| d.name.clone())
+        .collect::<Vec<_>>();
+    document.definitions.retain(|entry| {
+        let skipped = started.contains(&entry.definition.name);
+        if skipped {
+            info!(
+                config.log,
+                "Skipping definition '{}' of the definitions file, it is already started",
+                entry.definition.name
+            );
+        }
+        !skipped
+    });
+
+    let cli_args = &config.cli;
+    let binding = AddDefinitionBinding {
+        definitions,
+        log: config.log.clone(),
+        registry_path: cli_args.targets_dir.clone(),
+        poll_interval: cli_args.poll_interval,
+        registry_query_timeout: cli_args.registry_query_timeout,
+        rt,
+        handles,
+        jitter: Jitter::new(cli_args.poll_jitter, cli_args.poll_jitter_seed),
+        registry_source: config.registry_source.clone(),
+        metrics: config.metrics.clone(),
+        definitions_file: config.definitions_file.clone(),
+    };
+    // An import is only applied if all of its definitions are valid, so the
+    // invalid ones are dropped until the rest is.
+    let mut skipped = false;
+    loop {
+        let response = apply_import(ImportMode::Merge, document.clone(), &binding).await;
+        if response.applied {
+            info!(
+                config.log,
+                "Started {} definitions from the definitions file",
+                response.results.len()
+            );
+            return skipped;
+        }
+        // Without any change applied, there is one result per definition.
+        let mut results = response.results.into_iter();
+        document.definitions.retain(|_| {
+            let result = results.next().expect("one result per definition");
+            if result.status != ImportStatus::Invalid {
+                return true;
+            }
+            error!(
+                config.log,
+                "Skipping invalid definition '{}' of the definitions file: {}",
+                result.name,
+                result.error.unwrap_or_default()
+            );
+            false
+        });
+        skipped = true;
+    }
+}
+
+fn get_mainnet_definition(config: &ServerConfig) -> Definition {
+    let cli_args = &config.cli;
+    let (ic_stop_signal_sender, ic_stop_signal_rcv) = crossbeam::channel::bounded::<()>(0);
+
+    let mut definition = Definition::new(
+        vec![cli_args.nns_url.clone()],
+        cli_args.targets_dir.clone(),
+        Network::Mainnet.legacy_name(),
+        config.log.clone(),
+        Verification::default(),
+        cli_args.poll_interval,
+        ic_stop_signal_rcv,
+        cli_args.registry_query_timeout,
+        ic_stop_signal_sender,
+        Jitter::new(cli_args.poll_jitter, cli_args.poll_jitter_seed),
+    );
+    definition.registry_source = config.registry_source.clone();
+    definition.metrics = config.metrics.clone();
+    definition
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, BTreeSet};
+    use std::io::Read;
+    use std::time::{Duration, Instant};
+
+    use base64::{engine::general_purpose as b64, Engine as _};
+    use clap::Parser;
+    use futures_util::future::join_all;
+    use ic_crypto_utils_threshold_sig_der::threshold_sig_public_key_to_der;
+    use ic_protobuf::registry::subnet::v1::SubnetRecord;
+    use ic_registry_keys::{make_node_record_key, make_subnet_record_key};
+    use ic_registry_local_store::KeyMutation;
+    use multiservice_discovery_shared::contracts::log_target::{LogTargetDto, NodeType};
+    use multiservice_discovery_shared::contracts::target::TargetDto;
+    use prost::Message;
+    use slog::o;
+    use tempfile::TempDir;
+
+    use crate::definition::VerificationPolicy;
+    use crate::registry_source::{test_public_key, MockRegistrySource};
+    use crate::server_handlers::dto::{public_key_sha256, DeletionReportDto, ListedDefinitionDto};
+
+    use super::*;
+
+    /// A service running in-process on an ephemeral port.
+    struct Harness {
+        server: ServerHandle,
+        url: String,
+        client: reqwest::Client,
+        _dir: TempDir,
+    }
+
+    impl Harness {
+        async fn start(registry_source: Arc<dyn RegistrySource>, args: &[&str]) -> Self {
+            let dir = tempfile::tempdir().unwrap();
+            let port = std::net::TcpListener::bind("127.0.0.1:0")
+                .unwrap()
+                .local_addr()
+                .unwrap()
+                .port();
+            let listen_addr = format!("127.0.0.1:{}", port);
+            // Tests change definitions faster than the default rate limit
+            // allows.
+            let rate_limit: &[&str] = if args.contains(&"--mutation-rate-limit") {
+                &[]
+            } else {
+                &["--mutation-rate-limit", "0"]
+            };
+            let cli = CliArgs::parse_from(
+                [
+                    "multiservice-discovery",
+                    "--targets-dir",
+                    dir.path().to_str().unwrap(),
+                    "--listen-addr",
+                    &listen_addr,
+                    "--poll-jitter",
+                    "0",
+                ]
+                .iter()
+                .chain(rate_limit)
+                .chain(args),
+            );
+            let log = Logger::root(slog::Discard, o!());
+            let server = start_server(ServerConfig {
+                definitions_file: DefinitionsFile::new(
+                    cli.definitions_file.clone(),
+                    cli.definitions_file_exclude_secrets,
+                    log.clone(),
+                ),
+                definitions_document: cli.definitions_document().unwrap(),
+                node_annotations: cli.node_annotations().unwrap(),
+                cli,
+                log,
+                log_levels: LogLevels::new("info".parse().unwrap()),
+                registry_source,
+                metrics: Metrics::new(),
+            })
+            .await;
+
+            let harness = Self {
+                server,
+                url: format!("http://{}", listen_addr),
+                client: reqwest::Client::new(),
+                _dir: dir,
+            };
+            harness
+                .wait_until(Duration::from_secs(10), |h| async move {
+                    h.client.get(&h.url).send().await.is_ok()
+                })
+                .await;
+            harness
+        }
+
+        /// A service without mainnet, syncing from [`MockRegistrySource`].
+        async fn start_mocked() -> Self {
+            Self::start(
+                Arc::new(MockRegistrySource::default()),
+                &["--start-without-mainnet"],
+            )
+            .await
+        }
+
+        /// Wait until `condition` holds, panicking after `timeout`.
+        async fn wait_until<'a, F, Fut>(&'a self, timeout: Duration, condition: F)
+        where
+            F: Fn(&'a Self) -> Fut,
+            Fut: std::future::Future<Output = bool>,
+        {
+            let deadline = Instant::now() + timeout;
+            while !condition(self).await {
+                assert!(Instant::now() < deadline, "Timed out after {:?}", timeout);
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        }
+
+        /// Add a definition with an NNS url of its own. The mocked registry
+        /// is the same for every url.
+        async fn add(&self, name: &str) -> reqwest::StatusCode {
+            self.add_json(serde_json::json!({
+                "name": name,
+                "nns_urls": [format!("http://[::1]:1/{}", name)],
+                "public_key": null,
+            }))
+            .await
+            .status()
+        }
+
+        async fn add_json(&self, definition: serde_json::Value) -> reqwest::Response {
+            self.client
+                .post(&self.url)
+                .json(&definition)
+                .send()
+                .await
+                .unwrap()
+        }
+
+        async fn delete(&self, name: &str) -> reqwest::StatusCode {
+            self.client
+                .delete(format!("{}/{}", self.url, name))
+                .send()
+                .await
+                .unwrap()
+                .status()
+        }
+
+        /// Wait for the initial sync and a poll of the definition.
+        async fn sync(&self, name: &str) -> reqwest::StatusCode {
+            self.client
+                .post(format!("{}/definitions/{}/sync", self.url, name))
+                .send()
+                .await
+                .unwrap()
+                .status()
+        }
+
+        async fn get<T: serde::de::DeserializeOwned>(&self, path: &str) -> T {
+            self.client
+                .get(format!("{}{}", self.url, path))
+                .send()
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap()
+        }
+
+        async fn definitions(&self) -> Vec<String> {
+            self.get::<Vec<ListedDefinitionDto>>("/")
+                .await
+                .into_iter()
+                .map(|d| d.definition.name)
+                .collect()
+        }
+
+        async fn targets(&self) -> BTreeSet<TargetDto> {
+            self.get("/targets").await
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn shutdown_does_not_wait_for_exited_threads() {
+        let dir = tempfile::tempdir().unwrap();
+        let (stop_signal_sender, stop_signal) = crossbeam::channel::bounded::<()>(0);
+        let definition = Definition::new(
+            vec![url::Url::parse("http://[::1]:1").unwrap()],
+            dir.path().to_path_buf(),
+            "exited".to_string(),
+            Logger::root(slog::Discard, o!()),
+            Verification::default(),
+            Duration::from_secs(30),
+            stop_signal,
+            Duration::from_secs(5),
+            stop_signal_sender,
+            Jitter::none(),
+        );
+        // The thread exits without ever taking the stop signal.
+        let handle = std::thread::Builder::new()
+            .name("exited".to_string())
+            .spawn(|| {})
+            .unwrap();
+        let (stop_server, _) = oneshot::channel();
+        let server = ServerHandle {
+            definitions: Arc::new(Mutex::new(vec![definition])),
+            handles: Arc::new(Mutex::new(vec![handle])),
+            stop_server,
+            server: tokio::spawn(async {}),
+        };
+
+        tokio::time::timeout(Duration::from_secs(5), server.shutdown())
+            .await
+            .expect("shutdown waited for the exited thread");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn added_definitions_are_served_until_deleted() {
+        let harness = Harness::start_mocked().await;
+        assert!(harness.definitions().await.is_empty());
+
+        assert_eq!(harness.add("testnet").await, reqwest::StatusCode::OK);
+        assert_eq!(harness.definitions().await, vec!["testnet"]);
+        harness
+            .wait_until(Duration::from_secs(30), |h| async move {
+                !h.targets().await.is_empty()
+            })
+            .await;
+        assert!(harness
+            .targets()
+            .await
+            .iter()
+            .all(|t| t.ic_name == "testnet"));
+        let configs: Vec<serde_json::Value> = harness.get("/prom/targets").await;
+        assert!(!configs.is_empty());
+        assert!(configs.iter().all(|c| c["labels"]["ic"] == "testnet"));
+
+        assert_eq!(harness.delete("testnet").await, reqwest::StatusCode::OK);
+        assert!(harness.definitions().await.is_empty());
+        assert!(harness.targets().await.is_empty());
+
+        harness.server.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn definitions_with_the_same_targets_are_kept_apart() {
+        let harness = Harness::start_mocked().await;
+
+        assert_eq!(harness.add("a").await, reqwest::StatusCode::OK);
+        assert_eq!(harness.add("a").await, reqwest::StatusCode::BAD_REQUEST);
+        assert_eq!(harness.add("b").await, reqwest::StatusCode::OK);
+        assert_eq!(harness.delete("c").await, reqwest::StatusCode::BAD_REQUEST);
+        assert_eq!(harness.definitions().await, vec!["a", "b"]);
+
+        // Both definitions see the same registry, so deduplication leaves
+        // all targets to the first of them.
+        assert_eq!(harness.sync("a").await, reqwest::StatusCode::OK);
+        assert_eq!(harness.sync("b").await, reqwest::StatusCode::OK);
+        let targets = harness.targets().await;
+        assert!(!targets.is_empty());
+        assert!(targets.iter().all(|t| t.ic_name == "a"));
+
+        assert_eq!(harness.delete("a").await, reqwest::StatusCode::OK);
+        let targets = harness.targets().await;
+        assert!(!targets.is_empty());
+        assert!(targets.iter().all(|t| t.ic_name == "b"));
+
+        harness.server.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn log_level_is_read_with_the_auth_token() {
+        let harness = Harness::start(
+            Arc::new(MockRegistrySource::default()),
+            &["--start-without-mainnet", "--auth-token", "secret"],
+        )
+        .await;
+        let get = |token: Option<&str>| {
+            let request = harness.client.get(format!("{}/log_level", harness.url));
+            match token {
+                Some(token) => request.bearer_auth(token),
+                None => request,
+            }
+            .send()
+        };
+
+        let unauthorized = get(None).await.unwrap();
+        assert_eq!(unauthorized.status(), reqwest::StatusCode::UNAUTHORIZED);
+        let authorized = get(Some("secret")).await.unwrap();
+        assert_eq!(authorized.status(), reqwest::StatusCode::OK);
+
+        harness.server.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn definitions_are_deleted_in_bulk_with_the_auth_token() {
+        let harness = Harness::start(
+            Arc::new(MockRegistrySource::default()),
+            &["--start-without-mainnet", "--auth-token", "secret"],
+        )
+        .await;
+        for name in ["testnet-a", "other", "testnet-b"] {
+            assert_eq!(harness.add(name).await, reqwest::StatusCode::OK);
+        }
+        let delete = |path: &str, token: Option<&str>| {
+            let request = harness.client.delete(format!("{}{}", harness.url, path));
+            match token {
+                Some(token) => request.bearer_auth(token),
+                None => request,
+            }
+            .send()
+        };
+
+        let unauthorized = delete("/definitions?prefix=testnet-", None).await.unwrap();
+        assert_eq!(unauthorized.status(), reqwest::StatusCode::UNAUTHORIZED);
+        let deleted = delete("/definitions?prefix=testnet-", Some("secret"))
+            .await
+            .unwrap();
+        assert_eq!(deleted.status(), reqwest::StatusCode::OK);
+        let report: DeletionReportDto = deleted.json().await.unwrap();
+        assert_eq!(report.deleted, vec!["testnet-a", "testnet-b"]);
+        assert_eq!(harness.definitions().await, vec!["other"]);
+
+        let unconfirmed = delete("/definitions/all", Some("secret")).await.unwrap();
+        assert_eq!(unconfirmed.status(), reqwest::StatusCode::BAD_REQUEST);
+        let deleted = delete("/definitions/all?confirm=true", Some("secret"))
+            .await
+            .unwrap();
+        assert_eq!(deleted.status(), reqwest::StatusCode::OK);
+        assert!(harness.definitions().await.is_empty());
+
+        harness.server.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn exported_definitions_are_started_from_the_definitions_file() {
+        let harness = Harness::start_mocked().await;
+        assert_eq!(harness.add("a").await, reqwest::StatusCode::OK);
+        assert_eq!(harness.sync("a").await, reqwest::StatusCode::OK);
+        let added = harness
+            .add_json(serde_json::json!({
+                "name": "b",
+                "nns_urls": ["http://[::1]:1/b"],
+                "public_key": null,
+                "paused": true,
+                "port_overrides": { "replica": 19100 },
+            }))
+            .await;
+        assert_eq!(added.status(), reqwest::StatusCode::OK);
+        let added = harness
+            .client
+            .post(format!("{}/add_boundary_node", harness.url))
+            .json(&serde_json::json!({
+                "name": "bn-1",
+                "ic_name": "a",
+                "custom_labels": { "dc": "zh1" },
+                "targets": ["[2001:db8::1]:9100"],
+                "job_type": "node_exporter",
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(added.status(), reqwest::StatusCode::OK);
+        let exported: serde_json::Value = harness.get("/definitions/export").await;
+        assert_eq!(
+            exported["definitions"][0]["boundary_nodes"][0]["name"],
+            "bn-1"
+        );
+        let without_secrets: serde_json::Value = harness
+            .get("/definitions/export?exclude_secrets=true")
+            .await;
+        assert_eq!(
+            without_secrets["definitions"][0]["boundary_nodes"],
+            serde_json::json!([])
+        );
+        harness.server.shutdown().await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("definitions.json");
+        std::fs::write(&path, serde_json::to_vec(&exported).unwrap()).unwrap();
+        let restored = Harness::start(
+            Arc::new(MockRegistrySource::default()),
+            &[
+                "--start-without-mainnet",
+                "--definitions-file",
+                path.to_str().unwrap(),
+            ],
+        )
+        .await;
+        assert_eq!(restored.definitions().await, vec!["a", "b"]);
+        assert_eq!(
+            restored
+                .get::<serde_json::Value>("/definitions/export")
+                .await,
+            exported
+        );
+
+        restored.server.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn imported_definitions_serve_the_exported_targets() {
+        let harness = Harness::start_mocked().await;
+        assert_eq!(harness.add("a").await, reqwest::StatusCode::OK);
+        let added = harness
+            .add_json(serde_json::json!({
+                "name": "b",
+                "nns_urls": ["http://[::1]:1/b"],
+                "public_key": null,
+                "allow_duplicate_targets": true,
+                "port_overrides": { "replica": 19100 },
+            }))
+            .await;
+        assert_eq!(added.status(), reqwest::StatusCode::OK);
+        let added = harness
+            .client
+            .post(format!("{}/add_boundary_node", harness.url))
+            .json(&serde_json::json!({
+                "name": "bn-1",
+                "ic_name": "a",
+                "custom_labels": { "dc": "zh1" },
+                "targets": ["[2001:db8::1]:9100"],
+                "job_type": "node_exporter",
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(added.status(), reqwest::StatusCode::OK);
+        for name in ["a", "b"] {
+            assert_eq!(harness.sync(name).await, reqwest::StatusCode::OK);
+        }
+        let exported: serde_json::Value = harness.get("/definitions/export").await;
+        let targets = harness.targets().await;
+        assert!(targets.iter().any(|t| t.ic_name == "b"));
+        harness.server.shutdown().await;
+
+        let imported = Harness::start_mocked().await;
+        let response = imported
+            .client
+            .post(format!("{}/definitions/import?mode=merge", imported.url))
+            .json(&exported)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        for name in ["a", "b"] {
+            assert_eq!(imported.sync(name).await, reqwest::StatusCode::OK);
+        }
+        assert_eq!(imported.targets().await, targets);
+
+        imported.server.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn invalid_definitions_of_the_definitions_file_are_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("definitions.json");
+        let entry = |name: &str, public_key: Option<&str>| {
+            serde_json::json!({
+                "name": name,
+                "nns_urls": [format!("http://[::1]:1/{}", name)],
+                "public_key": public_key,
+                "poll_interval": { "secs": 30, "nanos": 0 },
+                "registry_query_timeout": { "secs": 5, "nanos": 0 },
+                "boundary_nodes": [],
+            })
+        };
+        let document = serde_json::json!({
+            "definitions": [entry("valid", None), entry("broken", Some("not base64!"))],
+        });
+        let contents = serde_json::to_vec(&document).unwrap();
+        std::fs::write(&path, &contents).unwrap();
+
+        let harness = Harness::start(
+            Arc::new(MockRegistrySource::default()),
+            &[
+                "--start-without-mainnet",
+                "--definitions-file",
+                path.to_str().unwrap(),
+            ],
+        )
+        .await;
+        assert_eq!(harness.definitions().await, vec!["valid"]);
+        // The skipped definition is only kept in the backup.
+        let backup = dir.path().join("definitions.json.bak");
+        assert_eq!(std::fs::read(backup).unwrap(), contents);
+        let written: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(written["definitions"].as_array().unwrap().len(), 1);
+        assert_eq!(written["definitions"][0]["name"], "valid");
+
+        harness.server.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn changes_are_written_to_the_definitions_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("definitions.json");
+        let harness = Harness::start(
+            Arc::new(MockRegistrySource::default()),
+            &[
+                "--start-without-mainnet",
+                "--definitions-file",
+                path.to_str().unwrap(),
+            ],
+        )
+        .await;
+        let written = || -> serde_json::Value {
+            serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap()
+        };
+        assert_eq!(written()["definitions"], serde_json::json!([]));
+
+        assert_eq!(harness.add("a").await, reqwest::StatusCode::OK);
+        let paused = harness
+            .client
+            .post(format!("{}/definitions/a/pause", harness.url))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(paused.status(), reqwest::StatusCode::OK);
+        let definitions = written()["definitions"].clone();
+        assert_eq!(definitions[0]["name"], "a");
+        assert_eq!(definitions[0]["paused"], true);
+        harness.server.shutdown().await;
+
+        let restored = Harness::start(
+            Arc::new(MockRegistrySource::default()),
+            &[
+                "--start-without-mainnet",
+                "--definitions-file",
+                path.to_str().unwrap(),
+            ],
+        )
+        .await;
+        let listed: Vec<ListedDefinitionDto> = restored.get("/").await;
+        assert_eq!(listed.len(), 1);
+        assert!(listed[0].definition.paused);
+
+        assert_eq!(restored.delete("a").await, reqwest::StatusCode::OK);
+        assert_eq!(written()["definitions"], serde_json::json!([]));
+        restored.server.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn watchers_are_notified_when_the_targets_change() {
+        let harness = Harness::start_mocked().await;
+        let watch = |etag: &str, timeout: u64| {
+            harness
+                .client
+                .get(format!(
+                    "{}/prom/targets/watch?timeout={}",
+                    harness.url, timeout
+                ))
+                .header("if-none-match", etag)
+                .send()
+        };
+
+        let current = watch("", 30).await.unwrap();
+        assert_eq!(current.status(), reqwest::StatusCode::OK);
+        let etag = current.json::<serde_json::Value>().await.unwrap()["etag"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        let unchanged = watch(&etag, 1).await.unwrap();
+        assert_eq!(unchanged.status(), reqwest::StatusCode::NOT_MODIFIED);
+
+        let (changed, added) = tokio::join!(watch(&etag, 30), harness.add("testnet"));
+        assert_eq!(added, reqwest::StatusCode::OK);
+        let changed = changed.unwrap();
+        assert_eq!(changed.status(), reqwest::StatusCode::OK);
+        let changed_etag = changed.headers()["etag"].to_str().unwrap().to_string();
+        assert_ne!(changed_etag, etag);
+        let targets = harness
+            .client
+            .get(format!("{}/prom/targets", harness.url))
+            .send()
+            .await
+            .unwrap();
+        assert!(targets.headers().contains_key("etag"));
+
+        // Waiting watchers do not hold up the shutdown.
+        let waiting = watch(&changed_etag, 300);
+        let (waiting, ()) = tokio::join!(waiting, async {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            tokio::time::timeout(Duration::from_secs(30), harness.server.shutdown())
+                .await
+                .unwrap();
+        });
+        assert_eq!(
+            waiting.unwrap().status(),
+            reqwest::StatusCode::SERVICE_UNAVAILABLE
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn scrape_configs_point_at_the_service() {
+        let harness = Harness::start_mocked().await;
+        assert_eq!(harness.add("testnet").await, reqwest::StatusCode::OK);
+
+        let document: serde_json::Value = harness.get("/prom/scrape_configs?format=json").await;
+        let configs = document["scrape_configs"].as_array().unwrap();
+        assert_eq!(configs.len(), 5);
+        assert_eq!(configs[0]["job_name"], "testnet_replica");
+        assert_eq!(configs[0]["scrape_interval"], "10s");
+        assert_eq!(
+            configs[0]["http_sd_configs"][0]["url"],
+            format!("{}/prom/targets", harness.url)
+        );
+        let yaml = harness
+            .client
+            .get(format!("{}/prom/scrape_configs", harness.url))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert!(yaml.starts_with("scrape_configs:\n- job_name: testnet_replica\n"));
+
+        harness.server.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn concurrent_identical_adds_start_one_definition() {
+        let harness = Harness::start_mocked().await;
+
+        let statuses = join_all((0..50).map(|_| harness.add("testnet"))).await;
+        let added = statuses
+            .iter()
+            .filter(|s| **s == reqwest::StatusCode::OK)
+            .count();
+        assert_eq!(added, 1);
+        assert!(statuses
+            .iter()
+            .all(|s| *s == reqwest::StatusCode::OK || *s == reqwest::StatusCode::BAD_REQUEST));
+        assert_eq!(harness.definitions().await, vec!["testnet"]);
+        assert_eq!(harness.server.handles.lock().await.len(), 1);
+
+        harness.server.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn mutations_are_rate_limited() {
+        let harness = Harness::start(
+            Arc::new(MockRegistrySource::default()),
+            &["--start-without-mainnet", "--mutation-rate-limit", "2"],
+        )
+        .await;
+
+        assert_eq!(harness.add("a").await, reqwest::StatusCode::OK);
+        assert_eq!(harness.add("b").await, reqwest::StatusCode::OK);
+        let limited = harness
+            .add_json(serde_json::json!({
+                "name": "c",
+                "nns_urls": ["http://[::1]:1/c"],
+                "public_key": null,
+            }))
+            .await;
+        assert_eq!(limited.status(), reqwest::StatusCode::TOO_MANY_REQUESTS);
+        let retry_after: u64 = limited.headers()["retry-after"]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!((1..=30).contains(&retry_after));
+        assert_eq!(
+            harness.delete("a").await,
+            reqwest::StatusCode::TOO_MANY_REQUESTS
+        );
+        // Reading is not limited.
+        assert_eq!(harness.definitions().await, vec!["a", "b"]);
+
+        harness.server.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn definitions_sharing_an_nns_conflict_unless_allowed() {
+        let harness = Harness::start_mocked().await;
+        let definition = |name: &str, nns_url: &str, allow_overlap: bool| {
+            serde_json::json!({
+                "name": name,
+                "nns_urls": [nns_url],
+                "public_key": null,
+                "allow_overlap": allow_overlap,
+            })
+        };
+
+        let added = harness
+            .add_json(definition("a", "http://[::1]:1", false))
+            .await;
+        assert_eq!(added.status(), reqwest::StatusCode::OK);
+        let conflict = harness
+            .add_json(definition("b", "HTTP://[::1]:1/", false))
+            .await;
+        assert_eq!(conflict.status(), reqwest::StatusCode::CONFLICT);
+        assert!(conflict.text().await.unwrap().contains("Definition a "));
+        let allowed = harness
+            .add_json(definition("b", "http://[::1]:1", true))
+            .await;
+        assert_eq!(allowed.status(), reqwest::StatusCode::OK);
+        assert_eq!(harness.definitions().await, vec!["a", "b"]);
+        let listed = harness.get::<Vec<ListedDefinitionDto>>("/").await;
+        assert!(listed[1].definition.allow_overlap);
+
+        harness.server.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn registry_changes_are_served_after_one_sync() {
+        let source = Arc::new(MockRegistrySource::default());
+        let harness = Harness::start(source.clone(), &["--start-without-mainnet"]).await;
+        assert_eq!(harness.add("testnet").await, reqwest::StatusCode::OK);
+        assert_eq!(harness.sync("testnet").await, reqwest::StatusCode::OK);
+        let targets = harness.targets().await;
+        let offboarded = targets.iter().find(|t| t.subnet_id.is_none()).unwrap();
+        let leaving = targets.iter().find(|t| t.subnet_id.is_some()).unwrap();
+        let subnet_id = leaving.subnet_id.unwrap();
+
+        // The latest record of the subnet, without the node.
+        let subnet_key = make_subnet_record_key(subnet_id);
+        let mut subnet = source
+            .changelog()
+            .iter()
+            .flatten()
+            .filter(|mutation| mutation.key == subnet_key)
+            .last()
+            .and_then(|mutation| mutation.value.as_ref())
+            .map(|value| SubnetRecord::decode(&value[..]).unwrap())
+            .unwrap();
+        let leaving_id = leaving.node_id.get().as_slice().to_vec();
+        subnet.membership.retain(|node_id| *node_id != leaving_id);
+        source.publish(vec![
+            KeyMutation {
+                key: make_node_record_key(offboarded.node_id),
+                value: None,
+            },
+            KeyMutation {
+                key: subnet_key,
+                value: Some(subnet.encode_to_vec()),
+            },
+        ]);
+        assert_eq!(harness.sync("testnet").await, reqwest::StatusCode::OK);
+
+        let synced = harness.targets().await;
+        assert_eq!(synced.len(), targets.len() - 1);
+        assert!(synced.iter().all(|t| t.node_id != offboarded.node_id));
+        let left = synced
+            .iter()
+            .find(|t| t.node_id == leaving.node_id)
+            .unwrap();
+        assert_eq!(left.subnet_id, None);
+        let configs: Vec<serde_json::Value> = harness.get("/prom/targets").await;
+        let offboarded_id = offboarded.node_id.to_string();
+        assert!(configs
+            .iter()
+            .all(|c| c["labels"]["ic_node"] != offboarded_id.as_str()));
+        let metrics = harness
+            .client
+            .get(format!("{}/metrics", harness.url))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert!(metrics.contains("msd_evicted_targets_total{definition=\"testnet\"} 1"));
+
+        harness.server.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn verification_policies_are_validated_and_listed() {
+        let harness = Harness::start_mocked().await;
+        let right_key =
+            b64::STANDARD.encode(threshold_sig_public_key_to_der(test_public_key(1)).unwrap());
+        let wrong_key =
+            b64::STANDARD.encode(threshold_sig_public_key_to_der(test_public_key(2)).unwrap());
+        let definition = |name: &str, verify: &str, public_key: Option<&str>| {
+            serde_json::json!({
+                "name": name,
+                "nns_urls": [format!("http://[::1]:1/{}", name)],
+                "public_key": public_key,
+                "verify": verify,
+            })
+        };
+        let listed = |name: &'static str| {
+            let harness = &harness;
+            async move {
+                let definition = harness
+                    .get::<Vec<ListedDefinitionDto>>("/")
+                    .await
+                    .into_iter()
+                    .find(|d| d.definition.name == name)
+                    .unwrap();
+                (
+                    definition.definition.verify.unwrap(),
+                    definition.public_key_sha256,
+                )
+            }
+        };
+        let pinned_hash = public_key_sha256(test_public_key(1));
+
+        let mismatch = harness
+            .add_json(definition("strict", "strict", Some(&wrong_key)))
+            .await;
+        assert_eq!(mismatch.status(), reqwest::StatusCode::BAD_REQUEST);
+        assert!(mismatch.text().await.unwrap().contains("does not verify"));
+        for (verify, public_key) in [("strict", None), ("none", Some(right_key.as_str()))] {
+            let invalid = harness
+                .add_json(definition("invalid", verify, public_key))
+                .await;
+            assert_eq!(invalid.status(), reqwest::StatusCode::BAD_REQUEST);
+        }
+
+        for (name, verify, public_key) in [
+            ("strict", "strict", Some(right_key.as_str())),
+            ("fetched", "fetch", None),
+            ("unverified", "none", None),
+        ] {
+            let added = harness.add_json(definition(name, verify, public_key)).await;
+            assert_eq!(added.status(), reqwest::StatusCode::OK);
+            assert_eq!(harness.sync(name).await, reqwest::StatusCode::OK);
+        }
+        assert_eq!(
+            listed("strict").await,
+            (VerificationPolicy::Strict, pinned_hash.clone())
+        );
+        assert_eq!(
+            listed("fetched").await,
+            (VerificationPolicy::Fetch, pinned_hash.clone())
+        );
+        assert_eq!(listed("unverified").await, (VerificationPolicy::None, None));
+
+        let update = |name: &str, verify: &str, public_key: &str| {
+            harness
+                .client
+                .put(format!("{}/definitions/{}/verify", harness.url, name))
+                .json(&serde_json::json!({ "verify": verify, "public_key": public_key }))
+                .send()
+        };
+        let rejected = update("unverified", "strict", &wrong_key).await.unwrap();
+        assert_eq!(rejected.status(), reqwest::StatusCode::BAD_REQUEST);
+        assert_eq!(listed("unverified").await, (VerificationPolicy::None, None));
+        let updated = update("unverified", "strict", &right_key).await.unwrap();
+        assert_eq!(updated.status(), reqwest::StatusCode::OK);
+        assert_eq!(
+            listed("unverified").await,
+            (VerificationPolicy::Strict, pinned_hash)
+        );
+
+        harness.server.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn port_overrides_apply_to_their_jobs_only() {
+        let harness = Harness::start_mocked().await;
+        let definition = |name: &str, port_overrides: serde_json::Value| {
+            serde_json::json!({
+                "name": name,
+                "nns_urls": [format!("http://[::1]:1/{}", name)],
+                "public_key": null,
+                "port_overrides": port_overrides,
+            })
+        };
+
+        let unknown = harness
+            .add_json(definition(
+                "unknown",
+                serde_json::json!({ "replicas": 19090 }),
+            ))
+            .await;
+        assert_eq!(unknown.status(), reqwest::StatusCode::BAD_REQUEST);
+        assert!(unknown
+            .text()
+            .await
+            .unwrap()
+            .contains("Unknown job 'replicas'"));
+        let added = harness
+            .add_json(definition(
+                "testnet",
+                serde_json::json!({ "replica": 19090 }),
+            ))
+            .await;
+        assert_eq!(added.status(), reqwest::StatusCode::OK);
+        assert_eq!(harness.sync("testnet").await, reqwest::StatusCode::OK);
+
+        let listed = harness.get::<Vec<ListedDefinitionDto>>("/").await;
+        assert_eq!(
+            listed[0].definition.port_overrides,
+            BTreeMap::from([("replica".to_string(), 19090)])
+        );
+        assert_eq!(listed[0].ports["replica"], 19090);
+        assert_eq!(listed[0].ports["node_exporter"], 9100);
+
+        let configs: Vec<serde_json::Value> = harness.get("/prom/targets").await;
+        let targets = |job: &str| {
+            configs
+                .iter()
+                .filter(|c| c["labels"]["job"] == job)
+                .flat_map(|c| c["targets"].as_array().unwrap().clone())
+                .map(|t| t.as_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert!(!targets("replica").is_empty());
+        assert!(targets("replica").iter().all(|t| t.contains("]:19090/")));
+        assert!(!targets("node_exporter").is_empty());
+        assert!(targets("node_exporter")
+            .iter()
+            .all(|t| t.contains("]:9100/")));
+
+        harness.server.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn log_targets_list_nodes_and_boundary_nodes() {
+        let harness = Harness::start_mocked().await;
+        assert_eq!(harness.add("testnet").await, reqwest::StatusCode::OK);
+        assert_eq!(harness.sync("testnet").await, reqwest::StatusCode::OK);
+        let added = harness
+            .client
+            .post(format!("{}/add_boundary_node", harness.url))
+            .json(&serde_json::json!({
+                "name": "api-bn-1",
+                "ic_name": "testnet",
+                "custom_labels": {},
+                "targets": ["[2001:db8::1]:9100"],
+                "job_type": "node_exporter",
+                "api_boundary": true,
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(added.status(), reqwest::StatusCode::OK);
+
+        let log_targets: Vec<LogTargetDto> = harness.get("/log_targets").await;
+
+        let (replicas, boundary_nodes): (Vec<_>, Vec<_>) = log_targets
+            .into_iter()
+            .partition(|t| t.node_type == NodeType::Replica);
+        let nodes = harness.targets().await;
+        assert_eq!(replicas.len(), nodes.len());
+        for replica in &replicas {
+            let node = nodes.iter().find(|n| n.node_id == replica.node_id).unwrap();
+            assert_eq!(replica.ic_name, "testnet");
+            assert_eq!(replica.name, node.node_id.to_string());
+            assert_eq!(replica.subnet_id, node.subnet_id);
+            assert_eq!(replica.dc_id, node.dc_id);
+            assert_eq!(replica.node_provider_id, node.node_provider_id);
+            let ip = node.targets.first().unwrap().ip();
+            assert_eq!(replica.journald_url, format!("http://[{}]:19531", ip));
+        }
+        assert_eq!(boundary_nodes.len(), 1);
+        assert_eq!(boundary_nodes[0].name, "api-bn-1");
+        assert_eq!(boundary_nodes[0].node_type, NodeType::ApiBoundaryNode);
+        assert_eq!(boundary_nodes[0].journald_url, "http://[2001:db8::1]:19531");
+
+        harness.server.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn prometheus_targets_are_gzipped_on_request() {
+        let harness = Harness::start_mocked().await;
+        assert_eq!(harness.add("testnet").await, reqwest::StatusCode::OK);
+        assert_eq!(harness.sync("testnet").await, reqwest::StatusCode::OK);
+        let url = format!("{}/prom/targets", harness.url);
+
+        let plain = harness.client.get(&url).send().await.unwrap();
+        assert!(plain.headers().get("content-encoding").is_none());
+        let plain = plain.bytes().await.unwrap();
+
+        let gzipped = harness
+            .client
+            .get(&url)
+            .header("accept-encoding", "gzip")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(gzipped.status(), reqwest::StatusCode::OK);
+        assert_eq!(gzipped.headers()["content-encoding"], "gzip");
+        let gzipped = gzipped.bytes().await.unwrap();
+        assert!(gzipped.len() < plain.len());
+
+        let mut decompressed = vec![];
+        flate2::read::GzDecoder::new(&gzipped[..])
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, plain);
+
+        harness.server.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn metrics_are_served_as_openmetrics_on_request() {
+        let harness = Harness::start_mocked().await;
+        let url = format!("{}/metrics", harness.url);
+        // Count a request, so that the counters have a sample.
+        harness.client.get(&url).send().await.unwrap();
+
+        let prometheus = harness.client.get(&url).send().await.unwrap();
+        assert_eq!(
+            prometheus.headers()["content-type"],
+            "text/plain; version=0.0.4; charset=utf-8"
+        );
+        let prometheus = prometheus.text().await.unwrap();
+        assert!(prometheus.contains("# TYPE msd_http_requests_total counter\n"));
+        assert!(!prometheus.contains("# EOF"));
+
+        let openmetrics = harness
+            .client
+            .get(&url)
+            .header(
+                "accept",
+                "application/openmetrics-text;version=1.0.0;q=0.5,text/plain;version=0.0.4;q=0.3",
+            )
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(openmetrics.status(), reqwest::StatusCode::OK);
+        assert_eq!(
+            openmetrics.headers()["content-type"],
+            "application/openmetrics-text; version=1.0.0; charset=utf-8"
+        );
+        let openmetrics = openmetrics.text().await.unwrap();
+        assert!(openmetrics.contains("# TYPE msd_http_requests counter\n"));
+        assert!(openmetrics
+            .contains("msd_http_requests_total{method=\"GET\",route=\"/metrics\",status=\"2xx\"}"));
+        assert!(openmetrics.ends_with("# EOF\n"));
+
+        harness.server.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "requires access to the IC mainnet"]
+    async fn mainnet_targets_are_served() {
+        let harness = Harness::start(Arc::new(NnsRegistrySource), &[]).await;
+
+        assert_eq!(harness.definitions().await, vec!["mercury"]);
+        harness
+            .wait_until(Duration::from_secs(300), |h| async move {
+                !h.targets().await.is_empty()
+            })
+            .await;
+
+        harness.server.shutdown().await;
+    }
+}
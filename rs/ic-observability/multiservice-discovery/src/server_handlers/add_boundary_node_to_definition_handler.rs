@@ -7,12 +7,14 @@ use tokio::sync::Mutex;
 use warp::Reply;
 
 use crate::definition::{BoundaryNode, Definition};
+use crate::definitions_file::DefinitionsFile;
 use crate::server_handlers::dto::BoundaryNodeDto;
 use crate::server_handlers::WebResult;
 
 pub struct AddBoundaryNodeToDefinitionBinding {
     pub definitions: Arc<Mutex<Vec<Definition>>>,
     pub log: Logger,
+    pub definitions_file: DefinitionsFile,
 }
 
 pub async fn add_boundary_node(
@@ -63,7 +65,9 @@ pub async fn add_boundary_node(
         custom_labels: boundary_node.custom_labels,
         targets: boundary_node.targets,
         job_type,
+        api_boundary: boundary_node.api_boundary,
     });
+    binding.definitions_file.save(&definitions);
 
     Ok(warp::reply::with_status(
         "success".to_string(),
@@ -1,16 +1,24 @@
 use base64::{engine::general_purpose as b64, Engine as _};
+use std::collections::BTreeMap;
 use std::path::PathBuf;
+use std::str::FromStr;
 use std::sync::Arc;
 use std::thread::JoinHandle;
 use std::time::Duration;
 
 use ic_crypto_utils_threshold_sig_der::parse_threshold_sig_key_from_der;
-use service_discovery::registry_sync::nns_reachable;
+use ic_registry_client::client::ThresholdSigPublicKey;
+use service_discovery::job_types::{JobAndPort, JobType};
 use slog::Logger;
 use tokio::sync::Mutex;
+use url::Url;
 use warp::Reply;
 
-use crate::definition::{wrap, Definition};
+use crate::definition::{name_reserved, spawn, validate_name, Definition, Verification};
+use crate::definitions_file::DefinitionsFile;
+use crate::jitter::Jitter;
+use crate::metrics::Metrics;
+use crate::registry_source::RegistrySource;
 use crate::server_handlers::dto::DefinitionDto;
 use crate::server_handlers::WebResult;
 
@@ -22,63 +30,356 @@ pub struct AddDefinitionBinding {
     pub registry_query_timeout: Duration,
     pub rt: tokio::runtime::Handle,
     pub handles: Arc<Mutex<Vec<JoinHandle<()>>>>,
+    pub jitter: Jitter,
+    pub registry_source: Arc<dyn RegistrySource>,
+    pub metrics: Metrics,
+    pub definitions_file: DefinitionsFile,
 }
 
 pub async fn add_definition(definition: DefinitionDto, binding: AddDefinitionBinding) -> WebResult<impl Reply> {
-    let public_key = match definition.public_key {
+    let mut definitions = binding.definitions.lock().await;
+
+    let verification =
+        match validate_definition(&definition, &definitions, &binding.handles, &*binding.registry_source).await {
+            Ok(verification) => verification,
+            Err(e) => return Ok(warp::reply::with_status(e, warp::http::StatusCode::BAD_REQUEST)),
+        };
+    if let Err(e) = check_overlap(&definition, &definitions) {
+        return Ok(warp::reply::with_status(e, warp::http::StatusCode::CONFLICT));
+    }
+
+    let definition = start_definition(
+        definition,
+        verification,
+        binding.poll_interval,
+        binding.registry_query_timeout,
+        &binding,
+    )
+    .await;
+    definitions.push(definition);
+    binding.definitions_file.save(&definitions);
+
+    Ok(warp::reply::with_status(
+        "success".to_string(),
+        warp::http::StatusCode::OK,
+    ))
+}
+
+/// Decode the base64 encoded DER public key of a definition, if any.
+pub(super) fn decode_public_key(public_key: &Option<String>) -> Result<Option<ThresholdSigPublicKey>, String> {
+    match public_key {
         Some(pk) => {
-            let decoded = b64::STANDARD.decode(pk).unwrap();
-
-            match parse_threshold_sig_key_from_der(&decoded) {
-                Ok(key) => Some(key),
-                Err(e) => {
-                    return Ok(warp::reply::with_status(
-                        e.to_string(),
-                        warp::http::StatusCode::BAD_REQUEST,
-                    ))
-                }
-            }
+            let decoded = b64::STANDARD
+                .decode(pk)
+                .map_err(|e| format!("Public key is not valid base64: {}", e))?;
+
+            parse_threshold_sig_key_from_der(&decoded)
+                .map(Some)
+                .map_err(|e| e.to_string())
         }
-        None => None,
-    };
+        None => Ok(None),
+    }
+}
 
-    let mut definitions = binding.definitions.lock().await;
+/// The verification policy of a definition together with its decoded public
+/// key, if the two go together.
+pub(super) fn decode_verification(definition: &DefinitionDto) -> Result<Verification, String> {
+    Verification::new(definition.verify, decode_public_key(&definition.public_key)?)
+}
 
-    if definitions.iter().any(|d| d.name == definition.name) {
-        return Ok(warp::reply::with_status(
-            "Definition with this name already exists".to_string(),
-            warp::http::StatusCode::BAD_REQUEST,
-        ));
+/// Decode the job names of the port overrides of a definition.
+pub(super) fn decode_port_overrides(definition: &DefinitionDto) -> Result<BTreeMap<JobType, u16>, String> {
+    definition
+        .port_overrides
+        .iter()
+        .map(|(job, port)| {
+            let job_type = JobType::from_str(job).map_err(|_| {
+                format!(
+                    "Unknown job '{}' in port_overrides, known jobs are: {}",
+                    job,
+                    JobAndPort::all()
+                        .iter()
+                        .map(|j| j.job_type.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            })?;
+            match port {
+                0 => Err(format!("Invalid port 0 for job '{}' in port_overrides", job)),
+                port => Ok((job_type, *port)),
+            }
+        })
+        .collect()
+}
+
+/// Check that the verification can be used for the registry of `nns_urls`,
+/// i.e. that its public key, if any, verifies the responses of the NNS.
+pub(super) async fn validate_verification(
+    verification: Verification,
+    nns_urls: &[Url],
+    registry_source: &dyn RegistrySource,
+) -> Result<(), String> {
+    match verification.public_key {
+        Some(public_key) => registry_source
+            .verify(nns_urls, public_key)
+            .await
+            .map_err(|e| format!("Public key does not verify the registry of the NNS: {}", e)),
+        None => Ok(()),
     }
+}
+
+/// Check that the definition can be added next to the `existing` ones and
+/// return how its registry is verified.
+pub(super) async fn validate_definition(
+    definition: &DefinitionDto,
+    existing: &[Definition],
+    handles: &Mutex<Vec<JoinHandle<()>>>,
+    registry_source: &dyn RegistrySource,
+) -> Result<Verification, String> {
+    validate_name(&definition.name)?;
+    let verification = decode_verification(definition)?;
+    decode_port_overrides(definition)?;
+
+    if existing.iter().any(|d| d.name == definition.name) {
+        return Err("Definition with this name already exists".to_string());
+    }
+    if name_reserved(&definition.name, handles).await {
+        return Err("A deleted definition with this name is still stopping, try again later".to_string());
+    }
+
+    validate_registry(definition, verification, registry_source).await?;
 
-    if !nns_reachable(definition.nns_urls.clone()).await {
-        return Ok(warp::reply::with_status(
-            "Couldn't ping nns of that definition".to_string(),
-            warp::http::StatusCode::BAD_REQUEST,
-        ));
+    Ok(verification)
+}
+
+/// Check that the definition can replace the running one with the same name
+/// and return how its registry is verified.
+pub(super) async fn validate_replacement(
+    definition: &DefinitionDto,
+    registry_source: &dyn RegistrySource,
+) -> Result<Verification, String> {
+    let verification = decode_verification(definition)?;
+    decode_port_overrides(definition)?;
+
+    validate_registry(definition, verification, registry_source).await?;
+
+    Ok(verification)
+}
+
+/// Check that the NNS of the definition is reachable and that its registry
+/// can be verified as requested.
+async fn validate_registry(
+    definition: &DefinitionDto,
+    verification: Verification,
+    registry_source: &dyn RegistrySource,
+) -> Result<(), String> {
+    if !registry_source.reachable(&definition.nns_urls).await {
+        return Err("Couldn't ping nns of that definition".to_string());
     }
+    validate_verification(verification, &definition.nns_urls, registry_source).await
+}
 
+/// Check that no `existing` definition syncs from any of the NNS urls of the
+/// definition, unless it explicitly allows the overlap.
+pub(super) fn check_overlap(definition: &DefinitionDto, existing: &[Definition]) -> Result<(), String> {
+    if definition.allow_overlap {
+        return Ok(());
+    }
+    match find_overlap(&definition.nns_urls, existing) {
+        Some((other, url)) => Err(format!(
+            "Definition {} already syncs from {}, set allow_overlap to add this one anyway",
+            other.name, url
+        )),
+        None => Ok(()),
+    }
+}
+
+/// The first of the `existing` definitions syncing from one of `nns_urls`,
+/// together with the url they share.
+fn find_overlap<'a>(nns_urls: &'a [Url], existing: &'a [Definition]) -> Option<(&'a Definition, &'a Url)> {
+    nns_urls.iter().find_map(|url| {
+        let normalized = normalize_url(url);
+        existing
+            .iter()
+            .find(|d| d.nns_urls.iter().any(|u| normalize_url(u) == normalized))
+            .map(|d| (d, url))
+    })
+}
+
+/// The form in which urls pointing at the same NNS compare equal. Parsing
+/// already lowercases the scheme and host, the default port and trailing
+/// slashes are dropped here.
+fn normalize_url(url: &Url) -> String {
+    format!(
+        "{}://{}:{}{}",
+        url.scheme(),
+        url.host_str().unwrap_or_default(),
+        url.port_or_known_default().unwrap_or_default(),
+        url.path().trim_end_matches('/')
+    )
+}
+
+/// Spawn the thread syncing the registry of a validated definition. The
+/// caller is responsible for adding the returned definition to the list.
+pub(super) async fn start_definition(
+    definition: DefinitionDto,
+    verification: Verification,
+    poll_interval: Duration,
+    registry_query_timeout: Duration,
+    binding: &AddDefinitionBinding,
+) -> Definition {
     let (stop_signal_sender, stop_signal_rcv) = crossbeam::channel::bounded::<()>(0);
-    let definition = Definition::new(
-        definition.nns_urls,
+    let port_overrides =
+        decode_port_overrides(&definition).expect("Port overrides are validated before the definition is started");
+    // Destructured without `..`, so that a new field of the DTO does not
+    // compile until it is carried over to the definition.
+    let DefinitionDto {
+        nns_urls,
+        name,
+        // Both are decoded into `verification` during validation.
+        public_key: _,
+        verify: _,
+        scrape_options,
+        allow_duplicate_targets,
+        allow_overlap,
+        paused,
+        port_overrides: _,
+    } = definition;
+    let mut definition = Definition::new(
+        nns_urls,
         binding.registry_path.clone(),
-        definition.name.clone(),
-        binding.log,
-        public_key,
-        binding.poll_interval,
+        name,
+        binding.log.clone(),
+        verification,
+        poll_interval,
         stop_signal_rcv,
-        binding.registry_query_timeout,
+        registry_query_timeout,
         stop_signal_sender,
+        binding.jitter.clone(),
     );
+    definition.scrape_options = scrape_options;
+    definition.allow_duplicate_targets = allow_duplicate_targets;
+    definition.allow_overlap = allow_overlap;
+    definition.set_paused(paused);
+    definition.set_port_overrides(port_overrides);
+    definition.registry_source = binding.registry_source.clone();
+    definition.metrics = binding.metrics.clone();
 
-    definitions.push(definition.clone());
-
-    let ic_handle = std::thread::spawn(wrap(definition, binding.rt));
+    let ic_handle = spawn(definition.clone(), binding.rt.clone());
     let mut handles = binding.handles.lock().await;
     handles.push(ic_handle);
 
-    Ok(warp::reply::with_status(
-        "success".to_string(),
-        warp::http::StatusCode::OK,
-    ))
+    definition
+}
+
+#[cfg(test)]
+mod tests {
+    use slog::o;
+
+    use super::*;
+
+    fn definition(dir: &tempfile::TempDir, name: &str, nns_urls: &[&str]) -> Definition {
+        let (stop_signal_sender, stop_signal_rcv) = crossbeam::channel::bounded::<()>(1);
+        Definition::new(
+            nns_urls.iter().map(|u| Url::parse(u).unwrap()).collect(),
+            dir.path().to_path_buf(),
+            name.to_string(),
+            Logger::root(slog::Discard, o!()),
+            Verification::default(),
+            Duration::from_secs(30),
+            stop_signal_rcv,
+            Duration::from_secs(5),
+            stop_signal_sender,
+            Jitter::none(),
+        )
+    }
+
+    fn dto(nns_urls: &[&str], allow_overlap: bool) -> DefinitionDto {
+        DefinitionDto {
+            nns_urls: nns_urls.iter().map(|u| Url::parse(u).unwrap()).collect(),
+            name: "new".to_string(),
+            public_key: None,
+            verify: None,
+            scrape_options: Default::default(),
+            allow_duplicate_targets: false,
+            allow_overlap,
+            paused: false,
+            port_overrides: Default::default(),
+        }
+    }
+
+    fn existing(dir: &tempfile::TempDir) -> Vec<Definition> {
+        vec![
+            definition(dir, "mainnet", &["https://ic0.app"]),
+            definition(
+                dir,
+                "testnet",
+                &["http://[2001:db8::1]:8080/", "http://[2001:db8::2]:8080/"],
+            ),
+        ]
+    }
+
+    #[test]
+    fn port_overrides_must_name_known_jobs() {
+        let with_overrides = |overrides: &[(&str, u16)]| DefinitionDto {
+            port_overrides: overrides.iter().map(|(job, port)| (job.to_string(), *port)).collect(),
+            ..dto(&["https://ic0.app"], false)
+        };
+
+        assert_eq!(
+            decode_port_overrides(&with_overrides(&[("replica", 19090), ("host_node_exporter", 19100)])),
+            Ok(BTreeMap::from([
+                (JobType::Replica, 19090),
+                (JobType::NodeExporter(service_discovery::job_types::NodeOS::Host), 19100),
+            ]))
+        );
+        let unknown = decode_port_overrides(&with_overrides(&[("replicas", 19090)])).unwrap_err();
+        assert!(unknown.contains("known jobs are: replica, orchestrator"), "{}", unknown);
+        assert!(decode_port_overrides(&with_overrides(&[("replica", 0)])).is_err());
+    }
+
+    #[test]
+    fn exact_duplicates_conflict() {
+        let dir = tempfile::tempdir().unwrap();
+        let err = check_overlap(&dto(&["https://ic0.app"], false), &existing(&dir)).unwrap_err();
+        assert!(err.contains("mainnet"), "{}", err);
+    }
+
+    #[test]
+    fn normalized_equal_urls_conflict() {
+        let dir = tempfile::tempdir().unwrap();
+        let existing = existing(&dir);
+        for url in ["HTTPS://IC0.app:443/", "https://ic0.app//", "http://[2001:db8::1]:8080"] {
+            assert!(
+                check_overlap(&dto(&[url], false), &existing).is_err(),
+                "{} should conflict",
+                url
+            );
+        }
+        for url in ["http://ic0.app", "https://ic0.app:8443", "https://ic0.app/api"] {
+            assert!(
+                check_overlap(&dto(&[url], false), &existing).is_ok(),
+                "{} should not conflict",
+                url
+            );
+        }
+    }
+
+    #[test]
+    fn partial_overlap_conflicts_with_the_overlapping_definition() {
+        let dir = tempfile::tempdir().unwrap();
+        let err = check_overlap(
+            &dto(&["http://[2001:db8::3]:8080/", "http://[2001:db8::2]:8080/"], false),
+            &existing(&dir),
+        )
+        .unwrap_err();
+        assert!(err.contains("testnet"), "{}", err);
+        assert!(err.contains("[2001:db8::2]:8080"), "{}", err);
+    }
+
+    #[test]
+    fn overlap_can_be_allowed() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(check_overlap(&dto(&["https://ic0.app"], true), &existing(&dir)).is_ok());
+    }
 }
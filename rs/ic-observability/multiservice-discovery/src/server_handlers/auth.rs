@@ -0,0 +1,38 @@
+use warp::http::StatusCode;
+use warp::reject::Reject;
+use warp::{Filter, Rejection, Reply};
+
+#[derive(Debug)]
+pub struct Unauthorized;
+
+impl Reject for Unauthorized {}
+
+/// Require an `Authorization: Bearer <token>` header matching `token`. If no
+/// token is configured every request is let through.
+pub fn with_auth(token: Option<String>) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and_then(move |header: Option<String>| {
+            let token = token.clone();
+            async move {
+                match token {
+                    None => Ok(()),
+                    Some(token) if header == Some(format!("Bearer {}", token)) => Ok(()),
+                    Some(_) => Err(warp::reject::custom(Unauthorized)),
+                }
+            }
+        })
+        .untuple_one()
+}
+
+/// Turn the rejections of [`with_auth`] into a 401 response and pass every
+/// other rejection on.
+pub async fn handle_unauthorized(rejection: Rejection) -> Result<impl Reply, Rejection> {
+    if rejection.find::<Unauthorized>().is_some() {
+        Ok(warp::reply::with_status(
+            "Missing or invalid auth token".to_string(),
+            StatusCode::UNAUTHORIZED,
+        ))
+    } else {
+        Err(rejection)
+    }
+}
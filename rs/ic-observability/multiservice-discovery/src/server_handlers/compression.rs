@@ -0,0 +1,53 @@
+use std::io::Write;
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use warp::hyper::body::Bytes;
+
+/// Whether an `Accept-Encoding` header allows a gzip compressed response,
+/// i.e. it lists `gzip` or `*` without `q=0`.
+pub fn accepts_gzip(accept_encoding: Option<&str>) -> bool {
+    let accept_encoding = match accept_encoding {
+        Some(accept_encoding) => accept_encoding,
+        None => return false,
+    };
+    accept_encoding.split(',').any(|coding| {
+        let mut params = coding.split(';').map(str::trim);
+        let name = params.next().unwrap_or_default();
+        let quality = params
+            .find_map(|p| p.strip_prefix("q="))
+            .map_or(Ok(1.0), str::parse::<f64>)
+            .unwrap_or(0.0);
+        ["gzip", "x-gzip", "*"]
+            .iter()
+            .any(|n| name.eq_ignore_ascii_case(n))
+            && quality > 0.0
+    })
+}
+
+pub fn gzip(body: &[u8]) -> Bytes {
+    let mut encoder = GzEncoder::new(vec![], Compression::default());
+    // Writing to a vector does not fail.
+    encoder.write_all(body).unwrap();
+    Bytes::from(encoder.finish().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gzip_is_accepted_unless_excluded() {
+        for header in ["gzip", "deflate, gzip;q=0.5", "br, *", "GZIP", "x-gzip"] {
+            assert!(accepts_gzip(Some(header)), "{} should accept gzip", header);
+        }
+        for header in ["", "identity", "deflate, br", "gzip;q=0", "gzip; q=0.0"] {
+            assert!(
+                !accepts_gzip(Some(header)),
+                "{} should not accept gzip",
+                header
+            );
+        }
+        assert!(!accepts_gzip(None));
+    }
+}
@@ -1,30 +1,82 @@
 use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
 
+use futures_util::future::join_all;
+use ic_management_types::Network;
+use serde::Deserialize;
+use slog::warn;
 use tokio::sync::Mutex;
+use warp::http::StatusCode;
 use warp::Reply;
 
-use crate::definition::Definition;
+use crate::definition::{stop_definition_within, Definition};
+use crate::definitions_file::DefinitionsFile;
+use crate::server_handlers::dto::DeletionReportDto;
 use crate::server_handlers::WebResult;
 
+/// How long a deletion waits for the thread of each definition.
+pub const STOP_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub struct DeleteDefinitionBinding {
+    pub definitions: Arc<Mutex<Vec<Definition>>>,
+    pub handles: Arc<Mutex<Vec<JoinHandle<()>>>>,
+    pub stop_timeout: Duration,
+    pub definitions_file: DefinitionsFile,
+}
+
+pub struct DeleteDefinitionsBinding {
+    pub definitions: Arc<Mutex<Vec<Definition>>>,
+    pub handles: Arc<Mutex<Vec<JoinHandle<()>>>>,
+    pub stop_timeout: Duration,
+    pub definitions_file: DefinitionsFile,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteDefinitionsQuery {
+    pub prefix: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteAllDefinitionsQuery {
+    #[serde(default)]
+    pub confirm: bool,
+}
+
 pub async fn delete_definition(
     name: String,
-    definitions: Arc<Mutex<Vec<Definition>>>,
+    binding: DeleteDefinitionBinding,
 ) -> WebResult<impl Reply> {
-    if name == "ic" {
+    if name == Network::Mainnet.legacy_name() {
         return Ok(warp::reply::with_status(
-            "Cannot delete ic definition".to_string(),
+            "Cannot delete the mainnet definition".to_string(),
             warp::http::StatusCode::BAD_REQUEST,
         ));
     }
 
-    let mut definitions = definitions.lock().await;
-
-    let index = definitions.iter().position(|d| d.name == name);
-
-    match index {
-        Some(index) => {
+    // The lock is only held to remove the definition, its thread keeps the
+    // name reserved until it stopped, so that a definition with the same name
+    // cannot be added in the meantime.
+    let definition = {
+        let mut definitions = binding.definitions.lock().await;
+        let index = definitions.iter().position(|d| d.name == name);
+        index.map(|index| {
             let definition = definitions.remove(index);
-            definition.stop_signal_sender.send(()).unwrap();
+            binding.definitions_file.save(&definitions);
+            definition
+        })
+    };
+
+    match definition {
+        Some(definition) => {
+            let log = definition.log.clone();
+            // A thread stuck in a long sync must not hang the request. It
+            // is left to finish on its own, its name stays reserved until then.
+            if let Err(e) =
+                stop_definition_within(definition, &binding.handles, binding.stop_timeout).await
+            {
+                warn!(log, "{}, detaching it", e);
+            }
             Ok(warp::reply::with_status(
                 "success".to_string(),
                 warp::http::StatusCode::OK,
@@ -36,3 +88,269 @@ pub async fn delete_definition(
         )),
     }
 }
+
+/// Delete the definitions whose name starts with the prefix.
+pub async fn delete_definitions(
+    query: DeleteDefinitionsQuery,
+    binding: DeleteDefinitionsBinding,
+) -> WebResult<impl Reply> {
+    if query.prefix.is_empty() {
+        return Ok(warp::reply::with_status(
+            "The prefix must not be empty, use /definitions/all to delete all definitions"
+                .to_string(),
+            StatusCode::BAD_REQUEST,
+        )
+        .into_response());
+    }
+    let report = delete_matching(&binding, |name| name.starts_with(&query.prefix)).await;
+    Ok(warp::reply::json(&report).into_response())
+}
+
+/// Delete all definitions, if confirmed with `confirm=true`.
+pub async fn delete_all_definitions(
+    query: DeleteAllDefinitionsQuery,
+    binding: DeleteDefinitionsBinding,
+) -> WebResult<impl Reply> {
+    if !query.confirm {
+        return Ok(warp::reply::with_status(
+            "Deleting all definitions must be confirmed with confirm=true".to_string(),
+            StatusCode::BAD_REQUEST,
+        )
+        .into_response());
+    }
+    let report = delete_matching(&binding, |_| true).await;
+    Ok(warp::reply::json(&report).into_response())
+}
+
+/// Remove the matching definitions and stop them concurrently. The mainnet
+/// definition is never deleted, as for a single deletion. A definition whose thread does not
+/// stop in time is reported as failed without holding up the others.
+async fn delete_matching<F>(binding: &DeleteDefinitionsBinding, matches: F) -> DeletionReportDto
+where
+    F: Fn(&str) -> bool,
+{
+    // As for a single deletion, the lock is released before the definitions
+    // are stopped.
+    let mainnet = Network::Mainnet.legacy_name();
+    let deleted = {
+        let mut definitions = binding.definitions.lock().await;
+        let (deleted, kept): (Vec<_>, Vec<_>) = definitions
+            .drain(..)
+            .partition(|d| d.name != mainnet && matches(&d.name));
+        *definitions = kept;
+        binding.definitions_file.save(&definitions);
+        deleted
+    };
+
+    let results = join_all(deleted.into_iter().map(|definition| async {
+        let name = definition.name.clone();
+        let result =
+            stop_definition_within(definition, &binding.handles, binding.stop_timeout).await;
+        (name, result)
+    }))
+    .await;
+
+    let mut report = DeletionReportDto::default();
+    for (name, result) in results {
+        match result {
+            Ok(()) => report.deleted.push(name),
+            Err(e) => {
+                report.failed.insert(name, e);
+            }
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use slog::{o, Logger};
+    use url::Url;
+
+    use crate::definition::{name_reserved, Verification};
+    use crate::jitter::Jitter;
+
+    use super::*;
+
+    /// A definition with a thread that stops once signalled, after `delay`.
+    fn running_definition(
+        dir: &tempfile::TempDir,
+        name: &str,
+        delay: Duration,
+    ) -> (Definition, JoinHandle<()>) {
+        let (stop_signal_sender, stop_signal_rcv) = crossbeam::channel::bounded::<()>(1);
+        let thread_stop_signal = stop_signal_rcv.clone();
+        let definition = Definition::new(
+            vec![Url::parse("https://ic0.app").unwrap()],
+            dir.path().to_path_buf(),
+            name.to_string(),
+            Logger::root(slog::Discard, o!()),
+            Verification::default(),
+            Duration::from_secs(30),
+            stop_signal_rcv,
+            Duration::from_secs(5),
+            stop_signal_sender,
+            Jitter::none(),
+        );
+        let handle = std::thread::Builder::new()
+            .name(name.to_string())
+            .spawn(move || {
+                thread_stop_signal.recv().ok();
+                std::thread::sleep(delay);
+            })
+            .unwrap();
+        (definition, handle)
+    }
+
+    fn binding(dir: &tempfile::TempDir, names: &[(&str, Duration)]) -> DeleteDefinitionsBinding {
+        let (definitions, handles) = names
+            .iter()
+            .map(|(name, delay)| running_definition(dir, name, *delay))
+            .unzip();
+        DeleteDefinitionsBinding {
+            definitions: Arc::new(Mutex::new(definitions)),
+            handles: Arc::new(Mutex::new(handles)),
+            stop_timeout: Duration::from_millis(500),
+            definitions_file: DefinitionsFile::none(Logger::root(slog::Discard, o!())),
+        }
+    }
+
+    async fn names(binding: &DeleteDefinitionsBinding) -> Vec<String> {
+        let definitions = binding.definitions.lock().await;
+        definitions.iter().map(|d| d.name.clone()).collect()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn definitions_are_deleted_by_prefix() {
+        let dir = tempfile::tempdir().unwrap();
+        let binding = binding(
+            &dir,
+            &[
+                ("testnet-a", Duration::ZERO),
+                ("other", Duration::ZERO),
+                ("testnet-b", Duration::ZERO),
+            ],
+        );
+
+        let report = delete_matching(&binding, |name| name.starts_with("testnet-")).await;
+        assert_eq!(report.deleted, vec!["testnet-a", "testnet-b"]);
+        assert!(report.failed.is_empty());
+        assert_eq!(names(&binding).await, vec!["other"]);
+        assert!(!dir.path().join("testnet-a").exists());
+        assert!(dir.path().join("other").exists());
+        assert_eq!(binding.handles.lock().await.len(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn mainnet_is_never_deleted() {
+        let dir = tempfile::tempdir().unwrap();
+        let binding = binding(
+            &dir,
+            &[("mercury", Duration::ZERO), ("other", Duration::ZERO)],
+        );
+
+        let single = DeleteDefinitionBinding {
+            definitions: binding.definitions.clone(),
+            handles: binding.handles.clone(),
+            stop_timeout: binding.stop_timeout,
+            definitions_file: binding.definitions_file.clone(),
+        };
+        let reply = delete_definition("mercury".to_string(), single)
+            .await
+            .unwrap();
+        assert_eq!(reply.into_response().status(), StatusCode::BAD_REQUEST);
+
+        let report = delete_matching(&binding, |_| true).await;
+        assert_eq!(report.deleted, vec!["other"]);
+        assert_eq!(names(&binding).await, vec!["mercury"]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn deleting_all_definitions_must_be_confirmed() {
+        let dir = tempfile::tempdir().unwrap();
+        let binding = binding(&dir, &[("a", Duration::ZERO), ("b", Duration::ZERO)]);
+        let query = |confirm| DeleteAllDefinitionsQuery { confirm };
+        let rebind = || DeleteDefinitionsBinding {
+            definitions: binding.definitions.clone(),
+            handles: binding.handles.clone(),
+            stop_timeout: binding.stop_timeout,
+            definitions_file: binding.definitions_file.clone(),
+        };
+
+        let rejected = delete_all_definitions(query(false), rebind())
+            .await
+            .unwrap();
+        assert_eq!(rejected.into_response().status(), StatusCode::BAD_REQUEST);
+        assert_eq!(names(&binding).await, vec!["a", "b"]);
+
+        let deleted = delete_all_definitions(query(true), rebind()).await.unwrap();
+        let response = deleted.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = warp::hyper::body::to_bytes(response.into_body())
+            .await
+            .unwrap();
+        let report: DeletionReportDto = serde_json::from_slice(&body).unwrap();
+        assert_eq!(report.deleted, vec!["a", "b"]);
+        assert!(names(&binding).await.is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn a_slow_definition_is_detached_after_the_timeout() {
+        let dir = tempfile::tempdir().unwrap();
+        let (definition, handle) = running_definition(&dir, "slow", Duration::from_secs(3));
+        let binding = DeleteDefinitionBinding {
+            definitions: Arc::new(Mutex::new(vec![definition])),
+            handles: Arc::new(Mutex::new(vec![handle])),
+            stop_timeout: Duration::from_millis(500),
+            definitions_file: DefinitionsFile::none(Logger::root(slog::Discard, o!())),
+        };
+        let definitions = binding.definitions.clone();
+        let handles = binding.handles.clone();
+
+        let start = std::time::Instant::now();
+        let deletion = tokio::spawn(delete_definition("slow".to_string(), binding));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        // Other requests are not blocked while the thread is stopping.
+        let locked = tokio::time::timeout(Duration::from_millis(100), definitions.lock()).await;
+        assert!(locked.unwrap().is_empty());
+        let reply = deletion.await.unwrap().unwrap();
+        assert!(
+            start.elapsed() < Duration::from_secs(2),
+            "{:?}",
+            start.elapsed()
+        );
+        assert_eq!(reply.into_response().status(), StatusCode::OK);
+        assert!(definitions.lock().await.is_empty());
+        // The name and the registry stay taken until the thread exits.
+        assert!(name_reserved("slow", &handles).await);
+        assert!(dir.path().join("slow").exists());
+
+        let deadline = std::time::Instant::now() + Duration::from_secs(10);
+        while name_reserved("slow", &handles).await {
+            assert!(
+                std::time::Instant::now() < deadline,
+                "slow definition never exited"
+            );
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert!(handles.lock().await.is_empty());
+        assert!(!dir.path().join("slow").exists());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn a_stuck_definition_does_not_abort_the_others() {
+        let dir = tempfile::tempdir().unwrap();
+        let binding = binding(
+            &dir,
+            &[("stuck", Duration::from_secs(2)), ("fine", Duration::ZERO)],
+        );
+
+        let report = delete_matching(&binding, |_| true).await;
+        assert_eq!(report.deleted, vec!["fine"]);
+        assert!(report.failed["stuck"].contains("did not stop"));
+        assert!(names(&binding).await.is_empty());
+        // The registry of the stuck definition is removed once it exits.
+        assert!(dir.path().join("stuck").exists());
+        assert!(!dir.path().join("fine").exists());
+    }
+}
@@ -1,34 +1,211 @@
 use base64::{engine::general_purpose as b64, Engine as _};
+use ic_crypto_utils_threshold_sig_der::threshold_sig_public_key_to_der;
+use ic_registry_client::client::ThresholdSigPublicKey;
+use multiservice_discovery_shared::builders::prometheus_config_structure::ScrapeOptions;
+use sha2::{Digest, Sha256};
 use std::collections::{BTreeMap, BTreeSet};
 
 use serde::{Deserialize, Serialize};
 use std::net::SocketAddr;
+use std::time::Duration;
 use url::Url;
 
-use crate::definition::Definition;
+use crate::definition::{Definition, VerificationPolicy};
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct DefinitionDto {
     pub nns_urls: Vec<Url>,
     pub name: String,
     pub public_key: Option<String>,
+    /// How the registry is verified. Defaults to `strict` if a public key is
+    /// given and to `fetch` otherwise.
+    #[serde(default)]
+    pub verify: Option<VerificationPolicy>,
+    #[serde(flatten)]
+    pub scrape_options: ScrapeOptions,
+    #[serde(default)]
+    pub allow_duplicate_targets: bool,
+    /// Add the definition even if another definition syncs from some of the
+    /// same NNS urls.
+    #[serde(default)]
+    pub allow_overlap: bool,
+    /// Whether the registry sync is paused. Definitions added with
+    /// `start_paused` do not sync until resumed.
+    #[serde(default, alias = "start_paused")]
+    pub paused: bool,
+    /// The ports that replace the default port of a job, e.g. `replica`, for
+    /// the targets of this definition.
+    #[serde(default)]
+    pub port_overrides: BTreeMap<String, u16>,
 }
 
 impl From<&Definition> for DefinitionDto {
     fn from(value: &Definition) -> Self {
+        let verification = value.verification();
         Self {
             name: value.name.clone(),
             nns_urls: value.nns_urls.clone(),
-            public_key: value.public_key.map(|pk| b64::STANDARD.encode(pk.into_bytes())),
+            // Encoded the same way as it is expected by `add_definition`.
+            public_key: verification
+                .public_key
+                .and_then(|pk| threshold_sig_public_key_to_der(pk).ok())
+                .map(|der| b64::STANDARD.encode(der)),
+            verify: Some(verification.policy),
+            scrape_options: value.scrape_options,
+            allow_duplicate_targets: value.allow_duplicate_targets,
+            allow_overlap: value.allow_overlap,
+            paused: value.is_paused(),
+            port_overrides: value
+                .port_overrides()
+                .iter()
+                .map(|(job, port)| (job.to_string(), *port))
+                .collect(),
         }
     }
 }
 
+/// A running definition as listed by `GET /`.
 #[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ListedDefinitionDto {
+    #[serde(flatten)]
+    pub definition: DefinitionDto,
+    /// When the registry of the definition is synced next, in RFC 3339.
+    pub next_sync: Option<String>,
+    /// The hex encoded SHA-256 of the DER encoded public key the registry is
+    /// verified with, once there is one.
+    pub public_key_sha256: Option<String>,
+    /// The port the targets of each job are served on, with the overrides
+    /// applied.
+    pub ports: BTreeMap<String, u16>,
+}
+
+impl From<&Definition> for ListedDefinitionDto {
+    fn from(value: &Definition) -> Self {
+        Self {
+            definition: value.into(),
+            next_sync: value
+                .next_sync()
+                .map(|t| humantime::format_rfc3339_seconds(t).to_string()),
+            public_key_sha256: value.verification().public_key.and_then(public_key_sha256),
+            ports: value
+                .ports()
+                .into_iter()
+                .map(|(job, port)| (job.to_string(), port))
+                .collect(),
+        }
+    }
+}
+
+/// How the public key of a definition is identified when listed.
+pub fn public_key_sha256(public_key: ThresholdSigPublicKey) -> Option<String> {
+    threshold_sig_public_key_to_der(public_key)
+        .ok()
+        .map(|der| hex::encode(Sha256::digest(der)))
+}
+
+/// A new verification policy for a running definition, as accepted by
+/// `PUT /definitions/{name}/verify`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct VerificationDto {
+    pub verify: VerificationPolicy,
+    #[serde(default)]
+    pub public_key: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct BoundaryNodeDto {
     pub name: String,
     pub ic_name: String,
     pub custom_labels: BTreeMap<String, String>,
     pub targets: BTreeSet<SocketAddr>,
     pub job_type: String,
+    #[serde(default)]
+    pub api_boundary: bool,
+}
+
+/// A definition together with the options it runs with, as exported by
+/// `GET /definitions/export`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ExportedDefinitionDto {
+    #[serde(flatten)]
+    pub definition: DefinitionDto,
+    pub poll_interval: Duration,
+    pub registry_query_timeout: Duration,
+    pub boundary_nodes: Vec<BoundaryNodeDto>,
+}
+
+impl From<&Definition> for ExportedDefinitionDto {
+    fn from(value: &Definition) -> Self {
+        Self {
+            definition: value.into(),
+            poll_interval: value.poll_interval,
+            registry_query_timeout: value.registry_query_timeout,
+            boundary_nodes: value
+                .boundary_nodes
+                .iter()
+                .map(|bn| BoundaryNodeDto {
+                    name: bn.name.clone(),
+                    ic_name: value.name.clone(),
+                    custom_labels: bn.custom_labels.clone(),
+                    targets: bn.targets.clone(),
+                    job_type: bn.job_type.to_string(),
+                    api_boundary: bn.api_boundary,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DefinitionsDocumentDto {
+    pub definitions: Vec<ExportedDefinitionDto>,
+}
+
+impl DefinitionsDocumentDto {
+    /// The document without the content supplied by operators that may be
+    /// confidential, i.e. the boundary nodes with their targets and labels.
+    pub fn without_secrets(mut self) -> Self {
+        for definition in &mut self.definitions {
+            definition.boundary_nodes.clear();
+        }
+        self
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportStatus {
+    Added,
+    Unchanged,
+    /// Stopped and started again, since it differs from the running one.
+    Replaced,
+    Removed,
+    Invalid,
+    /// Valid, but the running definition with the same name did not stop in
+    /// time, so it is removed without being started again.
+    Failed,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ImportResultDto {
+    pub name: String,
+    pub status: ImportStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The outcome of `DELETE /definitions` and `DELETE /definitions/all`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct DeletionReportDto {
+    pub deleted: Vec<String>,
+    /// The definitions that were removed but could not be cleaned up, with
+    /// the reason.
+    pub failed: BTreeMap<String, String>,
+}
+
+/// Sent by `GET /prom/targets/watch` once the targets changed.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TargetsChangedDto {
+    /// The entity tag `/prom/targets` now replies with.
+    pub etag: String,
 }
@@ -0,0 +1,31 @@
+use std::sync::Arc;
+
+use serde::Deserialize;
+use tokio::sync::Mutex;
+use warp::reply::json;
+use warp::Reply;
+
+use crate::definition::Definition;
+use crate::server_handlers::dto::DefinitionsDocumentDto;
+use crate::server_handlers::WebResult;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ExportQuery {
+    /// Leave out the content supplied by operators that may be confidential,
+    /// see [`DefinitionsDocumentDto::without_secrets`].
+    #[serde(default)]
+    pub exclude_secrets: bool,
+}
+
+pub async fn export_definitions(query: ExportQuery, definitions: Arc<Mutex<Vec<Definition>>>) -> WebResult<impl Reply> {
+    let definitions = definitions.lock().await;
+
+    let document = DefinitionsDocumentDto {
+        definitions: definitions.iter().map(|d| d.into()).collect(),
+    };
+    Ok(json(&if query.exclude_secrets {
+        document.without_secrets()
+    } else {
+        document
+    }))
+}
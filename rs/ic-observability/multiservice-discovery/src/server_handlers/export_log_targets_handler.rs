@@ -0,0 +1,80 @@
+use std::sync::Arc;
+
+use ic_types::{NodeId, PrincipalId};
+use multiservice_discovery_shared::builders::log_target_builder::LogTargetBuilder;
+use multiservice_discovery_shared::contracts::log_target::NodeType;
+use multiservice_discovery_shared::contracts::target::TargetDto;
+use service_discovery::job_types::JobType;
+use service_discovery::IcServiceDiscovery;
+use slog::Logger;
+use tokio::sync::Mutex;
+use warp::reply::json;
+use warp::Reply;
+
+use crate::definition::Definition;
+use crate::server_handlers::WebResult;
+
+pub struct ExportLogTargetsBinding {
+    pub definitions: Arc<Mutex<Vec<Definition>>>,
+    pub log: Logger,
+    pub builder: LogTargetBuilder,
+}
+
+/// Serve the nodes of all definitions and their boundary nodes for the
+/// journald log fetcher.
+pub async fn export_log_targets(binding: ExportLogTargetsBinding) -> WebResult<impl Reply> {
+    let definitions = binding.definitions.lock().await;
+
+    Ok(json(
+        &binding
+            .builder
+            .build(log_targets(&definitions, &binding.log)),
+    ))
+}
+
+/// The targets of every node with its type, the definitions in the order of
+/// their names so that the first of them keeps a node shared with another.
+fn log_targets(definitions: &[Definition], log: &Logger) -> Vec<(TargetDto, NodeType)> {
+    let mut definitions = definitions.iter().collect::<Vec<_>>();
+    definitions.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut targets = vec![];
+    for def in definitions {
+        // All jobs of a node share its address, except for the ones of the
+        // host OS.
+        if let Ok(target_groups) = def
+            .ic_discovery
+            .get_target_groups(JobType::Replica, log.clone())
+        {
+            for target_group in &target_groups {
+                let mut target = TargetDto::from(target_group);
+                target.name = target_group.node_id.to_string();
+                target.ic_name = def.name.clone();
+                targets.push((target, NodeType::Replica));
+            }
+        }
+
+        for bn in &def.boundary_nodes {
+            let target = TargetDto {
+                name: bn.name.clone(),
+                node_id: NodeId::from(PrincipalId::new_anonymous()),
+                jobs: vec![bn.job_type],
+                custom_labels: bn.custom_labels.clone(),
+                targets: bn.targets.clone(),
+                dc_id: "".to_string(),
+                ic_name: def.name.clone(),
+                node_provider_id: PrincipalId::new_anonymous(),
+                operator_id: PrincipalId::new_anonymous(),
+                subnet_id: None,
+                public_ipv4: None,
+            };
+            let node_type = if bn.api_boundary {
+                NodeType::ApiBoundaryNode
+            } else {
+                NodeType::BoundaryNode
+            };
+            targets.push((target, node_type));
+        }
+    }
+    targets
+}
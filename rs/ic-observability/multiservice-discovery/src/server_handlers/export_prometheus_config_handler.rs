@@ -1,32 +1,96 @@
-use std::{collections::BTreeMap, sync::Arc};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+};
 
 use service_discovery::{
     job_types::{JobType, NodeOS},
-    jobs::Job,
     IcServiceDiscovery,
 };
-use slog::Logger;
+use slog::{warn, Logger};
 use tokio::sync::Mutex;
+use warp::http::header::{HeaderValue, CONTENT_ENCODING, ETAG, VARY};
 use warp::reply::Reply;
 
+use crate::dedup::deduplicate_definition_targets;
 use crate::definition::Definition;
+use crate::metrics::Metrics;
 use multiservice_discovery_shared::{
-    builders::prometheus_config_structure::{map_target_group, PrometheusStaticConfig},
+    builders::prometheus_config_structure::{
+        map_target_group_with_options, PrometheusStaticConfig, Scheme, ScrapeOptions,
+    },
+    builders::transformers::{TargetTransformer, TransformerPipeline},
     contracts::target::TargetDto,
 };
 
-use super::WebResult;
+use super::compression::accepts_gzip;
+use super::serialized_cache::{targets_fingerprint, SerializedCache};
+use super::watch_targets_handler::targets_etag;
+use super::{text_reply, WebResult};
 
 pub struct ExportDefinitionConfigBinding {
     pub definitions: Arc<Mutex<Vec<Definition>>>,
     pub log: Logger,
+    pub transformers: TransformerPipeline,
+    pub cache: SerializedCache,
+    pub metrics: Metrics,
+    /// Drop the targets already exported by another definition.
+    pub deduplicate: bool,
 }
 
+/// Serve the targets in the Prometheus http_sd format, compressed with gzip
+/// if the client accepts it. The entity tag is the one announced by
+/// `/prom/targets/watch`.
 pub async fn export_prometheus_config(
+    accept_encoding: Option<String>,
     binding: ExportDefinitionConfigBinding,
 ) -> WebResult<impl Reply> {
     let definitions = binding.definitions.lock().await;
 
+    let fingerprint = targets_fingerprint(&definitions);
+    let body = binding
+        .cache
+        .get_or_build(fingerprint, || {
+            prometheus_configs(
+                &definitions,
+                &binding.log,
+                &binding.transformers,
+                &binding.metrics,
+                binding.deduplicate,
+            )
+        })
+        .await;
+
+    let status_code = if body.as_ref() != b"[]" {
+        warp::http::StatusCode::OK
+    } else {
+        warp::http::StatusCode::NOT_FOUND
+    };
+
+    let etag = HeaderValue::from_str(&targets_etag(&body)).unwrap();
+    let mut reply = if accepts_gzip(accept_encoding.as_deref()) {
+        let mut reply = text_reply(binding.cache.gzipped(fingerprint, body).await, status_code);
+        reply
+            .headers_mut()
+            .insert(CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+        reply
+    } else {
+        text_reply(body, status_code)
+    };
+    reply
+        .headers_mut()
+        .insert(VARY, HeaderValue::from_static("accept-encoding"));
+    reply.headers_mut().insert(ETAG, etag);
+    Ok(reply)
+}
+
+pub(super) fn prometheus_configs(
+    definitions: &[Definition],
+    log: &Logger,
+    transformers: &TransformerPipeline,
+    metrics: &Metrics,
+    deduplicate: bool,
+) -> BTreeSet<PrometheusStaticConfig> {
     let all_jobs = [
         JobType::Replica,
         JobType::Orchestrator,
@@ -39,10 +103,7 @@ pub async fn export_prometheus_config(
 
     for def in definitions.iter() {
         for job_type in all_jobs {
-            let targets = match def
-                .ic_discovery
-                .get_target_groups(job_type, binding.log.clone())
-            {
+            let targets = match def.ic_discovery.get_target_groups(job_type, log.clone()) {
                 Ok(targets) => targets,
                 Err(_) => continue,
             };
@@ -50,7 +111,7 @@ pub async fn export_prometheus_config(
             for target in targets {
                 if let Some(entry) = total_targets
                     .iter_mut()
-                    .find(|t| t.node_id == target.node_id)
+                    .find(|t| t.node_id == target.node_id && t.ic_name == def.name)
                 {
                     entry.jobs.push(job_type);
                 } else {
@@ -65,7 +126,27 @@ pub async fn export_prometheus_config(
         }
     }
 
-    let mut total_set = map_target_group(total_targets.into_iter().collect());
+    if deduplicate {
+        total_targets = deduplicate_definition_targets(total_targets, definitions, metrics);
+    }
+
+    let mut total_set = BTreeSet::new();
+    for def in definitions.iter() {
+        let (configs, skipped) = map_target_group_with_options(
+            total_targets
+                .iter()
+                .filter(|t| t.ic_name == def.name)
+                .cloned()
+                .collect(),
+            &def.scrape_options,
+            def.port_overrides(),
+        );
+        total_set.extend(configs);
+        metrics
+            .skipped_targets
+            .with_label_values(&[&def.name])
+            .inc_by(skipped as u64);
+    }
 
     definitions.iter().for_each(|def| {
         def.boundary_nodes.iter().for_each(|bn| {
@@ -86,20 +167,23 @@ pub async fn export_prometheus_config(
                 return;
             }
 
-            let binding = Job::all();
-            let job = binding.iter().find(|j| j._type == bn.job_type).unwrap();
+            // Boundary nodes are always scraped over HTTP unless the
+            // definition says otherwise.
+            let options = ScrapeOptions {
+                scheme: Some(def.scrape_options.scheme.unwrap_or(Scheme::Http)),
+                ..def.scrape_options
+            };
+            let targets = options.endpoints(&bn.targets, None, bn.job_type);
+            if targets.is_empty() {
+                metrics
+                    .skipped_targets
+                    .with_label_values(&[&def.name])
+                    .inc();
+                return;
+            }
 
             total_set.insert(PrometheusStaticConfig {
-                targets: bn
-                    .targets
-                    .clone()
-                    .iter()
-                    .map(|g| {
-                        let mut g = *g;
-                        g.set_port(job.port);
-                        format!("http://{}/{}", g, job.endpoint.trim_start_matches('/'),)
-                    })
-                    .collect(),
+                targets,
                 labels: {
                     let mut labels = BTreeMap::new();
                     labels.insert("ic".to_string(), def.name.clone());
@@ -112,13 +196,18 @@ pub async fn export_prometheus_config(
         })
     });
 
-    let prom_config = serde_json::to_string_pretty(&total_set).unwrap();
-
-    let status_code = if !total_set.is_empty() {
-        warp::http::StatusCode::OK
-    } else {
-        warp::http::StatusCode::NOT_FOUND
-    };
-
-    Ok(warp::reply::with_status(prom_config, status_code))
+    transformers
+        .transform(total_set.into_iter().collect())
+        .into_iter()
+        .filter(|config| match config.validate() {
+            Ok(()) => true,
+            Err(e) => {
+                warn!(
+                    log,
+                    "Dropping a static config Prometheus would reject: {}", e
+                );
+                false
+            }
+        })
+        .collect()
 }
@@ -0,0 +1,77 @@
+use std::sync::Arc;
+
+use serde::Deserialize;
+use tokio::sync::Mutex;
+use url::Url;
+use warp::http::StatusCode;
+use warp::hyper::body::Bytes;
+use warp::reply::{Reply, Response};
+
+use crate::definition::Definition;
+use crate::scrape_configs::{scrape_configs, ScrapeIntervals};
+use crate::server_handlers::WebResult;
+
+pub struct ExportScrapeConfigsBinding {
+    pub definitions: Arc<Mutex<Vec<Definition>>>,
+    pub intervals: ScrapeIntervals,
+    /// The URL Prometheus reaches the service at, if it differs from the one
+    /// the configs are requested on.
+    pub public_url: Option<Url>,
+    pub tls: bool,
+}
+
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum ScrapeConfigsFormat {
+    #[default]
+    Yaml,
+    Json,
+}
+
+#[derive(Deserialize)]
+pub struct ScrapeConfigsQuery {
+    #[serde(default)]
+    format: ScrapeConfigsFormat,
+}
+
+/// Serve the Prometheus `scrape_configs` for the targets of all definitions,
+/// discovered on `/prom/targets` of this service.
+pub async fn export_scrape_configs(
+    host: Option<String>,
+    query: ScrapeConfigsQuery,
+    binding: ExportScrapeConfigsBinding,
+) -> WebResult<Response> {
+    let base_url = match (&binding.public_url, host) {
+        (Some(public_url), _) => public_url.as_str().trim_end_matches('/').to_string(),
+        (None, Some(host)) => {
+            let scheme = if binding.tls { "https" } else { "http" };
+            format!("{}://{}", scheme, host)
+        }
+        (None, None) => {
+            return Ok(warp::reply::with_status(
+                "Cannot tell the URL of the service without a Host header or --public-url"
+                    .to_string(),
+                StatusCode::BAD_REQUEST,
+            )
+            .into_response())
+        }
+    };
+
+    let definitions = binding.definitions.lock().await;
+    let document = scrape_configs(
+        definitions
+            .iter()
+            .map(|d| (d.name.as_str(), &d.scrape_options)),
+        &format!("{}/prom/targets", base_url),
+        &binding.intervals,
+    );
+
+    Ok(match query.format {
+        ScrapeConfigsFormat::Yaml => warp::http::Response::builder()
+            .header("content-type", "application/yaml")
+            .body(Bytes::from(serde_yaml::to_string(&document).unwrap()))
+            .unwrap()
+            .into_response(),
+        ScrapeConfigsFormat::Json => warp::reply::json(&document).into_response(),
+    })
+}
@@ -8,20 +8,52 @@ use service_discovery::{
 use slog::Logger;
 use warp::reply::Reply;
 
+use crate::dedup::deduplicate_definition_targets;
 use crate::definition::Definition;
+use crate::global_labels::add_global_labels;
+use crate::metrics::Metrics;
 
-use super::WebResult;
+use super::serialized_cache::{targets_fingerprint, SerializedCache};
+use super::{text_reply, WebResult};
 use multiservice_discovery_shared::contracts::target::{map_to_target_dto, TargetDto};
 use tokio::sync::Mutex;
 
 pub struct ExportTargetsBinding {
     pub definitions: Arc<Mutex<Vec<Definition>>>,
     pub log: Logger,
+    pub cache: SerializedCache,
+    pub metrics: Metrics,
+    /// Drop the targets already exported by another definition.
+    pub deduplicate: bool,
+    pub global_labels: BTreeMap<String, String>,
 }
 
 pub async fn export_targets(binding: ExportTargetsBinding) -> WebResult<impl Reply> {
     let definitions = binding.definitions.lock().await;
 
+    let body = binding
+        .cache
+        .get_or_build(targets_fingerprint(&definitions), || {
+            targets(
+                &definitions,
+                &binding.log,
+                &binding.metrics,
+                binding.deduplicate,
+                &binding.global_labels,
+            )
+        })
+        .await;
+
+    Ok(text_reply(body, warp::http::StatusCode::OK))
+}
+
+fn targets(
+    definitions: &[Definition],
+    log: &Logger,
+    metrics: &Metrics,
+    deduplicate: bool,
+    global_labels: &BTreeMap<String, String>,
+) -> Vec<TargetDto> {
     let all_jobs = [
         JobType::Replica,
         JobType::Orchestrator,
@@ -34,10 +66,7 @@ pub async fn export_targets(binding: ExportTargetsBinding) -> WebResult<impl Rep
 
     for def in definitions.iter() {
         for job_type in all_jobs {
-            let targets = match def
-                .ic_discovery
-                .get_target_groups(job_type, binding.log.clone())
-            {
+            let targets = match def.ic_discovery.get_target_groups(job_type, log.clone()) {
                 Ok(targets) => targets,
                 Err(_) => continue,
             };
@@ -45,7 +74,7 @@ pub async fn export_targets(binding: ExportTargetsBinding) -> WebResult<impl Rep
             targets.iter().for_each(|target_group| {
                 if let Some(target) = total_targets
                     .iter_mut()
-                    .find(|t| t.node_id == target_group.node_id)
+                    .find(|t| t.node_id == target_group.node_id && t.ic_name == def.name)
                 {
                     target.jobs.push(job_type);
                 } else {
@@ -59,7 +88,13 @@ pub async fn export_targets(binding: ExportTargetsBinding) -> WebResult<impl Rep
                 }
             });
         }
+    }
 
+    if deduplicate {
+        total_targets = deduplicate_definition_targets(total_targets, definitions, metrics);
+    }
+
+    for def in definitions.iter() {
         def.boundary_nodes.iter().for_each(|bn| {
             if bn
                 .custom_labels
@@ -80,14 +115,11 @@ pub async fn export_targets(binding: ExportTargetsBinding) -> WebResult<impl Rep
                 node_provider_id: PrincipalId::new_anonymous(),
                 operator_id: PrincipalId::new_anonymous(),
                 subnet_id: None,
+                public_ipv4: None,
             });
         });
     }
 
-    let prom_config = serde_json::to_string_pretty(&total_targets).unwrap();
-
-    Ok(warp::reply::with_status(
-        prom_config,
-        warp::http::StatusCode::OK,
-    ))
+    add_global_labels(&mut total_targets, global_labels);
+    total_targets
 }
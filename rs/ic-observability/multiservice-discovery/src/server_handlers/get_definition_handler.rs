@@ -5,7 +5,7 @@ use warp::reply::json;
 use warp::Reply;
 
 use crate::definition::Definition;
-use crate::server_handlers::dto::DefinitionDto;
+use crate::server_handlers::dto::ListedDefinitionDto;
 use crate::server_handlers::WebResult;
 
 pub async fn get_definitions(definitions: Arc<Mutex<Vec<Definition>>>) -> WebResult<impl Reply> {
@@ -15,6 +15,6 @@ pub async fn get_definitions(definitions: Arc<Mutex<Vec<Definition>>>) -> WebRes
         &definitions
             .iter()
             .map(|d| d.into())
-            .collect::<Vec<DefinitionDto>>(),
+            .collect::<Vec<ListedDefinitionDto>>(),
     ))
 }
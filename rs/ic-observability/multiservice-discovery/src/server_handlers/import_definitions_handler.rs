@@ -0,0 +1,484 @@
+use std::collections::BTreeSet;
+use std::str::FromStr;
+use std::thread::JoinHandle;
+
+use futures_util::future::join_all;
+use serde::{Deserialize, Serialize};
+use service_discovery::job_types::JobType;
+use tokio::sync::Mutex;
+use warp::reply::json;
+use warp::Reply;
+
+use crate::definition::{
+    name_reserved, stop_definition_within, BoundaryNode, Definition, Verification,
+    VerificationPolicy,
+};
+use crate::registry_source::RegistrySource;
+use crate::server_handlers::add_definition_handler::{
+    check_overlap, decode_verification, start_definition, validate_definition,
+    validate_replacement, AddDefinitionBinding,
+};
+use crate::server_handlers::delete_definition_handler::STOP_TIMEOUT;
+use crate::server_handlers::dto::{
+    DefinitionsDocumentDto, ExportedDefinitionDto, ImportResultDto, ImportStatus,
+};
+use crate::server_handlers::WebResult;
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportMode {
+    /// Add the imported definitions next to the running ones.
+    #[default]
+    Merge,
+    /// Make the imported definitions the only running ones.
+    Replace,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ImportQuery {
+    #[serde(default)]
+    pub mode: ImportMode,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ImportResponseDto {
+    pub applied: bool,
+    pub results: Vec<ImportResultDto>,
+}
+
+/// A validated entry of the document, ready to be started.
+struct NewDefinition {
+    entry: ExportedDefinitionDto,
+    verification: Verification,
+    boundary_nodes: Vec<BoundaryNode>,
+}
+
+enum PlannedImport {
+    Add(NewDefinition),
+    /// Replace the running definition with the same name.
+    Replace(NewDefinition),
+    Unchanged,
+}
+
+/// Import a document produced by `GET /definitions/export`. Every entry is
+/// validated before any of them is applied, so either the whole document is
+/// imported or nothing changes.
+pub async fn import_definitions(
+    query: ImportQuery,
+    document: DefinitionsDocumentDto,
+    binding: AddDefinitionBinding,
+) -> WebResult<impl Reply> {
+    let response = apply_import(query.mode, document, &binding).await;
+    let status = if response.applied {
+        warp::http::StatusCode::OK
+    } else {
+        warp::http::StatusCode::BAD_REQUEST
+    };
+    Ok(warp::reply::with_status(json(&response), status))
+}
+
+/// Import `document` into the running definitions, as `POST
+/// /definitions/import` and `--definitions-file` do.
+pub async fn apply_import(
+    mode: ImportMode,
+    document: DefinitionsDocumentDto,
+    binding: &AddDefinitionBinding,
+) -> ImportResponseDto {
+    let mut definitions = binding.definitions.lock().await;
+
+    let mut results = vec![];
+    let mut planned = vec![];
+    let mut seen_names = BTreeSet::new();
+    for entry in document.definitions {
+        let name = entry.definition.name.clone();
+        match plan_import(
+            entry,
+            mode,
+            &definitions,
+            &seen_names,
+            &binding.handles,
+            &*binding.registry_source,
+        )
+        .await
+        {
+            Ok(plan) => {
+                let status = match plan {
+                    PlannedImport::Add(_) => ImportStatus::Added,
+                    PlannedImport::Replace(_) => ImportStatus::Replaced,
+                    PlannedImport::Unchanged => ImportStatus::Unchanged,
+                };
+                results.push(ImportResultDto {
+                    name: name.clone(),
+                    status,
+                    error: None,
+                });
+                planned.push(plan);
+            }
+            Err(e) => results.push(ImportResultDto {
+                name: name.clone(),
+                status: ImportStatus::Invalid,
+                error: Some(e),
+            }),
+        }
+        seen_names.insert(name);
+    }
+
+    if results.iter().any(|r| r.status == ImportStatus::Invalid) {
+        return ImportResponseDto {
+            applied: false,
+            results,
+        };
+    }
+
+    if mode == ImportMode::Replace {
+        let replaced = results
+            .iter()
+            .filter(|r| r.status == ImportStatus::Replaced)
+            .map(|r| r.name.clone())
+            .collect::<BTreeSet<_>>();
+        let (kept, stopping): (Vec<_>, Vec<_>) = definitions
+            .drain(..)
+            .partition(|d| seen_names.contains(&d.name) && !replaced.contains(&d.name));
+        *definitions = kept;
+        binding.definitions_file.save(&definitions);
+
+        // As for a deletion, the lock is released while the definitions are
+        // stopped. Their threads keep the names reserved until then.
+        drop(definitions);
+        let stopped = join_all(stopping.into_iter().map(|definition| async {
+            let name = definition.name.clone();
+            let result = stop_definition_within(definition, &binding.handles, STOP_TIMEOUT).await;
+            (name, result)
+        }))
+        .await;
+        for (name, result) in stopped {
+            if !replaced.contains(&name) {
+                results.push(ImportResultDto {
+                    name,
+                    status: ImportStatus::Removed,
+                    error: result.err(),
+                });
+            } else if let Err(e) = result {
+                fail(
+                    &mut results,
+                    &name,
+                    format!("{}, it is not started again", e),
+                );
+            }
+        }
+        definitions = binding.definitions.lock().await;
+    }
+
+    for plan in planned {
+        let new = match plan {
+            PlannedImport::Add(new) | PlannedImport::Replace(new) => new,
+            PlannedImport::Unchanged => continue,
+        };
+        let name = new.entry.definition.name.clone();
+        if results
+            .iter()
+            .any(|r| r.name == name && r.status == ImportStatus::Failed)
+        {
+            continue;
+        }
+        // The lock was released while replaced definitions were stopped.
+        if definitions.iter().any(|d| d.name == name)
+            || name_reserved(&name, &binding.handles).await
+        {
+            fail(
+                &mut results,
+                &name,
+                "A definition with this name was added during the import".to_string(),
+            );
+            continue;
+        }
+        let mut definition = start_definition(
+            new.entry.definition,
+            new.verification,
+            new.entry.poll_interval,
+            new.entry.registry_query_timeout,
+            binding,
+        )
+        .await;
+        for boundary_node in new.boundary_nodes {
+            definition.add_boundary_node(boundary_node);
+        }
+        definitions.push(definition);
+    }
+    binding.definitions_file.save(&definitions);
+
+    ImportResponseDto {
+        applied: true,
+        results,
+    }
+}
+
+/// Mark the result of the definition `name` as failed with `error`.
+fn fail(results: &mut [ImportResultDto], name: &str, error: String) {
+    if let Some(result) = results.iter_mut().find(|r| r.name == name) {
+        result.status = ImportStatus::Failed;
+        result.error = Some(error);
+    }
+}
+
+async fn plan_import(
+    entry: ExportedDefinitionDto,
+    mode: ImportMode,
+    definitions: &[Definition],
+    seen_names: &BTreeSet<String>,
+    handles: &Mutex<Vec<JoinHandle<()>>>,
+    registry_source: &dyn RegistrySource,
+) -> Result<PlannedImport, String> {
+    if seen_names.contains(&entry.definition.name) {
+        return Err("Definition with this name appears more than once in the document".to_string());
+    }
+
+    let boundary_nodes = entry
+        .boundary_nodes
+        .iter()
+        .map(|bn| {
+            JobType::from_str(&bn.job_type)
+                .map(|job_type| BoundaryNode {
+                    name: bn.name.clone(),
+                    targets: bn.targets.clone(),
+                    custom_labels: bn.custom_labels.clone(),
+                    job_type,
+                    api_boundary: bn.api_boundary,
+                })
+                .map_err(|e| format!("Job type {} is not supported: {}", bn.job_type, e))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // In replace mode a running definition with the same name is kept if it
+    // is the same definition, and stopped and started again otherwise. It
+    // cannot be changed in place since its registry directory is removed
+    // when it stops.
+    if mode == ImportMode::Replace {
+        if let Some(running) = definitions.iter().find(|d| d.name == entry.definition.name) {
+            if same_definition(running, &entry)? {
+                return Ok(PlannedImport::Unchanged);
+            }
+            let verification = validate_replacement(&entry.definition, registry_source).await?;
+            return Ok(PlannedImport::Replace(NewDefinition {
+                entry,
+                verification,
+                boundary_nodes,
+            }));
+        }
+    }
+
+    let verification =
+        validate_definition(&entry.definition, definitions, handles, registry_source).await?;
+    // Definitions a replace removes do not conflict with the imported ones.
+    if mode == ImportMode::Merge {
+        check_overlap(&entry.definition, definitions)?;
+    }
+
+    Ok(PlannedImport::Add(NewDefinition {
+        entry,
+        verification,
+        boundary_nodes,
+    }))
+}
+
+/// Whether the entry describes the running definition, including its
+/// options, boundary nodes and whether it is paused.
+fn same_definition(running: &Definition, entry: &ExportedDefinitionDto) -> Result<bool, String> {
+    let verification = decode_verification(&entry.definition)?;
+    let running_verification = running.verification();
+    // A key the running definition pinned since it was exported is no
+    // difference.
+    let same_verification = running_verification.policy == verification.policy
+        && (running_verification.public_key == verification.public_key
+            || verification.policy == VerificationPolicy::Fetch
+                && verification.public_key.is_none());
+
+    // The verification is compared above, everything else must be equal.
+    let without_verification = |exported: &ExportedDefinitionDto| {
+        let mut exported = exported.clone();
+        exported.definition.public_key = None;
+        exported.definition.verify = None;
+        exported
+    };
+    Ok(same_verification
+        && without_verification(&ExportedDefinitionDto::from(running))
+            == without_verification(entry))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use slog::{o, Logger};
+    use tokio::sync::Mutex;
+    use url::Url;
+
+    use crate::definitions_file::DefinitionsFile;
+    use crate::jitter::Jitter;
+    use crate::metrics::Metrics;
+    use crate::registry_source::MockRegistrySource;
+    use crate::server_handlers::dto::DefinitionDto;
+
+    use super::*;
+
+    fn binding(
+        dir: &tempfile::TempDir,
+        definitions: Arc<Mutex<Vec<Definition>>>,
+    ) -> AddDefinitionBinding {
+        AddDefinitionBinding {
+            definitions,
+            log: Logger::root(slog::Discard, o!()),
+            registry_path: dir.path().to_path_buf(),
+            poll_interval: Duration::from_secs(30),
+            registry_query_timeout: Duration::from_secs(5),
+            rt: tokio::runtime::Handle::current(),
+            handles: Arc::new(Mutex::new(vec![])),
+            jitter: Jitter::none(),
+            registry_source: Arc::new(MockRegistrySource::default()),
+            metrics: Metrics::new(),
+            definitions_file: DefinitionsFile::none(Logger::root(slog::Discard, o!())),
+        }
+    }
+
+    fn entry(name: &str, public_key: Option<&str>) -> ExportedDefinitionDto {
+        ExportedDefinitionDto {
+            definition: DefinitionDto {
+                nns_urls: vec![Url::parse("https://ic0.app").unwrap()],
+                name: name.to_string(),
+                public_key: public_key.map(|pk| pk.to_string()),
+                verify: None,
+                scrape_options: Default::default(),
+                allow_duplicate_targets: false,
+                allow_overlap: false,
+                paused: false,
+                port_overrides: Default::default(),
+            },
+            poll_interval: Duration::from_secs(30),
+            registry_query_timeout: Duration::from_secs(5),
+            boundary_nodes: vec![],
+        }
+    }
+
+    fn running_definition(dir: &tempfile::TempDir, name: &str) -> Definition {
+        let (stop_signal_sender, stop_signal_rcv) = crossbeam::channel::bounded::<()>(1);
+        Definition::new(
+            vec![Url::parse("https://ic0.app").unwrap()],
+            dir.path().to_path_buf(),
+            name.to_string(),
+            Logger::root(slog::Discard, o!()),
+            Verification::default(),
+            Duration::from_secs(30),
+            stop_signal_rcv,
+            Duration::from_secs(5),
+            stop_signal_sender,
+            Jitter::none(),
+        )
+    }
+
+    async fn body<R: Reply>(reply: R) -> (warp::http::StatusCode, Vec<u8>) {
+        let response = reply.into_response();
+        let status = response.status();
+        let body = warp::hyper::body::to_bytes(response.into_body())
+            .await
+            .unwrap();
+        (status, body.to_vec())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn invalid_entry_aborts_the_whole_import() {
+        let dir = tempfile::tempdir().unwrap();
+        let definitions = Arc::new(Mutex::new(vec![
+            running_definition(&dir, "kept"),
+            running_definition(&dir, "other"),
+        ]));
+
+        let document = DefinitionsDocumentDto {
+            definitions: vec![entry("kept", None), entry("broken", Some("not base64!"))],
+        };
+        let reply = import_definitions(
+            ImportQuery {
+                mode: ImportMode::Replace,
+            },
+            document,
+            binding(&dir, definitions.clone()),
+        )
+        .await
+        .unwrap();
+
+        let (status, body) = body(reply).await;
+        let response: ImportResponseDto = serde_json::from_slice(&body).unwrap();
+        assert_eq!(status, warp::http::StatusCode::BAD_REQUEST);
+        assert!(!response.applied);
+        assert_eq!(
+            response
+                .results
+                .iter()
+                .map(|r| r.status.clone())
+                .collect::<Vec<_>>(),
+            vec![ImportStatus::Unchanged, ImportStatus::Invalid]
+        );
+        // "other" would have been removed by a successful replace.
+        assert_eq!(
+            definitions
+                .lock()
+                .await
+                .iter()
+                .map(|d| d.name.clone())
+                .collect::<Vec<_>>(),
+            vec!["kept", "other"]
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn replace_restarts_changed_definitions() {
+        let dir = tempfile::tempdir().unwrap();
+        let definitions = Arc::new(Mutex::new(vec![
+            running_definition(&dir, "same"),
+            running_definition(&dir, "changed"),
+            running_definition(&dir, "gone"),
+        ]));
+        let mut changed = entry("changed", None);
+        changed.poll_interval = Duration::from_secs(60);
+        changed.definition.paused = true;
+
+        let binding = binding(&dir, definitions.clone());
+        let handles = binding.handles.clone();
+        let response = apply_import(
+            ImportMode::Replace,
+            DefinitionsDocumentDto {
+                definitions: vec![entry("same", None), changed],
+            },
+            &binding,
+        )
+        .await;
+
+        assert!(response.applied);
+        assert_eq!(
+            response
+                .results
+                .iter()
+                .map(|r| (r.name.as_str(), r.status.clone()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("same", ImportStatus::Unchanged),
+                ("changed", ImportStatus::Replaced),
+                ("gone", ImportStatus::Removed),
+            ]
+        );
+        let running = definitions.lock().await.drain(..).collect::<Vec<_>>();
+        assert_eq!(
+            running.iter().map(|d| d.name.as_str()).collect::<Vec<_>>(),
+            vec!["same", "changed"]
+        );
+        assert_eq!(running[1].poll_interval, Duration::from_secs(60));
+        assert!(running[1].is_paused());
+        assert!(!dir.path().join("gone").exists());
+
+        for definition in running {
+            stop_definition_within(definition, &handles, Duration::from_secs(5))
+                .await
+                .unwrap();
+        }
+    }
+}
@@ -0,0 +1,237 @@
+use std::time::Instant;
+
+use warp::body::BodyDeserializeError;
+use warp::http::{Method, StatusCode};
+use warp::path::FullPath;
+use warp::reject::{
+    InvalidHeader, InvalidQuery, LengthRequired, MethodNotAllowed, MissingHeader, PayloadTooLarge,
+    UnsupportedMediaType,
+};
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use crate::metrics::Metrics;
+
+/// Record the request count, latency and in-flight requests of `routes` in
+/// `metrics`, including the requests none of the routes accepted.
+pub fn instrument<F, R>(
+    routes: F,
+    metrics: Metrics,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone
+where
+    F: Filter<Extract = (R,), Error = Rejection> + Clone + Send + Sync + 'static,
+    R: Reply,
+{
+    let handled = routes
+        .map(|reply: R| Ok::<_, Rejection>(reply.into_response()))
+        .or_else(|rejection| async move { Ok::<_, Rejection>((Err(rejection),)) });
+
+    warp::method()
+        .and(warp::path::full())
+        .map(move |method: Method, path: FullPath| {
+            RequestTimer::start(&metrics, route_template(path.as_str()), method)
+        })
+        .and(handled)
+        .and_then(
+            |timer: RequestTimer, result: Result<Response, Rejection>| async move {
+                timer.finish(match &result {
+                    Ok(response) => response.status(),
+                    Err(rejection) => rejection_status(rejection),
+                });
+                result
+            },
+        )
+}
+
+/// The route a path is served by, with the definition name as a placeholder,
+/// so that the number of label values stays bounded.
+fn route_template(path: &str) -> &'static str {
+    let segments = path.trim_matches('/').split('/').collect::<Vec<_>>();
+    match segments.as_slice() {
+        [""] => "/",
+        ["targets"] => "/targets",
+        ["prom", "targets"] => "/prom/targets",
+        ["prom", "targets", "watch"] => "/prom/targets/watch",
+        ["prom", "scrape_configs"] => "/prom/scrape_configs",
+        ["log_targets"] => "/log_targets",
+        ["metrics"] => "/metrics",
+        ["log_level"] => "/log_level",
+        ["add_boundary_node"] => "/add_boundary_node",
+        ["definitions"] => "/definitions",
+        ["definitions", "all"] => "/definitions/all",
+        ["definitions", "export"] => "/definitions/export",
+        ["definitions", "import"] => "/definitions/import",
+        ["definitions", _, "pause"] => "/definitions/{name}/pause",
+        ["definitions", _, "resume"] => "/definitions/{name}/resume",
+        ["definitions", _, "sync"] => "/definitions/{name}/sync",
+        ["definitions", _, "verify"] => "/definitions/{name}/verify",
+        [_] => "/{name}",
+        _ => "other",
+    }
+}
+
+/// The status warp replies with for a rejection that was not recovered.
+fn rejection_status(rejection: &Rejection) -> StatusCode {
+    if rejection.is_not_found() {
+        StatusCode::NOT_FOUND
+    } else if rejection.find::<MethodNotAllowed>().is_some() {
+        StatusCode::METHOD_NOT_ALLOWED
+    } else if rejection.find::<BodyDeserializeError>().is_some()
+        || rejection.find::<InvalidQuery>().is_some()
+        || rejection.find::<InvalidHeader>().is_some()
+        || rejection.find::<MissingHeader>().is_some()
+    {
+        StatusCode::BAD_REQUEST
+    } else if rejection.find::<LengthRequired>().is_some() {
+        StatusCode::LENGTH_REQUIRED
+    } else if rejection.find::<PayloadTooLarge>().is_some() {
+        StatusCode::PAYLOAD_TOO_LARGE
+    } else if rejection.find::<UnsupportedMediaType>().is_some() {
+        StatusCode::UNSUPPORTED_MEDIA_TYPE
+    } else {
+        StatusCode::INTERNAL_SERVER_ERROR
+    }
+}
+
+/// Counts a request as in flight until finished or dropped, e.g. because
+/// the client went away.
+struct RequestTimer {
+    metrics: Metrics,
+    route: &'static str,
+    method: Method,
+    start: Instant,
+}
+
+impl RequestTimer {
+    fn start(metrics: &Metrics, route: &'static str, method: Method) -> Self {
+        metrics
+            .http_requests_in_flight
+            .with_label_values(&[route, method.as_str()])
+            .inc();
+        Self {
+            metrics: metrics.clone(),
+            route,
+            method,
+            start: Instant::now(),
+        }
+    }
+
+    fn finish(self, status: StatusCode) {
+        let labels = [self.route, self.method.as_str()];
+        self.metrics
+            .http_request_duration
+            .with_label_values(&labels)
+            .observe(self.start.elapsed().as_secs_f64());
+        let status_class = format!("{}xx", status.as_u16() / 100);
+        self.metrics
+            .http_requests
+            .with_label_values(&[self.route, self.method.as_str(), &status_class])
+            .inc();
+    }
+}
+
+impl Drop for RequestTimer {
+    fn drop(&mut self) {
+        self.metrics
+            .http_requests_in_flight
+            .with_label_values(&[self.route, self.method.as_str()])
+            .dec();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn routes() -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        let pause = warp::path!("definitions" / String / "pause")
+            .and(warp::post())
+            .map(|name: String| {
+                let status = if name == "missing" {
+                    StatusCode::BAD_REQUEST
+                } else {
+                    StatusCode::OK
+                };
+                warp::reply::with_status(name, status)
+            });
+        let targets = warp::path!("targets").and(warp::get()).map(|| "[]");
+        pause.or(targets)
+    }
+
+    fn requests(metrics: &Metrics, route: &str, method: &str, status: &str) -> u64 {
+        metrics
+            .http_requests
+            .with_label_values(&[route, method, status])
+            .get()
+    }
+
+    #[tokio::test]
+    async fn requests_are_counted_by_route_template_and_status_class() {
+        let metrics = Metrics::new();
+        let filter = instrument(routes(), metrics.clone());
+        let request = |method: &str, path: &str| {
+            warp::test::request()
+                .method(method)
+                .path(path)
+                .reply(&filter)
+        };
+
+        assert_eq!(request("GET", "/targets").await.status(), StatusCode::OK);
+        assert_eq!(
+            request("POST", "/definitions/a/pause").await.status(),
+            StatusCode::OK
+        );
+        assert_eq!(
+            request("POST", "/definitions/b/pause").await.status(),
+            StatusCode::OK
+        );
+        assert_eq!(
+            request("POST", "/definitions/missing/pause").await.status(),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            request("GET", "/no/such/route").await.status(),
+            StatusCode::NOT_FOUND
+        );
+
+        assert_eq!(requests(&metrics, "/targets", "GET", "2xx"), 1);
+        assert_eq!(
+            requests(&metrics, "/definitions/{name}/pause", "POST", "2xx"),
+            2
+        );
+        assert_eq!(
+            requests(&metrics, "/definitions/{name}/pause", "POST", "4xx"),
+            1
+        );
+        assert_eq!(requests(&metrics, "other", "GET", "4xx"), 1);
+        assert_eq!(
+            metrics
+                .http_request_duration
+                .with_label_values(&["/definitions/{name}/pause", "POST"])
+                .get_sample_count(),
+            3
+        );
+        assert_eq!(
+            metrics
+                .http_requests_in_flight
+                .with_label_values(&["/definitions/{name}/pause", "POST"])
+                .get(),
+            0
+        );
+        assert!(metrics.encode().contains(
+            "msd_http_requests_total{method=\"GET\",route=\"/targets\",status=\"2xx\"} 1"
+        ));
+    }
+
+    #[test]
+    fn paths_map_to_bounded_route_templates() {
+        assert_eq!(route_template("/"), "/");
+        assert_eq!(route_template("/mercury"), "/{name}");
+        assert_eq!(route_template("/prom/targets"), "/prom/targets");
+        assert_eq!(
+            route_template("/definitions/mercury/sync"),
+            "/definitions/{name}/sync"
+        );
+        assert_eq!(route_template("/definitions/mercury/other"), "other");
+    }
+}
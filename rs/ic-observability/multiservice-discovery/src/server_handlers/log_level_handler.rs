@@ -0,0 +1,56 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use slog::{info, Logger};
+use warp::hyper::body::Bytes;
+use warp::Reply;
+
+use crate::log_level::{LevelSpec, LogLevels};
+use crate::server_handlers::WebResult;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LogLevelDto {
+    pub level: String,
+    pub modules: BTreeMap<String, String>,
+}
+
+impl From<LevelSpec> for LogLevelDto {
+    fn from(value: LevelSpec) -> Self {
+        Self {
+            level: value.default.as_str().to_lowercase(),
+            modules: value
+                .modules
+                .into_iter()
+                .map(|(module, level)| (module, level.as_str().to_lowercase()))
+                .collect(),
+        }
+    }
+}
+
+pub struct LogLevelBinding {
+    pub levels: LogLevels,
+    pub log: Logger,
+}
+
+pub async fn get_log_level(binding: LogLevelBinding) -> WebResult<impl Reply> {
+    Ok(warp::reply::json(&LogLevelDto::from(binding.levels.get())))
+}
+
+/// Update the log levels with a spec like `debug` or
+/// `info,service_discovery=debug` sent as the request body.
+pub async fn put_log_level(spec: Bytes, binding: LogLevelBinding) -> WebResult<impl Reply> {
+    let spec = String::from_utf8_lossy(&spec);
+    match binding.levels.update(&spec) {
+        Ok(updated) => {
+            info!(binding.log, "Log level updated to {:?}", updated);
+            Ok(warp::reply::with_status(
+                warp::reply::json(&LogLevelDto::from(updated)),
+                warp::http::StatusCode::OK,
+            ))
+        }
+        Err(e) => Ok(warp::reply::with_status(
+            warp::reply::json(&e),
+            warp::http::StatusCode::BAD_REQUEST,
+        )),
+    }
+}
@@ -0,0 +1,80 @@
+use warp::http::header::{HeaderValue, CONTENT_TYPE, VARY};
+use warp::Reply;
+
+use crate::metrics::{Metrics, OPENMETRICS_CONTENT_TYPE};
+
+use super::WebResult;
+
+const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+pub struct MetricsBinding {
+    pub metrics: Metrics,
+}
+
+/// Serve the metrics in the OpenMetrics format to the clients asking for it
+/// in their `Accept` header, and in the Prometheus text format otherwise.
+pub async fn get_metrics(accept: Option<String>, binding: MetricsBinding) -> WebResult<impl Reply> {
+    let (body, content_type) = if accepts_openmetrics(accept.as_deref()) {
+        (
+            binding.metrics.encode_openmetrics(),
+            OPENMETRICS_CONTENT_TYPE,
+        )
+    } else {
+        (binding.metrics.encode(), PROMETHEUS_CONTENT_TYPE)
+    };
+    let mut reply = body.into_response();
+    reply
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+    reply
+        .headers_mut()
+        .insert(VARY, HeaderValue::from_static("accept"));
+    Ok(reply)
+}
+
+/// Whether an `Accept` header lists `application/openmetrics-text` without
+/// `q=0`, as Prometheus does when it negotiates the format.
+fn accepts_openmetrics(accept: Option<&str>) -> bool {
+    let accept = match accept {
+        Some(accept) => accept,
+        None => return false,
+    };
+    accept.split(',').any(|media_range| {
+        let mut params = media_range.split(';').map(str::trim);
+        let media_type = params.next().unwrap_or_default();
+        let quality = params
+            .find_map(|p| p.strip_prefix("q="))
+            .map_or(Ok(1.0), str::parse::<f64>)
+            .unwrap_or(0.0);
+        media_type.eq_ignore_ascii_case("application/openmetrics-text") && quality > 0.0
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn openmetrics_is_served_when_accepted() {
+        for header in [
+            "application/openmetrics-text",
+            "application/openmetrics-text;version=1.0.0;q=0.5,text/plain;version=0.0.4;q=0.3,*/*;q=0.2",
+            "text/plain, Application/OpenMetrics-Text; version=0.0.1",
+        ] {
+            assert!(accepts_openmetrics(Some(header)), "{} should accept OpenMetrics", header);
+        }
+        for header in [
+            "",
+            "text/plain;version=0.0.4",
+            "*/*",
+            "application/openmetrics-text;q=0",
+        ] {
+            assert!(
+                !accepts_openmetrics(Some(header)),
+                "{} should not accept OpenMetrics",
+                header
+            );
+        }
+        assert!(!accepts_openmetrics(None));
+    }
+}
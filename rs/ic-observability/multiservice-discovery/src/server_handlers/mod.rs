@@ -1,57 +1,288 @@
+use std::net::SocketAddr;
 use std::sync::Arc;
 use std::thread::JoinHandle;
+use std::time::Duration;
 
-use slog::{info, Logger};
+use multiservice_discovery_shared::builders::log_target_builder::LogTargetBuilder;
+use slog::{debug, info, warn, Logger};
+use tokio::net::TcpListener;
+use tokio::signal::unix::{signal, SignalKind};
 use tokio::sync::Mutex;
-use warp::{Filter, Rejection};
+use tokio_rustls::TlsAcceptor;
+use warp::http::StatusCode;
+use warp::hyper::body::Bytes;
+use warp::{Filter, Rejection, Reply};
 
 use crate::definition::Definition;
+use crate::file_sd::{run_file_sd_writer, FileSdWriter};
+use crate::jitter::Jitter;
+use crate::server::ServerConfig;
 use crate::server_handlers::add_boundary_node_to_definition_handler::add_boundary_node;
 use crate::server_handlers::add_boundary_node_to_definition_handler::AddBoundaryNodeToDefinitionBinding;
-use crate::server_handlers::add_definition_handler::{add_definition, AddDefinitionBinding};
-use crate::server_handlers::delete_definition_handler::delete_definition;
+use crate::server_handlers::add_definition_handler::add_definition;
+use crate::server_handlers::auth::{handle_unauthorized, with_auth};
+use crate::server_handlers::delete_definition_handler::{
+    delete_all_definitions, delete_definition, delete_definitions, DeleteAllDefinitionsQuery,
+    DeleteDefinitionBinding, DeleteDefinitionsBinding, DeleteDefinitionsQuery, STOP_TIMEOUT,
+};
+use crate::server_handlers::export_definitions_handler::{export_definitions, ExportQuery};
+use crate::server_handlers::export_log_targets_handler::{
+    export_log_targets, ExportLogTargetsBinding,
+};
 use crate::server_handlers::export_prometheus_config_handler::{
-    export_prometheus_config, ExportDefinitionConfigBinding,
+    export_prometheus_config, prometheus_configs, ExportDefinitionConfigBinding,
+};
+use crate::server_handlers::export_scrape_configs_handler::{
+    export_scrape_configs, ExportScrapeConfigsBinding, ScrapeConfigsQuery,
 };
 use crate::server_handlers::export_targets_handler::export_targets;
 use crate::server_handlers::export_targets_handler::ExportTargetsBinding;
 use crate::server_handlers::get_definition_handler::get_definitions;
-use crate::CliArgs;
+use crate::server_handlers::import_definitions_handler::{import_definitions, ImportQuery};
+use crate::server_handlers::instrumentation::instrument;
+use crate::server_handlers::log_level_handler::{get_log_level, put_log_level, LogLevelBinding};
+use crate::server_handlers::metrics_handler::{get_metrics, MetricsBinding};
+use crate::server_handlers::pause_definition_handler::{
+    pause_definition, resume_definition, PauseDefinitionBinding,
+};
+use crate::server_handlers::rate_limit::{handle_rate_limited, with_rate_limit, RateLimiter};
+use crate::server_handlers::serialized_cache::SerializedCache;
+use crate::server_handlers::sync_definition_handler::{sync_definition, SyncDefinitionBinding};
+use crate::server_handlers::update_verification_handler::{
+    update_verification, UpdateVerificationBinding,
+};
+use crate::server_handlers::watch_targets_handler::{
+    run_targets_watch, watch_targets, WatchQuery, WatchTargetsBinding, WATCH_INTERVAL,
+};
+use crate::tls::{server_config, CertResolver, TlsPaths};
 
 mod add_boundary_node_to_definition_handler;
 mod add_definition_handler;
+mod auth;
+mod compression;
 mod delete_definition_handler;
 pub mod dto;
+mod export_definitions_handler;
+mod export_log_targets_handler;
 mod export_prometheus_config_handler;
+mod export_scrape_configs_handler;
 mod export_targets_handler;
 mod get_definition_handler;
+mod import_definitions_handler;
+mod instrumentation;
+mod log_level_handler;
+mod metrics_handler;
+mod pause_definition_handler;
+mod rate_limit;
+mod serialized_cache;
+mod sync_definition_handler;
+mod update_verification_handler;
+mod watch_targets_handler;
+
+pub use add_definition_handler::AddDefinitionBinding;
+pub use import_definitions_handler::{apply_import, ImportMode};
+pub use serialized_cache::targets_fingerprint;
 
 pub type WebResult<T> = Result<T, Rejection>;
 
+/// Reply with an already serialized body, with the headers
+/// `warp::reply::with_status` sets for a `String`.
+fn text_reply(body: Bytes, status: StatusCode) -> warp::http::Response<Bytes> {
+    warp::http::Response::builder()
+        .status(status)
+        .header("content-type", "text/plain; charset=utf-8")
+        .body(body)
+        .unwrap()
+}
+
 pub async fn prepare_server(
     recv: tokio::sync::oneshot::Receiver<()>,
-    log: Logger,
+    config: ServerConfig,
     items: Arc<Mutex<Vec<Definition>>>,
-    cli: CliArgs,
     handles: Arc<Mutex<Vec<JoinHandle<()>>>>,
     rt: tokio::runtime::Handle,
 ) {
+    let ServerConfig {
+        cli,
+        log,
+        log_levels,
+        registry_source,
+        metrics,
+        definitions_file,
+        // Imported by `start_server` before the server is prepared.
+        definitions_document: _,
+        node_annotations,
+    } = config;
+    let listen_addr = cli.listen_addr;
+    let tls_paths = cli.tls_paths();
+    let jitter = Jitter::new(cli.poll_jitter, cli.poll_jitter_seed);
+    let transformers = cli.transformer_pipeline(node_annotations);
+    let deduplicate = !cli.allow_duplicate_targets;
+    let global_labels = cli.global_labels();
+    // Shared by all endpoints that add, change or delete definitions.
+    let rate_limiter = RateLimiter::per_minute(cli.mutation_rate_limit);
+
+    let file_sd = cli.file_sd_dir.clone().map(|dir| {
+        let log = log.clone();
+        let transformers = transformers.clone();
+        let writer = FileSdWriter::new(dir, metrics.clone());
+        let writer_metrics = metrics.clone();
+        tokio::spawn(run_file_sd_writer(
+            writer,
+            items.clone(),
+            cli.file_sd_debounce,
+            log.clone(),
+            move |definitions| {
+                prometheus_configs(
+                    definitions,
+                    &log,
+                    &transformers,
+                    &writer_metrics,
+                    deduplicate,
+                )
+            },
+        ))
+    });
+
+    // Shared by `/prom/targets` and the watch of its entity tag, so that a
+    // change is serialized once.
+    let export_prometheus_cache = SerializedCache::default();
+    let (etag_sender, etags) = tokio::sync::watch::channel(String::new());
+    let targets_watch = {
+        let log = log.clone();
+        let transformers = transformers.clone();
+        let metrics = metrics.clone();
+        tokio::spawn(run_targets_watch(
+            items.clone(),
+            export_prometheus_cache.clone(),
+            etag_sender,
+            WATCH_INTERVAL,
+            move |definitions| {
+                prometheus_configs(definitions, &log, &transformers, &metrics, deduplicate)
+            },
+        ))
+    };
+    // Stop the watch as soon as the shutdown starts, so that the requests
+    // waiting for a change are answered instead of holding up the graceful
+    // shutdown.
+    let (stop_sender, stop_receiver) = tokio::sync::oneshot::channel();
+    tokio::spawn(async move {
+        recv.await.ok();
+        targets_watch.abort();
+        stop_sender.send(()).ok();
+    });
+
     let add_items = items.clone();
     let add_log = log.clone();
+    let add_registry_path = cli.targets_dir.clone();
+    let add_handles = handles.clone();
+    let add_rt = rt.clone();
+    let add_jitter = jitter.clone();
+    let add_registry_source = registry_source.clone();
+    let add_metrics = metrics.clone();
+    let add_definitions_file = definitions_file.clone();
     let add = warp::path::end()
         .and(warp::post())
+        .and(with_rate_limit(rate_limiter.clone()))
         .and(warp::body::json())
         .and(warp::any().map(move || AddDefinitionBinding {
             definitions: add_items.clone(),
             log: add_log.clone(),
             poll_interval: cli.poll_interval,
             registry_query_timeout: cli.registry_query_timeout,
-            registry_path: cli.targets_dir.clone(),
-            handles: handles.clone(),
-            rt: rt.clone(),
+            registry_path: add_registry_path.clone(),
+            handles: add_handles.clone(),
+            rt: add_rt.clone(),
+            jitter: add_jitter.clone(),
+            registry_source: add_registry_source.clone(),
+            metrics: add_metrics.clone(),
+            definitions_file: add_definitions_file.clone(),
         }))
         .and_then(add_definition);
 
+    let export_definitions_items = items.clone();
+    let export_definitions = warp::path!("definitions" / "export")
+        .and(warp::get())
+        .and(warp::query::<ExportQuery>())
+        .and(warp::any().map(move || export_definitions_items.clone()))
+        .and_then(export_definitions);
+
+    let import_items = items.clone();
+    let import_log = log.clone();
+    let import_registry_path = cli.targets_dir.clone();
+    let import_handles = handles.clone();
+    let import_rt = rt.clone();
+    let import_metrics = metrics.clone();
+    let import_registry_source = registry_source.clone();
+    let import_definitions_file = definitions_file.clone();
+    let import_definitions = warp::path!("definitions" / "import")
+        .and(warp::post())
+        .and(with_auth(cli.auth_token.clone()))
+        .and(with_rate_limit(rate_limiter.clone()))
+        .and(warp::query::<ImportQuery>())
+        .and(warp::body::json())
+        .and(warp::any().map(move || AddDefinitionBinding {
+            definitions: import_items.clone(),
+            log: import_log.clone(),
+            poll_interval: cli.poll_interval,
+            registry_query_timeout: cli.registry_query_timeout,
+            registry_path: import_registry_path.clone(),
+            handles: import_handles.clone(),
+            rt: import_rt.clone(),
+            jitter: jitter.clone(),
+            registry_source: import_registry_source.clone(),
+            metrics: import_metrics.clone(),
+            definitions_file: import_definitions_file.clone(),
+        }))
+        .and_then(import_definitions);
+
+    let pause_items = items.clone();
+    let pause_definitions_file = definitions_file.clone();
+    let pause = warp::path!("definitions" / String / "pause")
+        .and(warp::post())
+        .and(with_auth(cli.auth_token.clone()))
+        .and(with_rate_limit(rate_limiter.clone()))
+        .and(warp::any().map(move || PauseDefinitionBinding {
+            definitions: pause_items.clone(),
+            definitions_file: pause_definitions_file.clone(),
+        }))
+        .and_then(pause_definition);
+
+    let resume_items = items.clone();
+    let resume_definitions_file = definitions_file.clone();
+    let resume = warp::path!("definitions" / String / "resume")
+        .and(warp::post())
+        .and(with_auth(cli.auth_token.clone()))
+        .and(with_rate_limit(rate_limiter.clone()))
+        .and(warp::any().map(move || PauseDefinitionBinding {
+            definitions: resume_items.clone(),
+            definitions_file: resume_definitions_file.clone(),
+        }))
+        .and_then(resume_definition);
+
+    let sync_items = items.clone();
+    let sync = warp::path!("definitions" / String / "sync")
+        .and(warp::post())
+        .and(with_auth(cli.auth_token.clone()))
+        .and(warp::any().map(move || SyncDefinitionBinding {
+            definitions: sync_items.clone(),
+        }))
+        .and_then(sync_definition);
+
+    let verify_items = items.clone();
+    let verify_definitions_file = definitions_file.clone();
+    let verify = warp::path!("definitions" / String / "verify")
+        .and(warp::put())
+        .and(with_auth(cli.auth_token.clone()))
+        .and(with_rate_limit(rate_limiter.clone()))
+        .and(warp::body::json())
+        .and(warp::any().map(move || UpdateVerificationBinding {
+            definitions: verify_items.clone(),
+            registry_source: registry_source.clone(),
+            definitions_file: verify_definitions_file.clone(),
+        }))
+        .and_then(update_verification);
+
     let get_items = items.clone();
     let get = warp::path::end()
         .and(warp::get())
@@ -59,55 +290,385 @@ pub async fn prepare_server(
         .and_then(get_definitions);
 
     let delete_items = items.clone();
+    let delete_handles = handles.clone();
+    let delete_definitions_file = definitions_file.clone();
     let delete = warp::path!(String)
         .and(warp::delete())
-        .and(warp::any().map(move || delete_items.clone()))
+        // Leave `/definitions` to the bulk deletion, including its rejections.
+        .and_then(|name: String| async move {
+            match name.as_str() {
+                "definitions" => Err(warp::reject::not_found()),
+                _ => Ok(name),
+            }
+        })
+        .and(with_rate_limit(rate_limiter.clone()))
+        .and(warp::any().map(move || DeleteDefinitionBinding {
+            definitions: delete_items.clone(),
+            handles: delete_handles.clone(),
+            stop_timeout: STOP_TIMEOUT,
+            definitions_file: delete_definitions_file.clone(),
+        }))
         .and_then(delete_definition);
 
+    let delete_matching_items = items.clone();
+    let delete_matching_handles = handles.clone();
+    let delete_matching_definitions_file = definitions_file.clone();
+    let delete_matching = warp::path!("definitions")
+        .and(warp::delete())
+        .and(with_auth(cli.auth_token.clone()))
+        .and(with_rate_limit(rate_limiter.clone()))
+        .and(warp::query::<DeleteDefinitionsQuery>())
+        .and(warp::any().map(move || DeleteDefinitionsBinding {
+            definitions: delete_matching_items.clone(),
+            handles: delete_matching_handles.clone(),
+            stop_timeout: STOP_TIMEOUT,
+            definitions_file: delete_matching_definitions_file.clone(),
+        }))
+        .and_then(delete_definitions);
+
+    let delete_all_items = items.clone();
+    let delete_all_handles = handles.clone();
+    let delete_all_definitions_file = definitions_file.clone();
+    let delete_all = warp::path!("definitions" / "all")
+        .and(warp::delete())
+        .and(with_auth(cli.auth_token.clone()))
+        .and(with_rate_limit(rate_limiter.clone()))
+        .and(warp::query::<DeleteAllDefinitionsQuery>())
+        .and(warp::any().map(move || DeleteDefinitionsBinding {
+            definitions: delete_all_items.clone(),
+            handles: delete_all_handles.clone(),
+            stop_timeout: STOP_TIMEOUT,
+            definitions_file: delete_all_definitions_file.clone(),
+        }))
+        .and_then(delete_all_definitions);
+
     let export_items = items.clone();
     let export_def_log = log.clone();
+    let export_prometheus_metrics = metrics.clone();
     let export_prometheus = warp::path!("prom" / "targets")
         .and(warp::get())
+        .and(warp::header::optional::<String>("accept-encoding"))
         .and(warp::any().map(move || ExportDefinitionConfigBinding {
             definitions: export_items.clone(),
             log: export_def_log.clone(),
+            transformers: transformers.clone(),
+            cache: export_prometheus_cache.clone(),
+            metrics: export_prometheus_metrics.clone(),
+            deduplicate,
         }))
         .and_then(export_prometheus_config);
 
+    let scrape_configs_items = items.clone();
+    let scrape_intervals = cli.scrape_intervals();
+    let public_url = cli.public_url.clone();
+    let tls = tls_paths.is_some();
+    let export_scrape_configs = warp::path!("prom" / "scrape_configs")
+        .and(warp::get())
+        .and(warp::header::optional::<String>("host"))
+        .and(warp::query::<ScrapeConfigsQuery>())
+        .and(warp::any().map(move || ExportScrapeConfigsBinding {
+            definitions: scrape_configs_items.clone(),
+            intervals: scrape_intervals.clone(),
+            public_url: public_url.clone(),
+            tls,
+        }))
+        .and_then(export_scrape_configs);
+
+    let watch_prometheus = warp::path!("prom" / "targets" / "watch")
+        .and(warp::get())
+        .and(warp::header::optional::<String>("if-none-match"))
+        .and(warp::query::<WatchQuery>())
+        .and(warp::any().map(move || WatchTargetsBinding {
+            etags: etags.clone(),
+        }))
+        .and_then(watch_targets);
+
     let export_targets_items = items.clone();
     let export_log = log.clone();
+    let export_targets_cache = SerializedCache::default();
+    let export_targets_metrics = metrics.clone();
     let export_targets = warp::path!("targets")
         .and(warp::get())
         .and(warp::any().map(move || ExportTargetsBinding {
             definitions: export_targets_items.clone(),
             log: export_log.clone(),
+            cache: export_targets_cache.clone(),
+            metrics: export_targets_metrics.clone(),
+            deduplicate,
+            global_labels: global_labels.clone(),
         }))
         .and_then(export_targets);
 
+    let export_log_targets_items = items.clone();
+    let export_log_targets_log = log.clone();
+    let log_target_builder = LogTargetBuilder::new(cli.journald_port);
+    let export_log_targets = warp::path!("log_targets")
+        .and(warp::get())
+        .and(warp::any().map(move || ExportLogTargetsBinding {
+            definitions: export_log_targets_items.clone(),
+            log: export_log_targets_log.clone(),
+            builder: log_target_builder.clone(),
+        }))
+        .and_then(export_log_targets);
+
     let add_boundary_node_targets = items.clone();
     let add_boundary_node_log = log.clone();
     let add_boundary_node = warp::path!("add_boundary_node")
         .and(warp::post())
+        .and(with_rate_limit(rate_limiter.clone()))
         .and(warp::body::json())
         .and(warp::any().map(move || AddBoundaryNodeToDefinitionBinding {
             definitions: add_boundary_node_targets.clone(),
             log: add_boundary_node_log.clone(),
+            definitions_file: definitions_file.clone(),
         }))
         .and_then(add_boundary_node);
 
+    let get_log_levels = log_levels.clone();
+    let get_log_level_log = log.clone();
+    // Reading the log level is protected as well, it reveals the
+    // configuration of the server.
+    let get_log_level = warp::path!("log_level")
+        .and(warp::get())
+        .and(with_auth(cli.auth_token.clone()))
+        .and(warp::any().map(move || LogLevelBinding {
+            levels: get_log_levels.clone(),
+            log: get_log_level_log.clone(),
+        }))
+        .and_then(get_log_level);
+
+    let put_log_level_log = log.clone();
+    let put_log_level = warp::path!("log_level")
+        .and(warp::put())
+        .and(with_auth(cli.auth_token.clone()))
+        .and(warp::body::bytes())
+        .and(warp::any().map(move || LogLevelBinding {
+            levels: log_levels.clone(),
+            log: put_log_level_log.clone(),
+        }))
+        .and_then(put_log_level);
+
+    let get_metrics_metrics = metrics.clone();
+    let get_metrics = warp::path!("metrics")
+        .and(warp::get())
+        .and(warp::header::optional::<String>("accept"))
+        .and(warp::any().map(move || MetricsBinding {
+            metrics: get_metrics_metrics.clone(),
+        }))
+        .and_then(get_metrics);
+
     let routes = add
         .or(get)
         .or(delete)
+        .or(delete_matching)
+        .or(delete_all)
         .or(export_prometheus)
+        .or(watch_prometheus)
+        .or(export_scrape_configs)
         .or(export_targets)
-        .or(add_boundary_node);
-
-    let routes = routes.with(warp::log("multiservice_discovery"));
-    let (_, server) =
-        warp::serve(routes).bind_with_graceful_shutdown(([0, 0, 0, 0], 8000), async {
-            recv.await.ok();
-        });
-    info!(log, "Server started on port {}", 8000);
-    server.await;
+        .or(export_log_targets)
+        .or(add_boundary_node)
+        .or(export_definitions)
+        .or(import_definitions)
+        .or(pause)
+        .or(resume)
+        .or(sync)
+        .or(verify)
+        .or(get_log_level)
+        .or(put_log_level)
+        .or(get_metrics)
+        .recover(handle_unauthorized)
+        .recover(handle_rate_limited);
+
+    let routes = instrument(routes, metrics).with(warp::log("multiservice_discovery"));
+    match tls_paths {
+        Some(tls_paths) => {
+            serve_tls(routes, listen_addr, tls_paths, stop_receiver, log.clone()).await
+        }
+        None => {
+            let (_, server) = warp::serve(routes).bind_with_graceful_shutdown(listen_addr, async {
+                stop_receiver.await.ok();
+            });
+            info!(log, "Server started on {}", listen_addr);
+            server.await;
+        }
+    }
+    if let Some(file_sd) = file_sd {
+        file_sd.abort();
+    }
     info!(log, "Server stopped");
 }
+
+/// Serve the routes over HTTPS until `recv` fires. On SIGHUP the certificate
+/// and key are read again and handed to new connections, the listening socket
+/// and the open connections are left alone. If the new pair is invalid the old
+/// one stays in use.
+/// How long a client has to complete the TLS handshake before its connection
+/// is dropped.
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long to wait before accepting connections again after an error.
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_secs(1);
+
+async fn serve_tls<F>(
+    routes: F,
+    listen_addr: SocketAddr,
+    tls_paths: TlsPaths,
+    recv: tokio::sync::oneshot::Receiver<()>,
+    log: Logger,
+) where
+    F: Filter<Error = Rejection> + Clone + Send + Sync + 'static,
+    F::Extract: Reply,
+{
+    let resolver = match tls_paths.load() {
+        Ok(key) => Arc::new(CertResolver::new(key)),
+        Err(e) => {
+            warn!(log, "Not starting server: {}", e);
+            return;
+        }
+    };
+    let listener = match TcpListener::bind(listen_addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            warn!(
+                log,
+                "Not starting server, failed to bind {}: {}", listen_addr, e
+            );
+            return;
+        }
+    };
+    let acceptor = TlsAcceptor::from(Arc::new(server_config(resolver.clone())));
+    let mut sighup = signal(SignalKind::hangup()).expect("Failed to register SIGHUP handler");
+
+    // Handshakes run in their own tasks so that a slow client does not hold
+    // up the others, the established streams are handed to the server.
+    let (stream_sender, stream_receiver) = tokio::sync::mpsc::unbounded_channel();
+    let accept_log = log.clone();
+    let accept = tokio::spawn(async move {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    // Errors such as running out of file descriptors persist
+                    // for a while, retrying right away would spin.
+                    warn!(accept_log, "Failed to accept connection: {}", e);
+                    tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+                    continue;
+                }
+            };
+            let acceptor = acceptor.clone();
+            let stream_sender = stream_sender.clone();
+            let handshake_log = accept_log.clone();
+            tokio::spawn(async move {
+                match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                    Ok(Ok(stream)) => {
+                        stream_sender.send(stream).ok();
+                    }
+                    Ok(Err(e)) => debug!(handshake_log, "TLS handshake failed: {}", e),
+                    Err(_) => debug!(
+                        handshake_log,
+                        "TLS handshake did not complete within {:?}", TLS_HANDSHAKE_TIMEOUT
+                    ),
+                }
+            });
+        }
+    });
+    let incoming = futures_util::stream::unfold(stream_receiver, |mut receiver| async move {
+        receiver
+            .recv()
+            .await
+            .map(|stream| (Ok::<_, std::io::Error>(stream), receiver))
+    });
+
+    let server = warp::serve(routes).serve_incoming_with_graceful_shutdown(incoming, async {
+        recv.await.ok();
+    });
+    tokio::pin!(server);
+    info!(log, "Server started on {} with TLS", listen_addr);
+
+    loop {
+        tokio::select! {
+            _ = &mut server => break,
+            _ = sighup.recv() => match tls_paths.load() {
+                Ok(key) => {
+                    info!(log, "Reloading TLS certificate");
+                    resolver.set(key);
+                }
+                Err(e) => warn!(log, "Keeping the current TLS certificate: {}", e),
+            },
+        }
+    }
+    accept.abort();
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use slog::o;
+
+    use crate::log_level::LogLevels;
+    use crate::CliArgs;
+
+    use super::*;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn serves_targets_over_tls_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let cert_path = dir.path().join("cert.pem");
+        let key_path = dir.path().join("key.pem");
+        std::fs::write(&cert_path, cert.serialize_pem().unwrap()).unwrap();
+        std::fs::write(&key_path, cert.serialize_private_key_pem()).unwrap();
+
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let listen_addr = format!("127.0.0.1:{}", port);
+        let cli = CliArgs::parse_from([
+            "multiservice-discovery",
+            "--targets-dir",
+            dir.path().to_str().unwrap(),
+            "--start-without-mainnet",
+            "--listen-addr",
+            &listen_addr,
+            "--tls-cert-path",
+            cert_path.to_str().unwrap(),
+            "--tls-key-path",
+            key_path.to_str().unwrap(),
+        ]);
+
+        let (stop_sender, stop_receiver) = tokio::sync::oneshot::channel();
+        let server = tokio::spawn(prepare_server(
+            stop_receiver,
+            ServerConfig::new(
+                cli,
+                Logger::root(slog::Discard, o!()),
+                LogLevels::new("info".parse().unwrap()),
+            ),
+            Arc::new(Mutex::new(vec![])),
+            Arc::new(Mutex::new(vec![])),
+            tokio::runtime::Handle::current(),
+        ));
+        tokio::time::sleep(Duration::from_millis(500)).await;
+
+        let client = reqwest::Client::builder()
+            .danger_accept_invalid_certs(true)
+            .build()
+            .unwrap();
+        let response = client
+            .get(format!("https://localhost:{}/targets", port))
+            .send()
+            .await
+            .unwrap();
+        assert!(response.status().is_success());
+        assert!(client
+            .get(format!("http://localhost:{}/targets", port))
+            .send()
+            .await
+            .is_err());
+
+        stop_sender.send(()).unwrap();
+        server.await.unwrap();
+    }
+}
@@ -0,0 +1,52 @@
+use std::sync::Arc;
+
+use tokio::sync::Mutex;
+use warp::Reply;
+
+use crate::definition::Definition;
+use crate::definitions_file::DefinitionsFile;
+use crate::server_handlers::WebResult;
+
+pub struct PauseDefinitionBinding {
+    pub definitions: Arc<Mutex<Vec<Definition>>>,
+    pub definitions_file: DefinitionsFile,
+}
+
+pub async fn pause_definition(
+    name: String,
+    binding: PauseDefinitionBinding,
+) -> WebResult<impl Reply> {
+    set_paused(name, true, binding).await
+}
+
+pub async fn resume_definition(
+    name: String,
+    binding: PauseDefinitionBinding,
+) -> WebResult<impl Reply> {
+    set_paused(name, false, binding).await
+}
+
+/// The paused state is shared with the thread running the definition, which
+/// picks it up with its next poll.
+async fn set_paused(
+    name: String,
+    paused: bool,
+    binding: PauseDefinitionBinding,
+) -> WebResult<impl Reply> {
+    let definitions = binding.definitions.lock().await;
+
+    match definitions.iter().find(|d| d.name == name) {
+        Some(definition) => {
+            definition.set_paused(paused);
+            binding.definitions_file.save(&definitions);
+            Ok(warp::reply::with_status(
+                "success".to_string(),
+                warp::http::StatusCode::OK,
+            ))
+        }
+        None => Ok(warp::reply::with_status(
+            "Definition with this name does not exist".to_string(),
+            warp::http::StatusCode::BAD_REQUEST,
+        )),
+    }
+}
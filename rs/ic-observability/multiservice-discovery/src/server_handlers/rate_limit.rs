@@ -0,0 +1,133 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use warp::http::StatusCode;
+use warp::reject::Reject;
+use warp::{Filter, Rejection, Reply};
+
+#[derive(Debug)]
+pub struct RateLimited {
+    retry_after: Duration,
+}
+
+impl Reject for RateLimited {}
+
+/// A token bucket shared by all the routes it limits. It holds up to a
+/// minute worth of requests and refills continuously.
+#[derive(Clone, Default)]
+pub struct RateLimiter {
+    bucket: Option<Arc<Mutex<Bucket>>>,
+}
+
+struct Bucket {
+    capacity: f64,
+    tokens: f64,
+    per_second: f64,
+    refilled_at: Instant,
+}
+
+impl RateLimiter {
+    /// Accept `requests` per minute, or any number of them if 0.
+    pub fn per_minute(requests: u32) -> Self {
+        if requests == 0 {
+            return Self::default();
+        }
+        let capacity = f64::from(requests);
+        Self {
+            bucket: Some(Arc::new(Mutex::new(Bucket {
+                capacity,
+                tokens: capacity,
+                per_second: capacity / 60.0,
+                refilled_at: Instant::now(),
+            }))),
+        }
+    }
+
+    /// Take a token at `now`, or return how long to wait for the next one.
+    fn acquire(&self, now: Instant) -> Result<(), Duration> {
+        let bucket = match &self.bucket {
+            Some(bucket) => bucket,
+            None => return Ok(()),
+        };
+        let mut bucket = bucket.lock().unwrap();
+        let elapsed = now.saturating_duration_since(bucket.refilled_at);
+        bucket.tokens =
+            (bucket.tokens + elapsed.as_secs_f64() * bucket.per_second).min(bucket.capacity);
+        bucket.refilled_at = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / bucket.per_second,
+            ))
+        }
+    }
+}
+
+/// Reject the request with [`RateLimited`] if `limiter` has no token left.
+pub fn with_rate_limit(
+    limiter: RateLimiter,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::any()
+        .and_then(move || {
+            let result = limiter.acquire(Instant::now());
+            async move {
+                result.map_err(|retry_after| warp::reject::custom(RateLimited { retry_after }))
+            }
+        })
+        .untuple_one()
+}
+
+/// Turn the rejections of [`with_rate_limit`] into a 429 response with a
+/// `Retry-After` header in whole seconds, and pass every other rejection on.
+pub async fn handle_rate_limited(rejection: Rejection) -> Result<impl Reply, Rejection> {
+    match rejection.find::<RateLimited>() {
+        Some(limited) => {
+            let retry_after = limited.retry_after.as_secs_f64().ceil().max(1.0) as u64;
+            Ok(warp::reply::with_header(
+                warp::reply::with_status(
+                    "Too many requests changing definitions".to_string(),
+                    StatusCode::TOO_MANY_REQUESTS,
+                ),
+                "retry-after",
+                retry_after.to_string(),
+            ))
+        }
+        None => Err(rejection),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_are_refilled_over_a_minute() {
+        let limiter = RateLimiter::per_minute(2);
+        let start = Instant::now();
+        assert!(limiter.acquire(start).is_ok());
+        assert!(limiter.acquire(start).is_ok());
+        let retry_after = limiter.acquire(start).unwrap_err();
+        assert_eq!(retry_after.as_secs_f64().round(), 30.0);
+
+        // Half a token after 15 seconds, which is not enough.
+        let retry_after = limiter
+            .acquire(start + Duration::from_secs(15))
+            .unwrap_err();
+        assert_eq!(retry_after.as_secs_f64().round(), 15.0);
+        assert!(limiter.acquire(start + Duration::from_secs(31)).is_ok());
+        // A long pause does not save up more than a minute worth of tokens.
+        let later = start + Duration::from_secs(3600);
+        assert!(limiter.acquire(later).is_ok());
+        assert!(limiter.acquire(later).is_ok());
+        assert!(limiter.acquire(later).is_err());
+    }
+
+    #[test]
+    fn zero_disables_the_limit() {
+        let limiter = RateLimiter::per_minute(0);
+        let now = Instant::now();
+        assert!((0..100).all(|_| limiter.acquire(now).is_ok()));
+    }
+}
@@ -0,0 +1,190 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+use serde::Serialize;
+use tokio::sync::Mutex;
+use warp::hyper::body::Bytes;
+
+use crate::definition::Definition;
+use crate::server_handlers::compression::gzip;
+
+/// The JSON body of an endpoint, serialized once and shared by all requests
+/// until the data it was built from changes. `Bytes` are reference counted,
+/// so concurrent requests do not copy the body.
+#[derive(Clone, Default)]
+pub struct SerializedCache {
+    inner: Arc<Mutex<Option<CachedBody>>>,
+}
+
+struct CachedBody {
+    fingerprint: u64,
+    body: Bytes,
+    /// The body compressed with gzip, once requested.
+    gzipped: Option<Bytes>,
+}
+
+impl SerializedCache {
+    /// Return the cached body if it was built for `fingerprint`, otherwise
+    /// serialize the value returned by `build` and cache it. Requests arriving
+    /// while the body is built wait for it instead of building their own.
+    pub async fn get_or_build<T, F>(&self, fingerprint: u64, build: F) -> Bytes
+    where
+        T: Serialize,
+        F: FnOnce() -> T,
+    {
+        let mut cached = self.inner.lock().await;
+        if let Some(cached) = cached.as_ref() {
+            if cached.fingerprint == fingerprint {
+                return cached.body.clone();
+            }
+        }
+        // Drop the outdated body before building the new one, so that both
+        // are not held at the same time.
+        *cached = None;
+
+        let mut buffer = vec![];
+        serde_json::to_writer_pretty(&mut buffer, &build()).unwrap();
+        let body = Bytes::from(buffer);
+        *cached = Some(CachedBody {
+            fingerprint,
+            body: body.clone(),
+            gzipped: None,
+        });
+        body
+    }
+
+    /// `body`, as returned by [`SerializedCache::get_or_build`] for
+    /// `fingerprint`, compressed with gzip. The compressed body is cached
+    /// along with the body, unless that was replaced in the meantime.
+    pub async fn gzipped(&self, fingerprint: u64, body: Bytes) -> Bytes {
+        let mut cached = self.inner.lock().await;
+        match cached.as_mut() {
+            Some(cached) if cached.fingerprint == fingerprint => cached
+                .gzipped
+                .get_or_insert_with(|| gzip(&cached.body))
+                .clone(),
+            _ => gzip(&body),
+        }
+    }
+}
+
+/// A hash of everything the exported targets are built from: the running
+/// definitions and their options, the versions of their registries and their
+/// boundary nodes.
+pub fn targets_fingerprint(definitions: &[Definition]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for definition in definitions {
+        definition.name.hash(&mut hasher);
+        definition.scrape_options.hash(&mut hasher);
+        definition.allow_duplicate_targets.hash(&mut hasher);
+        definition.port_overrides().hash(&mut hasher);
+        definition
+            .ic_discovery
+            .registry_versions()
+            .hash(&mut hasher);
+        for bn in &definition.boundary_nodes {
+            bn.name.hash(&mut hasher);
+            bn.targets.hash(&mut hasher);
+            bn.custom_labels.hash(&mut hasher);
+            bn.job_type.hash(&mut hasher);
+        }
+    }
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, BTreeSet};
+    use std::io::Read;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use multiservice_discovery_shared::builders::prometheus_config_structure::PrometheusStaticConfig;
+
+    use super::*;
+
+    fn synthetic_configs(count: usize) -> BTreeSet<PrometheusStaticConfig> {
+        (0..count)
+            .map(|i| PrometheusStaticConfig {
+                targets: BTreeSet::from([format!("http://[2001:db8::{:x}]:9100/metrics", i)]),
+                labels: BTreeMap::from([
+                    ("ic".to_string(), "mercury".to_string()),
+                    ("ic_node".to_string(), format!("node-{}", i)),
+                    ("job".to_string(), "node_exporter".to_string()),
+                ]),
+            })
+            .collect()
+    }
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    #[test]
+    fn cached_body_matches_previous_output() {
+        let configs = synthetic_configs(100);
+        let cache = SerializedCache::default();
+        let builds = AtomicUsize::new(0);
+        let build = || {
+            builds.fetch_add(1, Ordering::SeqCst);
+            configs.clone()
+        };
+
+        let first = block_on(cache.get_or_build(1, build));
+        let second = block_on(cache.get_or_build(1, build));
+        assert_eq!(builds.load(Ordering::SeqCst), 1);
+        assert_eq!(first, second);
+        assert_eq!(
+            first,
+            serde_json::to_string_pretty(&configs).unwrap().as_bytes()
+        );
+
+        let empty = block_on(cache.get_or_build(2, BTreeSet::<PrometheusStaticConfig>::new));
+        assert_eq!(empty, &b"[]"[..]);
+    }
+
+    #[test]
+    fn gzipped_body_is_compressed_once() {
+        let configs = synthetic_configs(100);
+        let cache = SerializedCache::default();
+
+        let body = block_on(cache.get_or_build(1, || configs.clone()));
+        let first = block_on(cache.gzipped(1, body.clone()));
+        let second = block_on(cache.gzipped(1, body.clone()));
+        assert_eq!(first.as_ptr(), second.as_ptr());
+        assert!(first.len() < body.len());
+
+        let mut decompressed = vec![];
+        flate2::read::GzDecoder::new(&first[..])
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, body);
+    }
+
+    #[test]
+    fn concurrent_requests_share_one_body() {
+        let requests = 16;
+        let cache = SerializedCache::default();
+        let builds = AtomicUsize::new(0);
+        let build = || {
+            builds.fetch_add(1, Ordering::SeqCst);
+            synthetic_configs(20_000)
+        };
+
+        let bodies = block_on(futures_util::future::join_all(
+            (0..requests).map(|_| cache.get_or_build(1, build)),
+        ));
+
+        // All the bodies are held at the same time, as they would be while
+        // being sent to slow clients, yet only one was ever built and they
+        // all point at the same buffer.
+        assert_eq!(bodies.len(), requests);
+        assert_eq!(builds.load(Ordering::SeqCst), 1);
+        assert!(bodies
+            .iter()
+            .all(|b| b.as_ptr() == bodies[0].as_ptr() && b.len() == bodies[0].len()));
+    }
+}
@@ -0,0 +1,55 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::Mutex;
+use warp::http::StatusCode;
+use warp::Reply;
+
+use crate::definition::{Definition, SyncError};
+use crate::server_handlers::WebResult;
+
+/// How long a request waits for the sync it triggered.
+const SYNC_TIMEOUT: Duration = Duration::from_secs(60);
+
+pub struct SyncDefinitionBinding {
+    pub definitions: Arc<Mutex<Vec<Definition>>>,
+}
+
+/// Poll the registry of the definition right away, replying once the poll
+/// completed.
+pub async fn sync_definition(
+    name: String,
+    binding: SyncDefinitionBinding,
+) -> WebResult<impl Reply> {
+    // The definitions must not stay locked while the sync runs.
+    let definition = {
+        let definitions = binding.definitions.lock().await;
+        definitions.iter().find(|d| d.name == name).cloned()
+    };
+    let definition = match definition {
+        Some(definition) => definition,
+        None => {
+            return Ok(warp::reply::with_status(
+                "Definition with this name does not exist".to_string(),
+                StatusCode::BAD_REQUEST,
+            ))
+        }
+    };
+
+    let (message, status) = match definition.sync_now(SYNC_TIMEOUT).await {
+        Ok(()) => ("success".to_string(), StatusCode::OK),
+        Err(SyncError::Paused) => (
+            "Definition is paused, resume it first".to_string(),
+            StatusCode::CONFLICT,
+        ),
+        Err(SyncError::Stopped) => (
+            "Definition was stopped before the sync completed".to_string(),
+            StatusCode::CONFLICT,
+        ),
+        Err(SyncError::Timeout) => (
+            format!("Sync did not complete within {:?}", SYNC_TIMEOUT),
+            StatusCode::GATEWAY_TIMEOUT,
+        ),
+    };
+    Ok(warp::reply::with_status(message, status))
+}
@@ -0,0 +1,67 @@
+use std::sync::Arc;
+
+use tokio::sync::Mutex;
+use warp::http::StatusCode;
+use warp::Reply;
+
+use crate::definition::{Definition, Verification};
+use crate::definitions_file::DefinitionsFile;
+use crate::registry_source::RegistrySource;
+use crate::server_handlers::add_definition_handler::{decode_public_key, validate_verification};
+use crate::server_handlers::dto::VerificationDto;
+use crate::server_handlers::WebResult;
+
+pub struct UpdateVerificationBinding {
+    pub definitions: Arc<Mutex<Vec<Definition>>>,
+    pub registry_source: Arc<dyn RegistrySource>,
+    pub definitions_file: DefinitionsFile,
+}
+
+/// Change how the registry of a running definition is verified. The new
+/// policy is validated the same way as the one of an added definition, and
+/// the current one is kept if it is invalid.
+pub async fn update_verification(
+    name: String,
+    update: VerificationDto,
+    binding: UpdateVerificationBinding,
+) -> WebResult<impl Reply> {
+    // The definitions must not stay locked while the key is verified.
+    let definition = {
+        let definitions = binding.definitions.lock().await;
+        definitions.iter().find(|d| d.name == name).cloned()
+    };
+    let definition = match definition {
+        Some(definition) => definition,
+        None => {
+            return Ok(warp::reply::with_status(
+                "Definition with this name does not exist".to_string(),
+                StatusCode::BAD_REQUEST,
+            ))
+        }
+    };
+
+    let verification = decode_public_key(&update.public_key)
+        .and_then(|public_key| Verification::new(Some(update.verify), public_key));
+    let verification = match verification {
+        Ok(verification) => verification,
+        Err(e) => return Ok(warp::reply::with_status(e, StatusCode::BAD_REQUEST)),
+    };
+    if let Err(e) = validate_verification(
+        verification,
+        &definition.nns_urls,
+        &*binding.registry_source,
+    )
+    .await
+    {
+        return Ok(warp::reply::with_status(e, StatusCode::BAD_REQUEST));
+    }
+
+    definition.set_verification(verification);
+    binding
+        .definitions_file
+        .save(&binding.definitions.lock().await);
+    Ok(warp::reply::with_status(
+        "success".to_string(),
+        StatusCode::OK,
+    ))
+}
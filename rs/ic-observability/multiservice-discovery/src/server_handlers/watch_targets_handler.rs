@@ -0,0 +1,187 @@
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use multiservice_discovery_shared::builders::prometheus_config_structure::PrometheusStaticConfig;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tokio::sync::{watch, Mutex};
+use warp::http::header::{HeaderValue, ETAG};
+use warp::http::StatusCode;
+use warp::reply::{Reply, Response};
+
+use crate::definition::Definition;
+use crate::server_handlers::dto::TargetsChangedDto;
+use crate::server_handlers::WebResult;
+
+use super::serialized_cache::{targets_fingerprint, SerializedCache};
+
+/// How often the targets are checked for changes.
+pub const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+/// How long a watch request waits for a change unless it asks otherwise.
+const DEFAULT_WATCH_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_WATCH_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// The entity tag of a serialized targets body.
+pub fn targets_etag(body: &[u8]) -> String {
+    format!("\"{}\"", hex::encode(Sha256::digest(body)))
+}
+
+/// Publish the entity tag of the targets built by `build` whenever it
+/// changes, until aborted. The body is built into `cache`, the cache of
+/// `/prom/targets`, so the next request for the targets does not build it
+/// again.
+pub async fn run_targets_watch<F>(
+    definitions: Arc<Mutex<Vec<Definition>>>,
+    cache: SerializedCache,
+    sender: watch::Sender<String>,
+    interval: Duration,
+    build: F,
+) where
+    F: Fn(&[Definition]) -> BTreeSet<PrometheusStaticConfig>,
+{
+    loop {
+        let body = {
+            let definitions = definitions.lock().await;
+            cache
+                .get_or_build(targets_fingerprint(&definitions), || build(&definitions))
+                .await
+        };
+        let etag = targets_etag(&body);
+        sender.send_if_modified(|current| {
+            if *current == etag {
+                false
+            } else {
+                *current = etag;
+                true
+            }
+        });
+        tokio::time::sleep(interval).await;
+    }
+}
+
+#[derive(Deserialize)]
+pub struct WatchQuery {
+    /// How long to wait for a change, in seconds.
+    timeout: Option<u64>,
+}
+
+pub struct WatchTargetsBinding {
+    /// The entity tag of the current targets, empty until first published.
+    pub etags: watch::Receiver<String>,
+}
+
+/// Reply with the entity tag of the targets as soon as it differs from the
+/// ones in `If-None-Match`, or with 304 once the timeout passed. Waiting
+/// requests are answered with 503 when the server shuts down.
+pub async fn watch_targets(
+    if_none_match: Option<String>,
+    query: WatchQuery,
+    mut binding: WatchTargetsBinding,
+) -> WebResult<Response> {
+    let timeout = query
+        .timeout
+        .map_or(DEFAULT_WATCH_TIMEOUT, Duration::from_secs)
+        .min(MAX_WATCH_TIMEOUT);
+    let known = |etag: &String| {
+        if_none_match
+            .as_deref()
+            .is_some_and(|tags| tags.split(',').any(|tag| tag.trim() == etag))
+    };
+
+    let changed = tokio::time::timeout(
+        timeout,
+        binding
+            .etags
+            .wait_for(|etag| !etag.is_empty() && !known(etag)),
+    )
+    .await
+    .map(|changed| changed.map(|etag| etag.clone()));
+
+    let (mut reply, etag) = match changed {
+        Ok(Ok(etag)) => (
+            warp::reply::json(&TargetsChangedDto { etag: etag.clone() }).into_response(),
+            etag,
+        ),
+        Ok(Err(_)) => {
+            return Ok(warp::reply::with_status(
+                "Server is shutting down".to_string(),
+                StatusCode::SERVICE_UNAVAILABLE,
+            )
+            .into_response())
+        }
+        Err(_) => (
+            StatusCode::NOT_MODIFIED.into_response(),
+            binding.etags.borrow().clone(),
+        ),
+    };
+    if let Ok(etag) = HeaderValue::from_str(&etag) {
+        if !etag.is_empty() {
+            reply.headers_mut().insert(ETAG, etag);
+        }
+    }
+    Ok(reply)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(timeout: u64) -> WatchQuery {
+        WatchQuery {
+            timeout: Some(timeout),
+        }
+    }
+
+    #[tokio::test]
+    async fn unknown_tags_are_replied_to_right_away() {
+        let (sender, etags) = watch::channel("\"a\"".to_string());
+
+        let reply = watch_targets(
+            None,
+            query(30),
+            WatchTargetsBinding {
+                etags: etags.clone(),
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(reply.status(), StatusCode::OK);
+        assert_eq!(reply.headers()[ETAG], "\"a\"");
+
+        let reply = watch_targets(
+            Some("\"b\", \"a\"".to_string()),
+            query(0),
+            WatchTargetsBinding {
+                etags: etags.clone(),
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(reply.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(reply.headers()[ETAG], "\"a\"");
+
+        let waiting = tokio::spawn(watch_targets(
+            Some("\"a\"".to_string()),
+            query(30),
+            WatchTargetsBinding { etags },
+        ));
+        sender.send("\"c\"".to_string()).unwrap();
+        let reply = waiting.await.unwrap().unwrap();
+        assert_eq!(reply.status(), StatusCode::OK);
+        assert_eq!(reply.headers()[ETAG], "\"c\"");
+    }
+
+    #[tokio::test]
+    async fn waiting_requests_end_when_the_watch_stops() {
+        let (sender, etags) = watch::channel("\"a\"".to_string());
+        let waiting = tokio::spawn(watch_targets(
+            Some("\"a\"".to_string()),
+            query(300),
+            WatchTargetsBinding { etags },
+        ));
+        drop(sender);
+        let reply = waiting.await.unwrap().unwrap();
+        assert_eq!(reply.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
@@ -0,0 +1,154 @@
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::{Certificate, PrivateKey, ServerConfig, SignatureScheme};
+use rustls_pemfile::Item;
+
+const PROBE_MESSAGE: &[u8] = b"multiservice-discovery tls key probe";
+
+#[derive(Clone, Debug)]
+pub struct TlsPaths {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+}
+
+impl TlsPaths {
+    /// Read the certificate chain and the private key from disk and make sure
+    /// they can be used to serve TLS.
+    pub fn load(&self) -> Result<Arc<CertifiedKey>, String> {
+        let cert = std::fs::read(&self.cert_path).map_err(|e| {
+            format!(
+                "Failed to read TLS certificate {}: {}",
+                self.cert_path.display(),
+                e
+            )
+        })?;
+        let key = std::fs::read(&self.key_path)
+            .map_err(|e| format!("Failed to read TLS key {}: {}", self.key_path.display(), e))?;
+
+        certified_key(&cert, &key).map(Arc::new)
+    }
+}
+
+/// Hands the current certificate to every handshake, so that a reloaded
+/// certificate is picked up by new connections without rebinding the socket.
+pub struct CertResolver {
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl CertResolver {
+    pub fn new(key: Arc<CertifiedKey>) -> Self {
+        Self {
+            current: RwLock::new(key),
+        }
+    }
+
+    pub fn set(&self, key: Arc<CertifiedKey>) {
+        *self.current.write().unwrap() = key;
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().unwrap().clone())
+    }
+}
+
+pub fn server_config(resolver: Arc<CertResolver>) -> ServerConfig {
+    let mut config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_cert_resolver(resolver);
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    config
+}
+
+fn certified_key(cert_pem: &[u8], key_pem: &[u8]) -> Result<CertifiedKey, String> {
+    let certs = rustls_pemfile::certs(&mut &cert_pem[..])
+        .map_err(|e| format!("Failed to parse PEM certificate chain: {}", e))?;
+    if certs.is_empty() {
+        return Err("No certificate found in the PEM certificate chain".to_string());
+    }
+
+    let mut key = None;
+    let mut reader = &key_pem[..];
+    while let Some(item) = rustls_pemfile::read_one(&mut reader)
+        .map_err(|e| format!("Failed to parse PEM private key: {}", e))?
+    {
+        match item {
+            Item::RSAKey(k) | Item::PKCS8Key(k) | Item::ECKey(k) => {
+                key = Some(PrivateKey(k));
+                break;
+            }
+            _ => continue,
+        }
+    }
+    let key = key.ok_or_else(|| "No private key found in the PEM key file".to_string())?;
+
+    // Sign a probe message with the private key and verify it against the
+    // public key of the leaf certificate, so that mismatching pairs are
+    // rejected up front instead of failing every handshake.
+    let signing_key = rustls::sign::any_supported_type(&key)
+        .map_err(|e| format!("Unsupported TLS private key: {}", e))?;
+    let signer = signing_key
+        .choose_scheme(&[
+            SignatureScheme::ECDSA_NISTP256_SHA256,
+            SignatureScheme::ECDSA_NISTP384_SHA384,
+            SignatureScheme::ED25519,
+            SignatureScheme::RSA_PSS_SHA256,
+        ])
+        .ok_or_else(|| "No supported signature scheme for the TLS private key".to_string())?;
+    let signature = signer
+        .sign(PROBE_MESSAGE)
+        .map_err(|e| format!("Failed to sign with the TLS private key: {}", e))?;
+    let algorithm = match signer.scheme() {
+        SignatureScheme::ECDSA_NISTP256_SHA256 => &webpki::ECDSA_P256_SHA256,
+        SignatureScheme::ECDSA_NISTP384_SHA384 => &webpki::ECDSA_P384_SHA384,
+        SignatureScheme::ED25519 => &webpki::ED25519,
+        _ => &webpki::RSA_PSS_2048_8192_SHA256_LEGACY_KEY,
+    };
+
+    webpki::EndEntityCert::try_from(certs[0].as_slice())
+        .map_err(|e| format!("Invalid TLS certificate: {:?}", e))?
+        .verify_signature(algorithm, PROBE_MESSAGE, &signature)
+        .map_err(|_| "The TLS private key does not match the certificate".to_string())?;
+
+    Ok(CertifiedKey::new(
+        certs.into_iter().map(Certificate).collect(),
+        signing_key,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_matching_pair() {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+
+        assert!(certified_key(
+            cert.serialize_pem().unwrap().as_bytes(),
+            cert.serialize_private_key_pem().as_bytes()
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn rejects_mismatching_pair() {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let other = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+
+        let err = certified_key(
+            cert.serialize_pem().unwrap().as_bytes(),
+            other.serialize_private_key_pem().as_bytes(),
+        )
+        .err();
+        assert_eq!(
+            err.as_deref(),
+            Some("The TLS private key does not match the certificate")
+        );
+    }
+}
@@ -43,6 +43,7 @@ mod tests {
             dc_id: "test".to_string(),
             operator_id: PrincipalId::new_anonymous(),
             node_provider_id: PrincipalId::new_anonymous(),
+            public_ipv4: None,
         }
     }
 
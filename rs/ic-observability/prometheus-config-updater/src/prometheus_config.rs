@@ -139,6 +139,7 @@ mod prometheus_serialize {
             dc_id: "test".to_string(),
             operator_id: PrincipalId::new_anonymous(),
             node_provider_id: PrincipalId::new_anonymous(),
+            public_ipv4: None,
         }
     }
 
@@ -9,7 +9,7 @@
 use std::{
     collections::{btree_map::Entry, BTreeMap, BTreeSet, HashMap},
     convert::TryFrom,
-    net::{IpAddr, Ipv6Addr, SocketAddr},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
     path::{Path, PathBuf},
     sync::{Arc, RwLock},
     time::Duration,
@@ -69,6 +69,10 @@ pub struct TargetGroup {
     pub dc_id: String,
     pub operator_id: PrincipalId,
     pub node_provider_id: PrincipalId,
+    /// The public IPv4 address of the node, if it has one. Unlike `targets`
+    /// it does not carry a port.
+    #[serde(default)]
+    pub public_ipv4: Option<Ipv4Addr>,
 }
 
 impl TargetGroup {
@@ -154,6 +158,26 @@ impl IcServiceDiscoveryImpl {
         Ok(())
     }
 
+    /// Update each loaded registry from its local store only, for stores
+    /// that are written by someone else than [`Self::update_registries`].
+    pub fn sync_registries_with_local_store(&self) -> Result<(), IcServiceDiscoveryError> {
+        for registry in self.registries.read().unwrap().values() {
+            registry.sync_with_local_store()?;
+        }
+        Ok(())
+    }
+
+    /// The latest version of each loaded registry, by IC name. The targets
+    /// only change when one of these does.
+    pub fn registry_versions(&self) -> BTreeMap<String, RegistryVersion> {
+        self.registries
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(ic_name, registry)| (ic_name.clone(), registry.get_latest_version()))
+            .collect()
+    }
+
     /// Synchronizes the in-memory cache with the state on disk.
     ///
     /// # Known Limitations
@@ -295,6 +319,11 @@ impl IcServiceDiscoveryImpl {
             .unwrap_or_default()
             .unwrap_or_default();
 
+        let public_ipv4 = node_record
+            .public_ipv4_config
+            .as_ref()
+            .and_then(|config| config.ip_addr.parse().ok());
+
         (*node_targets).insert(TargetGroup {
             targets: vec![socket_addr].into_iter().collect(),
             subnet_id,
@@ -304,6 +333,7 @@ impl IcServiceDiscoveryImpl {
             operator_id,
             node_provider_id: PrincipalId::try_from(node_operator.node_provider_principal_id)
                 .unwrap_or_default(),
+            public_ipv4,
         });
 
         Ok(())
@@ -336,14 +366,15 @@ impl IcServiceDiscovery for IcServiceDiscoveryImpl {
         log: Logger,
     ) -> Result<BTreeSet<TargetGroup>, IcServiceDiscoveryError> {
         let mut mapping: Option<Box<dyn Fn(SocketAddr) -> Option<SocketAddr>>> = None;
+        let host_port = self.jobs.get(&job).copied().unwrap_or_else(|| job.port());
 
         if job == JobType::NodeExporter(NodeOS::Host) {
-            mapping = Some(Box::new(|sockaddr: SocketAddr| {
-                guest_to_host_address((set_port(job.port()))(sockaddr))
+            mapping = Some(Box::new(move |sockaddr: SocketAddr| {
+                guest_to_host_address((set_port(host_port))(sockaddr))
             }));
         } else if job == JobType::MetricsProxy {
-            mapping = Some(Box::new(|sockaddr: SocketAddr| {
-                guest_to_host_address((set_port(job.port()))(sockaddr))
+            mapping = Some(Box::new(move |sockaddr: SocketAddr| {
+                guest_to_host_address((set_port(host_port))(sockaddr))
             }));
         }
 
@@ -384,9 +415,16 @@ impl IcServiceDiscovery for IcServiceDiscoveryImpl {
                         .into_iter()
                         .filter_map(&mapping.as_ref().unwrap())
                         .collect();
+                    // The IPv4 address is the one of the guest, the registry
+                    // has none for the host.
+                    let public_ipv4 = match job {
+                        JobType::NodeExporter(NodeOS::Host) | JobType::MetricsProxy => None,
+                        _ => target_group.public_ipv4,
+                    };
                     if !targets.is_empty() {
                         return Some(TargetGroup {
                             targets,
+                            public_ipv4,
                             ..target_group
                         });
                     }
@@ -30,15 +30,9 @@ pub async fn sync_local_registry(
 ) {
     let start = Instant::now();
     let local_store = Arc::new(LocalStoreImpl::new(local_path.clone()));
-    let registry_canister = RegistryCanister::new(nns_urls);
+    let registry_canister = RegistryCanister::new(nns_urls.clone());
 
-    let mut latest_version = if !Path::new(&local_path).exists() {
-        ZERO_REGISTRY_VERSION
-    } else {
-        let registry_cache = FakeRegistryClient::new(local_store.clone());
-        registry_cache.update_to_latest_version();
-        registry_cache.get_latest_version()
-    };
+    let latest_version = local_latest_version(&local_path, local_store);
     debug!(log, "Syncing registry version from version : {}", latest_version);
 
     if use_current_version && latest_version != ZERO_REGISTRY_VERSION {
@@ -51,8 +45,6 @@ pub async fn sync_local_registry(
         );
     }
 
-    let mut latest_certified_time = 0;
-    let mut updates = vec![];
     let nns_public_key = match public_key {
         Some(pk) => pk,
         _ => {
@@ -69,6 +61,28 @@ pub async fn sync_local_registry(
         }
     };
 
+    match sync_local_registry_with_key(log.clone(), local_path, nns_urls, nns_public_key).await {
+        Ok(()) => info!(log, "Synced all registry versions in : {:?}", start.elapsed()),
+        Err(e) => error!(log, "Failed to sync local registry: {}", e),
+    }
+}
+
+/// Sync the local store with the NNS, verifying the certified changes with
+/// `nns_public_key`. Fails without writing any of the new versions if they
+/// do not verify.
+pub async fn sync_local_registry_with_key(
+    log: Logger,
+    local_path: PathBuf,
+    nns_urls: Vec<Url>,
+    nns_public_key: ThresholdSigPublicKey,
+) -> anyhow::Result<()> {
+    let local_store = Arc::new(LocalStoreImpl::new(local_path.clone()));
+    let registry_canister = RegistryCanister::new(nns_urls);
+    let mut latest_version = local_latest_version(&local_path, local_store.clone());
+
+    let mut latest_certified_time = 0;
+    let mut updates = vec![];
+
     loop {
         if match registry_canister.get_latest_version().await {
             Ok(v) => {
@@ -83,79 +97,94 @@ pub async fn sync_local_registry(
             break;
         }
 
-        if let Ok((mut initial_records, _, t)) = registry_canister
+        let (mut initial_records, _, t) = registry_canister
             .get_certified_changes_since(latest_version.get(), &nns_public_key)
             .await
-        {
-            initial_records.sort_by_key(|r| r.version);
-            let changelog = initial_records.iter().fold(Changelog::default(), |mut cl, r| {
-                let rel_version = (r.version - latest_version).get();
-                if cl.len() < rel_version as usize {
-                    cl.push(ChangelogEntry::default());
-                }
-                cl.last_mut().unwrap().push(KeyMutation {
-                    key: r.key.clone(),
-                    value: r.value.clone(),
-                });
-                cl
+            .map_err(|e| {
+                anyhow::format_err!(
+                    "Failed to get certified changes since version {}: {}",
+                    latest_version,
+                    e
+                )
+            })?;
+        initial_records.sort_by_key(|r| r.version);
+        let changelog = initial_records.iter().fold(Changelog::default(), |mut cl, r| {
+            let rel_version = (r.version - latest_version).get();
+            if cl.len() < rel_version as usize {
+                cl.push(ChangelogEntry::default());
+            }
+            cl.last_mut().unwrap().push(KeyMutation {
+                key: r.key.clone(),
+                value: r.value.clone(),
             });
-
-            let versions_count = changelog.len();
-
-            changelog.into_iter().enumerate().for_each(|(i, ce)| {
-                let v = RegistryVersion::from(i as u64 + 1 + latest_version.get());
-                let local_registry_path = local_path.clone();
-                updates.push(async move {
-                    let path_str = format!("{:016x}.pb", v.get());
-                    let v_path = &[
-                        &path_str[0..10],
-                        &path_str[10..12],
-                        &path_str[12..14],
-                        &path_str[14..19],
-                    ]
-                    .iter()
-                    .collect::<PathBuf>();
-
-                    let path = local_registry_path.join(v_path.as_path());
-                    tokio::fs::create_dir_all(path.clone().parent().unwrap())
-                        .and_then(|_| async {
-                            tokio::fs::write(
-                                path,
-                                PbChangelogEntry {
-                                    key_mutations: ce
-                                        .iter()
-                                        .map(|km| {
-                                            let mutation_type = if km.value.is_some() {
-                                                MutationType::Set as i32
-                                            } else {
-                                                MutationType::Unset as i32
-                                            };
-                                            PbKeyMutation {
-                                                key: km.key.clone(),
-                                                value: km.value.clone().unwrap_or_default(),
-                                                mutation_type,
-                                            }
-                                        })
-                                        .collect(),
-                                }
-                                .encode_to_vec(),
-                            )
-                            .await
-                        })
+            cl
+        });
+
+        let versions_count = changelog.len();
+
+        changelog.into_iter().enumerate().for_each(|(i, ce)| {
+            let v = RegistryVersion::from(i as u64 + 1 + latest_version.get());
+            let local_registry_path = local_path.clone();
+            updates.push(async move {
+                let path_str = format!("{:016x}.pb", v.get());
+                let v_path = &[
+                    &path_str[0..10],
+                    &path_str[10..12],
+                    &path_str[12..14],
+                    &path_str[14..19],
+                ]
+                .iter()
+                .collect::<PathBuf>();
+
+                let path = local_registry_path.join(v_path.as_path());
+                tokio::fs::create_dir_all(path.clone().parent().unwrap())
+                    .and_then(|_| async {
+                        tokio::fs::write(
+                            path,
+                            PbChangelogEntry {
+                                key_mutations: ce
+                                    .iter()
+                                    .map(|km| {
+                                        let mutation_type = if km.value.is_some() {
+                                            MutationType::Set as i32
+                                        } else {
+                                            MutationType::Unset as i32
+                                        };
+                                        PbKeyMutation {
+                                            key: km.key.clone(),
+                                            value: km.value.clone().unwrap_or_default(),
+                                            mutation_type,
+                                        }
+                                    })
+                                    .collect(),
+                            }
+                            .encode_to_vec(),
+                        )
                         .await
-                })
-            });
+                    })
+                    .await
+            })
+        });
 
-            latest_version = latest_version.add(RegistryVersion::new(versions_count as u64));
+        latest_version = latest_version.add(RegistryVersion::new(versions_count as u64));
 
-            latest_certified_time = t.as_nanos_since_unix_epoch();
-            debug!(log, "Initial sync reached version {}", latest_version);
-        }
+        latest_certified_time = t.as_nanos_since_unix_epoch();
+        debug!(log, "Initial sync reached version {}", latest_version);
     }
 
     futures::future::join_all(updates).await;
     local_store.update_certified_time(latest_certified_time).unwrap();
-    info!(log, "Synced all registry versions in : {:?}", start.elapsed())
+    Ok(())
+}
+
+fn local_latest_version(local_path: &Path, local_store: Arc<LocalStoreImpl>) -> RegistryVersion {
+    if !local_path.exists() {
+        ZERO_REGISTRY_VERSION
+    } else {
+        let registry_cache = FakeRegistryClient::new(local_store);
+        registry_cache.update_to_latest_version();
+        registry_cache.get_latest_version()
+    }
 }
 
 async fn get_nns_public_key(registry_canister: &RegistryCanister) -> anyhow::Result<ThresholdSigPublicKey> {
@@ -181,6 +210,27 @@ async fn get_nns_public_key(registry_canister: &RegistryCanister) -> anyhow::Res
     )
 }
 
+/// The root public key the NNS reports, which is not verified against
+/// anything.
+pub async fn fetch_nns_public_key(nns_urls: Vec<Url>) -> anyhow::Result<ThresholdSigPublicKey> {
+    get_nns_public_key(&RegistryCanister::new(nns_urls)).await
+}
+
+/// Check that the latest certified registry changes of the NNS verify with
+/// `public_key`.
+pub async fn verify_nns_public_key(nns_urls: Vec<Url>, public_key: &ThresholdSigPublicKey) -> anyhow::Result<()> {
+    let registry_canister = RegistryCanister::new(nns_urls);
+    let latest_version = registry_canister
+        .get_latest_version()
+        .await
+        .map_err(|e| anyhow::format_err!("failed to get latest registry version: {}", e))?;
+    registry_canister
+        .get_certified_changes_since(latest_version.saturating_sub(1), public_key)
+        .await
+        .map_err(|e| anyhow::format_err!("failed to verify certified changes: {}", e))?;
+    Ok(())
+}
+
 pub async fn nns_reachable(nns_urls: Vec<Url>) -> bool {
     let registry_canister = RegistryCanister::new(nns_urls);
 